@@ -2,9 +2,10 @@
 //! unpublished) versions of arrow / parquet / datafusion so we can
 //! manage the version used by InfluxDB IOx in a single crate.
 
-// export arrow, parquet, and datafusion publically so we can have a single
-// reference in cargo
+// export arrow, arrow_flight, parquet, and datafusion publically so we can
+// have a single reference in cargo
 pub use arrow;
+pub use arrow_flight;
 pub use datafusion;
 pub use parquet;
 
@@ -1,3 +1,4 @@
+use chrono::Utc;
 use criterion::measurement::WallTime;
 use criterion::{criterion_group, criterion_main, Bencher, BenchmarkId, Criterion, Throughput};
 use data_types::data::{lines_to_replicated_write as lines_to_rw, ReplicatedWrite};
@@ -67,6 +68,54 @@ fn replicated_write_into_bytes(c: &mut Criterion) {
     );
 }
 
+// compares computing the partition key of each line with the template
+// to using a partition key generator, which caches formatted times
+fn partition_keys(c: &mut Criterion) {
+    let mut group = c.benchmark_group("partition_keys");
+    let rules = rules_with_time_partition();
+
+    for partition_count in [1, 100].iter() {
+        let config = Config {
+            line_count: 1_000,
+            partition_count: *partition_count,
+            table_count: 1,
+            tag_cardinality: 1,
+        };
+        group.throughput(Throughput::Elements(config.line_count as u64));
+
+        let lp = create_lp(&config);
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+        let default_time = Utc::now();
+
+        group.bench_with_input(
+            BenchmarkId::new("template", config.partition_count),
+            &lines,
+            |b, lines| {
+                b.iter(|| {
+                    for line in lines {
+                        rules.partition_key(line, &default_time).unwrap();
+                    }
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("generator", config.partition_count),
+            &lines,
+            |b, lines| {
+                b.iter(|| {
+                    let mut generator = rules.partition_key_generator(&default_time);
+                    for line in lines {
+                        generator.partition_key(line).unwrap();
+                    }
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
 // simulates the speed of marshalling the bytes into something like the mutable
 // buffer or read buffer, which won't use the replicated write structure anyway
 fn bytes_into_struct(c: &mut Criterion) {
@@ -375,6 +424,7 @@ fn create_lp(config: &Config) -> String {
 fn rules_with_time_partition() -> DatabaseRules {
     let partition_template = PartitionTemplate {
         parts: vec![TemplatePart::TimeFormat("%Y-%m-%d %H:%M:%S".to_string())],
+        ..Default::default()
     };
 
     DatabaseRules {
@@ -385,6 +435,7 @@ fn rules_with_time_partition() -> DatabaseRules {
 
 criterion_group!(
     benches,
+    partition_keys,
     lines_to_replicated_write,
     replicated_write_into_bytes,
     bytes_into_struct,
@@ -0,0 +1,216 @@
+//! This module contains `AccessPolicy`, which restricts the rows of a
+//! database each principal can query. It lets the data of several tenants
+//! share tables: every query of a principal only sees the rows whose
+//! columns match the principal's attributes, for example the rows whose
+//! `tenant_id` is the tenant of the token the query was sent with.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("A token is required to query this database"))]
+    MissingToken,
+
+    #[snafu(display("The token is not allowed to query this database"))]
+    UnknownToken,
+
+    #[snafu(display(
+        "Principal {} has no attribute {} to filter column {} by",
+        principal,
+        attribute,
+        column
+    ))]
+    MissingAttribute {
+        principal: String,
+        attribute: String,
+        column: String,
+    },
+
+    #[snafu(display(
+        "Principal {} only sees some rows of the database, so it can't read \
+         what is summarized from all of them",
+        principal
+    ))]
+    RestrictedRows { principal: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Restricts the rows each principal can query. Queries are only allowed
+/// for the principals of the policy, and only see the rows matching all
+/// of its row filters.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct AccessPolicy {
+    /// The principals allowed to query the database
+    #[serde(default)]
+    pub principals: Vec<Principal>,
+
+    /// The filters added to every query of a principal
+    #[serde(default)]
+    pub row_filters: Vec<RowFilter>,
+}
+
+/// Someone allowed to query a database with an access policy
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct Principal {
+    /// The name of the principal, for error messages and logs
+    pub name: String,
+
+    /// The hex encoded SHA-256 of the token the principal sends with its
+    /// queries. Only the hash is stored, so the rules don't reveal the
+    /// token.
+    pub token_sha256: String,
+
+    /// The attributes row filters refer to, such as the tenant of the
+    /// principal
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Only rows whose `column` equals the value of the attribute `attribute`
+/// of the principal are visible. Rows without a value for the column,
+/// including all of the rows of tables without the column, are never
+/// visible.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct RowFilter {
+    pub column: String,
+    pub attribute: String,
+}
+
+/// The predicate `column = value` a query of a principal must satisfy
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ColumnFilter {
+    pub column: String,
+    pub value: String,
+}
+
+impl AccessPolicy {
+    /// Returns the principal authenticated by `token`, which must be one
+    /// of the principals of the policy
+    pub fn authenticate(&self, token: Option<&str>) -> Result<&Principal> {
+        let token_sha256 = token_sha256(token.context(MissingToken)?);
+        self.principals
+            .iter()
+            .find(|principal| principal.token_sha256.eq_ignore_ascii_case(&token_sha256))
+            .context(UnknownToken)
+    }
+
+    /// Returns the filters the queries of `principal` must satisfy
+    pub fn column_filters(&self, principal: &Principal) -> Result<Vec<ColumnFilter>> {
+        self.row_filters
+            .iter()
+            .map(|filter| {
+                let value =
+                    principal
+                        .attributes
+                        .get(&filter.attribute)
+                        .context(MissingAttribute {
+                            principal: &principal.name,
+                            attribute: &filter.attribute,
+                            column: &filter.column,
+                        })?;
+                Ok(ColumnFilter {
+                    column: filter.column.clone(),
+                    value: value.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Checks that `principal` sees every row of the database, as the
+    /// summaries and schema of the database are gathered from all rows
+    pub fn check_unrestricted(&self, principal: &Principal) -> Result<()> {
+        ensure!(
+            self.row_filters.is_empty(),
+            RestrictedRows {
+                principal: &principal.name
+            }
+        );
+        Ok(())
+    }
+}
+
+/// Returns the hex encoded SHA-256 of `token`, as stored in
+/// `Principal::token_sha256`
+pub fn token_sha256(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> AccessPolicy {
+        AccessPolicy {
+            principals: vec![Principal {
+                name: "acme".to_string(),
+                token_sha256: token_sha256("acme-token"),
+                attributes: vec![("tenant".to_string(), "acme".to_string())]
+                    .into_iter()
+                    .collect(),
+            }],
+            row_filters: vec![RowFilter {
+                column: "tenant_id".to_string(),
+                attribute: "tenant".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn principals_are_authenticated_by_token() {
+        let mut policy = policy();
+
+        let principal = policy.authenticate(Some("acme-token")).unwrap();
+        assert_eq!(principal.name, "acme");
+        assert_eq!(
+            policy.column_filters(principal).unwrap(),
+            vec![ColumnFilter {
+                column: "tenant_id".to_string(),
+                value: "acme".to_string(),
+            }]
+        );
+
+        let err = policy.authenticate(Some("other-token")).unwrap_err();
+        assert!(matches!(err, Error::UnknownToken));
+        let err = policy.authenticate(None).unwrap_err();
+        assert!(matches!(err, Error::MissingToken));
+
+        // summaries are only readable by principals seeing every row
+        let principal = principal.clone();
+        let err = policy.check_unrestricted(&principal).unwrap_err();
+        assert!(matches!(err, Error::RestrictedRows { .. }));
+        policy.row_filters.clear();
+        policy.check_unrestricted(&principal).unwrap();
+    }
+
+    #[test]
+    fn missing_attributes_are_rejected() {
+        let mut policy = policy();
+        policy.row_filters.push(RowFilter {
+            column: "region".to_string(),
+            attribute: "region".to_string(),
+        });
+
+        let principal = policy.authenticate(Some("acme-token")).unwrap();
+        let err = policy.column_filters(principal).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Principal acme has no attribute region to filter column region by"
+        );
+    }
+
+    #[test]
+    fn token_hashes() {
+        assert_eq!(
+            token_sha256("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
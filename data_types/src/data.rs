@@ -1,16 +1,86 @@
 //! This module contains helper methods for constructing replicated writes
 //! based on `DatabaseRules`.
 
-use crate::database_rules::DatabaseRules;
-use crate::TIME_COLUMN_NAME;
+use crate::database_rules::{DatabaseRules, PartitionKeySource, WriteTransforms};
+use crate::schema::{InfluxColumnType, InfluxFieldType, Schema};
+use crate::{INGEST_TIME_COLUMN_NAME, TIME_COLUMN_NAME};
+use arrow_deps::arrow::{
+    array::{Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray},
+    datatypes::DataType as ArrowDataType,
+    record_batch::RecordBatch,
+};
 use generated_types::wal as wb;
-use influxdb_line_protocol::{FieldValue, ParsedLine};
+use influxdb_line_protocol::{FieldValue, ParsedLine, Series, TagSet};
 
-use std::{collections::BTreeMap, fmt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    fmt,
+    sync::Arc,
+};
 
 use chrono::Utc;
 use crc32fast::Hasher;
 use flatbuffers::FlatBufferBuilder;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+/// The format version of the `WriteBufferBatch`es written by this build.
+/// This must be incremented whenever the way data is encoded in a batch
+/// changes, so that servers running older builds reject the new batches
+/// rather than misinterpreting them.
+pub const WRITE_BUFFER_BATCH_VERSION: u16 = 1;
+
+/// The version of `WriteBufferBatch`es written before the format version
+/// was introduced. These are encoded identically to version 1.
+pub const LEGACY_WRITE_BUFFER_BATCH_VERSION: u16 = 0;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "replicated write from writer {} with sequence {} missing payload",
+        writer,
+        sequence
+    ))]
+    MissingPayload { writer: u32, sequence: u64 },
+
+    #[snafu(display(
+        "replicated write from writer {} with sequence {} has unsupported format version {} (supported versions: {} to {})",
+        writer,
+        sequence,
+        version,
+        LEGACY_WRITE_BUFFER_BATCH_VERSION,
+        WRITE_BUFFER_BATCH_VERSION
+    ))]
+    UnsupportedVersion {
+        writer: u32,
+        sequence: u64,
+        version: u16,
+    },
+
+    #[snafu(display("Invalid schema of record batch: {}", source))]
+    InvalidBatchSchema { source: crate::schema::Error },
+
+    #[snafu(display(
+        "Column {} of type {:?} of record batch can't be written",
+        column,
+        data_type
+    ))]
+    UnsupportedColumnType {
+        column: String,
+        data_type: ArrowDataType,
+    },
+
+    #[snafu(display("Column {} of record batch conflicts with its time column", column))]
+    InvalidTimeColumn { column: String },
+
+    #[snafu(display("Error partitioning row {} of record batch: {}", row, source))]
+    PartitioningRow {
+        row: usize,
+        source: crate::database_rules::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 pub fn type_description(value: wb::ColumnValue) -> &'static str {
     use wb::ColumnValue::*;
@@ -47,6 +117,43 @@ impl ReplicatedWrite {
         }
     }
 
+    /// Checks that the raw bytes are a valid ReplicatedWrite with a valid
+    /// WriteBufferBatch payload, so its accessors can be used safely.
+    /// Writes read back from storage should be verified before anything
+    /// else is done with them.
+    pub fn verify(&self) -> Result<(), crate::verification::Error> {
+        crate::verification::verify_replicated_write(&self.data)
+    }
+
+    /// Checks that the WriteBufferBatch in the payload of this
+    /// ReplicatedWrite was written in a format version this build
+    /// understands, returning the (migrated) version on success.
+    ///
+    /// Batches written before the version was introduced are treated as
+    /// version 1, which is encoded identically. Batches from newer builds
+    /// are rejected.
+    pub fn check_version(&self) -> Result<u16> {
+        let (writer, sequence) = self.writer_and_sequence();
+        let batch = self
+            .write_buffer_batch()
+            .context(MissingPayload { writer, sequence })?;
+
+        let version = batch.version();
+        ensure!(
+            version <= WRITE_BUFFER_BATCH_VERSION,
+            UnsupportedVersion {
+                writer,
+                sequence,
+                version
+            }
+        );
+
+        match version {
+            LEGACY_WRITE_BUFFER_BATCH_VERSION => Ok(1),
+            version => Ok(version),
+        }
+    }
+
     /// Returns true if this replicated write matches the writer and sequence.
     pub fn equal_to_writer_and_sequence(&self, writer_id: u32, sequence_number: u64) -> bool {
         let fb = self.to_fb();
@@ -64,6 +171,20 @@ impl ReplicatedWrite {
         &self.data
     }
 
+    /// Returns the table names and predicates of the deletes in this
+    /// replicated write
+    pub fn deletes(&self) -> Vec<(&str, &str)> {
+        self.write_buffer_batch()
+            .and_then(|batch| batch.entries())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let delete = entry.delete()?;
+                Some((delete.table_name()?, delete.predicate()?))
+            })
+            .collect()
+    }
+
     /// Returns the number of write buffer entries in this replicated write
     pub fn entry_count(&self) -> usize {
         if let Some(batch) = self.write_buffer_batch() {
@@ -98,6 +219,15 @@ impl fmt::Display for ReplicatedWrite {
         if let Some(batch) = self.write_buffer_batch() {
             if let Some(entries) = batch.entries() {
                 for entry in entries {
+                    if let Some(delete) = entry.delete() {
+                        writeln!(
+                            f,
+                            "delete:{} {}",
+                            delete.table_name().unwrap_or(""),
+                            delete.predicate().unwrap_or("")
+                        )?;
+                        continue;
+                    }
                     writeln!(f, "partition_key:{}", entry.partition_key().unwrap_or(""))?;
 
                     if let Some(tables) = entry.table_batches() {
@@ -167,17 +297,39 @@ pub fn lines_to_replicated_write(
     rules: &DatabaseRules,
 ) -> ReplicatedWrite {
     let default_time = Utc::now();
+    let mut partition_keys = rules.partition_key_generator(&default_time);
     let entry_bytes = split_lines_into_write_entry_partitions(
-        |line| rules.partition_key(line, &default_time).unwrap(),
+        |line| partition_keys.partition_key(line).unwrap(),
         lines,
     );
 
+    entries_to_replicated_write(writer, sequence, &entry_bytes)
+}
+
+/// Builds a `ReplicatedWrite` deleting the rows of the table `table_name`
+/// that `predicate` matches. How the predicate is encoded is up to the
+/// database applying the delete.
+pub fn delete_to_replicated_write(
+    writer: u32,
+    sequence: u64,
+    table_name: &str,
+    predicate: &str,
+) -> ReplicatedWrite {
+    let mut builder = BatchBuilder::new(0);
+    let entry = builder.add_delete_entry(table_name, predicate);
+    let entry_bytes = builder.finish(&[entry]);
+
+    entries_to_replicated_write(writer, sequence, &entry_bytes)
+}
+
+/// Wraps the bytes of a `WriteBufferBatch` in a `ReplicatedWrite`
+fn entries_to_replicated_write(writer: u32, sequence: u64, entry_bytes: &[u8]) -> ReplicatedWrite {
     let mut hasher = Hasher::new();
-    hasher.update(&entry_bytes);
+    hasher.update(entry_bytes);
     let checksum = hasher.finalize();
 
     let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
-    let payload = fbb.create_vector_direct(&entry_bytes);
+    let payload = fbb.create_vector_direct(entry_bytes);
 
     let write = wb::ReplicatedWrite::create(
         &mut fbb,
@@ -198,10 +350,10 @@ pub fn lines_to_replicated_write(
 }
 
 pub fn split_lines_into_write_entry_partitions(
-    partition_key_fn: impl Fn(&ParsedLine<'_>) -> String,
+    mut partition_key_fn: impl FnMut(&ParsedLine<'_>) -> String,
     lines: &[ParsedLine<'_>],
 ) -> Vec<u8> {
-    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+    let mut builder = BatchBuilder::new(lines.len());
 
     // split the lines into collections that go into partitions
     let mut partition_writes = BTreeMap::new();
@@ -219,204 +371,1314 @@ pub fn split_lines_into_write_entry_partitions(
     // per partition)
     let entries = partition_writes
         .into_iter()
-        .map(|(key, lines)| add_write_entry(&mut fbb, Some(&key), &lines))
+        .map(|(key, lines)| builder.add_write_entry(Some(&key), &lines))
         .collect::<Vec<_>>();
 
-    let entries_vec = fbb.create_vector(&entries);
+    builder.finish(&entries)
+}
 
-    let batch = wb::WriteBufferBatch::create(
-        &mut fbb,
-        &wb::WriteBufferBatchArgs {
-            entries: Some(entries_vec),
-        },
-    );
+/// The rows of a record batch written to a table, for writers that already
+/// have columnar data. The rows are checked, transformed and encoded as a
+/// write a column at a time, without being formatted and parsed as line
+/// protocol.
+///
+/// Columns are written as the IOx metadata of the schema of the batch
+/// says. Columns without metadata are mapped by name and type: the `time`
+/// column (Int64 nanoseconds since the epoch) holds the timestamps, Utf8
+/// columns are tags, and Int64, Float64 and Boolean columns are fields.
+/// Unsigned integers can't be stored by the mutable buffer and are
+/// rejected.
+///
+/// The rows are written like lines with the same values: null values are
+/// left out, rows without a timestamp are written at the current time and
+/// rows without any field value, which aren't points, are skipped.
+#[derive(Debug, Clone)]
+pub struct TableBatch {
+    table_name: String,
+    columns: Vec<BatchColumn>,
+    num_rows: usize,
+}
 
-    fbb.finish(batch, None);
+/// A column of a `TableBatch`
+#[derive(Debug, Clone)]
+struct BatchColumn {
+    name: String,
+    kind: ColumnKind,
+    /// The values of the column, an array of the type matching `kind`
+    values: ArrayRef,
+}
 
-    let (mut data, idx) = fbb.collapse();
-    data.split_off(idx)
+/// How the values of a column of a `TableBatch` are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    Tag,
+    Time,
+    I64,
+    F64,
+    Bool,
+    String,
 }
 
-fn add_write_entry<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    partition_key: Option<&str>,
-    lines: &[&ParsedLine<'_>],
-) -> flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>> {
-    // split into tables
-    let mut table_batches = BTreeMap::new();
-    for line in lines {
-        let measurement = line.series.measurement.as_str();
-        table_batches
-            .entry(measurement)
-            .or_insert_with(Vec::new)
-            .push(*line);
+impl ColumnKind {
+    fn is_field(self) -> bool {
+        !matches!(self, Self::Tag | Self::Time)
     }
 
-    // create TableWriteBatch for each table
-    let table_batches = table_batches
-        .into_iter()
-        .map(|(name, lines)| add_table_batch(fbb, name, &lines))
-        .collect::<Vec<_>>();
+    fn is_tag(self) -> bool {
+        self == Self::Tag
+    }
+}
 
-    // create write entry
-    let batches_vec = fbb.create_vector(&table_batches);
+impl TableBatch {
+    /// Maps the columns of `batch` to the tags, fields and time column of
+    /// the table `table_name`, checking that each can be written
+    pub fn try_new(table_name: impl Into<String>, batch: &RecordBatch) -> Result<Self> {
+        let schema = Schema::try_from(batch.schema()).context(InvalidBatchSchema)?;
 
-    let args = match partition_key {
-        Some(key) => {
-            let key = fbb.create_string(key);
-            wb::WriteBufferEntryArgs {
-                partition_key: Some(key),
-                table_batches: Some(batches_vec),
-                ..Default::default()
+        let mut has_time = false;
+        let mut columns = Vec::with_capacity(schema.len());
+        for (idx, values) in batch.columns().iter().enumerate() {
+            let (column_type, field) = schema.field(idx);
+            let name = field.name().as_str();
+            let unsupported = || UnsupportedColumnType {
+                column: name,
+                data_type: field.data_type().clone(),
+            };
+
+            let column_type = match column_type {
+                Some(column_type) => column_type,
+                None if name == TIME_COLUMN_NAME => InfluxColumnType::Timestamp,
+                None if field.data_type() == &ArrowDataType::Utf8 => InfluxColumnType::Tag,
+                None => match InfluxFieldType::try_from(field.data_type().clone()) {
+                    Ok(field_type) => InfluxColumnType::Field(field_type),
+                    Err(_) => return unsupported().fail(),
+                },
+            };
+
+            // the timestamps are always written to the time column
+            if column_type == InfluxColumnType::Timestamp {
+                ensure!(!has_time, InvalidTimeColumn { column: name });
+                has_time = true;
+            } else {
+                ensure!(name != TIME_COLUMN_NAME, InvalidTimeColumn { column: name });
             }
+
+            let kind = match column_type {
+                InfluxColumnType::Tag => ColumnKind::Tag,
+                InfluxColumnType::Timestamp => ColumnKind::Time,
+                InfluxColumnType::Field(InfluxFieldType::Integer) => ColumnKind::I64,
+                InfluxColumnType::Field(InfluxFieldType::Float) => ColumnKind::F64,
+                InfluxColumnType::Field(InfluxFieldType::Boolean) => ColumnKind::Bool,
+                InfluxColumnType::Field(InfluxFieldType::String) => ColumnKind::String,
+                InfluxColumnType::Field(InfluxFieldType::UInteger) => return unsupported().fail(),
+            };
+            let array = values.as_any();
+            let matches_kind = match kind {
+                ColumnKind::Tag | ColumnKind::String => array.is::<StringArray>(),
+                ColumnKind::Time | ColumnKind::I64 => array.is::<Int64Array>(),
+                ColumnKind::F64 => array.is::<Float64Array>(),
+                ColumnKind::Bool => array.is::<BooleanArray>(),
+            };
+            ensure!(matches_kind, unsupported());
+
+            columns.push(BatchColumn {
+                name: name.to_string(),
+                kind,
+                values: ArrayRef::clone(values),
+            });
         }
-        None => wb::WriteBufferEntryArgs {
-            table_batches: Some(batches_vec),
-            ..Default::default()
-        },
-    };
 
-    wb::WriteBufferEntry::create(fbb, &args)
-}
+        Ok(Self {
+            table_name: table_name.into(),
+            columns,
+            num_rows: batch.num_rows(),
+        })
+    }
 
-fn add_table_batch<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    name: &str,
-    lines: &[&ParsedLine<'_>],
-) -> flatbuffers::WIPOffset<wb::TableWriteBatch<'a>> {
-    // create Row
-    let rows = lines
-        .iter()
-        .map(|line| add_line(fbb, line))
-        .collect::<Vec<_>>();
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
 
-    let table_name = fbb.create_string(name);
-    let rows = fbb.create_vector(&rows);
+    /// The number of rows of the batch, including those that aren't points
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
 
-    wb::TableWriteBatch::create(
-        fbb,
-        &wb::TableWriteBatchArgs {
-            name: Some(table_name),
-            rows: Some(rows),
-        },
-    )
-}
+    /// Returns the (0 based) rows with NaN or infinite float field values,
+    /// with the names of those fields
+    pub fn non_finite_fields(&self) -> BTreeMap<usize, Vec<&str>> {
+        let mut rows = BTreeMap::new();
+        for column in &self.columns {
+            if column.kind != ColumnKind::F64 {
+                continue;
+            }
+            let values = column.array::<Float64Array>();
+            for row in 0..self.num_rows {
+                if column.is_valid(row) && !values.value(row).is_finite() {
+                    rows.entry(row)
+                        .or_insert_with(Vec::new)
+                        .push(column.name.as_str());
+                }
+            }
+        }
+        rows
+    }
+
+    /// Sets the float field values that are NaN or infinite to null. Rows
+    /// left without field values are not written.
+    pub fn remove_non_finite(&mut self) {
+        for column in &mut self.columns {
+            if column.kind != ColumnKind::F64 {
+                continue;
+            }
+            let finite = {
+                let values = column.array::<Float64Array>();
+                column.null_rows(|row| !values.value(row).is_finite())
+            };
+            column.values = finite;
+        }
+    }
 
-fn add_line<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    line: &ParsedLine<'_>,
-) -> flatbuffers::WIPOffset<wb::Row<'a>> {
-    let mut row_values = Vec::new();
+    /// Applies `transforms` to the rows of the batch, which are
+    /// transformed like lines with the same values. Rows left without
+    /// field values are not written.
+    pub fn apply_transforms(&mut self, transforms: &WriteTransforms) {
+        let drop_fields = &transforms.drop_fields;
+        self.columns
+            .retain(|column| !(column.kind.is_field() && drop_fields.contains(&column.name)));
 
-    if let Some(tags) = &line.series.tag_set {
-        for (column, value) in tags {
-            row_values.push(add_tag_value(fbb, column.as_str(), value.as_str()));
+        for (old, new) in transforms
+            .rename_fields
+            .iter()
+            .filter(|(old, new)| old != new)
+        {
+            self.rename_columns(old, new, ColumnKind::is_field);
+        }
+        for (old, new) in transforms
+            .rename_tags
+            .iter()
+            .filter(|(old, new)| old != new)
+        {
+            self.rename_columns(old, new, ColumnKind::is_tag);
+        }
+
+        let num_rows = self.num_rows;
+        for (name, value) in &transforms.inject_tags {
+            self.columns
+                .retain(|column| !(column.kind.is_tag() && &column.name == name));
+            self.columns.push(BatchColumn {
+                name: name.clone(),
+                kind: ColumnKind::Tag,
+                values: Arc::new(StringArray::from(vec![value.as_str(); num_rows])),
+            });
         }
     }
 
-    for (column, value) in &line.field_set {
-        let val = match value {
-            FieldValue::I64(v) => add_i64_value(fbb, column.as_str(), *v),
-            FieldValue::F64(v) => add_f64_value(fbb, column.as_str(), *v),
-            FieldValue::Boolean(v) => add_bool_value(fbb, column.as_str(), *v),
-            FieldValue::String(v) => add_string_value(fbb, column.as_str(), v.as_str()),
-        };
+    /// Renames the columns named `old` of the kinds `kind` accepts to
+    /// `new`. Like on a line, the renamed values replace those of the
+    /// columns already named `new` in the rows they are set in, so the
+    /// batch can have several columns named `new`, but only one of them
+    /// has a value in any row.
+    fn rename_columns(&mut self, old: &str, new: &str, kind: impl Fn(ColumnKind) -> bool) {
+        let renamed: Vec<_> = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| kind(column.kind) && column.name == old)
+            .map(|(idx, _)| idx)
+            .collect();
+        if renamed.is_empty() {
+            return;
+        }
+
+        let has_old: Vec<_> = (0..self.num_rows)
+            .map(|row| renamed.iter().any(|&idx| self.columns[idx].is_valid(row)))
+            .collect();
+        for column in &mut self.columns {
+            if kind(column.kind) && column.name == new {
+                column.values = column.null_rows(|row| has_old[row]);
+            }
+        }
+        for idx in renamed {
+            self.columns[idx].name = new.to_string();
+        }
+    }
+
+    /// Sets the field `_ingest_time` of every point of the batch to
+    /// `ingest_time`, in nanoseconds since the epoch, replacing any value
+    /// written by the client
+    pub fn set_ingest_time(&mut self, ingest_time: i64) {
+        let mut values = vec![None; self.num_rows];
+        for row in self.point_rows() {
+            values[row] = Some(ingest_time);
+        }
 
-        row_values.push(val);
+        self.columns
+            .retain(|column| !(column.kind.is_field() && column.name == INGEST_TIME_COLUMN_NAME));
+        self.columns.push(BatchColumn {
+            name: INGEST_TIME_COLUMN_NAME.to_string(),
+            kind: ColumnKind::I64,
+            values: Arc::new(Int64Array::from(values)),
+        });
     }
 
-    let time = line
-        .timestamp
-        .unwrap_or_else(|| Utc::now().timestamp_nanos());
-    row_values.push(add_i64_value(fbb, TIME_COLUMN_NAME, time));
+    /// Returns the points of the batch as lines, for the checks that count
+    /// lines, such as the quotas of series and bytes written
+    pub fn lines(&self) -> Vec<ParsedLine<'_>> {
+        self.point_rows()
+            .into_iter()
+            .map(|row| {
+                let valid = |kind: fn(ColumnKind) -> bool| {
+                    self.columns
+                        .iter()
+                        .filter(move |column| kind(column.kind) && column.is_valid(row))
+                };
+                let tag_set: TagSet<'_> = valid(ColumnKind::is_tag)
+                    .map(|column| {
+                        let value = column.array::<StringArray>().value(row);
+                        (column.name.as_str().into(), value.into())
+                    })
+                    .collect();
+                let tag_set = if tag_set.is_empty() {
+                    None
+                } else {
+                    Some(tag_set)
+                };
+                let field_set = valid(ColumnKind::is_field)
+                    .filter_map(|column| {
+                        Some((column.name.as_str().into(), column.field_value(row)?))
+                    })
+                    .collect();
 
-    let row_values = fbb.create_vector(&row_values);
+                ParsedLine {
+                    series: Series::new(self.table_name.as_str().into(), tag_set),
+                    field_set,
+                    timestamp: self.timestamp(row),
+                }
+            })
+            .collect()
+    }
 
-    wb::Row::create(
-        fbb,
-        &wb::RowArgs {
-            values: Some(row_values),
-        },
-    )
+    /// Returns the partition key of each point of the batch, like
+    /// `to_replicated_write` partitions them
+    pub fn partition_keys(
+        &self,
+        rules: &DatabaseRules,
+    ) -> Result<Vec<String>, crate::database_rules::Error> {
+        let default_time = Utc::now();
+        let mut partition_keys = rules.partition_key_generator(&default_time);
+        self.point_rows()
+            .into_iter()
+            .map(|row| partition_keys.partition_key(&BatchRow { batch: self, row }))
+            .collect()
+    }
+
+    /// Builds a `ReplicatedWrite` of the points of the batch, partitioned
+    /// like lines with the same values
+    pub fn to_replicated_write(
+        &self,
+        writer: u32,
+        sequence: u64,
+        rules: &DatabaseRules,
+    ) -> Result<ReplicatedWrite> {
+        let default_time = Utc::now();
+        let mut partition_keys = rules.partition_key_generator(&default_time);
+        let mut partition_rows = BTreeMap::new();
+        for row in self.point_rows() {
+            let key = partition_keys
+                .partition_key(&BatchRow { batch: self, row })
+                .context(PartitioningRow { row })?;
+            partition_rows.entry(key).or_insert_with(Vec::new).push(row);
+        }
+
+        let mut builder = BatchBuilder::new(self.num_rows);
+        let entries = builder.add_batch_entries(self, &partition_rows);
+        let entry_bytes = builder.finish(&entries);
+
+        Ok(entries_to_replicated_write(writer, sequence, &entry_bytes))
+    }
+
+    /// Returns the rows of the batch that are points, those with a field
+    /// value
+    fn point_rows(&self) -> Vec<usize> {
+        let mut is_point = vec![false; self.num_rows];
+        for column in &self.columns {
+            if !column.kind.is_field() {
+                continue;
+            }
+            for (row, is_point) in is_point.iter_mut().enumerate() {
+                *is_point = *is_point || column.is_valid(row);
+            }
+        }
+
+        is_point
+            .into_iter()
+            .enumerate()
+            .filter(|(_, is_point)| *is_point)
+            .map(|(row, _)| row)
+            .collect()
+    }
+
+    fn timestamp(&self, row: usize) -> Option<i64> {
+        self.columns
+            .iter()
+            .find(|column| column.kind == ColumnKind::Time && column.is_valid(row))
+            .map(|column| column.array::<Int64Array>().value(row))
+    }
 }
 
-fn add_tag_value<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    column: &str,
-    value: &str,
-) -> flatbuffers::WIPOffset<wb::Value<'a>> {
-    let value = fbb.create_string(&value);
-    let tv = wb::TagValue::create(fbb, &wb::TagValueArgs { value: Some(value) });
+impl BatchColumn {
+    /// Returns the values of the column as the array `T`, which was
+    /// checked against the kind of the column when the batch was created
+    fn array<T: Array + 'static>(&self) -> &T {
+        self.values
+            .as_any()
+            .downcast_ref()
+            .expect("the array of a column matches its kind")
+    }
+
+    fn is_valid(&self, row: usize) -> bool {
+        self.values.is_valid(row)
+    }
 
-    add_value(fbb, column, wb::ColumnValue::TagValue, tv.as_union_value())
+    /// Returns the value of the column in `row` if it is a field
+    fn field_value(&self, row: usize) -> Option<FieldValue<'_>> {
+        Some(match self.kind {
+            ColumnKind::I64 => FieldValue::I64(self.array::<Int64Array>().value(row)),
+            ColumnKind::F64 => FieldValue::F64(self.array::<Float64Array>().value(row)),
+            ColumnKind::Bool => FieldValue::Boolean(self.array::<BooleanArray>().value(row)),
+            ColumnKind::String => FieldValue::String(self.array::<StringArray>().value(row).into()),
+            ColumnKind::Tag | ColumnKind::Time => return None,
+        })
+    }
+
+    /// Returns the values of the column with those of the rows `null`
+    /// returns true for set to null
+    fn null_rows(&self, null: impl Fn(usize) -> bool) -> ArrayRef {
+        match self.kind {
+            ColumnKind::Tag | ColumnKind::String => {
+                let values = self.array::<StringArray>();
+                Arc::new(StringArray::from(self.kept(&null, |row| values.value(row))))
+            }
+            ColumnKind::Time | ColumnKind::I64 => {
+                let values = self.array::<Int64Array>();
+                Arc::new(Int64Array::from(self.kept(&null, |row| values.value(row))))
+            }
+            ColumnKind::F64 => {
+                let values = self.array::<Float64Array>();
+                Arc::new(Float64Array::from(
+                    self.kept(&null, |row| values.value(row)),
+                ))
+            }
+            ColumnKind::Bool => {
+                let values = self.array::<BooleanArray>();
+                Arc::new(BooleanArray::from(
+                    self.kept(&null, |row| values.value(row)),
+                ))
+            }
+        }
+    }
+
+    /// Returns `value` of each row of the column that has a value and that
+    /// `null` returns false for
+    fn kept<T>(&self, null: &dyn Fn(usize) -> bool, value: impl Fn(usize) -> T) -> Vec<Option<T>> {
+        (0..self.values.len())
+            .map(|row| {
+                if self.is_valid(row) && !null(row) {
+                    Some(value(row))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
 }
 
-fn add_string_value<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    column: &str,
-    value: &str,
-) -> flatbuffers::WIPOffset<wb::Value<'a>> {
-    let value_offset = fbb.create_string(value);
-
-    let sv = wb::StringValue::create(
-        fbb,
-        &wb::StringValueArgs {
-            value: Some(value_offset),
-        },
-    );
+/// A row of a `TableBatch`, as the point its partition key is computed
+/// from
+struct BatchRow<'a> {
+    batch: &'a TableBatch,
+    row: usize,
+}
 
-    add_value(
-        fbb,
-        column,
-        wb::ColumnValue::StringValue,
-        sv.as_union_value(),
-    )
+impl BatchRow<'_> {
+    /// Returns the column named `name` of the kinds `kind` accepts with a
+    /// value in the row
+    fn column(&self, name: &str, kind: fn(ColumnKind) -> bool) -> Option<&BatchColumn> {
+        self.batch
+            .columns
+            .iter()
+            .find(|column| column.name == name && kind(column.kind) && column.is_valid(self.row))
+    }
 }
 
-fn add_f64_value<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    column: &str,
-    value: f64,
-) -> flatbuffers::WIPOffset<wb::Value<'a>> {
-    let fv = wb::F64Value::create(fbb, &wb::F64ValueArgs { value });
+impl PartitionKeySource for BatchRow<'_> {
+    fn measurement(&self) -> &str {
+        &self.batch.table_name
+    }
+
+    fn tag_value(&self, tag: &str) -> Option<&str> {
+        self.column(tag, ColumnKind::is_tag)
+            .map(|column| column.array::<StringArray>().value(self.row))
+    }
 
-    add_value(fbb, column, wb::ColumnValue::F64Value, fv.as_union_value())
+    fn field_value(&self, field: &str) -> Option<FieldValue<'_>> {
+        self.column(field, ColumnKind::is_field)?
+            .field_value(self.row)
+    }
+
+    fn timestamp(&self) -> Option<i64> {
+        self.batch.timestamp(self.row)
+    }
 }
 
-fn add_i64_value<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    column: &str,
-    value: i64,
-) -> flatbuffers::WIPOffset<wb::Value<'a>> {
-    let iv = wb::I64Value::create(fbb, &wb::I64ValueArgs { value });
+/// Builds the flatbuffer of a `WriteBufferBatch` for a set of lines.
+///
+/// To reduce allocations and the size of the batch, strings that
+/// usually repeat across lines (column names and tag values) are
+/// only written once to the flatbuffer and shared by all the values
+/// that use them, and the buffer used to collect the values of each
+/// row is reused. Readers simply follow the offsets, so sharing
+/// strings doesn't change the format of the batch.
+struct BatchBuilder<'a, 'l> {
+    fbb: FlatBufferBuilder<'a>,
 
-    add_value(fbb, column, wb::ColumnValue::I64Value, iv.as_union_value())
+    /// The offsets of the column names and tag values written so far
+    strings: HashMap<&'l str, flatbuffers::WIPOffset<&'a str>>,
+
+    /// Reused to collect the values of each row
+    row_values: Vec<flatbuffers::WIPOffset<wb::Value<'a>>>,
+
+    /// The timestamp of lines without one
+    default_time: i64,
 }
 
-fn add_bool_value<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    column: &str,
-    value: bool,
-) -> flatbuffers::WIPOffset<wb::Value<'a>> {
-    let bv = wb::BoolValue::create(fbb, &wb::BoolValueArgs { value });
+impl<'a, 'l> BatchBuilder<'a, 'l> {
+    fn new(num_lines: usize) -> Self {
+        // a rough estimate of the encoded size of a line
+        let capacity = (num_lines * 128).max(1024);
+
+        Self {
+            fbb: FlatBufferBuilder::new_with_capacity(capacity),
+            strings: HashMap::new(),
+            row_values: Vec::new(),
+            default_time: Utc::now().timestamp_nanos(),
+        }
+    }
+
+    /// Finishes the batch with `entries` and returns its bytes
+    fn finish(mut self, entries: &[flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>>]) -> Vec<u8> {
+        let entries_vec = self.fbb.create_vector(entries);
+
+        let batch = wb::WriteBufferBatch::create(
+            &mut self.fbb,
+            &wb::WriteBufferBatchArgs {
+                entries: Some(entries_vec),
+                version: WRITE_BUFFER_BATCH_VERSION,
+            },
+        );
+
+        self.fbb.finish(batch, None);
+
+        let (mut data, idx) = self.fbb.collapse();
+        data.split_off(idx)
+    }
+
+    fn add_write_entry(
+        &mut self,
+        partition_key: Option<&str>,
+        lines: &[&'l ParsedLine<'_>],
+    ) -> flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>> {
+        // split into tables
+        let mut table_batches = BTreeMap::new();
+        for line in lines {
+            let measurement = line.series.measurement.as_str();
+            table_batches
+                .entry(measurement)
+                .or_insert_with(Vec::new)
+                .push(*line);
+        }
+
+        // create TableWriteBatch for each table
+        let table_batches = table_batches
+            .into_iter()
+            .map(|(name, lines)| self.add_table_batch(name, &lines))
+            .collect::<Vec<_>>();
+
+        // create write entry
+        let batches_vec = self.fbb.create_vector(&table_batches);
+
+        let args = match partition_key {
+            Some(key) => {
+                let key = self.fbb.create_string(key);
+                wb::WriteBufferEntryArgs {
+                    partition_key: Some(key),
+                    table_batches: Some(batches_vec),
+                    ..Default::default()
+                }
+            }
+            None => wb::WriteBufferEntryArgs {
+                table_batches: Some(batches_vec),
+                ..Default::default()
+            },
+        };
+
+        wb::WriteBufferEntry::create(&mut self.fbb, &args)
+    }
+
+    fn add_table_batch(
+        &mut self,
+        name: &str,
+        lines: &[&'l ParsedLine<'_>],
+    ) -> flatbuffers::WIPOffset<wb::TableWriteBatch<'a>> {
+        // create Row
+        let rows = lines
+            .iter()
+            .map(|&line| self.add_line(line))
+            .collect::<Vec<_>>();
+
+        self.finish_table_batch(name, &rows)
+    }
+
+    fn finish_table_batch(
+        &mut self,
+        name: &str,
+        rows: &[flatbuffers::WIPOffset<wb::Row<'a>>],
+    ) -> flatbuffers::WIPOffset<wb::TableWriteBatch<'a>> {
+        let table_name = self.fbb.create_string(name);
+        let rows = self.fbb.create_vector(rows);
 
-    add_value(fbb, column, wb::ColumnValue::BoolValue, bv.as_union_value())
+        wb::TableWriteBatch::create(
+            &mut self.fbb,
+            &wb::TableWriteBatchArgs {
+                name: Some(table_name),
+                rows: Some(rows),
+            },
+        )
+    }
+
+    /// Adds a write entry to the table of `batch` for each partition of
+    /// `partition_rows`, holding the rows of `batch` listed for it.
+    ///
+    /// The values are encoded a column at a time, so the kind of each
+    /// column is matched once and its array is read in order, and the
+    /// values of each row are then gathered into its `Row`.
+    fn add_batch_entries(
+        &mut self,
+        batch: &'l TableBatch,
+        partition_rows: &BTreeMap<String, Vec<usize>>,
+    ) -> Vec<flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>>> {
+        let rows: Vec<usize> = partition_rows.values().flatten().copied().collect();
+        let mut row_values: Vec<Vec<_>> = (0..batch.num_rows).map(|_| Vec::new()).collect();
+        let mut times = vec![self.default_time; batch.num_rows];
+
+        for column in &batch.columns {
+            let name = column.name.as_str();
+            let valid = rows.iter().copied().filter(|&row| column.is_valid(row));
+            match column.kind {
+                ColumnKind::Tag => {
+                    let values = column.array::<StringArray>();
+                    for row in valid {
+                        row_values[row].push(self.add_tag_value(name, values.value(row)));
+                    }
+                }
+                ColumnKind::Time => {
+                    let values = column.array::<Int64Array>();
+                    for row in valid {
+                        times[row] = values.value(row);
+                    }
+                }
+                ColumnKind::I64 => {
+                    let values = column.array::<Int64Array>();
+                    for row in valid {
+                        row_values[row].push(self.add_i64_value(name, values.value(row)));
+                    }
+                }
+                ColumnKind::F64 => {
+                    let values = column.array::<Float64Array>();
+                    for row in valid {
+                        row_values[row].push(self.add_f64_value(name, values.value(row)));
+                    }
+                }
+                ColumnKind::Bool => {
+                    let values = column.array::<BooleanArray>();
+                    for row in valid {
+                        row_values[row].push(self.add_bool_value(name, values.value(row)));
+                    }
+                }
+                ColumnKind::String => {
+                    let values = column.array::<StringArray>();
+                    for row in valid {
+                        row_values[row].push(self.add_string_value(name, values.value(row)));
+                    }
+                }
+            }
+        }
+
+        let mut entries = Vec::with_capacity(partition_rows.len());
+        for (partition_key, rows) in partition_rows {
+            let mut table_rows = Vec::with_capacity(rows.len());
+            for &row in rows {
+                let mut values = std::mem::take(&mut row_values[row]);
+                values.push(self.add_i64_value(TIME_COLUMN_NAME, times[row]));
+                let values = self.fbb.create_vector(&values);
+                table_rows.push(wb::Row::create(
+                    &mut self.fbb,
+                    &wb::RowArgs {
+                        values: Some(values),
+                    },
+                ));
+            }
+            let table_batch = self.finish_table_batch(&batch.table_name, &table_rows);
+
+            let batches_vec = self.fbb.create_vector(&[table_batch]);
+            let key = self.fbb.create_string(partition_key);
+            entries.push(wb::WriteBufferEntry::create(
+                &mut self.fbb,
+                &wb::WriteBufferEntryArgs {
+                    partition_key: Some(key),
+                    table_batches: Some(batches_vec),
+                    ..Default::default()
+                },
+            ));
+        }
+
+        entries
+    }
+
+    /// Adds a write buffer entry deleting the rows of the table
+    /// `table_name` that `predicate` matches
+    fn add_delete_entry(
+        &mut self,
+        table_name: &str,
+        predicate: &str,
+    ) -> flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>> {
+        let table_name = self.fbb.create_string(table_name);
+        let predicate = self.fbb.create_string(predicate);
+        let delete = wb::WriteBufferDelete::create(
+            &mut self.fbb,
+            &wb::WriteBufferDeleteArgs {
+                table_name: Some(table_name),
+                predicate: Some(predicate),
+            },
+        );
+
+        wb::WriteBufferEntry::create(
+            &mut self.fbb,
+            &wb::WriteBufferEntryArgs {
+                delete: Some(delete),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn add_line(&mut self, line: &'l ParsedLine<'_>) -> flatbuffers::WIPOffset<wb::Row<'a>> {
+        let mut row_values = std::mem::take(&mut self.row_values);
+        row_values.clear();
+
+        if let Some(tags) = &line.series.tag_set {
+            for (column, value) in tags {
+                row_values.push(self.add_tag_value(column.as_str(), value.as_str()));
+            }
+        }
+
+        for (column, value) in &line.field_set {
+            let val = match value {
+                FieldValue::I64(v) => self.add_i64_value(column.as_str(), *v),
+                FieldValue::F64(v) => self.add_f64_value(column.as_str(), *v),
+                FieldValue::Boolean(v) => self.add_bool_value(column.as_str(), *v),
+                FieldValue::String(v) => self.add_string_value(column.as_str(), v.as_str()),
+            };
+
+            row_values.push(val);
+        }
+
+        let time = line.timestamp.unwrap_or(self.default_time);
+        row_values.push(self.add_i64_value(TIME_COLUMN_NAME, time));
+
+        let values = self.fbb.create_vector(&row_values);
+        self.row_values = row_values;
+
+        wb::Row::create(
+            &mut self.fbb,
+            &wb::RowArgs {
+                values: Some(values),
+            },
+        )
+    }
+
+    /// Returns the offset of `s` in the flatbuffer, writing it if
+    /// it hasn't been written yet
+    fn shared_string(&mut self, s: &'l str) -> flatbuffers::WIPOffset<&'a str> {
+        let fbb = &mut self.fbb;
+        *self
+            .strings
+            .entry(s)
+            .or_insert_with(|| fbb.create_string(s))
+    }
+
+    fn add_tag_value(
+        &mut self,
+        column: &'l str,
+        value: &'l str,
+    ) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+        let value = self.shared_string(value);
+        let tv = wb::TagValue::create(&mut self.fbb, &wb::TagValueArgs { value: Some(value) });
+
+        self.add_value(column, wb::ColumnValue::TagValue, tv.as_union_value())
+    }
+
+    fn add_string_value(
+        &mut self,
+        column: &'l str,
+        value: &str,
+    ) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+        let value_offset = self.fbb.create_string(value);
+
+        let sv = wb::StringValue::create(
+            &mut self.fbb,
+            &wb::StringValueArgs {
+                value: Some(value_offset),
+            },
+        );
+
+        self.add_value(column, wb::ColumnValue::StringValue, sv.as_union_value())
+    }
+
+    fn add_f64_value(
+        &mut self,
+        column: &'l str,
+        value: f64,
+    ) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+        let fv = wb::F64Value::create(&mut self.fbb, &wb::F64ValueArgs { value });
+
+        self.add_value(column, wb::ColumnValue::F64Value, fv.as_union_value())
+    }
+
+    fn add_i64_value(
+        &mut self,
+        column: &'l str,
+        value: i64,
+    ) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+        let iv = wb::I64Value::create(&mut self.fbb, &wb::I64ValueArgs { value });
+
+        self.add_value(column, wb::ColumnValue::I64Value, iv.as_union_value())
+    }
+
+    fn add_bool_value(
+        &mut self,
+        column: &'l str,
+        value: bool,
+    ) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+        let bv = wb::BoolValue::create(&mut self.fbb, &wb::BoolValueArgs { value });
+
+        self.add_value(column, wb::ColumnValue::BoolValue, bv.as_union_value())
+    }
+
+    fn add_value(
+        &mut self,
+        column: &'l str,
+        value_type: wb::ColumnValue,
+        value: flatbuffers::WIPOffset<flatbuffers::UnionWIPOffset>,
+    ) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+        let column = self.shared_string(column);
+
+        wb::Value::create(
+            &mut self.fbb,
+            &wb::ValueArgs {
+                column: Some(column),
+                value_type,
+                value: Some(value),
+            },
+        )
+    }
 }
 
-fn add_value<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
-    column: &str,
-    value_type: wb::ColumnValue,
-    value: flatbuffers::WIPOffset<flatbuffers::UnionWIPOffset>,
-) -> flatbuffers::WIPOffset<wb::Value<'a>> {
-    let column = fbb.create_string(column);
-
-    wb::Value::create(
-        fbb,
-        &wb::ValueArgs {
-            column: Some(column),
-            value_type,
-            value: Some(value),
-        },
-    )
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_rules::{NonFiniteFloats, PartitionTemplate, TemplatePart};
+    use crate::schema::builder::SchemaBuilder;
+    use arrow_deps::arrow::{
+        array::UInt64Array,
+        datatypes::{Field, Schema as ArrowSchema},
+    };
+    use influxdb_line_protocol::parse_lines;
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = Cell::new(0);
+    }
+
+    /// Counts the allocations of each thread, so that a test can measure
+    /// its own while other tests run
+    struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T = (), E = TestError> = std::result::Result<T, E>;
+
+    /// Wraps a WriteBufferBatch with the specified version in a
+    /// ReplicatedWrite
+    fn write_with_version(version: u16) -> ReplicatedWrite {
+        let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+        let entries = fbb.create_vector::<flatbuffers::WIPOffset<wb::WriteBufferEntry<'_>>>(&[]);
+        let batch = wb::WriteBufferBatch::create(
+            &mut fbb,
+            &wb::WriteBufferBatchArgs {
+                entries: Some(entries),
+                version,
+            },
+        );
+        fbb.finish(batch, None);
+        let (mut data, idx) = fbb.collapse();
+        let entry_bytes = data.split_off(idx);
+
+        let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+        let payload = fbb.create_vector_direct(&entry_bytes);
+        let write = wb::ReplicatedWrite::create(
+            &mut fbb,
+            &wb::ReplicatedWriteArgs {
+                writer: 1,
+                sequence: 2,
+                checksum: 0,
+                payload: Some(payload),
+            },
+        );
+        fbb.finish(write, None);
+        let (mut data, idx) = fbb.collapse();
+        ReplicatedWrite {
+            data: data.split_off(idx),
+        }
+    }
+
+    #[test]
+    fn writes_current_version() -> TestResult {
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10").collect::<Result<_, _>>()?;
+        let write = lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default());
+
+        assert_eq!(
+            write.write_buffer_batch().unwrap().version(),
+            WRITE_BUFFER_BATCH_VERSION
+        );
+        assert_eq!(write.check_version()?, WRITE_BUFFER_BATCH_VERSION);
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_writes() -> TestResult {
+        let write = delete_to_replicated_write(1, 7, "cpu", "host = 'a'");
+        write.verify()?;
+        assert_eq!(write.check_version()?, WRITE_BUFFER_BATCH_VERSION);
+        assert_eq!(write.writer_and_sequence(), (1, 7));
+        assert_eq!(write.deletes(), vec![("cpu", "host = 'a'")]);
+
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10").collect::<Result<_, _>>()?;
+        let write = lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default());
+        assert!(write.deletes().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_version_is_migrated() -> TestResult {
+        let write = write_with_version(LEGACY_WRITE_BUFFER_BATCH_VERSION);
+        assert_eq!(write.check_version()?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let write = write_with_version(WRITE_BUFFER_BATCH_VERSION + 1);
+        let err = write.check_version().unwrap_err();
+        assert!(matches!(
+            err,
+            Error::UnsupportedVersion {
+                writer: 1,
+                sequence: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn missing_payload_is_rejected() {
+        let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+        let write = wb::ReplicatedWrite::create(
+            &mut fbb,
+            &wb::ReplicatedWriteArgs {
+                writer: 3,
+                sequence: 4,
+                checksum: 0,
+                payload: None,
+            },
+        );
+        fbb.finish(write, None);
+        let (mut data, idx) = fbb.collapse();
+        let write = ReplicatedWrite {
+            data: data.split_off(idx),
+        };
+
+        let err = write.check_version().unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MissingPayload {
+                writer: 3,
+                sequence: 4
+            }
+        ));
+    }
+
+    #[test]
+    fn batch_allocations_dont_grow_with_lines() -> TestResult {
+        let lp: String = (0..1000)
+            .map(|i| {
+                format!(
+                    "cpu,host=h{},region=west usage={},system={}i,idle={} {}\n",
+                    i % 10,
+                    i,
+                    i,
+                    i,
+                    i
+                )
+            })
+            .collect();
+        let lines: Vec<_> = parse_lines(&lp).collect::<Result<_, _>>()?;
+        let lines: Vec<_> = lines.iter().collect();
+
+        let before = allocations();
+        let mut builder = BatchBuilder::new(lines.len());
+        let entry = builder.add_write_entry(Some("p1"), &lines);
+        let bytes = builder.finish(&[entry]);
+        let allocated = allocations() - before;
+
+        // building the batch used to collect the values of each row in a
+        // vector of its own, so it allocated at least once a line; now
+        // only the buffers grow
+        assert!(allocated < lines.len() / 10, "{} allocations", allocated);
+
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&bytes);
+        let table = batch
+            .entries()
+            .unwrap()
+            .get(0)
+            .table_batches()
+            .unwrap()
+            .get(0);
+        assert_eq!(table.rows().unwrap().len(), lines.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_strings_are_shared() -> TestResult {
+        let lp = "cpu,host=a usage_system=1 10\n\
+                  cpu,host=a usage_system=2 20\n\
+                  cpu,host=b usage_system=3 30\n\
+                  mem,host=a usage_system=4 40";
+        let lines: Vec<_> = parse_lines(lp).collect::<Result<_, _>>()?;
+        let bytes = split_lines_into_write_entry_partitions(|_| "p1".to_string(), &lines);
+
+        // the column name is only written once for all rows and tables
+        let count = |s: &str| {
+            bytes
+                .windows(s.len())
+                .filter(|w| *w == s.as_bytes())
+                .count()
+        };
+        assert_eq!(count("usage_system"), 1);
+
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&bytes);
+        let entries = batch.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries.get(0).partition_key(), Some("p1"));
+
+        let mut rows = vec![];
+        for table in entries.get(0).table_batches().unwrap() {
+            for row in table.rows().unwrap() {
+                let values = row
+                    .values()
+                    .unwrap()
+                    .iter()
+                    .map(|value| {
+                        let val = match value.value_type() {
+                            wb::ColumnValue::TagValue => value
+                                .value_as_tag_value()
+                                .unwrap()
+                                .value()
+                                .unwrap()
+                                .to_string(),
+                            wb::ColumnValue::F64Value => {
+                                value.value_as_f64value().unwrap().value().to_string()
+                            }
+                            wb::ColumnValue::I64Value => {
+                                value.value_as_i64value().unwrap().value().to_string()
+                            }
+                            other => type_description(other).to_string(),
+                        };
+                        format!("{}={}", value.column().unwrap(), val)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                rows.push(format!("{} {}", table.name().unwrap(), values));
+            }
+        }
+
+        assert_eq!(
+            rows,
+            vec![
+                "cpu host=a usage_system=1 time=10",
+                "cpu host=a usage_system=2 time=20",
+                "cpu host=b usage_system=3 time=30",
+                "mem host=a usage_system=4 time=40",
+            ]
+        );
+
+        Ok(())
+    }
+
+    /// Returns the partitions, tables and rows of `write` as its `Display`
+    /// shows them, with the values of each row sorted, as batches and
+    /// lines order them differently
+    fn written_rows(write: &ReplicatedWrite) -> Vec<String> {
+        write
+            .to_string()
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with("writer:"))
+            .map(|line| {
+                let mut values: Vec<_> = line.split_whitespace().collect();
+                values.sort_unstable();
+                values.join(" ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn table_batches_are_written_like_lines() -> TestResult {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![
+                    TemplatePart::Column("region".to_string()),
+                    TemplatePart::TimeFormat("%Y".to_string()),
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // without metadata, the columns are mapped by name and type
+        let schema = Arc::new(ArrowSchema::new(vec![
+            Field::new("region", ArrowDataType::Utf8, true),
+            Field::new("usage", ArrowDataType::Float64, true),
+            Field::new("active", ArrowDataType::Boolean, true),
+            Field::new("time", ArrowDataType::Int64, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec![
+                Some("west"),
+                Some("east"),
+                None,
+                Some("west"),
+            ])),
+            Arc::new(Float64Array::from(vec![
+                Some(1.5),
+                Some(2.5),
+                Some(3.5),
+                None,
+            ])),
+            Arc::new(BooleanArray::from(vec![
+                Some(true),
+                None,
+                Some(false),
+                None,
+            ])),
+            Arc::new(Int64Array::from(vec![10, 20, 30, 40])),
+        ];
+        let batch = TableBatch::try_new("cpu", &RecordBatch::try_new(schema, columns)?)?;
+        let write = batch.to_replicated_write(1, 1, &rules)?;
+        write.verify()?;
+
+        // the last row has no field value, so it isn't a point
+        let lp = "cpu,region=west usage=1.5,active=true 10\n\
+                  cpu,region=east usage=2.5 20\n\
+                  cpu usage=3.5,active=false 30";
+        let lines: Vec<_> = parse_lines(lp).collect::<Result<_, _>>()?;
+        let expected = lines_to_replicated_write(1, 1, &lines, &rules);
+        assert_eq!(written_rows(&write), written_rows(&expected));
+        assert_eq!(
+            batch.partition_keys(&rules)?,
+            vec!["region_west-1970", "region_east-1970", "-1970"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn table_batch_columns_are_checked() -> TestResult {
+        // the metadata makes a string column a field
+        let schema = SchemaBuilder::new()
+            .tag("host")
+            .field("message", ArrowDataType::Utf8)
+            .timestamp()
+            .build()?;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["a"])),
+            Arc::new(StringArray::from(vec!["started"])),
+            Arc::new(Int64Array::from(vec![100])),
+        ];
+        let batch = TableBatch::try_new("events", &RecordBatch::try_new(schema.into(), columns)?)?;
+        let lines = batch.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].tag_value("host").unwrap(), &"a");
+        assert_eq!(
+            lines[0].field_value("message"),
+            Some(&FieldValue::String("started".into()))
+        );
+        assert_eq!(lines[0].timestamp, Some(100));
+
+        let schema = Arc::new(ArrowSchema::new(vec![
+            Field::new("count", ArrowDataType::UInt64, false),
+            Field::new("time", ArrowDataType::Int64, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(vec![1])),
+            Arc::new(Int64Array::from(vec![10])),
+        ];
+        let err = TableBatch::try_new("cpu", &RecordBatch::try_new(schema, columns)?).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedColumnType { .. }));
+
+        // the time column must hold nanoseconds as Int64
+        let schema = Arc::new(ArrowSchema::new(vec![
+            Field::new("usage", ArrowDataType::Float64, false),
+            Field::new("time", ArrowDataType::Float64, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Float64Array::from(vec![1.0])),
+            Arc::new(Float64Array::from(vec![10.0])),
+        ];
+        let err = TableBatch::try_new("cpu", &RecordBatch::try_new(schema, columns)?).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Column time of type Float64 of record batch can't be written"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn table_batches_are_transformed_like_lines() -> TestResult {
+        let mut transforms = WriteTransforms::default();
+        transforms
+            .inject_tags
+            .insert("source".to_string(), "router-1".to_string());
+        transforms
+            .rename_tags
+            .insert("hostname".to_string(), "host".to_string());
+        transforms.drop_fields.insert("debug".to_string());
+
+        let schema = Arc::new(ArrowSchema::new(vec![
+            Field::new("hostname", ArrowDataType::Utf8, true),
+            Field::new("host", ArrowDataType::Utf8, true),
+            Field::new("bar", ArrowDataType::Float64, true),
+            Field::new("debug", ArrowDataType::Boolean, true),
+            Field::new("time", ArrowDataType::Int64, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec![
+                Some("a"),
+                None,
+                Some("c"),
+                Some("d"),
+            ])),
+            Arc::new(StringArray::from(vec![
+                Some("x"),
+                Some("b"),
+                None,
+                Some("e"),
+            ])),
+            Arc::new(Float64Array::from(vec![
+                Some(1.0),
+                Some(2.0),
+                Some(f64::NAN),
+                None,
+            ])),
+            Arc::new(BooleanArray::from(vec![Some(true), None, None, Some(true)])),
+            Arc::new(Int64Array::from(vec![10, 20, 30, 40])),
+        ];
+        let mut batch = TableBatch::try_new("cpu", &RecordBatch::try_new(schema, columns)?)?;
+        assert_eq!(
+            batch.non_finite_fields(),
+            vec![(2, vec!["bar"])].into_iter().collect()
+        );
+
+        batch.remove_non_finite();
+        batch.apply_transforms(&transforms);
+
+        // line protocol has no literal for NaN, so it is set on the parsed
+        // line
+        let lp = "cpu,hostname=a,host=x bar=1,debug=true 10\n\
+                  cpu,host=b bar=2 20\n\
+                  cpu,hostname=c bar=3 30\n\
+                  cpu,hostname=d,host=e debug=true 40";
+        let mut lines: Vec<_> = parse_lines(lp).collect::<Result<_, _>>()?;
+        lines[2].field_set[0].1 = FieldValue::F64(f64::NAN);
+        NonFiniteFloats::remove(&mut lines);
+        transforms.apply(&mut lines);
+
+        let rules = DatabaseRules::default();
+        let write = batch.to_replicated_write(1, 1, &rules)?;
+        let expected = lines_to_replicated_write(1, 1, &lines, &rules);
+        assert_eq!(written_rows(&write), written_rows(&expected));
+        let rows: Vec<_> = written_rows(&write)
+            .into_iter()
+            .filter(|row| row.starts_with("bar:"))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                "bar:1 host:a source:router-1 time:10",
+                "bar:2 host:b source:router-1 time:20",
+            ]
+        );
+
+        // an ingest time written by the client is replaced
+        batch.set_ingest_time(5);
+        let lines = batch.lines();
+        assert_eq!(lines.len(), 2);
+        for line in &lines {
+            assert_eq!(
+                line.field_value(INGEST_TIME_COLUMN_NAME),
+                Some(&FieldValue::I64(5))
+            );
+        }
+
+        Ok(())
+    }
 }
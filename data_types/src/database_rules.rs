@@ -1,8 +1,17 @@
-use influxdb_line_protocol::ParsedLine;
+use influxdb_line_protocol::{EscapedStr, FieldValue, ParsedLine};
 
-use chrono::{DateTime, TimeZone, Utc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    convert::TryFrom,
+    fmt::Write,
+};
+
+use chrono::{DateTime, TimeZone as _, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
+
+use crate::{access_policy::AccessPolicy, time_zone::TimeZone};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -11,6 +20,12 @@ pub enum Error {
         source_module: &'static str,
         source: Box<dyn std::error::Error + Send + Sync + 'static>,
     },
+
+    #[snafu(display("Invalid table write rule regex {}: {}", regex, source))]
+    InvalidTableRegex { regex: String, source: regex::Error },
+
+    #[snafu(display("Partition template part {} is not supported", part))]
+    UnsupportedTemplatePart { part: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -100,6 +115,78 @@ pub struct DatabaseRules {
     /// configuration.
     #[serde(default)]
     pub wal_buffer_config: Option<WalBufferConfig>,
+
+    /// Restricts which tables writes may create or add rows to. By
+    /// default any table can be written.
+    #[serde(default)]
+    pub table_write_rules: TableWriteRules,
+
+    /// Changes made to the tags and fields of every line written, before
+    /// it is partitioned
+    #[serde(default)]
+    pub write_transforms: WriteTransforms,
+
+    /// Queries run periodically by the server to aggregate tables of this
+    /// database into other tables
+    #[serde(default)]
+    pub continuous_queries: Vec<ContinuousQuery>,
+
+    /// Limits on the resources writes and queries to this database may
+    /// use, so one database can't starve the others on the server
+    #[serde(default)]
+    pub quotas: DatabaseQuotas,
+
+    /// If set to `true`, the server adds the time it accepted each line,
+    /// in nanoseconds since the epoch, as the `_ingest_time` field
+    /// (`INGEST_TIME_COLUMN_NAME`). Queries can use it to keep the last
+    /// written of several points with the same timestamp, or to measure
+    /// how long data took to arrive. SQL queries only return it when
+    /// they name it: `SELECT *` leaves it out.
+    #[serde(default)]
+    pub record_ingest_time: bool,
+
+    /// If set, only the principals of the policy can query the database,
+    /// and their queries only see the rows the policy allows them to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_policy: Option<AccessPolicy>,
+
+    /// How the Parquet files of the snapshots of this database are written
+    #[serde(default)]
+    pub parquet_config: ParquetConfig,
+
+    /// If set, the open chunk of each partition of the mutable buffer is
+    /// closed once it reaches a size adapted to the ingest rate of the
+    /// partition. Otherwise chunks are only closed when their dictionary
+    /// is full or they are rolled over.
+    #[serde(default)]
+    pub chunk_sizing: Option<ChunkSizing>,
+
+    /// Limits the dictionary each chunk of the mutable buffer stores its
+    /// table names, column names and tag values in. The open chunk of a
+    /// partition is closed before a write that would not fit in its
+    /// dictionary.
+    #[serde(default)]
+    pub chunk_dictionary: ChunkDictionaryConfig,
+
+    /// The order of the tags in the series keys of tables, which series
+    /// are sorted and grouped by in the mutable buffer
+    #[serde(default)]
+    pub tag_orders: TagOrders,
+
+    /// How long the data of each table is kept
+    #[serde(default)]
+    pub retention: RetentionRules,
+
+    /// What is done with float field values that are NaN or infinite
+    #[serde(default)]
+    pub non_finite_floats: NonFiniteFloats,
+
+    /// If set, points with timestamps more than this far ahead of the
+    /// server's clock, usually sent by clients with skewed clocks, are
+    /// written to the partition `QUARANTINE_PARTITION_KEY` rather than
+    /// creating partitions for times that haven't come yet
+    #[serde(default)]
+    pub future_time_cap: Option<std::time::Duration>,
 }
 
 impl DatabaseRules {
@@ -108,8 +195,279 @@ impl DatabaseRules {
         line: &ParsedLine<'_>,
         default_time: &DateTime<Utc>,
     ) -> Result<String> {
+        if is_after(line, self.future_time_limit(default_time)) {
+            return Ok(QUARANTINE_PARTITION_KEY.to_string());
+        }
         self.partition_template.partition_key(line, default_time)
     }
+
+    /// Returns a generator for the partition keys of a batch of lines,
+    /// which is faster than calling `partition_key` for each line
+    pub fn partition_key_generator<'a>(
+        &'a self,
+        default_time: &'a DateTime<Utc>,
+    ) -> PartitionKeyGenerator<'a> {
+        let mut generator = self.partition_template.key_generator(default_time);
+        generator.future_time_limit = self.future_time_limit(default_time);
+        generator
+    }
+
+    /// Returns the latest timestamp, in nanoseconds since the epoch, of the
+    /// points that aren't quarantined when written at `now`, if the future
+    /// time is capped
+    pub fn future_time_limit(&self, now: &DateTime<Utc>) -> Option<i64> {
+        self.future_time_cap.map(|cap| {
+            let cap = i64::try_from(cap.as_nanos()).unwrap_or(i64::MAX);
+            now.timestamp_nanos().saturating_add(cap)
+        })
+    }
+}
+
+/// The key of the partition the points with timestamps past the future time
+/// cap of a database are written to
+pub const QUARANTINE_PARTITION_KEY: &str = "_quarantine";
+
+/// Returns true if `point` has a timestamp later than `limit`
+fn is_after<P: PartitionKeySource + ?Sized>(point: &P, limit: Option<i64>) -> bool {
+    matches!((point.timestamp(), limit), (Some(timestamp), Some(limit)) if timestamp > limit)
+}
+
+/// The values of a point its partition key is computed from, so that
+/// points that weren't parsed from line protocol, such as the rows of a
+/// record batch, are partitioned like lines
+pub trait PartitionKeySource {
+    /// The name of the table of the point
+    fn measurement(&self) -> &str;
+
+    /// The value of the tag `tag` of the point, if it has one
+    fn tag_value(&self, tag: &str) -> Option<&str>;
+
+    /// The value of the field `field` of the point, if it has one
+    fn field_value(&self, field: &str) -> Option<FieldValue<'_>>;
+
+    /// The timestamp of the point, in nanoseconds since the epoch, if it
+    /// has one
+    fn timestamp(&self) -> Option<i64>;
+}
+
+impl PartitionKeySource for ParsedLine<'_> {
+    fn measurement(&self) -> &str {
+        self.series.measurement.as_str()
+    }
+
+    fn tag_value(&self, tag: &str) -> Option<&str> {
+        ParsedLine::tag_value(self, tag).map(|value| value.as_str())
+    }
+
+    fn field_value(&self, field: &str) -> Option<FieldValue<'_>> {
+        ParsedLine::field_value(self, field).cloned()
+    }
+
+    fn timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+}
+
+/// `DatabaseQuotas` limit the resources used by a database. Writes and
+/// queries exceeding a quota are rejected rather than delayed, so clients
+/// can back off and retry. Each quota is unlimited unless set.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct DatabaseQuotas {
+    /// The maximum number of lines written per second
+    #[serde(default)]
+    pub max_lines_per_second: Option<u64>,
+    /// The maximum number of bytes of line protocol written per second
+    #[serde(default)]
+    pub max_bytes_per_second: Option<u64>,
+    /// The maximum number of distinct series (a table and set of tag
+    /// values) written since the database was loaded
+    #[serde(default)]
+    pub max_series: Option<u64>,
+    /// The maximum number of distinct partition keys written since the
+    /// database was loaded, which bounds the partitions created by
+    /// templates referencing high cardinality tags
+    #[serde(default)]
+    pub max_partitions: Option<u64>,
+    /// Writes are rejected while the mutable buffer uses at least this
+    /// many bytes
+    #[serde(default)]
+    pub max_memory_bytes: Option<u64>,
+    /// The maximum number of queries running at the same time
+    #[serde(default)]
+    pub max_concurrent_queries: Option<usize>,
+}
+
+/// `ChunkDictionaryConfig` limits the size of the dictionary of each chunk
+/// of the mutable buffer. Each limit is the largest the dictionary allows
+/// unless set. The dictionaries of the chunks of a database may share the
+/// memory of their strings, which saves memory when chunks repeat the same
+/// tag values.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone, Copy)]
+pub struct ChunkDictionaryConfig {
+    /// The maximum number of distinct strings in the dictionary
+    #[serde(default)]
+    pub max_entries: Option<usize>,
+    /// The maximum total size, in bytes, of the distinct strings in the
+    /// dictionary
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    /// Keep one copy of each string across the dictionaries of all chunks
+    /// of the database
+    #[serde(default)]
+    pub shared: bool,
+}
+
+/// `ChunkSizing` sizes chunks by how fast their partition is written to,
+/// so the files persisted from them land in a size band whatever the
+/// workload: a chunk is closed once it holds about `target_window_seconds`
+/// of writes at the ingest rate of its partition, but never before it
+/// holds `min_chunk_bytes`, and no later than when it holds
+/// `max_chunk_bytes`.
+///
+/// Sizes are those of chunks in memory. Parquet files are usually several
+/// times smaller, so the band should be scaled by the compression ratio of
+/// the data.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub struct ChunkSizing {
+    /// How many seconds of writes each chunk should hold
+    pub target_window_seconds: u64,
+    /// The size chunks are kept open until, however slowly their partition
+    /// is written to
+    pub min_chunk_bytes: u64,
+    /// The size chunks are closed at, however fast their partition is
+    /// written to
+    pub max_chunk_bytes: u64,
+}
+
+impl ChunkSizing {
+    /// Returns the size to close the open chunk of a partition at, given
+    /// the ingest rate of the partition in bytes per second, if known. The
+    /// size is clamped to the band, and is its maximum until the rate is
+    /// known.
+    pub fn close_size(&self, bytes_per_second: Option<f64>) -> u64 {
+        let max = self.max_chunk_bytes.max(self.min_chunk_bytes);
+        match bytes_per_second {
+            Some(rate) => {
+                let window_bytes = rate * self.target_window_seconds as f64;
+                if window_bytes >= max as f64 {
+                    max
+                } else {
+                    (window_bytes as u64).max(self.min_chunk_bytes)
+                }
+            }
+            None => max,
+        }
+    }
+}
+
+/// `TagOrders` sets the order of the tags in the series keys of tables.
+/// Series are sorted by the values of their tags in this order, so
+/// `read_filter` and the other series queries return the series sharing
+/// the first tags next to each other, and merge them with the least work
+/// when those tags are the ones queries group by. The rows of read buffer
+/// chunks and snapshots are sorted by their series keys in this order too,
+/// and then by time. For each table listed, its tags come first, in the
+/// order listed, and then its other tags by name. The tags of tables not
+/// listed are ordered by name.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct TagOrders {
+    /// The tags that come first, by table name
+    #[serde(default)]
+    pub tables: BTreeMap<String, Vec<String>>,
+}
+
+impl TagOrders {
+    /// Returns the key the tag `tag` of table `table` is sorted by to put
+    /// the tags of the table in the order of its series keys
+    pub fn sort_key<'a>(&self, table: &str, tag: &'a str) -> (usize, &'a str) {
+        let order = self.tables.get(table).map(Vec::as_slice).unwrap_or(&[]);
+        let position = order.iter().position(|first| first == tag);
+        (position.unwrap_or(order.len()), tag)
+    }
+}
+
+/// `RetentionRules` set how long data is kept. The data of a table in a
+/// chunk is dropped once its newest row is older than the retention period
+/// of the table: its override in `tables` if it has one, and `period`
+/// otherwise. Data is kept forever if neither is set.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct RetentionRules {
+    /// The retention period of the tables without an override
+    #[serde(default)]
+    pub period: Option<std::time::Duration>,
+    /// The retention periods overriding `period`, by table name
+    #[serde(default)]
+    pub tables: BTreeMap<String, std::time::Duration>,
+}
+
+impl RetentionRules {
+    /// Returns the retention period of the table `table`, if it has one
+    pub fn period(&self, table: &str) -> Option<std::time::Duration> {
+        self.tables.get(table).copied().or(self.period)
+    }
+
+    /// Returns true if the data of the table `table` whose rows span
+    /// `time_range` has expired at `now`, in nanoseconds since the epoch.
+    /// Data with rows without a timestamp never expires.
+    pub fn is_expired(&self, table: &str, time_range: Option<(i64, i64)>, now: i64) -> bool {
+        match (self.boundary(table, now), time_range) {
+            (Some(boundary), Some((_, max))) => max < boundary,
+            _ => false,
+        }
+    }
+
+    /// Returns the retention boundary of the table `table` at `now`: the
+    /// timestamp rows must have at least to be retained, if the table has
+    /// a retention period
+    pub fn boundary(&self, table: &str, now: i64) -> Option<i64> {
+        self.period(table)
+            .map(|period| retention_boundary(period, now))
+    }
+
+    /// Returns the retention boundary at `now` of the tables without an
+    /// override, if `period` is set
+    pub fn default_boundary(&self, now: i64) -> Option<i64> {
+        self.period.map(|period| retention_boundary(period, now))
+    }
+}
+
+fn retention_boundary(period: std::time::Duration, now: i64) -> i64 {
+    let period = i64::try_from(period.as_nanos()).unwrap_or(i64::MAX);
+    now.saturating_sub(period)
+}
+
+/// `ParquetConfig` controls how the Parquet files of snapshots are written.
+/// Each setting is left to the Parquet writer unless set.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct ParquetConfig {
+    /// The codec the pages of every column are compressed with
+    #[serde(default)]
+    pub compression: ParquetCompression,
+    /// Whether columns are dictionary encoded. Dictionaries shrink tags
+    /// and other columns with few distinct values, but rarely floats.
+    #[serde(default)]
+    pub dictionary_enabled: Option<bool>,
+    /// The approximate maximum size in bytes of each data page
+    #[serde(default)]
+    pub data_page_size: Option<usize>,
+}
+
+/// The compression codecs of Parquet files
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ParquetCompression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Brotli,
+    Lz4,
+    Zstd,
+}
+
+impl Default for ParquetCompression {
+    fn default() -> Self {
+        Self::Uncompressed
+    }
 }
 
 /// WalBufferConfig defines the configuration for buffering data from the WAL in
@@ -145,6 +503,42 @@ pub struct WalBufferConfig {
     /// if they haven't hit the size threshold. This allows them to be written
     /// out to object storage as they must be immutable first.
     pub close_segment_after: Option<std::time::Duration>,
+    /// Where segments are written when `store_segments` is set
+    #[serde(default)]
+    pub segment_storage: WalSegmentStorage,
+    /// If set, replaying the stored segments when the database is
+    /// recovered skips the writes that fail verification, keeping the
+    /// valid writes of their segments. Otherwise an invalid write fails
+    /// the recovery of the database.
+    #[serde(default)]
+    pub skip_invalid_writes: bool,
+}
+
+/// WalSegmentStorage selects the backend closed WAL segments of a database
+/// are written to.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WalSegmentStorage {
+    /// The object store of the server, under the directory of the database
+    ObjectStore,
+    /// A backend registered with the server under this name, such as a
+    /// directory of the local file system or an external log service
+    Backend { name: String },
+    /// A directory of the local file system, such as one on a device
+    /// dedicated to the WAL. Writes are rejected while less than
+    /// `min_free_bytes` are free on its file system, rather than failing
+    /// once segments can no longer be written.
+    Directory {
+        path: String,
+        #[serde(default)]
+        min_free_bytes: u64,
+    },
+}
+
+impl Default for WalSegmentStorage {
+    fn default() -> Self {
+        Self::ObjectStore
+    }
 }
 
 /// WalBufferRollover defines the behavior of what should happen if a write
@@ -169,43 +563,270 @@ pub enum WalBufferRollover {
 /// a formatted time, or a string column and regex captures of its value. For
 /// columns that do not appear in the input row, a blank value is output.
 ///
+/// A time format can reference the values of tags as `{tag}`, e.g.
+/// `%Y-%m-%d_{region}` partitions by day and region. As line protocol tag
+/// values can't be empty, lines without the tag get a blank value that
+/// can't collide with the partitions of any tag value. Characters other
+/// than ASCII letters, digits and `_` in tag values are percent-encoded.
+///
 /// The key is constructed in order of the template parts; thus ordering changes
 /// what partition key is generated.
 #[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
 pub struct PartitionTemplate {
     pub parts: Vec<TemplatePart>,
+    /// The time zone times are formatted in, so that e.g. daily
+    /// partitions start at local midnight. Defaults to UTC.
+    #[serde(default, skip_serializing_if = "TimeZone::is_utc")]
+    pub time_zone: TimeZone,
 }
 
 impl PartitionTemplate {
+    /// Returns the partition key for `line`. Use a `key_generator` to
+    /// compute the keys of many lines.
     pub fn partition_key(
         &self,
         line: &ParsedLine<'_>,
         default_time: &DateTime<Utc>,
     ) -> Result<String> {
-        let parts: Vec<_> = self
+        self.key_generator(default_time).partition_key(line)
+    }
+
+    /// Returns a generator for the partition keys of a batch of lines
+    pub fn key_generator<'a>(
+        &'a self,
+        default_time: &'a DateTime<Utc>,
+    ) -> PartitionKeyGenerator<'a> {
+        let time_formats = self
             .parts
             .iter()
             .map(|p| match p {
-                TemplatePart::Table => line.series.measurement.to_string(),
-                TemplatePart::Column(column) => match line.tag_value(&column) {
+                TemplatePart::TimeFormat(format) => {
+                    Some(TimeFormatCache::new(format, &self.time_zone))
+                }
+                _ => None,
+            })
+            .collect();
+
+        PartitionKeyGenerator {
+            template: self,
+            default_time,
+            time_formats,
+            future_time_limit: None,
+        }
+    }
+}
+
+/// The maximum number of formatted times cached for each time format
+/// part of a `PartitionKeyGenerator`
+const MAX_CACHED_TIMES: usize = 1024;
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+const NANOS_PER_MINUTE: i64 = 60 * NANOS_PER_SECOND;
+const NANOS_PER_HOUR: i64 = 60 * NANOS_PER_MINUTE;
+const NANOS_PER_DAY: i64 = 24 * NANOS_PER_HOUR;
+
+/// Computes the partition keys of many lines using a
+/// `PartitionTemplate`.
+///
+/// Formatting timestamps dominates the cost of computing partition
+/// keys. As all timestamps within the same period (e.g. the same
+/// hour for a format with hour resolution) are formatted the same,
+/// the generator buckets each timestamp arithmetically and only
+/// formats a timestamp the first time its bucket is seen.
+#[derive(Debug)]
+pub struct PartitionKeyGenerator<'a> {
+    template: &'a PartitionTemplate,
+    default_time: &'a DateTime<Utc>,
+
+    /// The cache for each part of the template that is a time format
+    time_formats: Vec<Option<TimeFormatCache>>,
+
+    /// The points with later timestamps are quarantined
+    future_time_limit: Option<i64>,
+}
+
+impl<'a> PartitionKeyGenerator<'a> {
+    /// Returns the partition key for `point`, usually a line
+    pub fn partition_key<P: PartitionKeySource + ?Sized>(&mut self, point: &P) -> Result<String> {
+        if is_after(point, self.future_time_limit) {
+            return Ok(QUARANTINE_PARTITION_KEY.to_string());
+        }
+
+        let default_time = self.default_time;
+        let parts = self
+            .template
+            .parts
+            .iter()
+            .zip(self.time_formats.iter_mut())
+            .map(|(p, time_format)| match p {
+                TemplatePart::Table => Ok(point.measurement().to_string()),
+                TemplatePart::Column(column) => Ok(match point.tag_value(&column) {
                     Some(v) => format!("{}_{}", column, v),
-                    None => match line.field_value(&column) {
+                    None => match point.field_value(&column) {
                         Some(v) => format!("{}_{}", column, v),
                         None => "".to_string(),
                     },
-                },
-                TemplatePart::TimeFormat(format) => match line.timestamp {
-                    Some(t) => Utc.timestamp_nanos(t).format(&format).to_string(),
-                    None => default_time.format(&format).to_string(),
-                },
-                _ => unimplemented!(),
+                }),
+                TemplatePart::TimeFormat(format) => {
+                    let time_format = time_format
+                        .as_mut()
+                        .expect("time format parts have a cache");
+                    let nanos = point
+                        .timestamp()
+                        .unwrap_or_else(|| default_time.timestamp_nanos());
+                    Ok(substitute_tag_values(
+                        time_format.format(format, nanos),
+                        point,
+                    ))
+                }
+                TemplatePart::RegexCapture(_) => UnsupportedTemplatePart {
+                    part: "RegexCapture",
+                }
+                .fail(),
+                TemplatePart::StrftimeColumn(_) => UnsupportedTemplatePart {
+                    part: "StrftimeColumn",
+                }
+                .fail(),
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(parts.join("-"))
     }
 }
 
+/// Replaces each `{tag}` in the formatted time `formatted` with the value
+/// of the tag of `point`, or nothing if `point` doesn't have the tag.
+///
+/// Tag values are percent-encoded (every byte but ASCII letters, digits
+/// and `_`), so they can't introduce path separators or the `-` that
+/// joins the parts of a partition key.
+fn substitute_tag_values<P: PartitionKeySource + ?Sized>(formatted: String, point: &P) -> String {
+    if !formatted.contains('{') {
+        return formatted;
+    }
+
+    let mut substituted = String::with_capacity(formatted.len());
+    let mut rest = formatted.as_str();
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        substituted.push_str(&rest[..start]);
+        if let Some(value) = point.tag_value(&rest[start + 1..end]) {
+            for byte in value.bytes() {
+                if byte.is_ascii_alphanumeric() || byte == b'_' {
+                    substituted.push(byte as char);
+                } else {
+                    write!(substituted, "%{:02X}", byte).expect("writing to a String");
+                }
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    substituted.push_str(rest);
+
+    substituted
+}
+
+/// Caches the formatted timestamps of a time format part of a
+/// template, by bucket
+#[derive(Debug)]
+struct TimeFormatCache {
+    /// The width of the periods of time that are all formatted the
+    /// same, or None if the format isn't understood (in which case
+    /// every timestamp is formatted)
+    bucket_nanos: Option<i64>,
+
+    /// The time zone times are formatted in. Buckets are aligned to its
+    /// midnight rather than UTC's.
+    time_zone: TimeZone,
+
+    /// (bucket, offset from UTC) -> formatted time. The offset is part of
+    /// the key as it changes within a bucket when daylight saving time
+    /// ends.
+    formatted: HashMap<(i64, i32), String>,
+
+    /// The keys of `formatted`, oldest first, so the oldest can be
+    /// evicted once it is full
+    buckets: VecDeque<(i64, i32)>,
+}
+
+impl TimeFormatCache {
+    fn new(format: &str, time_zone: &TimeZone) -> Self {
+        Self {
+            bucket_nanos: bucket_nanos(format),
+            time_zone: time_zone.clone(),
+            formatted: HashMap::new(),
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn format(&mut self, format: &str, nanos: i64) -> String {
+        let offset = self.time_zone.offset_at(nanos);
+        let bucket_nanos = match self.bucket_nanos {
+            Some(bucket_nanos) => bucket_nanos,
+            None => return offset.timestamp_nanos(nanos).format(format).to_string(),
+        };
+
+        let offset_seconds = offset.local_minus_utc();
+        let local_nanos = nanos.saturating_add(i64::from(offset_seconds) * NANOS_PER_SECOND);
+        let key = (local_nanos.div_euclid(bucket_nanos), offset_seconds);
+        if let Some(formatted) = self.formatted.get(&key) {
+            return formatted.clone();
+        }
+
+        if self.formatted.len() >= MAX_CACHED_TIMES {
+            if let Some(oldest) = self.buckets.pop_front() {
+                self.formatted.remove(&oldest);
+            }
+        }
+        let formatted = offset.timestamp_nanos(nanos).format(format).to_string();
+        self.formatted.insert(key, formatted.clone());
+        self.buckets.push_back(key);
+        formatted
+    }
+}
+
+/// Returns the width of the (epoch aligned) periods of time within
+/// which all timestamps produce the same output for the strftime
+/// `format`, or None if the format contains specifiers whose
+/// resolution isn't known.
+///
+/// Months and years are not a fixed width, but each consists of
+/// whole (UTC) days, so day buckets are used for them.
+fn bucket_nanos(format: &str) -> Option<i64> {
+    // a format without any time specifiers is the same for all times
+    let mut bucket_nanos = i64::MAX;
+
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+
+        // skip padding modifiers
+        let mut specifier = chars.next()?;
+        while matches!(specifier, '-' | '_' | '0') {
+            specifier = chars.next()?;
+        }
+
+        let specifier_nanos = match specifier {
+            '%' | 'n' | 't' | 'Z' | 'z' => continue,
+            'S' | 's' | 'T' | 'X' | 'r' | 'c' => NANOS_PER_SECOND,
+            'M' | 'R' => NANOS_PER_MINUTE,
+            'H' | 'I' | 'k' | 'l' | 'p' | 'P' => NANOS_PER_HOUR,
+            'Y' | 'C' | 'y' | 'G' | 'g' | 'm' | 'b' | 'B' | 'h' | 'd' | 'e' | 'a' | 'A' | 'w'
+            | 'u' | 'U' | 'W' | 'V' | 'j' | 'D' | 'x' | 'F' | 'v' => NANOS_PER_DAY,
+            // e.g. fractional seconds
+            _ => return None,
+        };
+        bucket_nanos = bucket_nanos.min(specifier_nanos);
+    }
+
+    Some(bucket_nanos)
+}
+
 /// `TemplatePart` specifies what part of a row should be used to compute this
 /// part of a partition key.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -277,6 +898,319 @@ pub enum MatchTables {
     Regex(String),
 }
 
+/// `TableWriteRules` allow or block writes to tables by name, so that
+/// a database shared by several teams can stop one of them from
+/// creating arbitrary tables.
+///
+/// A table may be written if it matches no `deny` rule and, when there
+/// are any `allow` rules, at least one of those. `Regex` rules must
+/// match the whole table name.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct TableWriteRules {
+    /// If not empty, only tables matching one of these can be written
+    #[serde(default)]
+    pub allow: Vec<MatchTables>,
+    /// Tables matching any of these can not be written, even if they are
+    /// allowed
+    #[serde(default)]
+    pub deny: Vec<MatchTables>,
+}
+
+impl TableWriteRules {
+    /// Compiles the rules into a `TableWriteFilter`, returning an error
+    /// if any of the regexes are invalid
+    pub fn filter(&self) -> Result<TableWriteFilter> {
+        Ok(TableWriteFilter {
+            allow: compile_table_matchers(&self.allow)?,
+            deny: compile_table_matchers(&self.deny)?,
+        })
+    }
+}
+
+/// Checks table names against compiled `TableWriteRules`. The default
+/// filter allows every table.
+#[derive(Debug, Default)]
+pub struct TableWriteFilter {
+    allow: Vec<TableMatcher>,
+    deny: Vec<TableMatcher>,
+}
+
+impl TableWriteFilter {
+    /// Returns a filter that rejects every table
+    pub fn deny_all() -> Self {
+        Self {
+            allow: vec![],
+            deny: vec![TableMatcher::All],
+        }
+    }
+
+    /// Returns why `table` may not be written, if it may not
+    pub fn check(&self, table: &str) -> Option<TableWriteRejection> {
+        if self.deny.iter().any(|m| m.matches(table)) {
+            Some(TableWriteRejection::Denied)
+        } else if !self.allow.is_empty() && !self.allow.iter().any(|m| m.matches(table)) {
+            Some(TableWriteRejection::NotAllowed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Why `TableWriteRules` reject writes to a table
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TableWriteRejection {
+    /// The table matches a `deny` rule
+    Denied,
+    /// There are `allow` rules and the table matches none of them
+    NotAllowed,
+}
+
+impl std::fmt::Display for TableWriteRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Denied => write!(f, "matches a deny rule"),
+            Self::NotAllowed => write!(f, "matches no allow rule"),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum TableMatcher {
+    All,
+    Table(String),
+    Regex(Regex),
+}
+
+impl TableMatcher {
+    fn matches(&self, table: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Table(name) => name == table,
+            Self::Regex(regex) => regex.is_match(table),
+        }
+    }
+}
+
+fn compile_table_matchers(rules: &[MatchTables]) -> Result<Vec<TableMatcher>> {
+    rules
+        .iter()
+        .map(|rule| match rule {
+            MatchTables::All => Ok(TableMatcher::All),
+            MatchTables::Table(name) => Ok(TableMatcher::Table(name.clone())),
+            MatchTables::Regex(regex) => Regex::new(&format!("^(?:{})$", regex))
+                .map(TableMatcher::Regex)
+                .context(InvalidTableRegex { regex }),
+        })
+        .collect()
+}
+
+/// `WriteTransforms` normalize the lines written to a database, so that
+/// data from agents that tag or name things differently can be made
+/// consistent at the database instead of in every client.
+///
+/// Transforms are applied in the order: drop fields, rename fields,
+/// rename tags, inject tags. Names are always the unescaped names.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct WriteTransforms {
+    /// Tags added to every line, e.g. `source=router-1`. An injected tag
+    /// replaces any tag of the same name already on the line.
+    #[serde(default)]
+    pub inject_tags: BTreeMap<String, String>,
+    /// Tags to rename, from the old name to the new one. A renamed tag
+    /// replaces any tag already on the line with the new name.
+    #[serde(default)]
+    pub rename_tags: BTreeMap<String, String>,
+    /// Fields to rename, from the old name to the new one. A renamed
+    /// field replaces any field already on the line with the new name.
+    #[serde(default)]
+    pub rename_fields: BTreeMap<String, String>,
+    /// Fields removed from every line. Lines left without any fields are
+    /// not written.
+    #[serde(default)]
+    pub drop_fields: BTreeSet<String>,
+}
+
+impl WriteTransforms {
+    /// Returns true if there are no transforms, so lines are written
+    /// unchanged
+    pub fn is_empty(&self) -> bool {
+        self.inject_tags.is_empty()
+            && self.rename_tags.is_empty()
+            && self.rename_fields.is_empty()
+            && self.drop_fields.is_empty()
+    }
+
+    /// Applies the transforms to `lines`, removing any left without
+    /// fields
+    pub fn apply<'a>(&'a self, lines: &mut Vec<ParsedLine<'a>>) {
+        for line in lines.iter_mut() {
+            self.apply_to_line(line);
+        }
+        lines.retain(|line| !line.field_set.is_empty());
+    }
+
+    fn apply_to_line<'a>(&'a self, line: &mut ParsedLine<'a>) {
+        let drop_fields = &self.drop_fields;
+        line.field_set
+            .retain(|(name, _)| !drop_fields.contains(name.as_str()));
+
+        for (old, new) in self.rename_fields.iter().filter(|(old, new)| old != new) {
+            if line.field_set.iter().any(|(name, _)| name == old) {
+                line.field_set.retain(|(name, _)| name != new);
+                for (name, _) in line.field_set.iter_mut().filter(|(name, _)| name == old) {
+                    *name = EscapedStr::from(new.as_str());
+                }
+            }
+        }
+
+        let tag_set = line.series.tag_set.get_or_insert_with(Default::default);
+        for (old, new) in self.rename_tags.iter().filter(|(old, new)| old != new) {
+            if tag_set.iter().any(|(name, _)| name == old) {
+                tag_set.retain(|(name, _)| name != new);
+                for (name, _) in tag_set.iter_mut().filter(|(name, _)| name == old) {
+                    *name = EscapedStr::from(new.as_str());
+                }
+            }
+        }
+
+        for (name, value) in &self.inject_tags {
+            tag_set.retain(|(tag, _)| tag != name);
+            tag_set.push((
+                EscapedStr::from(name.as_str()),
+                EscapedStr::from(value.as_str()),
+            ));
+        }
+
+        // keep the tags sorted, as series keys expect, and stop the series
+        // from being keyed by the tags it was parsed with
+        tag_set.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        if tag_set.is_empty() {
+            line.series.tag_set = None;
+        }
+        line.series.forget_raw_input();
+    }
+}
+
+/// `NonFiniteFloats` is what is done with float field values that are NaN
+/// or infinite when lines are written. Such values make aggregates NaN or
+/// infinite too, and NaN doesn't compare equal to anything, including
+/// itself.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum NonFiniteFloats {
+    /// The values are written as they are
+    Keep,
+    /// Writes with any such values are rejected
+    Reject,
+    /// The fields with such values are removed from their lines, so the
+    /// values are null. Lines left without fields are not written.
+    Null,
+}
+
+impl Default for NonFiniteFloats {
+    fn default() -> Self {
+        Self::Keep
+    }
+}
+
+impl NonFiniteFloats {
+    /// Returns the names of the fields of `line` whose values are NaN or
+    /// infinite floats
+    pub fn fields<'a>(line: &'a ParsedLine<'_>) -> Vec<&'a str> {
+        line.field_set
+            .iter()
+            .filter(|(_, value)| is_non_finite(value))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Removes the fields whose values are NaN or infinite floats from
+    /// `lines`, removing any lines left without fields
+    pub fn remove(lines: &mut Vec<ParsedLine<'_>>) {
+        for line in lines.iter_mut() {
+            line.field_set.retain(|(_, value)| !is_non_finite(value));
+        }
+        lines.retain(|line| !line.field_set.is_empty());
+    }
+}
+
+fn is_non_finite(value: &FieldValue<'_>) -> bool {
+    matches!(value, FieldValue::F64(v) if !v.is_finite())
+}
+
+/// `ContinuousQuery` aggregates a table of the database into another
+/// table one window of time at a time, e.g. to downsample it.
+///
+/// Windows are aligned to the epoch and are aggregated once they have
+/// closed, including windows that closed while the server was down.
+/// Rows written to a window after it has been aggregated are not
+/// included in its results.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct ContinuousQuery {
+    /// Identifies the query within its database
+    pub name: String,
+    /// The table that is aggregated
+    pub source_table: String,
+    /// The width of each window
+    pub every: std::time::Duration,
+    /// The tags whose values rows are grouped by within each window. The
+    /// results keep these tags.
+    #[serde(default)]
+    pub group_by_tags: Vec<String>,
+    /// The aggregates computed for each group
+    pub aggregates: Vec<ContinuousQueryAggregate>,
+    /// The database the results are written to, if not this one
+    #[serde(default)]
+    pub target_database: Option<String>,
+    /// The table the results are written to
+    pub target_table: String,
+}
+
+/// An aggregate of a field computed by a `ContinuousQuery`
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct ContinuousQueryAggregate {
+    pub function: AggregateFunction,
+    pub field: String,
+    /// The name of the field the result is written to, if not
+    /// `<function>_<field>` (e.g. `mean_usage`)
+    #[serde(default)]
+    pub target_field: Option<String>,
+}
+
+impl ContinuousQueryAggregate {
+    /// The name of the field the result is written to
+    pub fn target_field(&self) -> String {
+        match &self.target_field {
+            Some(target_field) => target_field.clone(),
+            None => format!("{}_{}", self.function, self.field),
+        }
+    }
+}
+
+/// The functions a `ContinuousQuery` can aggregate fields with. All but
+/// `Count` require a numeric field.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean,
+}
+
+impl std::fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Count => "count",
+            Self::Sum => "sum",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Mean => "mean",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 pub type HostGroupId = String;
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -289,6 +1223,7 @@ pub struct HostGroup {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone as _;
     use influxdb_line_protocol::parse_lines;
 
     type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -298,6 +1233,7 @@ mod tests {
     fn partition_key_with_table() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::Table],
+            ..Default::default()
         };
 
         let line = parse_line("cpu foo=1 10");
@@ -310,6 +1246,7 @@ mod tests {
     fn partition_key_with_int_field() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::Column("foo".to_string())],
+            ..Default::default()
         };
 
         let line = parse_line("cpu foo=1 10");
@@ -322,6 +1259,7 @@ mod tests {
     fn partition_key_with_float_field() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::Column("foo".to_string())],
+            ..Default::default()
         };
 
         let line = parse_line("cpu foo=1.1 10");
@@ -337,6 +1275,7 @@ mod tests {
     fn partition_key_with_string_field() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::Column("foo".to_string())],
+            ..Default::default()
         };
 
         let line = parse_line("cpu foo=\"asdf\" 10");
@@ -352,6 +1291,7 @@ mod tests {
     fn partition_key_with_bool_field() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::Column("bar".to_string())],
+            ..Default::default()
         };
 
         let line = parse_line("cpu bar=true 10");
@@ -367,6 +1307,7 @@ mod tests {
     fn partition_key_with_tag_column() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::Column("region".to_string())],
+            ..Default::default()
         };
 
         let line = parse_line("cpu,region=west usage_user=23.2 10");
@@ -382,6 +1323,7 @@ mod tests {
     fn partition_key_with_missing_column() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::Column("not_here".to_string())],
+            ..Default::default()
         };
 
         let line = parse_line("cpu,foo=asdf bar=true 10");
@@ -394,6 +1336,7 @@ mod tests {
     fn partition_key_with_time() -> Result {
         let template = PartitionTemplate {
             parts: vec![TemplatePart::TimeFormat("%Y-%m-%d %H:%M:%S".to_string())],
+            ..Default::default()
         };
 
         let line = parse_line("cpu,foo=asdf bar=true 1602338097000000000");
@@ -410,6 +1353,7 @@ mod tests {
         let format_string = "%Y-%m-%d %H:%M:%S";
         let template = PartitionTemplate {
             parts: vec![TemplatePart::TimeFormat(format_string.to_string())],
+            ..Default::default()
         };
 
         let default_time = Utc::now();
@@ -422,6 +1366,152 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn partition_key_generator() -> Result {
+        let formats = &[
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%d %H:00:00",
+            "%Y-%m-%d",
+            "%Y-%m",
+            "%-d/%-m/%y %I%p",
+            "%Y-%m-%d %H:%M:%S%.3f",
+            "no time",
+        ];
+        let timestamps = &[
+            None,
+            Some(0),
+            Some(-1),
+            Some(-NANOS_PER_DAY - 1),
+            Some(1_602_338_097_000_000_000),
+            Some(1_602_338_097_123_456_789),
+            Some(1_602_374_399_999_999_999),
+            Some(1_602_374_400_000_000_000),
+            Some(1_602_338_097_000_000_000),
+        ];
+
+        let default_time = Utc::now();
+        for format in formats {
+            let template = PartitionTemplate {
+                parts: vec![
+                    TemplatePart::Table,
+                    TemplatePart::TimeFormat(format.to_string()),
+                ],
+                ..Default::default()
+            };
+            let mut generator = template.key_generator(&default_time);
+
+            for timestamp in timestamps {
+                let line = match timestamp {
+                    Some(t) => format!("cpu,foo=asdf bar=true {}", t),
+                    None => "cpu,foo=asdf bar=true".to_string(),
+                };
+                let line = parse_line(&line);
+
+                assert_eq!(
+                    template.partition_key(&line, &default_time).unwrap(),
+                    generator.partition_key(&line).unwrap(),
+                    "format '{}', timestamp {:?}",
+                    format,
+                    timestamp
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_in_time_zone() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::TimeFormat("%Y-%m-%d %H:00".to_string())],
+            time_zone: "+05:30".parse()?,
+        };
+        let default_time = Utc.timestamp(0, 0);
+        let mut generator = template.key_generator(&default_time);
+
+        let cases = &[
+            // 2020-10-10 18:29:59 UTC is still the 10th in India
+            ("cpu a=1 1602354599000000000", "2020-10-10 23:00"),
+            // 18:30 UTC is midnight
+            ("cpu a=1 1602354600000000000", "2020-10-11 00:00"),
+            ("cpu a=1 1602358199000000000", "2020-10-11 00:00"),
+            ("cpu a=1 1602358200000000000", "2020-10-11 01:00"),
+            ("cpu a=1", "1970-01-01 05:00"),
+        ];
+        for (line, expected) in cases {
+            let line = parse_line(line);
+            assert_eq!(template.partition_key(&line, &default_time)?, *expected);
+            assert_eq!(generator.partition_key(&line)?, *expected);
+        }
+
+        // days follow daylight saving time, which ends in Berlin on
+        // 2020-10-25 at 01:00 UTC
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::TimeFormat("%Y-%m-%d %z".to_string())],
+            time_zone: "Europe/Berlin".parse()?,
+        };
+        let mut generator = template.key_generator(&default_time);
+        let cases = &[
+            // 2020-10-24 22:00 UTC is midnight in summer time
+            ("cpu a=1 1603576799000000000", "2020-10-24 +0200"),
+            ("cpu a=1 1603576800000000000", "2020-10-25 +0200"),
+            ("cpu a=1 1603587600000000000", "2020-10-25 +0100"),
+            // and 23:00 UTC once it ended
+            ("cpu a=1 1603666799000000000", "2020-10-25 +0100"),
+            ("cpu a=1 1603666800000000000", "2020-10-26 +0100"),
+        ];
+        for (line, expected) in cases {
+            let line = parse_line(line);
+            assert_eq!(template.partition_key(&line, &default_time)?, *expected);
+            assert_eq!(generator.partition_key(&line)?, *expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn time_format_cache_evicts_oldest() {
+        let mut cache = TimeFormatCache::new("%Y-%m-%d %H:%M:%S", &TimeZone::UTC);
+        for second in 0..=MAX_CACHED_TIMES as i64 {
+            cache.format("%Y-%m-%d %H:%M:%S", second * NANOS_PER_SECOND);
+        }
+
+        assert_eq!(cache.formatted.len(), MAX_CACHED_TIMES);
+        assert!(!cache.formatted.contains_key(&(0, 0)));
+        assert!(cache.formatted.contains_key(&(1, 0)));
+        assert!(cache.formatted.contains_key(&(MAX_CACHED_TIMES as i64, 0)));
+    }
+
+    #[test]
+    fn unsupported_template_parts() {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::StrftimeColumn(StrftimeColumn {
+                column: "t".to_string(),
+                format: "%Y".to_string(),
+            })],
+            ..Default::default()
+        };
+        let line = parse_line("cpu t=1i 10");
+        let err = template.partition_key(&line, &Utc::now()).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedTemplatePart { .. }));
+        assert_eq!(
+            err.to_string(),
+            "Partition template part StrftimeColumn is not supported"
+        );
+    }
+
+    #[test]
+    fn time_format_bucket_nanos() {
+        assert_eq!(bucket_nanos("%Y-%m-%d %H:%M:%S"), Some(NANOS_PER_SECOND));
+        assert_eq!(bucket_nanos("%Y-%m-%d %H:%M"), Some(NANOS_PER_MINUTE));
+        assert_eq!(bucket_nanos("%Y-%m-%d %-H:00"), Some(NANOS_PER_HOUR));
+        assert_eq!(bucket_nanos("%F"), Some(NANOS_PER_DAY));
+        assert_eq!(bucket_nanos("%Y-%m"), Some(NANOS_PER_DAY));
+        assert_eq!(bucket_nanos("100%%"), Some(i64::MAX));
+        assert_eq!(bucket_nanos("%S%.f"), None);
+        assert_eq!(bucket_nanos("%"), None);
+    }
+
     #[test]
     fn partition_key_with_many_parts() -> Result {
         let template = PartitionTemplate {
@@ -431,6 +1521,7 @@ mod tests {
                 TemplatePart::Column("usage_system".to_string()),
                 TemplatePart::TimeFormat("%Y-%m-%d %H:%M:%S".to_string()),
             ],
+            ..Default::default()
         };
 
         let line = parse_line(
@@ -444,6 +1535,221 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn partition_key_with_tag_values() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::TimeFormat(
+                "%Y-%m-%d_{region}/{host}".to_string(),
+            )],
+            ..Default::default()
+        };
+        let default_time = Utc::now();
+        let mut generator = template.key_generator(&default_time);
+
+        let cases = &[
+            (
+                "cpu,host=a,region=west usage=1 1602338097000000000",
+                "2020-10-10_west/a",
+            ),
+            (
+                "cpu,host=b,region=east usage=1 1602338097000000000",
+                "2020-10-10_east/b",
+            ),
+            // missing tags and fields of the same name are blank
+            (
+                "cpu,host=a usage=1,region=\"x\" 1602338097000000000",
+                "2020-10-10_/a",
+            ),
+            ("cpu usage=1 1602338097000000000", "2020-10-10_/"),
+            // tag values can't add path separators or key parts
+            (
+                "cpu,host=a,region=us/east-1 usage=1 1602338097000000000",
+                "2020-10-10_us%2Feast%2D1/a",
+            ),
+            (
+                "cpu,host=../%,region=west usage=1 1602338097000000000",
+                "2020-10-10_west/%2E%2E%2F%25",
+            ),
+        ];
+        for (line, expected) in cases {
+            let line = parse_line(line);
+            assert_eq!(template.partition_key(&line, &default_time)?, *expected);
+            assert_eq!(generator.partition_key(&line)?, *expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unclosed_tag_references_are_kept() {
+        let line = parse_line("cpu,region=west usage=1 1");
+        assert_eq!(
+            substitute_tag_values("{region}_{region".to_string(), &line),
+            "west_{region"
+        );
+        assert_eq!(
+            substitute_tag_values("{}{region}}".to_string(), &line),
+            "west}"
+        );
+    }
+
+    #[test]
+    fn table_write_rules() -> Result {
+        let rules = TableWriteRules {
+            allow: vec![
+                MatchTables::Table("cpu".to_string()),
+                MatchTables::Regex("team_a_.*".to_string()),
+            ],
+            deny: vec![MatchTables::Regex(".*_secret".to_string())],
+        };
+        let filter = rules.filter()?;
+
+        assert_eq!(filter.check("cpu"), None);
+        assert_eq!(filter.check("team_a_mem"), None);
+        assert_eq!(
+            filter.check("team_a_secret"),
+            Some(TableWriteRejection::Denied)
+        );
+        // regexes must match the whole name
+        assert_eq!(
+            filter.check("team_b_team_a_mem"),
+            Some(TableWriteRejection::NotAllowed)
+        );
+        assert_eq!(filter.check("cpu2"), Some(TableWriteRejection::NotAllowed));
+
+        // no rules allow everything
+        let filter = TableWriteRules::default().filter()?;
+        assert_eq!(filter.check("anything"), None);
+        assert_eq!(TableWriteFilter::default().check("anything"), None);
+        assert_eq!(
+            TableWriteFilter::deny_all().check("anything"),
+            Some(TableWriteRejection::Denied)
+        );
+
+        let rules = TableWriteRules {
+            deny: vec![MatchTables::Regex("(".to_string())],
+            ..Default::default()
+        };
+        assert!(matches!(
+            rules.filter(),
+            Err(Error::InvalidTableRegex { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_transforms() {
+        let mut transforms = WriteTransforms::default();
+        transforms
+            .inject_tags
+            .insert("source".to_string(), "router-1".to_string());
+        transforms
+            .rename_tags
+            .insert("hostname".to_string(), "host".to_string());
+        transforms
+            .rename_fields
+            .insert("usage".to_string(), "usage_user".to_string());
+        transforms.drop_fields.insert("debug".to_string());
+
+        let mut lines = parsed_lines(
+            "cpu,hostname=a,source=agent usage=1,debug=true 10\n\
+             cpu,host=b,hostname=a usage=1,usage_user=2 10\n\
+             cpu debug=false 10\n\
+             mem free=1i 10\n\
+             disk,zone=z used=1i 10",
+        );
+        transforms.apply(&mut lines);
+
+        // the series keys are those of the transformed lines, with their
+        // tags sorted
+        let series: Vec<_> = lines
+            .iter()
+            .map(|line| line.series.clone().generate_base().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            series,
+            vec![
+                "cpu,host=a,source=router-1",
+                "cpu,host=a,source=router-1",
+                "mem,source=router-1",
+                "disk,source=router-1,zone=z",
+            ]
+        );
+
+        let lines: Vec<_> = lines.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "cpu,host=a,source=router-1 usage_user=1 10",
+                "cpu,host=a,source=router-1 usage_user=1 10",
+                "mem,source=router-1 free=1i 10",
+                "disk,source=router-1,zone=z used=1i 10",
+            ]
+        );
+    }
+
+    #[test]
+    fn future_points_are_quarantined() {
+        let now = Utc.timestamp_nanos(1_000);
+        let mut rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let lines = parsed_lines("cpu foo=1 1000\ncpu foo=1 1100\ncpu foo=1 1101\ncpu foo=1");
+
+        let keys = |rules: &DatabaseRules| {
+            let mut generator = rules.partition_key_generator(&now);
+            let keys: Vec<_> = lines
+                .iter()
+                .map(|line| generator.partition_key(line).unwrap())
+                .collect();
+            for (line, key) in lines.iter().zip(&keys) {
+                assert_eq!(&rules.partition_key(line, &now).unwrap(), key);
+            }
+            keys
+        };
+
+        assert_eq!(keys(&rules), vec!["cpu", "cpu", "cpu", "cpu"]);
+
+        rules.future_time_cap = Some(std::time::Duration::from_nanos(100));
+        assert_eq!(rules.future_time_limit(&now), Some(1_100));
+        assert_eq!(
+            keys(&rules),
+            vec!["cpu", "cpu", QUARANTINE_PARTITION_KEY, "cpu"]
+        );
+
+        // the limit saturates rather than wrapping around
+        rules.future_time_cap = Some(std::time::Duration::from_secs(u64::MAX));
+        assert_eq!(rules.future_time_limit(&now), Some(i64::MAX));
+    }
+
+    #[test]
+    fn non_finite_floats() {
+        let mut lines = parsed_lines(
+            "cpu usage=1,load=1 10\n\
+             cpu load=2 20\n\
+             cpu usage=2,count=3i 30\n\
+             cpu usage=3 40",
+        );
+        lines[0].field_set[1].1 = FieldValue::F64(f64::INFINITY);
+        lines[1].field_set[0].1 = FieldValue::F64(f64::NEG_INFINITY);
+        lines[3].field_set[0].1 = FieldValue::F64(f64::NAN);
+
+        let fields: Vec<_> = lines.iter().map(NonFiniteFloats::fields).collect();
+        assert_eq!(
+            fields,
+            vec![vec!["load"], vec!["load"], vec![], vec!["usage"]]
+        );
+
+        NonFiniteFloats::remove(&mut lines);
+        let lines: Vec<_> = lines.iter().map(ToString::to_string).collect();
+        assert_eq!(lines, vec!["cpu usage=1 10", "cpu usage=2,count=3i 30"]);
+    }
+
     fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
         parse_lines(lp).map(|l| l.unwrap()).collect()
     }
@@ -451,4 +1757,64 @@ mod tests {
     fn parse_line(line: &str) -> ParsedLine<'_> {
         parsed_lines(line).pop().unwrap()
     }
+
+    #[test]
+    fn chunk_close_size_adapts_to_ingest_rate() {
+        let sizing = ChunkSizing {
+            target_window_seconds: 60,
+            min_chunk_bytes: 1_000,
+            max_chunk_bytes: 100_000,
+        };
+
+        assert_eq!(sizing.close_size(None), 100_000);
+        assert_eq!(sizing.close_size(Some(100.0)), 6_000);
+        // slow partitions still fill the minimum, fast ones stop at the maximum
+        assert_eq!(sizing.close_size(Some(1.0)), 1_000);
+        assert_eq!(sizing.close_size(Some(1e12)), 100_000);
+    }
+
+    #[test]
+    fn tags_are_sorted_in_table_order() {
+        let mut tag_orders = TagOrders::default();
+        tag_orders
+            .tables
+            .insert("cpu".to_string(), vec!["region".into(), "host".into()]);
+
+        let mut tags = vec!["az", "host", "region", "cpu"];
+        tags.sort_by_key(|tag| tag_orders.sort_key("cpu", *tag));
+        assert_eq!(tags, vec!["region", "host", "az", "cpu"]);
+
+        tags.sort_by_key(|tag| tag_orders.sort_key("mem", *tag));
+        assert_eq!(tags, vec!["az", "cpu", "host", "region"]);
+    }
+
+    #[test]
+    fn tables_expire_after_their_retention_period() {
+        let mut retention = RetentionRules {
+            period: Some(std::time::Duration::from_nanos(100)),
+            ..Default::default()
+        };
+        retention.tables.insert(
+            "debug_metrics".to_string(),
+            std::time::Duration::from_nanos(10),
+        );
+
+        assert!(!retention.is_expired("cpu", Some((0, 50)), 150));
+        assert!(retention.is_expired("cpu", Some((0, 49)), 150));
+        assert!(retention.is_expired("debug_metrics", Some((0, 50)), 150));
+        assert!(!retention.is_expired("debug_metrics", Some((0, 145)), 150));
+        // rows without timestamps are kept
+        assert!(!retention.is_expired("cpu", None, 150));
+
+        assert_eq!(retention.boundary("cpu", 150), Some(50));
+        assert_eq!(retention.boundary("debug_metrics", 150), Some(140));
+        assert_eq!(retention.default_boundary(150), Some(50));
+
+        // without a period, only the overridden tables expire
+        retention.period = None;
+        assert!(!retention.is_expired("cpu", Some((0, 0)), 150));
+        assert!(retention.is_expired("debug_metrics", Some((0, 0)), 150));
+        assert_eq!(retention.boundary("cpu", 150), None);
+        assert_eq!(retention.default_boundary(150), None);
+    }
 }
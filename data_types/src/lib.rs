@@ -9,14 +9,17 @@
     clippy::use_self
 )]
 
-pub use schema::TIME_COLUMN_NAME;
+pub use schema::{INGEST_TIME_COLUMN_NAME, TIME_COLUMN_NAME};
 
+pub mod access_policy;
 pub mod data;
 pub mod database_rules;
 pub mod error;
 pub mod names;
 pub mod partition_metadata;
 pub mod schema;
+pub mod time_zone;
+pub mod verification;
 
 mod database_name;
 pub use database_name::*;
@@ -1,30 +1,140 @@
 //! This module contains structs that describe the metadata for a partition
 //! including schema, summary statistics, and file locations in storage.
 
-use std::fmt::{Debug, Display};
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    str::FromStr,
+};
 
 use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, Snafu};
+
+/// The key of the key-value metadata of the Parquet files of snapshots
+/// that holds the version of the format they were written in
+pub const FORMAT_VERSION_METADATA_KEY: &str = "iox::format_version";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Snapshot format version {} is not supported: this version of IOx reads version {}.x",
+        version,
+        FormatVersion::CURRENT.major
+    ))]
+    UnsupportedVersion { version: FormatVersion },
+
+    #[snafu(display(
+        "Snapshot format version {} is newer than {}, so it can be read but not rewritten",
+        version,
+        FormatVersion::CURRENT
+    ))]
+    NewerVersion { version: FormatVersion },
+
+    #[snafu(display("Invalid snapshot format version '{}'", version))]
+    InvalidVersion { version: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The version of the format of the snapshots of partitions: the layout of
+/// their metadata and of their Parquet files.
+///
+/// The major version changes when snapshots can no longer be read the way
+/// they were, and readers refuse major versions other than their own. The
+/// minor version changes when something is added that older readers can
+/// ignore, so snapshots of a newer minor version are read, but not
+/// rewritten, as what was added would be lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct FormatVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl FormatVersion {
+    /// The version snapshots are written in
+    pub const CURRENT: Self = Self { major: 1, minor: 0 };
+
+    /// The version of the snapshots written before versions were recorded
+    pub const INITIAL: Self = Self { major: 1, minor: 0 };
+
+    fn initial() -> Self {
+        Self::INITIAL
+    }
+
+    /// Returns an error if snapshots of this version can't be read
+    pub fn check_readable(self) -> Result<()> {
+        ensure!(
+            self.major == Self::CURRENT.major,
+            UnsupportedVersion { version: self }
+        );
+        Ok(())
+    }
+
+    /// Returns an error if snapshots of this version can't be read, or
+    /// could lose what a newer minor version added if they were rewritten
+    pub fn check_rewritable(self) -> Result<()> {
+        self.check_readable()?;
+        ensure!(
+            self.minor <= Self::CURRENT.minor,
+            NewerVersion { version: self }
+        );
+        Ok(())
+    }
+}
+
+impl Display for FormatVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for FormatVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let context = || InvalidVersion { version: s };
+        let mut parts = s.splitn(2, '.');
+        let major = parts.next().and_then(|part| part.parse().ok());
+        let minor = parts.next().and_then(|part| part.parse().ok());
+        Ok(Self {
+            major: major.context(context())?,
+            minor: minor.context(context())?,
+        })
+    }
+}
 
 /// Describes the schema, summary statistics for each column in each table and
 /// the location of the partition in storage.
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Partition {
     /// The identifier for the partition, the partition key computed from
     /// PartitionRules
     pub key: String,
     /// The tables in this partition
     pub tables: Vec<Table>,
+    /// The CRC32 checksums of the files the tables are stored in, by table
+    /// name, recorded when the files are written. Partitions written
+    /// before checksums were recorded have none.
+    #[serde(default)]
+    pub checksums: BTreeMap<String, u32>,
+    /// The version of the format the partition was written in
+    #[serde(default = "FormatVersion::initial")]
+    pub format_version: FormatVersion,
 }
 
 /// Metadata and statistics information for a table.
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    /// The smallest and largest timestamps of the rows of the table, if
+    /// every row has a timestamp
+    #[serde(default)]
+    pub time_range: Option<(i64, i64)>,
 }
 
 /// Statistics and type information for a column.
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum Column {
     I64(Statistics<i64>),
     U64(Statistics<u64>),
@@ -110,6 +220,30 @@ impl Statistics<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn format_versions() {
+        let version: FormatVersion = "1.7".parse().unwrap();
+        assert_eq!(version, FormatVersion { major: 1, minor: 7 });
+        assert_eq!(version.to_string(), "1.7");
+        assert!(version.check_readable().is_ok());
+        assert_eq!(
+            version.check_rewritable().unwrap_err().to_string(),
+            "Snapshot format version 1.7 is newer than 1.0, so it can be read but not rewritten"
+        );
+
+        let version = FormatVersion { major: 2, minor: 0 };
+        assert_eq!(
+            version.check_readable().unwrap_err().to_string(),
+            "Snapshot format version 2.0 is not supported: this version of IOx reads version 1.x"
+        );
+        assert!(version.check_rewritable().is_err());
+        assert!(FormatVersion::CURRENT.check_rewritable().is_ok());
+
+        for invalid in &["", "1", "1.", "a.0", "1.0.0"] {
+            assert!(invalid.parse::<FormatVersion>().is_err(), "{}", invalid);
+        }
+    }
+
     #[test]
     fn statistics_update() {
         let mut stat = Statistics::new(23);
@@ -13,6 +13,10 @@ use arrow_deps::arrow::datatypes::{
 
 pub const TIME_COLUMN_NAME: &str = "time";
 
+/// The name of the field holding the time the server accepted each row,
+/// for databases that record it
+pub const INGEST_TIME_COLUMN_NAME: &str = "_ingest_time";
+
 pub mod builder;
 
 /// Database schema creation / validation errors.
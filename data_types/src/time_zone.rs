@@ -0,0 +1,578 @@
+//! This module contains `TimeZone`, the time zone partition keys are
+//! computed in and query results can be displayed in, and `UtcOffset`, a
+//! fixed offset from UTC.
+//!
+//! Besides fixed offsets, a time zone can follow daylight saving time
+//! rules, written as a POSIX TZ string (e.g. `CET-1CEST,M3.5.0,M10.5.0/3`)
+//! or as the name of a common zone (e.g. `Europe/Berlin`), which stands for
+//! the current rules of that zone. There is no tz database, so past
+//! changes to the rules of a zone are not known.
+
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Invalid UTC offset '{}', expected 'UTC' or one like '+05:30' or '-08:00'",
+        offset
+    ))]
+    InvalidUtcOffset { offset: String },
+
+    #[snafu(display(
+        "Invalid time zone '{}', expected 'UTC', an offset like '+05:30', a zone \
+         like 'Europe/Berlin' or a POSIX TZ string like 'CET-1CEST,M3.5.0,M10.5.0/3'",
+        time_zone
+    ))]
+    InvalidTimeZone { time_zone: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const SECONDS_PER_MINUTE: i32 = 60;
+const SECONDS_PER_HOUR: i32 = 60 * SECONDS_PER_MINUTE;
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// The current rules of common zones, as POSIX TZ strings
+const ZONES: &[(&str, &str)] = &[
+    ("Africa/Cairo", "EET-2"),
+    ("Africa/Johannesburg", "SAST-2"),
+    ("Africa/Lagos", "WAT-1"),
+    ("America/Anchorage", "AKST9AKDT,M3.2.0,M11.1.0"),
+    ("America/Chicago", "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Denver", "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Los_Angeles", "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Mexico_City", "CST6"),
+    ("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Phoenix", "MST7"),
+    ("America/Sao_Paulo", "<-03>3"),
+    ("America/Toronto", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Vancouver", "PST8PDT,M3.2.0,M11.1.0"),
+    ("Asia/Dubai", "<+04>-4"),
+    ("Asia/Hong_Kong", "HKT-8"),
+    ("Asia/Jakarta", "WIB-7"),
+    ("Asia/Kolkata", "IST-5:30"),
+    ("Asia/Seoul", "KST-9"),
+    ("Asia/Shanghai", "CST-8"),
+    ("Asia/Singapore", "<+08>-8"),
+    ("Asia/Tokyo", "JST-9"),
+    ("Australia/Brisbane", "AEST-10"),
+    ("Australia/Melbourne", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Perth", "AWST-8"),
+    ("Australia/Sydney", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Europe/Amsterdam", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Athens", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Helsinki", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Lisbon", "WET0WEST,M3.5.0/1,M10.5.0"),
+    ("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Madrid", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Moscow", "MSK-3"),
+    ("Europe/Paris", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Rome", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Stockholm", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Warsaw", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Zurich", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Pacific/Auckland", "NZST-12NZDT,M9.5.0,M4.1.0/3"),
+    ("Pacific/Honolulu", "HST10"),
+];
+
+/// A time zone: a fixed offset from UTC, or the rules of a zone
+/// observing daylight saving time. Written as a `UtcOffset`, a zone name
+/// or a POSIX TZ string.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeZone(Zone);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Zone {
+    Fixed(UtcOffset),
+    /// `name` is the zone name or TZ string the rules were parsed from
+    Rules {
+        name: String,
+        rules: ZoneRules,
+    },
+}
+
+/// The offsets of standard time and daylight saving time, and when
+/// daylight saving time starts and ends
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct ZoneRules {
+    /// The seconds standard time is east of UTC
+    standard: i32,
+    daylight: Option<DaylightSaving>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct DaylightSaving {
+    /// The seconds daylight saving time is east of UTC
+    offset: i32,
+    /// When daylight saving time starts, in local standard time
+    start: Transition,
+    /// When daylight saving time ends, in local daylight saving time
+    end: Transition,
+}
+
+/// A POSIX `Mm.w.d/time` transition: on weekday `weekday` (0 is Sunday)
+/// of week `week` (5 is the last) of month `month`, `seconds` after
+/// local midnight
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Transition {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    seconds: i32,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::UTC
+    }
+}
+
+impl TimeZone {
+    pub const UTC: Self = Self(Zone::Fixed(UtcOffset::UTC));
+
+    pub fn is_utc(&self) -> bool {
+        matches!(&self.0, Zone::Fixed(offset) if offset.is_utc())
+    }
+
+    /// The offset from UTC of local time at `nanos` after the epoch
+    pub fn offset_at(&self, nanos: i64) -> FixedOffset {
+        match &self.0 {
+            Zone::Fixed(offset) => offset.fixed_offset(),
+            Zone::Rules { rules, .. } => {
+                FixedOffset::east(rules.offset_at(nanos.div_euclid(NANOS_PER_SECOND)))
+            }
+        }
+    }
+}
+
+impl From<UtcOffset> for TimeZone {
+    fn from(offset: UtcOffset) -> Self {
+        Self(Zone::Fixed(offset))
+    }
+}
+
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Zone::Fixed(offset) => write!(f, "{}", offset),
+            Zone::Rules { name, .. } => f.write_str(name),
+        }
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(offset) = s.parse::<UtcOffset>() {
+            return Ok(offset.into());
+        }
+
+        let tz = ZONES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map_or(s, |(_, tz)| *tz);
+        let rules = ZoneRules::parse(tz).context(InvalidTimeZone { time_zone: s })?;
+        Ok(Self(Zone::Rules {
+            name: s.to_string(),
+            rules,
+        }))
+    }
+}
+
+impl TryFrom<String> for TimeZone {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<TimeZone> for String {
+    fn from(time_zone: TimeZone) -> Self {
+        time_zone.to_string()
+    }
+}
+
+impl ZoneRules {
+    /// Parses a POSIX TZ string with `Mm.w.d` transitions, such as
+    /// `EST5EDT,M3.2.0,M11.1.0`
+    fn parse(tz: &str) -> Option<Self> {
+        let mut parser = TzParser { rest: tz };
+
+        parser.name()?;
+        // POSIX offsets are west of UTC
+        let standard = -parser.time()?;
+        if parser.rest.is_empty() {
+            return Some(Self {
+                standard,
+                daylight: None,
+            });
+        }
+
+        parser.name()?;
+        let offset = if parser.rest.starts_with(',') {
+            standard + SECONDS_PER_HOUR
+        } else {
+            -parser.time()?
+        };
+        parser.expect(',')?;
+        let start = parser.transition()?;
+        parser.expect(',')?;
+        let end = parser.transition()?;
+        if !parser.rest.is_empty() || offset.abs() >= 24 * SECONDS_PER_HOUR {
+            return None;
+        }
+
+        Some(Self {
+            standard,
+            daylight: Some(DaylightSaving { offset, start, end }),
+        })
+    }
+
+    /// The seconds local time is east of UTC at `seconds` after the epoch
+    fn offset_at(&self, seconds: i64) -> i32 {
+        let daylight = match self.daylight {
+            Some(daylight) => daylight,
+            None => return self.standard,
+        };
+
+        let year = NaiveDateTime::from_timestamp(seconds + i64::from(self.standard), 0).year();
+        let start = daylight.start.local_seconds(year) - i64::from(self.standard);
+        let end = daylight.end.local_seconds(year) - i64::from(daylight.offset);
+        // in the southern hemisphere daylight saving time spans new year
+        let in_daylight = if start < end {
+            start <= seconds && seconds < end
+        } else {
+            seconds >= start || seconds < end
+        };
+
+        if in_daylight {
+            daylight.offset
+        } else {
+            self.standard
+        }
+    }
+}
+
+impl Transition {
+    /// The local time of the transition in `year`, in seconds after the
+    /// epoch
+    fn local_seconds(&self, year: i32) -> i64 {
+        let first = NaiveDate::from_ymd(year, self.month, 1);
+        let first_weekday = first.weekday().num_days_from_sunday();
+        let mut day = 1 + (self.weekday + 7 - first_weekday) % 7 + (self.week - 1) * 7;
+
+        let next_month = match self.month {
+            12 => NaiveDate::from_ymd(year + 1, 1, 1),
+            month => NaiveDate::from_ymd(year, month + 1, 1),
+        };
+        let days_in_month = next_month.pred().day();
+        while day > days_in_month {
+            day -= 7;
+        }
+
+        NaiveDate::from_ymd(year, self.month, day)
+            .and_hms(0, 0, 0)
+            .timestamp()
+            + i64::from(self.seconds)
+    }
+}
+
+/// Parses the parts of a POSIX TZ string
+struct TzParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> TzParser<'a> {
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.rest = self.rest.strip_prefix(c)?;
+        Some(())
+    }
+
+    /// A zone abbreviation: at least three letters, or anything but `>`
+    /// between `<` and `>`
+    fn name(&mut self) -> Option<&'a str> {
+        let (name, rest) = match self.rest.strip_prefix('<') {
+            Some(quoted) => {
+                let end = quoted.find('>')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => {
+                let end = self
+                    .rest
+                    .find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(self.rest.len());
+                (&self.rest[..end], &self.rest[end..])
+            }
+        };
+        if name.len() < 3 {
+            return None;
+        }
+        self.rest = rest;
+        Some(name)
+    }
+
+    /// A number of at most `max` with one or two digits
+    fn number(&mut self, max: i32) -> Option<i32> {
+        let digits = self
+            .rest
+            .bytes()
+            .take(2)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        let number = self.rest[..digits].parse().ok()?;
+        self.rest = &self.rest[digits..];
+        if number > max {
+            return None;
+        }
+        Some(number)
+    }
+
+    /// A signed `hh[:mm[:ss]]` time, in seconds
+    fn time(&mut self) -> Option<i32> {
+        if self.expect('-').is_some() {
+            return Some(-self.unsigned_time()?);
+        }
+        let _ = self.expect('+');
+        self.unsigned_time()
+    }
+
+    fn unsigned_time(&mut self) -> Option<i32> {
+        let mut seconds = self.number(24)? * SECONDS_PER_HOUR;
+        if self.expect(':').is_some() {
+            seconds += self.number(59)? * SECONDS_PER_MINUTE;
+            if self.expect(':').is_some() {
+                seconds += self.number(59)?;
+            }
+        }
+        Some(seconds)
+    }
+
+    /// An `Mm.w.d[/time]` transition, at 02:00 unless a time is given
+    fn transition(&mut self) -> Option<Transition> {
+        self.expect('M')?;
+        let month = self.number(12)? as u32;
+        self.expect('.')?;
+        let week = self.number(5)? as u32;
+        self.expect('.')?;
+        let weekday = self.number(6)? as u32;
+        let seconds = match self.expect('/') {
+            Some(()) => self.time()?,
+            None => 2 * SECONDS_PER_HOUR,
+        };
+
+        if month == 0 || week == 0 {
+            return None;
+        }
+        Some(Transition {
+            month,
+            week,
+            weekday,
+            seconds,
+        })
+    }
+}
+
+/// A fixed offset from UTC, written as `UTC` or as `+HH:MM` / `-HH:MM`
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UtcOffset {
+    seconds: i32,
+}
+
+impl UtcOffset {
+    pub const UTC: Self = Self { seconds: 0 };
+
+    /// Returns the offset `seconds` east of UTC (west if negative), or
+    /// None if it isn't less than a day
+    pub fn from_seconds(seconds: i32) -> Option<Self> {
+        if seconds.abs() >= 24 * SECONDS_PER_HOUR {
+            return None;
+        }
+        Some(Self { seconds })
+    }
+
+    pub fn is_utc(&self) -> bool {
+        self.seconds == 0
+    }
+
+    /// The number of seconds the offset is east of UTC
+    pub fn seconds(&self) -> i32 {
+        self.seconds
+    }
+
+    /// The offset as a chrono time zone, to convert times with
+    pub fn fixed_offset(&self) -> FixedOffset {
+        FixedOffset::east(self.seconds)
+    }
+}
+
+impl fmt::Display for UtcOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_utc() {
+            return write!(f, "UTC");
+        }
+        let sign = if self.seconds < 0 { '-' } else { '+' };
+        let seconds = self.seconds.abs();
+        write!(
+            f,
+            "{}{:02}:{:02}",
+            sign,
+            seconds / SECONDS_PER_HOUR,
+            seconds % SECONDS_PER_HOUR / SECONDS_PER_MINUTE
+        )
+    }
+}
+
+impl FromStr for UtcOffset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if matches!(s, "UTC" | "Z") {
+            return Ok(Self::UTC);
+        }
+
+        let invalid = || InvalidUtcOffset { offset: s };
+        let (negative, rest) = match s.chars().next() {
+            Some('+') => (false, &s[1..]),
+            Some('-') => (true, &s[1..]),
+            _ => return invalid().fail(),
+        };
+        let (hours, minutes) = match rest.find(':') {
+            Some(colon) => (&rest[..colon], &rest[colon + 1..]),
+            None => (rest, "00"),
+        };
+        let two_digits = |s: &str| s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit());
+        ensure!(two_digits(hours) && two_digits(minutes), invalid());
+        let hours: i32 = hours.parse().ok().context(invalid())?;
+        let minutes: i32 = minutes.parse().ok().context(invalid())?;
+        ensure!(minutes < 60, invalid());
+
+        let seconds = hours * SECONDS_PER_HOUR + minutes * SECONDS_PER_MINUTE;
+        Self::from_seconds(if negative { -seconds } else { seconds }).context(invalid())
+    }
+}
+
+impl TryFrom<String> for UtcOffset {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<UtcOffset> for String {
+    fn from(offset: UtcOffset) -> Self {
+        offset.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let cases = &[
+            ("UTC", 0, "UTC"),
+            ("Z", 0, "UTC"),
+            ("+00:00", 0, "UTC"),
+            ("+05:30", 5 * 3600 + 30 * 60, "+05:30"),
+            ("-08:00", -8 * 3600, "-08:00"),
+            ("-03", -3 * 3600, "-03:00"),
+        ];
+        for (s, seconds, displayed) in cases {
+            let offset: UtcOffset = s.parse().unwrap();
+            assert_eq!(offset.seconds(), *seconds, "{}", s);
+            assert_eq!(offset.to_string(), *displayed);
+        }
+
+        for s in &[
+            "", "05:30", "+5:30", "+24:00", "+01:60", "+01:5", "-0a:00", "++1:00", "EST",
+        ] {
+            let err = s.parse::<UtcOffset>().unwrap_err();
+            assert!(matches!(err, Error::InvalidUtcOffset { .. }), "{}", s);
+        }
+    }
+
+    #[test]
+    fn time_zones() {
+        let nanos = |rfc3339: &str| {
+            chrono::DateTime::parse_from_rfc3339(rfc3339)
+                .unwrap()
+                .timestamp_nanos()
+        };
+        let offset_at = |tz: &TimeZone, rfc3339| tz.offset_at(nanos(rfc3339)).local_minus_utc();
+
+        let fixed: TimeZone = "+05:30".parse().unwrap();
+        assert_eq!(fixed.to_string(), "+05:30");
+        assert_eq!(offset_at(&fixed, "2020-07-01T00:00:00Z"), 19800);
+        assert!(TimeZone::default().is_utc());
+
+        // daylight saving time in New York starts on the second Sunday
+        // of March at 02:00 EST and ends on the first Sunday of November
+        // at 02:00 EDT
+        let new_york: TimeZone = "America/New_York".parse().unwrap();
+        assert_eq!(new_york.to_string(), "America/New_York");
+        let cases = &[
+            ("2020-03-08T06:59:59Z", -5 * 3600),
+            ("2020-03-08T07:00:00Z", -4 * 3600),
+            ("2020-11-01T05:59:59Z", -4 * 3600),
+            ("2020-11-01T06:00:00Z", -5 * 3600),
+            ("2020-12-31T23:00:00Z", -5 * 3600),
+        ];
+        for (time, offset) in cases {
+            assert_eq!(offset_at(&new_york, time), *offset, "{}", time);
+        }
+
+        // in Sydney it spans new year, and POSIX strings work as well
+        let sydney: TimeZone = "AEST-10AEDT,M10.1.0,M4.1.0/3".parse().unwrap();
+        let cases = &[
+            ("2020-01-15T00:00:00Z", 11 * 3600),
+            ("2020-04-04T15:59:59Z", 11 * 3600),
+            ("2020-04-04T16:00:00Z", 10 * 3600),
+            ("2020-10-03T15:59:59Z", 10 * 3600),
+            ("2020-10-03T16:00:00Z", 11 * 3600),
+        ];
+        for (time, offset) in cases {
+            assert_eq!(offset_at(&sydney, time), *offset, "{}", time);
+        }
+
+        let kolkata: TimeZone = "Asia/Kolkata".parse().unwrap();
+        assert_eq!(offset_at(&kolkata, "2020-07-01T00:00:00Z"), 19800);
+        let quoted: TimeZone = "<-03>3".parse().unwrap();
+        assert_eq!(offset_at(&quoted, "2020-07-01T00:00:00Z"), -3 * 3600);
+
+        for s in &[
+            "Mars/Olympus_Mons",
+            "EST",
+            "EST5EDT",
+            "EST5EDT,M3.2.0",
+            "EST5EDT,M13.2.0,M11.1.0",
+            "EST5EDT,M3.6.0,M11.1.0",
+            "EST5EDT,J60,J300",
+            "ES5",
+        ] {
+            let err = s.parse::<TimeZone>().unwrap_err();
+            assert!(matches!(err, Error::InvalidTimeZone { .. }), "{}", s);
+        }
+
+        let serialized = String::from(new_york.clone());
+        assert_eq!(TimeZone::try_from(serialized).unwrap(), new_york);
+    }
+
+    #[test]
+    fn from_seconds() {
+        let offset = UtcOffset::from_seconds(-(3 * 3600 + 30 * 60)).unwrap();
+        assert_eq!(offset.to_string(), "-03:30");
+        assert_eq!("-00:30".parse::<UtcOffset>().unwrap().seconds(), -30 * 60);
+        assert!(UtcOffset::from_seconds(24 * 3600).is_none());
+    }
+}
@@ -0,0 +1,341 @@
+//! This module contains the verification of the flatbuffers of the WAL
+//! (`generated_types::wal`) before they are read.
+//!
+//! The generated accessors trust the bytes they are given: an offset read
+//! from corrupted bytes can point outside of the buffer, and an unknown
+//! union type or a string that isn't UTF-8 is undefined behavior. Bytes
+//! read back from storage are verified first, following the flatbuffers
+//! format: every offset must point inside the buffer, every table must
+//! have a vtable and fields that fit, and every string and vector must
+//! fit with its length. The fields and union types are looked up in the
+//! generated code, so the verification follows changes to the schema.
+
+use std::convert::TryFrom;
+
+use flatbuffers::VOffsetT;
+use generated_types::wal as wb;
+use snafu::{ensure, OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "{} at offset {} is out of bounds of the {} byte buffer",
+        what,
+        offset,
+        len
+    ))]
+    OutOfBounds {
+        what: &'static str,
+        offset: usize,
+        len: usize,
+    },
+
+    #[snafu(display("string at offset {} is not valid UTF-8", offset))]
+    InvalidUtf8 { offset: usize },
+
+    #[snafu(display("unknown union type {} at offset {}", union_type, offset))]
+    UnknownUnionType { union_type: u8, offset: usize },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Verifies a `Segment` and its `ReplicatedWriteData` tables. The payloads
+/// of the writes are only checked to fit, as they are verified on their
+/// own with `verify_replicated_write`.
+pub fn verify_segment(data: &[u8]) -> Result<()> {
+    let v = Verifier { buf: data };
+    let segment = v.root()?;
+    v.scalar(&segment, wb::Segment::VT_ID, 8)?;
+    v.scalar(&segment, wb::Segment::VT_WRITER_ID, 4)?;
+    for write in v.tables(&segment, wb::Segment::VT_WRITES)? {
+        v.vector(&write, wb::ReplicatedWriteData::VT_PAYLOAD, 1)?;
+    }
+    Ok(())
+}
+
+/// Verifies a `ReplicatedWrite` and the `WriteBufferBatch` in its payload
+pub fn verify_replicated_write(data: &[u8]) -> Result<()> {
+    let v = Verifier { buf: data };
+    let write = v.root()?;
+    v.scalar(&write, wb::ReplicatedWrite::VT_WRITER, 4)?;
+    v.scalar(&write, wb::ReplicatedWrite::VT_SEQUENCE, 8)?;
+    v.scalar(&write, wb::ReplicatedWrite::VT_CHECKSUM, 4)?;
+    match v.vector(&write, wb::ReplicatedWrite::VT_PAYLOAD, 1)? {
+        Some((start, len)) => verify_write_buffer_batch(&data[start..start + len]),
+        None => Ok(()),
+    }
+}
+
+fn verify_write_buffer_batch(data: &[u8]) -> Result<()> {
+    let v = Verifier { buf: data };
+    let batch = v.root()?;
+    v.scalar(&batch, wb::WriteBufferBatch::VT_VERSION, 2)?;
+
+    for entry in v.tables(&batch, wb::WriteBufferBatch::VT_ENTRIES)? {
+        v.string(&entry, wb::WriteBufferEntry::VT_PARTITION_KEY)?;
+        if let Some(delete) = v.table(&entry, wb::WriteBufferEntry::VT_DELETE)? {
+            v.string(&delete, wb::WriteBufferDelete::VT_TABLE_NAME)?;
+            v.string(&delete, wb::WriteBufferDelete::VT_PREDICATE)?;
+        }
+
+        for table_batch in v.tables(&entry, wb::WriteBufferEntry::VT_TABLE_BATCHES)? {
+            v.string(&table_batch, wb::TableWriteBatch::VT_NAME)?;
+            for row in v.tables(&table_batch, wb::TableWriteBatch::VT_ROWS)? {
+                for value in v.tables(&row, wb::Row::VT_VALUES)? {
+                    verify_value(&v, &value)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Verifies a `Value`, whose `ColumnValue` union is stored as a type and
+/// a table whose layout depends on the type
+fn verify_value(v: &Verifier<'_>, value: &Table) -> Result<()> {
+    v.string(value, wb::Value::VT_COLUMN)?;
+
+    let (offset, union_type) = match v.scalar(value, wb::Value::VT_VALUE_TYPE, 1)? {
+        Some(offset) => (offset, v.buf[offset]),
+        None => return Ok(()),
+    };
+    let value_type = wb::ENUM_VALUES_COLUMN_VALUE
+        .iter()
+        .copied()
+        .find(|value_type| *value_type as u8 == union_type)
+        .context(UnknownUnionType { union_type, offset })?;
+    let column_value = match v.table(value, wb::Value::VT_VALUE)? {
+        Some(table) => table,
+        None => return Ok(()),
+    };
+
+    match value_type {
+        wb::ColumnValue::TagValue => v.string(&column_value, wb::TagValue::VT_VALUE),
+        wb::ColumnValue::StringValue => v.string(&column_value, wb::StringValue::VT_VALUE),
+        wb::ColumnValue::I64Value => v
+            .scalar(&column_value, wb::I64Value::VT_VALUE, 8)
+            .map(|_| ()),
+        wb::ColumnValue::U64Value => v
+            .scalar(&column_value, wb::U64Value::VT_VALUE, 8)
+            .map(|_| ()),
+        wb::ColumnValue::F64Value => v
+            .scalar(&column_value, wb::F64Value::VT_VALUE, 8)
+            .map(|_| ()),
+        wb::ColumnValue::BoolValue => v
+            .scalar(&column_value, wb::BoolValue::VT_VALUE, 1)
+            .map(|_| ()),
+        // a value without a type has no table to read
+        wb::ColumnValue::NONE => Ok(()),
+    }
+}
+
+/// The location of a table in the buffer and of its vtable
+#[derive(Debug)]
+struct Table {
+    position: usize,
+    len: usize,
+    vtable: usize,
+    vtable_len: usize,
+}
+
+#[derive(Debug)]
+struct Verifier<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Verifier<'a> {
+    /// Checks that `size` bytes at `offset` are inside the buffer
+    fn range(&self, what: &'static str, offset: usize, size: usize) -> Result<()> {
+        let len = self.buf.len();
+        ensure!(
+            offset.checked_add(size).map_or(false, |end| end <= len),
+            OutOfBounds { what, offset, len }
+        );
+        Ok(())
+    }
+
+    fn read(&self, what: &'static str, offset: usize, size: usize) -> Result<&'a [u8]> {
+        self.range(what, offset, size)?;
+        Ok(&self.buf[offset..offset + size])
+    }
+
+    fn read_u16(&self, what: &'static str, offset: usize) -> Result<usize> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.read(what, offset, 2)?);
+        Ok(u16::from_le_bytes(bytes) as usize)
+    }
+
+    fn read_u32(&self, what: &'static str, offset: usize) -> Result<usize> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read(what, offset, 4)?);
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    /// Follows the offset stored at `offset`, which is relative to where
+    /// it is stored
+    fn follow(&self, what: &'static str, offset: usize) -> Result<usize> {
+        let target = offset
+            .checked_add(self.read_u32(what, offset)?)
+            .context(OutOfBounds {
+                what,
+                offset,
+                len: self.buf.len(),
+            })?;
+        self.range(what, target, 0)?;
+        Ok(target)
+    }
+
+    fn root(&self) -> Result<Table> {
+        let position = self.read_u32("root table offset", 0)?;
+        self.table_at(position)
+    }
+
+    fn table_at(&self, position: usize) -> Result<Table> {
+        // the vtable is found by subtracting a signed offset from the table
+        let soffset = self.read_u32("table", position)? as u32 as i32;
+        let vtable = i64::try_from(position).expect("buffer fits in an i64") - i64::from(soffset);
+        let vtable = usize::try_from(vtable).ok().context(OutOfBounds {
+            what: "vtable",
+            offset: position,
+            len: self.buf.len(),
+        })?;
+
+        let vtable_len = self.read_u16("vtable", vtable)?;
+        let len = self.read_u16("vtable", vtable + 2)?;
+        self.range("vtable", vtable, vtable_len.max(4))?;
+        self.range("table", position, len)?;
+
+        Ok(Table {
+            position,
+            len,
+            vtable,
+            vtable_len,
+        })
+    }
+
+    /// Returns where the field of `table` whose vtable offset is `field`
+    /// is stored, if it is present, checking that its `size` bytes fit in
+    /// the table
+    fn scalar(&self, table: &Table, field: VOffsetT, size: usize) -> Result<Option<usize>> {
+        let entry = usize::from(field);
+        if entry + 2 > table.vtable_len {
+            return Ok(None);
+        }
+        let offset = self.read_u16("vtable entry", table.vtable + entry)?;
+        if offset == 0 {
+            return Ok(None);
+        }
+
+        ensure!(
+            offset + size <= table.len,
+            OutOfBounds {
+                what: "field",
+                offset: table.position + offset,
+                len: self.buf.len(),
+            }
+        );
+        Ok(Some(table.position + offset))
+    }
+
+    /// Returns the table the field `field` of `table` refers to
+    fn table(&self, table: &Table, field: VOffsetT) -> Result<Option<Table>> {
+        match self.scalar(table, field, 4)? {
+            Some(offset) => Ok(Some(self.table_at(self.follow("table", offset)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the start and length in bytes of the elements of the
+    /// vector the field `field` of `table` refers to
+    fn vector(
+        &self,
+        table: &Table,
+        field: VOffsetT,
+        element_size: usize,
+    ) -> Result<Option<(usize, usize)>> {
+        let offset = match self.scalar(table, field, 4)? {
+            Some(offset) => self.follow("vector", offset)?,
+            None => return Ok(None),
+        };
+
+        let count = self.read_u32("vector", offset)?;
+        let start = offset + 4;
+        let len = count.checked_mul(element_size).context(OutOfBounds {
+            what: "vector",
+            offset,
+            len: self.buf.len(),
+        })?;
+        self.range("vector", start, len)?;
+        Ok(Some((start, len)))
+    }
+
+    /// Returns the tables the vector of tables field `field` of `table`
+    /// refers to
+    fn tables(&self, table: &Table, field: VOffsetT) -> Result<Vec<Table>> {
+        match self.vector(table, field, 4)? {
+            Some((start, len)) => (start..start + len)
+                .step_by(4)
+                .map(|offset| self.table_at(self.follow("table", offset)?))
+                .collect(),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Checks the string the field `field` of `table` refers to
+    fn string(&self, table: &Table, field: VOffsetT) -> Result<()> {
+        if let Some((start, len)) = self.vector(table, field, 1)? {
+            ensure!(
+                std::str::from_utf8(&self.buf[start..start + len]).is_ok(),
+                InvalidUtf8 { offset: start }
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use influxdb_line_protocol::parse_lines;
+
+    fn write_bytes(lp: &str) -> Vec<u8> {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default()).data
+    }
+
+    #[test]
+    fn valid_writes_are_verified() {
+        let data = write_bytes(
+            "cpu,host=a usage=0.5,cores=4i,up=true,name=\"x\" 10\nmem,host=a free=1i 10",
+        );
+        verify_replicated_write(&data).unwrap();
+    }
+
+    #[test]
+    fn truncated_writes_are_rejected() {
+        let data = write_bytes("cpu,host=a usage=0.5 10");
+        let err = verify_replicated_write(&data[..data.len() / 2]).unwrap_err();
+        assert!(matches!(err, Error::OutOfBounds { .. }), "{}", err);
+        assert!(verify_replicated_write(&[]).is_err());
+    }
+
+    #[test]
+    fn corrupted_writes_never_panic() {
+        let data = write_bytes("cpu,host=a usage=0.5,cores=4i,name=\"x\" 10");
+        for i in 0..data.len() {
+            for &byte in &[0x00, 0x7f, 0xff] {
+                let mut corrupted = data.clone();
+                corrupted[i] = byte;
+                // only whether verification returns matters
+                let _ = verify_replicated_write(&corrupted);
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_bounds_root_is_rejected() {
+        let err = verify_replicated_write(&[0xff, 0, 0, 0]).unwrap_err();
+        assert!(matches!(err, Error::OutOfBounds { .. }), "{}", err);
+    }
+}
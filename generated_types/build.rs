@@ -22,16 +22,19 @@ fn main() -> Result<()> {
 
 /// Schema used with IOx specific gRPC requests
 ///
-/// Creates `influxdata.platform.storage.rs` and
-/// `com.github.influxdata.idpe.storage.read.rs`
+/// Creates `influxdata.platform.storage.rs`,
+/// `com.github.influxdata.idpe.storage.read.rs` and `google.rpc.rs`
 fn generate_grpc_types(root: &Path) -> Result<()> {
     let proto_files = vec![
+        root.join("google/rpc/status.proto"),
+        root.join("google/rpc/error_details.proto"),
         root.join("test.proto"),
         root.join("predicate.proto"),
         root.join("storage_common.proto"),
         root.join("storage_common_idpe.proto"),
         root.join("service.proto"),
         root.join("source.proto"),
+        root.join("iox_storage.proto"),
     ];
 
     // Tell cargo to recompile if any of these proto files are changed
@@ -15,6 +15,12 @@ include!(concat!(
 ));
 include!(concat!(env!("OUT_DIR"), "/wal_generated.rs"));
 
+pub mod google {
+    pub mod rpc {
+        include!(concat!(env!("OUT_DIR"), "/google.rpc.rs"));
+    }
+}
+
 // Can't implement `Default` because `prost::Message` implements `Default`
 impl TimestampRange {
     pub fn max() -> Self {
@@ -3,7 +3,13 @@ use std::num::NonZeroU32;
 use data_types::database_rules::DatabaseRules;
 use reqwest::{Method, Url};
 
-use crate::errors::{CreateDatabaseError, Error, ServerErrorResponse};
+use crate::errors::{
+    CreateDatabaseError, Error, GetDatabaseError, QueryError, ServerErrorResponse, WriteError,
+};
+
+/// A row of SQL query results, mapping column names to values. NULL values
+/// are `Value::Null`.
+pub type Row = serde_json::Map<String, serde_json::Value>;
 
 // TODO: move DatabaseRules / WriterId to the API client
 
@@ -104,6 +110,87 @@ impl Client {
         }
     }
 
+    /// Fetches the rules of an IOx database.
+    pub async fn get_database(
+        &self,
+        name: impl AsRef<str>,
+    ) -> Result<DatabaseRules, GetDatabaseError> {
+        const DB_PATH: &str = "iox/api/v1/databases/";
+
+        let url = self
+            .url_for(DB_PATH)
+            .join(name.as_ref())
+            .map_err(|_| GetDatabaseError::InvalidName)?;
+
+        let r = self.http.request(Method::GET, url).send().await?;
+
+        match r {
+            r if r.status() == 200 => Ok(r.json().await?),
+            r => Err(ServerErrorResponse::from_response(r).await.into()),
+        }
+    }
+
+    /// Writes line protocol `lp_data` to the database the InfluxDB 2.x `org`
+    /// and `bucket` map to.
+    pub async fn write(
+        &self,
+        org: impl AsRef<str>,
+        bucket: impl AsRef<str>,
+        lp_data: impl Into<String>,
+    ) -> Result<(), WriteError> {
+        const WRITE_PATH: &str = "api/v2/write";
+
+        let r = self
+            .http
+            .request(Method::POST, self.url_for(WRITE_PATH))
+            .query(&[("org", org.as_ref()), ("bucket", bucket.as_ref())])
+            .body(lp_data.into())
+            .send()
+            .await?;
+
+        match r {
+            r if r.status() == 204 => Ok(()),
+            r => Err(ServerErrorResponse::from_response(r).await.into()),
+        }
+    }
+
+    /// Runs the SQL query `sql` against the database the InfluxDB 2.x `org`
+    /// and `bucket` map to, returning all the result rows.
+    pub async fn query(
+        &self,
+        org: impl AsRef<str>,
+        bucket: impl AsRef<str>,
+        sql: impl AsRef<str>,
+    ) -> Result<Vec<Row>, QueryError> {
+        const READ_PATH: &str = "api/v2/read";
+
+        let r = self
+            .http
+            .request(Method::GET, self.url_for(READ_PATH))
+            .query(&[
+                ("org", org.as_ref()),
+                ("bucket", bucket.as_ref()),
+                ("sql_query", sql.as_ref()),
+                ("format", "json"),
+            ])
+            .send()
+            .await?;
+
+        match r {
+            r if r.status() == 200 => {
+                // the results are JSON lines, one object per row
+                let body = r.text().await?;
+                let rows = body
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(serde_json::from_str)
+                    .collect::<Result<Vec<Row>, _>>()?;
+                Ok(rows)
+            }
+            r => Err(ServerErrorResponse::from_response(r).await.into()),
+        }
+    }
+
     /// Set the server's writer ID.
     pub async fn set_writer_id(&self, id: NonZeroU32) -> Result<(), Error> {
         const SET_WRITER_PATH: &str = "iox/api/v1/id";
@@ -249,6 +336,31 @@ mod tests {
         assert!(matches!(dbg!(err), CreateDatabaseError::InvalidName))
     }
 
+    #[tokio::test]
+    async fn test_get_database() {
+        let endpoint = maybe_skip_integration!();
+        let c = ClientBuilder::default().build(endpoint).unwrap();
+
+        let rand_name: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(10)
+            .map(char::from)
+            .collect();
+
+        let err = c
+            .get_database(&rand_name)
+            .await
+            .expect_err("expected request to fail");
+        assert!(matches!(dbg!(err), GetDatabaseError::NotFound));
+
+        c.create_database(&rand_name, &DatabaseRules::default())
+            .await
+            .expect("create database failed");
+        c.get_database(&rand_name)
+            .await
+            .expect("get database failed");
+    }
+
     #[test]
     fn test_default() {
         // Ensures the Default impl does not panic
@@ -0,0 +1,47 @@
+use thiserror::Error;
+
+use super::{ApiErrorCode, HttpError, ServerErrorResponse};
+
+/// Error responses when fetching the rules of an IOx database.
+#[derive(Debug, Error)]
+pub enum GetDatabaseError {
+    /// The database name contains an invalid character.
+    #[error("the database name contains an invalid character")]
+    InvalidName,
+
+    /// The requested database does not exist.
+    #[error("the requested database does not exist")]
+    NotFound,
+
+    /// An unknown server error occured.
+    ///
+    /// The error string contains the error string returned by the server.
+    #[error(transparent)]
+    ServerError(ServerErrorResponse),
+
+    /// A non-application HTTP request/response error occurred.
+    #[error(transparent)]
+    HttpError(#[from] HttpError),
+}
+
+/// Convert a [`ServerErrorResponse`] into a [`GetDatabaseError`].
+///
+/// This conversion plucks any errors with API error codes that are applicable
+/// to [`GetDatabaseError`] types, and everything else becomes a
+/// `ServerError`.
+impl From<ServerErrorResponse> for GetDatabaseError {
+    fn from(err: ServerErrorResponse) -> Self {
+        match err.error_code() {
+            Some(c) if c == ApiErrorCode::DB_INVALID_NAME as u32 => Self::InvalidName,
+            Some(c) if c == ApiErrorCode::DB_NOT_FOUND as u32 => Self::NotFound,
+            _ => Self::ServerError(err),
+        }
+    }
+}
+
+/// Convert errors from the underlying HTTP client into `HttpError` instances.
+impl From<reqwest::Error> for GetDatabaseError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::HttpError(err.into())
+    }
+}
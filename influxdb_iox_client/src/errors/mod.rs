@@ -29,6 +29,15 @@ pub use server_error_response::*;
 mod create_database;
 pub use create_database::*;
 
+mod get_database;
+pub use get_database::*;
+
+mod write;
+pub use write::*;
+
+mod query;
+pub use query::*;
+
 /// Constants used in API error codes.
 ///
 /// Expressing this as a enum prevents reuse of discriminants, and as they're
@@ -47,6 +56,11 @@ pub enum ApiErrorCode {
 
     /// The database referenced does not exist.
     DB_NOT_FOUND = 103,
+
+    /// The request exceeds a size limit of the server: the size of its
+    /// body, its number of lines of line protocol or the length of its
+    /// SQL query.
+    REQUEST_TOO_LARGE = 104,
 }
 
 impl From<ApiErrorCode> for u32 {
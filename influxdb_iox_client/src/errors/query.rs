@@ -0,0 +1,45 @@
+use thiserror::Error;
+
+use super::{ApiErrorCode, HttpError, ServerErrorResponse};
+
+/// Error responses when running a SQL query.
+#[derive(Debug, Error)]
+pub enum QueryError {
+    /// A row of the query results could not be decoded.
+    #[error("invalid row in query results: {0}")]
+    InvalidRow(#[from] serde_json::Error),
+
+    /// The query exceeds the limit of the server on the length of a
+    /// query.
+    #[error("the query is too long: {0}")]
+    TooLong(ServerErrorResponse),
+
+    /// The IOx server has responded with an error, for example because the
+    /// query is invalid.
+    #[error(transparent)]
+    ServerError(ServerErrorResponse),
+
+    /// A non-application HTTP request/response error occurred.
+    #[error(transparent)]
+    HttpError(#[from] HttpError),
+}
+
+/// Convert a [`ServerErrorResponse`] into a [`QueryError`].
+///
+/// This conversion plucks any errors with API error codes that are applicable
+/// to [`QueryError`] types, and everything else becomes a `ServerError`.
+impl From<ServerErrorResponse> for QueryError {
+    fn from(err: ServerErrorResponse) -> Self {
+        match err.error_code() {
+            Some(c) if c == ApiErrorCode::REQUEST_TOO_LARGE as u32 => Self::TooLong(err),
+            _ => Self::ServerError(err),
+        }
+    }
+}
+
+/// Convert errors from the underlying HTTP client into `HttpError` instances.
+impl From<reqwest::Error> for QueryError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::HttpError(err.into())
+    }
+}
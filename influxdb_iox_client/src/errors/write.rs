@@ -0,0 +1,48 @@
+use thiserror::Error;
+
+use super::{ApiErrorCode, HttpError, ServerErrorResponse};
+
+/// Error responses when writing line protocol to an IOx database.
+#[derive(Debug, Error)]
+pub enum WriteError {
+    /// The database the org and bucket map to does not exist.
+    #[error("the database for the org and bucket does not exist")]
+    DatabaseNotFound,
+
+    /// The write exceeds the limit of the server on the size of a
+    /// request or its number of lines, so it should be split up.
+    #[error("the write is too large: {0}")]
+    TooLarge(ServerErrorResponse),
+
+    /// An unknown server error occured, for example because the line
+    /// protocol is malformed.
+    ///
+    /// The error string contains the error string returned by the server.
+    #[error(transparent)]
+    ServerError(ServerErrorResponse),
+
+    /// A non-application HTTP request/response error occurred.
+    #[error(transparent)]
+    HttpError(#[from] HttpError),
+}
+
+/// Convert a [`ServerErrorResponse`] into a [`WriteError`].
+///
+/// This conversion plucks any errors with API error codes that are applicable
+/// to [`WriteError`] types, and everything else becomes a `ServerError`.
+impl From<ServerErrorResponse> for WriteError {
+    fn from(err: ServerErrorResponse) -> Self {
+        match err.error_code() {
+            Some(c) if c == ApiErrorCode::DB_NOT_FOUND as u32 => Self::DatabaseNotFound,
+            Some(c) if c == ApiErrorCode::REQUEST_TOO_LARGE as u32 => Self::TooLarge(err),
+            _ => Self::ServerError(err),
+        }
+    }
+}
+
+/// Convert errors from the underlying HTTP client into `HttpError` instances.
+impl From<reqwest::Error> for WriteError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::HttpError(err.into())
+    }
+}
@@ -140,7 +140,7 @@ impl nom::error::ParseError<&str> for Error {
 ///
 /// assert_eq!(timestamp, Some(1590488773254420000));
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParsedLine<'a> {
     pub series: Series<'a>,
     pub field_set: FieldSet<'a>,
@@ -230,9 +230,10 @@ impl<'a> Display for ParsedLine<'a> {
 
 /// Represents the identifier of a series (measurement, tagset) for
 /// line protocol data
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Series<'a> {
-    raw_input: &'a str,
+    /// The input the series was parsed from, if it is unchanged since
+    raw_input: Option<&'a str>,
     pub measurement: EscapedStr<'a>,
     pub tag_set: Option<TagSet<'a>>,
 }
@@ -259,16 +260,37 @@ impl<'a> Display for Series<'a> {
 }
 
 impl<'a> Series<'a> {
+    /// Returns the series of `measurement` with the tags `tag_set`, for
+    /// lines built from values rather than parsed
+    pub fn new(measurement: EscapedStr<'a>, tag_set: Option<TagSet<'a>>) -> Self {
+        Self {
+            raw_input: None,
+            measurement,
+            tag_set,
+        }
+    }
+
     pub fn generate_base(self) -> Result<Cow<'a, str>> {
-        match (!self.is_escaped(), self.is_sorted_and_unique()) {
-            (true, true) => Ok(self.raw_input.into()),
-            (_, true) => self.generate_base_with_escaping().map(Into::into),
-            (_, _) => self
+        match (
+            self.raw_input,
+            !self.is_escaped(),
+            self.is_sorted_and_unique(),
+        ) {
+            (Some(raw_input), true, true) => Ok(raw_input.into()),
+            (_, _, true) => self.generate_base_with_escaping().map(Into::into),
+            (_, _, _) => self
                 .generate_base_with_escaping_sorting_deduplicating()
                 .map(Into::into),
         }
     }
 
+    /// Forgets the input the series was parsed from. This must be called
+    /// after changing the measurement or tags of the series, so that
+    /// `generate_base` generates it from them.
+    pub fn forget_raw_input(&mut self) {
+        self.raw_input = None;
+    }
+
     fn generate_base_with_escaping(self) -> Result<String> {
         let mut series_base = self.measurement.to_string();
         for (tag_key, tag_value) in self.tag_set.unwrap_or_default() {
@@ -591,7 +613,7 @@ fn series(i: &str) -> IResult<&str, Series<'_>> {
     map(
         series_and_raw_input,
         |(raw_input, (measurement, tag_set))| Series {
-            raw_input,
+            raw_input: Some(raw_input),
             measurement,
             tag_set,
         },
@@ -985,10 +1007,10 @@ fn map_fail<'a, R1, R2>(
 // copy / pasted from influxdb2_client to avoid a dependency on that crate
 
 /// Characters to escape when writing measurement names
-const MEASUREMENT_DELIMITERS: &[char] = &[',', ' '];
+const MEASUREMENT_DELIMITERS: &[char] = &[',', ' ', '\\'];
 
 /// Characters to escape when writing tag keys
-const TAG_KEY_DELIMITERS: &[char] = &[',', '=', ' '];
+const TAG_KEY_DELIMITERS: &[char] = &[',', '=', ' ', '\\'];
 
 /// Characters to escape when writing tag values
 const TAG_VALUE_DELIMITERS: &[char] = TAG_KEY_DELIMITERS;
@@ -997,14 +1019,46 @@ const TAG_VALUE_DELIMITERS: &[char] = TAG_KEY_DELIMITERS;
 const FIELD_KEY_DELIMITERS: &[char] = TAG_KEY_DELIMITERS;
 
 /// Characters to escape when writing string values in fields
-const FIELD_VALUE_STRING_DELIMITERS: &[char] = &['"'];
+const FIELD_VALUE_STRING_DELIMITERS: &[char] = &['"', '\\'];
+
+/// The parts of a line that a name or string value can be written to,
+/// each of which escapes a different set of characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinePart {
+    Measurement,
+    TagKey,
+    TagValue,
+    FieldKey,
+    /// The contents of a string field value, without its quotes
+    FieldStringValue,
+}
+
+impl LinePart {
+    fn delimiters(self) -> &'static [char] {
+        match self {
+            Self::Measurement => MEASUREMENT_DELIMITERS,
+            Self::TagKey => TAG_KEY_DELIMITERS,
+            Self::TagValue => TAG_VALUE_DELIMITERS,
+            Self::FieldKey => FIELD_KEY_DELIMITERS,
+            Self::FieldStringValue => FIELD_VALUE_STRING_DELIMITERS,
+        }
+    }
+}
+
+/// Returns `value` backslash escaped so that it parses back to `value`
+/// when written as the `part` of a line
+pub fn escape(value: &str, part: LinePart) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    escape_and_write_value(&mut escaped, value, part.delimiters()).expect("writing to a string");
+    escaped
+}
 
 /// Writes a str value to f, escaping all caracters in
 /// escaping_escaping specificiation.
 ///
 /// Use the constants defined in this module
 fn escape_and_write_value(
-    f: &mut fmt::Formatter<'_>,
+    f: &mut impl fmt::Write,
     value: &str,
     escaping_specification: &[char],
 ) -> fmt::Result {
@@ -1470,6 +1524,22 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn generate_base_of_changed_series() -> Result {
+        let input = "foo,tag1=1";
+        let (_, mut series) = series(input)?;
+        series
+            .tag_set
+            .as_mut()
+            .unwrap()
+            .push((EscapedStr::from("tag2"), EscapedStr::from("2")));
+        series.forget_raw_input();
+
+        assert_eq!(series.generate_base()?, "foo,tag1=1,tag2=2");
+
+        Ok(())
+    }
+
     #[test]
     fn parse_tag_set_duplicate_tags() -> Result {
         let input = "foo,tag=1,tag=2";
@@ -1926,7 +1996,7 @@ her"#,
     #[test]
     fn series_display_no_tags() -> Result {
         let series = Series {
-            raw_input: "foo",
+            raw_input: Some("foo"),
             measurement: EscapedStr::from("m"),
             tag_set: None,
         };
@@ -1937,7 +2007,7 @@ her"#,
     #[test]
     fn series_display_one_tag() -> Result {
         let series = Series {
-            raw_input: "foo",
+            raw_input: Some("foo"),
             measurement: EscapedStr::from("m"),
             tag_set: Some(smallvec![(
                 EscapedStr::from("tag1"),
@@ -1951,7 +2021,7 @@ her"#,
     #[test]
     fn series_display_two_tags() -> Result {
         let series = Series {
-            raw_input: "foo",
+            raw_input: Some("foo"),
             measurement: EscapedStr::from("m"),
             tag_set: Some(smallvec![
                 (EscapedStr::from("tag1"), EscapedStr::from("val1")),
@@ -1965,7 +2035,7 @@ her"#,
     #[test]
     fn parsed_line_display_one_field_no_timestamp() -> Result {
         let series = Series {
-            raw_input: "foo",
+            raw_input: Some("foo"),
             measurement: EscapedStr::from("m"),
             tag_set: Some(smallvec![(
                 EscapedStr::from("tag1"),
@@ -1987,7 +2057,7 @@ her"#,
     #[test]
     fn parsed_line_display_one_field_timestamp() -> Result {
         let series = Series {
-            raw_input: "foo",
+            raw_input: Some("foo"),
             measurement: EscapedStr::from("m"),
             tag_set: Some(smallvec![(
                 EscapedStr::from("tag1"),
@@ -2009,7 +2079,7 @@ her"#,
     #[test]
     fn parsed_line_display_two_fields_timestamp() -> Result {
         let series = Series {
-            raw_input: "foo",
+            raw_input: Some("foo"),
             measurement: EscapedStr::from("m"),
             tag_set: Some(smallvec![(
                 EscapedStr::from("tag1"),
@@ -2037,7 +2107,7 @@ her"#,
     #[test]
     fn parsed_line_display_escaped() -> Result {
         let series = Series {
-            raw_input: "foo",
+            raw_input: Some("foo"),
             measurement: EscapedStr::from("m,and m"),
             tag_set: Some(smallvec![(
                 EscapedStr::from("tag ,1"),
@@ -2062,6 +2132,31 @@ her"#,
         Ok(())
     }
 
+    #[test]
+    fn escaped_backslashes_round_trip() -> Result {
+        let lp = format!(
+            r#"{},{}={} {}="{}" 1"#,
+            escape(r"m\1", LinePart::Measurement),
+            escape(r"t\ag", LinePart::TagKey),
+            escape(r"a\=b", LinePart::TagValue),
+            escape(r"f\,", LinePart::FieldKey),
+            escape(r#"s\"x"#, LinePart::FieldStringValue),
+        );
+        assert_eq!(lp, r#"m\\1,t\\ag=a\\\=b f\\\,="s\\\"x" 1"#);
+
+        let vals = parse(&lp)?;
+        assert_eq!(vals.len(), 1);
+        assert_eq!(vals[0].series.measurement, r"m\1");
+        assert_eq!(vals[0].series.tag_set.as_ref().unwrap()[0].0, r"t\ag");
+        assert_eq!(vals[0].series.tag_set.as_ref().unwrap()[0].1, r"a\=b");
+        assert_eq!(vals[0].field_set[0].0, r"f\,");
+        assert_eq!(
+            vals[0].field_value(r"f\,").unwrap().unwrap_string(),
+            r#"s\"x"#
+        );
+        Ok(())
+    }
+
     #[test]
     fn field_value_returned() -> Result {
         let input = r#"foo asdf=true 1234"#;
@@ -30,6 +30,7 @@ use std::{
 };
 use tracing::debug;
 
+pub mod line_protocol;
 pub mod parquet;
 
 #[derive(Debug, Clone, Copy)]
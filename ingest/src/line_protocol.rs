@@ -0,0 +1,282 @@
+//! This module contains a table writer that keeps the rows of the tables
+//! converted from TSM files, so they can be written to a database as
+//! parsed lines, like any other line protocol. The lines are built from
+//! the values of the rows rather than rendered as text and parsed again,
+//! so any measurement, tag or field value converts.
+
+use std::sync::{Arc, Mutex};
+
+use data_types::schema::{InfluxColumnType, Schema};
+use influxdb_line_protocol::{FieldValue, ParsedLine, Series};
+use packers::{Error as TableError, IOxTableWriter, IOxTableWriterSource, Packers};
+
+/// An `IOxTableWriterSource` whose writers keep the rows of each table
+#[derive(Debug, Default, Clone)]
+pub struct LineProtocolWriterSource {
+    tables: Arc<Mutex<Vec<ConvertedTable>>>,
+}
+
+impl LineProtocolWriterSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the tables written so far, removing them from this source
+    pub fn take_tables(&self) -> Vec<ConvertedTable> {
+        std::mem::take(&mut *self.tables.lock().expect("mutex poisoned"))
+    }
+}
+
+impl IOxTableWriterSource for LineProtocolWriterSource {
+    fn next_writer(&mut self, schema: &Schema) -> Result<Box<dyn IOxTableWriter>, TableError> {
+        let measurement = schema.measurement().ok_or_else(|| TableError::Data {
+            source: "table has no measurement name".into(),
+        })?;
+        let columns = (0..schema.len())
+            .map(|idx| {
+                let (column_type, field) = schema.field(idx);
+                (column_type, field.name().to_string())
+            })
+            .collect();
+
+        Ok(Box::new(LineProtocolTableWriter {
+            table: Some(ConvertedTable {
+                measurement: measurement.to_string(),
+                columns,
+                rows: Vec::new(),
+            }),
+            tables: Arc::clone(&self.tables),
+        }))
+    }
+}
+
+/// The rows of a table converted from TSM files
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertedTable {
+    measurement: String,
+    /// The type and name of each column
+    columns: Vec<(Option<InfluxColumnType>, String)>,
+    rows: Vec<ConvertedRow>,
+}
+
+/// The values of a row of a `ConvertedTable`, by column index
+#[derive(Debug, Clone, PartialEq)]
+struct ConvertedRow {
+    tags: Vec<(usize, String)>,
+    fields: Vec<(usize, ConvertedValue)>,
+    time: Option<i64>,
+}
+
+/// The value of a field of a `ConvertedRow`
+#[derive(Debug, Clone, PartialEq)]
+enum ConvertedValue {
+    F64(f64),
+    I64(i64),
+    String(String),
+    Boolean(bool),
+}
+
+impl ConvertedValue {
+    fn field_value(&self) -> FieldValue<'_> {
+        match self {
+            Self::F64(v) => FieldValue::F64(*v),
+            Self::I64(v) => FieldValue::I64(*v),
+            Self::String(v) => FieldValue::String(v.as_str().into()),
+            Self::Boolean(v) => FieldValue::Boolean(*v),
+        }
+    }
+}
+
+impl ConvertedTable {
+    pub fn measurement(&self) -> &str {
+        &self.measurement
+    }
+
+    /// Returns the rows of the table as lines. Rows with no fields can't be
+    /// written as line protocol and are left out.
+    pub fn lines(&self) -> Vec<ParsedLine<'_>> {
+        self.rows
+            .iter()
+            .filter(|row| !row.fields.is_empty())
+            .map(|row| {
+                let tag_set = row
+                    .tags
+                    .iter()
+                    .map(|(idx, value)| {
+                        (self.columns[*idx].1.as_str().into(), value.as_str().into())
+                    })
+                    .collect();
+                let tag_set = if row.tags.is_empty() {
+                    None
+                } else {
+                    Some(tag_set)
+                };
+                let field_set = row
+                    .fields
+                    .iter()
+                    .map(|(idx, value)| (self.columns[*idx].1.as_str().into(), value.field_value()))
+                    .collect();
+
+                ParsedLine {
+                    series: Series::new(self.measurement.as_str().into(), tag_set),
+                    field_set,
+                    timestamp: row.time,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct LineProtocolTableWriter {
+    /// The table being written, until the writer is closed
+    table: Option<ConvertedTable>,
+    tables: Arc<Mutex<Vec<ConvertedTable>>>,
+}
+
+impl IOxTableWriter for LineProtocolTableWriter {
+    fn write_batch(&mut self, packers: &[Packers]) -> Result<(), TableError> {
+        let table = self.table.as_mut().ok_or_else(|| TableError::Data {
+            source: "table writer is closed".into(),
+        })?;
+        let num_rows = packers.first().map_or(0, Packers::num_rows);
+
+        for row in 0..num_rows {
+            let mut converted = ConvertedRow {
+                tags: Vec::new(),
+                fields: Vec::new(),
+                time: None,
+            };
+            for (idx, packer) in packers.iter().enumerate() {
+                if packer.is_null(row) {
+                    continue;
+                }
+                match table
+                    .columns
+                    .get(idx)
+                    .and_then(|(column_type, _)| *column_type)
+                {
+                    Some(InfluxColumnType::Tag) => converted
+                        .tags
+                        .push((idx, str_value(packer, row)?.to_string())),
+                    Some(InfluxColumnType::Field(_)) => {
+                        converted.fields.push((idx, field_value(packer, row)?))
+                    }
+                    Some(InfluxColumnType::Timestamp) => {
+                        converted.time = packer.i64_packer().get(row).copied();
+                    }
+                    None => {}
+                }
+            }
+            table.rows.push(converted);
+        }
+
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), TableError> {
+        if let Some(table) = self.table.take() {
+            self.tables.lock().expect("mutex poisoned").push(table);
+        }
+        Ok(())
+    }
+}
+
+/// Returns an error for a value missing from a row that isn't null
+fn missing_value(packer: &Packers, row: usize) -> TableError {
+    TableError::Data {
+        source: format!("row {} of {:?} has no value", row, packer).into(),
+    }
+}
+
+/// Returns the string in `row` of the tag or string field `packer`
+fn str_value(packer: &Packers, row: usize) -> Result<&str, TableError> {
+    match packer {
+        Packers::Bytes(p) => p
+            .get(row)
+            .ok_or_else(|| missing_value(packer, row))?
+            .as_utf8()
+            .map_err(TableError::from_other),
+        Packers::String(p) => p
+            .get(row)
+            .map(String::as_str)
+            .ok_or_else(|| missing_value(packer, row)),
+        _ => Err(TableError::ColumnWithMixedTypes {
+            column_name: None,
+            details: format!("expected strings, found {:?}", packer),
+        }),
+    }
+}
+
+fn field_value(packer: &Packers, row: usize) -> Result<ConvertedValue, TableError> {
+    let missing = || missing_value(packer, row);
+    Ok(match packer {
+        Packers::Float(p) => ConvertedValue::F64(*p.get(row).ok_or_else(missing)?),
+        Packers::Integer(p) => ConvertedValue::I64(*p.get(row).ok_or_else(missing)?),
+        Packers::Boolean(p) => ConvertedValue::Boolean(*p.get(row).ok_or_else(missing)?),
+        Packers::Bytes(_) | Packers::String(_) => {
+            ConvertedValue::String(str_value(packer, row)?.to_string())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::schema::{builder::SchemaBuilder, InfluxFieldType};
+    use packers::{ByteArray, Packer};
+
+    #[test]
+    fn converts_rows_to_lines() {
+        let schema = SchemaBuilder::new()
+            .measurement("#cpu load")
+            .tag("host")
+            .influx_field("usage", InfluxFieldType::Float)
+            .influx_field("note", InfluxFieldType::String)
+            .timestamp()
+            .build()
+            .unwrap();
+        let packers = vec![
+            Packers::Bytes(Packer::from(vec![
+                Some(ByteArray::from("a,b\\")),
+                None,
+                Some(ByteArray::from("c")),
+            ])),
+            Packers::Float(Packer::from(vec![Some(1.0), Some(f64::NAN), None])),
+            Packers::Bytes(Packer::from(vec![
+                None,
+                Some(ByteArray::from("say \"hi\"")),
+                None,
+            ])),
+            Packers::Integer(Packer::from(vec![Some(10), Some(20), Some(30)])),
+        ];
+
+        let mut source = LineProtocolWriterSource::new();
+        let mut writer = source.next_writer(&schema).unwrap();
+        writer.write_batch(&packers).unwrap();
+        writer.close().unwrap();
+
+        let tables = source.take_tables();
+        assert!(source.take_tables().is_empty());
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].measurement(), "#cpu load");
+
+        // the values are kept as they are, and the row without fields is
+        // left out
+        let lines = tables[0].lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].series.measurement, "#cpu load");
+        assert_eq!(lines[0].tag_value("host").unwrap(), &"a,b\\");
+        assert_eq!(lines[0].field_value("usage"), Some(&FieldValue::F64(1.0)));
+        assert_eq!(lines[0].timestamp, Some(10));
+        assert!(lines[1].tag_value("host").is_none());
+        assert!(matches!(
+            lines[1].field_value("usage"),
+            Some(FieldValue::F64(v)) if v.is_nan()
+        ));
+        assert_eq!(
+            lines[1].field_value("note"),
+            Some(&FieldValue::String("say \"hi\"".into()))
+        );
+    }
+}
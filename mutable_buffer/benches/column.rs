@@ -0,0 +1,136 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use data_types::partition_metadata::Statistics;
+use mutable_buffer::benchmarks::{Bitmap, Column, NullableBoolVec};
+
+const ROWS: [usize; 3] = [1_000, 10_000, 100_000];
+
+// Percentage of rows in the filtered column that are non-null
+const DENSITY: [usize; 3] = [1, 10, 100];
+
+fn push_bools(c: &mut Criterion) {
+    let mut group = c.benchmark_group("push_bools");
+
+    for &num_rows in &ROWS {
+        group.throughput(Throughput::Elements(num_rows as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("packed", num_rows),
+            &num_rows,
+            |b, &num_rows| {
+                b.iter(|| {
+                    let mut vals = NullableBoolVec::default();
+                    for i in 0..num_rows {
+                        vals.push(bool_value(i));
+                    }
+                    vals
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("vec_of_option", num_rows),
+            &num_rows,
+            |b, &num_rows| {
+                b.iter(|| {
+                    let mut vals = Vec::new();
+                    for i in 0..num_rows {
+                        vals.push(bool_value(i));
+                    }
+                    vals
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn has_non_null_i64_range(c: &mut Criterion) {
+    let mut group = c.benchmark_group("has_non_null_i64_range");
+
+    for &num_rows in &ROWS {
+        let time_column = Column::I64((0..num_rows as i64).map(Some).collect(), Statistics::new(0));
+
+        for &density in &DENSITY {
+            group.throughput(Throughput::Elements(num_rows as u64));
+
+            let validity: Bitmap = (0..num_rows).map(|i| i % 100 < density).collect();
+            let values: Vec<Option<u32>> = validity
+                .iter()
+                .map(|valid| if valid { Some(0) } else { None })
+                .collect();
+
+            // no row is in range, so every row has to be checked
+            let (start, end) = (-10, -1);
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("packed_density_{}", density), num_rows),
+                &validity,
+                |b, validity| {
+                    b.iter(|| {
+                        time_column
+                            .has_non_null_i64_range(validity, start, end)
+                            .unwrap()
+                    });
+                },
+            );
+
+            // The previous implementation, which stored each column
+            // as a `Vec<Option<_>>` and checked every row
+            let times: Vec<Option<i64>> = (0..num_rows as i64).map(Some).collect();
+            group.bench_with_input(
+                BenchmarkId::new(format!("vec_of_option_density_{}", density), num_rows),
+                &values,
+                |b, values| {
+                    b.iter(|| {
+                        times.iter().zip(values.iter()).any(|(time, value)| {
+                            matches!(time, Some(time) if start <= *time && *time < end)
+                                && value.is_some()
+                        })
+                    });
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn i64_range_selection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("i64_range_selection");
+
+    for &num_rows in &ROWS {
+        let mut stats = Statistics::new(0);
+        stats.update(num_rows as i64);
+        let time_column = Column::I64((0..num_rows as i64).map(Some).collect(), stats);
+
+        group.throughput(Throughput::Elements(num_rows as u64));
+
+        // select the middle half of the rows
+        let (start, end) = (num_rows as i64 / 4, 3 * num_rows as i64 / 4);
+
+        group.bench_with_input(
+            BenchmarkId::new("selection", num_rows),
+            &time_column,
+            |b, time_column| {
+                b.iter(|| time_column.i64_range_selection(start, end).unwrap());
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bool_value(i: usize) -> Option<bool> {
+    if i % 7 == 0 {
+        None
+    } else {
+        Some(i % 2 == 0)
+    }
+}
+
+criterion_group!(
+    benches,
+    push_bools,
+    has_non_null_i64_range,
+    i64_range_selection
+);
+criterion_main!(benches);
@@ -0,0 +1,276 @@
+//! A growable, packed bitmap used to store boolean values and the
+//! validity (null / non-null) of column values in the mutable buffer
+//! using a single bit per row.
+
+/// The number of bits in each word of a [`Bitmap`](struct.Bitmap.html)
+pub const WORD_BITS: usize = 64;
+
+/// A growable sequence of bits, packed into 64 bit words.
+///
+/// Bit `i` is stored in bit `i % 64` of word `i / 64`. Bits past
+/// `len` in the last word are always zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    /// Create an empty bitmap
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a bitmap of `len` bits, all unset
+    pub fn new_unset(len: usize) -> Self {
+        Self {
+            words: vec![0; Self::words_for(len)],
+            len,
+        }
+    }
+
+    /// Create a bitmap of `len` bits from `words`, packed as described
+    /// above. Any bits past `len` are cleared.
+    pub fn from_words(mut words: Vec<u64>, len: usize) -> Self {
+        assert_eq!(
+            words.len(),
+            Self::words_for(len),
+            "wrong number of words for bitmap of length {}",
+            len
+        );
+
+        let tail_bits = len % WORD_BITS;
+        if tail_bits != 0 {
+            *words.last_mut().expect("bitmap has words") &= (1 << tail_bits) - 1;
+        }
+        Self { words, len }
+    }
+
+    /// Append `value` to the end of this bitmap
+    pub fn push(&mut self, value: bool) {
+        let bit = self.len % WORD_BITS;
+        if bit == 0 {
+            self.words.push(0);
+        }
+        if value {
+            *self.words.last_mut().expect("word was just pushed") |= 1 << bit;
+        }
+        self.len += 1;
+    }
+
+    /// Grows this bitmap to `len` bits, the new bits unset. Does nothing
+    /// if it already has at least `len` bits.
+    pub fn extend_unset(&mut self, len: usize) {
+        if len > self.len {
+            self.words.resize(Self::words_for(len), 0);
+            self.len = len;
+        }
+    }
+
+    /// Sets the bit at `index`. Panics if `index` is out of bounds
+    pub fn set(&mut self, index: usize) {
+        assert!(
+            index < self.len,
+            "bitmap index {} out of bounds for length {}",
+            index,
+            self.len
+        );
+        self.words[index / WORD_BITS] |= 1 << (index % WORD_BITS);
+    }
+
+    /// Unsets every bit that is not also set in `other`. Bits past
+    /// the end of `other` are unset.
+    pub fn and_assign(&mut self, other: &Self) {
+        for (index, word) in self.words.iter_mut().enumerate() {
+            *word &= other.words.get(index).copied().unwrap_or(0);
+        }
+    }
+
+    /// Returns the value of the bit at `index`. Panics if `index` is
+    /// out of bounds
+    pub fn get(&self, index: usize) -> bool {
+        assert!(
+            index < self.len,
+            "bitmap index {} out of bounds for length {}",
+            index,
+            self.len
+        );
+        self.words[index / WORD_BITS] & (1 << (index % WORD_BITS)) != 0
+    }
+
+    /// Returns the number of bits in this bitmap
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of set bits
+    pub fn count_set(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Returns true if any bit is set in both `self` and `other`
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .any(|(a, b)| a & b != 0)
+    }
+
+    /// Returns the underlying words of this bitmap
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Returns an iterator over the values of all bits
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).map(move |index| self.get(index))
+    }
+
+    /// Returns an iterator over the indexes of the set bits, in
+    /// increasing order. Unset bits are skipped a word at a time.
+    pub fn iter_set(&self) -> impl Iterator<Item = usize> + '_ {
+        Self::set_indexes(self.words.iter().copied())
+    }
+
+    /// Returns an iterator over the indexes of the bits that are set
+    /// in both `self` and `other`, in increasing order.
+    pub fn iter_set_in_both<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = usize> + 'a {
+        Self::set_indexes(
+            self.words
+                .iter()
+                .zip(other.words.iter())
+                .map(|(a, b)| a & b),
+        )
+    }
+
+    /// Returns the approximate memory used by this bitmap, in bytes
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.words.capacity() * std::mem::size_of::<u64>()
+    }
+
+    fn set_indexes(words: impl Iterator<Item = u64>) -> impl Iterator<Item = usize> {
+        words.enumerate().flat_map(|(word_index, word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                // clear the lowest set bit
+                word &= word - 1;
+                Some(word_index * WORD_BITS + bit)
+            })
+        })
+    }
+
+    fn words_for(len: usize) -> usize {
+        (len + WORD_BITS - 1) / WORD_BITS
+    }
+}
+
+impl std::iter::FromIterator<bool> for Bitmap {
+    fn from_iter<I: IntoIterator<Item = bool>>(iter: I) -> Self {
+        let mut bitmap = Self::new();
+        for value in iter {
+            bitmap.push(value);
+        }
+        bitmap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_get() {
+        let mut bitmap = Bitmap::new();
+        assert!(bitmap.is_empty());
+
+        for i in 0..130 {
+            bitmap.push(i % 3 == 0);
+        }
+
+        assert_eq!(bitmap.len(), 130);
+        assert_eq!(bitmap.words().len(), 3);
+        for i in 0..130 {
+            assert_eq!(bitmap.get(i), i % 3 == 0, "bit {}", i);
+        }
+        assert_eq!(bitmap.count_set(), 44);
+    }
+
+    #[test]
+    fn new_unset() {
+        let mut bitmap = Bitmap::new_unset(64);
+        assert_eq!(bitmap.len(), 64);
+        assert_eq!(bitmap.count_set(), 0);
+
+        bitmap.push(true);
+        assert_eq!(bitmap.words(), &[0, 1]);
+        assert!(bitmap.get(64));
+    }
+
+    #[test]
+    #[should_panic(expected = "bitmap index 3 out of bounds for length 3")]
+    fn get_out_of_bounds() {
+        let bitmap: Bitmap = vec![true, false, true].into_iter().collect();
+        bitmap.get(3);
+    }
+
+    #[test]
+    fn set_and_assign() {
+        let mut bitmap = Bitmap::new_unset(130);
+        bitmap.set(0);
+        bitmap.set(70);
+        bitmap.set(129);
+        assert_eq!(bitmap.iter_set().collect::<Vec<_>>(), vec![0, 70, 129]);
+
+        let other: Bitmap = (0..100).map(|i| i >= 50).collect();
+        bitmap.and_assign(&other);
+        assert_eq!(bitmap.iter_set().collect::<Vec<_>>(), vec![70]);
+        assert_eq!(bitmap.len(), 130);
+    }
+
+    #[test]
+    fn from_words() {
+        let bitmap = Bitmap::from_words(vec![u64::MAX, u64::MAX], 66);
+        assert_eq!(bitmap.len(), 66);
+        assert_eq!(bitmap.count_set(), 66);
+        assert_eq!(bitmap.words(), &[u64::MAX, 0b11]);
+
+        let other: Bitmap = (0..66).map(|i| i == 65).collect();
+        assert!(bitmap.intersects(&other));
+        assert!(!Bitmap::new_unset(66).intersects(&other));
+    }
+
+    #[test]
+    fn extend_unset() {
+        let mut bitmap: Bitmap = (0..3).map(|i| i == 1).collect();
+        bitmap.extend_unset(130);
+        assert_eq!(bitmap.len(), 130);
+        bitmap.set(129);
+        assert_eq!(bitmap.iter_set().collect::<Vec<_>>(), vec![1, 129]);
+
+        // never shrinks
+        bitmap.extend_unset(10);
+        assert_eq!(bitmap.len(), 130);
+    }
+
+    #[test]
+    fn iter_set() {
+        let bitmap: Bitmap = (0..200).map(|i| i == 1 || i == 63 || i == 150).collect();
+        assert_eq!(bitmap.iter_set().collect::<Vec<_>>(), vec![1, 63, 150]);
+
+        let other: Bitmap = (0..200).map(|i| i > 100).collect();
+        assert_eq!(
+            bitmap.iter_set_in_both(&other).collect::<Vec<_>>(),
+            vec![150]
+        );
+
+        assert_eq!(Bitmap::new().iter_set().count(), 0);
+    }
+}
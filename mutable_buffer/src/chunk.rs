@@ -6,19 +6,21 @@ use arrow_deps::{
     datafusion::{
         error::DataFusionError,
         logical_plan::{Expr, ExpressionVisitor, Operator, Recursion},
-        optimizer::utils::expr_to_column_names,
         prelude::*,
+        scalar::ScalarValue,
     },
 };
 
 use chrono::{DateTime, Utc};
 use generated_types::wal as wb;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use data_types::{partition_metadata::Table as TableStats, TIME_COLUMN_NAME};
 use query::{
+    func::regex_match::REGEX_MATCH_FUNCTION_NAME,
     predicate::{Predicate, TimestampRange},
     util::AndExprBuilder,
+    TableRowCount, TableSeriesKeys, TableTagValues, TableValues,
 };
 
 use crate::dictionary::{Dictionary, Error as DictionaryError};
@@ -68,6 +70,16 @@ pub enum Error {
 
     #[snafu(display("Attempt to write table batch without a name"))]
     TableWriteWithoutName,
+
+    #[snafu(display(
+        "Error inserting table name '{}' into dictionary: {}",
+        table_name,
+        source
+    ))]
+    InsertingTableName {
+        table_name: String,
+        source: DictionaryError,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -99,11 +111,15 @@ pub struct Chunk {
 
     /// map of the dictionary ID for the table name to the table
     pub tables: HashMap<u32, Table>,
+
+    /// The total size of the tables, kept up to date as they are
+    /// written so `size` doesn't have to add them up
+    tables_size: usize,
 }
 
 /// Describes the result of translating a set of strings into
 /// chunk specific ids
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ChunkIdSet {
     /// At least one of the strings was not present in the chunks'
     /// dictionary.
@@ -119,7 +135,7 @@ pub enum ChunkIdSet {
 /// a 'Compiled' set of predicates / filters that can be evaluated on
 /// this chunk (where strings have been translated to chunk
 /// specific u32 ids)
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChunkPredicate {
     /// If present, restrict the request to just those tables whose
     /// names are in table_names. If present but empty, means there
@@ -140,26 +156,53 @@ pub struct ChunkPredicate {
     pub chunk_exprs: Vec<Expr>,
 
     /// If Some, then the table must contain all columns specified
-    /// to pass the predicate
+    /// to pass the predicate. These are the columns for which a null
+    /// value rules out a row; `chunk_exprs` may reference others.
     pub required_columns: Option<ChunkIdSet>,
 
     /// The id of the "time" column in this chunk
     pub time_column_id: u32,
 
-    /// Timestamp range: only rows within this range should be considered
+    /// Timestamp range: only rows within this range should be
+    /// considered. It is that of the table last passed to
+    /// `select_table`.
     pub range: Option<TimestampRange>,
+
+    /// The timestamp ranges of the tables with their own, by table id
+    pub table_ranges: BTreeMap<u32, TimestampRange>,
+
+    /// The timestamp range of the other tables
+    pub default_range: Option<TimestampRange>,
+
+    /// The `column = 'value'` expressions in `chunk_exprs`, as
+    /// (column id, value id) pairs. The value id is None if the value
+    /// is not in this chunk, in which case no row can match.
+    /// Expressions on columns not in this chunk are not included.
+    pub tag_equalities: Vec<(u32, Option<u32>)>,
 }
 
 impl ChunkPredicate {
+    /// Sets `range` to the timestamp range of the rows of the table
+    /// `table_id`, before that table is evaluated
+    pub fn select_table(&mut self, table_id: u32) {
+        self.range = self
+            .table_ranges
+            .get(&table_id)
+            .copied()
+            .or(self.default_range);
+    }
+
     /// Creates and adds a datafuson predicate representing the
-    /// combination of predicate and timestamp.
-    pub fn filter_expr(&self) -> Option<Expr> {
+    /// combination of predicate and timestamp. References to columns
+    /// for which `has_column` returns false are replaced by nulls, as
+    /// that is what those columns hold for a table without them.
+    pub fn filter_expr(&self, has_column: impl Fn(&str) -> bool) -> Option<Expr> {
         // build up a list of expressions
         let mut builder =
             AndExprBuilder::default().append_opt(self.make_timestamp_predicate_expr());
 
         for expr in &self.chunk_exprs {
-            builder = builder.append_expr(expr.clone());
+            builder = builder.append_expr(missing_columns_as_null(expr, &has_column));
         }
 
         builder.build()
@@ -198,10 +241,18 @@ fn make_range_expr(range: &TimestampRange) -> Expr {
 
 impl Chunk {
     pub fn new(id: u32) -> Self {
+        Self::new_with_dictionary(id, Dictionary::new())
+    }
+
+    /// Create a new chunk that uses the (empty) `dictionary`
+    pub fn new_with_dictionary(id: u32, dictionary: Dictionary) -> Self {
+        assert!(dictionary.is_empty(), "chunk dictionary must start empty");
+
         Self {
             id,
-            dictionary: Dictionary::new(),
+            dictionary,
             tables: HashMap::new(),
+            tables_size: 0,
             time_of_first_write: None,
             time_of_last_write: None,
             time_closed: None,
@@ -224,9 +275,41 @@ impl Chunk {
         Ok(())
     }
 
+    /// Returns an error if the table names, column names and tag values of
+    /// `entry` that are not in the dictionary of this chunk yet don't all
+    /// fit in it
+    pub fn check_dictionary_room(
+        &self,
+        entry: &wb::WriteBufferEntry<'_>,
+    ) -> Result<(), DictionaryError> {
+        let mut strings = Vec::new();
+        for batch in entry.table_batches().into_iter().flatten() {
+            strings.extend(batch.name());
+            for row in batch.rows().into_iter().flatten() {
+                for value in row.values().into_iter().flatten() {
+                    strings.extend(value.column());
+                    if let Some(tag) = value.value_as_tag_value() {
+                        strings.extend(tag.value());
+                    }
+                }
+            }
+        }
+
+        // most writes fit even if all of their strings are new, which is
+        // cheaper to check than finding the new ones
+        let bytes = strings.iter().map(|s| s.len()).sum();
+        if self.dictionary.has_room(strings.len(), bytes) {
+            return Ok(());
+        }
+        self.dictionary.check_room(strings)
+    }
+
     fn write_table_batch(&mut self, batch: &wb::TableWriteBatch<'_>) -> Result<()> {
         let table_name = batch.name().context(TableWriteWithoutName)?;
-        let table_id = self.dictionary.lookup_value_or_insert(table_name);
+        let table_id = self
+            .dictionary
+            .lookup_value_or_insert(table_name)
+            .context(InsertingTableName { table_name })?;
 
         let table = self
             .tables
@@ -234,14 +317,32 @@ impl Chunk {
             .or_insert_with(|| Table::new(table_id));
 
         if let Some(rows) = batch.rows() {
-            table
-                .append_rows(&mut self.dictionary, &rows)
-                .context(TableWrite { table_name })?;
+            // rows written before an error are kept, so they are counted
+            let size = table.size();
+            let written = table.append_rows(&mut self.dictionary, &rows);
+            self.tables_size += table.size().saturating_sub(size);
+            written.context(TableWrite { table_name })?;
         }
 
         Ok(())
     }
 
+    /// Returns the approximate memory used by the data of this chunk,
+    /// including its dictionary but not the inverted indexes of its tag
+    /// columns, in bytes
+    pub fn size(&self) -> usize {
+        self.dictionary.size() + self.tables_size
+    }
+
+    /// Returns the approximate memory used by the inverted indexes of
+    /// the tag columns of the tables in this chunk, in bytes
+    pub fn tag_index_size(&self) -> usize {
+        self.tables
+            .values()
+            .map(|table| table.tag_index_size())
+            .sum()
+    }
+
     /// Mark the chunk as closed
     pub fn mark_closed(&mut self) {
         assert!(self.time_closed.is_none());
@@ -259,11 +360,14 @@ impl Chunk {
             .fail();
         }
 
+        let mut chunk_predicate = chunk_predicate.clone();
         self.tables
             .iter()
             .filter_map(|(&table_id, table)| {
+                chunk_predicate.select_table(table_id);
+
                 // could match is good enough for this metadata query
-                match table.could_match_predicate(chunk_predicate) {
+                match table.could_match_predicate(&chunk_predicate) {
                     Ok(true) => Some(self.dictionary.lookup_id(table_id).context(
                         TableIdNotFoundInDictionary {
                             table_id,
@@ -291,27 +395,37 @@ impl Chunk {
 
         let range = predicate.range;
 
+        // tables not in this chunk's dictionary are not in this chunk
+        let table_ranges = predicate
+            .table_ranges
+            .iter()
+            .filter_map(|(table_name, range)| Some((self.dictionary.id(table_name)?, *range)))
+            .collect();
+
         // it would be nice to avoid cloning all the exprs here.
         let chunk_exprs = predicate.exprs.clone();
 
-        // In order to evaluate expressions in the table, all columns
-        // referenced in the expression must appear (I think, not sure
-        // about NOT, etc so panic if we see one of those);
+        // Tables without a column hold nulls for it, so tables can be
+        // skipped only if they lack a column for which a null rules out
+        // every row (e.g. `host` in `host = 'a'`, but not in
+        // `host IS NULL` or `host != 'a' OR region = 'b'`)
         let mut visitor = SupportVisitor {};
         let mut predicate_columns: HashSet<String> = HashSet::new();
         for expr in &chunk_exprs {
             visitor = expr.accept(visitor).context(UnsupportedPredicate)?;
-            expr_to_column_names(&expr, &mut predicate_columns).unwrap();
+            predicate_columns.extend(null_rejecting_columns(expr));
         }
 
-        // if there are any column references in the expression, ensure they appear in
-        // any table
+        // if any column must be non null for a row to pass, ensure it
+        // appears in any table
         let required_columns = if predicate_columns.is_empty() {
             None
         } else {
             Some(self.make_chunk_ids(predicate_columns.iter()))
         };
 
+        let tag_equalities = self.compile_equalities(&chunk_exprs);
+
         Ok(ChunkPredicate {
             table_name_predicate,
             field_name_predicate: field_restriction,
@@ -319,9 +433,47 @@ impl Chunk {
             required_columns,
             time_column_id,
             range,
+            table_ranges,
+            default_range: range,
+            tag_equalities,
         })
     }
 
+    /// Finds the `column = 'value'` expressions in `exprs` (which are
+    /// AND'ed together) and translates them into ids of this chunk
+    fn compile_equalities(&self, exprs: &[Expr]) -> Vec<(u32, Option<u32>)> {
+        let mut equalities = vec![];
+
+        let mut exprs: Vec<&Expr> = exprs.iter().collect();
+        while let Some(expr) = exprs.pop() {
+            if let Expr::BinaryExpr { left, op, right } = expr {
+                match (left.as_ref(), op, right.as_ref()) {
+                    (_, Operator::And, _) => {
+                        exprs.push(left);
+                        exprs.push(right);
+                    }
+                    (
+                        Expr::Column(column_name),
+                        Operator::Eq,
+                        Expr::Literal(ScalarValue::Utf8(Some(value))),
+                    )
+                    | (
+                        Expr::Literal(ScalarValue::Utf8(Some(value))),
+                        Operator::Eq,
+                        Expr::Column(column_name),
+                    ) => {
+                        if let Some(column_id) = self.dictionary.id(column_name) {
+                            equalities.push((column_id, self.dictionary.id(value)));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        equalities
+    }
+
     /// Converts a potential set of strings into a set of ids in terms
     /// of this dictionary. If there are no matching Strings in the
     /// chunks dictionary, those strings are ignored and a
@@ -405,6 +557,90 @@ impl Chunk {
         Ok(())
     }
 
+    /// Returns the number of rows of the named table and, if every row
+    /// has a timestamp, their time range, without converting the table
+    /// to arrow. Tables that don't exist in this chunk have no rows.
+    pub fn table_row_count(&self, table_name: &str) -> Result<(usize, Option<(i64, i64)>)> {
+        Ok(self
+            .table(table_name)?
+            .map_or((0, None), |table| (table.row_count(), table.time_range())))
+    }
+
+    /// Returns the distinct values of each tag column of each table in
+    /// this chunk, by table name and then tag column name
+    pub fn all_tag_values(&self) -> Result<BTreeMap<String, BTreeMap<String, BTreeSet<String>>>> {
+        let mut tag_values = BTreeMap::new();
+
+        for (&table_id, table) in &self.tables {
+            let table_name =
+                self.dictionary
+                    .lookup_id(table_id)
+                    .context(TableIdNotFoundInDictionary {
+                        table_id,
+                        chunk: self.id,
+                    })?;
+
+            let values = table
+                .all_tag_values(self)
+                .context(NamedTableError { table_name })?;
+            tag_values.insert(table_name.to_string(), values);
+        }
+
+        Ok(tag_values)
+    }
+
+    /// Returns the distinct values of the tag column `column` of each
+    /// table in this chunk that start with `prefix`, by table name. Tables
+    /// without the tag column are left out.
+    pub fn tag_values_with_prefix(
+        &self,
+        column: &str,
+        prefix: &str,
+    ) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let mut tag_values = BTreeMap::new();
+
+        for (&table_id, table) in &self.tables {
+            let table_name =
+                self.dictionary
+                    .lookup_id(table_id)
+                    .context(TableIdNotFoundInDictionary {
+                        table_id,
+                        chunk: self.id,
+                    })?;
+
+            let values = table
+                .tag_values_with_prefix(self, column, prefix)
+                .context(NamedTableError { table_name })?;
+            if let Some(values) = values {
+                tag_values.insert(table_name.to_string(), values);
+            }
+        }
+
+        Ok(tag_values)
+    }
+
+    /// Returns the series keys of each table in this chunk, by table name
+    pub fn series_keys(&self) -> Result<TableSeriesKeys> {
+        let mut series_keys = TableSeriesKeys::new();
+
+        for (&table_id, table) in &self.tables {
+            let table_name =
+                self.dictionary
+                    .lookup_id(table_id)
+                    .context(TableIdNotFoundInDictionary {
+                        table_id,
+                        chunk: self.id,
+                    })?;
+
+            let keys = table
+                .series_keys(self)
+                .context(NamedTableError { table_name })?;
+            series_keys.insert(table_name.to_string(), keys);
+        }
+
+        Ok(series_keys)
+    }
+
     /// Returns a vec of the summary statistics of the tables in this chunk
     pub fn table_stats(&self) -> Result<Vec<TableStats>> {
         let mut stats = Vec::with_capacity(self.tables.len());
@@ -423,6 +659,7 @@ impl Chunk {
             stats.push(TableStats {
                 name: name.to_string(),
                 columns,
+                time_range: table.time_range(),
             });
         }
 
@@ -486,11 +723,41 @@ impl query::PartitionChunk for Chunk {
         self.table_to_arrow(dst, table_name, columns)
     }
 
+    fn table_row_count(&self, table_name: &str) -> Result<Option<TableRowCount>, Self::Error> {
+        let (rows, time_range) = self.table_row_count(table_name)?;
+        Ok(Some(TableRowCount {
+            rows: rows as u64,
+            time_range,
+        }))
+    }
+
+    fn all_tag_values(&self) -> Result<TableTagValues, Self::Error> {
+        self.all_tag_values()
+    }
+
+    fn tag_values_with_prefix(
+        &self,
+        column: &str,
+        prefix: &str,
+    ) -> Result<TableValues, Self::Error> {
+        self.tag_values_with_prefix(column, prefix)
+    }
+
+    fn series_keys(&self) -> Result<TableSeriesKeys, Self::Error> {
+        self.series_keys()
+    }
+
     async fn table_names(&self, _predicate: &Predicate) -> Result<LogicalPlan, Self::Error> {
         unimplemented!("please use table_names function directly")
     }
 }
 
+/// Returns true if predicates with the expression `expr` can be compiled
+/// by `Chunk::compile_predicate`, which rejects the others
+pub fn supports_expr(expr: &Expr) -> bool {
+    expr.accept(SupportVisitor {}).is_ok()
+}
+
 /// Used to figure out if we know how to deal with this kind of
 /// predicate in the write buffer
 struct SupportVisitor {}
@@ -500,9 +767,14 @@ impl ExpressionVisitor for SupportVisitor {
         match expr {
             Expr::Literal(..) => Ok(Recursion::Continue(self)),
             Expr::Column(..) => Ok(Recursion::Continue(self)),
+            Expr::Not(..) | Expr::IsNull(..) | Expr::IsNotNull(..) => Ok(Recursion::Continue(self)),
+            Expr::ScalarUDF { fun, .. } if fun.name == REGEX_MATCH_FUNCTION_NAME => {
+                Ok(Recursion::Continue(self))
+            }
             Expr::BinaryExpr { op, .. } => {
                 match op {
                     Operator::Eq
+                    | Operator::NotEq
                     | Operator::Lt
                     | Operator::LtEq
                     | Operator::Gt
@@ -514,7 +786,7 @@ impl ExpressionVisitor for SupportVisitor {
                     | Operator::And
                     | Operator::Or => Ok(Recursion::Continue(self)),
                     // Unsupported (need to think about ramifications)
-                    Operator::NotEq | Operator::Modulus | Operator::Like | Operator::NotLike => {
+                    Operator::Modulus | Operator::Like | Operator::NotLike => {
                         Err(DataFusionError::NotImplemented(format!(
                             "Operator {:?} not yet supported in IOx MutableBuffer",
                             op
@@ -530,9 +802,182 @@ impl ExpressionVisitor for SupportVisitor {
     }
 }
 
+/// Returns the columns of `expr` that make it null when any of them is
+/// null. Only expressions accepted by `SupportVisitor` are expected.
+fn null_propagating_columns(expr: &Expr) -> HashSet<String> {
+    match expr {
+        Expr::Column(name) => std::iter::once(name.clone()).collect(),
+        // `NULL OR true` is true and `NULL AND false` is false, so only
+        // columns that make both sides null make the result null
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        }
+        | Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => null_propagating_columns(left)
+            .intersection(&null_propagating_columns(right))
+            .cloned()
+            .collect(),
+        Expr::BinaryExpr { left, right, .. } => {
+            let mut columns = null_propagating_columns(left);
+            columns.extend(null_propagating_columns(right));
+            columns
+        }
+        Expr::Not(expr) => null_propagating_columns(expr),
+        // regex_match is null where its value is null
+        Expr::ScalarUDF { args, .. } => args.iter().flat_map(null_propagating_columns).collect(),
+        _ => HashSet::new(),
+    }
+}
+
+/// Returns the columns of `expr` for which a null value means `expr`
+/// can not be true, so rows (and tables) without them can be skipped
+fn null_rejecting_columns(expr: &Expr) -> HashSet<String> {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            let mut columns = null_rejecting_columns(left);
+            columns.extend(null_rejecting_columns(right));
+            columns
+        }
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => null_rejecting_columns(left)
+            .intersection(&null_rejecting_columns(right))
+            .cloned()
+            .collect(),
+        Expr::IsNull(_) => HashSet::new(),
+        Expr::IsNotNull(expr) => null_propagating_columns(expr),
+        _ => null_propagating_columns(expr),
+    }
+}
+
+/// Returns a copy of `expr` with references to columns for which
+/// `has_column` returns false replaced by null literals. Where such a
+/// column is compared to a literal, the null has the literal's type.
+fn missing_columns_as_null(expr: &Expr, has_column: &impl Fn(&str) -> bool) -> Expr {
+    let rewrite = |expr: &Expr| Box::new(missing_columns_as_null(expr, has_column));
+
+    match expr {
+        Expr::Column(name) if !has_column(name) => Expr::Literal(ScalarValue::Utf8(None)),
+        Expr::BinaryExpr { left, op, right } => {
+            let (left, right) = match (left.as_ref(), right.as_ref()) {
+                (Expr::Column(name), Expr::Literal(value)) if !has_column(name) => {
+                    (Box::new(Expr::Literal(null_like(value))), right.clone())
+                }
+                (Expr::Literal(value), Expr::Column(name)) if !has_column(name) => {
+                    (left.clone(), Box::new(Expr::Literal(null_like(value))))
+                }
+                (left, right) => (rewrite(left), rewrite(right)),
+            };
+            Expr::BinaryExpr {
+                left,
+                op: *op,
+                right,
+            }
+        }
+        Expr::Not(expr) => Expr::Not(rewrite(expr)),
+        Expr::IsNull(expr) => Expr::IsNull(rewrite(expr)),
+        Expr::IsNotNull(expr) => Expr::IsNotNull(rewrite(expr)),
+        Expr::ScalarUDF { fun, args } => Expr::ScalarUDF {
+            fun: fun.clone(),
+            args: args
+                .iter()
+                .map(|arg| missing_columns_as_null(arg, has_column))
+                .collect(),
+        },
+        expr => expr.clone(),
+    }
+}
+
+/// Returns a null value of the same type as `value`
+fn null_like(value: &ScalarValue) -> ScalarValue {
+    match value {
+        ScalarValue::Boolean(_) => ScalarValue::Boolean(None),
+        ScalarValue::Float64(_) => ScalarValue::Float64(None),
+        ScalarValue::Int64(_) => ScalarValue::Int64(None),
+        ScalarValue::UInt64(_) => ScalarValue::UInt64(None),
+        _ => ScalarValue::Utf8(None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use query::func::regex_match::make_regex_match_expr;
+
+    fn sorted(columns: HashSet<String>) -> Vec<String> {
+        let mut columns: Vec<_> = columns.into_iter().collect();
+        columns.sort();
+        columns
+    }
+
+    #[test]
+    fn null_rejecting_columns_of_exprs() {
+        let cases = vec![
+            (col("host").eq(lit("a")), vec!["host"]),
+            (col("host").not_eq(lit("a")), vec!["host"]),
+            (
+                col("host").eq(lit("a")).and(col("region").eq(lit("b"))),
+                vec!["host", "region"],
+            ),
+            (
+                col("host").eq(lit("a")).or(col("region").eq(lit("b"))),
+                vec![],
+            ),
+            (
+                col("host").eq(lit("a")).or(col("host").eq(lit("b"))),
+                vec!["host"],
+            ),
+            (Expr::IsNull(Box::new(col("host"))), vec![]),
+            (Expr::IsNotNull(Box::new(col("host"))), vec!["host"]),
+            (
+                Expr::IsNull(Box::new(col("host"))).or(col("host").not_eq(lit("a"))),
+                vec![],
+            ),
+            (
+                make_regex_match_expr(col("host"), "^a").unwrap(),
+                vec!["host"],
+            ),
+            (
+                Expr::Not(Box::new(make_regex_match_expr(col("host"), "^a").unwrap())),
+                vec!["host"],
+            ),
+        ];
+
+        for (expr, expected) in cases {
+            expr.accept(SupportVisitor {})
+                .expect("expression is supported");
+            assert_eq!(
+                sorted(null_rejecting_columns(&expr)),
+                expected,
+                "columns of {:?}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn missing_columns_become_nulls() {
+        let expr = Expr::IsNull(Box::new(col("host")))
+            .or(col("usage").gt(lit(1.5)))
+            .or(col("region").not_eq(lit("west")));
+        let rewritten = missing_columns_as_null(&expr, &|name| name == "region");
+
+        let expected = Expr::IsNull(Box::new(Expr::Literal(ScalarValue::Utf8(None))))
+            .or(Expr::Literal(ScalarValue::Float64(None)).gt(lit(1.5)))
+            .or(col("region").not_eq(lit("west")));
+        assert_eq!(format!("{:?}", rewritten), format!("{:?}", expected));
+    }
 
     #[test]
     fn test_make_range_expr() {
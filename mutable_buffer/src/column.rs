@@ -1,7 +1,12 @@
+use std::ops::Range;
+
 use generated_types::wal as wb;
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
 
-use crate::dictionary::Dictionary;
+use crate::{
+    bitmap::{Bitmap, WORD_BITS},
+    dictionary::{Dictionary, Error as DictionaryError},
+};
 use data_types::{data::type_description, partition_metadata::Statistics};
 
 use arrow_deps::arrow::datatypes::DataType as ArrowDataType;
@@ -23,18 +28,182 @@ pub enum Error {
 
     #[snafu(display("InternalError: Applying i64 range on a column with non-i64 type"))]
     InternalTypeMismatchForTimePredicate,
+
+    #[snafu(display("Error inserting tag value into dictionary: {}", source))]
+    InsertingTagValue { source: DictionaryError },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// A sequence of optional values, stored as a dense vector of values
+/// and a packed validity bitmap. Null rows hold `T::default()`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NullableVec<T> {
+    values: Vec<T>,
+    validity: Bitmap,
+}
+
+impl<T: Default + Clone> NullableVec<T> {
+    /// Create a vector of `len` nulls
+    pub fn new_nulls(len: usize) -> Self {
+        Self {
+            values: vec![T::default(); len],
+            validity: Bitmap::new_unset(len),
+        }
+    }
+
+    pub fn push(&mut self, value: Option<T>) {
+        match value {
+            Some(value) => self.push_value(value),
+            None => self.push_null(),
+        }
+    }
+
+    pub fn push_value(&mut self, value: T) {
+        self.values.push(value);
+        self.validity.push(true);
+    }
+
+    pub fn push_null(&mut self) {
+        self.values.push(T::default());
+        self.validity.push(false);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the value at `index`, or None if it is null
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if self.validity.get(index) {
+            Some(&self.values[index])
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over all values, including nulls
+    pub fn iter(&self) -> impl Iterator<Item = Option<&T>> + '_ {
+        self.values
+            .iter()
+            .zip(self.validity.iter())
+            .map(|(value, valid)| if valid { Some(value) } else { None })
+    }
+
+    /// Returns an iterator over the non-null values
+    pub fn iter_non_null(&self) -> impl Iterator<Item = &T> + '_ {
+        self.validity
+            .iter_set()
+            .map(move |index| &self.values[index])
+    }
+
+    /// Returns the raw values. Values of null rows are `T::default()`
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    /// Returns the validity bitmap; a set bit means the value is non-null
+    pub fn validity(&self) -> &Bitmap {
+        &self.validity
+    }
+
+    /// Returns the approximate memory used by this vector, in bytes,
+    /// not including memory owned by the values
+    pub fn size(&self) -> usize {
+        self.values.capacity() * std::mem::size_of::<T>() + self.validity.size()
+    }
+}
+
+impl<T: Default + Clone> std::iter::FromIterator<Option<T>> for NullableVec<T> {
+    fn from_iter<I: IntoIterator<Item = Option<T>>>(iter: I) -> Self {
+        let mut vec = Self::default();
+        for value in iter {
+            vec.push(value);
+        }
+        vec
+    }
+}
+
+/// A sequence of optional booleans, stored as two packed bitmaps (one
+/// for the values and one for their validity)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NullableBoolVec {
+    values: Bitmap,
+    validity: Bitmap,
+}
+
+impl NullableBoolVec {
+    /// Create a vector of `len` nulls
+    pub fn new_nulls(len: usize) -> Self {
+        Self {
+            values: Bitmap::new_unset(len),
+            validity: Bitmap::new_unset(len),
+        }
+    }
+
+    pub fn push(&mut self, value: Option<bool>) {
+        self.values.push(value.unwrap_or(false));
+        self.validity.push(value.is_some());
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the value at `index`, or None if it is null
+    pub fn get(&self, index: usize) -> Option<bool> {
+        if self.validity.get(index) {
+            Some(self.values.get(index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over all values, including nulls
+    pub fn iter(&self) -> impl Iterator<Item = Option<bool>> + '_ {
+        self.values
+            .iter()
+            .zip(self.validity.iter())
+            .map(|(value, valid)| if valid { Some(value) } else { None })
+    }
+
+    /// Returns the validity bitmap; a set bit means the value is non-null
+    pub fn validity(&self) -> &Bitmap {
+        &self.validity
+    }
+
+    /// Returns the approximate memory used by this vector, in bytes
+    pub fn size(&self) -> usize {
+        self.values.size() + self.validity.size()
+    }
+}
+
+impl std::iter::FromIterator<Option<bool>> for NullableBoolVec {
+    fn from_iter<I: IntoIterator<Item = Option<bool>>>(iter: I) -> Self {
+        let mut vec = Self::default();
+        for value in iter {
+            vec.push(value);
+        }
+        vec
+    }
+}
+
 /// Stores the actual data for columns in a chunk along with summary
 /// statistics
 #[derive(Debug, Clone)]
 pub enum Column {
-    F64(Vec<Option<f64>>, Statistics<f64>),
-    I64(Vec<Option<i64>>, Statistics<i64>),
-    String(Vec<Option<String>>, Statistics<String>),
-    Bool(Vec<Option<bool>>, Statistics<bool>),
-    Tag(Vec<Option<u32>>, Statistics<String>),
+    F64(NullableVec<f64>, Statistics<f64>),
+    I64(NullableVec<i64>, Statistics<i64>),
+    String(NullableVec<String>, Statistics<String>),
+    Bool(NullableBoolVec, Statistics<bool>),
+    Tag(NullableVec<u32>, Statistics<String>),
 }
 
 impl Column {
@@ -51,8 +220,8 @@ impl Column {
                     .value_as_f64value()
                     .expect("f64 value should be present")
                     .value();
-                let mut vals = vec![None; capacity];
-                vals.push(Some(val));
+                let mut vals = NullableVec::new_nulls(capacity);
+                vals.push_value(val);
                 Self::F64(vals, Statistics::new(val))
             }
             I64Value => {
@@ -60,8 +229,8 @@ impl Column {
                     .value_as_i64value()
                     .expect("i64 value should be present")
                     .value();
-                let mut vals = vec![None; capacity];
-                vals.push(Some(val));
+                let mut vals = NullableVec::new_nulls(capacity);
+                vals.push_value(val);
                 Self::I64(vals, Statistics::new(val))
             }
             StringValue => {
@@ -70,8 +239,8 @@ impl Column {
                     .expect("string value should be present")
                     .value()
                     .expect("string must be present");
-                let mut vals = vec![None; capacity];
-                vals.push(Some(val.to_string()));
+                let mut vals = NullableVec::new_nulls(capacity);
+                vals.push_value(val.to_string());
                 Self::String(vals, Statistics::new(val.to_string()))
             }
             BoolValue => {
@@ -79,7 +248,7 @@ impl Column {
                     .value_as_bool_value()
                     .expect("bool value should be present")
                     .value();
-                let mut vals = vec![None; capacity];
+                let mut vals = NullableBoolVec::new_nulls(capacity);
                 vals.push(Some(val));
                 Self::Bool(vals, Statistics::new(val))
             }
@@ -89,9 +258,11 @@ impl Column {
                     .expect("tag value should be present")
                     .value()
                     .expect("tag value must have string value");
-                let mut vals = vec![None; capacity];
-                let id = dictionary.lookup_value_or_insert(val);
-                vals.push(Some(id));
+                let mut vals = NullableVec::new_nulls(capacity);
+                let id = dictionary
+                    .lookup_value_or_insert(val)
+                    .context(InsertingTagValue)?;
+                vals.push_value(id);
                 Self::Tag(vals, Statistics::new(val.to_string()))
             }
             _ => {
@@ -117,6 +288,21 @@ impl Column {
         self.len() == 0
     }
 
+    /// Returns the approximate memory used by the values of this
+    /// column, in bytes. Tag values are stored in the chunk's
+    /// dictionary and only their ids are counted. The contents of string
+    /// values are not counted either, as that means reading all of them;
+    /// tables track those as they are written.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::F64(v, _) => v.size(),
+            Self::I64(v, _) => v.size(),
+            Self::String(v, _) => v.size(),
+            Self::Bool(v, _) => v.size(),
+            Self::Tag(v, _) => v.size(),
+        }
+    }
+
     pub fn type_description(&self) -> &'static str {
         match self {
             Self::F64(_, _) => "f64",
@@ -138,13 +324,53 @@ impl Column {
         }
     }
 
+    /// Returns an error if `value` can't be pushed to this column because
+    /// it is of another type
+    pub fn check_type(&self, value: &wb::Value<'_>) -> Result<()> {
+        use wb::ColumnValue::*;
+
+        let matches = matches!(
+            (self, value.value_type()),
+            (Self::Tag(..), TagValue)
+                | (Self::String(..), StringValue)
+                | (Self::Bool(..), BoolValue)
+                | (Self::I64(..), I64Value)
+                | (Self::F64(..), F64Value)
+        );
+        if matches {
+            Ok(())
+        } else {
+            TypeMismatch {
+                existing_column_type: self.type_description(),
+                inserted_value_type: type_description(value.value_type()),
+            }
+            .fail()
+        }
+    }
+
+    /// Returns an error if a column can't be created for `value` because
+    /// its type is unknown
+    pub fn check_new_type(value: &wb::Value<'_>) -> Result<()> {
+        use wb::ColumnValue::*;
+
+        match value.value_type() {
+            TagValue | StringValue | BoolValue | I64Value | F64Value => Ok(()),
+            _ => UnknownColumnType {
+                inserted_value_type: type_description(value.value_type()),
+            }
+            .fail(),
+        }
+    }
+
     pub fn push(&mut self, dictionary: &mut Dictionary, value: &wb::Value<'_>) -> Result<()> {
         let inserted = match self {
             Self::Tag(vals, stats) => match value.value_as_tag_value() {
                 Some(tag) => {
                     let tag_value = tag.value().expect("tag must have string value");
-                    let id = dictionary.lookup_value_or_insert(tag_value);
-                    vals.push(Some(id));
+                    let id = dictionary
+                        .lookup_value_or_insert(tag_value)
+                        .context(InsertingTagValue)?;
+                    vals.push_value(id);
                     Statistics::update_string(stats, tag_value);
                     true
                 }
@@ -153,7 +379,7 @@ impl Column {
             Self::String(vals, stats) => match value.value_as_string_value() {
                 Some(str_val) => {
                     let str_val = str_val.value().expect("string must have value");
-                    vals.push(Some(str_val.to_string()));
+                    vals.push_value(str_val.to_string());
                     Statistics::update_string(stats, str_val);
                     true
                 }
@@ -171,7 +397,7 @@ impl Column {
             Self::I64(vals, stats) => match value.value_as_i64value() {
                 Some(i64_val) => {
                     let i64_val = i64_val.value();
-                    vals.push(Some(i64_val));
+                    vals.push_value(i64_val);
                     stats.update(i64_val);
                     true
                 }
@@ -180,7 +406,7 @@ impl Column {
             Self::F64(vals, stats) => match value.value_as_f64value() {
                 Some(f64_val) => {
                     let f64_val = f64_val.value();
-                    vals.push(Some(f64_val));
+                    vals.push_value(f64_val);
                     stats.update(f64_val);
                     true
                 }
@@ -203,32 +429,27 @@ impl Column {
     // if the length is equal to the passed in value. This is used to ensure
     // columns are all the same length.
     pub fn push_none_if_len_equal(&mut self, len: usize) {
+        if self.len() != len {
+            return;
+        }
         match self {
-            Self::F64(v, _) => {
-                if v.len() == len {
-                    v.push(None);
-                }
-            }
-            Self::I64(v, _) => {
-                if v.len() == len {
-                    v.push(None);
-                }
-            }
-            Self::String(v, _) => {
-                if v.len() == len {
-                    v.push(None);
-                }
-            }
-            Self::Bool(v, _) => {
-                if v.len() == len {
-                    v.push(None);
-                }
-            }
-            Self::Tag(v, _) => {
-                if v.len() == len {
-                    v.push(None);
-                }
-            }
+            Self::F64(v, _) => v.push_null(),
+            Self::I64(v, _) => v.push_null(),
+            Self::String(v, _) => v.push_null(),
+            Self::Bool(v, _) => v.push(None),
+            Self::Tag(v, _) => v.push_null(),
+        }
+    }
+
+    /// Returns the validity bitmap of this column; a set bit means
+    /// the value in that row is non-null
+    pub fn validity(&self) -> &Bitmap {
+        match self {
+            Self::F64(v, _) => v.validity(),
+            Self::I64(v, _) => v.validity(),
+            Self::String(v, _) => v.validity(),
+            Self::Bool(v, _) => v.validity(),
+            Self::Tag(v, _) => v.validity(),
         }
     }
 
@@ -252,29 +473,75 @@ impl Column {
         matches!(self, Self::Tag(..))
     }
 
-    /// Returns true if there exists at least one row idx where this
-    /// self[i] is within the range [min_value, max_value). Inclusive
-    /// of `start`, exclusive of `end` and where col[i] is non null
-    pub fn has_non_null_i64_range<T>(
+    /// Returns a bitmap with a bit set for each row whose value is
+    /// non-null and within the range [start, end). Inclusive of
+    /// `start`, exclusive of `end`.
+    ///
+    /// The values are compared a word (64 rows) at a time without
+    /// branches so the comparisons can be vectorized, and the
+    /// resulting bitmap can be reused to evaluate the range against
+    /// any number of other columns of the same table.
+    pub fn i64_range_selection(&self, start: i64, end: i64) -> Result<Bitmap> {
+        self.i64_range_selection_in_rows(start, end, std::iter::once(0..self.len()))
+    }
+
+    /// Like `i64_range_selection`, but only considers the rows in
+    /// `candidate_rows`; all other rows are left unselected. Each
+    /// range must start at a multiple of `WORD_BITS`, and may extend
+    /// past the end of the column.
+    pub fn i64_range_selection_in_rows(
         &self,
-        column: &[Option<T>],
         start: i64,
         end: i64,
-    ) -> Result<bool> {
+        candidate_rows: impl IntoIterator<Item = Range<usize>>,
+    ) -> Result<Bitmap> {
         match self {
-            Self::I64(v, _) => {
-                for (index, val) in v.iter().enumerate() {
-                    if let Some(val) = val {
-                        if start <= *val && *val < end && column[index].is_some() {
-                            return Ok(true);
+            Self::I64(v, stats) => {
+                let mut words = vec![0_u64; (v.len() + WORD_BITS - 1) / WORD_BITS];
+                if stats.max < start || stats.min >= end {
+                    return Ok(Bitmap::from_words(words, v.len()));
+                }
+
+                let values = v.values();
+                let validity = v.validity().words();
+                for rows in candidate_rows {
+                    assert_eq!(
+                        rows.start % WORD_BITS,
+                        0,
+                        "candidate rows must be aligned to bitmap words"
+                    );
+                    let rows = rows.start.min(values.len())..rows.end.min(values.len());
+                    let first_word = rows.start / WORD_BITS;
+
+                    for (word_index, chunk) in values[rows].chunks(WORD_BITS).enumerate() {
+                        let word_index = first_word + word_index;
+                        let mut word = 0_u64;
+                        for (bit, &value) in chunk.iter().enumerate() {
+                            word |= (((start <= value) & (value < end)) as u64) << bit;
                         }
+                        words[word_index] = word & validity[word_index];
                     }
                 }
-                Ok(false)
+
+                Ok(Bitmap::from_words(words, v.len()))
             }
             _ => InternalTypeMismatchForTimePredicate {}.fail(),
         }
     }
+
+    /// Returns true if this column is non-null in at least one of the
+    /// rows set in `selection`
+    pub fn has_non_null_selected(&self, selection: &Bitmap) -> bool {
+        self.validity().intersects(selection)
+    }
+
+    /// Returns true if there exists at least one row idx where this
+    /// self[i] is within the range [min_value, max_value). Inclusive
+    /// of `start`, exclusive of `end` and where the row is non null
+    /// in both this column and the column with validity `validity`.
+    pub fn has_non_null_i64_range(&self, validity: &Bitmap, start: i64, end: i64) -> Result<bool> {
+        Ok(self.i64_range_selection(start, end)?.intersects(validity))
+    }
 }
 
 #[cfg(test)]
@@ -288,14 +555,17 @@ mod tests {
     fn test_has_i64_range() -> Result {
         let mut stats = Statistics::new(1);
         stats.update(2);
-        let col = Column::I64(vec![Some(1), None, Some(2)], stats.clone());
+        let col = Column::I64(
+            vec![Some(1), None, Some(2)].into_iter().collect(),
+            stats.clone(),
+        );
         assert!(!col.has_i64_range(-1, 0)?);
         assert!(!col.has_i64_range(0, 1)?);
         assert!(col.has_i64_range(1, 2)?);
         assert!(col.has_i64_range(2, 3)?);
         assert!(!col.has_i64_range(3, 4)?);
 
-        let col = Column::I64(vec![Some(2), None, Some(1)], stats);
+        let col = Column::I64(vec![Some(2), None, Some(1)].into_iter().collect(), stats);
         assert!(!col.has_i64_range(-1, 0)?);
         assert!(!col.has_i64_range(0, 1)?);
         assert!(col.has_i64_range(1, 2)?);
@@ -308,7 +578,7 @@ mod tests {
     #[test]
     fn test_has_i64_range_does_not_panic() -> Result {
         // providing the wrong column type should get an internal error, not a panic
-        let col = Column::F64(vec![Some(1.2)], Statistics::new(1.2));
+        let col = Column::F64(vec![Some(1.2)].into_iter().collect(), Statistics::new(1.2));
         let res = col.has_i64_range(-1, 0);
         assert!(res.is_err());
         let res_string = format!("{:?}", res);
@@ -324,12 +594,12 @@ mod tests {
 
     #[test]
     fn test_has_non_null_i64_range_() -> Result {
-        let none_col: Vec<Option<u32>> = vec![None, None, None];
-        let some_col: Vec<Option<u32>> = vec![Some(0), Some(0), Some(0)];
+        let none_col = Bitmap::new_unset(3);
+        let some_col: Bitmap = vec![true, true, true].into_iter().collect();
 
         let mut stats = Statistics::new(1);
         stats.update(2);
-        let col = Column::I64(vec![Some(1), None, Some(2)], stats);
+        let col = Column::I64(vec![Some(1), None, Some(2)].into_iter().collect(), stats);
 
         assert!(!col.has_non_null_i64_range(&some_col, -1, 0)?);
         assert!(!col.has_non_null_i64_range(&some_col, 0, 1)?);
@@ -345,4 +615,77 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_nullable_vec() {
+        let mut vals = NullableVec::new_nulls(2);
+        vals.push_value(3_i64);
+        vals.push(None);
+        vals.push(Some(5));
+
+        assert_eq!(vals.len(), 5);
+        assert_eq!(vals.get(0), None);
+        assert_eq!(vals.get(2), Some(&3));
+        assert_eq!(
+            vals.iter().collect::<Vec<_>>(),
+            vec![None, None, Some(&3), None, Some(&5)]
+        );
+        assert_eq!(vals.iter_non_null().collect::<Vec<_>>(), vec![&3, &5]);
+        assert_eq!(vals.values(), &[0, 0, 3, 0, 5]);
+        assert_eq!(vals.validity().count_set(), 2);
+    }
+
+    #[test]
+    fn test_nullable_bool_vec() {
+        let mut vals = NullableBoolVec::new_nulls(1);
+        vals.push(Some(true));
+        vals.push(Some(false));
+        vals.push(None);
+
+        assert_eq!(vals.len(), 4);
+        assert_eq!(
+            vals.iter().collect::<Vec<_>>(),
+            vec![None, Some(true), Some(false), None]
+        );
+        assert_eq!(vals.get(2), Some(false));
+        assert_eq!(vals.validity().count_set(), 2);
+    }
+
+    #[test]
+    fn test_i64_range_selection() -> Result {
+        let values: Vec<_> = (0..100)
+            .map(|i| if i % 10 == 0 { None } else { Some(i) })
+            .collect();
+        let mut stats = Statistics::new(1);
+        stats.update(99);
+        let col = Column::I64(values.into_iter().collect(), stats);
+
+        let selection = col.i64_range_selection(8, 71)?;
+        assert_eq!(selection.len(), 100);
+        let expected: Vec<_> = (8..71).filter(|i| i % 10 != 0).collect();
+        assert_eq!(selection.iter_set().collect::<Vec<_>>(), expected);
+
+        // outside of the statistics' range
+        let selection = col.i64_range_selection(100, 200)?;
+        assert_eq!(selection.len(), 100);
+        assert_eq!(selection.count_set(), 0);
+
+        let other = Column::F64(
+            (0..100)
+                .map(|i| if i == 69 { Some(1.0) } else { None })
+                .collect(),
+            Statistics::new(1.0),
+        );
+        assert!(other.has_non_null_selected(&col.i64_range_selection(8, 71)?));
+        assert!(!other.has_non_null_selected(&col.i64_range_selection(8, 69)?));
+
+        assert!(other.i64_range_selection(0, 1).is_err());
+
+        // only the candidate rows are considered
+        let selection = col.i64_range_selection_in_rows(0, 100, vec![64..128])?;
+        let expected: Vec<_> = (64..100).filter(|i| i % 10 != 0).collect();
+        assert_eq!(selection.iter_set().collect::<Vec<_>>(), expected);
+
+        Ok(())
+    }
 }
@@ -4,10 +4,12 @@ use query::group_by::GroupByAndAggregate;
 use query::group_by::WindowDuration;
 use query::{
     exec::{stringset::StringSet, FieldListPlan, SeriesSetPlan, SeriesSetPlans, StringSetPlan},
+    lifecycle::LifecycleLog,
     predicate::Predicate,
-    Database,
+    Database, DatabaseError, DatabaseErrorKind, LifecycleEventKind,
 };
 
+use crate::bitmap::Bitmap;
 use crate::column::Column;
 use crate::table::Table;
 use crate::{
@@ -16,12 +18,18 @@ use crate::{
 };
 
 use std::collections::{BTreeSet, HashMap, HashSet};
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
 use arrow_deps::datafusion::{error::DataFusionError, logical_plan::LogicalPlan};
-use data_types::data::ReplicatedWrite;
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::{ChunkSizing, TagOrders},
+};
 
-use crate::dictionary::Error as DictionaryError;
+use crate::dictionary::{DictionaryLimits, Error as DictionaryError, StringPool};
 
 use async_trait::async_trait;
 use snafu::{ResultExt, Snafu};
@@ -121,6 +129,59 @@ impl From<crate::partition::Error> for Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+impl Error {
+    /// Classifies this error for callers that need to react to the
+    /// cause of the error (e.g. to choose a response code)
+    pub fn kind(&self) -> DatabaseErrorKind {
+        match self {
+            Self::TableNameNotFoundInDictionary { .. }
+            | Self::ColumnNameNotFoundInDictionary { .. } => DatabaseErrorKind::NotFound,
+            Self::UnsupportedColumnTypeForListingValues { .. } => DatabaseErrorKind::SchemaConflict,
+            // errors from the partitions, chunks and tables are
+            // classified by their root cause
+            Self::PassThrough { .. } | Self::DroppingChunk { .. } => {
+                source_kind(self).unwrap_or(DatabaseErrorKind::Internal)
+            }
+            _ => DatabaseErrorKind::Internal,
+        }
+    }
+}
+
+impl From<Error> for DatabaseError {
+    fn from(e: Error) -> Self {
+        Self::new(e.kind(), e)
+    }
+}
+
+/// Returns the kind of the first error in the chain of sources of
+/// `err` that has a known cause
+fn source_kind(err: &(dyn std::error::Error + 'static)) -> Option<DatabaseErrorKind> {
+    let mut err = err.source();
+    while let Some(e) = err {
+        if matches!(
+            e.downcast_ref::<DictionaryError>(),
+            Some(DictionaryError::DictionaryFull { .. })
+        ) {
+            return Some(DatabaseErrorKind::ResourceExhausted);
+        }
+        if matches!(
+            e.downcast_ref::<crate::column::Error>(),
+            Some(crate::column::Error::TypeMismatch { .. })
+        ) {
+            return Some(DatabaseErrorKind::SchemaConflict);
+        }
+        if matches!(
+            e.downcast_ref::<crate::partition::Error>(),
+            Some(crate::partition::Error::UnknownChunk { .. })
+                | Some(crate::partition::Error::DropUnknownChunk { .. })
+        ) {
+            return Some(DatabaseErrorKind::NotFound);
+        }
+        err = e.source();
+    }
+    None
+}
+
 #[derive(Debug, Default)]
 /// This implements the mutable buffer. See the module doc comments
 /// for more details.
@@ -129,6 +190,36 @@ pub struct MutableBufferDb {
 
     /// Maps partition keys to partitions which hold the actual data
     partitions: RwLock<HashMap<String, Arc<RwLock<Partition>>>>,
+
+    /// The limits for the dictionaries of the chunks in this database
+    dictionary_limits: DictionaryLimits,
+
+    /// If set, the dictionaries of all chunks in this database share
+    /// their strings via this pool, so values that are written to many
+    /// partitions (such as common tag values) are only stored once
+    string_pool: Option<StringPool>,
+
+    /// If true, equality predicates on tags are evaluated using an
+    /// inverted index of the tag columns of each table, which is
+    /// built the first time it is needed
+    tag_index: bool,
+
+    /// If set, the open chunk of each partition is closed once it
+    /// reaches the size this sizes it to for the ingest rate of the
+    /// partition
+    chunk_sizing: Option<ChunkSizing>,
+
+    /// The order of the tags in the series keys of tables, which the
+    /// series of series set plans are sorted by
+    tag_orders: TagOrders,
+
+    /// The total size of the partitions, kept up to date as they are
+    /// written and their chunks dropped
+    size: AtomicUsize,
+
+    /// If set, the creation of partitions and the closing of their
+    /// chunks are recorded in this log
+    lifecycle: Option<Arc<LifecycleLog>>,
 }
 
 impl MutableBufferDb {
@@ -140,6 +231,102 @@ impl MutableBufferDb {
         }
     }
 
+    /// Limit the dictionary of each chunk in this database to
+    /// `dictionary_limits`
+    pub fn with_dictionary_limits(self, dictionary_limits: DictionaryLimits) -> Self {
+        Self {
+            dictionary_limits,
+            ..self
+        }
+    }
+
+    /// Share the strings of the dictionaries of all chunks in this
+    /// database. Must be called before any data is written.
+    pub fn with_shared_dictionaries(self) -> Self {
+        Self {
+            string_pool: Some(StringPool::new()),
+            ..self
+        }
+    }
+
+    /// Use inverted indexes of the tag columns of each table to skip
+    /// tables without any rows matching equality predicates on tags
+    /// (such as `env = 'prod'`)
+    pub fn with_tag_index(self, tag_index: bool) -> Self {
+        Self { tag_index, ..self }
+    }
+
+    /// Close the open chunk of each partition once it reaches the size
+    /// `chunk_sizing` sizes it to for the ingest rate of the partition
+    pub fn with_chunk_sizing(self, chunk_sizing: Option<ChunkSizing>) -> Self {
+        Self {
+            chunk_sizing,
+            ..self
+        }
+    }
+
+    /// Order the tags in the series keys of tables by `tag_orders`
+    pub fn with_tag_orders(self, tag_orders: TagOrders) -> Self {
+        Self { tag_orders, ..self }
+    }
+
+    /// Record the creation of partitions and the closing of their chunks
+    /// (including those closed as they fill up) in `lifecycle`
+    pub fn with_lifecycle_log(self, lifecycle: Arc<LifecycleLog>) -> Self {
+        Self {
+            lifecycle: Some(lifecycle),
+            ..self
+        }
+    }
+
+    /// Returns the approximate memory used by the data of all chunks,
+    /// not including their inverted indexes (see `tag_index_size`), in
+    /// bytes. The size is tracked as the chunks change, so this is cheap
+    /// enough to call for every write.
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Returns the approximate memory used by the inverted indexes of
+    /// the tag columns of all chunks, in bytes
+    pub async fn tag_index_size(&self) -> usize {
+        let mut size = 0;
+        for partition in self.partition_snapshot().await {
+            let partition = partition.read().await;
+            size += partition
+                .iter()
+                .map(|chunk| chunk.tag_index_size())
+                .sum::<usize>();
+        }
+        size
+    }
+
+    /// Returns the number of times the open chunk of a partition was closed
+    /// because its dictionary was near capacity or had no room for a write
+    pub async fn dictionary_rollovers(&self) -> u64 {
+        let mut rollovers = 0;
+        for partition in self.partition_snapshot().await {
+            rollovers += partition.read().await.dictionary_rollovers();
+        }
+        rollovers
+    }
+
+    /// Returns the number of times the open chunk of a partition was closed
+    /// because it reached the size `chunk_sizing` sizes it to
+    pub async fn size_rollovers(&self) -> u64 {
+        let mut rollovers = 0;
+        for partition in self.partition_snapshot().await {
+            rollovers += partition.read().await.size_rollovers();
+        }
+        rollovers
+    }
+
+    /// Returns the pool of strings shared by the dictionaries in this
+    /// database, if dictionaries are shared
+    pub fn string_pool(&self) -> Option<&StringPool> {
+        self.string_pool.as_ref()
+    }
+
     /// Directs the writes from batch into the appropriate partitions
     async fn write_entries_to_partitions(&self, batch: &wal::WriteBufferBatch<'_>) -> Result<()> {
         if let Some(entries) = batch.entries() {
@@ -150,7 +337,11 @@ impl MutableBufferDb {
 
                 let partition = self.get_partition(key).await;
                 let mut partition = partition.write().await;
-                partition.write_entry(&entry)?
+                let size = partition.size();
+                let written = partition.write_entry(&entry);
+                self.size
+                    .fetch_add(partition.size().saturating_sub(size), Ordering::Relaxed);
+                written?
             }
         }
 
@@ -164,6 +355,17 @@ impl MutableBufferDb {
         Ok(partition.rollover_chunk())
     }
 
+    /// Returns true if the partition `partition_key` holds no data,
+    /// including if it doesn't exist
+    pub async fn partition_is_empty(&self, partition_key: &str) -> bool {
+        let partition = match self.partitions.read().await.get(partition_key) {
+            Some(partition) => Arc::clone(partition),
+            None => return true,
+        };
+        let partition = partition.read().await;
+        partition.is_empty()
+    }
+
     /// return the specified chunk from the partition
     /// Returns None if no such chunk exists.
     pub async fn get_chunk(&self, partition_key: &str, chunk_id: u32) -> Option<Arc<Chunk>> {
@@ -177,12 +379,23 @@ impl MutableBufferDb {
 
     /// drop the the specified chunk from the partition
     pub async fn drop_chunk(&self, partition_key: &str, chunk_id: u32) -> Result<Arc<Chunk>> {
-        self.get_partition(partition_key)
+        let chunk = self
+            .get_partition(partition_key)
             .await
             .write()
             .await
             .drop_chunk(chunk_id)
-            .context(DroppingChunk { partition_key })
+            .context(DroppingChunk { partition_key })?;
+        self.size.fetch_sub(chunk.size(), Ordering::Relaxed);
+
+        // Release any shared strings only used by the dropped chunk. The
+        // strings are only released once all references to the chunk are
+        // gone, so strings still in use by it may be pruned later.
+        if let Some(string_pool) = &self.string_pool {
+            string_pool.prune();
+        }
+
+        Ok(chunk)
     }
 }
 
@@ -191,6 +404,10 @@ impl Database for MutableBufferDb {
     type Error = Error;
     type Chunk = Chunk;
 
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
         match write.write_buffer_batch() {
             Some(b) => self.write_entries_to_partitions(&b).await?,
@@ -208,7 +425,7 @@ impl Database for MutableBufferDb {
     // return all column names in this database, while applying optional predicates
     async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
         let has_exprs = predicate.has_exprs();
-        let mut filter = ChunkTableFilter::new(predicate);
+        let mut filter = self.new_filter(predicate);
 
         if has_exprs {
             let mut visitor = NamePredVisitor::new();
@@ -224,7 +441,7 @@ impl Database for MutableBufferDb {
     /// return all field names in this database, while applying optional
     /// predicates
     async fn field_column_names(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error> {
-        let mut filter = ChunkTableFilter::new(predicate);
+        let mut filter = self.new_filter(predicate);
         let mut visitor = TableFieldPredVisitor::new();
         self.accept(&mut filter, &mut visitor).await?;
         Ok(visitor.into_fieldlist_plan())
@@ -238,7 +455,7 @@ impl Database for MutableBufferDb {
         predicate: Predicate,
     ) -> Result<StringSetPlan, Self::Error> {
         let has_exprs = predicate.has_exprs();
-        let mut filter = ChunkTableFilter::new(predicate);
+        let mut filter = self.new_filter(predicate);
 
         if has_exprs {
             let mut visitor = ValuePredVisitor::new(column_name);
@@ -252,8 +469,8 @@ impl Database for MutableBufferDb {
     }
 
     async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error> {
-        let mut filter = ChunkTableFilter::new(predicate);
-        let mut visitor = SeriesVisitor::new();
+        let mut filter = self.new_filter(predicate);
+        let mut visitor = SeriesVisitor::new(&self.tag_orders);
         self.accept(&mut filter, &mut visitor).await?;
         Ok(visitor.plans.into())
     }
@@ -263,19 +480,19 @@ impl Database for MutableBufferDb {
         predicate: Predicate,
         gby_agg: GroupByAndAggregate,
     ) -> Result<SeriesSetPlans, Self::Error> {
-        let mut filter = ChunkTableFilter::new(predicate);
+        let mut filter = self.new_filter(predicate);
 
         match gby_agg {
             GroupByAndAggregate::Columns { agg, group_columns } => {
                 // Add any specified groups as predicate columns (so we
                 // can skip tables without those tags)
                 let mut filter = filter.add_required_columns(&group_columns);
-                let mut visitor = GroupsVisitor::new(agg, group_columns);
+                let mut visitor = GroupsVisitor::new(agg, group_columns, &self.tag_orders);
                 self.accept(&mut filter, &mut visitor).await?;
                 Ok(visitor.plans.into())
             }
             GroupByAndAggregate::Window { agg, every, offset } => {
-                let mut visitor = WindowGroupsVisitor::new(agg, every, offset);
+                let mut visitor = WindowGroupsVisitor::new(agg, every, offset, &self.tag_orders);
                 self.accept(&mut filter, &mut visitor).await?;
                 Ok(visitor.plans.into())
             }
@@ -359,12 +576,16 @@ trait Visitor {
         Ok(())
     }
 
-    // called every time a column is visited
+    // called every time a column is visited. If the predicate has a
+    // timestamp range, `selection` has a bit set for each row of
+    // the table whose timestamp is within that range. It is computed
+    // once per table and shared by all of its columns
     fn visit_column(
         &mut self,
         _table: &Table,
         _column_id: u32,
         _column: &Column,
+        _selection: Option<&Bitmap>,
         _filter: &mut ChunkTableFilter,
     ) -> Result<()> {
         Ok(())
@@ -382,6 +603,12 @@ trait Visitor {
 }
 
 impl MutableBufferDb {
+    /// Creates the filter used to evaluate `predicate` while visiting
+    /// the tables of this database
+    fn new_filter(&self, predicate: Predicate) -> ChunkTableFilter {
+        ChunkTableFilter::new(predicate).with_tag_index(self.tag_index)
+    }
+
     /// returns the number of partitions in this database
     pub async fn len(&self) -> usize {
         self.partitions.read().await.len()
@@ -394,18 +621,30 @@ impl MutableBufferDb {
 
     /// Retrieve (or create) the partition for the specified partition key
     async fn get_partition(&self, partition_key: &str) -> Arc<RwLock<Partition>> {
-        // until we think this code is likely to be a contention hot
-        // spot, simply use a write lock even when often a read lock
-        // would do.
-        let mut partitions = self.partitions.write().await;
-
-        if let Some(partition) = partitions.get(partition_key) {
-            partition.clone()
-        } else {
-            let partition = Arc::new(RwLock::new(Partition::new(partition_key)));
-            partitions.insert(partition_key.to_string(), partition.clone());
-            partition
+        // Every write and query looks up partitions, so only take the
+        // write lock when the partition has to be created
+        if let Some(partition) = self.partitions.read().await.get(partition_key) {
+            return Arc::clone(partition);
         }
+
+        let mut partitions = self.partitions.write().await;
+        // another task may have created it while the lock was released
+        let partition = partitions
+            .entry(partition_key.to_string())
+            .or_insert_with(|| {
+                let partition = Partition::new_with_dictionary_config(
+                    partition_key,
+                    self.dictionary_limits,
+                    self.string_pool.clone(),
+                )
+                .with_chunk_sizing(self.chunk_sizing)
+                .with_lifecycle_log(self.lifecycle.clone());
+                if let Some(lifecycle) = &self.lifecycle {
+                    lifecycle.record_now(partition_key, None, LifecycleEventKind::Created, 0);
+                }
+                Arc::new(RwLock::new(partition))
+            });
+        Arc::clone(partition)
     }
 
     /// get a snapshot of all the current partitions -- useful so that
@@ -433,6 +672,9 @@ impl MutableBufferDb {
         visitor: &mut V,
     ) -> Result<()> {
         for partition in self.partition_snapshot().await.into_iter() {
+            // The chunks are visited in place under the read lock of their
+            // partition, which only holds up writes to that partition,
+            // rather than under the lock of the whole database
             let partition = partition.read().await;
 
             if filter.should_visit_partition(&partition)? {
@@ -444,11 +686,13 @@ impl MutableBufferDb {
                         if filter.should_visit_table(table)? {
                             visitor.pre_visit_table(table, chunk, filter)?;
 
+                            let selection = filter.time_range_selection(table)?;
                             for (column_id, column_index) in &table.column_id_to_index {
                                 visitor.visit_column(
                                     table,
                                     *column_id,
                                     &table.columns[*column_index],
+                                    selection.as_ref(),
                                     filter,
                                 )?
                             }
@@ -486,6 +730,10 @@ struct ChunkTableFilter {
     /// A 'compiled' version of the predicate to evaluate on tables /
     /// columns in a particular chunk during the walk
     chunk_predicate: Option<ChunkPredicate>,
+
+    /// If true, skip tables without any rows matching the equality
+    /// predicates on tags, using the inverted index of each table
+    use_tag_index: bool,
 }
 
 impl ChunkTableFilter {
@@ -494,9 +742,16 @@ impl ChunkTableFilter {
             predicate,
             additional_required_columns: None,
             chunk_predicate: None,
+            use_tag_index: false,
         }
     }
 
+    /// Sets whether the inverted tag index is used to skip tables
+    fn with_tag_index(mut self, use_tag_index: bool) -> Self {
+        self.use_tag_index = use_tag_index;
+        self
+    }
+
     /// adds the specified columns to a list of columns that must be
     /// present in a table.
     fn add_required_columns(mut self, column_names: &[String]) -> Self {
@@ -534,9 +789,34 @@ impl ChunkTableFilter {
         Ok(())
     }
 
-    /// If returns false, skips visiting _table and all its columns
+    /// If returns false, skips visiting _table and all its columns.
+    /// Otherwise the table is visited with its own timestamp range.
     fn should_visit_table(&mut self, table: &Table) -> Result<bool> {
-        Ok(table.could_match_predicate(self.chunk_predicate())?)
+        self.chunk_predicate
+            .as_mut()
+            .expect("Visited chunk to compile predicate")
+            .select_table(table.id);
+        let chunk_predicate = self.chunk_predicate();
+        if !table.could_match_predicate(chunk_predicate)? {
+            return Ok(false);
+        }
+
+        if self.use_tag_index {
+            if let Some(mut selection) = table.tag_equality_selection(chunk_predicate) {
+                if let Some(time_selection) = table.time_range_selection(chunk_predicate)? {
+                    selection.and_assign(&time_selection);
+                }
+                return Ok(selection.iter_set().next().is_some());
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the rows of `table` whose timestamps are within the
+    /// range of the predicate, if it has a timestamp range
+    fn time_range_selection(&self, table: &Table) -> Result<Option<Bitmap>> {
+        Ok(table.time_range_selection(self.chunk_predicate())?)
     }
 
     /// If returns false, skips visiting partition
@@ -573,15 +853,14 @@ impl NameVisitor {
 impl Visitor for NameVisitor {
     fn visit_column(
         &mut self,
-        table: &Table,
+        _table: &Table,
         column_id: u32,
         column: &Column,
-        filter: &mut ChunkTableFilter,
+        selection: Option<&Bitmap>,
+        _filter: &mut ChunkTableFilter,
     ) -> Result<()> {
-        if let Column::Tag(column, _) = column {
-            if table.column_matches_predicate(column, filter.chunk_predicate())? {
-                self.chunk_column_ids.insert(column_id);
-            }
+        if column.is_tag() && Table::column_matches_selection(column, selection) {
+            self.chunk_column_ids.insert(column_id);
         }
         Ok(())
     }
@@ -710,10 +989,11 @@ impl<'a> Visitor for ValueVisitor<'a> {
 
     fn visit_column(
         &mut self,
-        table: &Table,
+        _table: &Table,
         column_id: u32,
         column: &Column,
-        filter: &mut ChunkTableFilter,
+        selection: Option<&Bitmap>,
+        _filter: &mut ChunkTableFilter,
     ) -> Result<()> {
         if Some(column_id) != self.column_id {
             return Ok(());
@@ -724,30 +1004,21 @@ impl<'a> Visitor for ValueVisitor<'a> {
                 // if we have a timestamp prediate, find all values
                 // where the timestamp is within range. Otherwise take
                 // all values.
-                let chunk_predicate = filter.chunk_predicate();
-                match chunk_predicate.range {
+                match selection {
                     None => {
                         // take all non-null values
-                        column.iter().filter_map(|&s| s).for_each(|value_id| {
+                        column.iter_non_null().for_each(|&value_id| {
                             self.chunk_value_ids.insert(value_id);
                         });
                     }
-                    Some(range) => {
-                        // filter out all values that don't match the timestmap
-                        let time_column = table.column_i64(chunk_predicate.time_column_id)?;
-
-                        column
-                            .iter()
-                            .zip(time_column.iter())
-                            .filter_map(|(&column_value_id, &timestamp_value)| {
-                                if range.contains_opt(timestamp_value) {
-                                    column_value_id
-                                } else {
-                                    None
-                                }
-                            })
-                            .for_each(|value_id| {
-                                self.chunk_value_ids.insert(value_id);
+                    Some(selection) => {
+                        // take the non-null values where the timestamp is
+                        // within range
+                        let value_ids = column.values();
+                        selection
+                            .iter_set_in_both(column.validity())
+                            .for_each(|index| {
+                                self.chunk_value_ids.insert(value_ids[index]);
                             });
                     }
                 }
@@ -817,17 +1088,21 @@ impl<'a> Visitor for ValuePredVisitor<'a> {
 
 /// Return DataFusion plans to calculate which series pass the
 /// specified predicate.
-struct SeriesVisitor {
+struct SeriesVisitor<'a> {
+    tag_orders: &'a TagOrders,
     plans: Vec<SeriesSetPlan>,
 }
 
-impl SeriesVisitor {
-    fn new() -> Self {
-        Self { plans: Vec::new() }
+impl<'a> SeriesVisitor<'a> {
+    fn new(tag_orders: &'a TagOrders) -> Self {
+        Self {
+            tag_orders,
+            plans: Vec::new(),
+        }
     }
 }
 
-impl Visitor for SeriesVisitor {
+impl<'a> Visitor for SeriesVisitor<'a> {
     fn pre_visit_table(
         &mut self,
         table: &Table,
@@ -835,7 +1110,7 @@ impl Visitor for SeriesVisitor {
         filter: &mut ChunkTableFilter,
     ) -> Result<()> {
         self.plans
-            .push(table.series_set_plan(filter.chunk_predicate(), chunk)?);
+            .push(table.series_set_plan(filter.chunk_predicate(), self.tag_orders, chunk)?);
 
         Ok(())
     }
@@ -843,23 +1118,25 @@ impl Visitor for SeriesVisitor {
 
 /// Return DataFusion plans to calculate series that pass the
 /// specified predicate, grouped according to grouped_columns
-struct GroupsVisitor {
+struct GroupsVisitor<'a> {
     agg: Aggregate,
     group_columns: Vec<String>,
+    tag_orders: &'a TagOrders,
     plans: Vec<SeriesSetPlan>,
 }
 
-impl GroupsVisitor {
-    fn new(agg: Aggregate, group_columns: Vec<String>) -> Self {
+impl<'a> GroupsVisitor<'a> {
+    fn new(agg: Aggregate, group_columns: Vec<String>, tag_orders: &'a TagOrders) -> Self {
         Self {
             agg,
             group_columns,
+            tag_orders,
             plans: Vec::new(),
         }
     }
 }
 
-impl Visitor for GroupsVisitor {
+impl<'a> Visitor for GroupsVisitor<'a> {
     fn pre_visit_table(
         &mut self,
         table: &Table,
@@ -870,6 +1147,7 @@ impl Visitor for GroupsVisitor {
             filter.chunk_predicate(),
             self.agg,
             &self.group_columns,
+            self.tag_orders,
             chunk,
         )?);
 
@@ -879,26 +1157,33 @@ impl Visitor for GroupsVisitor {
 
 /// Return DataFusion plans to calculate series that pass the
 /// specified predicate, grouped using the window definition
-struct WindowGroupsVisitor {
+struct WindowGroupsVisitor<'a> {
     agg: Aggregate,
     every: WindowDuration,
     offset: WindowDuration,
+    tag_orders: &'a TagOrders,
 
     plans: Vec<SeriesSetPlan>,
 }
 
-impl WindowGroupsVisitor {
-    fn new(agg: Aggregate, every: WindowDuration, offset: WindowDuration) -> Self {
+impl<'a> WindowGroupsVisitor<'a> {
+    fn new(
+        agg: Aggregate,
+        every: WindowDuration,
+        offset: WindowDuration,
+        tag_orders: &'a TagOrders,
+    ) -> Self {
         Self {
             agg,
             every,
             offset,
+            tag_orders,
             plans: Vec::new(),
         }
     }
 }
 
-impl Visitor for WindowGroupsVisitor {
+impl<'a> Visitor for WindowGroupsVisitor<'a> {
     fn pre_visit_table(
         &mut self,
         table: &Table,
@@ -910,6 +1195,7 @@ impl Visitor for WindowGroupsVisitor {
             self.agg,
             &self.every,
             &self.offset,
+            self.tag_orders,
             chunk,
         )?);
 
@@ -928,6 +1214,7 @@ mod tests {
             Executor,
         },
         frontend::sql::SQLQueryPlanner,
+        func::regex_match::make_regex_match_expr,
         predicate::PredicateBuilder,
         Database,
     };
@@ -939,10 +1226,11 @@ mod tests {
             record_batch::RecordBatch,
         },
         assert_table_eq,
-        datafusion::{physical_plan::collect, prelude::*},
+        datafusion::{logical_plan::Expr, physical_plan::collect, prelude::*},
     };
+    use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
     use influxdb_line_protocol::{parse_lines, ParsedLine};
-    use test_helpers::{assert_contains, str_pair_vec_to_vec};
+    use test_helpers::str_pair_vec_to_vec;
     use tokio::sync::mpsc;
 
     type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -1474,6 +1762,90 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_size() {
+        let db = MutableBufferDb::new("size_db");
+        assert_eq!(db.size(), 0);
+
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1,status=\"ok\" 10")
+            .map(|l| l.unwrap())
+            .collect();
+        write_lines(&db, &lines).await;
+        let size = db.size();
+        assert!(size > 0);
+
+        let lines: Vec<_> = parse_lines("cpu,host=b usage=2,status=\"degraded\" 20")
+            .map(|l| l.unwrap())
+            .collect();
+        write_lines(&db, &lines).await;
+        assert!(db.size() > size);
+
+        // the size is that of the chunks, and shrinks as they are dropped
+        let partition_keys = db.partition_keys().await.unwrap();
+        assert_eq!(partition_keys.len(), 1);
+        let chunk = db.rollover_partition(&partition_keys[0]).await.unwrap();
+        assert_eq!(db.size(), chunk.size());
+        db.drop_chunk(&partition_keys[0], chunk.id()).await.unwrap();
+        assert_eq!(db.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_query_series_filter_tag_index() -> Result {
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=CA,city=LA temp=90.0 200",
+            "h2o,state=CA,city=LA temp=90.0 350",
+            "o2,state=MA,city=Boston temp=50.4,reading=50 100",
+            "o2,state=MA,city=Boston temp=53.4,reading=51 250",
+        ];
+        let lp_data = lp_lines.join("\n");
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+
+        let predicate = || {
+            PredicateBuilder::default()
+                .timestamp_range(200, 300)
+                .add_expr(col("state").eq(lit("CA")))
+                .build()
+        };
+
+        // without the index, a plan is created for each table
+        let db = MutableBufferDb::new("column_namedb");
+        write_lines(&db, &lines).await;
+        let plans = db.query_series(predicate()).await.unwrap();
+        assert_eq!(plans.plans.len(), 2);
+        assert_eq!(db.tag_index_size().await, 0);
+
+        // with the index, o2 is skipped as it has no rows with state=CA
+        let db = MutableBufferDb::new("column_namedb").with_tag_index(true);
+        write_lines(&db, &lines).await;
+        let plans = db.query_series(predicate()).await.unwrap();
+        assert_eq!(plans.plans.len(), 1);
+        assert!(db.tag_index_size().await > 0);
+
+        let results = run_and_gather_results(plans).await;
+        assert_eq!(results.len(), 1);
+        let series_set0 = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(*series_set0.table_name, "h2o");
+        assert_eq!(series_set0.num_rows, 1);
+
+        // rows with state=CA, but outside of the time range
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(300, 310)
+            .add_expr(col("state").eq(lit("CA")))
+            .build();
+        let plans = db.query_series(predicate).await.unwrap();
+        assert_eq!(plans.plans.len(), 0);
+
+        // a value that isn't in the chunk at all
+        let predicate = PredicateBuilder::default()
+            .add_expr(col("state").eq(lit("NY")))
+            .build();
+        let plans = db.query_series(predicate).await.unwrap();
+        assert_eq!(plans.plans.len(), 0);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_query_series_pred_refers_to_column_not_in_table() -> Result {
         let db = MutableBufferDb::new("column_namedb");
@@ -1536,7 +1908,8 @@ mod tests {
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.4 100",
-            "h2o,state=MA,city=Boston temp=72.4 250",
+            "h2o,state=CA,city=LA temp=72.4 250",
+            "o2,city=Boston reading=50 100",
         ];
 
         let lp_data = lp_lines.join("\n");
@@ -1544,16 +1917,87 @@ mod tests {
         let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
         write_lines(&db, &lines).await;
 
+        let num_rows = |results: Vec<Result<SeriesSet, SeriesSetError>>| -> Vec<(String, usize)> {
+            results
+                .into_iter()
+                .map(|r| {
+                    let series_set = r.expect("Correctly converted");
+                    (series_set.table_name.to_string(), series_set.num_rows)
+                })
+                .collect()
+        };
+
         let predicate = PredicateBuilder::default()
             .add_expr(col("state").not_eq(lit("MA")))
             .build();
+        let results = run_and_gather_results(db.query_series(predicate).await.unwrap()).await;
+        assert_eq!(num_rows(results), vec![("h2o".to_string(), 1)]);
 
-        // Should err as the neq path isn't implemented yet
-        let err = db.query_series(predicate).await.unwrap_err();
-        assert_contains!(
-            err.to_string(),
-            "Operator NotEq not yet supported in IOx MutableBuffer"
+        // o2 has no state, which is null and so passes `IS NULL`
+        let predicate = PredicateBuilder::default()
+            .add_expr(Expr::IsNull(Box::new(col("state"))).or(col("state").not_eq(lit("MA"))))
+            .build();
+        let results = run_and_gather_results(db.query_series(predicate).await.unwrap()).await;
+        assert_eq!(
+            num_rows(results),
+            vec![("h2o".to_string(), 1), ("o2".to_string(), 1)]
         );
+
+        let predicate = PredicateBuilder::default()
+            .add_expr(Expr::Not(Box::new(
+                make_regex_match_expr(col("city"), "^Bos").unwrap(),
+            )))
+            .build();
+        let results = run_and_gather_results(db.query_series(predicate).await.unwrap()).await;
+        assert_eq!(num_rows(results), vec![("h2o".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_error_kinds() {
+        let db = MutableBufferDb::new("error_kinds").with_dictionary_limits(DictionaryLimits {
+            max_entries: 6,
+            ..Default::default()
+        });
+
+        let write = |lp: &str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default())
+        };
+        let kind = |err: Error| DatabaseError::from(err).kind();
+
+        db.store_replicated_write(&write("h2o,state=MA temp=70.4 100"))
+            .await
+            .unwrap();
+
+        // temp is a float column
+        let err = db
+            .store_replicated_write(&write("h2o,state=MA temp=\"hot\" 200"))
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), DatabaseErrorKind::SchemaConflict);
+
+        // the dictionary only has space for one more string
+        let err = db
+            .store_replicated_write(&write("h2o,state=NY,city=Albany temp=1 300"))
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), DatabaseErrorKind::ResourceExhausted);
+
+        // temp is not a tag column
+        let err = db
+            .column_values("temp", PredicateBuilder::default().build())
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), DatabaseErrorKind::SchemaConflict);
+
+        let err = db
+            .column_values("county", PredicateBuilder::default().build())
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), DatabaseErrorKind::NotFound);
+
+        let err = db.drop_chunk("", 42).await.unwrap_err();
+        assert_eq!(kind(err), DatabaseErrorKind::NotFound);
     }
 
     #[tokio::test]
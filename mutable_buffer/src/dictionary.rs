@@ -1,6 +1,19 @@
 //! Contains a structure to map from strings to u32 symbols based on
 //! string interning.
-use snafu::{OptionExt, Snafu};
+//!
+//! Each chunk has its own dictionary. By default, each dictionary
+//! stores its own copy of every string. Optionally, dictionaries can
+//! share the memory for the strings via a [`StringPool`](struct.StringPool.html)
+//! (typically one per database), which saves memory when the same
+//! (tag) values are written to many partitions. Ids are always
+//! specific to each dictionary.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use data_types::database_rules::ChunkDictionaryConfig;
+use snafu::{ensure, OptionExt, Snafu};
 use string_interner::{
     backend::StringBackend, DefaultHashBuilder, DefaultSymbol, StringInterner, Symbol,
 };
@@ -12,14 +25,155 @@ pub enum Error {
 
     #[snafu(display("Dictionary lookup error for value {}", value))]
     DictionaryValueLookupError { value: String },
+
+    #[snafu(display(
+        "Dictionary full: can not insert value '{}' into dictionary with {} entries ({} bytes). Limits: {} entries, {} bytes",
+        value,
+        len,
+        size,
+        max_entries,
+        max_bytes
+    ))]
+    DictionaryFull {
+        value: String,
+        len: usize,
+        size: usize,
+        max_entries: usize,
+        max_bytes: usize,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The largest number of entries a dictionary can hold. Ids are u32s
+/// and the interner reserves one value (`u32::MAX`) for itself.
+pub const MAX_DICTIONARY_ENTRIES: usize = u32::MAX as usize - 1;
+
+/// The default limit on the total size of the strings in a dictionary
+pub const DEFAULT_MAX_DICTIONARY_BYTES: usize = 1024 * 1024 * 1024;
+
+/// A dictionary is considered to be near capacity once it is within
+/// `1 / NEAR_CAPACITY_DIVISOR` of either of its limits (e.g. 90% full)
+const NEAR_CAPACITY_DIVISOR: usize = 10;
+
+/// Limits on the size of a dictionary. Once a dictionary is near
+/// capacity, the chunk that owns it should be closed so new values go
+/// to a chunk with a fresh dictionary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DictionaryLimits {
+    /// The maximum number of distinct values in the dictionary. Can
+    /// not be larger than `MAX_DICTIONARY_ENTRIES`
+    pub max_entries: usize,
+
+    /// The maximum total size, in bytes, of the distinct values in
+    /// the dictionary
+    pub max_bytes: usize,
+}
+
+impl Default for DictionaryLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: MAX_DICTIONARY_ENTRIES,
+            max_bytes: DEFAULT_MAX_DICTIONARY_BYTES,
+        }
+    }
+}
+
+impl From<&ChunkDictionaryConfig> for DictionaryLimits {
+    fn from(config: &ChunkDictionaryConfig) -> Self {
+        let default = Self::default();
+        Self {
+            max_entries: config
+                .max_entries
+                .map_or(default.max_entries, |max_entries| {
+                    max_entries.min(MAX_DICTIONARY_ENTRIES)
+                }),
+            max_bytes: config.max_bytes.unwrap_or(default.max_bytes),
+        }
+    }
+}
+
+/// A set of strings shared between dictionaries. Cloning a
+/// `StringPool` produces another handle to the same pool.
+#[derive(Debug, Clone, Default)]
+pub struct StringPool {
+    strings: Arc<Mutex<HashSet<Arc<str>>>>,
+}
+
+impl StringPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the pooled copy of `value`, adding it to the pool if
+    /// it is not yet present
+    pub fn intern(&self, value: &str) -> Arc<str> {
+        let mut strings = self.strings.lock().expect("mutex poisoned");
+        match strings.get(value) {
+            Some(s) => Arc::clone(s),
+            None => {
+                let s: Arc<str> = Arc::from(value);
+                strings.insert(Arc::clone(&s));
+                s
+            }
+        }
+    }
+
+    /// Removes all strings that are no longer used by any dictionary
+    /// from the pool, returning the number of strings removed
+    pub fn prune(&self) -> usize {
+        let mut strings = self.strings.lock().expect("mutex poisoned");
+        let len = strings.len();
+        strings.retain(|s| Arc::strong_count(s) > 1);
+        len - strings.len()
+    }
+
+    /// Returns the number of distinct strings in the pool
+    pub fn len(&self) -> usize {
+        self.strings.lock().expect("mutex poisoned").len()
+    }
+
+    /// Returns true if the pool holds no strings
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total size, in bytes, of the distinct strings in
+    /// the pool
+    pub fn size(&self) -> usize {
+        self.strings
+            .lock()
+            .expect("mutex poisoned")
+            .iter()
+            .map(|s| s.len())
+            .sum()
+    }
+}
+
+/// How a dictionary stores its strings
+#[derive(Debug, Clone)]
+enum Backend {
+    /// The dictionary owns its own copy of each string
+    Local(StringInterner<DefaultSymbol, StringBackend<DefaultSymbol>, DefaultHashBuilder>),
+
+    /// The strings are shared with other dictionaries via a pool
+    Shared {
+        pool: StringPool,
+        ids: HashMap<Arc<str>, u32>,
+        values: Vec<Arc<str>>,
+    },
+}
+
 #[derive(Debug, Clone)]
-pub struct Dictionary(
-    StringInterner<DefaultSymbol, StringBackend<DefaultSymbol>, DefaultHashBuilder>,
-);
+pub struct Dictionary {
+    backend: Backend,
+
+    /// The limits for this dictionary
+    limits: DictionaryLimits,
+
+    /// The total size, in bytes, of the strings in this dictionary
+    size: usize,
+}
 
 impl Default for Dictionary {
     fn default() -> Self {
@@ -29,13 +183,114 @@ impl Default for Dictionary {
 
 impl Dictionary {
     pub fn new() -> Self {
-        Self(StringInterner::new())
+        Self::new_with_limits(DictionaryLimits::default())
+    }
+
+    /// Create a new, empty dictionary that holds no more than `limits`
+    pub fn new_with_limits(limits: DictionaryLimits) -> Self {
+        let limits = DictionaryLimits {
+            max_entries: limits.max_entries.min(MAX_DICTIONARY_ENTRIES),
+            ..limits
+        };
+
+        Self {
+            backend: Backend::Local(StringInterner::new()),
+            limits,
+            size: 0,
+        }
+    }
+
+    /// Create a new, empty dictionary that holds no more than
+    /// `limits`, sharing the memory for its strings via `pool`
+    pub fn new_shared(limits: DictionaryLimits, pool: StringPool) -> Self {
+        Self {
+            backend: Backend::Shared {
+                pool,
+                ids: HashMap::new(),
+                values: Vec::new(),
+            },
+            ..Self::new_with_limits(limits)
+        }
     }
 
     /// Returns the id corresponding to value, adding an entry for the
-    /// id if it is not yet present in the dictionary.
-    pub fn lookup_value_or_insert(&mut self, value: &str) -> u32 {
-        symbol_to_u32(self.0.get_or_intern(value))
+    /// id if it is not yet present in the dictionary. Returns an error if
+    /// the value is not present and there is no more room in the
+    /// dictionary.
+    pub fn lookup_value_or_insert(&mut self, value: &str) -> Result<u32> {
+        if let Some(id) = self.id(value) {
+            return Ok(id);
+        }
+
+        let len = self.len();
+        let size = self.size;
+        let DictionaryLimits {
+            max_entries,
+            max_bytes,
+        } = self.limits;
+        ensure!(
+            len < max_entries && size + value.len() <= max_bytes,
+            DictionaryFull {
+                value,
+                len,
+                size,
+                max_entries,
+                max_bytes,
+            }
+        );
+
+        self.size += value.len();
+        let id = match &mut self.backend {
+            Backend::Local(interner) => symbol_to_u32(interner.get_or_intern(value)),
+            Backend::Shared { pool, ids, values } => {
+                let value = pool.intern(value);
+                let id = values.len() as u32;
+                values.push(Arc::clone(&value));
+                ids.insert(value, id);
+                id
+            }
+        };
+        Ok(id)
+    }
+
+    /// Returns true if `entries` more values of `bytes` bytes in total fit
+    /// in the dictionary
+    pub fn has_room(&self, entries: usize, bytes: usize) -> bool {
+        self.len() + entries <= self.limits.max_entries
+            && self.size + bytes <= self.limits.max_bytes
+    }
+
+    /// Returns an error if the distinct `values` that are not yet in the
+    /// dictionary don't all fit in it. Nothing is inserted, so a write can
+    /// be checked before any of it is applied.
+    pub fn check_room<'a>(&self, values: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        let mut new_values = HashSet::new();
+        let mut new_bytes = 0;
+        let mut last = None;
+        for value in values {
+            if self.id(value).is_none() && new_values.insert(value) {
+                new_bytes += value.len();
+                last = Some(value);
+            }
+        }
+
+        let len = self.len();
+        let size = self.size;
+        let DictionaryLimits {
+            max_entries,
+            max_bytes,
+        } = self.limits;
+        ensure!(
+            len + new_values.len() <= max_entries && size + new_bytes <= max_bytes,
+            DictionaryFull {
+                value: last.unwrap_or_default(),
+                len,
+                size,
+                max_entries,
+                max_bytes,
+            }
+        );
+        Ok(())
     }
 
     /// Returns the ID in self.dictionary that corresponds to `value`, if any.
@@ -49,20 +304,219 @@ impl Dictionary {
     /// if any. No error is returned to avoid an allocation when no value is
     /// present
     pub fn id(&self, value: &str) -> Option<u32> {
-        self.0.get(value).map(symbol_to_u32)
+        match &self.backend {
+            Backend::Local(interner) => interner.get(value).map(symbol_to_u32),
+            Backend::Shared { ids, .. } => ids.get(value).copied(),
+        }
     }
 
     /// Returns the str in self.dictionary that corresponds to `id`,
     /// if any. Returns an error if no such id is found
     pub fn lookup_id(&self, id: u32) -> Result<&str> {
-        let symbol =
-            Symbol::try_from_usize(id as usize).expect("to be able to convert u32 to symbol");
-        self.0
-            .resolve(symbol)
-            .context(DictionaryIdLookupError { id })
+        match &self.backend {
+            Backend::Local(interner) => {
+                let symbol =
+                    Symbol::try_from_usize(id as usize).context(DictionaryIdLookupError { id })?;
+                interner.resolve(symbol)
+            }
+            Backend::Shared { values, .. } => values.get(id as usize).map(|v| v.as_ref()),
+        }
+        .context(DictionaryIdLookupError { id })
+    }
+
+    /// Returns the number of distinct values in this dictionary
+    pub fn len(&self) -> usize {
+        match &self.backend {
+            Backend::Local(interner) => interner.len(),
+            Backend::Shared { values, .. } => values.len(),
+        }
+    }
+
+    /// Returns true if there are no values in this dictionary
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total size, in bytes, of the values in this
+    /// dictionary. For dictionaries that share their strings, the
+    /// memory for some or all of these bytes is shared with other
+    /// dictionaries.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns true if the strings of this dictionary are shared with
+    /// other dictionaries
+    pub fn is_shared(&self) -> bool {
+        matches!(self.backend, Backend::Shared { .. })
+    }
+
+    /// Returns the limits of this dictionary
+    pub fn limits(&self) -> &DictionaryLimits {
+        &self.limits
+    }
+
+    /// Returns true if this dictionary is close to either of its
+    /// limits and should not be used for new data
+    pub fn is_near_capacity(&self) -> bool {
+        let near = |value: usize, limit: usize| value >= limit - limit / NEAR_CAPACITY_DIVISOR;
+
+        near(self.len(), self.limits.max_entries) || near(self.size, self.limits.max_bytes)
     }
 }
 
 fn symbol_to_u32(sym: DefaultSymbol) -> u32 {
     sym.to_usize() as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_and_insert() {
+        let mut dictionary = Dictionary::new();
+        assert!(dictionary.is_empty());
+
+        let foo = dictionary.lookup_value_or_insert("foo").unwrap();
+        let bar = dictionary.lookup_value_or_insert("bar").unwrap();
+        assert_ne!(foo, bar);
+        assert_eq!(dictionary.lookup_value_or_insert("foo").unwrap(), foo);
+
+        assert_eq!(dictionary.len(), 2);
+        assert_eq!(dictionary.size(), 6);
+        assert_eq!(dictionary.lookup_id(bar).unwrap(), "bar");
+        assert_eq!(dictionary.lookup_value("foo").unwrap(), foo);
+        assert!(dictionary.lookup_value("baz").is_err());
+        assert!(dictionary.lookup_id(u32::MAX).is_err());
+    }
+
+    #[test]
+    fn entry_limit() {
+        let mut dictionary = Dictionary::new_with_limits(DictionaryLimits {
+            max_entries: 10,
+            ..Default::default()
+        });
+
+        for i in 0..8 {
+            dictionary.lookup_value_or_insert(&i.to_string()).unwrap();
+        }
+        assert!(!dictionary.is_near_capacity());
+
+        dictionary.lookup_value_or_insert("8").unwrap();
+        assert!(dictionary.is_near_capacity());
+        dictionary.lookup_value_or_insert("9").unwrap();
+
+        let err = dictionary.lookup_value_or_insert("10").unwrap_err();
+        assert!(matches!(err, Error::DictionaryFull { len: 10, .. }));
+
+        // existing values can still be looked up
+        assert_eq!(
+            dictionary.lookup_value_or_insert("1").unwrap(),
+            dictionary.lookup_value("1").unwrap()
+        );
+        assert_eq!(dictionary.len(), 10);
+    }
+
+    #[test]
+    fn byte_limit() {
+        let mut dictionary = Dictionary::new_with_limits(DictionaryLimits {
+            max_bytes: 100,
+            ..Default::default()
+        });
+
+        dictionary.lookup_value_or_insert(&"a".repeat(80)).unwrap();
+        assert!(!dictionary.is_near_capacity());
+        dictionary.lookup_value_or_insert(&"b".repeat(10)).unwrap();
+        assert!(dictionary.is_near_capacity());
+
+        let err = dictionary
+            .lookup_value_or_insert(&"c".repeat(11))
+            .unwrap_err();
+        assert!(matches!(err, Error::DictionaryFull { size: 90, .. }));
+
+        dictionary.lookup_value_or_insert(&"c".repeat(10)).unwrap();
+        assert_eq!(dictionary.size(), 100);
+    }
+
+    #[test]
+    fn check_room() {
+        let mut dictionary = Dictionary::new_with_limits(DictionaryLimits {
+            max_entries: 3,
+            ..Default::default()
+        });
+        dictionary.lookup_value_or_insert("a").unwrap();
+
+        // values already present and repeated values are counted once
+        dictionary.check_room(vec!["a", "b", "b", "c"]).unwrap();
+        let err = dictionary.check_room(vec!["b", "c", "d"]).unwrap_err();
+        assert!(matches!(err, Error::DictionaryFull { len: 1, .. }));
+
+        // nothing was inserted
+        assert_eq!(dictionary.len(), 1);
+    }
+
+    #[test]
+    fn limits_from_config() {
+        let limits = DictionaryLimits::from(&ChunkDictionaryConfig::default());
+        assert_eq!(limits, DictionaryLimits::default());
+
+        let limits = DictionaryLimits::from(&ChunkDictionaryConfig {
+            max_entries: Some(usize::MAX),
+            max_bytes: Some(1024),
+        });
+        assert_eq!(limits.max_entries, MAX_DICTIONARY_ENTRIES);
+        assert_eq!(limits.max_bytes, 1024);
+    }
+
+    #[test]
+    fn shared_dictionaries() {
+        let pool = StringPool::new();
+        let mut dictionary1 = Dictionary::new_shared(DictionaryLimits::default(), pool.clone());
+        let mut dictionary2 = Dictionary::new_shared(DictionaryLimits::default(), pool.clone());
+        assert!(dictionary1.is_shared());
+        assert!(!Dictionary::new().is_shared());
+
+        let west1 = dictionary1.lookup_value_or_insert("us-west").unwrap();
+        let east1 = dictionary1.lookup_value_or_insert("us-east").unwrap();
+        assert_eq!(
+            dictionary1.lookup_value_or_insert("us-west").unwrap(),
+            west1
+        );
+
+        // ids are per dictionary
+        let east2 = dictionary2.lookup_value_or_insert("us-east").unwrap();
+        assert_eq!(dictionary2.lookup_id(east2).unwrap(), "us-east");
+        assert_eq!(dictionary1.lookup_id(east1).unwrap(), "us-east");
+        assert!(dictionary2.id("us-west").is_none());
+        assert!(dictionary2.lookup_id(east2 + 1).is_err());
+
+        // but the strings are stored once
+        assert_eq!(dictionary1.size() + dictionary2.size(), 21);
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.size(), 14);
+
+        // strings are removed from the pool once no dictionary uses them
+        assert_eq!(pool.prune(), 0);
+        std::mem::drop(dictionary1);
+        assert_eq!(pool.prune(), 1);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(dictionary2.lookup_id(east2).unwrap(), "us-east");
+    }
+
+    #[test]
+    fn shared_dictionary_limits() {
+        let limits = DictionaryLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+        let mut dictionary = Dictionary::new_shared(limits, StringPool::new());
+
+        dictionary.lookup_value_or_insert("a").unwrap();
+        dictionary.lookup_value_or_insert("b").unwrap();
+        assert!(dictionary.is_near_capacity());
+
+        let err = dictionary.lookup_value_or_insert("c").unwrap_err();
+        assert!(matches!(err, Error::DictionaryFull { len: 2, .. }));
+    }
+}
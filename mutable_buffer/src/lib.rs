@@ -56,13 +56,23 @@
     clippy::use_self
 )]
 
+mod bitmap;
 pub mod chunk;
 mod column;
 pub mod database;
 mod dictionary;
 mod partition;
 mod table;
+mod tag_index;
+mod time_index;
 
 // Allow restore chunks to be used outside of this crate (for
 // benchmarking)
 pub use crate::database::MutableBufferDb;
+pub use crate::dictionary::{DictionaryLimits, StringPool};
+
+/// Internal types exposed for benchmarking
+pub mod benchmarks {
+    pub use crate::bitmap::Bitmap;
+    pub use crate::column::{Column, NullableBoolVec, NullableVec};
+}
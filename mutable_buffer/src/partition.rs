@@ -1,11 +1,17 @@
 //! Holds one or more Chunks.
 
+use data_types::database_rules::ChunkSizing;
 use generated_types::wal as wb;
-use std::{collections::BTreeMap, sync::Arc};
+use query::{lifecycle::LifecycleLog, LifecycleEventKind};
+use std::{collections::BTreeMap, sync::Arc, time::Instant};
 
-use crate::chunk::{Chunk, Error as ChunkError};
+use crate::{
+    chunk::{Chunk, Error as ChunkError},
+    dictionary::{Dictionary, DictionaryLimits, Error as DictionaryError, StringPool},
+};
 
 use snafu::{ResultExt, Snafu};
+use tracing::info;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -19,6 +25,16 @@ pub enum Error {
         source: ChunkError,
     },
 
+    #[snafu(display(
+        "Write to partition with key '{}' in mutable buffer does not fit in the dictionary of a new chunk: {}",
+        partition_key,
+        source
+    ))]
+    WriteDoesNotFitDictionary {
+        partition_key: String,
+        source: DictionaryError,
+    },
+
     #[snafu(display(
         "Can not drop open chunk '{}' of partition with key '{}' in mutable buffer",
         chunk_id,
@@ -74,15 +90,70 @@ pub struct Partition {
     /// Responsible for assigning ids to chunks. Eventually, this might
     /// need to start at a number other than 0.
     id_generator: u32,
+
+    /// The limits for the dictionary of each chunk in this partition
+    dictionary_limits: DictionaryLimits,
+
+    /// If set, the dictionaries of the chunks in this partition share
+    /// their strings via this pool
+    string_pool: Option<StringPool>,
+
+    /// The number of times the open chunk was closed because its
+    /// dictionary was near capacity
+    dictionary_rollovers: u64,
+
+    /// If set, the open chunk is closed once it reaches the size this
+    /// sizes it to for the ingest rate of the partition
+    chunk_sizing: Option<ChunkSizing>,
+
+    /// How fast the partition is written to, tracked if `chunk_sizing` is
+    /// set
+    ingest_rate: IngestRate,
+
+    /// The number of times the open chunk was closed because it reached
+    /// the size `chunk_sizing` sizes it to
+    size_rollovers: u64,
+
+    /// The total size of the closed chunks
+    closed_chunks_size: usize,
+
+    /// The size of the open chunk, updated as it is written to
+    open_chunk_size: usize,
+
+    /// If set, the closing of chunks is recorded in this log
+    lifecycle: Option<Arc<LifecycleLog>>,
 }
 
 impl Partition {
     pub fn new(key: impl Into<String>) -> Self {
+        Self::new_with_dictionary_limits(key, DictionaryLimits::default())
+    }
+
+    /// Create a new partition whose chunks' dictionaries hold no more
+    /// than `dictionary_limits`
+    pub fn new_with_dictionary_limits(
+        key: impl Into<String>,
+        dictionary_limits: DictionaryLimits,
+    ) -> Self {
+        Self::new_with_dictionary_config(key, dictionary_limits, None)
+    }
+
+    /// Create a new partition whose chunks' dictionaries hold no more
+    /// than `dictionary_limits`, and share their strings via
+    /// `string_pool`, if any.
+    pub fn new_with_dictionary_config(
+        key: impl Into<String>,
+        dictionary_limits: DictionaryLimits,
+        string_pool: Option<StringPool>,
+    ) -> Self {
         // TODO: for existing partitions, does this need to pick up at preexisting ID?
         let mut id_generator = 0;
 
         let key: String = key.into();
-        let open_chunk = Chunk::new(id_generator);
+        let open_chunk = Chunk::new_with_dictionary(
+            id_generator,
+            Self::new_dictionary(dictionary_limits, string_pool.as_ref()),
+        );
         id_generator += 1;
 
         Self {
@@ -90,17 +161,108 @@ impl Partition {
             open_chunk,
             closed_chunks: BTreeMap::new(),
             id_generator,
+            dictionary_limits,
+            string_pool,
+            dictionary_rollovers: 0,
+            chunk_sizing: None,
+            ingest_rate: IngestRate::new(Instant::now()),
+            size_rollovers: 0,
+            closed_chunks_size: 0,
+            open_chunk_size: 0,
+            lifecycle: None,
+        }
+    }
+
+    /// Close the open chunk of this partition once it reaches the size
+    /// `chunk_sizing` sizes it to for the ingest rate of the partition
+    pub fn with_chunk_sizing(self, chunk_sizing: Option<ChunkSizing>) -> Self {
+        Self {
+            chunk_sizing,
+            ..self
+        }
+    }
+
+    /// Record the closing of chunks of this partition in `lifecycle`, if
+    /// set
+    pub fn with_lifecycle_log(self, lifecycle: Option<Arc<LifecycleLog>>) -> Self {
+        Self { lifecycle, ..self }
+    }
+
+    /// Create a dictionary for a new chunk in this partition
+    fn new_dictionary(limits: DictionaryLimits, string_pool: Option<&StringPool>) -> Dictionary {
+        match string_pool {
+            Some(string_pool) => Dictionary::new_shared(limits, string_pool.clone()),
+            None => Dictionary::new_with_limits(limits),
         }
     }
 
     /// write data to the open chunk
+    ///
+    /// If the dictionary of the open chunk is near capacity, or doesn't
+    /// have room for the names and tag values of the data, the open chunk
+    /// is closed first and the data is written to a new chunk (with a new
+    /// dictionary). Data that doesn't fit in a new dictionary either is
+    /// rejected without writing any of it.
     pub fn write_entry(&mut self, entry: &wb::WriteBufferEntry<'_>) -> Result<()> {
+        self.write_entry_at(entry, Instant::now())
+    }
+
+    /// write data to the open chunk, as if at `now`
+    ///
+    /// The open chunk is also closed first if `chunk_sizing` is set and
+    /// the chunk has reached the size it sizes chunks to.
+    fn write_entry_at(&mut self, entry: &wb::WriteBufferEntry<'_>, now: Instant) -> Result<()> {
         assert_eq!(
             entry
                 .partition_key()
                 .expect("partition key should be present"),
             self.key
         );
+
+        let mut room = self.open_chunk.check_dictionary_room(entry);
+        let dictionary = &self.open_chunk.dictionary;
+        if !self.open_chunk.is_empty() && (dictionary.is_near_capacity() || room.is_err()) {
+            info!(
+                partition_key = self.key.as_str(),
+                chunk_id = self.open_chunk.id(),
+                dictionary_len = dictionary.len(),
+                dictionary_size = dictionary.size(),
+                "Closing chunk with dictionary near capacity"
+            );
+            self.dictionary_rollovers += 1;
+            self.rollover_chunk();
+            room = self.open_chunk.check_dictionary_room(entry);
+        } else if let Some(chunk_sizing) = &self.chunk_sizing {
+            let close_size = chunk_sizing.close_size(self.ingest_rate.bytes_per_second);
+            let size = self.open_chunk_size as u64;
+            if !self.open_chunk.is_empty() && size >= close_size {
+                info!(
+                    partition_key = self.key.as_str(),
+                    chunk_id = self.open_chunk.id(),
+                    size,
+                    close_size,
+                    "Closing chunk sized for the ingest rate of its partition"
+                );
+                self.size_rollovers += 1;
+                self.rollover_chunk();
+            }
+        }
+        room.context(WriteDoesNotFitDictionary {
+            partition_key: &self.key,
+        })?;
+
+        let written = self.write_open_chunk(entry);
+
+        let size = self.open_chunk.size();
+        let added = size.saturating_sub(self.open_chunk_size);
+        self.open_chunk_size = size;
+        if self.chunk_sizing.is_some() {
+            self.ingest_rate.record(added, now);
+        }
+        written
+    }
+
+    fn write_open_chunk(&mut self, entry: &wb::WriteBufferEntry<'_>) -> Result<()> {
         self.open_chunk
             .write_entry(entry)
             .with_context(|| WritingChunkData {
@@ -159,13 +321,24 @@ impl Partition {
     pub fn rollover_chunk(&mut self) -> Arc<Chunk> {
         let chunk_id = self.id_generator;
         self.id_generator += 1;
-        let mut chunk = Chunk::new(chunk_id);
+        let dictionary = Self::new_dictionary(self.dictionary_limits, self.string_pool.as_ref());
+        let mut chunk = Chunk::new_with_dictionary(chunk_id, dictionary);
         std::mem::swap(&mut chunk, &mut self.open_chunk);
+        self.open_chunk_size = 0;
         chunk.mark_closed();
         let chunk = Arc::new(chunk);
         if !chunk.is_empty() {
             let existing_value = self.closed_chunks.insert(chunk.id(), chunk.clone());
             assert!(existing_value.is_none());
+            self.closed_chunks_size += chunk.size();
+            if let Some(lifecycle) = &self.lifecycle {
+                lifecycle.record_now(
+                    &self.key,
+                    Some(chunk.id()),
+                    LifecycleEventKind::Closed,
+                    chunk.size() as u64,
+                );
+            }
         }
         chunk
     }
@@ -173,7 +346,12 @@ impl Partition {
     /// Drop the specified chunk for the partition, returning a reference to the
     /// chunk
     pub fn drop_chunk(&mut self, chunk_id: u32) -> Result<Arc<Chunk>> {
-        self.closed_chunks.remove(&chunk_id).ok_or_else(|| {
+        let dropped = self.closed_chunks.remove(&chunk_id);
+        if let Some(chunk) = &dropped {
+            self.closed_chunks_size -= chunk.size();
+        }
+
+        dropped.ok_or_else(|| {
             let partition_key = self.key.clone();
             if self.open_chunk.id() == chunk_id {
                 Error::DropOpenChunk {
@@ -191,18 +369,93 @@ impl Partition {
         })
     }
 
+    /// Returns the approximate memory used by the data of the chunks of
+    /// this partition, not including the inverted indexes of their tag
+    /// columns, in bytes
+    pub fn size(&self) -> usize {
+        self.closed_chunks_size + self.open_chunk_size
+    }
+
+    /// Returns true if none of the chunks of this partition hold data
+    pub fn is_empty(&self) -> bool {
+        self.closed_chunks.is_empty() && self.open_chunk.is_empty()
+    }
+
     /// Return the partition key shared by all data stored in this
     /// partition
     pub fn key(&self) -> &str {
         &self.key
     }
 
+    /// Return the number of times the open chunk of this partition
+    /// was closed because its dictionary was near capacity
+    pub fn dictionary_rollovers(&self) -> u64 {
+        self.dictionary_rollovers
+    }
+
+    /// Return the number of times the open chunk of this partition
+    /// was closed because it reached the size `chunk_sizing` sizes it to
+    pub fn size_rollovers(&self) -> u64 {
+        self.size_rollovers
+    }
+
+    /// Return how many bytes per second are written to this partition,
+    /// if `chunk_sizing` is set and enough writes have been seen
+    pub fn ingest_rate(&self) -> Option<f64> {
+        self.ingest_rate.bytes_per_second
+    }
+
     /// in Return an iterator over each Chunk in this partition
     pub fn iter(&self) -> ChunkIter<'_> {
         ChunkIter::new(self)
     }
 }
 
+/// An exponentially weighted moving average of the bytes written to a
+/// partition per second, sampled at most once a second so bursts of
+/// writes don't swing it
+#[derive(Debug, Clone, Copy)]
+struct IngestRate {
+    bytes_per_second: Option<f64>,
+    sample_start: Instant,
+    sample_bytes: usize,
+}
+
+impl IngestRate {
+    /// The shortest time a sample of the rate covers
+    const SAMPLE_SECONDS: f64 = 1.0;
+
+    /// The weight of each new sample in the average
+    const SAMPLE_WEIGHT: f64 = 0.3;
+
+    fn new(now: Instant) -> Self {
+        Self {
+            bytes_per_second: None,
+            sample_start: now,
+            sample_bytes: 0,
+        }
+    }
+
+    /// Records that `bytes` were written at `now`
+    fn record(&mut self, bytes: usize, now: Instant) {
+        self.sample_bytes += bytes;
+
+        let elapsed = now.saturating_duration_since(self.sample_start);
+        let elapsed = elapsed.as_secs_f64();
+        if elapsed < Self::SAMPLE_SECONDS {
+            return;
+        }
+
+        let rate = self.sample_bytes as f64 / elapsed;
+        self.bytes_per_second = Some(match self.bytes_per_second {
+            Some(average) => Self::SAMPLE_WEIGHT * rate + (1.0 - Self::SAMPLE_WEIGHT) * average,
+            None => rate,
+        });
+        self.sample_start = now;
+        self.sample_bytes = 0;
+    }
+}
+
 /// information on chunks for this partition
 #[derive(Debug, Default, PartialEq)]
 pub struct PartitionChunkInfo {
@@ -260,6 +513,7 @@ mod tests {
         arrow::record_batch::RecordBatch, assert_table_eq, test_util::sort_record_batch,
     };
     use influxdb_line_protocol::parse_lines;
+    use std::time::Duration;
 
     #[tokio::test]
     async fn test_rollover_chunk() {
@@ -783,6 +1037,244 @@ mod tests {
         assert_table_eq!(expected2, &dump_chunk_table(&chunk0_rollover, "h2o"));
     }
 
+    #[tokio::test]
+    async fn test_rollover_chunk_dictionary_near_capacity() {
+        let limits = DictionaryLimits {
+            max_entries: 10,
+            ..Default::default()
+        };
+        let mut partition = Partition::new_with_dictionary_limits("a_key", limits);
+
+        // h2o, state, MA, city, Boston, temp, time
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
+        assert_eq!(all_ids_with_data(&partition), vec![0]);
+
+        // CA, LA brings the dictionary near capacity
+        load_data(&mut partition, &["h2o,state=CA,city=LA temp=71.4 200"]).await;
+        assert_eq!(all_ids_with_data(&partition), vec![0]);
+        assert_eq!(partition.dictionary_rollovers(), 0);
+
+        // so the next write goes to a new chunk
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=72.4 300"]).await;
+        assert_eq!(all_ids_with_data(&partition), vec![0, 1]);
+        assert_eq!(partition.dictionary_rollovers(), 1);
+
+        let expected = &[
+            "+--------+-------+------+------+",
+            "| city   | state | temp | time |",
+            "+--------+-------+------+------+",
+            "| Boston | MA    | 70.4 | 100  |",
+            "| LA     | CA    | 71.4 | 200  |",
+            "| Boston | MA    | 72.4 | 300  |",
+            "+--------+-------+------+------+",
+        ];
+        assert_table_eq!(expected, &dump_table(&partition, "h2o"));
+    }
+
+    #[tokio::test]
+    async fn test_rollover_chunk_dictionary_without_room() {
+        let limits = DictionaryLimits {
+            max_entries: 10,
+            ..Default::default()
+        };
+        let mut partition = Partition::new_with_dictionary_limits("a_key", limits);
+
+        // h2o, state, MA, city, Boston, temp, time
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
+
+        // the write needs six more strings, so it goes to a new chunk even
+        // though the dictionary isn't near capacity yet
+        load_data(&mut partition, &["cpu,region=west,host=a usage=1 200"]).await;
+        assert_eq!(all_ids_with_data(&partition), vec![0, 1]);
+        assert_eq!(partition.dictionary_rollovers(), 1);
+
+        // a write that doesn't fit in a new dictionary either is rejected
+        // without writing any of its rows
+        let lines: Vec<_> = parse_lines("mem,a=1,b=2,c=3 free=1 300\nmem,d=4,e=5,f=6 free=2 400")
+            .map(|l| l.unwrap())
+            .collect();
+        let data = split_lines_into_write_entry_partitions(|_| partition.key().into(), &lines);
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+        let entry = batch.entries().unwrap().get(0);
+        let err = partition.write_entry(&entry).unwrap_err();
+        assert!(matches!(err, Error::WriteDoesNotFitDictionary { .. }));
+        assert_eq!(all_ids_with_data(&partition), vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_rollover_chunk_at_max_size() {
+        let sizing = ChunkSizing {
+            target_window_seconds: 60,
+            min_chunk_bytes: 1,
+            max_chunk_bytes: 1,
+        };
+        let mut partition = Partition::new("a_key").with_chunk_sizing(Some(sizing));
+
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
+        assert_eq!(all_ids_with_data(&partition), vec![0]);
+
+        // the rate isn't known yet, so the chunk is closed at the max size
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 200"]).await;
+        assert_eq!(all_ids_with_data(&partition), vec![0, 1]);
+        assert_eq!(partition.size_rollovers(), 1);
+        assert_eq!(partition.ingest_rate(), None);
+
+        // the size of the open chunk is kept up to date as it is written to
+        let chunks_size: usize = partition.iter().map(|chunk| chunk.size()).sum();
+        assert_eq!(partition.size(), chunks_size);
+    }
+
+    #[tokio::test]
+    async fn test_rollover_chunk_records_closed_chunks() {
+        let sizing = ChunkSizing {
+            target_window_seconds: 60,
+            min_chunk_bytes: 1,
+            max_chunk_bytes: 1,
+        };
+        let lifecycle = Arc::new(LifecycleLog::default());
+        let mut partition = Partition::new("a_key")
+            .with_chunk_sizing(Some(sizing))
+            .with_lifecycle_log(Some(Arc::clone(&lifecycle)));
+
+        // the chunk closed as it fills up is recorded like one closed on
+        // request, but empty chunks aren't
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
+        load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 200"]).await;
+        partition.rollover_chunk();
+        partition.rollover_chunk();
+
+        let events = lifecycle.events();
+        let closed: Vec<_> = events
+            .iter()
+            .map(|event| (event.partition_key.as_str(), event.chunk_id, event.kind))
+            .collect();
+        assert_eq!(
+            closed,
+            vec![
+                ("a_key", Some(0), LifecycleEventKind::Closed),
+                ("a_key", Some(1), LifecycleEventKind::Closed),
+            ]
+        );
+        assert!(events.iter().all(|event| event.size > 0));
+    }
+
+    #[tokio::test]
+    async fn test_rollover_chunk_sized_for_ingest_rate() {
+        let sizing = ChunkSizing {
+            target_window_seconds: 1,
+            min_chunk_bytes: 1,
+            max_chunk_bytes: u64::MAX,
+        };
+        let mut partition = Partition::new("a_key").with_chunk_sizing(Some(sizing));
+        let start = Instant::now();
+
+        load_data_at(
+            &mut partition,
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            start,
+        );
+        load_data_at(
+            &mut partition,
+            "h2o,state=MA,city=Boston temp=71.4 200",
+            start + Duration::from_secs(1),
+        );
+        assert!(partition.ingest_rate().is_some());
+        assert_eq!(all_ids_with_data(&partition), vec![0]);
+
+        // the chunk holds a second of writes at the rate so far
+        load_data_at(
+            &mut partition,
+            "h2o,state=MA,city=Boston temp=72.4 300",
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(all_ids_with_data(&partition), vec![0, 1]);
+        assert_eq!(partition.size_rollovers(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_kept_open_until_min_size() {
+        let sizing = ChunkSizing {
+            target_window_seconds: 1,
+            min_chunk_bytes: 1024 * 1024,
+            max_chunk_bytes: u64::MAX,
+        };
+        let mut partition = Partition::new("a_key").with_chunk_sizing(Some(sizing));
+        let start = Instant::now();
+
+        for i in 0..5 {
+            load_data_at(
+                &mut partition,
+                &format!("h2o,state=MA,city=Boston temp=70.4 {}", i),
+                start + Duration::from_secs(i),
+            );
+        }
+        assert!(partition.ingest_rate().is_some());
+        assert_eq!(all_ids_with_data(&partition), vec![0]);
+        assert_eq!(partition.size_rollovers(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_write_dictionary_full() {
+        let limits = DictionaryLimits {
+            max_entries: 5,
+            ..Default::default()
+        };
+        let mut partition = Partition::new_with_dictionary_limits("a_key", limits);
+
+        let lines: Vec<_> = parse_lines("h2o,state=MA,city=Boston temp=70.4 100")
+            .map(|l| l.unwrap())
+            .collect();
+        let data = split_lines_into_write_entry_partitions(|_| partition.key().into(), &lines);
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+        let entry = batch.entries().unwrap().get(0);
+
+        let err = partition.write_entry(&entry).unwrap_err();
+        assert!(
+            err.to_string().contains("Dictionary full"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shared_dictionaries() {
+        let string_pool = StringPool::new();
+        let mut partition1 = Partition::new_with_dictionary_config(
+            "a_key",
+            Default::default(),
+            Some(string_pool.clone()),
+        );
+        let mut partition2 = Partition::new_with_dictionary_config(
+            "b_key",
+            Default::default(),
+            Some(string_pool.clone()),
+        );
+
+        load_data(&mut partition1, &["h2o,state=MA temp=70.4 100"]).await;
+        // h2o, state, MA, temp, time
+        assert_eq!(string_pool.len(), 5);
+
+        // only the new values are added to the pool
+        load_data(&mut partition2, &["h2o,state=CA temp=71.4 200"]).await;
+        assert_eq!(string_pool.len(), 6);
+
+        let expected = &[
+            "+-------+------+------+",
+            "| state | temp | time |",
+            "+-------+------+------+",
+            "| CA    | 71.4 | 200  |",
+            "+-------+------+------+",
+        ];
+        assert_table_eq!(expected, &dump_table(&partition2, "h2o"));
+
+        // dropping the chunk makes its unshared values prunable
+        partition2.rollover_chunk();
+        partition2.drop_chunk(0).unwrap();
+        drop(partition2);
+        assert_eq!(string_pool.prune(), 1);
+        assert_eq!(string_pool.len(), 5);
+    }
+
     fn row_count(table_name: &str, chunk: &Chunk) -> u32 {
         let stats = chunk.table_stats().unwrap();
         for s in &stats {
@@ -813,6 +1305,16 @@ mod tests {
         }
     }
 
+    fn load_data_at(partition: &mut Partition, lp_data: &str, now: Instant) {
+        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        let data = split_lines_into_write_entry_partitions(|_| partition.key().into(), &lines);
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+        for entry in batch.entries().unwrap() {
+            partition.write_entry_at(&entry, now).unwrap()
+        }
+    }
+
     fn dump_table(partition: &Partition, table_name: &str) -> Vec<RecordBatch> {
         let mut dst = vec![];
         let requested_columns = []; // empty ==> request all columns
@@ -1,23 +1,30 @@
 use generated_types::wal as wb;
 use query::{
-    exec::{field::FieldColumns, make_schema_pivot, SeriesSetPlan},
+    exec::{field::FieldColumns, make_schema_pivot, series_key::series_key, SeriesSetPlan},
     func::selectors::{selector_first, selector_last, selector_max, selector_min, SelectorOutput},
     func::window::make_window_bound_expr,
     group_by::{Aggregate, WindowDuration},
 };
 use tracing::debug;
 
-use std::{collections::BTreeSet, collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
 
 use crate::{
+    bitmap::Bitmap,
     chunk::ChunkIdSet,
     chunk::{Chunk, ChunkPredicate},
     column,
     column::Column,
     dictionary::{Dictionary, Error as DictionaryError},
+    tag_index::{LazyTagIndex, TagIndex},
+    time_index::TimeIndex,
 };
 use data_types::{
-    partition_metadata::Column as ColumnStats, schema::builder::SchemaBuilder, TIME_COLUMN_NAME,
+    database_rules::TagOrders, partition_metadata::Column as ColumnStats,
+    schema::builder::SchemaBuilder, TIME_COLUMN_NAME,
 };
 use snafu::{OptionExt, ResultExt, Snafu};
 
@@ -25,7 +32,7 @@ use arrow_deps::{
     arrow,
     arrow::{
         array::{ArrayRef, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder},
-        datatypes::DataType as ArrowDataType,
+        datatypes::{DataType as ArrowDataType, Schema},
         record_batch::RecordBatch,
     },
     datafusion::{
@@ -50,18 +57,6 @@ pub enum Error {
         source: column::Error,
     },
 
-    #[snafu(display(
-        "Internal error: Expected column {} to be type {} but was {}",
-        column_id,
-        expected_column_type,
-        actual_column_type
-    ))]
-    InternalColumnTypeMismatch {
-        column_id: u32,
-        expected_column_type: String,
-        actual_column_type: String,
-    },
-
     #[snafu(display("Internal error: unexpected aggregate request for None aggregate",))]
     InternalUnexpectedNoneAggregate {},
 
@@ -110,6 +105,23 @@ pub enum Error {
     ))]
     InternalNoColumnInIndex { column_name: String, column_id: u32 },
 
+    #[snafu(display(
+        "Error inserting column name '{}' into dictionary: {}",
+        column_name,
+        source
+    ))]
+    InsertingColumnName {
+        column_name: String,
+        source: DictionaryError,
+    },
+
+    #[snafu(display(
+        "The names and tag values of a row don't fit in the dictionary of table {}: {}",
+        table,
+        source
+    ))]
+    RowDoesNotFitDictionary { table: u32, source: DictionaryError },
+
     #[snafu(display("Error creating column from wal for column {}: {}", column, source))]
     CreatingFromWal {
         column: u32,
@@ -154,6 +166,21 @@ pub struct Table {
 
     /// Actual column storage
     pub columns: Vec<Column>,
+
+    /// The id of the time column, once it has been written
+    time_column_id: Option<u32>,
+
+    /// Index of the values in the time column, used to prune rows
+    /// that can not match timestamp predicates
+    time_index: TimeIndex,
+
+    /// Inverted index of the tag columns, built on demand and extended
+    /// with new rows to evaluate equality predicates on tags
+    tag_index: LazyTagIndex,
+
+    /// The total length of the string field values written, so `size`
+    /// doesn't have to read them
+    string_bytes: usize,
 }
 
 type ArcStringVec = Vec<Arc<String>>;
@@ -164,6 +191,10 @@ impl Table {
             id,
             column_id_to_index: HashMap::new(),
             columns: Vec::new(),
+            time_column_id: None,
+            time_index: TimeIndex::new(),
+            tag_index: LazyTagIndex::default(),
+            string_bytes: 0,
         }
     }
 
@@ -173,13 +204,53 @@ impl Table {
         values: &flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<wb::Value<'_>>>,
     ) -> Result<()> {
         let row_count = self.row_count();
+        let mut time = None;
+
+        // Check the whole row before changing any column, so a row that
+        // can't be written leaves all of the columns the same length
+        let mut strings = Vec::with_capacity(values.len() * 2);
+        for value in values {
+            let column_name = value
+                .column()
+                .context(ColumnNameNotInRow { table: self.id })?;
+            strings.push(column_name);
+            if let Some(tag) = value.value_as_tag_value() {
+                strings.push(tag.value().expect("tag must have string value"));
+            }
+
+            let column = dictionary
+                .id(column_name)
+                .and_then(|column_id| self.column_id_to_index.get(&column_id))
+                .map(|&idx| &self.columns[idx]);
+            match column {
+                Some(column) => column.check_type(&value),
+                None => Column::check_new_type(&value),
+            }
+            .context(ColumnError {
+                column: column_name,
+            })?;
+        }
+        dictionary
+            .check_room(strings)
+            .context(RowDoesNotFitDictionary { table: self.id })?;
+        let string_bytes: usize = values
+            .iter()
+            .filter_map(|value| value.value_as_string_value()?.value())
+            .map(str::len)
+            .sum();
 
-        // insert new columns and validate existing ones
+        // insert new columns and push the values of existing ones
         for value in values {
             let column_name = value
                 .column()
                 .context(ColumnNameNotInRow { table: self.id })?;
-            let column_id = dictionary.lookup_value_or_insert(column_name);
+            let column_id = dictionary
+                .lookup_value_or_insert(column_name)
+                .context(InsertingColumnName { column_name })?;
+
+            if column_name == TIME_COLUMN_NAME {
+                time = value.value_as_i64value().map(|v| (column_id, v.value()));
+            }
 
             let column = match self.column_id_to_index.get(&column_id) {
                 Some(idx) => &mut self.columns[*idx],
@@ -205,6 +276,12 @@ impl Table {
         for col in &mut self.columns {
             col.push_none_if_len_equal(row_count);
         }
+        self.string_bytes += string_bytes;
+
+        if let Some((time_column_id, time)) = time {
+            self.time_column_id = Some(time_column_id);
+            self.time_index.insert(row_count, time);
+        }
 
         Ok(())
     }
@@ -213,6 +290,172 @@ impl Table {
         self.columns.first().map_or(0, |v| v.len())
     }
 
+    /// Returns the smallest and largest timestamps of the rows of the
+    /// table, if every row has a timestamp
+    pub fn time_range(&self) -> Option<(i64, i64)> {
+        let column = self.column(self.time_column_id?).ok()?;
+        match column {
+            Column::I64(_, stats) if stats.count as usize == self.row_count() => {
+                Some((stats.min, stats.max))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the distinct values of each tag column, by column name.
+    /// The distinct value ids are taken from the inverted index of the
+    /// tag columns, which only reads the rows written since its last use.
+    pub fn all_tag_values(&self, chunk: &Chunk) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let row_count = self.row_count();
+        let columns = self
+            .column_id_to_index
+            .iter()
+            .map(|(&column_id, &column_index)| (column_id, &self.columns[column_index]));
+        self.tag_index
+            .with_index(row_count, columns, |index| self.tag_values(chunk, index))
+    }
+
+    /// Returns the distinct values of the tag column `column` that start
+    /// with `prefix`, or `None` if the table has no such tag column. The
+    /// values that don't match are compared in place, without copying
+    /// them out of the dictionary.
+    pub fn tag_values_with_prefix(
+        &self,
+        chunk: &Chunk,
+        column: &str,
+        prefix: &str,
+    ) -> Result<Option<BTreeSet<String>>> {
+        let column_id = match chunk.dictionary.id(column) {
+            Some(column_id) => column_id,
+            None => return Ok(None),
+        };
+        match self.column_id_to_index.get(&column_id) {
+            Some(&column_index) if matches!(self.columns[column_index], Column::Tag(_, _)) => {}
+            _ => return Ok(None),
+        }
+
+        let row_count = self.row_count();
+        let columns = self
+            .column_id_to_index
+            .iter()
+            .map(|(&column_id, &column_index)| (column_id, &self.columns[column_index]));
+        self.tag_index.with_index(row_count, columns, |index| {
+            index
+                .value_ids(column_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|value_id| {
+                    match chunk.dictionary.lookup_id(value_id).context(
+                        TagValueIdNotFoundInDictionary {
+                            value: value_id,
+                            chunk: chunk.id,
+                        },
+                    ) {
+                        Ok(value) if !value.starts_with(prefix) => None,
+                        value => Some(value.map(ToString::to_string)),
+                    }
+                })
+                .collect::<Result<_>>()
+                .map(Some)
+        })
+    }
+
+    fn tag_values(
+        &self,
+        chunk: &Chunk,
+        index: &TagIndex,
+    ) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let mut tag_values = BTreeMap::new();
+
+        for (&column_id, &column_index) in &self.column_id_to_index {
+            if let Column::Tag(_, _) = &self.columns[column_index] {
+                let column_name = chunk.dictionary.lookup_id(column_id).context(
+                    ColumnIdNotFoundInDictionary {
+                        column_id,
+                        chunk: chunk.id,
+                    },
+                )?;
+
+                let values = index
+                    .value_ids(column_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|value_id| {
+                        let value = chunk.dictionary.lookup_id(value_id).context(
+                            TagValueIdNotFoundInDictionary {
+                                value: value_id,
+                                chunk: chunk.id,
+                            },
+                        )?;
+                        Ok(value.to_string())
+                    })
+                    .collect::<Result<_>>()?;
+
+                tag_values.insert(column_name.to_string(), values);
+            }
+        }
+
+        Ok(tag_values)
+    }
+
+    /// Returns the series key of each distinct combination of tag values
+    /// of the rows of the table. The combinations are found by their
+    /// dictionary ids, so each series key is only looked up and built
+    /// once.
+    pub fn series_keys(&self, chunk: &Chunk) -> Result<BTreeSet<String>> {
+        let mut tags = self
+            .column_id_to_index
+            .iter()
+            .filter_map(
+                |(&column_id, &column_index)| match &self.columns[column_index] {
+                    Column::Tag(values, _) => Some((column_id, values)),
+                    _ => None,
+                },
+            )
+            .map(|(column_id, values)| {
+                let column_name = chunk.dictionary.lookup_id(column_id).context(
+                    ColumnIdNotFoundInDictionary {
+                        column_id,
+                        chunk: chunk.id,
+                    },
+                )?;
+                Ok((column_name, values))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        tags.sort_by_key(|(column_name, _)| *column_name);
+
+        let series: HashSet<Vec<Option<u32>>> = (0..self.row_count())
+            .map(|row| {
+                tags.iter()
+                    .map(|(_, values)| values.get(row).copied())
+                    .collect()
+            })
+            .collect();
+
+        series
+            .into_iter()
+            .map(|value_ids| {
+                let values = value_ids
+                    .into_iter()
+                    .map(|value_id| {
+                        value_id
+                            .map(|value_id| {
+                                chunk.dictionary.lookup_id(value_id).context(
+                                    TagValueIdNotFoundInDictionary {
+                                        value: value_id,
+                                        chunk: chunk.id,
+                                    },
+                                )
+                            })
+                            .transpose()
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let column_names = tags.iter().map(|(column_name, _)| *column_name);
+                Ok(series_key(column_names.zip(values)))
+            })
+            .collect()
+    }
+
     /// Returns a reference to the specified column
     fn column(&self, column_id: u32) -> Result<&Column> {
         Ok(self
@@ -222,21 +465,6 @@ impl Table {
             .expect("invalid column id"))
     }
 
-    /// Returns a reference to the specified column as a slice of
-    /// i64s. Errors if the type is not i64
-    pub fn column_i64(&self, column_id: u32) -> Result<&[Option<i64>]> {
-        let column = self.column(column_id)?;
-        match column {
-            Column::I64(vals, _) => Ok(vals),
-            _ => InternalColumnTypeMismatch {
-                column_id,
-                expected_column_type: "i64",
-                actual_column_type: column.type_description(),
-            }
-            .fail(),
-        }
-    }
-
     pub fn append_rows(
         &mut self,
         dictionary: &mut Dictionary,
@@ -252,12 +480,16 @@ impl Table {
     }
 
     /// Creates and adds a datafuson filtering expression, if any out of the
-    /// combination of predicate and timestamp. Returns the builder
+    /// combination of predicate and timestamp. Returns the builder.
+    ///
+    /// Columns the predicate references that are not in `schema` (the
+    /// schema of the scan) are null for every row.
     fn add_datafusion_predicate(
         plan_builder: LogicalPlanBuilder,
+        schema: &Schema,
         chunk_predicate: &ChunkPredicate,
     ) -> Result<LogicalPlanBuilder> {
-        match chunk_predicate.filter_expr() {
+        match chunk_predicate.filter_expr(|name| schema.field_with_name(name).is_ok()) {
             Some(df_predicate) => plan_builder.filter(df_predicate).context(BuildingPlan),
             None => Ok(plan_builder),
         }
@@ -311,10 +543,11 @@ impl Table {
 
         let projection = None;
 
-        let plan_builder = LogicalPlanBuilder::scan_memory(vec![vec![data]], schema, projection)
-            .context(BuildingPlan)?;
+        let plan_builder =
+            LogicalPlanBuilder::scan_memory(vec![vec![data]], Arc::clone(&schema), projection)
+                .context(BuildingPlan)?;
 
-        let plan_builder = Self::add_datafusion_predicate(plan_builder, chunk_predicate)?;
+        let plan_builder = Self::add_datafusion_predicate(plan_builder, &schema, chunk_predicate)?;
 
         // add optional selection to remove time column
         let plan_builder = if !need_time_column {
@@ -381,7 +614,8 @@ impl Table {
     /// The output looks like:
     /// (tag_col1, tag_col2, ... field1, field2, ... timestamp)
     ///
-    /// The order of the tag_columns is orderd by name.
+    /// The tag_columns are in the order of the series keys of the table
+    /// set by `tag_orders`, by name unless set.
     ///
     /// The data is sorted on tag_col1, tag_col2, ...) so that all
     /// rows for a particular series (groups where all tags are the
@@ -389,9 +623,10 @@ impl Table {
     pub fn series_set_plan(
         &self,
         chunk_predicate: &ChunkPredicate,
+        tag_orders: &TagOrders,
         chunk: &Chunk,
     ) -> Result<SeriesSetPlan> {
-        self.series_set_plan_impl(chunk_predicate, None, chunk)
+        self.series_set_plan_impl(chunk_predicate, None, tag_orders, chunk)
     }
 
     /// Creates the plans for computing series set, ensuring that
@@ -407,10 +642,11 @@ impl Table {
         &self,
         chunk_predicate: &ChunkPredicate,
         prefix_columns: Option<&[String]>,
+        tag_orders: &TagOrders,
         chunk: &Chunk,
     ) -> Result<SeriesSetPlan> {
         let (mut tag_columns, field_columns) =
-            self.tag_and_field_column_names(chunk_predicate, chunk)?;
+            self.tag_and_field_column_names(chunk_predicate, tag_orders, chunk)?;
 
         // reorder tag_columns to have the prefix columns, if requested
         if let Some(prefix_columns) = prefix_columns {
@@ -467,11 +703,12 @@ impl Table {
         let projection = None;
 
         // And build the plan from the bottom up
-        let plan_builder = LogicalPlanBuilder::scan_memory(vec![vec![data]], schema, projection)
-            .context(BuildingPlan)?;
+        let plan_builder =
+            LogicalPlanBuilder::scan_memory(vec![vec![data]], Arc::clone(&schema), projection)
+                .context(BuildingPlan)?;
 
         // Filtering
-        Self::add_datafusion_predicate(plan_builder, chunk_predicate)
+        Self::add_datafusion_predicate(plan_builder, &schema, chunk_predicate)
     }
 
     /// Look up this table's name as a string
@@ -494,14 +731,15 @@ impl Table {
         chunk_predicate: &ChunkPredicate,
         agg: Aggregate,
         group_columns: &[String],
+        tag_orders: &TagOrders,
         chunk: &Chunk,
     ) -> Result<SeriesSetPlan> {
         let num_prefix_tag_group_columns = group_columns.len();
 
         let plan = if let Aggregate::None = agg {
-            self.series_set_plan_impl(chunk_predicate, Some(&group_columns), chunk)?
+            self.series_set_plan_impl(chunk_predicate, Some(&group_columns), tag_orders, chunk)?
         } else {
-            self.aggregate_series_set_plan(chunk_predicate, agg, group_columns, chunk)?
+            self.aggregate_series_set_plan(chunk_predicate, agg, group_columns, tag_orders, chunk)?
         };
 
         Ok(plan.grouped(num_prefix_tag_group_columns))
@@ -550,10 +788,11 @@ impl Table {
         chunk_predicate: &ChunkPredicate,
         agg: Aggregate,
         group_columns: &[String],
+        tag_orders: &TagOrders,
         chunk: &Chunk,
     ) -> Result<SeriesSetPlan> {
         let (tag_columns, field_columns) =
-            self.tag_and_field_column_names(chunk_predicate, chunk)?;
+            self.tag_and_field_column_names(chunk_predicate, tag_orders, chunk)?;
 
         // order the tag columns so that the group keys come first (we will group and
         // order in the same order)
@@ -632,10 +871,11 @@ impl Table {
         agg: Aggregate,
         every: &WindowDuration,
         offset: &WindowDuration,
+        tag_orders: &TagOrders,
         chunk: &Chunk,
     ) -> Result<SeriesSetPlan> {
         let (tag_columns, field_columns) =
-            self.tag_and_field_column_names(chunk_predicate, chunk)?;
+            self.tag_and_field_column_names(chunk_predicate, tag_orders, chunk)?;
 
         // Scan and Filter
         let plan_builder = self.scan_with_predicates(chunk_predicate, chunk)?;
@@ -714,10 +954,13 @@ impl Table {
 
     // Returns (tag_columns, field_columns) vectors with the names of
     // all tag and field columns, respectively, after any predicates
-    // have been applied. The vectors are sorted by lexically by name.
+    // have been applied. The tag columns are in the order of the series
+    // keys of the table, and the field columns are sorted lexically by
+    // name.
     fn tag_and_field_column_names(
         &self,
         chunk_predicate: &ChunkPredicate,
+        tag_orders: &TagOrders,
         chunk: &Chunk,
     ) -> Result<(ArcStringVec, ArcStringVec)> {
         let mut tag_columns = Vec::with_capacity(self.column_id_to_index.len());
@@ -743,10 +986,15 @@ impl Table {
             }
         }
 
-        // tag columns are always sorted by name (aka sorted by tag
-        // key) in the output schema, so ensure the columns are sorted
-        // (the select exprs)
-        tag_columns.sort();
+        // tag columns are always sorted in the order of the series keys of
+        // the table (by tag key unless configured) in the output schema,
+        // so ensure the columns are sorted (the select exprs)
+        let table_name = self.table_name(chunk);
+        tag_columns.sort_by(|a, b| {
+            tag_orders
+                .sort_key(&table_name, a)
+                .cmp(&tag_orders.sort_key(&table_name, b))
+        });
 
         // Sort the field columns too so that the output always comes
         // out in a predictable order
@@ -878,7 +1126,7 @@ impl Table {
                     schema_builder = schema_builder.field(column_name, ArrowDataType::Utf8);
                     let mut builder = StringBuilder::with_capacity(vals.len(), vals.len() * 10);
 
-                    for v in vals {
+                    for v in vals.iter() {
                         match v {
                             None => builder.append_null(),
                             Some(s) => builder.append_value(s),
@@ -892,7 +1140,7 @@ impl Table {
                     schema_builder = schema_builder.tag(column_name);
                     let mut builder = StringBuilder::with_capacity(vals.len(), vals.len() * 10);
 
-                    for v in vals {
+                    for v in vals.iter() {
                         match v {
                             None => builder.append_null(),
                             Some(value_id) => {
@@ -914,8 +1162,8 @@ impl Table {
                     schema_builder = schema_builder.field(column_name, ArrowDataType::Float64);
                     let mut builder = Float64Builder::new(vals.len());
 
-                    for v in vals {
-                        builder.append_option(*v).context(ArrowError {})?;
+                    for v in vals.iter() {
+                        builder.append_option(v.copied()).context(ArrowError {})?;
                     }
 
                     Arc::new(builder.finish())
@@ -928,8 +1176,8 @@ impl Table {
                     };
                     let mut builder = Int64Builder::new(vals.len());
 
-                    for v in vals {
-                        builder.append_option(*v).context(ArrowError {})?;
+                    for v in vals.iter() {
+                        builder.append_option(v.copied()).context(ArrowError {})?;
                     }
 
                     Arc::new(builder.finish())
@@ -938,8 +1186,8 @@ impl Table {
                     schema_builder = schema_builder.field(column_name, ArrowDataType::Boolean);
                     let mut builder = BooleanBuilder::new(vals.len());
 
-                    for v in vals {
-                        builder.append_option(*v).context(ArrowError {})?;
+                    for v in vals.iter() {
+                        builder.append_option(v).context(ArrowError {})?;
                     }
 
                     Arc::new(builder.finish())
@@ -997,6 +1245,10 @@ impl Table {
             None => Ok(true),
             Some(range) => {
                 let time_column_id = chunk_predicate.time_column_id;
+                if self.time_column_id == Some(time_column_id) {
+                    return Ok(self.time_index.could_contain(range.start, range.end));
+                }
+
                 let time_column = self.column(time_column_id)?;
                 time_column.has_i64_range(range.start, range.end).context(
                     ColumnPredicateEvaluation {
@@ -1025,27 +1277,97 @@ impl Table {
         true
     }
 
-    /// returns true if there are any rows in column that are non-null
-    /// and within the timestamp range specified by pred
-    pub fn column_matches_predicate<T>(
-        &self,
-        column: &[Option<T>],
-        chunk_predicate: &ChunkPredicate,
-    ) -> Result<bool> {
+    /// Returns a bitmap with a bit set for each row of this table
+    /// whose timestamp is within the range of `chunk_predicate`, or
+    /// None if the predicate has no timestamp range.
+    ///
+    /// The bitmap can be reused to evaluate the range against all
+    /// columns of this table (see `column_matches_selection`)
+    pub fn time_range_selection(&self, chunk_predicate: &ChunkPredicate) -> Result<Option<Bitmap>> {
         match chunk_predicate.range {
-            None => Ok(true),
+            None => Ok(None),
             Some(range) => {
                 let time_column_id = chunk_predicate.time_column_id;
                 let time_column = self.column(time_column_id)?;
-                time_column
-                    .has_non_null_i64_range(column, range.start, range.end)
-                    .context(ColumnPredicateEvaluation {
-                        column: time_column_id,
-                    })
+
+                // only compare the values in rows the time index
+                // can't rule out
+                let selection = if self.time_column_id == Some(time_column_id) {
+                    let candidate_rows = self.time_index.candidate_rows(range.start, range.end);
+                    time_column.i64_range_selection_in_rows(range.start, range.end, candidate_rows)
+                } else {
+                    time_column.i64_range_selection(range.start, range.end)
+                };
+
+                selection.map(Some).context(ColumnPredicateEvaluation {
+                    column: time_column_id,
+                })
             }
         }
     }
 
+    /// Returns the rows of this table that match all the equality
+    /// predicates on tag columns of this table in `chunk_predicate`,
+    /// using (and building or extending, if needed) the inverted index of
+    /// the tag columns. Returns None if there are no such predicates.
+    pub fn tag_equality_selection(&self, chunk_predicate: &ChunkPredicate) -> Option<Bitmap> {
+        let mut equalities = chunk_predicate
+            .tag_equalities
+            .iter()
+            .filter(|(column_id, _)| {
+                self.column_id_to_index
+                    .get(column_id)
+                    .map_or(false, |&column_index| self.columns[column_index].is_tag())
+            })
+            .peekable();
+
+        // don't build the index unless it is needed
+        equalities.peek()?;
+
+        let row_count = self.row_count();
+        let columns = self
+            .column_id_to_index
+            .iter()
+            .map(|(&column_id, &column_index)| (column_id, &self.columns[column_index]));
+        self.tag_index.with_index(row_count, columns, |index| {
+            let mut selection: Option<Bitmap> = None;
+            for &(column_id, value_id) in equalities {
+                let rows = value_id
+                    .and_then(|value_id| index.rows(column_id, value_id))
+                    .unwrap_or_else(|| Bitmap::new_unset(row_count));
+
+                match &mut selection {
+                    Some(selection) => selection.and_assign(&rows),
+                    None => selection = Some(rows),
+                }
+            }
+            selection
+        })
+    }
+
+    /// Returns the approximate memory used by the values of the columns
+    /// of this table, in bytes
+    pub fn size(&self) -> usize {
+        let columns: usize = self.columns.iter().map(|column| column.size()).sum();
+        columns + self.string_bytes
+    }
+
+    /// Returns the approximate memory used by the inverted index of
+    /// the tag columns, in bytes, or 0 if it has not been built
+    pub fn tag_index_size(&self) -> usize {
+        self.tag_index.size()
+    }
+
+    /// returns true if there are any rows in column that are non-null
+    /// and selected by `selection`, as returned by
+    /// `time_range_selection`. All rows are selected if `selection` is None
+    pub fn column_matches_selection(column: &Column, selection: Option<&Bitmap>) -> bool {
+        match selection {
+            None => true,
+            Some(selection) => column.has_non_null_selected(selection),
+        }
+    }
+
     pub fn stats(&self) -> Vec<ColumnStats> {
         self.columns
             .iter()
@@ -1284,11 +1606,64 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_rejected_row_leaves_columns_aligned() {
+        let mut chunk = Chunk::new_with_dictionary(
+            42,
+            Dictionary::new_with_limits(crate::dictionary::DictionaryLimits {
+                max_entries: 7,
+                ..Default::default()
+            }),
+        );
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o").unwrap());
+        write_lines_to_table(&mut table, dictionary, vec!["h2o,state=MA temp=70.4 100"]);
+
+        let append = |table: &mut Table, dictionary: &mut Dictionary, lp: &str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let data = split_lines_into_write_entry_partitions(chunk_key_func, &lines);
+            let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+            let entry = batch.entries().unwrap().get(0);
+            let rows = entry.table_batches().unwrap().get(0).rows().unwrap();
+            table.append_rows(dictionary, &rows)
+        };
+
+        // the type of the last value is wrong
+        let err = append(
+            &mut table,
+            dictionary,
+            "h2o,state=CA,city=LA temp=\"hot\" 200",
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::ColumnError { .. }), "{}", err);
+
+        // the tag values don't fit in the dictionary
+        let err = append(
+            &mut table,
+            dictionary,
+            "h2o,state=WA,city=Seattle temp=71.0 300",
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, Error::RowDoesNotFitDictionary { .. }),
+            "{}",
+            err
+        );
+
+        assert_eq!(table.row_count(), 1);
+        assert!(table.columns.iter().all(|column| column.len() == 1));
+        assert!(dictionary.id("city").is_none());
+
+        append(&mut table, dictionary, "h2o,state=CA temp=71.4 200").unwrap();
+        assert_eq!(table.row_count(), 2);
+        assert!(table.columns.iter().all(|column| column.len() == 2));
+    }
+
     #[test]
     fn test_has_columns() {
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.4 100",
@@ -1298,7 +1673,7 @@ mod tests {
         write_lines_to_table(&mut table, dictionary, lp_lines);
 
         let state_symbol = dictionary.id("state").unwrap();
-        let new_symbol = dictionary.lookup_value_or_insert("not_a_columns");
+        let new_symbol = dictionary.lookup_value_or_insert("not_a_columns").unwrap();
 
         assert!(table.has_columns(None));
 
@@ -1326,11 +1701,100 @@ mod tests {
         assert!(!table.has_columns(Some(&pred)));
     }
 
+    #[test]
+    fn test_all_tag_values() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=MA,city=Cambridge temp=72.4 250",
+            "h2o,state=CA temp=80.0 300",
+        ];
+
+        write_lines_to_table(&mut table, dictionary, lp_lines);
+
+        let tag_values = table.all_tag_values(&chunk).unwrap();
+        let expected: BTreeMap<_, BTreeSet<_>> = vec![
+            ("city", vec!["Boston", "Cambridge"]),
+            ("state", vec!["CA", "MA"]),
+        ]
+        .into_iter()
+        .map(|(tag, values)| {
+            let values = values.into_iter().map(str::to_string).collect();
+            (tag.to_string(), values)
+        })
+        .collect();
+        assert_eq!(tag_values, expected);
+    }
+
+    #[test]
+    fn test_tag_values_with_prefix() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=MA,city=Cambridge temp=72.4 250",
+            "h2o,state=CA,city=Berkeley temp=80.0 300",
+        ];
+
+        write_lines_to_table(&mut table, dictionary, lp_lines);
+
+        let values = table
+            .tag_values_with_prefix(&chunk, "city", "B")
+            .unwrap()
+            .unwrap();
+        let expected: BTreeSet<_> = vec!["Berkeley", "Boston"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(values, expected);
+
+        // fields and unknown columns aren't tag columns
+        assert!(table
+            .tag_values_with_prefix(&chunk, "temp", "")
+            .unwrap()
+            .is_none());
+        assert!(table
+            .tag_values_with_prefix(&chunk, "zone", "")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_series_keys() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=MA,city=Boston temp=71.4 200",
+            r"h2o,state=MA,city=New\ York temp=72.4 250",
+            "h2o,state=CA temp=80.0 300",
+        ];
+
+        write_lines_to_table(&mut table, dictionary, lp_lines);
+
+        let series_keys: Vec<_> = table.series_keys(&chunk).unwrap().into_iter().collect();
+        assert_eq!(
+            series_keys,
+            vec![
+                "city=Boston,state=MA",
+                r"city=New\ York,state=MA",
+                "state=CA",
+            ]
+        );
+    }
+
     #[test]
     fn test_matches_table_name_predicate() {
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o").unwrap());
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.4 100",
@@ -1360,7 +1824,7 @@ mod tests {
     fn test_matches_column_name_predicate() {
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o").unwrap());
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.4,awesomeness=1000 100",
@@ -1406,7 +1870,7 @@ mod tests {
     async fn test_series_set_plan() {
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.4 100",
@@ -1420,7 +1884,7 @@ mod tests {
         let predicate = PredicateBuilder::default().build();
         let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
         let series_set_plan = table
-            .series_set_plan(&chunk_predicate, &chunk)
+            .series_set_plan(&chunk_predicate, &TagOrders::default(), &chunk)
             .expect("creating the series set plan");
 
         assert_eq!(series_set_plan.table_name.as_ref(), "table_name");
@@ -1453,7 +1917,7 @@ mod tests {
 
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
         let lp_lines = vec![
             "h2o,zz_tag=A,state=MA,city=Kingston temp=70.1 800",
@@ -1468,7 +1932,7 @@ mod tests {
         let predicate = PredicateBuilder::default().build();
         let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
         let series_set_plan = table
-            .series_set_plan(&chunk_predicate, &chunk)
+            .series_set_plan(&chunk_predicate, &TagOrders::default(), &chunk)
             .expect("creating the series set plan");
 
         assert_eq!(series_set_plan.table_name.as_ref(), "table_name");
@@ -1496,13 +1960,61 @@ mod tests {
         assert_eq!(expected, results, "expected output");
     }
 
+    #[tokio::test]
+    async fn test_series_set_plan_tag_order() {
+        // test that the tags come out in the order configured for the table
+
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
+
+        let lp_lines = vec![
+            "h2o,zz_tag=A,state=MA,city=Kingston temp=70.1 800",
+            "h2o,state=MA,city=Kingston,zz_tag=B temp=70.2 100",
+            "h2o,state=CA,city=Boston temp=70.3 250",
+            "h2o,state=MA,city=Boston,zz_tag=A temp=70.4 1000",
+        ];
+
+        write_lines_to_table(&mut table, dictionary, lp_lines);
+
+        let mut tag_orders = TagOrders::default();
+        tag_orders
+            .tables
+            .insert("table_name".to_string(), vec!["state".to_string()]);
+        let predicate = PredicateBuilder::default().build();
+        let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
+        let series_set_plan = table
+            .series_set_plan(&chunk_predicate, &tag_orders, &chunk)
+            .expect("creating the series set plan");
+
+        assert_eq!(
+            series_set_plan.tag_columns,
+            *str_vec_to_arc_vec(&["state", "city", "zz_tag"])
+        );
+
+        let results = run_plan(series_set_plan.plan).await;
+
+        let expected = vec![
+            "+-------+----------+--------+------+------+",
+            "| state | city     | zz_tag | temp | time |",
+            "+-------+----------+--------+------+------+",
+            "| CA    | Boston   |        | 70.3 | 250  |",
+            "| MA    | Boston   | A      | 70.4 | 1000 |",
+            "| MA    | Kingston | A      | 70.1 | 800  |",
+            "| MA    | Kingston | B      | 70.2 | 100  |",
+            "+-------+----------+--------+------+------+",
+        ];
+
+        assert_eq!(expected, results, "expected output");
+    }
+
     #[tokio::test]
     async fn test_series_set_plan_filter() {
         // test that filters are applied reasonably
 
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.4 100",
@@ -1521,7 +2033,7 @@ mod tests {
         let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
 
         let series_set_plan = table
-            .series_set_plan(&chunk_predicate, &chunk)
+            .series_set_plan(&chunk_predicate, &TagOrders::default(), &chunk)
             .expect("creating the series set plan");
 
         assert_eq!(series_set_plan.table_name.as_ref(), "table_name");
@@ -1891,7 +2403,7 @@ mod tests {
     async fn test_grouped_window_series_set_plan_nanoseconds() {
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.0 100",
@@ -1925,7 +2437,14 @@ mod tests {
         let offset = WindowDuration::from_nanoseconds(0);
 
         let plan = table
-            .window_grouped_series_set_plan(&chunk_predicate, agg, &every, &offset, &chunk)
+            .window_grouped_series_set_plan(
+                &chunk_predicate,
+                agg,
+                &every,
+                &offset,
+                &TagOrders::default(),
+                &chunk,
+            )
             .expect("creating the grouped_series set plan");
 
         assert_eq!(plan.tag_columns, *str_vec_to_arc_vec(&["city", "state"]));
@@ -1955,7 +2474,7 @@ mod tests {
     async fn test_grouped_window_series_set_plan_months() {
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
         let lp_lines = vec![
             "h2o,state=MA,city=Boston temp=70.0 1583020800000000000", // 2020-03-01T00:00:00Z
@@ -1974,7 +2493,14 @@ mod tests {
         let offset = WindowDuration::from_months(0, false);
 
         let plan = table
-            .window_grouped_series_set_plan(&chunk_predicate, agg, &every, &offset, &chunk)
+            .window_grouped_series_set_plan(
+                &chunk_predicate,
+                agg,
+                &every,
+                &offset,
+                &TagOrders::default(),
+                &chunk,
+            )
             .expect("creating the grouped_series set plan");
 
         assert_eq!(plan.tag_columns, *str_vec_to_arc_vec(&["city", "state"]));
@@ -2000,7 +2526,7 @@ mod tests {
     async fn test_field_name_plan() {
         let mut chunk = Chunk::new(42);
         let dictionary = &mut chunk.dictionary;
-        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
         let lp_lines = vec![
             // Order this so field3 comes before field2
@@ -2177,7 +2703,7 @@ mod tests {
         fn new(lp_lines: Vec<&str>) -> Self {
             let mut chunk = Chunk::new(42);
             let dictionary = &mut chunk.dictionary;
-            let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+            let mut table = Table::new(dictionary.lookup_value_or_insert("table_name").unwrap());
 
             write_lines_to_table(&mut table, dictionary, lp_lines);
             Self { chunk, table }
@@ -2197,7 +2723,13 @@ mod tests {
 
             let grouped_series_set_plan = self
                 .table
-                .grouped_series_set_plan(&chunk_predicate, agg, &group_columns, &self.chunk)
+                .grouped_series_set_plan(
+                    &chunk_predicate,
+                    agg,
+                    &group_columns,
+                    &TagOrders::default(),
+                    &self.chunk,
+                )
                 .expect("creating the grouped_series set plan");
 
             // ensure the group prefix got to the right place
@@ -0,0 +1,244 @@
+//! An inverted index from the values of the tag columns of a table to
+//! the rows containing those values, used to evaluate equality
+//! predicates on tags (e.g. `env = 'prod'`) without scanning the tag
+//! columns.
+//!
+//! The index is built the first time it is needed. Each later use
+//! indexes only the rows written to the table since the previous one,
+//! so every row is indexed once.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{bitmap::Bitmap, column::Column};
+
+/// Maps the value ids of each tag column of a table to the rows that
+/// contain that value
+#[derive(Debug, Default, Clone)]
+pub struct TagIndex {
+    /// column id -> value id -> rows with that value. A bitmap may be
+    /// shorter than `row_count`; the missing rows are unset.
+    columns: HashMap<u32, HashMap<u32, Bitmap>>,
+
+    /// The number of rows of the table indexed
+    row_count: usize,
+}
+
+impl TagIndex {
+    /// Builds the index for the `columns` (as `(column_id, column)`
+    /// pairs) of a table with `row_count` rows. Non tag columns are
+    /// ignored.
+    pub fn new<'a>(row_count: usize, columns: impl Iterator<Item = (u32, &'a Column)>) -> Self {
+        let mut index = Self::default();
+        index.extend(row_count, columns);
+        index
+    }
+
+    /// Indexes the rows of the `columns` of a table, which now has
+    /// `row_count` rows, written since the index was last built or
+    /// extended
+    pub fn extend<'a>(
+        &mut self,
+        row_count: usize,
+        columns: impl Iterator<Item = (u32, &'a Column)>,
+    ) {
+        if row_count <= self.row_count {
+            return;
+        }
+
+        for (column_id, column) in columns {
+            if let Column::Tag(vals, _) = column {
+                let values = self.columns.entry(column_id).or_default();
+                let value_ids = vals.values();
+                let validity = vals.validity();
+                for row in self.row_count..row_count.min(validity.len()) {
+                    if validity.get(row) {
+                        let rows = values.entry(value_ids[row]).or_default();
+                        rows.extend_unset(row + 1);
+                        rows.set(row);
+                    }
+                }
+            }
+        }
+        self.row_count = row_count;
+    }
+
+    /// Returns the number of rows indexed
+    pub fn row_count(&self) -> usize {
+        self.row_count
+    }
+
+    /// Returns true if `column_id` is an indexed tag column
+    pub fn contains_column(&self, column_id: u32) -> bool {
+        self.columns.contains_key(&column_id)
+    }
+
+    /// Returns the rows where the tag column `column_id` has the
+    /// value `value_id`. Returns None if `column_id` is not an indexed
+    /// tag column.
+    pub fn rows(&self, column_id: u32, value_id: u32) -> Option<Bitmap> {
+        self.columns.get(&column_id).map(|values| {
+            let mut rows = values.get(&value_id).cloned().unwrap_or_default();
+            rows.extend_unset(self.row_count);
+            rows
+        })
+    }
+
+    /// Returns the distinct value ids of the tag column `column_id`, in
+    /// no particular order. Returns None if `column_id` is not an indexed
+    /// tag column.
+    pub fn value_ids(&self, column_id: u32) -> Option<impl Iterator<Item = u32> + '_> {
+        self.columns
+            .get(&column_id)
+            .map(|values| values.keys().copied())
+    }
+
+    /// Returns the approximate memory used by this index, in bytes
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self
+                .columns
+                .values()
+                .map(|values| {
+                    values
+                        .values()
+                        .map(|rows| std::mem::size_of::<u32>() + rows.size())
+                        .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+}
+
+/// A `TagIndex` that is built on first use, and brought up to date with
+/// the rows written since on each later use
+#[derive(Debug, Default)]
+pub struct LazyTagIndex {
+    index: Mutex<Option<TagIndex>>,
+}
+
+impl LazyTagIndex {
+    /// Calls `f` with the index of the `columns` of a table with
+    /// `row_count` rows, building the index or indexing the rows written
+    /// since its last use first
+    pub fn with_index<'a, R>(
+        &self,
+        row_count: usize,
+        columns: impl Iterator<Item = (u32, &'a Column)>,
+        f: impl FnOnce(&TagIndex) -> R,
+    ) -> R {
+        let mut index = self.index.lock().expect("tag index mutex poisoned");
+        let index = index.get_or_insert_with(TagIndex::default);
+        index.extend(row_count, columns);
+        f(index)
+    }
+
+    /// Returns the approximate memory used by the index, in bytes, or
+    /// 0 if it has not been built
+    pub fn size(&self) -> usize {
+        self.index
+            .lock()
+            .expect("tag index mutex poisoned")
+            .as_ref()
+            .map_or(0, |index| index.size())
+    }
+}
+
+impl Clone for LazyTagIndex {
+    fn clone(&self) -> Self {
+        let index = self.index.lock().expect("tag index mutex poisoned").clone();
+        Self {
+            index: Mutex::new(index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::partition_metadata::Statistics;
+
+    #[test]
+    fn index_tag_columns() {
+        let tag = Column::Tag(
+            vec![Some(1), Some(2), None, Some(1)].into_iter().collect(),
+            Statistics::new("a".to_string()),
+        );
+        let field = Column::I64(
+            vec![Some(1), Some(2), Some(3), Some(4)]
+                .into_iter()
+                .collect(),
+            Statistics::new(1),
+        );
+
+        let index = TagIndex::new(4, vec![(10, &tag), (11, &field)].into_iter());
+        assert!(index.contains_column(10));
+        assert!(!index.contains_column(11));
+
+        let rows = |column_id, value_id| {
+            index
+                .rows(column_id, value_id)
+                .map(|rows| rows.iter_set().collect::<Vec<_>>())
+        };
+        assert_eq!(rows(10, 1), Some(vec![0, 3]));
+        assert_eq!(rows(10, 2), Some(vec![1]));
+        assert_eq!(rows(10, 3), Some(vec![]));
+        assert_eq!(rows(11, 1), None);
+        assert!(index.size() > 0);
+
+        let mut value_ids: Vec<_> = index.value_ids(10).unwrap().collect();
+        value_ids.sort_unstable();
+        assert_eq!(value_ids, vec![1, 2]);
+        assert!(index.value_ids(11).is_none());
+    }
+
+    #[test]
+    fn extend_tag_index() {
+        let tag = Column::Tag(
+            vec![Some(1), Some(2), None, Some(1), Some(3)]
+                .into_iter()
+                .collect(),
+            Statistics::new("a".to_string()),
+        );
+
+        // index the first two rows, then the rest
+        let mut index = TagIndex::new(2, vec![(10, &tag)].into_iter());
+        assert_eq!(index.row_count(), 2);
+        assert_eq!(
+            index.rows(10, 1).unwrap().iter_set().collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert_eq!(index.rows(10, 3).unwrap().len(), 2);
+
+        index.extend(5, vec![(10, &tag)].into_iter());
+        assert_eq!(index.row_count(), 5);
+        let rows = |value_id| index.rows(10, value_id).unwrap();
+        assert_eq!(rows(1).iter_set().collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(rows(3).iter_set().collect::<Vec<_>>(), vec![4]);
+        assert_eq!(rows(2).len(), 5);
+    }
+
+    #[test]
+    fn lazy_tag_index() {
+        let tag = Column::Tag(
+            vec![Some(1), Some(2)].into_iter().collect(),
+            Statistics::new("a".to_string()),
+        );
+
+        let lazy = LazyTagIndex::default();
+        assert_eq!(lazy.size(), 0);
+
+        let rows = lazy.with_index(1, vec![(10, &tag)].into_iter(), |index| {
+            index.rows(10, 2).unwrap().count_set()
+        });
+        assert_eq!(rows, 0);
+        assert!(lazy.size() > 0);
+
+        // the row written since is indexed on the next use
+        let rows = lazy.with_index(2, vec![(10, &tag)].into_iter(), |index| {
+            index.rows(10, 2).unwrap().iter_set().collect::<Vec<_>>()
+        });
+        assert_eq!(rows, vec![1]);
+
+        let clone = lazy.clone();
+        assert_eq!(clone.size(), lazy.size());
+    }
+}
@@ -0,0 +1,154 @@
+//! A coarse index over the timestamps of the rows of a table, used to
+//! prune tables and row ranges that can not match a timestamp
+//! predicate without scanning the entire time column.
+//!
+//! Rows are grouped into fixed size blocks in the order they are
+//! written, and the minimum and maximum timestamp of each block is
+//! recorded. A timestamp range can then only match rows in blocks
+//! whose [min, max] overlaps the range.
+
+use std::ops::Range;
+
+use crate::bitmap::WORD_BITS;
+
+/// The number of rows in each block of the index. This is a multiple
+/// of the number of bits in a bitmap word so that the row ranges
+/// returned by the index are aligned to bitmap words.
+pub const ROWS_PER_BLOCK: usize = 16 * WORD_BITS;
+
+/// The minimum and maximum timestamps of a group of rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    min: i64,
+    max: i64,
+}
+
+impl TimeRange {
+    fn new(time: i64) -> Self {
+        Self {
+            min: time,
+            max: time,
+        }
+    }
+
+    fn update(&mut self, time: i64) {
+        self.min = self.min.min(time);
+        self.max = self.max.max(time);
+    }
+
+    /// Returns true if any time in [min, max] is within [start, end)
+    fn overlaps(&self, start: i64, end: i64) -> bool {
+        self.min < end && self.max >= start
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TimeIndex {
+    /// The range of all timestamps in the table, if it has any
+    range: Option<TimeRange>,
+
+    /// The range of the timestamps in each block of rows. Blocks
+    /// without any (non-null) timestamps have no range.
+    blocks: Vec<Option<TimeRange>>,
+}
+
+impl TimeIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that row `row` has timestamp `time`
+    pub fn insert(&mut self, row: usize, time: i64) {
+        match &mut self.range {
+            Some(range) => range.update(time),
+            None => self.range = Some(TimeRange::new(time)),
+        }
+
+        let block = row / ROWS_PER_BLOCK;
+        if self.blocks.len() <= block {
+            self.blocks.resize(block + 1, None);
+        }
+        match &mut self.blocks[block] {
+            Some(range) => range.update(time),
+            block_range => *block_range = Some(TimeRange::new(time)),
+        }
+    }
+
+    /// Returns true if any row could have a timestamp within the range
+    /// [start, end). Inclusive of `start`, exclusive of `end`
+    pub fn could_contain(&self, start: i64, end: i64) -> bool {
+        match self.range {
+            Some(range) if range.overlaps(start, end) => {
+                self.candidate_rows(start, end).next().is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the ranges of rows (in increasing order) that could have
+    /// timestamps within the range [start, end). Rows outside of these
+    /// ranges definitely do not. Each returned range starts at a
+    /// multiple of `ROWS_PER_BLOCK`, and the last range may extend
+    /// past the last row.
+    pub fn candidate_rows(&self, start: i64, end: i64) -> impl Iterator<Item = Range<usize>> + '_ {
+        let overlaps = move |range: &Option<TimeRange>| matches!(range, Some(range) if range.overlaps(start, end));
+        let mut blocks = self.blocks.iter().enumerate().peekable();
+
+        std::iter::from_fn(move || {
+            // find the first matching block ...
+            let (first, _) = blocks.find(|(_, range)| overlaps(range))?;
+
+            // ... and merge all directly following matching blocks
+            let mut last = first;
+            while let Some((block, range)) = blocks.peek() {
+                if !overlaps(range) {
+                    break;
+                }
+                last = *block;
+                blocks.next();
+            }
+
+            Some(first * ROWS_PER_BLOCK..(last + 1) * ROWS_PER_BLOCK)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let index = TimeIndex::new();
+        assert!(!index.could_contain(i64::MIN, i64::MAX));
+        assert_eq!(index.candidate_rows(i64::MIN, i64::MAX).count(), 0);
+    }
+
+    #[test]
+    fn candidate_rows() {
+        let mut index = TimeIndex::new();
+
+        // block 0: times 0..1000, block 1: no times, block 2 and 3:
+        // times 5000..6000, block 4: times 0..1000
+        for row in 0..ROWS_PER_BLOCK {
+            index.insert(row, row as i64 % 1000);
+            index.insert(2 * ROWS_PER_BLOCK + row, 5000 + row as i64 % 1000);
+            index.insert(3 * ROWS_PER_BLOCK + row, 5000 + row as i64 % 1000);
+            index.insert(4 * ROWS_PER_BLOCK + row, row as i64 % 1000);
+        }
+
+        assert_eq!(index.range, Some(TimeRange { min: 0, max: 5999 }));
+
+        let b = ROWS_PER_BLOCK;
+        let candidates = |start, end| index.candidate_rows(start, end).collect::<Vec<_>>();
+        assert_eq!(candidates(0, 10), vec![0..b, 4 * b..5 * b]);
+        assert_eq!(candidates(5500, 5501), vec![2 * b..4 * b]);
+        assert_eq!(candidates(0, 10_000), vec![0..b, 2 * b..5 * b]);
+        assert_eq!(candidates(1000, 5000), vec![]);
+
+        assert!(index.could_contain(999, 1000));
+        // within the overall range, but no block matches
+        assert!(!index.could_contain(1000, 5000));
+        assert!(!index.could_contain(6000, 7000));
+    }
+}
@@ -1,23 +1,37 @@
 //! This module contains the IOx implementation for using S3 as the object
 //! store.
 use crate::{
+    check_range, next_part,
     path::{cloud::CloudConverter, ObjectStorePath, DELIMITER},
-    Error, ListResult, NoDataFromS3, ObjectMeta, Result, UnableToDeleteDataFromS3,
-    UnableToGetDataFromS3, UnableToGetPieceOfDataFromS3, UnableToPutDataToS3,
+    AlreadyExists, DataDoesNotMatchLength, Error, ListResult, NoDataFromS3, NoUploadIdFromS3,
+    NotFound, ObjectMeta, PreconditionFailed, PutCondition, Result,
+    UnableToCompleteMultipartUploadToS3, UnableToCopyDataInS3, UnableToDeleteDataFromS3,
+    UnableToGetDataFromS3, UnableToGetPieceOfDataFromS3, UnableToHeadDataFromS3,
+    UnableToPutDataToS3, UnableToReadDataToPut, UnableToStartMultipartUploadToS3,
+    UnableToUploadPartToS3, COPY_SOURCE_ENCODE_SET,
 };
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use chrono::{DateTime, Utc};
 use futures::{stream, Stream, TryStreamExt};
-use rusoto_core::ByteStream;
+use percent_encoding::utf8_percent_encode;
+use rusoto_core::{signature::SignedRequest, ByteStream, RusotoError};
 use rusoto_credential::ChainProvider;
 use rusoto_s3::S3;
-use snafu::{futures::TryStreamExt as _, OptionExt, ResultExt};
+use snafu::{ensure, futures::TryStreamExt as _, OptionExt, ResultExt};
 use std::convert::TryFrom;
-use std::{fmt, io};
+use std::{fmt, io, ops::Range};
+
+/// The size of the parts of multipart uploads. S3 requires every part but
+/// the last to be at least 5 MiB.
+const MULTIPART_PART_SIZE: usize = 5 * 1024 * 1024;
 
 /// Configuration for connecting to [Amazon S3](https://aws.amazon.com/s3/).
 pub struct AmazonS3 {
     client: rusoto_s3::S3Client,
+    /// The client `client` signs and sends its requests with, which sends
+    /// the requests `rusoto_s3` can't make, such as conditional puts
+    request_client: rusoto_core::Client,
+    region: rusoto_core::Region,
     bucket_name: String,
 }
 
@@ -25,6 +39,7 @@ impl fmt::Debug for AmazonS3 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AmazonS3")
             .field("client", &"rusoto_s3::S3Client")
+            .field("region", &self.region)
             .field("bucket_name", &self.bucket_name)
             .finish()
     }
@@ -48,8 +63,11 @@ impl AmazonS3 {
         let http_client = rusoto_core::request::HttpClient::new()
             .expect("Current implementation of rusoto_core has no way for this to fail");
         let credentials_provider = ChainProvider::new();
+        let request_client = rusoto_core::Client::new_with(credentials_provider, http_client);
         Self {
-            client: rusoto_s3::S3Client::new_with(http_client, credentials_provider, region),
+            client: rusoto_s3::S3Client::new_with_client(request_client.clone(), region.clone()),
+            request_client,
+            region,
             bucket_name: bucket_name.into(),
         }
     }
@@ -78,6 +96,216 @@ impl AmazonS3 {
         Ok(())
     }
 
+    /// Save the provided bytes to the specified location, unless there is
+    /// already an object there.
+    pub async fn put_if_not_exists<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        self.put_with_condition(location, bytes, length, PutCondition::NotExists)
+            .await
+    }
+
+    /// Save the provided bytes to the specified location, if the object
+    /// there has the entity tag `e_tag`.
+    pub async fn put_if_match<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+        e_tag: &str,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        self.put_with_condition(location, bytes, length, PutCondition::Match(e_tag))
+            .await
+    }
+
+    /// Puts the object with the conditional header of `condition`. The
+    /// requests of `rusoto_s3` have no conditional headers for puts, so the
+    /// request is built here, and signed and sent like those of `client`.
+    async fn put_with_condition<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+        condition: PutCondition<'_>,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let key = CloudConverter::convert(&location);
+        let mut request = SignedRequest::new(
+            "PUT",
+            "s3",
+            &self.region,
+            &format!("/{}/{}", self.bucket_name, key),
+        );
+        match condition {
+            PutCondition::NotExists => request.add_header("If-None-Match", "*"),
+            PutCondition::Match(e_tag) => request.add_header("If-Match", e_tag),
+        }
+        request.set_payload_stream(ByteStream::new_with_size(bytes, length));
+
+        let response = self
+            .request_client
+            .sign_and_dispatch(request)
+            .await
+            .map_err(RusotoError::<rusoto_s3::PutObjectError>::from)
+            .context(UnableToPutDataToS3 {
+                bucket: &self.bucket_name,
+                location: &key,
+            })?;
+
+        match (condition, response.status.as_u16()) {
+            (_, _) if response.status.is_success() => Ok(()),
+            (PutCondition::NotExists, 412) => AlreadyExists { location: key }.fail(),
+            // there is no version of a missing object to match
+            (PutCondition::Match(_), 404) | (PutCondition::Match(_), 412) => {
+                PreconditionFailed { location: key }.fail()
+            }
+            _ => {
+                let source = match response.buffer().await {
+                    Ok(response) => RusotoError::Unknown(response),
+                    Err(e) => RusotoError::HttpDispatch(e),
+                };
+                Err(source).context(UnableToPutDataToS3 {
+                    bucket: &self.bucket_name,
+                    location: key,
+                })
+            }
+        }
+    }
+
+    /// Save the bytes of `bytes`, whose length isn't known up front, to the
+    /// specified location. Objects larger than a part are sent with a
+    /// multipart upload, one part at a time as they are read.
+    pub async fn put_multipart<S>(&self, location: &ObjectStorePath, bytes: S) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let mut bytes = Box::pin(bytes);
+        let first_part = next_part(&mut bytes, MULTIPART_PART_SIZE)
+            .await
+            .context(UnableToReadDataToPut)?;
+        if first_part.len() < MULTIPART_PART_SIZE {
+            // the whole object fits in a single request
+            let length = first_part.len();
+            let first_part = io::Result::Ok(first_part);
+            return self
+                .put(location, stream::once(async move { first_part }), length)
+                .await;
+        }
+
+        let key = CloudConverter::convert(&location);
+        let create_request = rusoto_s3::CreateMultipartUploadRequest {
+            bucket: self.bucket_name.clone(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        let upload_id = self
+            .client
+            .create_multipart_upload(create_request)
+            .await
+            .context(UnableToStartMultipartUploadToS3 {
+                bucket: &self.bucket_name,
+                location: &key,
+            })?
+            .upload_id
+            .context(NoUploadIdFromS3 {
+                bucket: &self.bucket_name,
+                location: &key,
+            })?;
+
+        let parts = match self
+            .upload_parts(&key, &upload_id, first_part, &mut bytes)
+            .await
+        {
+            Ok(parts) => parts,
+            Err(e) => {
+                // the parts already uploaded are kept, and billed, until the
+                // upload is aborted. If aborting fails too, the error of the
+                // upload is the one worth returning.
+                let abort_request = rusoto_s3::AbortMultipartUploadRequest {
+                    bucket: self.bucket_name.clone(),
+                    key,
+                    upload_id,
+                    ..Default::default()
+                };
+                let _ = self.client.abort_multipart_upload(abort_request).await;
+                return Err(e);
+            }
+        };
+
+        let complete_request = rusoto_s3::CompleteMultipartUploadRequest {
+            bucket: self.bucket_name.clone(),
+            key: key.clone(),
+            upload_id,
+            multipart_upload: Some(rusoto_s3::CompletedMultipartUpload { parts: Some(parts) }),
+            ..Default::default()
+        };
+        self.client
+            .complete_multipart_upload(complete_request)
+            .await
+            .context(UnableToCompleteMultipartUploadToS3 {
+                bucket: &self.bucket_name,
+                location: key,
+            })?;
+        Ok(())
+    }
+
+    /// Uploads `first_part`, then the rest of `bytes` one part at a time, as
+    /// the parts of the multipart upload `upload_id` of `key`
+    async fn upload_parts<S>(
+        &self,
+        key: &str,
+        upload_id: &str,
+        first_part: Bytes,
+        bytes: &mut S,
+    ) -> Result<Vec<rusoto_s3::CompletedPart>>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Unpin,
+    {
+        let mut parts = Vec::new();
+        let mut part = first_part;
+        while !part.is_empty() {
+            let part_number = parts.len() as i64 + 1;
+            let upload_request = rusoto_s3::UploadPartRequest {
+                bucket: self.bucket_name.clone(),
+                key: key.to_string(),
+                upload_id: upload_id.to_string(),
+                part_number,
+                content_length: Some(part.len() as i64),
+                body: Some(part.to_vec().into()),
+                ..Default::default()
+            };
+            let uploaded =
+                self.client
+                    .upload_part(upload_request)
+                    .await
+                    .context(UnableToUploadPartToS3 {
+                        bucket: &self.bucket_name,
+                        location: key,
+                        part_number,
+                    })?;
+            parts.push(rusoto_s3::CompletedPart {
+                e_tag: uploaded.e_tag,
+                part_number: Some(part_number),
+            });
+
+            part = next_part(bytes, MULTIPART_PART_SIZE)
+                .await
+                .context(UnableToReadDataToPut)?;
+        }
+        Ok(parts)
+    }
+
     /// Return the bytes that are stored at the specified location.
     pub async fn get(
         &self,
@@ -109,6 +337,127 @@ impl AmazonS3 {
             .err_into())
     }
 
+    /// Return the bytes in `range` of the object at the specified location,
+    /// with a range request so only they are downloaded.
+    pub async fn get_range(
+        &self,
+        location: &ObjectStorePath,
+        range: Range<usize>,
+    ) -> Result<Bytes> {
+        // HTTP ranges can't be empty, so empty ranges are only checked
+        // against the size of the object
+        if range.start >= range.end {
+            check_range(&range, self.head(location).await?.size)?;
+            return Ok(Bytes::new());
+        }
+
+        let key = CloudConverter::convert(&location);
+        let get_request = rusoto_s3::GetObjectRequest {
+            bucket: self.bucket_name.clone(),
+            key: key.clone(),
+            range: Some(format!("bytes={}-{}", range.start, range.end - 1)),
+            ..Default::default()
+        };
+        let resp = match self.client.get_object(get_request).await {
+            Ok(resp) => resp,
+            // S3 refuses ranges starting past the end of the object
+            Err(RusotoError::Unknown(resp)) if resp.status.as_u16() == 416 => {
+                check_range(&range, self.head(location).await?.size)?;
+                return Err(RusotoError::Unknown(resp)).context(UnableToGetDataFromS3 {
+                    bucket: &self.bucket_name,
+                    location: &key,
+                });
+            }
+            Err(e) => {
+                return Err(e).context(UnableToGetDataFromS3 {
+                    bucket: &self.bucket_name,
+                    location: &key,
+                })
+            }
+        };
+
+        // S3 truncates ranges ending past the end of the object, and tells
+        // its size in the `Content-Range` header, as in `bytes 0-9/10`
+        if let Some(size) = resp
+            .content_range
+            .as_deref()
+            .and_then(|content_range| content_range.rsplit('/').next())
+            .and_then(|size| size.parse().ok())
+        {
+            check_range(&range, size)?;
+        }
+
+        let data = resp
+            .body
+            .context(NoDataFromS3 {
+                bucket: &self.bucket_name,
+                location: &key,
+            })?
+            .map_ok(|b| BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .context(UnableToGetPieceOfDataFromS3 {
+                bucket: &self.bucket_name,
+                location: &key,
+            })?;
+
+        ensure!(
+            data.len() == range.end - range.start,
+            DataDoesNotMatchLength {
+                expected: range.end - range.start,
+                actual: data.len(),
+            }
+        );
+
+        Ok(data.freeze())
+    }
+
+    /// Return the metadata of the object at the specified location, with a
+    /// HEAD request.
+    pub async fn head(&self, location: &ObjectStorePath) -> Result<ObjectMeta> {
+        let key = CloudConverter::convert(&location);
+        let head_request = rusoto_s3::HeadObjectRequest {
+            bucket: self.bucket_name.clone(),
+            key: key.clone(),
+            ..Default::default()
+        };
+
+        // HEAD responses have no body, so S3 reports missing keys by their
+        // status only
+        let resp = match self.client.head_object(head_request).await {
+            Ok(resp) => resp,
+            Err(RusotoError::Service(rusoto_s3::HeadObjectError::NoSuchKey(_))) => {
+                return NotFound { location: key }.fail()
+            }
+            Err(RusotoError::Unknown(resp)) if resp.status.as_u16() == 404 => {
+                return NotFound { location: key }.fail()
+            }
+            Err(source) => {
+                return Err(source).context(UnableToHeadDataFromS3 {
+                    bucket: self.bucket_name.to_owned(),
+                    location: key,
+                })
+            }
+        };
+
+        // HEAD responses have the last modified time of the HTTP header
+        let last_modified = match resp.last_modified {
+            Some(value) => DateTime::parse_from_rfc2822(&value)
+                .map_err(|err| Error::UnableToParseLastModifiedTime { value, err })?
+                .with_timezone(&Utc),
+            None => Utc::now(),
+        };
+        let size = usize::try_from(resp.content_length.unwrap_or(0))
+            .expect("unsupported size on this platform");
+
+        Ok(ObjectMeta {
+            location: location.clone(),
+            last_modified,
+            size,
+            e_tag: resp.e_tag,
+        })
+    }
+
     /// Delete the object at the specified location.
     pub async fn delete(&self, location: &ObjectStorePath) -> Result<()> {
         let key = CloudConverter::convert(&location);
@@ -128,6 +477,36 @@ impl AmazonS3 {
         Ok(())
     }
 
+    /// Copy the object at `from` to `to`, overwriting any object there. The
+    /// object is copied by S3, in a single request, so it must be no larger
+    /// than 5 GB.
+    pub async fn copy(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        let from = CloudConverter::convert(&from);
+        let to = CloudConverter::convert(&to);
+        // the source is URL encoded, unlike the destination key
+        let copy_source = format!(
+            "{}/{}",
+            self.bucket_name,
+            utf8_percent_encode(&from, COPY_SOURCE_ENCODE_SET)
+        );
+        let copy_request = rusoto_s3::CopyObjectRequest {
+            bucket: self.bucket_name.clone(),
+            copy_source,
+            key: to.clone(),
+            ..Default::default()
+        };
+
+        self.client
+            .copy_object(copy_request)
+            .await
+            .context(UnableToCopyDataInS3 {
+                bucket: &self.bucket_name,
+                from,
+                to,
+            })?;
+        Ok(())
+    }
+
     /// List all the objects with the given prefix.
     pub async fn list<'a>(
         &'a self,
@@ -194,25 +573,25 @@ impl AmazonS3 {
     }
 
     /// List objects with the given prefix and a set delimiter of `/`. Returns
-    /// common prefixes (directories) in addition to object metadata.
+    /// common prefixes (directories) in addition to object metadata, at most
+    /// `max_results` of them, after the continuation token `token` if any.
     pub async fn list_with_delimiter<'a>(
         &'a self,
         prefix: &'a ObjectStorePath,
-        next_token: &Option<String>,
+        token: Option<&str>,
+        max_results: usize,
     ) -> Result<ListResult> {
         let converted_prefix = CloudConverter::convert(prefix);
 
-        let mut list_request = rusoto_s3::ListObjectsV2Request {
+        let list_request = rusoto_s3::ListObjectsV2Request {
             bucket: self.bucket_name.clone(),
             prefix: Some(converted_prefix),
             delimiter: Some(DELIMITER.to_string()),
+            continuation_token: token.map(ToString::to_string),
+            max_keys: Some(max_results as i64),
             ..Default::default()
         };
 
-        if let Some(t) = next_token {
-            list_request.continuation_token = Some(t.clone());
-        }
-
         let resp = match self.client.list_objects_v2(list_request).await {
             Ok(resp) => resp,
             Err(e) => {
@@ -251,6 +630,7 @@ impl AmazonS3 {
                     location,
                     last_modified,
                     size,
+                    e_tag: object.e_tag,
                 }
             })
             .collect();
@@ -310,7 +690,10 @@ impl Error {
 mod tests {
     use crate::{
         path::ObjectStorePath,
-        tests::{get_nonexistent_object, list_with_delimiter, put_get_delete_list},
+        tests::{
+            get_nonexistent_object, list_with_delimiter, list_with_delimiter_paginated,
+            put_get_delete_list, put_if_match, put_if_not_exists,
+        },
         AmazonS3, Error, ObjectStore,
     };
     use bytes::Bytes;
@@ -406,6 +789,9 @@ mod tests {
         check_credentials(put_get_delete_list(&integration).await)?;
 
         check_credentials(list_with_delimiter(&integration).await).unwrap();
+        check_credentials(list_with_delimiter_paginated(&integration).await).unwrap();
+        check_credentials(put_if_not_exists(&integration).await).unwrap();
+        check_credentials(put_if_match(&integration).await).unwrap();
 
         Ok(())
     }
@@ -1,17 +1,37 @@
 //! This module contains the IOx implementation for using Azure Blob storage as
 //! the object store.
 use crate::{
-    path::{cloud::CloudConverter, ObjectStorePath},
-    DataDoesNotMatchLength, Result, UnableToDeleteDataFromAzure, UnableToGetDataFromAzure,
-    UnableToListDataFromAzure, UnableToPutDataToAzure,
+    next_part,
+    path::{cloud::CloudConverter, ObjectStorePath, DELIMITER},
+    slice_object, AlreadyExists, CopyNotCompletedInAzure, DataDoesNotMatchLength, Error,
+    ListResult, NotFound, ObjectMeta, PreconditionFailed, PutCondition, Result,
+    UnableToCopyDataInAzure, UnableToDeleteDataFromAzure, UnableToGetDataFromAzure,
+    UnableToHeadDataFromAzure, UnableToListDataFromAzure, UnableToPutBlockListToAzure,
+    UnableToPutBlockToAzure, UnableToPutDataToAzure, UnableToReadDataToPut, COPY_SOURCE_ENCODE_SET,
 };
-use azure_sdk_core::prelude::*;
-use azure_sdk_storage_blob::prelude::*;
+use azure_sdk_core::{
+    errors::{check_status_extract_headers_and_body, AzureError},
+    prelude::*,
+};
+use azure_sdk_storage_blob::{
+    blob::{BlobBlockType, BlockList, CopyStatus},
+    prelude::*,
+};
+use azure_sdk_storage_core::client::Client as _;
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use futures::{stream, FutureExt, Stream, TryStreamExt};
+use hyper::{Method, StatusCode};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use snafu::{ensure, ResultExt};
-use std::io;
 use std::sync::Arc;
+use std::{io, ops::Range, time::Duration};
+
+/// The size of the blocks of blobs put without a known length
+const BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// How often the status of a copy is checked while Azure is copying
+const COPY_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Configuration for connecting to [Microsoft Azure Blob Storage](https://azure.microsoft.com/en-us/services/storage/blobs/).
 #[derive(Debug)]
@@ -85,6 +105,173 @@ impl MicrosoftAzure {
         Ok(())
     }
 
+    /// Save the provided bytes to the specified location, unless there is
+    /// already an object there.
+    pub async fn put_if_not_exists<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        self.put_with_condition(location, bytes, length, PutCondition::NotExists)
+            .await
+    }
+
+    /// Save the provided bytes to the specified location, if the object
+    /// there has the entity tag `e_tag`.
+    pub async fn put_if_match<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+        e_tag: &str,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        self.put_with_condition(location, bytes, length, PutCondition::Match(e_tag))
+            .await
+    }
+
+    /// Puts the blob with the conditional header of `condition`. The
+    /// requests of the Azure SDK have no conditional headers for puts, so
+    /// the request is built here, and signed and sent by `client`.
+    async fn put_with_condition<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+        condition: PutCondition<'_>,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let key = CloudConverter::convert(&location);
+        let content = bytes
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .context(UnableToReadDataToPut)?;
+
+        ensure!(
+            content.len() == length,
+            DataDoesNotMatchLength {
+                actual: content.len(),
+                expected: length,
+            }
+        );
+
+        let uri = format!(
+            "{}/{}/{}",
+            self.client.blob_uri(),
+            self.container_name,
+            utf8_percent_encode(&key, COPY_SOURCE_ENCODE_SET)
+        );
+        let (header, value) = match condition {
+            PutCondition::NotExists => ("If-None-Match", "*"),
+            PutCondition::Match(e_tag) => ("If-Match", e_tag),
+        };
+        let response = self
+            .client
+            .perform_request(
+                &uri,
+                &Method::PUT,
+                &|request| {
+                    request
+                        .header("x-ms-blob-type", "BlockBlob")
+                        .header(header, value)
+                },
+                Some(&content),
+            )
+            .context(UnableToPutDataToAzure { location: &key })?;
+
+        match check_status_extract_headers_and_body(response.response_future, StatusCode::CREATED)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(AzureError::UnexpectedHTTPResult(result)) => {
+                match (condition, result.status_code().as_u16()) {
+                    (PutCondition::NotExists, 409) | (PutCondition::NotExists, 412) => {
+                        AlreadyExists { location: key }.fail()
+                    }
+                    // there is no version of a missing blob to match
+                    (PutCondition::Match(_), 404) | (PutCondition::Match(_), 412) => {
+                        PreconditionFailed { location: key }.fail()
+                    }
+                    _ => Err(AzureError::UnexpectedHTTPResult(result))
+                        .context(UnableToPutDataToAzure { location: key }),
+                }
+            }
+            Err(source) => Err(source).context(UnableToPutDataToAzure { location: key }),
+        }
+    }
+
+    /// Save the bytes of `bytes`, whose length isn't known up front, to the
+    /// specified location. Blobs larger than a block are put one block at a
+    /// time as they are read, and committed once all blocks are put.
+    pub async fn put_multipart<S>(&self, location: &ObjectStorePath, bytes: S) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let mut bytes = Box::pin(bytes);
+        let mut block = next_part(&mut bytes, BLOCK_SIZE)
+            .await
+            .context(UnableToReadDataToPut)?;
+        if block.len() < BLOCK_SIZE {
+            // the whole blob fits in a single request
+            let length = block.len();
+            let block = io::Result::Ok(block);
+            return self
+                .put(location, stream::once(async move { block }), length)
+                .await;
+        }
+
+        // blocks that are never committed, such as those of a failed
+        // upload, are discarded by Azure
+        let location = CloudConverter::convert(&location);
+        let mut block_ids = Vec::new();
+        while !block.is_empty() {
+            // the ids of the blocks of a blob must all have the same length
+            let block_id = format!("{:08}", block_ids.len()).into_bytes();
+            self.client
+                .put_block()
+                .with_container_name(&self.container_name)
+                .with_blob_name(&location)
+                .with_body(&block)
+                .with_block_id(&block_id)
+                .finalize()
+                .await
+                .context(UnableToPutBlockToAzure {
+                    location: &location,
+                })?;
+            block_ids.push(block_id);
+
+            block = next_part(&mut bytes, BLOCK_SIZE)
+                .await
+                .context(UnableToReadDataToPut)?;
+        }
+
+        let mut block_list = BlockList::default();
+        for block_id in &block_ids {
+            block_list
+                .blocks
+                .push(BlobBlockType::Uncommitted(&block_id[..]));
+        }
+        self.client
+            .put_block_list()
+            .with_container_name(&self.container_name)
+            .with_blob_name(&location)
+            .with_block_list(&block_list)
+            .finalize()
+            .await
+            .context(UnableToPutBlockListToAzure { location })?;
+
+        Ok(())
+    }
+
     /// Return the bytes that are stored at the specified location.
     pub async fn get(
         &self,
@@ -108,6 +295,43 @@ impl MicrosoftAzure {
         .into_stream())
     }
 
+    /// Return the bytes in `range` of the object at the specified location.
+    /// The whole blob is downloaded, as the client can't request a range.
+    pub async fn get_range(
+        &self,
+        location: &ObjectStorePath,
+        range: Range<usize>,
+    ) -> Result<Bytes> {
+        slice_object(self.get(location).await?, range).await
+    }
+
+    /// Return the metadata of the blob at the specified location, from its
+    /// properties.
+    pub async fn head(&self, location: &ObjectStorePath) -> Result<ObjectMeta> {
+        let key = CloudConverter::convert(&location);
+        let properties = match self
+            .client
+            .get_blob_properties()
+            .with_container_name(&self.container_name)
+            .with_blob_name(&key)
+            .finalize()
+            .await
+        {
+            Ok(properties) => properties,
+            Err(AzureError::UnexpectedHTTPResult(result)) if result.status_code() == 404 => {
+                return NotFound { location: key }.fail()
+            }
+            Err(source) => return Err(source).context(UnableToHeadDataFromAzure { location: key }),
+        };
+
+        Ok(ObjectMeta {
+            location: location.clone(),
+            last_modified: properties.blob.last_modified,
+            size: properties.blob.content_length as usize,
+            e_tag: Some(properties.blob.etag),
+        })
+    }
+
     /// Delete the object at the specified location.
     pub async fn delete(&self, location: &ObjectStorePath) -> Result<()> {
         let location = CloudConverter::convert(&location);
@@ -125,6 +349,66 @@ impl MicrosoftAzure {
         Ok(())
     }
 
+    /// Copy the object at `from` to `to`, overwriting any object there. The
+    /// blob is copied by Azure. Copies Azure hasn't completed when it
+    /// responds are waited for, by polling the properties of the copy.
+    pub async fn copy(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        let from = CloudConverter::convert(&from);
+        let to = CloudConverter::convert(&to);
+        // the source is URL encoded, unlike the destination blob name
+        let source_url = format!(
+            "{}/{}/{}",
+            self.client.blob_uri(),
+            self.container_name,
+            utf8_percent_encode(&from, COPY_SOURCE_ENCODE_SET)
+        );
+
+        let mut status = self
+            .client
+            .copy_blob()
+            .with_container_name(&self.container_name)
+            .with_blob_name(&to)
+            .with_source_url(&source_url)
+            .finalize()
+            .await
+            .context(UnableToCopyDataInAzure {
+                from: &from,
+                to: &to,
+            })?
+            .copy_status;
+        let mut description = None;
+
+        while let CopyStatus::Pending = status {
+            tokio::time::delay_for(COPY_POLL_INTERVAL).await;
+            let blob = self
+                .client
+                .get_blob_properties()
+                .with_container_name(&self.container_name)
+                .with_blob_name(&to)
+                .finalize()
+                .await
+                .context(UnableToCopyDataInAzure {
+                    from: &from,
+                    to: &to,
+                })?
+                .blob;
+            // blobs that were never copied have no copy status
+            status = blob.copy_status.unwrap_or(CopyStatus::Success);
+            description = blob.copy_status_description;
+        }
+
+        ensure!(
+            matches!(status, CopyStatus::Success),
+            CopyNotCompletedInAzure {
+                from,
+                to,
+                status: format!("{:?}", status),
+                description: description.unwrap_or_default(),
+            }
+        );
+        Ok(())
+    }
+
     /// List all the objects with the given prefix.
     pub async fn list<'a>(
         &'a self,
@@ -179,12 +463,112 @@ impl MicrosoftAzure {
             Some((Ok(names), next_state))
         }))
     }
+
+    /// List objects with the given prefix and a set delimiter of `/`. Returns
+    /// common prefixes (directories) in addition to object metadata, from a
+    /// page of at most `max_results` blobs and blob prefixes after the
+    /// continuation token `token` if any. The client can't list blobs by a
+    /// delimiter, so the request is built here, signed and sent by `client`,
+    /// and its response is parsed by `parse_blob_list`.
+    pub async fn list_with_delimiter(
+        &self,
+        prefix: &ObjectStorePath,
+        token: Option<&str>,
+        max_results: usize,
+    ) -> Result<ListResult> {
+        let converted_prefix = CloudConverter::convert(prefix);
+
+        let mut uri = format!(
+            "{}/{}?restype=container&comp=list&prefix={}&delimiter={}&maxresults={}",
+            self.client.blob_uri(),
+            self.container_name,
+            utf8_percent_encode(&converted_prefix, NON_ALPHANUMERIC),
+            utf8_percent_encode(DELIMITER, NON_ALPHANUMERIC),
+            max_results
+        );
+        if let Some(token) = token {
+            uri.push_str("&marker=");
+            uri.extend(utf8_percent_encode(token, NON_ALPHANUMERIC));
+        }
+
+        let response = self
+            .client
+            .perform_request(&uri, &Method::GET, &|request| request, None)
+            .context(UnableToListDataFromAzure)?;
+        let (_, body) =
+            check_status_extract_headers_and_body(response.response_future, StatusCode::OK)
+                .await
+                .context(UnableToListDataFromAzure)?;
+
+        parse_blob_list(&body)
+    }
+}
+
+/// Parses the blobs, blob prefixes and continuation token of the response
+/// `body` of a List Blobs request
+fn parse_blob_list(body: &[u8]) -> Result<ListResult> {
+    let invalid = |details: String| Error::InvalidListFromAzure { details };
+    let text = |element: &xml::Element, name: &str| {
+        element
+            .get_child(name, None)
+            .map(xml::Element::content_str)
+            .ok_or_else(|| invalid(format!("{} is missing", name)))
+    };
+
+    let body = std::str::from_utf8(body).map_err(|e| invalid(e.to_string()))?;
+    // the body may start with a byte order mark
+    let root: xml::Element = body
+        .trim_start_matches('\u{feff}')
+        .parse()
+        .map_err(|e| invalid(format!("{:?}", e)))?;
+
+    let mut result = ListResult::default();
+    if let Some(blobs) = root.get_child("Blobs", None) {
+        for blob in blobs.get_children("Blob", None) {
+            let properties = blob
+                .get_child("Properties", None)
+                .ok_or_else(|| invalid("Properties is missing".to_string()))?;
+            let last_modified = text(properties, "Last-Modified")?;
+            let size = text(properties, "Content-Length")?;
+
+            result.objects.push(ObjectMeta {
+                location: ObjectStorePath::from_cloud_unchecked(text(blob, "Name")?),
+                last_modified: DateTime::parse_from_rfc2822(&last_modified)
+                    .map_err(|err| Error::UnableToParseLastModifiedTime {
+                        value: last_modified.clone(),
+                        err,
+                    })?
+                    .with_timezone(&Utc),
+                size: size
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid Content-Length {}", size)))?,
+                e_tag: Some(text(properties, "Etag")?),
+            });
+        }
+        for prefix in blobs.get_children("BlobPrefix", None) {
+            result
+                .common_prefixes
+                .push(ObjectStorePath::from_cloud_unchecked(text(prefix, "Name")?));
+        }
+    }
+    result.next_token = root
+        .get_child("NextMarker", None)
+        .map(xml::Element::content_str)
+        .filter(|marker| !marker.is_empty());
+
+    Ok(result)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{tests::put_get_delete_list, ObjectStore};
+    use crate::{
+        tests::{
+            list_with_delimiter, list_with_delimiter_paginated, put_get_delete_list, put_if_match,
+            put_if_not_exists,
+        },
+        ObjectStore,
+    };
     use std::env;
 
     type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -243,7 +627,54 @@ mod tests {
 
         let integration = ObjectStore::new_microsoft_azure(azure);
         put_get_delete_list(&integration).await?;
+        put_if_not_exists(&integration).await?;
+        put_if_match(&integration).await?;
+        list_with_delimiter(&integration).await?;
+        list_with_delimiter_paginated(&integration).await?;
 
         Ok(())
     }
+
+    #[test]
+    fn parses_blob_lists() {
+        let body = "\u{feff}<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+            <EnumerationResults ServiceEndpoint=\"https://account.blob.core.windows.net/\" \
+                ContainerName=\"container\">\
+              <Prefix>mydb/</Prefix><MaxResults>2</MaxResults><Delimiter>/</Delimiter>\
+              <Blobs>\
+                <Blob><Name>mydb/rules.pb</Name><Properties>\
+                  <Last-Modified>Wed, 13 Jan 2021 10:00:00 GMT</Last-Modified>\
+                  <Etag>0x8D8B7A3C2D6E9F0</Etag><Content-Length>42</Content-Length>\
+                </Properties></Blob>\
+                <BlobPrefix><Name>mydb/wal/</Name></BlobPrefix>\
+              </Blobs>\
+              <NextMarker>2!72!MDAwMDM2</NextMarker>\
+            </EnumerationResults>";
+
+        let result = parse_blob_list(body.as_bytes()).unwrap();
+        assert_eq!(result.next_token.as_deref(), Some("2!72!MDAwMDM2"));
+        assert_eq!(
+            result.common_prefixes,
+            vec![ObjectStorePath::from_cloud_unchecked("mydb/wal/")]
+        );
+        assert_eq!(result.objects.len(), 1);
+        let object = &result.objects[0];
+        assert_eq!(
+            object.location,
+            ObjectStorePath::from_cloud_unchecked("mydb/rules.pb")
+        );
+        assert_eq!(object.size, 42);
+        assert_eq!(object.e_tag.as_deref(), Some("0x8D8B7A3C2D6E9F0"));
+        assert_eq!(
+            object.last_modified.to_rfc3339(),
+            "2021-01-13T10:00:00+00:00"
+        );
+
+        let last_page = parse_blob_list(
+            b"<EnumerationResults><Blobs></Blobs><NextMarker /></EnumerationResults>",
+        )
+        .unwrap();
+        assert!(last_page.next_token.is_none());
+        assert!(last_page.objects.is_empty());
+    }
 }
@@ -1,15 +1,31 @@
 //! This module contains the IOx implementation for using local disk as the
 //! object store.
 use crate::{
-    path::{file::FileConverter, ObjectStorePath},
-    DataDoesNotMatchLength, Result, UnableToCopyDataToFile, UnableToCreateDir, UnableToCreateFile,
-    UnableToDeleteFile, UnableToOpenFile, UnableToPutDataInMemory, UnableToReadBytes,
+    paginate,
+    path::{file::FileConverter, parsed::DirsAndFileName, ObjectStorePath},
+    AlreadyExists, DataDoesNotMatchLength, ListResult, NotFound, ObjectMeta, PreconditionFailed,
+    RangeOutOfBounds, Result, UnableToCopyDataToFile, UnableToCopyFile, UnableToCreateDir,
+    UnableToCreateFile, UnableToDeleteFile, UnableToLinkFile, UnableToOpenFile,
+    UnableToProcessEntry, UnableToPutDataInMemory, UnableToReadBytes, UnableToReadDataToPut,
+    UnableToReadMetadata, UnableToRenameFile, UnableToSyncDir,
 };
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use futures::{stream, Stream, TryStreamExt};
 use snafu::{ensure, futures::TryStreamExt as _, OptionExt, ResultExt};
-use std::{io, path::PathBuf};
-use tokio::fs;
+use std::{
+    collections::BTreeSet,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+};
 use tokio_util::codec::{BytesCodec, FramedRead};
 use walkdir::WalkDir;
 
@@ -18,6 +34,8 @@ use walkdir::WalkDir;
 #[derive(Debug)]
 pub struct File {
     root: ObjectStorePath,
+    /// Held while `put_if_match` checks and replaces a file
+    conditional_puts: Mutex<()>,
 }
 
 impl File {
@@ -25,6 +43,7 @@ impl File {
     pub fn new(root: impl Into<PathBuf>) -> Self {
         Self {
             root: ObjectStorePath::from_path_buf_unchecked(root),
+            conditional_puts: Mutex::new(()),
         }
     }
 
@@ -80,6 +99,120 @@ impl File {
         Ok(())
     }
 
+    /// Save the provided bytes to the specified location, unless there is
+    /// already a file there. The bytes are written to a temporary file,
+    /// which is then linked to the location. Linking fails if there is a
+    /// file there, so only one of concurrent puts creates it, and the file
+    /// is only seen at the location once complete.
+    pub async fn put_if_not_exists<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let path = self.path(location);
+        let temp_path = write_temp_file(&path, bytes).await?;
+
+        let linked = async {
+            check_length(&temp_path, length).await?;
+            match fs::hard_link(&temp_path, &path).await {
+                Ok(()) => sync_parent_dir(&path).await,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => AlreadyExists {
+                    location: path.display().to_string(),
+                }
+                .fail(),
+                Err(source) => Err(source).context(UnableToLinkFile {
+                    from: &temp_path,
+                    to: &path,
+                }),
+            }
+        }
+        .await;
+
+        // the file is at the location once linked
+        let _ = fs::remove_file(&temp_path).await;
+        linked
+    }
+
+    /// Save the provided bytes to the specified location, if the file there
+    /// has the entity tag `e_tag`. The bytes are written to a temporary
+    /// file, which replaces the file once its entity tag is checked. The
+    /// check and the replacement are atomic among the puts of this `File`,
+    /// but not with other processes writing the same files.
+    pub async fn put_if_match<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+        e_tag: &str,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let path = self.path(location);
+        let temp_path = write_temp_file(&path, bytes).await?;
+
+        let replaced = async {
+            check_length(&temp_path, length).await?;
+
+            let _conditional_put = self.conditional_puts.lock().await;
+            let current = match fs::metadata(&path).await {
+                Ok(metadata) => {
+                    let last_modified = metadata
+                        .modified()
+                        .context(UnableToReadMetadata { path: &path })?;
+                    Some(file_e_tag(metadata.len(), last_modified))
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(source) => return Err(source).context(UnableToReadMetadata { path: &path }),
+            };
+            ensure!(
+                current.as_deref() == Some(e_tag),
+                PreconditionFailed {
+                    location: path.display().to_string(),
+                }
+            );
+
+            fs::rename(&temp_path, &path)
+                .await
+                .context(UnableToRenameFile {
+                    from: &temp_path,
+                    to: &path,
+                })?;
+            sync_parent_dir(&path).await
+        }
+        .await;
+
+        if replaced.is_err() {
+            let _ = fs::remove_file(&temp_path).await;
+        }
+        replaced
+    }
+
+    /// Save the bytes of `bytes`, whose length isn't known up front, to the
+    /// specified location. They are written to a temporary file as they are
+    /// read, which is renamed to the location once complete, so a partly
+    /// written file is never seen there.
+    pub async fn put_multipart<S>(&self, location: &ObjectStorePath, bytes: S) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let path = self.path(location);
+        let temp_path = write_temp_file(&path, bytes).await?;
+
+        if let Err(e) = fs::rename(&temp_path, &path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(e).context(UnableToRenameFile {
+                from: temp_path,
+                to: path,
+            });
+        }
+        Ok(())
+    }
+
     /// Return the bytes that are stored at the specified location.
     pub async fn get(
         &self,
@@ -97,6 +230,61 @@ impl File {
         Ok(s)
     }
 
+    /// Return the bytes in `range` of the object at the specified location.
+    pub async fn get_range(
+        &self,
+        location: &ObjectStorePath,
+        range: Range<usize>,
+    ) -> Result<Bytes> {
+        let path = self.path(location);
+
+        let mut file = fs::File::open(&path)
+            .await
+            .context(UnableToOpenFile { path: &path })?;
+        let size = file
+            .metadata()
+            .await
+            .context(UnableToReadBytes { path: &path })?
+            .len() as usize;
+        ensure!(
+            range.start <= range.end && range.end <= size,
+            RangeOutOfBounds { range, size }
+        );
+
+        file.seek(io::SeekFrom::Start(range.start as u64))
+            .await
+            .context(UnableToReadBytes { path: &path })?;
+        let mut data = vec![0; range.end - range.start];
+        file.read_exact(&mut data)
+            .await
+            .context(UnableToReadBytes { path })?;
+
+        Ok(data.into())
+    }
+
+    /// Return the metadata of the file at the specified location.
+    pub async fn head(&self, location: &ObjectStorePath) -> Result<ObjectMeta> {
+        let path = self.path(location);
+        let metadata = match fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return NotFound {
+                    location: path.display().to_string(),
+                }
+                .fail()
+            }
+            Err(source) => return Err(source).context(UnableToReadMetadata { path }),
+        };
+        let last_modified = metadata.modified().context(UnableToReadMetadata { path })?;
+
+        Ok(ObjectMeta {
+            location: location.clone(),
+            last_modified: DateTime::<Utc>::from(last_modified),
+            size: metadata.len() as usize,
+            e_tag: Some(file_e_tag(metadata.len(), last_modified)),
+        })
+    }
+
     /// Delete the object at the specified location.
     pub async fn delete(&self, location: &ObjectStorePath) -> Result<()> {
         let path = self.path(location);
@@ -106,6 +294,27 @@ impl File {
         Ok(())
     }
 
+    /// Copy the file at `from` to `to`, overwriting any file there.
+    pub async fn copy(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        let (from, to) = (self.path(from), self.path(to));
+        create_parent_dir(&to).await?;
+        fs::copy(&from, &to)
+            .await
+            .context(UnableToCopyFile { from, to })?;
+        Ok(())
+    }
+
+    /// Rename the file at `from` to `to`, overwriting any file there. The
+    /// rename is atomic if both are on the same filesystem.
+    pub async fn rename(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        let (from, to) = (self.path(from), self.path(to));
+        create_parent_dir(&to).await?;
+        fs::rename(&from, &to)
+            .await
+            .context(UnableToRenameFile { from, to })?;
+        Ok(())
+    }
+
     /// List all the objects with the given prefix.
     pub async fn list<'a>(
         &'a self,
@@ -119,7 +328,9 @@ impl File {
         let s = walkdir.into_iter().filter_map(move |result_dir_entry| {
             result_dir_entry
                 .ok()
-                .filter(|dir_entry| dir_entry.file_type().is_file())
+                .filter(|dir_entry| {
+                    dir_entry.file_type().is_file() && !is_temp_file(dir_entry.path())
+                })
                 .map(|file| {
                     let relative_path = file.path().strip_prefix(&root_path).expect(
                         "Must start with root path because this came from walking the root",
@@ -132,6 +343,202 @@ impl File {
 
         Ok(stream::iter(s))
     }
+
+    /// List objects with the given prefix and a set delimiter of `/`. Returns
+    /// common prefixes (directories) in addition to object metadata, at most
+    /// `max_results` of them, after the token `token` if any. The pages are
+    /// cut from all the results, like `paginate` describes.
+    pub async fn list_with_delimiter<'a>(
+        &'a self,
+        prefix: &'a ObjectStorePath,
+        token: Option<&str>,
+        max_results: usize,
+    ) -> Result<ListResult> {
+        let root_path = FileConverter::convert(&self.root);
+        let prefix: DirsAndFileName = prefix.into();
+
+        // Only the directories of the prefix have to be walked
+        let mut prefix_dirs = prefix.clone();
+        prefix_dirs.file_name = None;
+        let walkdir = WalkDir::new(self.path(&prefix_dirs.into()))
+            // Don't include the prefix directory itself
+            .min_depth(1);
+
+        // Only objects in this base level should be returned in the
+        // response. Otherwise, we just collect the common prefixes.
+        let mut common_prefixes = BTreeSet::new();
+        let mut objects = vec![];
+        for dir_entry in walkdir
+            .into_iter()
+            .filter_map(|result_dir_entry| result_dir_entry.ok())
+            .filter(|dir_entry| dir_entry.file_type().is_file() && !is_temp_file(dir_entry.path()))
+        {
+            let relative_path = dir_entry
+                .path()
+                .strip_prefix(&root_path)
+                .expect("Must start with root path because this came from walking below the root");
+            let location = DirsAndFileName::from_encoded_file_path(relative_path);
+            if !location.prefix_matches(&prefix) {
+                continue;
+            }
+
+            let parts = location
+                .parts_after_prefix(&prefix)
+                .expect("must have prefix if it matches");
+
+            if parts.len() >= 2 {
+                let mut full_prefix = prefix.clone();
+                full_prefix.push_part_as_dir(&parts[0]);
+                common_prefixes.insert(full_prefix);
+            } else {
+                let metadata = dir_entry
+                    .metadata()
+                    .map_err(io::Error::from)
+                    .context(UnableToProcessEntry)?;
+                let last_modified = metadata.modified().context(UnableToProcessEntry)?;
+                objects.push(ObjectMeta {
+                    location: location.into(),
+                    last_modified: DateTime::<Utc>::from(last_modified),
+                    size: metadata.len() as usize,
+                    e_tag: Some(file_e_tag(metadata.len(), last_modified)),
+                });
+            }
+        }
+
+        let result = ListResult {
+            objects,
+            common_prefixes: common_prefixes.into_iter().map(Into::into).collect(),
+            next_token: None,
+        };
+        Ok(paginate(result, token, max_results))
+    }
+}
+
+/// Creates the directory `path` is in, if it doesn't exist
+async fn create_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .context(UnableToCreateDir { path: parent })?;
+    }
+    Ok(())
+}
+
+/// Syncs the directory of `path`, so that a file moved or linked there stays
+/// there after a crash
+async fn sync_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        let dir = fs::File::open(parent)
+            .await
+            .context(UnableToSyncDir { path: parent })?;
+        dir.sync_all()
+            .await
+            .context(UnableToSyncDir { path: parent })?;
+    }
+    Ok(())
+}
+
+/// Returns the entity tag of a file of `size` bytes last modified at
+/// `last_modified`. Files are replaced by new files, whose modification
+/// time is when they were written.
+fn file_e_tag(size: u64, last_modified: SystemTime) -> String {
+    let modified = last_modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", size, modified)
+}
+
+/// Checks that the file at `path` has `length` bytes
+async fn check_length(path: &Path, length: usize) -> Result<()> {
+    let actual = fs::metadata(path)
+        .await
+        .context(UnableToReadMetadata { path })?
+        .len() as usize;
+    ensure!(
+        actual == length,
+        DataDoesNotMatchLength {
+            actual,
+            expected: length,
+        }
+    );
+    Ok(())
+}
+
+/// The suffix of the names of temporary files, which are left out of
+/// listings
+const TEMP_FILE_SUFFIX: &str = ".tmp";
+
+/// Returns a new path for a temporary file in the directory of `path`, on
+/// the same filesystem, so it can be moved to `path` atomically
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT_TEMP_FILE: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(
+        ".{}.{}-{}{}",
+        file_name,
+        std::process::id(),
+        NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed),
+        TEMP_FILE_SUFFIX
+    ))
+}
+
+/// Returns true if `path` is a temporary file written by this integration
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .map_or(false, |file_name| {
+            file_name.starts_with('.') && file_name.ends_with(TEMP_FILE_SUFFIX)
+        })
+}
+
+/// Writes the bytes of `bytes` to a new temporary file in the directory of
+/// `path` as they are read, and flushes it to disk. Returns the path of the
+/// temporary file, which is removed if writing it fails.
+async fn write_temp_file<S>(path: &Path, bytes: S) -> Result<PathBuf>
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+{
+    create_parent_dir(path).await?;
+    let temp_path = temp_path(path);
+    let mut file = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .await
+    {
+        Ok(f) => f,
+        Err(err) => {
+            return UnableToCreateFile {
+                path: temp_path,
+                err,
+            }
+            .fail()
+        }
+    };
+
+    let mut bytes = Box::pin(bytes);
+    let written: Result<()> = async {
+        while let Some(data) = bytes.try_next().await.context(UnableToReadDataToPut)? {
+            file.write_all(&data)
+                .await
+                .context(UnableToCopyDataToFile)?;
+        }
+        file.sync_all().await.context(UnableToCopyDataToFile)
+    }
+    .await;
+
+    match written {
+        Ok(()) => Ok(temp_path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path).await;
+            Err(e)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -143,7 +550,12 @@ mod tests {
 
     use tempfile::TempDir;
 
-    use crate::{tests::put_get_delete_list, Error, ObjectStore};
+    use crate::{
+        tests::{
+            list_with_delimiter_paginated, put_get_delete_list, put_if_match, put_if_not_exists,
+        },
+        Error, ObjectStore,
+    };
     use futures::stream;
 
     #[tokio::test]
@@ -152,6 +564,79 @@ mod tests {
         let integration = ObjectStore::new_file(File::new(root.path()));
 
         put_get_delete_list(&integration).await?;
+        list_with_delimiter_paginated(&integration).await?;
+        put_if_not_exists(&integration).await?;
+        put_if_match(&integration).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_list_with_delimiter() -> Result<()> {
+        let root = TempDir::new()?;
+        let storage = ObjectStore::new_file(File::new(root.path()));
+
+        let data = Bytes::from("arbitrary data");
+        let files = [
+            "mydb/wal/000/000/000.segment",
+            "mydb/wal/000/000/001.segment",
+            "mydb/wal/001/001/000.segment",
+            "mydb/wal/foo.test",
+            "mydb/data/whatevs",
+        ];
+        for f in &files {
+            let mut location = ObjectStorePath::default();
+            let mut parts: Vec<_> = f.split('/').collect();
+            let file_name = parts.pop().unwrap();
+            location.push_all_dirs(&parts);
+            location.set_file_name(file_name);
+
+            let stream_data = std::io::Result::Ok(data.clone());
+            storage
+                .put(
+                    &location,
+                    futures::stream::once(async move { stream_data }),
+                    data.len(),
+                )
+                .await?;
+        }
+
+        let mut prefix = ObjectStorePath::default();
+        prefix.push_all_dirs(&["mydb", "wal"]);
+
+        let mut expected_000 = prefix.clone();
+        expected_000.push_dir("000");
+        let mut expected_001 = prefix.clone();
+        expected_001.push_dir("001");
+        let mut expected_location = prefix.clone();
+        expected_location.set_file_name("foo.test");
+
+        let result = storage.list_with_delimiter(&prefix).await?;
+        assert_eq!(result.common_prefixes, vec![expected_000, expected_001]);
+        assert_eq!(result.objects.len(), 1);
+        assert_eq!(result.objects[0].location, expected_location);
+        assert_eq!(result.objects[0].size, data.len());
+
+        // List with a prefix containing a partial "file name"
+        let mut prefix = ObjectStorePath::default();
+        prefix.push_all_dirs(&["mydb", "wal", "000", "000"]);
+        prefix.set_file_name("001");
+
+        let mut expected_location = ObjectStorePath::default();
+        expected_location.push_all_dirs(&["mydb", "wal", "000", "000"]);
+        expected_location.set_file_name("001.segment");
+
+        let result = storage.list_with_delimiter(&prefix).await?;
+        assert!(result.common_prefixes.is_empty());
+        assert_eq!(result.objects.len(), 1);
+        assert_eq!(result.objects[0].location, expected_location);
+
+        // Nothing is listed under a prefix that doesn't exist
+        let mut prefix = ObjectStorePath::default();
+        prefix.push_dir("otherdb");
+        let result = storage.list_with_delimiter(&prefix).await?;
+        assert!(result.common_prefixes.is_empty());
+        assert!(result.objects.is_empty());
+
         Ok(())
     }
 
@@ -175,6 +660,31 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn failed_multipart_put_leaves_nothing() -> Result<()> {
+        let root = TempDir::new()?;
+        let storage = ObjectStore::new_file(File::new(root.path()));
+
+        let mut location = ObjectStorePath::default();
+        location.push_dir("mydb");
+        location.set_file_name("snapshot");
+        let bytes = stream::iter(vec![
+            Ok(Bytes::from("arbitrary data")),
+            Err(io::Error::new(io::ErrorKind::Other, "stream failed")),
+        ]);
+        let res = storage.put_multipart(&location, bytes).await;
+        assert!(matches!(
+            res.err().unwrap(),
+            Error::UnableToReadDataToPut { .. }
+        ));
+
+        // neither the object nor its temporary file is left
+        assert!(storage.head(&location).await.is_err());
+        assert_eq!(std::fs::read_dir(root.path().join("mydb"))?.count(), 0);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn creates_dir_if_not_present() -> Result<()> {
         let root = TempDir::new()?;
@@ -1,20 +1,127 @@
 //! This module contains the IOx implementation for using Google Cloud Storage
 //! as the object store.
 use crate::{
-    path::{cloud::CloudConverter, ObjectStorePath},
-    DataDoesNotMatchLength, Result, UnableToDeleteDataFromGcs, UnableToDeleteDataFromGcs2,
-    UnableToGetDataFromGcs, UnableToGetDataFromGcs2, UnableToListDataFromGcs,
-    UnableToListDataFromGcs2, UnableToPutDataToGcs,
+    next_part,
+    path::{cloud::CloudConverter, ObjectStorePath, DELIMITER},
+    slice_object, DataDoesNotMatchLength, Error, InvalidSizeFromGcs, ListResult, NotFound,
+    ObjectMeta, Result, UnableToAuthenticateToGcs, UnableToComposeDataInGcs,
+    UnableToComposeDataInGcs2, UnableToCopyDataInGcs, UnableToCopyDataInGcs2,
+    UnableToDeleteDataFromGcs, UnableToDeleteDataFromGcs2, UnableToGetDataFromGcs,
+    UnableToGetDataFromGcs2, UnableToHeadDataFromGcs, UnableToHeadDataFromGcs2,
+    UnableToListDataFromGcs, UnableToListDataFromGcs2, UnableToListDataFromGcs3,
+    UnableToPutDataToGcs, UnableToPutDataToGcs2, UnableToReadDataToPut,
 };
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use cloud_storage::object::{ComposeRequest, SourceObject};
 use futures::{Stream, TryStreamExt};
-use snafu::{ensure, ResultExt};
-use std::io;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{convert::TryFrom, io, ops::Range};
+
+/// The size of the parts of objects of unknown length, each of which is
+/// uploaded as its own object before they are composed
+const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// The most objects Google Cloud Storage composes in one request
+const MAX_COMPOSE_SOURCES: usize = 32;
+
+/// The endpoint of the JSON API of Google Cloud Storage, used for the
+/// requests the client of `cloud_storage` can't make
+const JSON_API_URL: &str = "https://storage.googleapis.com/storage/v1";
+
+/// The endpoint exchanging the signed assertions of service accounts for
+/// access tokens
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+/// The scope of the access tokens used for the JSON API
+const TOKEN_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_only";
+
+/// How long the access tokens are requested for, in seconds
+const TOKEN_LIFETIME_SECS: i64 = 3600;
+
+/// How long before it expires an access token is replaced, in seconds
+const TOKEN_EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// The errors of getting an access token for the JSON API
+#[derive(Debug, Snafu)]
+#[allow(missing_docs)]
+pub enum AuthError {
+    #[snafu(display("The SERVICE_ACCOUNT environment variable must be set"))]
+    MissingServiceAccount,
+    #[snafu(display("Unable to read the service account file {}: {}", path, source))]
+    ReadingServiceAccount { path: String, source: io::Error },
+    #[snafu(display("Unable to parse the service account file {}: {}", path, source))]
+    ParsingServiceAccount {
+        path: String,
+        source: serde_json::Error,
+    },
+    #[snafu(display("Unable to sign the access token request: {}", source))]
+    SigningTokenRequest { source: jsonwebtoken::errors::Error },
+    #[snafu(display("Unable to request an access token: {}", source))]
+    RequestingToken { source: reqwest::Error },
+}
 
 /// Configuration for connecting to [Google Cloud Storage](https://cloud.google.com/storage/).
 #[derive(Debug)]
 pub struct GoogleCloudStorage {
     bucket_name: String,
+    client: reqwest::Client,
+    /// The access token of the requests to the JSON API, once requested
+    token: tokio::sync::Mutex<Option<AccessToken>>,
+}
+
+/// An access token of the service account, for the JSON API
+#[derive(Debug)]
+struct AccessToken {
+    value: String,
+    /// When the token expires, in seconds since the epoch
+    expires_at: i64,
+}
+
+/// The fields of the service account file needed to sign token requests
+#[derive(Debug, Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+}
+
+/// The claims of the assertion exchanged for an access token
+#[derive(Debug, Serialize)]
+struct TokenClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// A page of the objects of a bucket listed by the JSON API
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectList {
+    #[serde(default)]
+    items: Vec<ListedObject>,
+    #[serde(default)]
+    prefixes: Vec<String>,
+    next_page_token: Option<String>,
+}
+
+/// An object of an `ObjectList`; the JSON API sends sizes as strings
+#[derive(Debug, Deserialize)]
+struct ListedObject {
+    name: String,
+    updated: String,
+    size: String,
+    etag: String,
 }
 
 impl GoogleCloudStorage {
@@ -22,9 +129,66 @@ impl GoogleCloudStorage {
     pub fn new(bucket_name: impl Into<String>) -> Self {
         Self {
             bucket_name: bucket_name.into(),
+            client: reqwest::Client::new(),
+            token: Default::default(),
         }
     }
 
+    /// Returns an access token for the requests to the JSON API, requesting
+    /// one for the service account of the `SERVICE_ACCOUNT` file, like
+    /// `cloud_storage` does, when there is none yet or it is about to expire
+    async fn access_token(&self) -> Result<String, AuthError> {
+        let mut token = self.token.lock().await;
+        let now = Utc::now().timestamp();
+        if let Some(token) = token.as_ref() {
+            if token.expires_at > now + TOKEN_EXPIRY_MARGIN_SECS {
+                return Ok(token.value.clone());
+            }
+        }
+
+        let path = std::env::var("SERVICE_ACCOUNT")
+            .ok()
+            .context(MissingServiceAccount)?;
+        let data = tokio::fs::read(&path)
+            .await
+            .context(ReadingServiceAccount { path: &path })?;
+        let account: ServiceAccount =
+            serde_json::from_slice(&data).context(ParsingServiceAccount { path: &path })?;
+
+        let claims = TokenClaims {
+            iss: &account.client_email,
+            scope: TOKEN_SCOPE,
+            aud: TOKEN_URL,
+            iat: now,
+            exp: now + TOKEN_LIFETIME_SECS,
+        };
+        let key = EncodingKey::from_rsa_pem(account.private_key.as_bytes())
+            .context(SigningTokenRequest)?;
+        let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)
+            .context(SigningTokenRequest)?;
+
+        let response: TokenResponse = self
+            .client
+            .post(TOKEN_URL)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context(RequestingToken)?
+            .json()
+            .await
+            .context(RequestingToken)?;
+
+        *token = Some(AccessToken {
+            value: response.access_token.clone(),
+            expires_at: now + response.expires_in,
+        });
+        Ok(response.access_token)
+    }
+
     /// Save the provided bytes to the specified location.
     pub async fn put<S>(&self, location: &ObjectStorePath, bytes: S, length: usize) -> Result<()>
     where
@@ -66,6 +230,134 @@ impl GoogleCloudStorage {
         Ok(())
     }
 
+    /// Save the bytes of `bytes`, whose length isn't known up front, to the
+    /// specified location. The client has no resumable uploads, so objects
+    /// larger than a part are uploaded one part at a time as they are read,
+    /// each as its own temporary object, and the parts are then composed
+    /// into the object by Google Cloud Storage.
+    pub async fn put_multipart<S>(&self, location: &ObjectStorePath, bytes: S) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let key = CloudConverter::convert(&location);
+        let mut bytes = Box::pin(bytes);
+        let first_part = next_part(&mut bytes, MULTIPART_PART_SIZE)
+            .await
+            .context(UnableToReadDataToPut)?;
+        let second_part = next_part(&mut bytes, MULTIPART_PART_SIZE)
+            .await
+            .context(UnableToReadDataToPut)?;
+        if second_part.is_empty() {
+            // the whole object fits in a single request
+            return self.create_object(&key, first_part).await;
+        }
+
+        // the parts of concurrent uploads to the same location are told
+        // apart by when their upload started
+        let upload_id = Utc::now().timestamp_nanos();
+        let mut part_keys = Vec::new();
+        let mut part = first_part;
+        let mut next = second_part;
+        let result = async {
+            while !part.is_empty() {
+                let part_key = format!("{}.upload-{}.part-{}", key, upload_id, part_keys.len());
+                self.create_object(&part_key, part).await?;
+                part_keys.push(part_key);
+
+                part = next;
+                next = next_part(&mut bytes, MULTIPART_PART_SIZE)
+                    .await
+                    .context(UnableToReadDataToPut)?;
+            }
+            self.compose(&key, &part_keys).await
+        }
+        .await;
+
+        // the parts are only needed until they are composed. If deleting
+        // one fails, the error of the upload is the one worth returning.
+        for part_key in part_keys {
+            let bucket_name = self.bucket_name.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                cloud_storage::Object::delete(&bucket_name, &part_key)
+            })
+            .await;
+        }
+
+        result
+    }
+
+    /// Uploads `data` as the object `key`
+    async fn create_object(&self, key: &str, data: Bytes) -> Result<()> {
+        let key_copy = key.to_string();
+        let bucket_name = self.bucket_name.clone();
+
+        tokio::task::spawn_blocking(move || {
+            cloud_storage::Object::create(
+                &bucket_name,
+                &data,
+                &key_copy,
+                "application/octet-stream",
+            )
+        })
+        .await
+        .context(UnableToPutDataToGcs {
+            bucket: &self.bucket_name,
+            location: key,
+        })?
+        .context(UnableToPutDataToGcs2 {
+            bucket: &self.bucket_name,
+            location: key,
+        })?;
+
+        Ok(())
+    }
+
+    /// Composes the objects `sources`, in order, into the object `key`. At
+    /// most `MAX_COMPOSE_SOURCES` objects are composed in a request, so
+    /// each request after the first composes the object so far with the
+    /// next sources.
+    async fn compose(&self, key: &str, sources: &[String]) -> Result<()> {
+        let mut composed = false;
+        for sources in sources.chunks(MAX_COMPOSE_SOURCES - 1) {
+            let mut names = Vec::with_capacity(MAX_COMPOSE_SOURCES);
+            if composed {
+                names.push(key.to_string());
+            }
+            names.extend(sources.iter().cloned());
+
+            let request = ComposeRequest {
+                kind: "storage#composeRequest".to_string(),
+                source_objects: names
+                    .into_iter()
+                    .map(|name| SourceObject {
+                        name,
+                        generation: None,
+                        object_preconditions: None,
+                    })
+                    .collect(),
+                destination: None,
+            };
+            let key_copy = key.to_string();
+            let bucket_name = self.bucket_name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                cloud_storage::Object::compose(&bucket_name, &request, &key_copy)
+            })
+            .await
+            .context(UnableToComposeDataInGcs {
+                bucket: &self.bucket_name,
+                location: key,
+            })?
+            .context(UnableToComposeDataInGcs2 {
+                bucket: &self.bucket_name,
+                location: key,
+            })?;
+            composed = true;
+        }
+
+        Ok(())
+    }
+
     /// Return the bytes that are stored at the specified location.
     pub async fn get(
         &self,
@@ -91,6 +383,51 @@ impl GoogleCloudStorage {
         Ok(futures::stream::once(async move { Ok(bytes.into()) }))
     }
 
+    /// Return the bytes in `range` of the object at the specified location.
+    /// The whole object is downloaded, as the client can't request a range.
+    pub async fn get_range(
+        &self,
+        location: &ObjectStorePath,
+        range: Range<usize>,
+    ) -> Result<Bytes> {
+        slice_object(self.get(location).await?, range).await
+    }
+
+    /// Return the metadata of the object at the specified location.
+    pub async fn head(&self, location: &ObjectStorePath) -> Result<ObjectMeta> {
+        let key = CloudConverter::convert(&location);
+        let key_copy = key.clone();
+        let bucket_name = self.bucket_name.clone();
+
+        let object = tokio::task::spawn_blocking(move || {
+            cloud_storage::Object::read(&bucket_name, &key_copy)
+        })
+        .await
+        .context(UnableToHeadDataFromGcs {
+            bucket: &self.bucket_name,
+            location: key.clone(),
+        })?;
+        let object = match object {
+            Ok(object) => object,
+            Err(cloud_storage::Error::Google(response)) if response.error.code == 404 => {
+                return NotFound { location: key }.fail()
+            }
+            Err(source) => {
+                return Err(source).context(UnableToHeadDataFromGcs2 {
+                    bucket: &self.bucket_name,
+                    location: key,
+                })
+            }
+        };
+
+        Ok(ObjectMeta {
+            location: location.clone(),
+            last_modified: object.updated,
+            size: usize::try_from(object.size).expect("unsupported size on this platform"),
+            e_tag: Some(object.etag),
+        })
+    }
+
     /// Delete the object at the specified location.
     pub async fn delete(&self, location: &ObjectStorePath) -> Result<()> {
         let location = CloudConverter::convert(&location);
@@ -113,6 +450,33 @@ impl GoogleCloudStorage {
         Ok(())
     }
 
+    /// Copy the object at `from` to `to`, overwriting any object there. The
+    /// object is copied by Google Cloud Storage.
+    pub async fn copy(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        let from = CloudConverter::convert(&from);
+        let to = CloudConverter::convert(&to);
+        let from_copy = from.clone();
+        let to_copy = to.clone();
+        let bucket_name = self.bucket_name.clone();
+
+        tokio::task::spawn_blocking(move || {
+            cloud_storage::Object::read(&bucket_name, &from_copy)?.copy(&bucket_name, &to_copy)
+        })
+        .await
+        .context(UnableToCopyDataInGcs {
+            bucket: &self.bucket_name,
+            from: from.clone(),
+            to: to.clone(),
+        })?
+        .context(UnableToCopyDataInGcs2 {
+            bucket: &self.bucket_name,
+            from,
+            to,
+        })?;
+
+        Ok(())
+    }
+
     /// List all the objects with the given prefix.
     pub async fn list<'a>(
         &'a self,
@@ -140,13 +504,101 @@ impl GoogleCloudStorage {
                 .collect())
         }))
     }
+
+    /// List objects with the given prefix and a set delimiter of `/`. Returns
+    /// common prefixes (directories) in addition to object metadata, from a
+    /// page of at most `max_results` objects and common prefixes after the
+    /// page token `token` if any. The client of `cloud_storage` can only
+    /// list every object of a prefix, so the page is requested from the JSON
+    /// API, which groups the objects by the delimiter.
+    pub async fn list_with_delimiter(
+        &self,
+        prefix: &ObjectStorePath,
+        token: Option<&str>,
+        max_results: usize,
+    ) -> Result<ListResult> {
+        let converted_prefix = CloudConverter::convert(prefix);
+        let access_token = self
+            .access_token()
+            .await
+            .context(UnableToAuthenticateToGcs)?;
+
+        let url = format!(
+            "{}/b/{}/o",
+            JSON_API_URL,
+            utf8_percent_encode(&self.bucket_name, NON_ALPHANUMERIC)
+        );
+        let max_results = max_results.to_string();
+        let mut query = vec![
+            ("prefix", converted_prefix.as_str()),
+            ("delimiter", DELIMITER),
+            ("maxResults", max_results.as_str()),
+        ];
+        if let Some(token) = token {
+            query.push(("pageToken", token));
+        }
+
+        let list: ObjectList = self
+            .client
+            .get(&url)
+            .bearer_auth(access_token)
+            .query(&query)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context(UnableToListDataFromGcs3 {
+                bucket: &self.bucket_name,
+            })?
+            .json()
+            .await
+            .context(UnableToListDataFromGcs3 {
+                bucket: &self.bucket_name,
+            })?;
+
+        let objects = list
+            .items
+            .into_iter()
+            .map(|object| {
+                let last_modified = DateTime::parse_from_rfc3339(&object.updated)
+                    .map_err(|err| Error::UnableToParseLastModifiedTime {
+                        value: object.updated.clone(),
+                        err,
+                    })?
+                    .with_timezone(&Utc);
+                let size = object.size.parse().context(InvalidSizeFromGcs {
+                    location: &object.name,
+                    size: &object.size,
+                })?;
+
+                Ok(ObjectMeta {
+                    location: ObjectStorePath::from_cloud_unchecked(object.name),
+                    last_modified,
+                    size,
+                    e_tag: Some(object.etag),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ListResult {
+            next_token: list.next_page_token,
+            common_prefixes: list
+                .prefixes
+                .into_iter()
+                .map(ObjectStorePath::from_cloud_unchecked)
+                .collect(),
+            objects,
+        })
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
         path::ObjectStorePath,
-        tests::{get_nonexistent_object, put_get_delete_list},
+        tests::{
+            get_nonexistent_object, list_with_delimiter, list_with_delimiter_paginated,
+            put_get_delete_list,
+        },
         Error, GoogleCloudStorage, ObjectStore,
     };
     use bytes::Bytes;
@@ -192,6 +644,8 @@ mod test {
         let integration =
             ObjectStore::new_google_cloud_storage(GoogleCloudStorage::new(&bucket_name));
         put_get_delete_list(&integration).await?;
+        list_with_delimiter(&integration).await?;
+        list_with_delimiter_paginated(&integration).await?;
         Ok(())
     }
 
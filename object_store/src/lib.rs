@@ -10,10 +10,17 @@
 //! # object_store
 //!
 //! This crate provides APIs for interacting with object storage services. It
-//! currently supports PUT, GET, DELETE, and list for Google Cloud Storage,
-//! Amazon S3, in-memory and local file storage.
+//! currently supports PUT (of objects of known or unknown length), GET (of
+//! whole objects or byte ranges), HEAD, DELETE, copy, rename and list
+//! (optionally by delimiter and in pages) for Google Cloud Storage, Amazon
+//! S3, Azure Blob Storage, in-memory and local file storage.
 //!
-//! Future compatibility will include Azure Blob Storage, Minio, and Ceph.
+//! Conditional PUTs, only if there is no object yet (`put_if_not_exists`) or
+//! only if the object has a given entity tag (`put_if_match`), are supported
+//! by Amazon S3, Azure Blob Storage, in-memory and local file storage. Google
+//! Cloud Storage fails them with `Error::ConditionalPutNotSupported`.
+//!
+//! Future compatibility will include Minio and Ceph.
 
 pub mod aws;
 pub mod azure;
@@ -27,13 +34,14 @@ use azure::MicrosoftAzure;
 use disk::File;
 use gcp::GoogleCloudStorage;
 use memory::InMemory;
-use path::ObjectStorePath;
+use path::{cloud::CloudConverter, ObjectStorePath};
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use chrono::{DateTime, Utc};
-use futures::{Stream, StreamExt, TryStreamExt};
-use snafu::Snafu;
-use std::{io, path::PathBuf};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use snafu::{ensure, ResultExt, Snafu};
+use std::{io, ops::Range, path::PathBuf};
 
 /// Universal interface to multiple object store services.
 #[derive(Debug)]
@@ -82,6 +90,101 @@ impl ObjectStore {
         Ok(())
     }
 
+    /// Save the provided bytes to the specified location only if there is
+    /// no object there yet, failing with `Error::AlreadyExists` otherwise,
+    /// so that of several writers racing to create the same object, such as
+    /// a lock, exactly one succeeds. The client of Google Cloud Storage
+    /// can't send preconditions, so it fails with
+    /// `Error::ConditionalPutNotSupported` rather than risk overwriting an
+    /// object.
+    pub async fn put_if_not_exists<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            AmazonS3(s3) => s3.put_if_not_exists(location, bytes, length).await?,
+            GoogleCloudStorage(_) => ConditionalPutNotSupported {
+                integration: "Google Cloud Storage",
+            }
+            .fail()?,
+            InMemory(in_mem) => in_mem.put_if_not_exists(location, bytes, length).await?,
+            File(file) => file.put_if_not_exists(location, bytes, length).await?,
+            MicrosoftAzure(azure) => azure.put_if_not_exists(location, bytes, length).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Save the provided bytes to the specified location only if the object
+    /// there has the entity tag `e_tag`, as returned by `head`, failing
+    /// with `Error::PreconditionFailed` otherwise, so that of several
+    /// writers updating the same object from the same version, exactly one
+    /// succeeds. Like `put_if_not_exists`, it isn't supported by Google
+    /// Cloud Storage.
+    pub async fn put_if_match<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+        e_tag: &str,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            AmazonS3(s3) => s3.put_if_match(location, bytes, length, e_tag).await?,
+            GoogleCloudStorage(_) => ConditionalPutNotSupported {
+                integration: "Google Cloud Storage",
+            }
+            .fail()?,
+            InMemory(in_mem) => in_mem.put_if_match(location, bytes, length, e_tag).await?,
+            File(file) => file.put_if_match(location, bytes, length, e_tag).await?,
+            MicrosoftAzure(azure) => azure.put_if_match(location, bytes, length, e_tag).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Save the bytes of `bytes`, whose length isn't known up front, such
+    /// as a file being written, to the specified location. The cloud
+    /// integrations receive large objects in parts as they are read, and
+    /// local files are written as they are read; the object is only seen at
+    /// the location once it is complete. The in-memory integration reads
+    /// the whole object before saving it.
+    pub async fn put_multipart<S>(&self, location: &ObjectStorePath, bytes: S) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            AmazonS3(s3) => s3.put_multipart(location, bytes).await?,
+            MicrosoftAzure(azure) => azure.put_multipart(location, bytes).await?,
+            GoogleCloudStorage(gcs) => gcs.put_multipart(location, bytes).await?,
+            File(file) => file.put_multipart(location, bytes).await?,
+            InMemory(_) => {
+                let data = bytes
+                    .map_ok(|b| BytesMut::from(&b[..]))
+                    .try_concat()
+                    .await
+                    .context(UnableToReadDataToPut)?
+                    .freeze();
+                let length = data.len();
+                let data = io::Result::Ok(data);
+                self.put(location, stream::once(async move { data }), length)
+                    .await?
+            }
+        }
+
+        Ok(())
+    }
+
     /// Return the bytes that are stored at the specified location.
     pub async fn get(
         &self,
@@ -98,6 +201,38 @@ impl ObjectStore {
         .err_into())
     }
 
+    /// Return the bytes in `range` of the object at the specified location,
+    /// such as the footer of a Parquet file. Only the range is fetched from
+    /// the integrations with range requests; the others fetch the whole
+    /// object.
+    pub async fn get_range(
+        &self,
+        location: &ObjectStorePath,
+        range: Range<usize>,
+    ) -> Result<Bytes> {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            AmazonS3(s3) => s3.get_range(location, range).await,
+            GoogleCloudStorage(gcs) => gcs.get_range(location, range).await,
+            InMemory(in_mem) => in_mem.get_range(location, range).await,
+            File(file) => file.get_range(location, range).await,
+            MicrosoftAzure(azure) => azure.get_range(location, range).await,
+        }
+    }
+
+    /// Return the metadata of the object at the specified location, without
+    /// fetching its bytes. Fails if there is no object there.
+    pub async fn head(&self, location: &ObjectStorePath) -> Result<ObjectMeta> {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            AmazonS3(s3) => s3.head(location).await,
+            GoogleCloudStorage(gcs) => gcs.head(location).await,
+            InMemory(in_mem) => in_mem.head(location).await,
+            File(file) => file.head(location).await,
+            MicrosoftAzure(azure) => azure.head(location).await,
+        }
+    }
+
     /// Delete the object at the specified location.
     pub async fn delete(&self, location: &ObjectStorePath) -> Result<()> {
         use ObjectStoreIntegration::*;
@@ -112,6 +247,45 @@ impl ObjectStore {
         Ok(())
     }
 
+    /// Copy the object at `from` to `to`, overwriting any object there. The
+    /// cloud integrations copy the object server side, without downloading
+    /// it.
+    pub async fn copy(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            AmazonS3(s3) => s3.copy(from, to).await?,
+            GoogleCloudStorage(gcs) => gcs.copy(from, to).await?,
+            InMemory(in_mem) => in_mem.copy(from, to).await?,
+            File(file) => file.copy(from, to).await?,
+            MicrosoftAzure(azure) => azure.copy(from, to).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Move the object at `from` to `to`, overwriting any object there, such
+    /// as to promote a temporary file once it is complete. The cloud
+    /// integrations, which can't rename objects, copy the object server side
+    /// and then delete it, so it is briefly at both locations.
+    pub async fn rename(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            InMemory(in_mem) => in_mem.rename(from, to).await?,
+            File(file) => file.rename(from, to).await?,
+            AmazonS3(_) | GoogleCloudStorage(_) | MicrosoftAzure(_) => {
+                // deleting an object copied onto itself would lose it
+                if CloudConverter::convert(from) == CloudConverter::convert(to) {
+                    self.head(from).await?;
+                } else {
+                    self.copy(from, to).await?;
+                    self.delete(from).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// List all the objects with the given prefix.
     pub async fn list<'a>(
         &'a self,
@@ -130,18 +304,36 @@ impl ObjectStore {
 
     /// List objects with the given prefix and an implementation specific
     /// delimiter. Returns common prefixes (directories) in addition to object
-    /// metadata.
+    /// metadata. Only the first page of up to `MAX_LIST_RESULTS` results is
+    /// returned; use `list_with_delimiter_paginated` to get the others.
     pub async fn list_with_delimiter<'a>(
         &'a self,
         prefix: &'a ObjectStorePath,
     ) -> Result<ListResult> {
+        self.list_with_delimiter_paginated(prefix, None, MAX_LIST_RESULTS)
+            .await
+    }
+
+    /// List a page of the objects with the given prefix and an
+    /// implementation specific delimiter, like `list_with_delimiter`. The
+    /// page has at most `max_results` objects and common prefixes, up to
+    /// `MAX_LIST_RESULTS`. Pass the `next_token` of a page as `token` to
+    /// get the next page; the last page has no `next_token`.
+    pub async fn list_with_delimiter_paginated<'a>(
+        &'a self,
+        prefix: &'a ObjectStorePath,
+        token: Option<&'a str>,
+        max_results: usize,
+    ) -> Result<ListResult> {
+        let max_results = max_results.max(1).min(MAX_LIST_RESULTS);
+
         use ObjectStoreIntegration::*;
         match &self.0 {
-            AmazonS3(s3) => s3.list_with_delimiter(prefix, &None).await,
-            GoogleCloudStorage(_gcs) => unimplemented!(),
-            InMemory(in_mem) => in_mem.list_with_delimiter(prefix, &None).await,
-            File(_file) => unimplemented!(),
-            MicrosoftAzure(_azure) => unimplemented!(),
+            AmazonS3(s3) => s3.list_with_delimiter(prefix, token, max_results).await,
+            GoogleCloudStorage(gcs) => gcs.list_with_delimiter(prefix, token, max_results).await,
+            InMemory(in_mem) => in_mem.list_with_delimiter(prefix, token, max_results).await,
+            File(file) => file.list_with_delimiter(prefix, token, max_results).await,
+            MicrosoftAzure(azure) => azure.list_with_delimiter(prefix, token, max_results).await,
         }
     }
 
@@ -176,10 +368,14 @@ pub enum ObjectStoreIntegration {
     MicrosoftAzure(Box<MicrosoftAzure>),
 }
 
+/// The most objects and common prefixes in a page of the results of
+/// `list_with_delimiter`, which is the limit of the cloud APIs
+pub const MAX_LIST_RESULTS: usize = 1000;
+
 /// Result of a list call that includes objects, prefixes (directories) and a
 /// token for the next set of results. Individual results sets are limited to
 /// 1,000 objects.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ListResult {
     /// Token passed to the API for the next page of list results.
     pub next_token: Option<String>,
@@ -198,6 +394,115 @@ pub struct ObjectMeta {
     pub last_modified: DateTime<Utc>,
     /// The size in bytes of the object
     pub size: usize,
+    /// The entity tag of the contents of the object, if the integration
+    /// has one, for conditional puts with `put_if_match`
+    pub e_tag: Option<String>,
+}
+
+/// Cuts the page of at most `max_results` objects and common prefixes after
+/// `token` out of the complete results `result`, for the integrations that
+/// list all the objects at once. The objects and common prefixes are ordered
+/// by their cloud paths, and the token of a page is the path of its last
+/// object or common prefix.
+fn paginate(result: ListResult, token: Option<&str>, max_results: usize) -> ListResult {
+    enum Entry {
+        Object(ObjectMeta),
+        CommonPrefix(ObjectStorePath),
+    }
+
+    let objects = result.objects.into_iter().map(|object| {
+        let key = path::cloud::CloudConverter::convert(&object.location);
+        (key, Entry::Object(object))
+    });
+    let common_prefixes = result.common_prefixes.into_iter().map(|prefix| {
+        let key = path::cloud::CloudConverter::convert(&prefix);
+        (key, Entry::CommonPrefix(prefix))
+    });
+    let mut entries: Vec<_> = objects
+        .chain(common_prefixes)
+        .filter(|(key, _)| token.map_or(true, |token| key.as_str() > token))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut page = ListResult::default();
+    if entries.len() > max_results {
+        entries.truncate(max_results);
+        page.next_token = entries.last().map(|(key, _)| key.clone());
+    }
+    for (_, entry) in entries {
+        match entry {
+            Entry::Object(object) => page.objects.push(object),
+            Entry::CommonPrefix(prefix) => page.common_prefixes.push(prefix),
+        }
+    }
+    page
+}
+
+/// The condition of a conditional put, for the integrations sending it as a
+/// header
+#[derive(Debug, Clone, Copy)]
+enum PutCondition<'a> {
+    /// There is no object at the location
+    NotExists,
+    /// The object at the location has this entity tag
+    Match(&'a str),
+}
+
+/// The characters encoded in the source of copies: all but the unreserved
+/// characters of URLs and the delimiter
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Returns the bytes in `range` of the whole object read from `stream`, for
+/// the integrations without range requests
+async fn slice_object<S>(stream: S, range: Range<usize>) -> Result<Bytes>
+where
+    S: Stream<Item = Result<Bytes>>,
+{
+    let data = stream
+        .map_ok(|b| BytesMut::from(&b[..]))
+        .try_concat()
+        .await?;
+    slice_range(data.freeze(), range)
+}
+
+/// Reads the next part of an object of unknown length from `stream`: at
+/// least `part_size` bytes, or the rest of the stream if it is shorter.
+/// The part is empty once the whole stream has been read.
+async fn next_part<S>(stream: &mut S, part_size: usize) -> io::Result<Bytes>
+where
+    S: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    let mut part = BytesMut::new();
+    while part.len() < part_size {
+        match stream.try_next().await? {
+            Some(bytes) => part.extend_from_slice(&bytes),
+            None => break,
+        }
+    }
+    Ok(part.freeze())
+}
+
+/// Returns the bytes in `range` of the object `data`
+fn slice_range(data: Bytes, range: Range<usize>) -> Result<Bytes> {
+    check_range(&range, data.len())?;
+    Ok(data.slice(range))
+}
+
+/// Fails unless `range` is within the bounds of an object of `size` bytes
+fn check_range(range: &Range<usize>, size: usize) -> Result<()> {
+    ensure!(
+        range.start <= range.end && range.end <= size,
+        RangeOutOfBounds {
+            range: range.clone(),
+            size
+        }
+    );
+    Ok(())
 }
 
 /// A specialized `Result` for object store-related errors
@@ -211,17 +516,61 @@ pub enum Error {
         expected: usize,
         actual: usize,
     },
+    #[snafu(display("An object already exists at {}", location))]
+    AlreadyExists {
+        location: String,
+    },
+    #[snafu(display("No object exists at {}", location))]
+    NotFound {
+        location: String,
+    },
+    #[snafu(display("The object at {} doesn't have the expected entity tag", location))]
+    PreconditionFailed {
+        location: String,
+    },
+    #[snafu(display("{} doesn't support conditional puts", integration))]
+    ConditionalPutNotSupported {
+        integration: &'static str,
+    },
+    #[snafu(display(
+        "Range {:?} is out of the bounds of an object of {} bytes",
+        range,
+        size
+    ))]
+    RangeOutOfBounds {
+        range: Range<usize>,
+        size: usize,
+    },
     #[snafu(display("Unable to parse last modified time {}: {}", value, err))]
     UnableToParseLastModifiedTime {
         value: String,
         err: chrono::ParseError,
     },
+    #[snafu(display("Unable to read the data to put: {}", source))]
+    UnableToReadDataToPut {
+        source: io::Error,
+    },
 
     UnableToPutDataToGcs {
         source: tokio::task::JoinError,
         bucket: String,
         location: String,
     },
+    UnableToPutDataToGcs2 {
+        source: cloud_storage::Error,
+        bucket: String,
+        location: String,
+    },
+    UnableToComposeDataInGcs {
+        source: tokio::task::JoinError,
+        bucket: String,
+        location: String,
+    },
+    UnableToComposeDataInGcs2 {
+        source: cloud_storage::Error,
+        bucket: String,
+        location: String,
+    },
     UnableToListDataFromGcs {
         source: tokio::task::JoinError,
         bucket: String,
@@ -230,11 +579,47 @@ pub enum Error {
         source: cloud_storage::Error,
         bucket: String,
     },
+    UnableToListDataFromGcs3 {
+        source: reqwest::Error,
+        bucket: String,
+    },
+    #[snafu(display("Unable to authenticate to GCS: {}", source))]
+    UnableToAuthenticateToGcs {
+        source: gcp::AuthError,
+    },
+    #[snafu(display("Invalid size {} of GCS object {}: {}", size, location, source))]
+    InvalidSizeFromGcs {
+        source: std::num::ParseIntError,
+        location: String,
+        size: String,
+    },
+    UnableToHeadDataFromGcs {
+        source: tokio::task::JoinError,
+        bucket: String,
+        location: String,
+    },
+    UnableToHeadDataFromGcs2 {
+        source: cloud_storage::Error,
+        bucket: String,
+        location: String,
+    },
     UnableToDeleteDataFromGcs {
         source: tokio::task::JoinError,
         bucket: String,
         location: String,
     },
+    UnableToCopyDataInGcs {
+        source: tokio::task::JoinError,
+        bucket: String,
+        from: String,
+        to: String,
+    },
+    UnableToCopyDataInGcs2 {
+        source: cloud_storage::Error,
+        bucket: String,
+        from: String,
+        to: String,
+    },
     UnableToDeleteDataFromGcs2 {
         source: cloud_storage::Error,
         bucket: String,
@@ -261,11 +646,22 @@ pub enum Error {
         bucket: String,
         location: String,
     },
+    UnableToHeadDataFromS3 {
+        source: rusoto_core::RusotoError<rusoto_s3::HeadObjectError>,
+        bucket: String,
+        location: String,
+    },
     UnableToDeleteDataFromS3 {
         source: rusoto_core::RusotoError<rusoto_s3::DeleteObjectError>,
         bucket: String,
         location: String,
     },
+    UnableToCopyDataInS3 {
+        source: rusoto_core::RusotoError<rusoto_s3::CopyObjectError>,
+        bucket: String,
+        from: String,
+        to: String,
+    },
     NoDataFromS3 {
         bucket: String,
         location: String,
@@ -284,6 +680,26 @@ pub enum Error {
         source: rusoto_core::RusotoError<rusoto_s3::ListObjectsV2Error>,
         bucket: String,
     },
+    UnableToStartMultipartUploadToS3 {
+        source: rusoto_core::RusotoError<rusoto_s3::CreateMultipartUploadError>,
+        bucket: String,
+        location: String,
+    },
+    NoUploadIdFromS3 {
+        bucket: String,
+        location: String,
+    },
+    UnableToUploadPartToS3 {
+        source: rusoto_core::RusotoError<rusoto_s3::UploadPartError>,
+        bucket: String,
+        location: String,
+        part_number: i64,
+    },
+    UnableToCompleteMultipartUploadToS3 {
+        source: rusoto_core::RusotoError<rusoto_s3::CompleteMultipartUploadError>,
+        bucket: String,
+        location: String,
+    },
 
     UnableToPutDataInMemory {
         source: std::io::Error,
@@ -298,13 +714,47 @@ pub enum Error {
         source: azure_sdk_core::errors::AzureError,
         location: String,
     },
+    UnableToHeadDataFromAzure {
+        source: azure_sdk_core::errors::AzureError,
+        location: String,
+    },
     UnableToDeleteDataFromAzure {
         source: azure_sdk_core::errors::AzureError,
         location: String,
     },
+    UnableToCopyDataInAzure {
+        source: azure_sdk_core::errors::AzureError,
+        from: String,
+        to: String,
+    },
+    #[snafu(display(
+        "Copy of {} to {} in Azure ended with status {}: {}",
+        from,
+        to,
+        status,
+        description
+    ))]
+    CopyNotCompletedInAzure {
+        from: String,
+        to: String,
+        status: String,
+        description: String,
+    },
     UnableToListDataFromAzure {
         source: azure_sdk_core::errors::AzureError,
     },
+    #[snafu(display("Invalid list of blobs from Azure: {}", details))]
+    InvalidListFromAzure {
+        details: String,
+    },
+    UnableToPutBlockToAzure {
+        source: azure_sdk_core::errors::AzureError,
+        location: String,
+    },
+    UnableToPutBlockListToAzure {
+        source: azure_sdk_core::errors::AzureError,
+        location: String,
+    },
 
     #[snafu(display("Unable to create file {}: {}", path.display(), err))]
     UnableToCreateFile {
@@ -326,11 +776,54 @@ pub enum Error {
         source: io::Error,
         path: PathBuf,
     },
+    #[snafu(display("Unable to read metadata of file {}: {}", path.display(), source))]
+    UnableToReadMetadata {
+        source: io::Error,
+        path: PathBuf,
+    },
     #[snafu(display("Unable to delete file {}: {}", path.display(), source))]
     UnableToDeleteFile {
         source: io::Error,
         path: PathBuf,
     },
+    #[snafu(display(
+        "Unable to copy file {} to {}: {}",
+        from.display(),
+        to.display(),
+        source
+    ))]
+    UnableToCopyFile {
+        source: io::Error,
+        from: PathBuf,
+        to: PathBuf,
+    },
+    #[snafu(display(
+        "Unable to rename file {} to {}: {}",
+        from.display(),
+        to.display(),
+        source
+    ))]
+    UnableToRenameFile {
+        source: io::Error,
+        from: PathBuf,
+        to: PathBuf,
+    },
+    #[snafu(display(
+        "Unable to link file {} to {}: {}",
+        from.display(),
+        to.display(),
+        source
+    ))]
+    UnableToLinkFile {
+        source: io::Error,
+        from: PathBuf,
+        to: PathBuf,
+    },
+    #[snafu(display("Unable to sync dir {}: {}", path.display(), source))]
+    UnableToSyncDir {
+        source: io::Error,
+        path: PathBuf,
+    },
     #[snafu(display("Unable to list directory {}: {}", path.display(), source))]
     UnableToListDirectory {
         source: io::Error,
@@ -346,8 +839,11 @@ pub enum Error {
     },
 }
 
-#[cfg(test)]
-mod tests {
+/// The behavior every object store integration must have, shared by the
+/// tests of the integrations. Enable the `test_suite` feature to run them
+/// against a store configured by another crate.
+#[cfg(any(test, feature = "test_suite"))]
+pub mod tests {
     use super::*;
     use futures::stream;
 
@@ -367,7 +863,9 @@ mod tests {
             .await
     }
 
-    pub(crate) async fn put_get_delete_list(storage: &ObjectStore) -> Result<()> {
+    /// Checks putting, getting, listing and deleting an object. Expects the
+    /// store to be empty.
+    pub async fn put_get_delete_list(storage: &ObjectStore) -> Result<()> {
         delete_fixtures(storage).await;
 
         let content_list = flatten_list_stream(storage, None).await?;
@@ -415,7 +913,70 @@ mod tests {
             .await?;
         assert_eq!(&*read_data, data);
 
+        let meta = storage.head(&location).await?;
+        assert_eq!(meta.location, location);
+        assert_eq!(meta.size, data.len());
+
+        let read_range = storage.get_range(&location, 3..7).await?;
+        assert_eq!(read_range, data.slice(3..7));
+        // ranges past the end of the object aren't truncated
+        assert!(storage.get_range(&location, 10..20).await.is_err());
+        assert!(storage.get_range(&location, 20..20).await.is_err());
+        assert!(storage.get_range(&location, 0..0).await?.is_empty());
+
+        // objects of unknown length, large enough to be put in several
+        // parts by the integrations with multipart uploads
+        let mut multipart_location = location.clone();
+        multipart_location.set_file_name("test_file.multipart");
+        let chunk = Bytes::from(vec![7_u8; 1024 * 1024]);
+        for &chunks in &[1, 6] {
+            let stream_data: Vec<_> = (0..chunks)
+                .map(|_| std::io::Result::Ok(chunk.clone()))
+                .collect();
+            storage
+                .put_multipart(&multipart_location, stream::iter(stream_data))
+                .await?;
+
+            let read_data = storage
+                .get(&multipart_location)
+                .await?
+                .map_ok(|b| bytes::BytesMut::from(&b[..]))
+                .try_concat()
+                .await?;
+            assert_eq!(read_data.len(), chunks * chunk.len());
+            assert!(read_data.iter().all(|&b| b == 7));
+        }
+        storage.delete(&multipart_location).await?;
+
+        // copies are independent of the original, and renames leave nothing
+        // behind
+        let mut copy_location = location.clone();
+        copy_location.set_file_name("test_file.copy");
+        let mut renamed_location = ObjectStorePath::default();
+        renamed_location.push_dir("test_renamed");
+        renamed_location.set_file_name("test_file.json");
+        storage.copy(&location, &copy_location).await?;
+        storage.rename(&copy_location, &renamed_location).await?;
+        let content_list = flatten_list_stream(storage, None).await?;
+        assert_eq!(content_list.len(), 2, "{:?}", content_list);
+        assert!(content_list.contains(&location));
+        assert!(content_list.contains(&renamed_location));
+        let read_data = storage
+            .get(&renamed_location)
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        assert_eq!(&*read_data, data);
+
+        // renaming an object onto itself keeps it
+        storage.rename(&renamed_location, &renamed_location).await?;
+        assert!(storage.head(&renamed_location).await.is_ok());
+        storage.delete(&renamed_location).await?;
+
         storage.delete(&location).await?;
+        let err = storage.head(&location).await.unwrap_err();
+        assert!(matches!(err, Error::NotFound { .. }), "{}", err);
 
         let content_list = flatten_list_stream(storage, None).await?;
         assert!(content_list.is_empty());
@@ -423,7 +984,9 @@ mod tests {
         Ok(())
     }
 
-    pub(crate) async fn list_with_delimiter(storage: &ObjectStore) -> Result<()> {
+    /// Checks listing objects and common prefixes below a prefix. Expects
+    /// the store to be empty.
+    pub async fn list_with_delimiter(storage: &ObjectStore) -> Result<()> {
         delete_fixtures(storage).await;
 
         let content_list = flatten_list_stream(storage, None).await?;
@@ -505,7 +1068,185 @@ mod tests {
         Ok(())
     }
 
-    pub(crate) async fn get_nonexistent_object(
+    /// Checks paging through the objects and common prefixes below a
+    /// prefix. Expects the store to be empty.
+    pub async fn list_with_delimiter_paginated(storage: &ObjectStore) -> Result<()> {
+        delete_fixtures(storage).await;
+
+        let data = Bytes::from("arbitrary data");
+        let files: Vec<_> = [
+            "mydb/a/000.segment",
+            "mydb/b/000.segment",
+            "mydb/b/001.segment",
+            "mydb/c",
+            "mydb/d",
+            "mydb/e/f/000.segment",
+        ]
+        .iter()
+        .map(|&s| ObjectStorePath::from_cloud_unchecked(s))
+        .collect();
+
+        for f in &files {
+            let stream_data = std::io::Result::Ok(data.clone());
+            storage
+                .put(
+                    f,
+                    futures::stream::once(async move { stream_data }),
+                    data.len(),
+                )
+                .await?;
+        }
+
+        let mut prefix = ObjectStorePath::default();
+        prefix.push_dir("mydb");
+
+        let mut pages = vec![];
+        let mut token = None;
+        loop {
+            let result = storage
+                .list_with_delimiter_paginated(&prefix, token.as_deref(), 2)
+                .await?;
+            let mut page: Vec<_> = result
+                .common_prefixes
+                .iter()
+                .chain(result.objects.iter().map(|object| &object.location))
+                .map(|path| storage.convert_path(path))
+                .collect();
+            page.sort();
+            pages.push(page);
+
+            token = result.next_token;
+            if token.is_none() {
+                break;
+            }
+        }
+
+        let dir = |name: &str| {
+            let mut path = prefix.clone();
+            path.push_dir(name);
+            storage.convert_path(&path)
+        };
+        let file = |name: &str| {
+            let mut path = prefix.clone();
+            path.set_file_name(name);
+            storage.convert_path(&path)
+        };
+        assert_eq!(
+            pages,
+            vec![
+                vec![dir("a"), dir("b")],
+                vec![file("c"), file("d")],
+                vec![dir("e")],
+            ]
+        );
+
+        for f in &files {
+            storage.delete(f).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a conditional put creates an object only once, by
+    /// stores supporting conditional puts. Expects the store to be empty.
+    pub async fn put_if_not_exists(storage: &ObjectStore) -> Result<()> {
+        let location = ObjectStorePath::from_cloud_unchecked("mydb/lock");
+        let put = |data: &'static str| {
+            let stream_data = std::io::Result::Ok(Bytes::from(data));
+            storage.put_if_not_exists(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                data.len(),
+            )
+        };
+
+        put("first").await?;
+        let err = put("second").await.unwrap_err();
+        assert!(
+            matches!(err, super::Error::AlreadyExists { .. }),
+            "Expected an AlreadyExists error, got {:?}",
+            err
+        );
+
+        // the object isn't overwritten
+        let data = storage
+            .get(&location)
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        assert_eq!(&data[..], b"first");
+
+        // nor is it created again until deleted
+        storage.delete(&location).await?;
+        put("third").await?;
+        storage.delete(&location).await?;
+
+        Ok(())
+    }
+
+    /// Checks that a conditional put only replaces the version of an object
+    /// it was conditioned on. Expects the store to be empty.
+    pub async fn put_if_match(storage: &ObjectStore) -> Result<()> {
+        let location = ObjectStorePath::from_cloud_unchecked("mydb/config");
+        let put = |data: &'static str, e_tag: String| {
+            let location = &location;
+            async move {
+                let stream_data = std::io::Result::Ok(Bytes::from(data));
+                storage
+                    .put_if_match(
+                        location,
+                        futures::stream::once(async move { stream_data }),
+                        data.len(),
+                        &e_tag,
+                    )
+                    .await
+            }
+        };
+        let assert_precondition_failed = |result: super::Result<()>| {
+            let err = result.unwrap_err();
+            assert!(
+                matches!(err, super::Error::PreconditionFailed { .. }),
+                "Expected a PreconditionFailed error, got {:?}",
+                err
+            );
+        };
+
+        // there is no version of a missing object to match
+        assert_precondition_failed(put("first", "\"missing\"".to_string()).await);
+
+        let stream_data = std::io::Result::Ok(Bytes::from("first"));
+        storage
+            .put(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                5,
+            )
+            .await?;
+        let first = storage.head(&location).await?.e_tag.expect("no entity tag");
+
+        put("second", first.clone()).await?;
+        let second = storage.head(&location).await?.e_tag.expect("no entity tag");
+        assert_ne!(first, second);
+
+        // the version replaced can't be matched again
+        assert_precondition_failed(put("third", first).await);
+        let data = storage
+            .get(&location)
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        assert_eq!(&data[..], b"second");
+
+        storage.delete(&location).await?;
+
+        Ok(())
+    }
+
+    /// Gets `location`, or a default path, which mustn't exist, returning
+    /// the error of the store
+    pub async fn get_nonexistent_object(
         storage: &ObjectStore,
         location: Option<ObjectStorePath>,
     ) -> Result<Bytes> {
@@ -533,6 +1274,12 @@ mod tests {
             "mydb/wal/001/001/000.segment",
             "mydb/wal/foo.test",
             "mydb/data/whatevs",
+            "mydb/a/000.segment",
+            "mydb/b/000.segment",
+            "mydb/b/001.segment",
+            "mydb/c",
+            "mydb/d",
+            "mydb/e/f/000.segment",
         ]
         .iter()
         .map(|&s| ObjectStorePath::from_cloud_unchecked(s))
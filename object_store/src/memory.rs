@@ -1,16 +1,22 @@
 //! This module contains the IOx implementation for using memory as the object
 //! store.
 use crate::{
-    path::{parsed::DirsAndFileName, ObjectStorePath},
-    DataDoesNotMatchLength, ListResult, NoDataInMemory, ObjectMeta, Result,
-    UnableToPutDataInMemory,
+    paginate,
+    path::{cloud::CloudConverter, parsed::DirsAndFileName, ObjectStorePath},
+    slice_range, AlreadyExists, DataDoesNotMatchLength, ListResult, NoDataInMemory, NotFound,
+    ObjectMeta, PreconditionFailed, Result, UnableToPutDataInMemory,
 };
 use bytes::Bytes;
 use chrono::Utc;
 use futures::{Stream, TryStreamExt};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::BTreeSet;
-use std::{collections::BTreeMap, io};
+use std::{
+    collections::{btree_map::Entry, hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    io,
+    ops::Range,
+};
 use tokio::sync::RwLock;
 
 /// In-memory storage suitable for testing or for opting out of using a cloud
@@ -61,6 +67,87 @@ impl InMemory {
         Ok(())
     }
 
+    /// Save the provided bytes to the specified location, unless there is
+    /// already an object there.
+    pub async fn put_if_not_exists<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let content = bytes
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .context(UnableToPutDataInMemory)?;
+
+        ensure!(
+            content.len() == length,
+            DataDoesNotMatchLength {
+                actual: content.len(),
+                expected: length,
+            }
+        );
+
+        // the check and the insert are under the same lock, so only one of
+        // concurrent puts inserts the object
+        let mut storage = self.storage.write().await;
+        match storage.entry(location.into()) {
+            Entry::Occupied(_) => AlreadyExists {
+                location: CloudConverter::convert(location),
+            }
+            .fail(),
+            Entry::Vacant(entry) => {
+                entry.insert(content.freeze());
+                Ok(())
+            }
+        }
+    }
+
+    /// Save the provided bytes to the specified location, if the object
+    /// there has the entity tag `e_tag`.
+    pub async fn put_if_match<S>(
+        &self,
+        location: &ObjectStorePath,
+        bytes: S,
+        length: usize,
+        e_tag: &str,
+    ) -> Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let content = bytes
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .context(UnableToPutDataInMemory)?;
+
+        ensure!(
+            content.len() == length,
+            DataDoesNotMatchLength {
+                actual: content.len(),
+                expected: length,
+            }
+        );
+
+        // the check and the replacement are under the same lock, so only one
+        // of concurrent puts replaces the object
+        let mut storage = self.storage.write().await;
+        match storage.get_mut(&location.into()) {
+            Some(data) if content_e_tag(data) == e_tag => {
+                *data = content.freeze();
+                Ok(())
+            }
+            _ => PreconditionFailed {
+                location: CloudConverter::convert(location),
+            }
+            .fail(),
+        }
+    }
+
     /// Return the bytes that are stored at the specified location.
     pub async fn get(
         &self,
@@ -78,12 +165,63 @@ impl InMemory {
         Ok(futures::stream::once(async move { Ok(data) }))
     }
 
+    /// Return the bytes in `range` of the object at the specified location.
+    pub async fn get_range(
+        &self,
+        location: &ObjectStorePath,
+        range: Range<usize>,
+    ) -> Result<Bytes> {
+        let location = location.into();
+        let data = self
+            .storage
+            .read()
+            .await
+            .get(&location)
+            .cloned()
+            .context(NoDataInMemory)?;
+
+        slice_range(data, range)
+    }
+
+    /// Return the metadata of the object at the specified location. Objects
+    /// in memory don't keep when they were modified, so the current time is
+    /// returned.
+    pub async fn head(&self, location: &ObjectStorePath) -> Result<ObjectMeta> {
+        let storage = self.storage.read().await;
+        let data = storage.get(&location.into()).context(NotFound {
+            location: CloudConverter::convert(location),
+        })?;
+
+        Ok(ObjectMeta {
+            location: location.clone(),
+            last_modified: Utc::now(),
+            size: data.len(),
+            e_tag: Some(content_e_tag(data)),
+        })
+    }
+
     /// Delete the object at the specified location.
     pub async fn delete(&self, location: &ObjectStorePath) -> Result<()> {
         self.storage.write().await.remove(&location.into());
         Ok(())
     }
 
+    /// Copy the object at `from` to `to`, overwriting any object there.
+    pub async fn copy(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        let mut storage = self.storage.write().await;
+        let data = storage.get(&from.into()).cloned().context(NoDataInMemory)?;
+        storage.insert(to.into(), data);
+        Ok(())
+    }
+
+    /// Move the object at `from` to `to`, overwriting any object there.
+    pub async fn rename(&self, from: &ObjectStorePath, to: &ObjectStorePath) -> Result<()> {
+        let mut storage = self.storage.write().await;
+        let data = storage.remove(&from.into()).context(NoDataInMemory)?;
+        storage.insert(to.into(), data);
+        Ok(())
+    }
+
     /// List all the objects with the given prefix.
     pub async fn list<'a>(
         &'a self,
@@ -107,14 +245,14 @@ impl InMemory {
     }
 
     /// List objects with the given prefix and a set delimiter of `/`. Returns
-    /// common prefixes (directories) in addition to object metadata. The
-    /// memory implementation returns all results, as opposed to the cloud
-    /// versions which limit their results to 1k or more because of API
-    /// limitations.
+    /// common prefixes (directories) in addition to object metadata, at most
+    /// `max_results` of them, after the token `token` if any. The pages are
+    /// cut from all the results, like `paginate` describes.
     pub async fn list_with_delimiter<'a>(
         &'a self,
         prefix: &'a ObjectStorePath,
-        _next_token: &Option<String>,
+        token: Option<&str>,
+        max_results: usize,
     ) -> Result<ListResult> {
         let mut common_prefixes = BTreeSet::new();
         let last_modified = Utc::now();
@@ -144,19 +282,29 @@ impl InMemory {
                     location: k.into(),
                     last_modified,
                     size: v.len(),
+                    e_tag: Some(content_e_tag(v)),
                 };
                 objects.push(object);
             }
         }
 
-        Ok(ListResult {
+        let result = ListResult {
             objects,
             common_prefixes: common_prefixes.into_iter().map(Into::into).collect(),
             next_token: None,
-        })
+        };
+        Ok(paginate(result, token, max_results))
     }
 }
 
+/// Returns the entity tag of the contents `data` of an object, a hash of
+/// them, as objects in memory have no versions
+fn content_e_tag(data: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,7 +313,10 @@ mod tests {
     type Result<T, E = TestError> = std::result::Result<T, E>;
 
     use crate::{
-        tests::{list_with_delimiter, put_get_delete_list},
+        tests::{
+            list_with_delimiter, list_with_delimiter_paginated, put_get_delete_list, put_if_match,
+            put_if_not_exists,
+        },
         Error, ObjectStore,
     };
     use futures::stream;
@@ -178,6 +329,12 @@ mod tests {
 
         list_with_delimiter(&integration).await.unwrap();
 
+        list_with_delimiter_paginated(&integration).await?;
+
+        put_if_not_exists(&integration).await?;
+
+        put_if_match(&integration).await?;
+
         Ok(())
     }
 
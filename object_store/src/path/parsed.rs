@@ -99,6 +99,20 @@ impl DirsAndFileName {
     pub(crate) fn push_part_as_dir(&mut self, part: &PathPart) {
         self.directories.push(part.to_owned());
     }
+
+    /// Creates the path of a file from its filesystem path, whose parts
+    /// are already encoded as filesystem storage encodes them.
+    pub(crate) fn from_encoded_file_path(path: &std::path::Path) -> Self {
+        let mut parts: Vec<_> = path
+            .iter()
+            .map(|s| PathPart(s.to_string_lossy().into_owned()))
+            .collect();
+        let file_name = parts.pop();
+        Self {
+            directories: parts,
+            file_name,
+        }
+    }
 }
 
 impl From<PathRepresentation> for DirsAndFileName {
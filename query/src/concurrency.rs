@@ -0,0 +1,85 @@
+//! This module contains the accounting of the queries running against a
+//! database, used to limit how many of them may run at the same time.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Counts the queries running against a database
+#[derive(Debug, Default)]
+pub struct QueryConcurrency {
+    running: Arc<AtomicUsize>,
+}
+
+impl QueryConcurrency {
+    /// Registers the start of a query, unless `max` queries are already
+    /// running (there is no limit if `max` is None). The query counts as
+    /// running until the returned guard is dropped.
+    pub fn try_start(&self, max: Option<usize>) -> Option<QueryGuard> {
+        let max = max.unwrap_or(usize::MAX);
+        self.running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                if running < max {
+                    Some(running + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+
+        Some(QueryGuard {
+            running: Some(Arc::clone(&self.running)),
+        })
+    }
+
+    /// The number of queries currently running
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+/// Held while a query runs. The default guard doesn't count towards any
+/// limit.
+#[derive(Debug, Default)]
+pub struct QueryGuard {
+    running: Option<Arc<AtomicUsize>>,
+}
+
+impl Drop for QueryGuard {
+    fn drop(&mut self) {
+        if let Some(running) = &self.running {
+            running.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_are_limited_while_running() {
+        let concurrency = QueryConcurrency::default();
+
+        let first = concurrency.try_start(Some(2)).unwrap();
+        let second = concurrency.try_start(Some(2)).unwrap();
+        assert!(concurrency.try_start(Some(2)).is_none());
+        assert_eq!(concurrency.running(), 2);
+
+        drop(first);
+        let third = concurrency.try_start(Some(2)).unwrap();
+        assert_eq!(concurrency.running(), 2);
+
+        drop(second);
+        drop(third);
+        assert_eq!(concurrency.running(), 0);
+
+        let unlimited: Vec<_> = (0..10)
+            .map(|_| concurrency.try_start(None).unwrap())
+            .collect();
+        assert_eq!(concurrency.running(), 10);
+        drop(unlimited);
+        assert_eq!(concurrency.running(), 0);
+    }
+}
@@ -1,11 +1,14 @@
 //! This module handles the manipulation / execution of storage
 //! plans. This is currently implemented using DataFusion, and this
 //! interface abstracts away many of the details
+pub mod batch_size;
 pub(crate) mod context;
 mod counters;
 pub mod field;
 pub mod fieldlist;
+pub mod plan_cache;
 mod schema_pivot;
+pub mod series_key;
 pub mod seriesset;
 pub mod stringset;
 
@@ -15,13 +18,16 @@ use arrow_deps::{
     arrow::record_batch::RecordBatch,
     datafusion::{self, logical_plan::LogicalPlan},
 };
+use batch_size::BatchSizeConfig;
 use counters::ExecutionCounters;
+pub use counters::QueryMetrics;
 
 use context::IOxExecutionContext;
 use field::FieldColumns;
 use schema_pivot::SchemaPivotNode;
 
 use fieldlist::{FieldList, IntoFieldList};
+use plan_cache::PlanCache;
 use seriesset::{Error as SeriesSetError, SeriesSetConverter, SeriesSetItem};
 use stringset::{IntoStringSet, StringSet, StringSetRef};
 use tokio::sync::mpsc::{self, error::SendError};
@@ -238,6 +244,13 @@ pub enum FieldListPlan {
 #[derive(Debug, Default)]
 pub struct Executor {
     counters: Arc<ExecutionCounters>,
+
+    /// Controls the size of the RecordBatches produced by plans run
+    /// by this executor
+    batch_config: BatchSizeConfig,
+
+    /// Plans for SQL statements run by this executor
+    plan_cache: PlanCache,
 }
 
 impl Executor {
@@ -245,6 +258,25 @@ impl Executor {
         Self::default()
     }
 
+    /// Create an executor whose plans produce RecordBatches sized
+    /// according to `batch_config`
+    pub fn new_with_batch_config(batch_config: BatchSizeConfig) -> Self {
+        Self {
+            batch_config,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the default RecordBatch sizing used by this executor
+    pub fn batch_config(&self) -> &BatchSizeConfig {
+        &self.batch_config
+    }
+
+    /// Returns the cache of plans for SQL statements run by this executor
+    pub fn plan_cache(&self) -> &PlanCache {
+        &self.plan_cache
+    }
+
     /// Executes this plan and returns the resulting set of strings
     pub async fn to_string_set(&self, plan: StringSetPlan) -> Result<StringSetRef> {
         match plan {
@@ -357,10 +389,9 @@ impl Executor {
                 let handles = plans
                     .into_iter()
                     .map(|plan| {
-                        let counters = self.counters.clone();
+                        let ctx = self.new_context();
 
                         tokio::task::spawn(async move {
-                            let ctx = IOxExecutionContext::new(counters);
                             let physical_plan = ctx
                                 .prepare_plan(&plan)
                                 .await
@@ -399,7 +430,16 @@ impl Executor {
 
     /// Create a new execution context, suitable for executing a new query
     pub fn new_context(&self) -> IOxExecutionContext {
-        IOxExecutionContext::new(self.counters.clone())
+        self.new_context_with_batch_config(self.batch_config)
+    }
+
+    /// Create a new execution context, suitable for executing a new
+    /// query, that overrides the RecordBatch sizing of this executor
+    pub fn new_context_with_batch_config(
+        &self,
+        batch_config: BatchSizeConfig,
+    ) -> IOxExecutionContext {
+        IOxExecutionContext::new(self.counters.clone(), batch_config)
     }
 
     /// plans and runs the plans in parallel and collects the results
@@ -0,0 +1,204 @@
+//! This module contains the configuration that controls the size of
+//! the RecordBatches produced by query execution, and the code to
+//! split oversized batches accordingly.
+//!
+//! Limiting only the number of rows in each batch is not enough:
+//! tables with many (or wide) columns produce very large batches for
+//! the same number of rows. Thus, in addition to the maximum number of
+//! rows, a target size in bytes can be configured, in which case the
+//! number of rows per batch is reduced for wide rows.
+
+use arrow_deps::arrow::{
+    self,
+    array::{Array, ArrayRef},
+    record_batch::RecordBatch,
+};
+
+/// The default maximum number of rows in each RecordBatch
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Controls the size of the RecordBatches produced during query
+/// execution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchSizeConfig {
+    /// The maximum number of rows in each RecordBatch
+    pub max_rows: usize,
+
+    /// If set, the target (approximate) maximum size, in bytes, of
+    /// each RecordBatch. The number of rows per batch is reduced for
+    /// tables with wide rows so that each batch stays under this
+    /// size. Each batch always contains at least one row.
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for BatchSizeConfig {
+    fn default() -> Self {
+        Self {
+            max_rows: DEFAULT_BATCH_SIZE,
+            max_bytes: None,
+        }
+    }
+}
+
+impl BatchSizeConfig {
+    /// Create a config that limits batches to `max_rows` rows
+    pub fn new(max_rows: usize) -> Self {
+        Self {
+            max_rows: max_rows.max(1),
+            max_bytes: None,
+        }
+    }
+
+    /// Also limit batches to approximately `max_bytes` bytes
+    pub fn with_max_bytes(self, max_bytes: usize) -> Self {
+        Self {
+            max_bytes: Some(max_bytes),
+            ..self
+        }
+    }
+
+    /// Returns the number of rows that should be placed in each
+    /// batch for data shaped like `batch`, taking into account the
+    /// (average) width of its rows
+    pub fn rows_per_batch(&self, batch: &RecordBatch) -> usize {
+        let max_rows = self.max_rows.max(1);
+
+        let max_bytes = match self.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return max_rows,
+        };
+
+        let num_rows = batch.num_rows();
+        if num_rows == 0 {
+            return max_rows;
+        }
+
+        let total_bytes: usize = batch
+            .columns()
+            .iter()
+            .map(|c| c.get_array_memory_size())
+            .sum();
+        let bytes_per_row = (total_bytes / num_rows).max(1);
+
+        (max_bytes / bytes_per_row).max(1).min(max_rows)
+    }
+
+    /// Splits `batch` into (zero copy) slices that are no larger than
+    /// allowed by this config. If `batch` is already small enough,
+    /// it is returned as is.
+    pub fn split_batch(&self, batch: RecordBatch) -> arrow::error::Result<Vec<RecordBatch>> {
+        let num_rows = batch.num_rows();
+        let rows_per_batch = self.rows_per_batch(&batch);
+
+        if num_rows <= rows_per_batch {
+            return Ok(vec![batch]);
+        }
+
+        let schema = batch.schema();
+        (0..num_rows)
+            .step_by(rows_per_batch)
+            .map(|offset| {
+                let len = rows_per_batch.min(num_rows - offset);
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|c| c.slice(offset, len))
+                    .collect::<Vec<ArrayRef>>();
+                RecordBatch::try_new(schema.clone(), columns)
+            })
+            .collect()
+    }
+
+    /// Splits each batch in `batches` according to this config
+    pub fn split_batches(
+        &self,
+        batches: Vec<RecordBatch>,
+    ) -> arrow::error::Result<Vec<RecordBatch>> {
+        let mut result = Vec::with_capacity(batches.len());
+        for batch in batches {
+            result.append(&mut self.split_batch(batch)?);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_deps::arrow::{
+        array::Int64Array,
+        datatypes::{DataType, Field, Schema},
+    };
+
+    use super::*;
+
+    fn make_batch(num_rows: usize, num_columns: usize) -> RecordBatch {
+        let fields = (0..num_columns)
+            .map(|i| Field::new(&format!("c{}", i), DataType::Int64, false))
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+
+        let columns = (0..num_columns)
+            .map(|_| {
+                Arc::new(Int64Array::from((0..num_rows as i64).collect::<Vec<_>>())) as ArrayRef
+            })
+            .collect();
+
+        RecordBatch::try_new(schema, columns).unwrap()
+    }
+
+    fn batch_lens(batches: &[RecordBatch]) -> Vec<usize> {
+        batches.iter().map(|b| b.num_rows()).collect()
+    }
+
+    #[test]
+    fn split_by_rows() {
+        let config = BatchSizeConfig::new(4);
+
+        let batches = config.split_batch(make_batch(10, 2)).unwrap();
+        assert_eq!(batch_lens(&batches), vec![4, 4, 2]);
+
+        let values = batches[1]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(values.value(0), 4);
+        assert_eq!(values.value(3), 7);
+
+        // small batches are passed through
+        let batches = config.split_batch(make_batch(3, 2)).unwrap();
+        assert_eq!(batch_lens(&batches), vec![3]);
+
+        let batches = config.split_batch(make_batch(0, 2)).unwrap();
+        assert_eq!(batch_lens(&batches), vec![0]);
+    }
+
+    #[test]
+    fn split_by_bytes() {
+        let batch = make_batch(1000, 10);
+        let total_bytes: usize = batch
+            .columns()
+            .iter()
+            .map(|c| c.get_array_memory_size())
+            .sum();
+
+        // allow roughly a quarter of the batch
+        let config = BatchSizeConfig::new(1000).with_max_bytes(total_bytes / 4);
+        let rows = config.rows_per_batch(&batch);
+        assert!(rows > 0 && rows <= 250, "rows: {}", rows);
+
+        let batches = config.split_batch(batch).unwrap();
+        assert!(batches.len() >= 4);
+        assert_eq!(batch_lens(&batches).iter().sum::<usize>(), 1000);
+
+        // max_rows still applies for narrow rows
+        let config = BatchSizeConfig::new(100).with_max_bytes(usize::MAX);
+        assert_eq!(config.rows_per_batch(&make_batch(1000, 1)), 100);
+
+        // always at least one row per batch
+        let config = BatchSizeConfig::new(100).with_max_bytes(1);
+        assert_eq!(config.rows_per_batch(&make_batch(1000, 10)), 1);
+    }
+}
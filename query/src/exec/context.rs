@@ -25,7 +25,7 @@ use tracing::debug;
 // Reuse DataFusion error and Result types for this module
 pub use arrow_deps::datafusion::error::{DataFusionError as Error, Result};
 
-use super::counters::ExecutionCounters;
+use super::{batch_size::BatchSizeConfig, counters::ExecutionCounters};
 
 /// This structure implements the DataFusion notion of "query planner"
 /// and is needed to create plans with the IOx extension nodes.
@@ -87,6 +87,7 @@ impl ExtensionPlanner for IOxExtensionPlanner {
 /// and providing visibility into what plans are running
 pub struct IOxExecutionContext {
     counters: Arc<ExecutionCounters>,
+    batch_config: BatchSizeConfig,
     inner: ExecutionContext,
 }
 
@@ -94,23 +95,32 @@ impl fmt::Debug for IOxExecutionContext {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("IOxExecutionContext")
             .field("counters", &self.counters)
+            .field("batch_config", &self.batch_config)
             .field("inner", &"<DataFusion ExecutionContext>")
             .finish()
     }
 }
 
 impl IOxExecutionContext {
-    /// Create an ExecutionContext suitable for executing DataFusion plans
-    pub fn new(counters: Arc<ExecutionCounters>) -> Self {
-        const BATCH_SIZE: usize = 1000;
-
+    /// Create an ExecutionContext suitable for executing DataFusion
+    /// plans, producing RecordBatches sized according to `batch_config`
+    pub fn new(counters: Arc<ExecutionCounters>, batch_config: BatchSizeConfig) -> Self {
         // TBD: Should we be reusing an execution context across all executions?
-        let config = ExecutionConfig::new().with_batch_size(BATCH_SIZE);
+        let config = ExecutionConfig::new().with_batch_size(batch_config.max_rows);
 
         let config = config.with_query_planner(Arc::new(IOxQueryPlanner {}));
         let inner = ExecutionContext::with_config(config);
 
-        Self { counters, inner }
+        Self {
+            counters,
+            batch_config,
+            inner,
+        }
+    }
+
+    /// returns the configuration used to size RecordBatches in this context
+    pub fn batch_config(&self) -> &BatchSizeConfig {
+        &self.batch_config
     }
 
     /// returns a reference to the inner datafusion execution context
@@ -123,15 +133,22 @@ impl IOxExecutionContext {
         &mut self.inner
     }
 
-    /// Prepare a SQL statement for execution. This assumes that any
-    /// tables referenced in the SQL have been registered with this context
-    pub async fn prepare_sql(&mut self, sql: &str) -> Result<Arc<dyn ExecutionPlan>> {
+    /// Prepare (optimize + plan) a pre-created logical plan for execution
+    pub async fn prepare_plan(&self, plan: &LogicalPlan) -> Result<Arc<dyn ExecutionPlan>> {
+        let plan = self.optimize(plan)?;
+        self.create_physical_plan(&plan)
+    }
+
+    /// Create an optimized logical plan for a SQL statement. This assumes
+    /// that any tables referenced in the SQL have been registered with this
+    /// context
+    pub fn plan_sql(&mut self, sql: &str) -> Result<LogicalPlan> {
         let logical_plan = self.inner.sql(sql)?.to_logical_plan();
-        self.prepare_plan(&logical_plan).await
+        self.optimize(&logical_plan)
     }
 
-    /// Prepare (optimize + plan) a pre-created logical plan for execution
-    pub async fn prepare_plan(&self, plan: &LogicalPlan) -> Result<Arc<dyn ExecutionPlan>> {
+    /// Optimize a pre-created logical plan
+    pub fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
         debug!(
             "Creating plan: Initial plan\n----\n{}\n{}\n----",
             plan.display_indent_schema(),
@@ -146,7 +163,13 @@ impl IOxExecutionContext {
             plan.display_graphviz(),
         );
 
-        self.inner.create_physical_plan(&plan)
+        Ok(plan)
+    }
+
+    /// Create a physical plan for an optimized logical plan, reading the
+    /// tables it refers to by name from this context
+    pub fn create_physical_plan(&self, plan: &LogicalPlan) -> Result<Arc<dyn ExecutionPlan>> {
+        self.inner.create_physical_plan(plan)
     }
 
     /// Executes the logical plan using DataFusion and produces RecordBatches
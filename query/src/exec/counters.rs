@@ -1,5 +1,8 @@
 use std::{sync::atomic::AtomicU64, sync::atomic::Ordering};
 
+use arrow_deps::arrow::record_batch::RecordBatch;
+use tracing::info;
+
 // Various statistics for execution
 #[derive(Debug, Default)]
 pub struct ExecutionCounters {
@@ -11,3 +14,74 @@ impl ExecutionCounters {
         self.plans_run.fetch_add(1, Ordering::Relaxed);
     }
 }
+
+/// Statistics about the work done to plan and run a single query.
+///
+/// A planner given one of these (e.g. via
+/// `SQLQueryPlanner::with_metrics`) counts the partitions and chunks it
+/// considers, the rows it reads and its plan cache hits; the caller
+/// counts the batches the plan produces with `add_batches`.
+#[derive(Debug, Default)]
+pub struct QueryMetrics {
+    /// Number of partitions whose chunks were considered
+    pub partitions_considered: AtomicU64,
+    /// Number of chunks considered
+    pub chunks_considered: AtomicU64,
+    /// Number of considered chunks skipped because their data could
+    /// not match the query predicate
+    pub chunks_pruned: AtomicU64,
+    /// Number of rows read from chunks
+    pub rows_scanned: AtomicU64,
+    /// Number of record batches produced by the query plan
+    pub batches_produced: AtomicU64,
+    /// Number of rows in the record batches produced by the query plan
+    pub rows_produced: AtomicU64,
+    /// Number of statements whose plan was found in the plan cache
+    pub plan_cache_hits: AtomicU64,
+}
+
+impl QueryMetrics {
+    pub fn add_partitions_considered(&self, partitions: usize) {
+        self.partitions_considered
+            .fetch_add(partitions as u64, Ordering::Relaxed);
+    }
+
+    pub fn inc_chunks_considered(&self) {
+        self.chunks_considered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_chunks_pruned(&self) {
+        self.chunks_pruned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_plan_cache_hits(&self) {
+        self.plan_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_rows_scanned(&self, rows: usize) {
+        self.rows_scanned.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Count `batches` as output of the query
+    pub fn add_batches(&self, batches: &[RecordBatch]) {
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        self.batches_produced
+            .fetch_add(batches.len() as u64, Ordering::Relaxed);
+        self.rows_produced.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Emit the current values as an event, attributed to the current
+    /// tracing span
+    pub fn emit(&self) {
+        info!(
+            partitions_considered = self.partitions_considered.load(Ordering::Relaxed),
+            chunks_considered = self.chunks_considered.load(Ordering::Relaxed),
+            chunks_pruned = self.chunks_pruned.load(Ordering::Relaxed),
+            rows_scanned = self.rows_scanned.load(Ordering::Relaxed),
+            batches_produced = self.batches_produced.load(Ordering::Relaxed),
+            rows_produced = self.rows_produced.load(Ordering::Relaxed),
+            plan_cache_hits = self.plan_cache_hits.load(Ordering::Relaxed),
+            "query metrics"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! This module contains a cache of optimized logical plans for SQL
+//! statements, so dashboards that re-issue the same query every few
+//! seconds don't pay for parsing, analyzing and optimizing it each time.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use arrow_deps::{arrow::datatypes::SchemaRef, datafusion::logical_plan::LogicalPlan};
+
+/// The number of plans kept unless configured otherwise
+pub const DEFAULT_PLAN_CACHE_CAPACITY: usize = 100;
+
+/// The schemas of the tables a statement was planned against, in the order
+/// they were registered
+pub type TableSchemas = Vec<(String, SchemaRef)>;
+
+/// Caches optimized logical plans by database and statement fingerprint.
+///
+/// The plans refer to their tables by name, so a cached plan can be turned
+/// into a physical plan over the current data of those tables, as long as
+/// their schemas are the ones it was planned against. A plan is dropped as
+/// soon as it is looked up with different schemas (e.g. because a table
+/// gained a column). When full, the least recently used plan is evicted.
+#[derive(Debug)]
+pub struct PlanCache {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The entries by database name and statement fingerprint
+    entries: HashMap<(String, String), Entry>,
+    /// Incremented on every use, to find the least recently used entry
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    schemas: TableSchemas,
+    plan: LogicalPlan,
+    last_used: u64,
+}
+
+impl Default for PlanCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_PLAN_CACHE_CAPACITY)
+    }
+}
+
+impl PlanCache {
+    /// Create a cache holding at most `capacity` plans. A capacity of zero
+    /// disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Default::default(),
+        }
+    }
+
+    /// Returns the plan cached for `fingerprint` in the database `db_name`
+    /// if it was planned against `schemas`
+    pub fn get(
+        &self,
+        db_name: &str,
+        fingerprint: &str,
+        schemas: &[(String, SchemaRef)],
+    ) -> Option<LogicalPlan> {
+        let key = (db_name.to_string(), fingerprint.to_string());
+        let mut state = self.state.lock().expect("mutex poisoned");
+        state.clock += 1;
+        let now = state.clock;
+
+        let entry = state.entries.get_mut(&key)?;
+        if entry.schemas.as_slice() != schemas {
+            state.entries.remove(&key);
+            return None;
+        }
+
+        entry.last_used = now;
+        Some(entry.plan.clone())
+    }
+
+    /// Caches `plan`, planned against `schemas`, for `fingerprint` in the
+    /// database `db_name`
+    pub fn insert(
+        &self,
+        db_name: impl Into<String>,
+        fingerprint: impl Into<String>,
+        schemas: TableSchemas,
+        plan: LogicalPlan,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let key = (db_name.into(), fingerprint.into());
+        let mut state = self.state.lock().expect("mutex poisoned");
+        state.clock += 1;
+        let now = state.clock;
+
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        state.entries.insert(
+            key,
+            Entry {
+                schemas,
+                plan,
+                last_used: now,
+            },
+        );
+    }
+
+    /// The number of cached plans
+    pub fn len(&self) -> usize {
+        self.state.lock().expect("mutex poisoned").entries.len()
+    }
+
+    /// Returns true if no plans are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::{
+        arrow::datatypes::{DataType, Field, Schema},
+        datafusion::logical_plan::LogicalPlanBuilder,
+    };
+    use std::sync::Arc;
+
+    fn schemas(table: &str, columns: &[&str]) -> TableSchemas {
+        let fields = columns
+            .iter()
+            .map(|name| Field::new(name, DataType::Int64, true))
+            .collect();
+        vec![(table.to_string(), Arc::new(Schema::new(fields)))]
+    }
+
+    fn plan() -> LogicalPlan {
+        LogicalPlanBuilder::empty(false).build().unwrap()
+    }
+
+    #[test]
+    fn plans_are_reused_until_the_schema_changes() {
+        let cache = PlanCache::default();
+        let cpu = schemas("cpu", &["usage", "time"]);
+
+        assert!(cache.get("db", "SELECT * FROM cpu", &cpu).is_none());
+        cache.insert("db", "SELECT * FROM cpu", cpu.clone(), plan());
+        assert!(cache.get("db", "SELECT * FROM cpu", &cpu).is_some());
+        assert!(cache.get("db", "SELECT * FROM mem", &cpu).is_none());
+
+        // the table gained a column
+        let cpu = schemas("cpu", &["usage", "user", "time"]);
+        assert!(cache.get("db", "SELECT * FROM cpu", &cpu).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn plans_are_cached_per_database() {
+        let cache = PlanCache::default();
+        let cpu = schemas("cpu", &["usage", "time"]);
+
+        cache.insert("db1", "SELECT * FROM cpu", cpu.clone(), plan());
+        assert!(cache.get("db1", "SELECT * FROM cpu", &cpu).is_some());
+        assert!(cache.get("db2", "SELECT * FROM cpu", &cpu).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_plans_are_evicted() {
+        let cache = PlanCache::new(2);
+        let cpu = schemas("cpu", &["usage", "time"]);
+
+        cache.insert("db", "a", cpu.clone(), plan());
+        cache.insert("db", "b", cpu.clone(), plan());
+        assert!(cache.get("db", "a", &cpu).is_some());
+
+        cache.insert("db", "c", cpu.clone(), plan());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("db", "a", &cpu).is_some());
+        assert!(cache.get("db", "b", &cpu).is_none());
+        assert!(cache.get("db", "c", &cpu).is_some());
+    }
+
+    #[test]
+    fn zero_capacity_disables_caching() {
+        let cache = PlanCache::new(0);
+        let cpu = schemas("cpu", &["usage", "time"]);
+
+        cache.insert("db", "a", cpu.clone(), plan());
+        assert!(cache.get("db", "a", &cpu).is_none());
+    }
+}
@@ -0,0 +1,334 @@
+//! This module contains code to project the tag set of each row in a
+//! record batch into a single "series key" column.
+//!
+//! The series key is the canonical string representation of a
+//! series: the `tag=value` pairs of the row, sorted by tag name, and
+//! separated by commas. For example, a row with `region=west` and
+//! `host=a` has the series key `host=a,region=west`.
+//!
+//! Tags with null values are not part of the series and are omitted
+//! from the key. Tag names and values are escaped the same way as in line
+//! protocol so that the key is unambiguous.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::Arc,
+};
+
+use arrow_deps::arrow::{
+    self,
+    array::{Array, ArrayRef, StringArray, StringBuilder},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+use influxdb_line_protocol::{escape, LinePart};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+use crate::{PartitionChunk, TableSeriesKeys};
+
+/// The default name of the column produced by
+/// [`add_series_key_column`](fn.add_series_key_column.html)
+pub const SERIES_KEY_COLUMN_NAME: &str = "_series";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Tag column '{}' not found in record batch schema", column_name))]
+    TagColumnNotFound { column_name: String },
+
+    #[snafu(display(
+        "Tag column '{}' has type {:?}, but tag columns must be Utf8",
+        column_name,
+        data_type
+    ))]
+    TagColumnNotString {
+        column_name: String,
+        data_type: DataType,
+    },
+
+    #[snafu(display("Column '{}' already exists in record batch", column_name))]
+    SeriesKeyColumnExists { column_name: String },
+
+    #[snafu(display("Error building record batch with series key: {}", source))]
+    BuildingRecordBatch { source: arrow::error::ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Computes the series key for each row in `batch` using the columns
+/// named in `tag_columns`, which may be specified in any order.
+pub fn series_key_array(batch: &RecordBatch, tag_columns: &[Arc<String>]) -> Result<StringArray> {
+    let schema = batch.schema();
+
+    // sort the tag columns by name so the key is canonical
+    let mut tag_columns = tag_columns
+        .iter()
+        .map(|column_name| {
+            let index = schema
+                .index_of(column_name)
+                .ok()
+                .context(TagColumnNotFound {
+                    column_name: column_name.as_str(),
+                })?;
+
+            let data_type = schema.field(index).data_type();
+            ensure!(
+                data_type == &DataType::Utf8,
+                TagColumnNotString {
+                    column_name: column_name.as_str(),
+                    data_type: data_type.clone(),
+                }
+            );
+
+            let array = batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .expect("Utf8 column was a StringArray");
+
+            Ok((column_name.as_str(), array))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    tag_columns.sort_by_key(|(name, _)| *name);
+
+    let num_rows = batch.num_rows();
+    let mut builder = StringBuilder::new(num_rows);
+    for row in 0..num_rows {
+        let key = series_key(
+            tag_columns
+                .iter()
+                .map(|(name, array)| (*name, string_value(array, row))),
+        );
+        builder
+            .append_value(&key)
+            .expect("appending to string builder");
+    }
+
+    Ok(builder.finish())
+}
+
+/// Returns the series key of `tags`, pairs of tag name and value that
+/// must be sorted by name. Tags without a value are left out.
+pub fn series_key<'a>(tags: impl IntoIterator<Item = (&'a str, Option<&'a str>)>) -> String {
+    let mut key = String::new();
+    for (name, value) in tags {
+        if let Some(value) = value {
+            if !key.is_empty() {
+                key.push(',');
+            }
+            key.push_str(&escape(name, LinePart::TagKey));
+            key.push('=');
+            key.push_str(&escape(value, LinePart::TagValue));
+        }
+    }
+    key
+}
+
+/// Adds the series key of each row of `batch`, whose columns are the tag
+/// columns of a table, to `keys`. The key of each distinct combination of
+/// tag values is only built once. Columns that aren't strings are ignored.
+pub fn add_series_keys(batch: &RecordBatch, keys: &mut BTreeSet<String>) {
+    let schema = batch.schema();
+    let mut tags: Vec<_> = schema
+        .fields()
+        .iter()
+        .zip(batch.columns())
+        .filter_map(|(field, column)| {
+            let values = column.as_any().downcast_ref::<StringArray>()?;
+            Some((field.name().as_str(), values))
+        })
+        .collect();
+    tags.sort_by_key(|(name, _)| *name);
+
+    let series: HashSet<Vec<_>> = (0..batch.num_rows())
+        .map(|row| {
+            tags.iter()
+                .map(|(_, values)| string_value(values, row))
+                .collect()
+        })
+        .collect();
+
+    let names = tags.iter().map(|(name, _)| *name);
+    keys.extend(
+        series
+            .into_iter()
+            .map(|values| series_key(names.clone().zip(values))),
+    );
+}
+
+/// Returns the series keys of each table of `chunk`, read from the tag
+/// columns of the table as record batches
+pub fn chunk_series_keys<C: PartitionChunk + ?Sized>(
+    chunk: &C,
+) -> std::result::Result<TableSeriesKeys, C::Error> {
+    let mut series_keys = TableSeriesKeys::new();
+    for (table_name, tags) in chunk.all_tag_values()? {
+        let mut keys = BTreeSet::new();
+        if tags.is_empty() {
+            keys.insert(String::new());
+        } else {
+            let columns: Vec<_> = tags.keys().map(String::as_str).collect();
+            let mut batches = Vec::new();
+            chunk.table_to_arrow(&mut batches, &table_name, &columns)?;
+            for batch in &batches {
+                add_series_keys(batch, &mut keys);
+            }
+        }
+        series_keys.insert(table_name, keys);
+    }
+    Ok(series_keys)
+}
+
+fn string_value(array: &StringArray, row: usize) -> Option<&str> {
+    if array.is_null(row) {
+        None
+    } else {
+        Some(array.value(row))
+    }
+}
+
+/// Returns a new record batch with all the columns of `batch` and an
+/// additional Utf8 column named
+/// [`SERIES_KEY_COLUMN_NAME`](constant.SERIES_KEY_COLUMN_NAME.html)
+/// containing the series key of each row.
+pub fn add_series_key_column(
+    batch: &RecordBatch,
+    tag_columns: &[Arc<String>],
+) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    ensure!(
+        schema.index_of(SERIES_KEY_COLUMN_NAME).is_err(),
+        SeriesKeyColumnExists {
+            column_name: SERIES_KEY_COLUMN_NAME,
+        }
+    );
+
+    let series_keys = series_key_array(batch, tag_columns)?;
+
+    let mut fields = schema.fields().clone();
+    fields.push(Field::new(SERIES_KEY_COLUMN_NAME, DataType::Utf8, false));
+    let schema = Arc::new(Schema::new(fields));
+
+    let mut columns = batch.columns().to_vec();
+    columns.push(Arc::new(series_keys) as ArrayRef);
+
+    RecordBatch::try_new(schema, columns).context(BuildingRecordBatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::array::Int64Array;
+    use test_helpers::str_vec_to_arc_vec;
+
+    fn make_batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("region", DataType::Utf8, true),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+
+        let region = StringArray::from(vec![Some("west"), None, Some("us east")]);
+        let host = StringArray::from(vec![Some("a"), Some("b"), Some("c=d")]);
+        let time = Int64Array::from(vec![1, 2, 3]);
+
+        RecordBatch::try_new(
+            schema,
+            vec![Arc::new(region), Arc::new(host), Arc::new(time)],
+        )
+        .unwrap()
+    }
+
+    fn to_vec(array: &StringArray) -> Vec<&str> {
+        (0..array.len()).map(|i| array.value(i)).collect()
+    }
+
+    #[test]
+    fn test_series_key_sorted_by_tag_name() {
+        let batch = make_batch();
+
+        let keys = series_key_array(&batch, &str_vec_to_arc_vec(&["region", "host"])).unwrap();
+        assert_eq!(
+            to_vec(&keys),
+            vec!["host=a,region=west", "host=b", r"host=c\=d,region=us\ east"]
+        );
+
+        // order of tag columns doesn't matter
+        let keys = series_key_array(&batch, &str_vec_to_arc_vec(&["host", "region"])).unwrap();
+        assert_eq!(
+            to_vec(&keys),
+            vec!["host=a,region=west", "host=b", r"host=c\=d,region=us\ east"]
+        );
+    }
+
+    #[test]
+    fn test_series_key_escapes_like_line_protocol() {
+        let key = series_key(vec![
+            ("dir", Some(r"C:\temp")),
+            ("empty", None),
+            ("host name", Some("a,b")),
+        ]);
+        assert_eq!(key, r"dir=C:\\temp,host\ name=a\,b");
+    }
+
+    #[test]
+    fn test_add_series_keys() {
+        let batch = make_batch();
+
+        // the time column isn't a tag, and each key is only added once
+        let mut keys = BTreeSet::new();
+        add_series_keys(&batch, &mut keys);
+        add_series_keys(&batch, &mut keys);
+        assert_eq!(
+            keys.into_iter().collect::<Vec<_>>(),
+            vec!["host=a,region=west", "host=b", r"host=c\=d,region=us\ east"]
+        );
+    }
+
+    #[test]
+    fn test_series_key_no_tags() {
+        let batch = make_batch();
+
+        let keys = series_key_array(&batch, &[]).unwrap();
+        assert_eq!(to_vec(&keys), vec!["", "", ""]);
+    }
+
+    #[test]
+    fn test_series_key_errors() {
+        let batch = make_batch();
+
+        let err = series_key_array(&batch, &str_vec_to_arc_vec(&["foo"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Tag column 'foo' not found in record batch schema"
+        );
+
+        let err = series_key_array(&batch, &str_vec_to_arc_vec(&["time"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Tag column 'time' has type Int64, but tag columns must be Utf8"
+        );
+    }
+
+    #[test]
+    fn test_add_series_key_column() {
+        let batch = make_batch();
+
+        let batch = add_series_key_column(&batch, &str_vec_to_arc_vec(&["host"])).unwrap();
+        assert_eq!(batch.num_columns(), 4);
+        assert_eq!(batch.schema().field(3).name(), SERIES_KEY_COLUMN_NAME);
+
+        let keys = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(to_vec(keys), vec!["host=a", "host=b", r"host=c\=d"]);
+
+        // can't add it twice
+        let err = add_series_key_column(&batch, &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Column '_series' already exists in record batch"
+        );
+    }
+}
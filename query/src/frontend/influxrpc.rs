@@ -18,9 +18,15 @@
 /// While the underlying storage is the same for columns in different
 /// categories with the same data type, columns of different
 /// categories are treated differently in the different query types.
+use std::sync::Arc;
+
 use snafu::{ResultExt, Snafu};
 
-use crate::{exec::StringSetPlan, predicate::Predicate, Database, PartitionChunk};
+use crate::{
+    exec::{QueryMetrics, StringSetPlan},
+    predicate::Predicate,
+    Database, PartitionChunk,
+};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -38,12 +44,21 @@ pub enum Error {
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Default, Debug)]
-pub struct InfluxRPCPlanner {}
+pub struct InfluxRPCPlanner {
+    /// Records the work done for queries planned by this planner
+    metrics: Arc<QueryMetrics>,
+}
 
 impl InfluxRPCPlanner {
     /// Create a new instance of the RPC planner
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Record the partitions and chunks considered and pruned while
+    /// planning queries in `metrics`
+    pub fn with_metrics(self, metrics: Arc<QueryMetrics>) -> Self {
+        Self { metrics }
     }
 
     /// Returns a plan that lists the names of tables in this
@@ -54,6 +69,7 @@ impl InfluxRPCPlanner {
         database: &D,
         predicate: Predicate,
     ) -> Result<StringSetPlan> {
+        let predicate = database.retained(predicate);
         let mut plans = Vec::new();
 
         let partition_keys = database
@@ -61,10 +77,12 @@ impl InfluxRPCPlanner {
             .await
             .map_err(|e| Box::new(e) as _)
             .context(ListingPartitions)?;
+        self.metrics.add_partitions_considered(partition_keys.len());
 
         for key in partition_keys {
             // TODO prune partitions somehow
             for chunk in &database.chunks(&key).await {
+                self.metrics.inc_chunks_considered();
                 if chunk.might_pass_predicate(&predicate) {
                     let plan = chunk
                         .table_names(&predicate)
@@ -73,6 +91,8 @@ impl InfluxRPCPlanner {
                         .context(TableNamePlan)?;
 
                     plans.push(plan);
+                } else {
+                    self.metrics.inc_chunks_pruned();
                 }
             }
         }
@@ -1,17 +1,46 @@
-use std::sync::Arc;
+use std::{borrow::Cow, str::FromStr, sync::Arc};
 
-use snafu::{ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 
-use crate::{exec::Executor, Database, PartitionChunk};
-use arrow_deps::datafusion::{
-    datasource::MemTable, error::DataFusionError, physical_plan::ExecutionPlan,
+use crate::{
+    exec::{batch_size::BatchSizeConfig, Executor, QueryMetrics},
+    func::cardinality::{
+        make_cardinality_udf, TagCardinality, CARDINALITY_FUNCTION_NAME, TAG_CARDINALITY_TABLE_NAME,
+    },
+    system_tables::{
+        ColumnEncodings, LifecycleEvents, ObjectStoreContents, SeriesOverlap,
+        COLUMN_ENCODINGS_TABLE_NAME, LIFECYCLE_EVENTS_TABLE_NAME, OBJECT_STORE_TABLE_NAME,
+        SERIES_OVERLAP_TABLE_NAME,
+    },
+    util::make_scan_plan,
+    Database, PartitionChunk, TableRowCount,
+};
+use arrow_deps::{
+    arrow::{
+        array::{Array, BooleanArray, Int64Array, StringArray, UInt64Array},
+        compute::kernels::filter::filter_record_batch,
+        datatypes::{DataType, Field, Schema, SchemaRef},
+        error::ArrowError,
+        record_batch::RecordBatch,
+    },
+    datafusion::{datasource::MemTable, error::DataFusionError, physical_plan::ExecutionPlan},
 };
+use data_types::{access_policy::ColumnFilter, INGEST_TIME_COLUMN_NAME, TIME_COLUMN_NAME};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Error preparing query {}", source))]
     Preparing { source: crate::exec::context::Error },
 
+    #[snafu(display(
+        "Unknown chunk selection '{}': expected all, in_memory or persisted",
+        name
+    ))]
+    UnknownChunkSelection { name: String },
+
+    #[snafu(display("Unterminated hint in query {}: expected */", query))]
+    UnterminatedHint { query: String },
+
     #[snafu(display("Invalid sql query: {} : {}", query, source))]
     InvalidSqlQuery {
         query: String,
@@ -38,42 +67,376 @@ pub enum Error {
 
     #[snafu(display("No rows found in table {} while executing '{}'", table, query))]
     InternalNoRowsInTable { table: String, query: String },
+
+    #[snafu(display(
+        "Internal error splitting record batches for table {}: {}",
+        table,
+        source
+    ))]
+    InternalSplittingBatches { table: String, source: ArrowError },
+
+    #[snafu(display("Internal error reading tag values: {}", source))]
+    InternalTagValues {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Internal error reading column encodings: {}", source))]
+    InternalColumnEncodings {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Internal error reading series keys: {}", source))]
+    InternalSeriesKeys {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Internal error listing stored objects: {}", source))]
+    InternalStoredObjects {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Internal error creating system table {}: {}", table, source))]
+    InternalSystemTableCreation { table: String, source: ArrowError },
+
+    #[snafu(display("Internal error counting the rows of table {}: {}", table, source))]
+    InternalTableRowCount {
+        table: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display(
+        "System table {} can't be queried by principals restricted to some rows",
+        table
+    ))]
+    RestrictedSystemTable { table: String },
+
+    #[snafu(display(
+        "Column {} of table {} can't be used to restrict rows: only strings and tags can",
+        column,
+        table
+    ))]
+    InvalidRowFilterColumn { table: String, column: String },
+
+    #[snafu(display("Internal error restricting the rows of table {}: {}", table, source))]
+    InternalRowFiltering { table: String, source: ArrowError },
+
+    #[snafu(display("Internal error: the time column of table {} isn't an i64", table))]
+    InternalTimeColumnType { table: String },
+
+    #[snafu(display("Internal error creating row count of table {}: {}", table, source))]
+    InternalCountCreation { table: String, source: ArrowError },
+
+    #[snafu(display(
+        "Internal error creating plan for row count of table {}: {}",
+        table,
+        source
+    ))]
+    InternalCountPlanCreation {
+        table: String,
+        source: DataFusionError,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Which chunks a query reads: for example only the data that is only in
+/// memory, for latency-sensitive alerting, or only persisted data, to
+/// verify a backfill. Data is persisted once it is snapshotted to object
+/// storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkSelection {
+    All,
+    /// Only the chunks whose data hasn't been persisted
+    InMemory,
+    /// Only the chunks whose data has been persisted, whether or not it is
+    /// also still in memory
+    Persisted,
+}
+
+impl Default for ChunkSelection {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+impl FromStr for ChunkSelection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(Self::All),
+            "in_memory" => Ok(Self::InMemory),
+            "persisted" => Ok(Self::Persisted),
+            _ => UnknownChunkSelection { name: s }.fail(),
+        }
+    }
+}
+
+impl ChunkSelection {
+    /// Returns true if queries with this selection read `chunk` of the
+    /// partition `partition_key` of `database`
+    pub fn includes<D: Database>(
+        self,
+        database: &D,
+        partition_key: &str,
+        chunk: &D::Chunk,
+    ) -> bool {
+        match self {
+            Self::All => true,
+            Self::InMemory => !database.chunk_persisted(partition_key, chunk),
+            Self::Persisted => database.chunk_persisted(partition_key, chunk),
+        }
+    }
+}
+
 /// This struct can create plans for running SQL queries against databases
 #[derive(Debug, Default)]
-pub struct SQLQueryPlanner {}
+pub struct SQLQueryPlanner {
+    /// If set, overrides the executor's RecordBatch sizing for
+    /// queries planned by this planner
+    batch_config: Option<BatchSizeConfig>,
+
+    /// Records the work done for queries planned by this planner
+    metrics: Arc<QueryMetrics>,
+
+    /// The predicates every row read by queries planned by this planner
+    /// must satisfy
+    column_filters: Vec<ColumnFilter>,
+
+    /// The chunks read by queries planned by this planner without a hint
+    /// selecting them
+    chunk_selection: ChunkSelection,
+}
 
 impl SQLQueryPlanner {
+    /// Use `batch_config` to size the RecordBatches produced by the
+    /// planned query, rather than the executor's default
+    pub fn with_batch_config(self, batch_config: BatchSizeConfig) -> Self {
+        Self {
+            batch_config: Some(batch_config),
+            ..self
+        }
+    }
+
+    /// Record the partitions, chunks and rows read while planning
+    /// queries in `metrics`
+    pub fn with_metrics(self, metrics: Arc<QueryMetrics>) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Only read the rows satisfying all of `column_filters`, such as
+    /// the filters an access policy requires for the principal querying.
+    /// The rows are filtered as the tables are read, so no part of the
+    /// query can see the others. System tables summarize all rows, so
+    /// they can't be queried.
+    pub fn with_column_filters(self, column_filters: Vec<ColumnFilter>) -> Self {
+        Self {
+            column_filters,
+            ..self
+        }
+    }
+
+    /// Only read the chunks `chunk_selection` includes, unless a query
+    /// selects its chunks with a hint
+    pub fn with_chunk_selection(self, chunk_selection: ChunkSelection) -> Self {
+        Self {
+            chunk_selection,
+            ..self
+        }
+    }
+
     /// Plan a SQL query against the data in `database`, and return a
     /// DataFusion physical execution plan. The plan can then be
     /// executed using `executor` in a streaming fashion.
+    ///
+    /// The query can start with a hint selecting the chunks it reads,
+    /// such as `/*+ in_memory */ select ...`, which overrides the chunk
+    /// selection of the planner.
     pub async fn query<D: Database>(
         &self,
         database: &D,
         query: &str,
         executor: &Executor,
     ) -> Result<Arc<dyn ExecutionPlan>> {
-        let mut ctx = executor.new_context();
+        let (hint, query) = chunk_selection_hint(query)?;
+        let chunk_selection = hint.unwrap_or(self.chunk_selection);
+
+        let mut ctx = match self.batch_config {
+            Some(batch_config) => executor.new_context_with_batch_config(batch_config),
+            None => executor.new_context(),
+        };
 
         // figure out the table names that appear in the sql
-        let table_names = table_names(query)?;
+        let statements = parse(query)?;
+        let table_names = table_names(query, &statements)?;
+        let scan_limit = scan_limit(&statements);
 
         let partition_keys = database.partition_keys().await.unwrap();
+        self.metrics.add_partitions_considered(partition_keys.len());
+
+        // Answer counting queries from the row counts the chunks keep,
+        // rather than reading the table, unless only some rows are counted:
+        // the rows of the principal, or those not past the retention
+        // boundary of the table
+        let restricted = !self.column_filters.is_empty();
+        let count = count_only(&statements).filter(|count| {
+            database
+                .retention_boundary(Some(count.table.as_str()))
+                .is_none()
+        });
+        if let (false, Some(count)) = (restricted, count) {
+            if let Some(rows) =
+                count_rows(database, &partition_keys, &count, chunk_selection).await?
+            {
+                let table = &count.table;
+                let batch = RecordBatch::try_new(
+                    Arc::new(Schema::new(vec![Field::new(
+                        &count.column_name,
+                        DataType::UInt64,
+                        false,
+                    )])),
+                    vec![Arc::new(UInt64Array::from(vec![rows]))],
+                )
+                .context(InternalCountCreation { table })?;
+
+                let plan = make_scan_plan(batch).context(InternalCountPlanCreation { table })?;
+                return ctx.create_physical_plan(&plan).context(Preparing);
+            }
+        }
+
+        // Gather the tag values from the chunk dictionaries only if the
+        // query needs them
+        let fingerprint = fingerprint(&statements);
+        let calls_cardinality = calls_function(query, CARDINALITY_FUNCTION_NAME)?;
+        if restricted && calls_cardinality {
+            return RestrictedSystemTable {
+                table: CARDINALITY_FUNCTION_NAME,
+            }
+            .fail();
+        }
+        let tag_cardinality =
+            if calls_cardinality || table_names.iter().any(|t| t == TAG_CARDINALITY_TABLE_NAME) {
+                let tag_cardinality = tag_cardinality(database, &partition_keys).await?;
+                Some(Arc::new(tag_cardinality))
+            } else {
+                None
+            };
 
         // Register a table provider for each table so DataFusion
         // knows what the schema of that table is and how to obtain
         // its data when needed.
+        let mut schemas = Vec::with_capacity(table_names.len());
         for table in &table_names {
-            let mut data = Vec::new();
-            for partition_key in &partition_keys {
+            let system_table = match (table.as_str(), &tag_cardinality) {
+                (TAG_CARDINALITY_TABLE_NAME, Some(tag_cardinality)) => {
+                    Some(tag_cardinality.to_batch())
+                }
+                (COLUMN_ENCODINGS_TABLE_NAME, _) => Some(
+                    column_encodings(database, &partition_keys)
+                        .await?
+                        .to_batch(),
+                ),
+                (SERIES_OVERLAP_TABLE_NAME, _) => {
+                    Some(series_overlap(database, &partition_keys).await?.to_batch())
+                }
+                (OBJECT_STORE_TABLE_NAME, _) => {
+                    let objects = database
+                        .stored_objects()
+                        .await
+                        .map_err(|e| Box::new(e) as _)
+                        .context(InternalStoredObjects)?;
+                    Some(ObjectStoreContents::new(objects).to_batch())
+                }
+                (LIFECYCLE_EVENTS_TABLE_NAME, _) => {
+                    Some(LifecycleEvents::new(database.lifecycle_events()).to_batch())
+                }
+                _ => None,
+            };
+            if let Some(batch) = system_table {
+                ensure!(!restricted, RestrictedSystemTable { table });
+                let batch = batch.context(InternalSystemTableCreation { table })?;
+                let schema = batch.schema();
+                schemas.push((table.clone(), schema.clone()));
+                let provider = Box::new(
+                    MemTable::try_new(schema, vec![vec![batch]])
+                        .context(InternalMemTableCreation { table })?,
+                );
+                ctx.inner_mut().register_table(&table, provider);
+                continue;
+            }
+
+            // Each chunk's data becomes a separate partition of the
+            // table, so DataFusion can work on the chunks in parallel;
+            // for example aggregating each one partially before
+            // combining the partial results, rather than aggregating
+            // all the rows of the table at once
+            let mut partitions = Vec::new();
+            let mut excluded = Vec::new();
+            let mut rows_read = 0;
+            let boundary = database.retention_boundary(Some(table.as_str()));
+            'scan: for partition_key in &partition_keys {
                 for chunk in database.chunks(partition_key).await {
+                    // stop once there are enough rows to satisfy a LIMIT,
+                    // but not before some rows tell the schema of the table
+                    // (which even `LIMIT 0` needs)
+                    let enough = matches!(scan_limit, Some(limit) if rows_read >= limit);
+                    if enough && !partitions.is_empty() {
+                        break 'scan;
+                    }
+                    if !chunk_selection.includes(database, partition_key, chunk.as_ref()) {
+                        excluded.push(chunk);
+                        continue;
+                    }
+                    self.metrics.inc_chunks_considered();
+
+                    // Rows past the retention boundary of the table may not
+                    // have been dropped yet, so they are left out, skipping
+                    // the chunks whose time range says they all are
+                    let expired_before = match boundary {
+                        Some(boundary) => {
+                            let time_range = chunk
+                                .table_row_count(table)
+                                .map_err(|e| Box::new(e) as _)
+                                .context(InternalTableRowCount { table })?
+                                .and_then(|count| count.time_range);
+                            match time_range {
+                                Some((_, max)) if max < boundary => {
+                                    self.metrics.inc_chunks_pruned();
+                                    excluded.push(chunk);
+                                    continue;
+                                }
+                                Some((min, _)) if min >= boundary => None,
+                                _ => Some(boundary),
+                            }
+                        }
+                        None => None,
+                    };
+
+                    let mut data = Vec::new();
                     chunk
                         .table_to_arrow(&mut data, &table, &[])
                         .map_err(|e| Box::new(e) as _)
-                        .context(InternalTableConversion { table })?
+                        .context(InternalTableConversion { table })?;
+                    if restricted {
+                        data = data
+                            .iter()
+                            .map(|batch| filter_rows(table, batch, &self.column_filters))
+                            .collect::<Result<_>>()?;
+                    }
+                    if let Some(boundary) = expired_before {
+                        data = data
+                            .iter()
+                            .map(|batch| retained_rows(table, batch, boundary))
+                            .collect::<Result<_>>()?;
+                    }
+
+                    let rows = data.iter().map(|b| b.num_rows()).sum();
+                    self.metrics.add_rows_scanned(rows);
+                    rows_read += rows;
+
+                    if !data.is_empty() {
+                        partitions.push(data);
+                    }
                 }
             }
 
@@ -84,44 +447,365 @@ impl SQLQueryPlanner {
             // the entire table here)
 
             // if the table was reported to exist, it should not be empty (eventually we
-            // should get the schema and table data separtely)
-            if data.is_empty() {
-                return InternalNoRowsInTable { table, query }.fail();
+            // should get the schema and table data separtely). If every
+            // chunk holding it was left out, the query sees no rows, with
+            // the schema of the table in one of those chunks.
+            if partitions.is_empty() {
+                let schema = excluded
+                    .iter()
+                    .find_map(|chunk| {
+                        let mut data = Vec::new();
+                        // chunks left out before their tables were looked at
+                        // may not hold this one
+                        chunk.table_to_arrow(&mut data, &table, &[]).ok()?;
+                        data.first().map(RecordBatch::schema)
+                    })
+                    .context(InternalNoRowsInTable { table, query })?;
+                schemas.push((table.clone(), schema.clone()));
+                let provider = Box::new(
+                    MemTable::try_new(schema, vec![vec![]])
+                        .context(InternalMemTableCreation { table })?,
+                );
+                ctx.inner_mut().register_table(&table, provider);
+                continue;
             }
 
-            let schema = data[0].schema().clone();
+            // Chunks are converted into a single RecordBatch each, which
+            // can be huge for wide tables, so split them up here
+            let partitions = partitions
+                .into_iter()
+                .map(|data| ctx.batch_config().split_batches(data))
+                .collect::<Result<Vec<_>, _>>()
+                .context(InternalSplittingBatches { table })?;
+
+            let schema = partitions[0][0].schema().clone();
+            schemas.push((table.clone(), schema.clone()));
             let provider = Box::new(
-                MemTable::try_new(schema, vec![data])
+                MemTable::try_new(schema, partitions)
                     .context(InternalMemTableCreation { table })?,
             );
 
             ctx.inner_mut().register_table(&table, provider);
         }
 
-        ctx.prepare_sql(query).await.context(Preparing)
+        // `SELECT *` leaves out the time each row was accepted
+        let query = hide_ingest_time(query, &statements, &schemas);
+        let query = query.as_ref();
+
+        // The `cardinality` function answers from the tag values just
+        // gathered, which a cached plan would keep using, so plans
+        // calling it are never cached
+        if let (true, Some(tag_cardinality)) = (calls_cardinality, tag_cardinality) {
+            ctx.inner_mut()
+                .register_udf(make_cardinality_udf(tag_cardinality));
+            let plan = ctx.plan_sql(query).context(Preparing)?;
+            return ctx.create_physical_plan(&plan).context(Preparing);
+        }
+
+        // The cached plans refer to tables by name, so they can be
+        // reused with the data just registered as long as the schemas
+        // are unchanged
+        let plan_cache = executor.plan_cache();
+        let plan = match plan_cache.get(database.name(), &fingerprint, &schemas) {
+            Some(plan) => {
+                self.metrics.inc_plan_cache_hits();
+                plan
+            }
+            None => {
+                let plan = ctx.plan_sql(query).context(Preparing)?;
+                plan_cache.insert(database.name(), fingerprint, schemas, plan.clone());
+                plan
+            }
+        };
+
+        ctx.create_physical_plan(&plan).context(Preparing)
+    }
+}
+
+/// Returns the rows of `batch` of `table` satisfying all of `filters`.
+/// Rows without a value for a filtered column never satisfy it.
+fn filter_rows(table: &str, batch: &RecordBatch, filters: &[ColumnFilter]) -> Result<RecordBatch> {
+    let mut keep = vec![true; batch.num_rows()];
+    for filter in filters {
+        let column = match batch.schema().index_of(&filter.column) {
+            Ok(index) => batch.column(index),
+            Err(_) => {
+                keep.iter_mut().for_each(|keep| *keep = false);
+                continue;
+            }
+        };
+        let column =
+            column
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .context(InvalidRowFilterColumn {
+                    table,
+                    column: &filter.column,
+                })?;
+
+        for (row, keep) in keep.iter_mut().enumerate() {
+            *keep = *keep && column.is_valid(row) && column.value(row) == filter.value;
+        }
+    }
+
+    filter_record_batch(batch, &BooleanArray::from(keep)).context(InternalRowFiltering { table })
+}
+
+/// Returns the rows of `batch` of `table` with a timestamp of at least
+/// `boundary`. Rows without a timestamp never expire.
+fn retained_rows(table: &str, batch: &RecordBatch, boundary: i64) -> Result<RecordBatch> {
+    let index = match batch.schema().index_of(TIME_COLUMN_NAME) {
+        Ok(index) => index,
+        Err(_) => return Ok(batch.clone()),
+    };
+    let times = batch
+        .column(index)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .context(InternalTimeColumnType { table })?;
+
+    let keep: Vec<_> = (0..batch.num_rows())
+        .map(|row| times.is_null(row) || times.value(row) >= boundary)
+        .collect();
+    filter_record_batch(batch, &BooleanArray::from(keep)).context(InternalRowFiltering { table })
+}
+
+/// Gathers the distinct values of the tags of every table from the
+/// dictionaries of all chunks
+async fn tag_cardinality<D: Database>(
+    database: &D,
+    partition_keys: &[String],
+) -> Result<TagCardinality> {
+    let mut tag_cardinality = TagCardinality::default();
+    for partition_key in partition_keys {
+        for chunk in database.chunks(partition_key).await {
+            let tag_values = chunk
+                .all_tag_values()
+                .map_err(|e| Box::new(e) as _)
+                .context(InternalTagValues)?;
+            tag_cardinality.add(tag_values);
+        }
+    }
+    Ok(tag_cardinality)
+}
+
+/// Gathers the encodings of the columns of all chunks
+async fn column_encodings<D: Database>(
+    database: &D,
+    partition_keys: &[String],
+) -> Result<ColumnEncodings> {
+    let mut column_encodings = ColumnEncodings::default();
+    for partition_key in partition_keys {
+        for chunk in database.chunks(partition_key).await {
+            let encodings = chunk
+                .column_encodings()
+                .map_err(|e| Box::new(e) as _)
+                .context(InternalColumnEncodings)?;
+            column_encodings.add(partition_key, chunk.id(), encodings);
+        }
     }
+    Ok(column_encodings)
+}
+
+/// Gathers the series keys of all chunks
+async fn series_overlap<D: Database>(
+    database: &D,
+    partition_keys: &[String],
+) -> Result<SeriesOverlap> {
+    let mut series_overlap = SeriesOverlap::default();
+    for partition_key in partition_keys {
+        for chunk in database.chunks(partition_key).await {
+            let series_keys = chunk
+                .series_keys()
+                .map_err(|e| Box::new(e) as _)
+                .context(InternalSeriesKeys)?;
+            series_overlap.add(partition_key, series_keys);
+        }
+    }
+    Ok(series_overlap)
+}
+
+/// Counts the rows of `count.table` within its time range using only the
+/// row counts and time ranges of the chunks. Returns `None` if that isn't
+/// possible (a chunk doesn't know its row count, or only some of its rows
+/// are in the time range) or no chunk has rows for the table, in which
+/// case the query has to be run normally.
+async fn count_rows<D: Database>(
+    database: &D,
+    partition_keys: &[String],
+    count: &CountOnly,
+    chunk_selection: ChunkSelection,
+) -> Result<Option<u64>> {
+    let table = &count.table;
+    let mut total = 0;
+    let mut found = false;
+
+    for partition_key in partition_keys {
+        for chunk in database.chunks(partition_key).await {
+            if !chunk_selection.includes(database, partition_key, chunk.as_ref()) {
+                continue;
+            }
+            let row_count = chunk
+                .table_row_count(table)
+                .map_err(|e| Box::new(e) as _)
+                .context(InternalTableRowCount { table })?;
+
+            let TableRowCount { rows, time_range } = match row_count {
+                Some(row_count) => row_count,
+                None => return Ok(None),
+            };
+            if rows == 0 {
+                continue;
+            }
+            found = true;
+
+            if count.time_range == TimeBounds::default() {
+                total += rows;
+                continue;
+            }
+
+            match time_range {
+                Some((min, max)) if count.time_range.contains(min, max) => total += rows,
+                Some((min, max)) if count.time_range.excludes(min, max) => {}
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    Ok(if found { Some(total) } else { None })
 }
 
 use sqlparser::{
-    ast::{SetExpr, Statement, TableFactor},
+    ast::{BinaryOperator, Expr, Ident, SelectItem, SetExpr, Statement, TableFactor, Value},
     dialect::GenericDialect,
-    parser::Parser,
+    parser::{Parser, ParserError},
+    tokenizer::{Token, Tokenizer},
 };
 
+/// Splits the hint selecting the chunks a query reads, such as
+/// `/*+ persisted */`, off the start of `query`
+fn chunk_selection_hint(query: &str) -> Result<(Option<ChunkSelection>, &str)> {
+    let hint = match query.trim_start().strip_prefix("/*+") {
+        Some(hint) => hint,
+        None => return Ok((None, query)),
+    };
+    let end = hint.find("*/").context(UnterminatedHint { query })?;
+    let selection = hint[..end].trim().to_lowercase().parse()?;
+    Ok((Some(selection), &hint[end + 2..]))
+}
+
+fn parse(query: &str) -> Result<Vec<Statement>> {
+    let dialect = GenericDialect {};
+    Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })
+}
+
+/// Identifies a query regardless of its whitespace and keyword case, so
+/// that a statement re-issued with different formatting can reuse the
+/// same cached plan
+fn fingerprint(statements: &[Statement]) -> String {
+    statements
+        .iter()
+        .map(|statement| statement.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Returns true if the query calls the function `name`: the name, in any
+/// case, followed by a parenthesis. Mentions of the name in string
+/// literals or as part of other names don't count.
+fn calls_function(query: &str, name: &str) -> Result<bool> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, query)
+        .tokenize()
+        .map_err(ParserError::from)
+        .context(InvalidSqlQuery { query })?;
+
+    let mut tokens = tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .peekable();
+    while let Some(token) = tokens.next() {
+        if let Token::Word(word) = token {
+            if word.value.eq_ignore_ascii_case(name) && tokens.peek() == Some(&&Token::LParen) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Replaces the wildcards selecting the columns of a single table that
+/// has the `INGEST_TIME_COLUMN_NAME` column with its other columns, so
+/// the time each row was accepted is only returned when asked for by
+/// name. Returns the query to plan, which is `query` itself unless a
+/// wildcard was replaced.
+fn hide_ingest_time<'a>(
+    query: &'a str,
+    statements: &[Statement],
+    schemas: &[(String, SchemaRef)],
+) -> Cow<'a, str> {
+    let mut statements = statements.to_vec();
+    let mut replaced = false;
+    for statement in &mut statements {
+        let select = match statement {
+            Statement::Query(q) => match &mut q.body {
+                SetExpr::Select(select) => select,
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let table = match select.from.as_slice() {
+            [from] if from.joins.is_empty() => match &from.relation {
+                TableFactor::Table { name, .. } => name.to_string(),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let schema = match schemas.iter().find(|(name, _)| *name == table) {
+            Some((_, schema)) if schema.field_with_name(INGEST_TIME_COLUMN_NAME).is_ok() => schema,
+            _ => continue,
+        };
+
+        let columns: Vec<_> = schema
+            .fields()
+            .iter()
+            .filter(|field| field.name() != INGEST_TIME_COLUMN_NAME)
+            .map(|field| {
+                SelectItem::UnnamedExpr(Expr::Identifier(Ident::with_quote(
+                    '"',
+                    field.name().as_str(),
+                )))
+            })
+            .collect();
+        for item in std::mem::take(&mut select.projection) {
+            match item {
+                SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => {
+                    select.projection.extend(columns.iter().cloned());
+                    replaced = true;
+                }
+                item => select.projection.push(item),
+            }
+        }
+    }
+
+    if replaced {
+        Cow::Owned(fingerprint(&statements))
+    } else {
+        Cow::Borrowed(query)
+    }
+}
+
 /// return a list of table names that appear in the query
 /// TODO find some way to avoid using sql parser direcly here
-fn table_names(query: &str) -> Result<Vec<String>> {
+fn table_names(query: &str, statements: &[Statement]) -> Result<Vec<String>> {
     let mut tables = vec![];
 
-    let dialect = GenericDialect {};
-    let ast = Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })?;
-
-    for statement in ast {
+    for statement in statements {
         match statement {
             Statement::Query(q) => {
-                if let SetExpr::Select(q) = q.body {
-                    for item in q.from {
-                        if let TableFactor::Table { name, .. } = item.relation {
+                if let SetExpr::Select(q) = &q.body {
+                    for item in &q.from {
+                        if let TableFactor::Table { name, .. } = &item.relation {
                             tables.push(name.to_string());
                         }
                     }
@@ -130,7 +814,7 @@ fn table_names(query: &str) -> Result<Vec<String>> {
             _ => {
                 return UnsupportedStatement {
                     query: query.to_string(),
-                    statement,
+                    statement: Box::new(statement.clone()),
                 }
                 .fail()
             }
@@ -138,3 +822,399 @@ fn table_names(query: &str) -> Result<Vec<String>> {
     }
     Ok(tables)
 }
+
+/// Returns the number of rows a query needs from its table, if it is a
+/// plain scan of a single table with a LIMIT. Scanning can stop once that
+/// many rows have been read, as the rows left unread can't change the
+/// results.
+///
+/// Anything that needs to look at every row (filters, aggregates,
+/// grouping, ordering, ...) disables this.
+fn scan_limit(statements: &[Statement]) -> Option<usize> {
+    let query = match statements {
+        [Statement::Query(query)] => query,
+        _ => return None,
+    };
+
+    if !query.order_by.is_empty() || query.offset.is_some() {
+        return None;
+    }
+
+    let limit = match &query.limit {
+        Some(Expr::Value(Value::Number(n))) => n.parse().ok()?,
+        _ => return None,
+    };
+
+    let select = match &query.body {
+        SetExpr::Select(select) => select,
+        _ => return None,
+    };
+
+    let plain_scan = !select.distinct
+        && select.selection.is_none()
+        && select.group_by.is_empty()
+        && select.having.is_none()
+        && matches!(select.from.as_slice(), [from] if from.joins.is_empty())
+        && select.projection.iter().all(|item| match item {
+            SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => true,
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                matches!(expr, Expr::Identifier(_) | Expr::CompoundIdentifier(_))
+            }
+        });
+
+    if plain_scan {
+        Some(limit)
+    } else {
+        None
+    }
+}
+
+/// A query that only counts the rows of a table, optionally restricted to
+/// a range of times
+#[derive(Debug, PartialEq)]
+struct CountOnly {
+    table: String,
+    /// The name of the single column of the results
+    column_name: String,
+    time_range: TimeBounds,
+}
+
+/// An inclusive range of timestamps
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TimeBounds {
+    start: i64,
+    end: i64,
+}
+
+impl Default for TimeBounds {
+    fn default() -> Self {
+        Self {
+            start: i64::MIN,
+            end: i64::MAX,
+        }
+    }
+}
+
+impl TimeBounds {
+    /// Returns true if every time in [min, max] is within the range
+    fn contains(&self, min: i64, max: i64) -> bool {
+        self.start <= min && max <= self.end
+    }
+
+    /// Returns true if no time in [min, max] is within the range
+    fn excludes(&self, min: i64, max: i64) -> bool {
+        max < self.start || self.end < min
+    }
+
+    /// Narrows the range to the times for which `time <op> value` is
+    /// true. Returns None if `op` isn't a comparison.
+    fn restrict(&mut self, op: &BinaryOperator, value: i64) -> Option<()> {
+        match op {
+            BinaryOperator::Gt => self.start = self.start.max(value.checked_add(1)?),
+            BinaryOperator::GtEq => self.start = self.start.max(value),
+            BinaryOperator::Lt => self.end = self.end.min(value.checked_sub(1)?),
+            BinaryOperator::LtEq => self.end = self.end.min(value),
+            BinaryOperator::Eq => {
+                self.start = self.start.max(value);
+                self.end = self.end.min(value);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Narrows the range by `expr`, which must be a conjunction of
+    /// comparisons of the time column with integer literals
+    fn restrict_by(&mut self, expr: &Expr) -> Option<()> {
+        match expr {
+            Expr::Nested(expr) => self.restrict_by(expr),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => {
+                self.restrict_by(left)?;
+                self.restrict_by(right)
+            }
+            Expr::BinaryOp { left, op, right } => match (left.as_ref(), right.as_ref()) {
+                (Expr::Identifier(column), value) if column.value == TIME_COLUMN_NAME => {
+                    self.restrict(op, timestamp(value)?)
+                }
+                (value, Expr::Identifier(column)) if column.value == TIME_COLUMN_NAME => {
+                    let op = match op {
+                        BinaryOperator::Gt => BinaryOperator::Lt,
+                        BinaryOperator::GtEq => BinaryOperator::LtEq,
+                        BinaryOperator::Lt => BinaryOperator::Gt,
+                        BinaryOperator::LtEq => BinaryOperator::GtEq,
+                        op => op.clone(),
+                    };
+                    self.restrict(&op, timestamp(value)?)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+fn timestamp(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Value(Value::Number(n)) => n.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Returns what to count if the query is `SELECT count(*) FROM <table>`,
+/// optionally with a WHERE clause that only restricts the time column to
+/// a range of integer timestamps. Such a query can be answered without
+/// reading any data.
+fn count_only(statements: &[Statement]) -> Option<CountOnly> {
+    let query = match statements {
+        [Statement::Query(query)] => query,
+        _ => return None,
+    };
+
+    if !query.order_by.is_empty() || query.limit.is_some() || query.offset.is_some() {
+        return None;
+    }
+
+    let select = match &query.body {
+        SetExpr::Select(select) => select,
+        _ => return None,
+    };
+
+    if select.distinct || !select.group_by.is_empty() || select.having.is_some() {
+        return None;
+    }
+
+    let table = match select.from.as_slice() {
+        [from] if from.joins.is_empty() => match &from.relation {
+            TableFactor::Table { name, .. } => name.to_string(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (expr, alias) = match select.projection.as_slice() {
+        [SelectItem::UnnamedExpr(expr)] => (expr, None),
+        [SelectItem::ExprWithAlias { expr, alias }] => (expr, Some(alias.to_string())),
+        _ => return None,
+    };
+
+    let is_count_star = match expr {
+        Expr::Function(function) => {
+            function.name.to_string().eq_ignore_ascii_case("count")
+                && !function.distinct
+                && function.over.is_none()
+                && matches!(function.args.as_slice(), [Expr::Wildcard])
+        }
+        _ => false,
+    };
+    if !is_count_star {
+        return None;
+    }
+
+    let mut time_range = TimeBounds::default();
+    if let Some(selection) = &select.selection {
+        time_range.restrict_by(selection)?;
+    }
+
+    Some(CountOnly {
+        table,
+        // the name DataFusion gives the results of `count(*)`
+        column_name: alias.unwrap_or_else(|| "COUNT(UInt8(1))".to_string()),
+        time_range,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(query: &str) -> Option<usize> {
+        scan_limit(&parse(query).unwrap())
+    }
+
+    #[test]
+    fn ingest_time_is_hidden_from_wildcards() {
+        let with_ingest_time = Arc::new(Schema::new(vec![
+            Field::new("usage", DataType::Float64, true),
+            Field::new(INGEST_TIME_COLUMN_NAME, DataType::Int64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let without = Arc::new(Schema::new(vec![Field::new("free", DataType::Int64, true)]));
+        let schemas = vec![
+            ("cpu".to_string(), with_ingest_time),
+            ("mem".to_string(), without),
+        ];
+        let rewrite =
+            |query: &str| hide_ingest_time(query, &parse(query).unwrap(), &schemas).into_owned();
+
+        assert_eq!(
+            rewrite("select * from cpu where usage > 1"),
+            r#"SELECT "usage", "time" FROM cpu WHERE usage > 1"#
+        );
+        assert_eq!(
+            rewrite("select c.*, _ingest_time from cpu as c"),
+            r#"SELECT "usage", "time", _ingest_time FROM cpu AS c"#
+        );
+        // queries naming their columns, and tables without the column,
+        // are planned as they are
+        let query = "select usage, _ingest_time from cpu";
+        assert_eq!(rewrite(query), query);
+        let query = "select * from mem";
+        assert_eq!(rewrite(query), query);
+    }
+
+    #[test]
+    fn scan_limit_only_for_plain_scans() {
+        assert_eq!(limit("select * from cpu limit 10"), Some(10));
+        assert_eq!(limit("select host, usage as u from cpu limit 3"), Some(3));
+
+        assert_eq!(limit("select * from cpu"), None);
+        assert_eq!(limit("select * from cpu where usage > 1 limit 10"), None);
+        assert_eq!(limit("select count(*) from cpu limit 10"), None);
+        assert_eq!(limit("select host from cpu group by host limit 10"), None);
+        assert_eq!(limit("select distinct host from cpu limit 10"), None);
+        assert_eq!(limit("select * from cpu order by usage limit 10"), None);
+        assert_eq!(limit("select * from cpu limit 10 offset 5"), None);
+        assert_eq!(
+            limit("select * from cpu join mem on cpu.host = mem.host limit 10"),
+            None
+        );
+    }
+
+    #[test]
+    fn calls_function_only_for_calls() {
+        let calls = |query| calls_function(query, "cardinality").unwrap();
+        assert!(calls("select cardinality('cpu', 'host')"));
+        assert!(calls("select CARDINALITY ('cpu', 'host') from t"));
+
+        assert!(!calls("select * from cpu"));
+        assert!(!calls("select * from cpu where host = 'cardinality('"));
+        assert!(!calls("select my_cardinality('cpu', 'host')"));
+        assert!(!calls("select cardinality from t"));
+    }
+
+    #[test]
+    fn chunk_selection_hints() {
+        let (hint, query) = chunk_selection_hint("select * from cpu").unwrap();
+        assert_eq!(hint, None);
+        assert_eq!(query, "select * from cpu");
+
+        let (hint, query) = chunk_selection_hint(" /*+ IN_MEMORY */ select * from cpu").unwrap();
+        assert_eq!(hint, Some(ChunkSelection::InMemory));
+        assert_eq!(query, " select * from cpu");
+
+        let (hint, _) = chunk_selection_hint("/*+persisted*/select * from cpu").unwrap();
+        assert_eq!(hint, Some(ChunkSelection::Persisted));
+
+        let err = chunk_selection_hint("/*+ newest */ select * from cpu").unwrap_err();
+        assert!(matches!(err, Error::UnknownChunkSelection { .. }));
+        let err = chunk_selection_hint("/*+ persisted select * from cpu").unwrap_err();
+        assert!(matches!(err, Error::UnterminatedHint { .. }));
+    }
+
+    fn count(query: &str) -> Option<CountOnly> {
+        count_only(&parse(query).unwrap())
+    }
+
+    fn bounds(start: i64, end: i64) -> TimeBounds {
+        TimeBounds { start, end }
+    }
+
+    #[test]
+    fn count_only_queries() {
+        assert_eq!(
+            count("select count(*) from cpu"),
+            Some(CountOnly {
+                table: "cpu".into(),
+                column_name: "COUNT(UInt8(1))".into(),
+                time_range: TimeBounds::default(),
+            })
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) AS n FROM cpu WHERE time >= 10 AND (time < 20)"),
+            Some(CountOnly {
+                table: "cpu".into(),
+                column_name: "n".into(),
+                time_range: bounds(10, 19),
+            })
+        );
+
+        let time_range = |query| count(query).map(|count| count.time_range);
+        assert_eq!(
+            time_range("select count(*) from cpu where 10 < time and time <= 20"),
+            Some(bounds(11, 20))
+        );
+        assert_eq!(
+            time_range("select count(*) from cpu where time = 5"),
+            Some(bounds(5, 5))
+        );
+
+        assert_eq!(count("select count(*) from cpu where host = 'a'"), None);
+        assert_eq!(
+            count("select count(*) from cpu where time > 1 or time < 0"),
+            None
+        );
+        assert_eq!(count("select count(usage) from cpu"), None);
+        assert_eq!(count("select count(distinct host) from cpu"), None);
+        assert_eq!(count("select count(*), max(usage) from cpu"), None);
+        assert_eq!(count("select host, count(*) from cpu group by host"), None);
+        assert_eq!(count("select count(*) from cpu limit 0"), None);
+        assert_eq!(
+            count("select count(*) from cpu join mem on cpu.host = mem.host"),
+            None
+        );
+    }
+
+    #[test]
+    fn time_bounds_classify_chunks() {
+        let range = bounds(10, 19);
+        assert!(range.contains(10, 19));
+        assert!(!range.contains(9, 15));
+        assert!(range.excludes(20, 30));
+        assert!(range.excludes(0, 9));
+        assert!(!range.excludes(15, 30));
+    }
+
+    #[test]
+    fn rows_are_filtered_by_string_columns() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("tenant_id", DataType::Utf8, true),
+            Field::new("usage", DataType::UInt64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![Some("acme"), Some("globex"), None])),
+                Arc::new(UInt64Array::from(vec![1, 2, 3])),
+            ],
+        )
+        .unwrap();
+        let filter = |column: &str| ColumnFilter {
+            column: column.to_string(),
+            value: "acme".to_string(),
+        };
+
+        let rows = filter_rows("cpu", &batch, &[filter("tenant_id")]).unwrap();
+        assert_eq!(rows.num_rows(), 1);
+        let usage = rows
+            .column(1)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(usage.value(0), 1);
+
+        // tables without the column have no visible rows
+        let rows = filter_rows("cpu", &batch, &[filter("region")]).unwrap();
+        assert_eq!(rows.num_rows(), 0);
+
+        let err = filter_rows("cpu", &batch, &[filter("usage")]).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidRowFilterColumn { .. }),
+            "{}",
+            err
+        );
+    }
+}
@@ -1,3 +1,5 @@
 //! Special IOx functions used in DataFusion plans
+pub mod cardinality;
+pub mod regex_match;
 pub mod selectors;
 pub mod window;
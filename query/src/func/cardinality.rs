@@ -0,0 +1,200 @@
+//! This module contains the `cardinality` SQL function and the
+//! `system.tag_cardinality` table, which report the number of distinct
+//! values of each tag of each table. They let users find tags with a
+//! runaway number of values without exporting any data.
+
+use std::sync::Arc;
+
+use arrow_deps::{
+    arrow::{
+        array::{Array, ArrayRef, StringArray, StringBuilder, UInt64Array, UInt64Builder},
+        datatypes::{DataType, Field, Schema},
+        error::Result as ArrowResult,
+        record_batch::RecordBatch,
+    },
+    datafusion::{
+        physical_plan::{functions::ScalarFunctionImplementation, udf::ScalarUDF},
+        prelude::create_udf,
+    },
+};
+
+use crate::TableTagValues;
+
+// Reuse DataFusion error and Result types for this module
+pub use arrow_deps::datafusion::error::{DataFusionError as Error, Result};
+
+/// The name of the function returning the cardinality of a tag, called
+/// as `cardinality(<table name>, <tag name>)`
+pub const CARDINALITY_FUNCTION_NAME: &str = "cardinality";
+
+/// The name of the table listing the cardinality of every tag
+pub const TAG_CARDINALITY_TABLE_NAME: &str = "system.tag_cardinality";
+
+/// The distinct values of the tags of all tables, gathered from the
+/// dictionaries of any number of chunks
+#[derive(Debug, Default)]
+pub struct TagCardinality {
+    values: TableTagValues,
+}
+
+impl TagCardinality {
+    /// Adds the tag values of a chunk. Values that were already added
+    /// from another chunk are only counted once.
+    pub fn add(&mut self, chunk_values: TableTagValues) {
+        for (table_name, tags) in chunk_values {
+            let table = self.values.entry(table_name).or_default();
+            for (tag, values) in tags {
+                table.entry(tag).or_default().extend(values);
+            }
+        }
+    }
+
+    /// Returns the number of distinct values of `tag` in `table_name`, or
+    /// None if the table has no such tag
+    pub fn get(&self, table_name: &str, tag: &str) -> Option<u64> {
+        self.values
+            .get(table_name)?
+            .get(tag)
+            .map(|values| values.len() as u64)
+    }
+
+    /// Returns the contents of the `system.tag_cardinality` table: the
+    /// number of distinct values of every tag, ordered by table and tag
+    /// name
+    pub fn to_batch(&self) -> ArrowResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("tag", DataType::Utf8, false),
+            Field::new("cardinality", DataType::UInt64, false),
+        ]));
+
+        let mut table_names = StringBuilder::new(self.values.len());
+        let mut tags = StringBuilder::new(self.values.len());
+        let mut cardinalities = UInt64Builder::new(self.values.len());
+
+        for (table_name, table) in &self.values {
+            for (tag, values) in table {
+                table_names.append_value(table_name)?;
+                tags.append_value(tag)?;
+                cardinalities.append_value(values.len() as u64)?;
+            }
+        }
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(table_names.finish()),
+                Arc::new(tags.finish()),
+                Arc::new(cardinalities.finish()),
+            ],
+        )
+    }
+}
+
+/// This is the implementation of the `cardinality` function. Its result
+/// is null where the table has no such tag.
+fn cardinality(args: &[ArrayRef], tag_cardinality: &TagCardinality) -> Result<ArrayRef> {
+    // this is guaranteed by DataFusion based on the function's signature.
+    assert_eq!(args.len(), 2);
+
+    let table_names = args[0]
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast of table name failed");
+    let tags = args[1]
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast of tag failed");
+
+    let cardinalities: UInt64Array = (0..table_names.len())
+        .map(|i| {
+            if table_names.is_null(i) || tags.is_null(i) {
+                None
+            } else {
+                tag_cardinality.get(table_names.value(i), tags.value(i))
+            }
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    Ok(Arc::new(cardinalities))
+}
+
+/// Create the `cardinality` user defined function, answering from
+/// `tag_cardinality`
+pub fn make_cardinality_udf(tag_cardinality: Arc<TagCardinality>) -> ScalarUDF {
+    let func_ptr: ScalarFunctionImplementation =
+        Arc::new(move |args| cardinality(args, &tag_cardinality));
+
+    create_udf(
+        CARDINALITY_FUNCTION_NAME,
+        vec![DataType::Utf8, DataType::Utf8], // argument types
+        Arc::new(DataType::UInt64),           // return type
+        func_ptr,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::assert_table_eq;
+
+    fn tag_values(table_name: &str, tag: &str, values: &[&str]) -> TableTagValues {
+        let values = values.iter().map(|v| v.to_string()).collect();
+        let tags = vec![(tag.to_string(), values)].into_iter().collect();
+        vec![(table_name.to_string(), tags)].into_iter().collect()
+    }
+
+    fn tag_cardinality() -> TagCardinality {
+        let mut tag_cardinality = TagCardinality::default();
+        tag_cardinality.add(tag_values("cpu", "host", &["a", "b"]));
+        tag_cardinality.add(tag_values("cpu", "host", &["b", "c"]));
+        tag_cardinality.add(tag_values("cpu", "region", &["west"]));
+        tag_cardinality.add(tag_values("mem", "host", &["a"]));
+        tag_cardinality
+    }
+
+    #[test]
+    fn values_are_counted_once() {
+        let tag_cardinality = tag_cardinality();
+        assert_eq!(tag_cardinality.get("cpu", "host"), Some(3));
+        assert_eq!(tag_cardinality.get("cpu", "region"), Some(1));
+        assert_eq!(tag_cardinality.get("cpu", "env"), None);
+        assert_eq!(tag_cardinality.get("disk", "host"), None);
+
+        let expected = vec![
+            "+------------+--------+-------------+",
+            "| table_name | tag    | cardinality |",
+            "+------------+--------+-------------+",
+            "| cpu        | host   | 3           |",
+            "| cpu        | region | 1           |",
+            "| mem        | host   | 1           |",
+            "+------------+--------+-------------+",
+        ];
+        assert_table_eq!(expected, &[tag_cardinality.to_batch().unwrap()]);
+    }
+
+    #[test]
+    fn cardinality_function() {
+        let table_names: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("cpu"),
+            Some("mem"),
+            None,
+            Some("disk"),
+        ]));
+        let tags: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("host"),
+            Some("host"),
+            Some("host"),
+            Some("host"),
+        ]));
+
+        let result = cardinality(&[table_names, tags], &tag_cardinality()).unwrap();
+        let expected: ArrayRef = Arc::new(UInt64Array::from(vec![Some(3), Some(1), None, None]));
+        assert_eq!(
+            &expected, &result,
+            "Expected:\n{:?}\nActual:\n{:?}",
+            expected, result,
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! This module contains the `regex_match` function, which evaluates the
+//! regular expression (`=~`, `!~`) and prefix comparisons that Flux and
+//! InfluxQL push down in storage gRPC predicates.
+
+use std::sync::Arc;
+
+use arrow_deps::{
+    arrow::{
+        array::{Array, ArrayRef, BooleanArray, StringArray},
+        datatypes::DataType,
+    },
+    datafusion::{
+        logical_plan::Expr, physical_plan::functions::ScalarFunctionImplementation, prelude::*,
+    },
+};
+use regex::Regex;
+
+// Reuse DataFusion error and Result types for this module
+pub use arrow_deps::datafusion::error::{DataFusionError as Error, Result};
+
+/// The name of the function, called as `regex_match(<value>, <pattern>)`
+pub const REGEX_MATCH_FUNCTION_NAME: &str = "regex_match";
+
+fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern)
+        .map_err(|e| Error::Plan(format!("Invalid regular expression '{}': {}", pattern, e)))
+}
+
+/// This is the implementation of the `regex_match` function. Its result
+/// is null where the value is null.
+fn regex_match(args: &[ArrayRef]) -> Result<ArrayRef> {
+    // this is guaranteed by DataFusion based on the function's signature.
+    assert_eq!(args.len(), 2);
+
+    let values = args[0]
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast of values failed");
+    let patterns = args[1]
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast of patterns failed");
+
+    // The pattern is always a literal, so all rows have the same one
+    let regex = if patterns.is_empty() {
+        None
+    } else {
+        Some(compile(patterns.value(0))?)
+    };
+
+    let matches: BooleanArray = (0..values.len())
+        .map(|i| match &regex {
+            Some(regex) if !values.is_null(i) => Some(regex.is_match(values.value(i))),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    Ok(Arc::new(matches))
+}
+
+/// Create a DataFusion `Expr` that is true where `input` matches the
+/// regular expression `pattern`. An invalid pattern is reported here
+/// rather than when the plan runs.
+pub fn make_regex_match_expr(input: Expr, pattern: &str) -> Result<Expr> {
+    compile(pattern)?;
+
+    let func_ptr: ScalarFunctionImplementation = Arc::new(regex_match);
+    let udf = create_udf(
+        REGEX_MATCH_FUNCTION_NAME,
+        vec![DataType::Utf8, DataType::Utf8], // argument types
+        Arc::new(DataType::Boolean),          // return type
+        func_ptr,
+    );
+
+    Ok(udf.call(vec![input, lit(pattern)]))
+}
+
+/// Create a DataFusion `Expr` that is true where `input` starts with
+/// `prefix`
+pub fn make_starts_with_expr(input: Expr, prefix: &str) -> Expr {
+    make_regex_match_expr(input, &starts_with_pattern(prefix))
+        .expect("escaped prefix is a valid regular expression")
+}
+
+/// Returns true if the empty string matches the regular expression
+/// `pattern`
+pub fn regex_matches_empty(pattern: &str) -> Result<bool> {
+    Ok(compile(pattern)?.is_match(""))
+}
+
+fn starts_with_pattern(prefix: &str) -> String {
+    format!("^{}", regex::escape(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(values: Vec<Option<&str>>, pattern: &str) -> Vec<Option<bool>> {
+        let values: ArrayRef = Arc::new(StringArray::from(values));
+        let patterns: ArrayRef = Arc::new(StringArray::from(vec![pattern; values.len()]));
+
+        let result = regex_match(&[values, patterns]).unwrap();
+        let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+        (0..result.len())
+            .map(|i| {
+                if result.is_null(i) {
+                    None
+                } else {
+                    Some(result.value(i))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_values() {
+        let values = vec![Some("server01"), None, Some("host.a"), Some("hostxa")];
+        assert_eq!(
+            run(values.clone(), "^server"),
+            vec![Some(true), None, Some(false), Some(false)]
+        );
+        assert_eq!(
+            run(values, &starts_with_pattern("host.")),
+            vec![Some(false), None, Some(true), Some(false)]
+        );
+        assert_eq!(run(vec![], "^server"), vec![]);
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected_when_planning() {
+        let err = make_regex_match_expr(col("host"), "(unclosed").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Invalid regular expression '(unclosed'"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(regex_matches_empty("(unclosed").is_err());
+    }
+
+    #[test]
+    fn expressions() {
+        let expr = make_regex_match_expr(col("host"), "^a.*").unwrap();
+        assert_eq!(format!("{:?}", expr), "regex_match(#host, Utf8(\"^a.*\"))");
+
+        let expr = make_starts_with_expr(col("host"), "a.b");
+        assert_eq!(
+            format!("{:?}", expr),
+            "regex_match(#host, Utf8(\"^a\\.b\"))"
+        );
+
+        assert!(regex_matches_empty("^$").unwrap());
+        assert!(regex_matches_empty("a*").unwrap());
+        assert!(!regex_matches_empty("a+").unwrap());
+    }
+}
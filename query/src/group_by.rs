@@ -2,7 +2,7 @@
 //! and Aggregate functions in IOx, designed to be compatible with
 //! InfluxDB classic
 
-use arrow_deps::datafusion::logical_plan::Expr;
+use arrow_deps::datafusion::logical_plan::{lit, Expr};
 use snafu::Snafu;
 
 use crate::func::window;
@@ -129,6 +129,20 @@ impl Aggregate {
             Self::None => AggregateNotSupported { agg: "None" }.fail(),
         }
     }
+
+    /// Returns true if the aggregate can be calculated for windows of time
+    /// (`GroupByAndAggregate::Window`), which is only done with the
+    /// DataFusion expressions of the aggregates
+    pub fn supports_window(&self) -> bool {
+        self.to_datafusion_expr(lit(0_i64)).is_ok()
+    }
+
+    /// Returns true if the aggregate can be calculated for groups of series
+    /// (`GroupByAndAggregate::Columns`), which is done with the DataFusion
+    /// expressions of the aggregates or with the selector functions
+    pub fn supports_group(&self) -> bool {
+        !matches!(self, Self::None)
+    }
 }
 
 impl WindowDuration {
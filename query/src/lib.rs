@@ -5,18 +5,36 @@
     clippy::use_self
 )]
 
-use arrow_deps::{arrow::record_batch::RecordBatch, datafusion::logical_plan::LogicalPlan};
+use arrow_deps::{
+    arrow::record_batch::RecordBatch,
+    datafusion::logical_plan::{Expr, LogicalPlan},
+};
 use async_trait::async_trait;
-use data_types::{data::ReplicatedWrite, partition_metadata::Table as TableStats};
+use concurrency::QueryGuard;
+use data_types::{
+    data::ReplicatedWrite,
+    names::{org_and_bucket_to_database, OrgBucketMappingError},
+    partition_metadata::Table as TableStats,
+    DatabaseName,
+};
 use exec::{Executor, FieldListPlan, SeriesSetPlans, StringSetPlan};
+use snafu::Snafu;
 
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    sync::Arc,
+};
 
+pub mod concurrency;
 pub mod exec;
 pub mod frontend;
 pub mod func;
 pub mod group_by;
+pub mod lifecycle;
+pub mod output;
 pub mod predicate;
+pub mod system_tables;
 pub mod util;
 
 use self::group_by::GroupByAndAggregate;
@@ -32,7 +50,7 @@ use self::predicate::Predicate;
 /// trait and into the various query planners.
 #[async_trait]
 pub trait Database: Debug + Send + Sync {
-    type Error: std::error::Error + Send + Sync + 'static;
+    type Error: std::error::Error + Into<DatabaseError> + Send + Sync + 'static;
     type Chunk: PartitionChunk;
 
     /// Stores the replicated write into the database.
@@ -46,6 +64,125 @@ pub trait Database: Debug + Send + Sync {
     /// complete copy of the data being queried.
     async fn chunks(&self, partition_key: &str) -> Vec<Arc<Self::Chunk>>;
 
+    /// Returns the name of the database, which keeps what is cached for
+    /// queries of one database (such as their plans) apart from the
+    /// others
+    fn name(&self) -> &str;
+
+    /// Registers the start of a query against this database, returning
+    /// a guard to hold until the query has finished. Fails if the
+    /// database is already running as many queries as it allows.
+    fn start_query(&self) -> Result<QueryGuard, Self::Error> {
+        Ok(QueryGuard::default())
+    }
+
+    /// Returns true if the rows a query can see depend on who sent it.
+    /// Such databases can only be queried through planners that apply the
+    /// row filters of the principal, such as `SQLQueryPlanner`.
+    fn restricts_rows(&self) -> bool {
+        false
+    }
+
+    /// Returns the smallest and largest timestamps of the rows of `table`
+    /// in the partition `partition_key`, from the row counts its chunks
+    /// keep, so no rows are read. Returns `None` if the partition holds
+    /// no rows of the table, or if one of its chunks can't tell its time
+    /// range without reading it.
+    async fn partition_time_range(
+        &self,
+        partition_key: &str,
+        table: &str,
+    ) -> Result<Option<(i64, i64)>, DatabaseError> {
+        let mut time_range = None;
+        for chunk in self.chunks(partition_key).await {
+            let row_count = chunk
+                .table_row_count(table)
+                .map_err(|e| DatabaseError::new(DatabaseErrorKind::Internal, e))?;
+            match row_count {
+                Some(row_count) => {
+                    time_range = util::union_time_ranges(time_range, row_count.time_range)
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(time_range)
+    }
+
+    /// Returns the smallest and largest timestamps of the retained rows
+    /// of `table` in the database, without reading rows, as
+    /// `partition_time_range` does for each partition. Rows older than the
+    /// retention boundary of the table are left out, as planners leave
+    /// them out, by starting the range at the boundary.
+    async fn time_range(&self, table: &str) -> Result<Option<(i64, i64)>, DatabaseError> {
+        let mut time_range = None;
+        for partition_key in self.partition_keys().await.map_err(Into::into)? {
+            let partition_time_range = self.partition_time_range(&partition_key, table).await?;
+            time_range = util::union_time_ranges(time_range, partition_time_range);
+        }
+
+        Ok(match (time_range, self.retention_boundary(Some(table))) {
+            (Some((_, max)), Some(boundary)) if max < boundary => None,
+            (Some((min, max)), Some(boundary)) => Some((min.max(boundary), max)),
+            (time_range, _) => time_range,
+        })
+    }
+
+    /// Returns the objects the database keeps in object storage, for the
+    /// `system.object_store` table. Databases that don't keep objects in
+    /// object storage have none.
+    async fn stored_objects(&self) -> Result<Vec<StoredObject>, Self::Error> {
+        Ok(vec![])
+    }
+
+    /// Returns the retention boundary of `table`, or that of the tables
+    /// without their own retention period if not set: the timestamp rows
+    /// must have at least to be retained. Rows older than it may not have
+    /// been dropped yet, so planners leave them out. Databases that keep
+    /// their rows forever have none.
+    fn retention_boundary(&self, _table: Option<&str>) -> Option<i64> {
+        None
+    }
+
+    /// Returns the names of the tables with their own retention period,
+    /// whose boundary may differ from `retention_boundary(None)`
+    fn tables_with_own_retention(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Returns true if the data of `chunk` of the partition `partition_key`
+    /// has been persisted, such as by snapshotting it to object storage,
+    /// even if it is also still held in memory
+    fn chunk_persisted(&self, _partition_key: &str, chunk: &Self::Chunk) -> bool {
+        chunk.is_persisted()
+    }
+
+    /// Returns `predicate` restricted to the retained rows of each table
+    /// it selects, by the retention boundary of that table. Tables without
+    /// any retained rows in its range are given an empty range.
+    fn retained(&self, mut predicate: Predicate) -> Predicate {
+        let tables = match &predicate.table_names {
+            Some(tables) => tables.iter().cloned().collect(),
+            None => self.tables_with_own_retention(),
+        };
+        for table in tables {
+            if let Some(boundary) = self.retention_boundary(Some(table.as_str())) {
+                predicate = predicate.table_starting_at(&table, boundary);
+            }
+        }
+
+        match self.retention_boundary(None) {
+            Some(boundary) => predicate.starting_at(boundary),
+            None => predicate,
+        }
+    }
+
+    /// Returns the transitions the partitions of the database went
+    /// through, oldest first, for the `system.lifecycle_events` table.
+    /// Databases that don't record them have none.
+    fn lifecycle_events(&self) -> Vec<LifecycleEvent> {
+        vec![]
+    }
+
     // ----------
     // The functions below are slated for removal (migration into a gRPC query
     // frontend) ---------
@@ -95,6 +232,87 @@ pub trait Database: Debug + Send + Sync {
     ) -> Result<SeriesSetPlans, Self::Error>;
 }
 
+/// The errors returned by a `Database`, classified by their cause so
+/// that callers (e.g. the HTTP and gRPC services) can react to them
+/// without inspecting error messages. Every `Database::Error` converts
+/// into a `DatabaseError`.
+#[derive(Debug, Snafu)]
+pub enum DatabaseError {
+    /// Something referenced by the request (e.g. a table or column)
+    /// does not exist
+    #[snafu(display("{}", source))]
+    NotFound {
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    /// The request conflicts with the existing schema (e.g. writing a
+    /// value of the wrong type to a column)
+    #[snafu(display("{}", source))]
+    SchemaConflict {
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    /// The database ran out of some resource (e.g. dictionary space)
+    /// while handling the request
+    #[snafu(display("{}", source))]
+    ResourceExhausted {
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    /// Any other error
+    #[snafu(display("{}", source))]
+    Internal {
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+}
+
+/// The kind of a [`DatabaseError`](enum.DatabaseError.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseErrorKind {
+    NotFound,
+    SchemaConflict,
+    ResourceExhausted,
+    Internal,
+}
+
+impl DatabaseError {
+    /// Wraps `source` into a `DatabaseError` of kind `kind`
+    pub fn new(
+        kind: DatabaseErrorKind,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        let source = Box::new(source);
+        match kind {
+            DatabaseErrorKind::NotFound => Self::NotFound { source },
+            DatabaseErrorKind::SchemaConflict => Self::SchemaConflict { source },
+            DatabaseErrorKind::ResourceExhausted => Self::ResourceExhausted { source },
+            DatabaseErrorKind::Internal => Self::Internal { source },
+        }
+    }
+
+    pub fn kind(&self) -> DatabaseErrorKind {
+        match self {
+            Self::NotFound { .. } => DatabaseErrorKind::NotFound,
+            Self::SchemaConflict { .. } => DatabaseErrorKind::SchemaConflict,
+            Self::ResourceExhausted { .. } => DatabaseErrorKind::ResourceExhausted,
+            Self::Internal { .. } => DatabaseErrorKind::Internal,
+        }
+    }
+
+    /// Returns the first `DatabaseError` in the chain of sources
+    /// starting at (and including) `err`, if any
+    pub fn find_in<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a Self> {
+        let mut err = Some(err);
+        while let Some(e) = err {
+            if let Some(database_error) = e.downcast_ref::<Self>() {
+                return Some(database_error);
+            }
+            err = e.source();
+        }
+        None
+    }
+}
+
 /// Collection of data that shares the same partition key
 #[async_trait]
 pub trait PartitionChunk: Debug + Send + Sync {
@@ -117,6 +335,16 @@ pub trait PartitionChunk: Debug + Send + Sync {
         true
     }
 
+    /// Returns true if chunks of this type can be queried with predicates
+    /// having the expression `expr`. The storage RPC reports the
+    /// comparisons it can push down to the chunks from this.
+    fn supports_expr(_expr: &Expr) -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+
     /// Returns a datafusion plan that produces
     /// a single string column representing the names
     /// of the tables that have at least one row that matches the
@@ -136,6 +364,161 @@ pub trait PartitionChunk: Debug + Send + Sync {
         table_name: &str,
         columns: &[&str],
     ) -> Result<(), Self::Error>;
+
+    /// Returns the number of rows the chunk holds for the table and
+    /// the range of their timestamps, if the chunk keeps track of them
+    /// and can tell without reading the table's data. If `Ok(None)` is
+    /// returned, the rows have to be read with `table_to_arrow` to be
+    /// counted.
+    fn table_row_count(&self, _table_name: &str) -> Result<Option<TableRowCount>, Self::Error> {
+        Ok(None)
+    }
+
+    /// Returns true if the data of the chunk was loaded from persisted
+    /// storage, such as a snapshot in object storage, rather than written
+    /// to this server's memory
+    fn is_persisted(&self) -> bool {
+        false
+    }
+
+    /// Returns the distinct values of each tag column of each table in
+    /// the chunk, as recorded in the chunk's dictionaries
+    fn all_tag_values(&self) -> Result<TableTagValues, Self::Error>;
+
+    /// Returns the distinct values of the tag column `column` of each table
+    /// in the chunk that start with `prefix`, by table name. Tables without
+    /// the tag column are left out.
+    ///
+    /// By default, the values are filtered from `all_tag_values`; chunks
+    /// whose dictionaries can be searched by prefix should override this.
+    fn tag_values_with_prefix(
+        &self,
+        column: &str,
+        prefix: &str,
+    ) -> Result<TableValues, Self::Error> {
+        Ok(self
+            .all_tag_values()?
+            .into_iter()
+            .filter_map(|(table_name, mut tags)| {
+                let values = tags
+                    .remove(column)?
+                    .into_iter()
+                    .filter(|value| value.starts_with(prefix))
+                    .collect();
+                Some((table_name, values))
+            })
+            .collect())
+    }
+
+    /// Returns the encoding each column of each table is stored with, for
+    /// chunks that choose encodings per column. Other chunks return no
+    /// encodings.
+    fn column_encodings(&self) -> Result<Vec<ColumnEncoding>, Self::Error> {
+        Ok(vec![])
+    }
+
+    /// Returns the series of each table in the chunk: the distinct
+    /// combinations of tag values of its rows, as series keys such as
+    /// `host=a,region=west`. A table without tags has a single series,
+    /// whose key is empty.
+    ///
+    /// By default, the tag columns of each table are read as record
+    /// batches to find their distinct combinations.
+    fn series_keys(&self) -> Result<TableSeriesKeys, Self::Error> {
+        exec::series_key::chunk_series_keys(self)
+    }
+}
+
+/// The distinct values of each tag column of each table, by table name
+/// and then tag column name
+pub type TableTagValues = BTreeMap<String, BTreeMap<String, BTreeSet<String>>>;
+
+/// The series keys of each table, by table name
+pub type TableSeriesKeys = BTreeMap<String, BTreeSet<String>>;
+
+/// The distinct values of a column of each table, by table name
+pub type TableValues = BTreeMap<String, BTreeSet<String>>;
+
+/// The number of rows a chunk holds for a table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableRowCount {
+    pub rows: u64,
+    /// The smallest and largest timestamps of the rows. None if there
+    /// are no rows, or if any of them has no timestamp
+    pub time_range: Option<(i64, i64)>,
+}
+
+/// The encoding a chunk stores a column of a table with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnEncoding {
+    pub table_name: String,
+    /// The part of the table the encoding was chosen for, if the chunk
+    /// splits tables into row groups
+    pub row_group: u64,
+    pub column_name: String,
+    pub encoding: String,
+    pub rows: u64,
+    /// The size in bytes of the encoded column data
+    pub size: u64,
+}
+
+/// An object a database keeps in object storage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredObject {
+    pub path: String,
+    /// The size of the object in bytes
+    pub size: u64,
+    /// When the object was last modified, in nanoseconds since the epoch
+    pub last_modified: i64,
+    /// Whether the catalog of the database refers to the object. Objects
+    /// it doesn't refer to are left over, for example from a snapshot
+    /// that didn't finish, and can be deleted.
+    pub referenced: bool,
+}
+
+/// What happened to a partition or one of its chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEventKind {
+    /// The partition was first written to
+    Created,
+    /// The chunk was closed to writes
+    Closed,
+    /// The chunk was written to object storage
+    Persisted,
+    /// The chunk was loaded into the read buffer, or rewritten there
+    Compacted,
+    /// The chunk was removed from the mutable buffer, its data being
+    /// kept in the read buffer
+    Evicted,
+    /// The chunk was removed from memory along with its data
+    Dropped,
+}
+
+impl LifecycleEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Closed => "closed",
+            Self::Persisted => "persisted",
+            Self::Compacted => "compacted",
+            Self::Evicted => "evicted",
+            Self::Dropped => "dropped",
+        }
+    }
+}
+
+/// A transition in the lifecycle of a partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifecycleEvent {
+    pub partition_key: String,
+    /// The chunk the event is about, if it isn't about the whole partition
+    pub chunk_id: Option<u32>,
+    pub kind: LifecycleEventKind,
+    /// When the event happened, in nanoseconds since the epoch
+    pub time: i64,
+    /// The size in bytes of the chunk after the event, or before it if
+    /// the chunk was removed
+    pub size: u64,
 }
 
 #[async_trait]
@@ -155,6 +538,24 @@ pub trait DatabaseStore: Debug + Send + Sync {
     /// doesn't exist.
     async fn db_or_create(&self, name: &str) -> Result<Arc<Self::Database>, Self::Error>;
 
+    /// Returns why the database `name` can't be served yet, if it exists
+    /// but is still being loaded. By default, databases are served as
+    /// soon as they exist.
+    fn db_not_ready(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    /// Returns the name of the database storing the data of the InfluxDB
+    /// 2.x `org` and `bucket`. By default, this is the name
+    /// `org_and_bucket_to_database` derives from them.
+    async fn namespace_db_name(
+        &self,
+        org: &str,
+        bucket: &str,
+    ) -> Result<DatabaseName<'static>, OrgBucketMappingError> {
+        org_and_bucket_to_database(org, bucket)
+    }
+
     /// Provide a query executor to use for running queries on
     /// databases in this `DatabaseStore`
     fn executor(&self) -> Arc<Executor>;
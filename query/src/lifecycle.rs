@@ -0,0 +1,80 @@
+//! This module contains the log of the lifecycle events of the partitions
+//! of a database, which is shared by the buffers holding the partitions
+//! so each transition is recorded where it happens.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::Utc;
+
+use crate::{LifecycleEvent, LifecycleEventKind};
+
+/// The most events kept per database. The oldest events are forgotten
+/// first.
+pub const MAX_LIFECYCLE_EVENTS: usize = 10_000;
+
+/// The most recent lifecycle events of a database, oldest first
+#[derive(Debug, Default)]
+pub struct LifecycleLog {
+    events: Mutex<VecDeque<LifecycleEvent>>,
+}
+
+impl LifecycleLog {
+    pub fn record(&self, event: LifecycleEvent) {
+        let mut events = self.events.lock().expect("mutex poisoned");
+        if events.len() == MAX_LIFECYCLE_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Records that `kind` happens now to chunk `chunk_id` of the
+    /// partition `partition_key` (or to the whole partition if `chunk_id`
+    /// is not set), whose size is then `size` bytes
+    pub fn record_now(
+        &self,
+        partition_key: &str,
+        chunk_id: Option<u32>,
+        kind: LifecycleEventKind,
+        size: u64,
+    ) {
+        self.record(LifecycleEvent {
+            partition_key: partition_key.to_string(),
+            chunk_id,
+            kind,
+            time: Utc::now().timestamp_nanos(),
+            size,
+        })
+    }
+
+    pub fn events(&self) -> Vec<LifecycleEvent> {
+        let events = self.events.lock().expect("mutex poisoned");
+        events.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(chunk_id: u32) -> LifecycleEvent {
+        LifecycleEvent {
+            partition_key: "region_west".to_string(),
+            chunk_id: Some(chunk_id),
+            kind: LifecycleEventKind::Closed,
+            time: 0,
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn forgets_the_oldest_events() {
+        let log = LifecycleLog::default();
+        for chunk_id in 0..=MAX_LIFECYCLE_EVENTS as u32 {
+            log.record(event(chunk_id));
+        }
+
+        let events = log.events();
+        assert_eq!(events.len(), MAX_LIFECYCLE_EVENTS);
+        assert_eq!(events[0], event(1));
+    }
+}
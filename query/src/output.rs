@@ -0,0 +1,470 @@
+//! This module contains code to serialize query results, such as those
+//! returned by the HTTP query endpoint, as JSON lines or as CSV
+//! (RFC 4180).
+//!
+//! Both formats render timestamp columns as RFC 3339 strings with
+//! nanosecond precision, in UTC unless another time zone is asked for.
+//! NULLs are `null` in JSON and an empty, unquoted field in CSV, where
+//! string values are always quoted so that an empty string can be told
+//! apart from a NULL, and a numeric looking string from a number.
+use std::{fmt::Write, str::FromStr};
+
+use arrow_deps::arrow::{
+    array::{
+        Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray,
+        TimestampMicrosecondArray, TimestampMillisecondArray, TimestampNanosecondArray,
+        TimestampSecondArray, UInt64Array,
+    },
+    datatypes::{DataType, TimeUnit},
+    error::ArrowError,
+    record_batch::RecordBatch,
+    util::pretty::pretty_format_batches,
+};
+use chrono::{SecondsFormat, TimeZone as _};
+use data_types::time_zone::TimeZone;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Unknown query output format '{}', expected one of 'pretty', 'csv' or 'json'",
+        name
+    ))]
+    UnknownFormat { name: String },
+
+    #[snafu(display(
+        "Can't serialize column '{}' of type {:?} as {:?}",
+        column,
+        data_type,
+        format
+    ))]
+    UnsupportedDataType {
+        column: String,
+        data_type: DataType,
+        format: QueryOutputFormat,
+    },
+
+    #[snafu(display("Error formatting results as a table: {}", source))]
+    PrettyFormatting { source: ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The formats query results can be serialized in
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum QueryOutputFormat {
+    /// An ASCII table, for humans
+    Pretty,
+    /// RFC 4180 CSV with a header row
+    Csv,
+    /// One JSON object per row, separated by newlines
+    JsonLines,
+}
+
+impl Default for QueryOutputFormat {
+    fn default() -> Self {
+        Self::Pretty
+    }
+}
+
+impl FromStr for QueryOutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::JsonLines),
+            _ => UnknownFormat { name: s }.fail(),
+        }
+    }
+}
+
+impl QueryOutputFormat {
+    /// The value of the HTTP `Content-Type` header for this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Pretty => "text/plain",
+            Self::Csv => "text/csv",
+            Self::JsonLines => "application/x-ndjson",
+        }
+    }
+
+    /// Serializes `batches` in this format, with times in UTC
+    pub fn format(&self, batches: &[RecordBatch]) -> Result<String> {
+        self.format_in(batches, &TimeZone::UTC)
+    }
+
+    /// Serializes `batches` in this format, with times in `time_zone`.
+    /// The pretty format always shows times as stored.
+    pub fn format_in(&self, batches: &[RecordBatch], time_zone: &TimeZone) -> Result<String> {
+        match self {
+            Self::Pretty => pretty_format_batches(batches).context(PrettyFormatting),
+            Self::Csv => batches_to_csv(batches, time_zone),
+            Self::JsonLines => batches_to_json_lines(batches, time_zone),
+        }
+    }
+}
+
+/// Serializes `batches` as CSV, with a header row naming the columns of
+/// the first batch and times in `time_zone`. Rows end with CRLF, as
+/// RFC 4180 asks.
+pub fn batches_to_csv(batches: &[RecordBatch], time_zone: &TimeZone) -> Result<String> {
+    let mut csv = String::new();
+
+    if let Some(first) = batches.first() {
+        let schema = first.schema();
+        let header: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| csv_header(field.name()))
+            .collect();
+        csv.push_str(&header.join(","));
+        csv.push_str("\r\n");
+    }
+
+    for batch in batches {
+        let columns = columns(batch, QueryOutputFormat::Csv)?;
+        for row in 0..batch.num_rows() {
+            for (i, column) in columns.iter().enumerate() {
+                if i > 0 {
+                    csv.push(',');
+                }
+                match column.value(row) {
+                    Value::Null => {}
+                    Value::Bool(v) => write!(csv, "{}", v).unwrap(),
+                    Value::I64(v) => write!(csv, "{}", v).unwrap(),
+                    Value::U64(v) => write!(csv, "{}", v).unwrap(),
+                    Value::F64(v) => write!(csv, "{}", v).unwrap(),
+                    Value::Str(v) => csv.push_str(&csv_quote(v)),
+                    Value::Time(nanos) => csv.push_str(&rfc3339(nanos, time_zone)),
+                }
+            }
+            csv.push_str("\r\n");
+        }
+    }
+
+    Ok(csv)
+}
+
+/// Serializes `batches` as JSON lines, one object per row with a member
+/// per column, in column order and times in `time_zone`. Floats that JSON
+/// can't represent (NaN and the infinities) are written as `null`.
+pub fn batches_to_json_lines(batches: &[RecordBatch], time_zone: &TimeZone) -> Result<String> {
+    let mut json = String::new();
+
+    for batch in batches {
+        let schema = batch.schema();
+        let names: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| json_string(field.name()))
+            .collect();
+        let columns = columns(batch, QueryOutputFormat::JsonLines)?;
+
+        for row in 0..batch.num_rows() {
+            json.push('{');
+            for (i, (name, column)) in names.iter().zip(&columns).enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                json.push_str(name);
+                json.push(':');
+                match column.value(row) {
+                    Value::Null => json.push_str("null"),
+                    Value::Bool(v) => write!(json, "{}", v).unwrap(),
+                    Value::I64(v) => write!(json, "{}", v).unwrap(),
+                    Value::U64(v) => write!(json, "{}", v).unwrap(),
+                    Value::F64(v) if v.is_finite() => write!(json, "{}", v).unwrap(),
+                    Value::F64(_) => json.push_str("null"),
+                    Value::Str(v) => json.push_str(&json_string(v)),
+                    Value::Time(nanos) => json.push_str(&json_string(&rfc3339(nanos, time_zone))),
+                }
+            }
+            json.push_str("}\n");
+        }
+    }
+
+    Ok(json)
+}
+
+/// A typed view of a column of a record batch
+#[derive(Debug)]
+enum Column<'a> {
+    Bool(&'a BooleanArray),
+    I64(&'a Int64Array),
+    U64(&'a UInt64Array),
+    F64(&'a Float64Array),
+    Str(&'a StringArray),
+    TimeSecond(&'a TimestampSecondArray),
+    TimeMillisecond(&'a TimestampMillisecondArray),
+    TimeMicrosecond(&'a TimestampMicrosecondArray),
+    TimeNanosecond(&'a TimestampNanosecondArray),
+}
+
+/// A single value of a column
+#[derive(Debug)]
+enum Value<'a> {
+    Null,
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(&'a str),
+    /// Nanoseconds since the epoch
+    Time(i64),
+}
+
+impl<'a> Column<'a> {
+    fn try_new(array: &'a ArrayRef) -> Option<Self> {
+        let any = array.as_any();
+        Some(match array.data_type() {
+            DataType::Boolean => Self::Bool(any.downcast_ref()?),
+            DataType::Int64 => Self::I64(any.downcast_ref()?),
+            DataType::UInt64 => Self::U64(any.downcast_ref()?),
+            DataType::Float64 => Self::F64(any.downcast_ref()?),
+            DataType::Utf8 => Self::Str(any.downcast_ref()?),
+            DataType::Timestamp(TimeUnit::Second, _) => Self::TimeSecond(any.downcast_ref()?),
+            DataType::Timestamp(TimeUnit::Millisecond, _) => {
+                Self::TimeMillisecond(any.downcast_ref()?)
+            }
+            DataType::Timestamp(TimeUnit::Microsecond, _) => {
+                Self::TimeMicrosecond(any.downcast_ref()?)
+            }
+            DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+                Self::TimeNanosecond(any.downcast_ref()?)
+            }
+            _ => return None,
+        })
+    }
+
+    fn value(&self, row: usize) -> Value<'a> {
+        let array: &dyn Array = match self {
+            Self::Bool(a) => *a,
+            Self::I64(a) => *a,
+            Self::U64(a) => *a,
+            Self::F64(a) => *a,
+            Self::Str(a) => *a,
+            Self::TimeSecond(a) => *a,
+            Self::TimeMillisecond(a) => *a,
+            Self::TimeMicrosecond(a) => *a,
+            Self::TimeNanosecond(a) => *a,
+        };
+        if array.is_null(row) {
+            return Value::Null;
+        }
+
+        match self {
+            Self::Bool(a) => Value::Bool(a.value(row)),
+            Self::I64(a) => Value::I64(a.value(row)),
+            Self::U64(a) => Value::U64(a.value(row)),
+            Self::F64(a) => Value::F64(a.value(row)),
+            Self::Str(a) => Value::Str(a.value(row)),
+            Self::TimeSecond(a) => Value::Time(a.value(row).saturating_mul(1_000_000_000)),
+            Self::TimeMillisecond(a) => Value::Time(a.value(row).saturating_mul(1_000_000)),
+            Self::TimeMicrosecond(a) => Value::Time(a.value(row).saturating_mul(1_000)),
+            Self::TimeNanosecond(a) => Value::Time(a.value(row)),
+        }
+    }
+}
+
+fn columns(batch: &RecordBatch, format: QueryOutputFormat) -> Result<Vec<Column<'_>>> {
+    let schema = batch.schema();
+    batch
+        .columns()
+        .iter()
+        .zip(schema.fields())
+        .map(|(array, field)| {
+            Column::try_new(array).ok_or_else(|| Error::UnsupportedDataType {
+                column: field.name().clone(),
+                data_type: field.data_type().clone(),
+                format,
+            })
+        })
+        .collect()
+}
+
+fn rfc3339(nanos: i64, time_zone: &TimeZone) -> String {
+    time_zone
+        .offset_at(nanos)
+        .timestamp_nanos(nanos)
+        .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Quotes `s` as a CSV field, doubling any quotes in it
+fn csv_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Column names are only quoted if they have to be
+fn csv_header(name: &str) -> String {
+    if name.contains(|c: char| matches!(c, ',' | '"' | '\r' | '\n')) {
+        csv_quote(name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Quotes and escapes `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::datatypes::{Field, Schema};
+    use std::sync::Arc;
+
+    fn batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("usage", DataType::Float64, true),
+            Field::new("count", DataType::Int64, true),
+            Field::new("ok", DataType::Boolean, true),
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Nanosecond, None),
+                false,
+            ),
+        ]));
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("a,\"b\""),
+                    Some(""),
+                    None,
+                    Some("42"),
+                ])),
+                Arc::new(Float64Array::from(vec![
+                    Some(0.5),
+                    None,
+                    Some(f64::NAN),
+                    Some(2.0),
+                ])),
+                Arc::new(Int64Array::from(vec![Some(1), Some(-2), None, Some(3)])),
+                Arc::new(BooleanArray::from(vec![
+                    Some(true),
+                    Some(false),
+                    None,
+                    Some(true),
+                ])),
+                Arc::new(TimestampNanosecondArray::from_vec(
+                    vec![
+                        0,
+                        1_500_000_000,
+                        1_000_000_000_123,
+                        1_600_000_000_000_000_000,
+                    ],
+                    None,
+                )),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        let csv = QueryOutputFormat::Csv.format(&[batch()]).unwrap();
+        let expected = "host,usage,count,ok,time\r\n\
+                        \"a,\"\"b\"\"\",0.5,1,true,1970-01-01T00:00:00Z\r\n\
+                        \"\",,-2,false,1970-01-01T00:00:01.500Z\r\n\
+                        ,NaN,,,1970-01-01T00:16:40.000000123Z\r\n\
+                        \"42\",2,3,true,2020-09-13T12:26:40Z\r\n";
+        assert_eq!(csv, expected);
+    }
+
+    #[test]
+    fn json_lines() {
+        let json = QueryOutputFormat::JsonLines.format(&[batch()]).unwrap();
+        let expected = r#"{"host":"a,\"b\"","usage":0.5,"count":1,"ok":true,"time":"1970-01-01T00:00:00Z"}
+{"host":"","usage":null,"count":-2,"ok":false,"time":"1970-01-01T00:00:01.500Z"}
+{"host":null,"usage":null,"count":null,"ok":null,"time":"1970-01-01T00:16:40.000000123Z"}
+{"host":"42","usage":2,"count":3,"ok":true,"time":"2020-09-13T12:26:40Z"}
+"#;
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn times_in_time_zone() {
+        let time_zone = "-08:00".parse().unwrap();
+        let csv = QueryOutputFormat::Csv
+            .format_in(&[batch()], &time_zone)
+            .unwrap();
+        let times: Vec<_> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.rsplit(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                "1969-12-31T16:00:00-08:00",
+                "1969-12-31T16:00:01.500-08:00",
+                "1969-12-31T16:16:40.000000123-08:00",
+                "2020-09-13T04:26:40-08:00",
+            ]
+        );
+
+        let json = QueryOutputFormat::JsonLines
+            .format_in(&[batch()], &time_zone)
+            .unwrap();
+        assert!(json.starts_with(
+            r#"{"host":"a,\"b\"","usage":0.5,"count":1,"ok":true,"time":"1969-12-31T16:00:00-08:00"}"#
+        ));
+    }
+
+    #[test]
+    fn multiple_batches_have_one_header() {
+        let csv = QueryOutputFormat::Csv.format(&[batch(), batch()]).unwrap();
+        assert_eq!(csv.matches("host,usage").count(), 1);
+        assert_eq!(csv.lines().count(), 9);
+    }
+
+    #[test]
+    fn unsupported_types_are_errors() {
+        let schema = Arc::new(Schema::new(vec![Field::new("v", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(arrow_deps::arrow::array::Int32Array::from(vec![
+                1,
+            ]))],
+        )
+        .unwrap();
+
+        let err = QueryOutputFormat::Csv.format(&[batch]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Can't serialize column 'v' of type Int32 as Csv"
+        );
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!(
+            "json".parse::<QueryOutputFormat>().unwrap(),
+            QueryOutputFormat::JsonLines
+        );
+        assert_eq!(
+            "csv".parse::<QueryOutputFormat>().unwrap(),
+            QueryOutputFormat::Csv
+        );
+        assert!("xml".parse::<QueryOutputFormat>().is_err());
+    }
+}
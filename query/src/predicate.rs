@@ -3,7 +3,7 @@
 //! mode as well as for arbitrary other predicates that are expressed
 //! by DataFusion's `Expr` type.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use arrow_deps::datafusion::logical_plan::Expr;
 
@@ -67,6 +67,10 @@ pub struct Predicate {
 
     /// Optional partition key filter
     pub partition_key: Option<String>,
+
+    /// Timestamp ranges overriding `range` for the rows of some tables,
+    /// by table name
+    pub table_ranges: BTreeMap<String, TimestampRange>,
 }
 
 impl Predicate {
@@ -74,6 +78,37 @@ impl Predicate {
     pub fn has_exprs(&self) -> bool {
         !self.exprs.is_empty()
     }
+
+    /// Returns the timestamp range of the rows of the table `table`
+    pub fn table_range(&self, table: &str) -> Option<TimestampRange> {
+        self.table_ranges.get(table).copied().or(self.range)
+    }
+
+    /// Restricts the predicate to rows with a timestamp of at least
+    /// `start`, such as the retention boundary of a database. A range
+    /// ending before `start` becomes empty.
+    pub fn starting_at(mut self, start: i64) -> Self {
+        self.range = Some(range_starting_at(self.range, start));
+        self
+    }
+
+    /// Restricts the predicate to rows of the table `table` with a
+    /// timestamp of at least `start`, such as the retention boundary of
+    /// that table, leaving the range of the other tables as it is
+    pub fn table_starting_at(mut self, table: &str, start: i64) -> Self {
+        let range = range_starting_at(self.table_range(table), start);
+        self.table_ranges.insert(table.to_string(), range);
+        self
+    }
+}
+
+/// Returns `range` starting at `start` at the earliest, and no later than
+/// where it ends
+fn range_starting_at(range: Option<TimestampRange>, start: i64) -> TimestampRange {
+    match range {
+        Some(range) => TimestampRange::new(range.start.max(start).min(range.end), range.end),
+        None => TimestampRange::new(start, i64::MAX),
+    }
 }
 
 #[derive(Debug, Default)]
@@ -205,4 +240,33 @@ mod tests {
 
         assert!(!range.contains_opt(None));
     }
+
+    #[test]
+    fn test_starting_at() {
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(100, 200)
+            .build()
+            .table_starting_at("cpu", 150)
+            .starting_at(120);
+        assert_eq!(predicate.range, Some(TimestampRange::new(120, 200)));
+        assert_eq!(
+            predicate.table_range("cpu"),
+            Some(TimestampRange::new(150, 200))
+        );
+        assert_eq!(
+            predicate.table_range("mem"),
+            Some(TimestampRange::new(120, 200))
+        );
+
+        // a range ending before the start becomes empty
+        let predicate = predicate.starting_at(300).table_starting_at("cpu", 300);
+        assert_eq!(predicate.range, Some(TimestampRange::new(200, 200)));
+        assert_eq!(
+            predicate.table_range("cpu"),
+            Some(TimestampRange::new(200, 200))
+        );
+
+        let predicate = Predicate::default().starting_at(100);
+        assert_eq!(predicate.range, Some(TimestampRange::new(100, i64::MAX)));
+    }
 }
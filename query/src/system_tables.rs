@@ -0,0 +1,434 @@
+//! This module contains the `system.column_encodings` table, which reports
+//! the encoding chosen for each column of each chunk and the size of the
+//! encoded data, so the encoding choices can be tuned against real data.
+//!
+//! It also contains the `system.series_overlap` table, which reports how
+//! many series of each table are written to more than one chunk of a
+//! partition. Queries have to deduplicate the rows of those series across
+//! chunks, so the partitions with the most overlap are the ones that
+//! benefit most from compaction.
+//!
+//! The `system.object_store` table lists the objects a database keeps in
+//! object storage, and whether its catalog refers to them, so storage use
+//! can be audited and left over objects found.
+//!
+//! The `system.lifecycle_events` table is a timeline of the transitions
+//! the partitions of a database went through, such as chunks being
+//! closed, persisted or dropped, with the size of the chunk at the time,
+//! so changes in memory use or query speed can be traced back to them.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use arrow_deps::arrow::{
+    array::{BooleanBuilder, Int64Builder, StringBuilder, UInt32Builder, UInt64Builder},
+    datatypes::{DataType, Field, Schema},
+    error::Result as ArrowResult,
+    record_batch::RecordBatch,
+};
+
+use crate::{ColumnEncoding, LifecycleEvent, StoredObject, TableSeriesKeys};
+
+/// The name of the table listing the encoding of every column of every
+/// chunk
+pub const COLUMN_ENCODINGS_TABLE_NAME: &str = "system.column_encodings";
+
+/// The column encodings of any number of chunks
+#[derive(Debug, Default)]
+pub struct ColumnEncodings {
+    encodings: Vec<(String, u32, ColumnEncoding)>,
+}
+
+impl ColumnEncodings {
+    /// Adds the column encodings of the chunk `chunk_id` of the partition
+    /// `partition_key`
+    pub fn add(&mut self, partition_key: &str, chunk_id: u32, encodings: Vec<ColumnEncoding>) {
+        self.encodings.extend(
+            encodings
+                .into_iter()
+                .map(|encoding| (partition_key.to_string(), chunk_id, encoding)),
+        );
+    }
+
+    /// Returns the contents of the `system.column_encodings` table, in the
+    /// order the chunks were added
+    pub fn to_batch(&self) -> ArrowResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("partition_key", DataType::Utf8, false),
+            Field::new("chunk_id", DataType::UInt64, false),
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("row_group", DataType::UInt64, false),
+            Field::new("column_name", DataType::Utf8, false),
+            Field::new("encoding", DataType::Utf8, false),
+            Field::new("rows", DataType::UInt64, false),
+            Field::new("size", DataType::UInt64, false),
+        ]));
+
+        let len = self.encodings.len();
+        let mut partition_keys = StringBuilder::new(len);
+        let mut chunk_ids = UInt64Builder::new(len);
+        let mut table_names = StringBuilder::new(len);
+        let mut row_groups = UInt64Builder::new(len);
+        let mut column_names = StringBuilder::new(len);
+        let mut encodings = StringBuilder::new(len);
+        let mut rows = UInt64Builder::new(len);
+        let mut sizes = UInt64Builder::new(len);
+
+        for (partition_key, chunk_id, encoding) in &self.encodings {
+            partition_keys.append_value(partition_key)?;
+            chunk_ids.append_value(*chunk_id as u64)?;
+            table_names.append_value(&encoding.table_name)?;
+            row_groups.append_value(encoding.row_group)?;
+            column_names.append_value(&encoding.column_name)?;
+            encodings.append_value(&encoding.encoding)?;
+            rows.append_value(encoding.rows)?;
+            sizes.append_value(encoding.size)?;
+        }
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(partition_keys.finish()),
+                Arc::new(chunk_ids.finish()),
+                Arc::new(table_names.finish()),
+                Arc::new(row_groups.finish()),
+                Arc::new(column_names.finish()),
+                Arc::new(encodings.finish()),
+                Arc::new(rows.finish()),
+                Arc::new(sizes.finish()),
+            ],
+        )
+    }
+}
+
+/// The name of the table listing the series overlapping between the
+/// chunks of each partition
+pub const SERIES_OVERLAP_TABLE_NAME: &str = "system.series_overlap";
+
+/// The series of the chunks of any number of partitions, by partition key
+/// and then table name
+#[derive(Debug, Default)]
+pub struct SeriesOverlap {
+    tables: BTreeMap<String, BTreeMap<String, TableSeries>>,
+}
+
+/// The series of the chunks of a partition for a table
+#[derive(Debug, Default)]
+struct TableSeries {
+    chunks: u64,
+    /// The number of chunks each series is in, by series key
+    series: BTreeMap<String, u64>,
+}
+
+impl TableSeries {
+    /// The number of series in more than one chunk
+    fn overlapping_series(&self) -> u64 {
+        self.series.values().filter(|&&chunks| chunks > 1).count() as u64
+    }
+
+    /// The number of times series are in a chunk besides the first one
+    /// they are in, which is how many times the rows of a series have to
+    /// be merged with those of another chunk at query time
+    fn duplicate_series(&self) -> u64 {
+        self.series.values().map(|chunks| chunks - 1).sum()
+    }
+}
+
+impl SeriesOverlap {
+    /// Adds the series keys of a chunk of the partition `partition_key`
+    pub fn add(&mut self, partition_key: &str, series_keys: TableSeriesKeys) {
+        let partition = self.tables.entry(partition_key.to_string()).or_default();
+        for (table_name, keys) in series_keys {
+            let table = partition.entry(table_name).or_default();
+            table.chunks += 1;
+            for key in keys {
+                *table.series.entry(key).or_default() += 1;
+            }
+        }
+    }
+
+    /// Returns the partition keys with the number of their series in more
+    /// than one chunk, ordered from the most overlap to the least. This is
+    /// the order partitions would best be compacted in.
+    pub fn partitions_by_overlap(&self) -> Vec<(String, u64)> {
+        let mut partitions: Vec<_> = self
+            .tables
+            .iter()
+            .map(|(partition_key, tables)| {
+                let overlapping = tables.values().map(TableSeries::overlapping_series).sum();
+                (partition_key.clone(), overlapping)
+            })
+            .collect();
+        partitions.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+        partitions
+    }
+
+    /// Returns the contents of the `system.series_overlap` table, ordered
+    /// by partition key and table name
+    pub fn to_batch(&self) -> ArrowResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("partition_key", DataType::Utf8, false),
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("chunks", DataType::UInt64, false),
+            Field::new("series", DataType::UInt64, false),
+            Field::new("overlapping_series", DataType::UInt64, false),
+            Field::new("duplicate_series", DataType::UInt64, false),
+        ]));
+
+        let len = self.tables.values().map(|tables| tables.len()).sum();
+        let mut partition_keys = StringBuilder::new(len);
+        let mut table_names = StringBuilder::new(len);
+        let mut chunks = UInt64Builder::new(len);
+        let mut series = UInt64Builder::new(len);
+        let mut overlapping_series = UInt64Builder::new(len);
+        let mut duplicate_series = UInt64Builder::new(len);
+
+        for (partition_key, tables) in &self.tables {
+            for (table_name, table) in tables {
+                partition_keys.append_value(partition_key)?;
+                table_names.append_value(table_name)?;
+                chunks.append_value(table.chunks)?;
+                series.append_value(table.series.len() as u64)?;
+                overlapping_series.append_value(table.overlapping_series())?;
+                duplicate_series.append_value(table.duplicate_series())?;
+            }
+        }
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(partition_keys.finish()),
+                Arc::new(table_names.finish()),
+                Arc::new(chunks.finish()),
+                Arc::new(series.finish()),
+                Arc::new(overlapping_series.finish()),
+                Arc::new(duplicate_series.finish()),
+            ],
+        )
+    }
+}
+
+/// The name of the table listing the objects of the database in object
+/// storage
+pub const OBJECT_STORE_TABLE_NAME: &str = "system.object_store";
+
+/// The objects of a database in object storage
+#[derive(Debug, Default)]
+pub struct ObjectStoreContents {
+    objects: Vec<StoredObject>,
+}
+
+impl ObjectStoreContents {
+    pub fn new(mut objects: Vec<StoredObject>) -> Self {
+        objects.sort_by(|a, b| a.path.cmp(&b.path));
+        Self { objects }
+    }
+
+    /// Returns the contents of the `system.object_store` table, ordered by
+    /// path
+    pub fn to_batch(&self) -> ArrowResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("last_modified", DataType::Int64, false),
+            Field::new("referenced", DataType::Boolean, false),
+        ]));
+
+        let len = self.objects.len();
+        let mut paths = StringBuilder::new(len);
+        let mut sizes = UInt64Builder::new(len);
+        let mut last_modified = Int64Builder::new(len);
+        let mut referenced = BooleanBuilder::new(len);
+
+        for object in &self.objects {
+            paths.append_value(&object.path)?;
+            sizes.append_value(object.size)?;
+            last_modified.append_value(object.last_modified)?;
+            referenced.append_value(object.referenced)?;
+        }
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(paths.finish()),
+                Arc::new(sizes.finish()),
+                Arc::new(last_modified.finish()),
+                Arc::new(referenced.finish()),
+            ],
+        )
+    }
+}
+
+/// The name of the table listing the lifecycle events of the partitions
+/// of the database
+pub const LIFECYCLE_EVENTS_TABLE_NAME: &str = "system.lifecycle_events";
+
+/// The lifecycle events of the partitions of a database
+#[derive(Debug, Default)]
+pub struct LifecycleEvents {
+    events: Vec<LifecycleEvent>,
+}
+
+impl LifecycleEvents {
+    pub fn new(mut events: Vec<LifecycleEvent>) -> Self {
+        // the sort is stable, so events recorded at the same time keep
+        // their order
+        events.sort_by_key(|event| event.time);
+        Self { events }
+    }
+
+    /// Returns the contents of the `system.lifecycle_events` table,
+    /// ordered by time
+    pub fn to_batch(&self) -> ArrowResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("time", DataType::Int64, false),
+            Field::new("partition_key", DataType::Utf8, false),
+            Field::new("chunk_id", DataType::UInt32, true),
+            Field::new("event", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+        ]));
+
+        let len = self.events.len();
+        let mut times = Int64Builder::new(len);
+        let mut partition_keys = StringBuilder::new(len);
+        let mut chunk_ids = UInt32Builder::new(len);
+        let mut kinds = StringBuilder::new(len);
+        let mut sizes = UInt64Builder::new(len);
+
+        for event in &self.events {
+            times.append_value(event.time)?;
+            partition_keys.append_value(&event.partition_key)?;
+            chunk_ids.append_option(event.chunk_id)?;
+            kinds.append_value(event.kind.as_str())?;
+            sizes.append_value(event.size)?;
+        }
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(times.finish()),
+                Arc::new(partition_keys.finish()),
+                Arc::new(chunk_ids.finish()),
+                Arc::new(kinds.finish()),
+                Arc::new(sizes.finish()),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LifecycleEventKind;
+    use arrow_deps::assert_table_eq;
+
+    fn encoding(column_name: &str, encoding: &str, size: u64) -> ColumnEncoding {
+        ColumnEncoding {
+            table_name: "cpu".to_string(),
+            row_group: 0,
+            column_name: column_name.to_string(),
+            encoding: encoding.to_string(),
+            rows: 10,
+            size,
+        }
+    }
+
+    #[test]
+    fn column_encodings_table() {
+        let mut encodings = ColumnEncodings::default();
+        encodings.add(
+            "2020-11-19",
+            1,
+            vec![
+                encoding("host", "RLE_DICTIONARY", 300),
+                encoding("time", "FIXED_U32", 64),
+            ],
+        );
+        encodings.add("2020-11-20", 0, vec![encoding("usage", "FIXED_F64", 104)]);
+
+        let expected = vec![
+            "+---------------+----------+------------+-----------+-------------+----------------+------+------+",
+            "| partition_key | chunk_id | table_name | row_group | column_name | encoding       | rows | size |",
+            "+---------------+----------+------------+-----------+-------------+----------------+------+------+",
+            "| 2020-11-19    | 1        | cpu        | 0         | host        | RLE_DICTIONARY | 10   | 300  |",
+            "| 2020-11-19    | 1        | cpu        | 0         | time        | FIXED_U32      | 10   | 64   |",
+            "| 2020-11-20    | 0        | cpu        | 0         | usage       | FIXED_F64      | 10   | 104  |",
+            "+---------------+----------+------------+-----------+-------------+----------------+------+------+",
+        ];
+        assert_table_eq!(expected, &[encodings.to_batch().unwrap()]);
+    }
+
+    fn series_keys(table_name: &str, keys: &[&str]) -> TableSeriesKeys {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+        vec![(table_name.to_string(), keys)].into_iter().collect()
+    }
+
+    #[test]
+    fn series_overlap_table() {
+        let mut overlap = SeriesOverlap::default();
+        overlap.add("2020-11-19", series_keys("cpu", &["host=a", "host=b"]));
+        overlap.add("2020-11-19", series_keys("cpu", &["host=b", "host=c"]));
+        overlap.add("2020-11-19", series_keys("cpu", &["host=b"]));
+        overlap.add("2020-11-19", series_keys("mem", &["host=a"]));
+        overlap.add("2020-11-20", series_keys("cpu", &["host=a"]));
+
+        let expected = vec![
+            "+---------------+------------+--------+--------+--------------------+------------------+",
+            "| partition_key | table_name | chunks | series | overlapping_series | duplicate_series |",
+            "+---------------+------------+--------+--------+--------------------+------------------+",
+            "| 2020-11-19    | cpu        | 3      | 3      | 1                  | 2                |",
+            "| 2020-11-19    | mem        | 1      | 1      | 0                  | 0                |",
+            "| 2020-11-20    | cpu        | 1      | 1      | 0                  | 0                |",
+            "+---------------+------------+--------+--------+--------------------+------------------+",
+        ];
+        assert_table_eq!(expected, &[overlap.to_batch().unwrap()]);
+
+        assert_eq!(
+            overlap.partitions_by_overlap(),
+            vec![("2020-11-19".to_string(), 1), ("2020-11-20".to_string(), 0)]
+        );
+    }
+
+    fn event(
+        time: i64,
+        partition_key: &str,
+        chunk_id: Option<u32>,
+        kind: LifecycleEventKind,
+        size: u64,
+    ) -> LifecycleEvent {
+        LifecycleEvent {
+            partition_key: partition_key.to_string(),
+            chunk_id,
+            kind,
+            time,
+            size,
+        }
+    }
+
+    #[test]
+    fn lifecycle_events_table() {
+        let events = LifecycleEvents::new(vec![
+            event(20, "2020-11-19", Some(0), LifecycleEventKind::Closed, 512),
+            event(10, "2020-11-19", None, LifecycleEventKind::Created, 0),
+            event(
+                30,
+                "2020-11-19",
+                Some(0),
+                LifecycleEventKind::Compacted,
+                128,
+            ),
+            event(30, "2020-11-19", Some(0), LifecycleEventKind::Evicted, 512),
+        ]);
+
+        let expected = vec![
+            "+------+---------------+----------+-----------+------+",
+            "| time | partition_key | chunk_id | event     | size |",
+            "+------+---------------+----------+-----------+------+",
+            "| 10   | 2020-11-19    |          | created   | 0    |",
+            "| 20   | 2020-11-19    | 0        | closed    | 512  |",
+            "| 30   | 2020-11-19    | 0        | compacted | 128  |",
+            "| 30   | 2020-11-19    | 0        | evicted   | 512  |",
+            "+------+---------------+----------+-----------+------+",
+        ];
+        assert_table_eq!(expected, &[events.to_batch().unwrap()]);
+    }
+}
@@ -13,7 +13,8 @@ use crate::{
         stringset::{StringSet, StringSetRef},
         SeriesSetPlans, StringSetPlan,
     },
-    Database, DatabaseStore, PartitionChunk, Predicate,
+    Database, DatabaseError, DatabaseErrorKind, DatabaseStore, PartitionChunk, Predicate,
+    TableRowCount, TableTagValues,
 };
 
 use data_types::{
@@ -32,6 +33,9 @@ use tokio::sync::Mutex;
 
 #[derive(Debug, Default)]
 pub struct TestDatabase {
+    /// The name of this test database
+    name: String,
+
     /// Partitions which have been saved to this test database
     /// Key is partition name
     /// Value is map of chunk_id to chunk
@@ -130,11 +134,25 @@ pub enum TestError {
 
 pub type Result<T, E = TestError> = std::result::Result<T, E>;
 
+impl From<TestError> for DatabaseError {
+    fn from(e: TestError) -> Self {
+        Self::new(DatabaseErrorKind::Internal, e)
+    }
+}
+
 impl TestDatabase {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Create a test database named `name`
+    pub fn new_with_name(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Self::default()
+        }
+    }
+
     /// Get all lines written to this database
     pub async fn get_lines(&self) -> Vec<String> {
         self.saved_lines.lock().await.clone()
@@ -287,6 +305,10 @@ impl Database for TestDatabase {
     type Error = TestError;
     type Chunk = TestChunk;
 
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Adds the replicated write to this database
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
         self.replicated_writes.lock().await.push(write.clone());
@@ -434,6 +456,10 @@ pub struct TestChunk {
 
     /// A copy of the captured predicate passed
     pub table_names_predicate: std::sync::Mutex<Option<Predicate>>,
+
+    /// The time ranges of the rows of tables, reported by
+    /// `table_row_count`
+    pub table_time_ranges: BTreeMap<String, (i64, i64)>,
 }
 
 impl TestChunk {
@@ -449,6 +475,13 @@ impl TestChunk {
         self
     }
 
+    /// Adds a table whose rows are timestamped from `min` to `max`
+    pub fn with_table_time_range(mut self, name: impl Into<String>, min: i64, max: i64) -> Self {
+        let name = name.into();
+        self.table_time_ranges.insert(name.clone(), (min, max));
+        self.with_table(name)
+    }
+
     /// Get a copy of any predicate passed to table_names
     pub fn table_names_predicate(&self) -> Option<Predicate> {
         self.table_names_predicate
@@ -481,6 +514,22 @@ impl PartitionChunk for TestChunk {
         unimplemented!()
     }
 
+    fn all_tag_values(&self) -> Result<TableTagValues, Self::Error> {
+        unimplemented!()
+    }
+
+    fn table_row_count(&self, table_name: &str) -> Result<Option<TableRowCount>, Self::Error> {
+        // a table with a time range holds a row at each end of it
+        let row_count = match self.table_time_ranges.get(table_name) {
+            Some(&time_range) => TableRowCount {
+                rows: 2,
+                time_range: Some(time_range),
+            },
+            None => TableRowCount::default(),
+        };
+        Ok(Some(row_count))
+    }
+
     async fn table_names(&self, predicate: &Predicate) -> Result<LogicalPlan, Self::Error> {
         // save the predicate
         self.table_names_predicate
@@ -543,7 +592,7 @@ impl DatabaseStore for TestDatabaseStore {
         if let Some(db) = databases.get(name) {
             Ok(db.clone())
         } else {
-            let new_db = Arc::new(TestDatabase::new());
+            let new_db = Arc::new(TestDatabase::new_with_name(name));
             databases.insert(name.to_string(), new_db.clone());
             Ok(new_db)
         }
@@ -572,6 +621,7 @@ impl TestLPWriter {
         // partitions data in hourly segments
         let partition_template = PartitionTemplate {
             parts: vec![TemplatePart::TimeFormat("%Y-%m-%dT%H".to_string())],
+            ..Default::default()
         };
 
         let rules = DatabaseRules {
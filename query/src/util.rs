@@ -60,3 +60,13 @@ pub fn make_scan_plan(batch: RecordBatch) -> std::result::Result<LogicalPlan, Da
     let projection = None; // scan all columns
     LogicalPlanBuilder::scan_memory(partitions, schema, projection)?.build()
 }
+
+/// Returns the smallest time range holding both `a` and `b`, each the
+/// smallest and largest timestamp of some rows, or `None` if there are
+/// no such rows
+pub fn union_time_ranges(a: Option<(i64, i64)>, b: Option<(i64, i64)>) -> Option<(i64, i64)> {
+    match (a, b) {
+        (Some((a_min, a_max)), Some((b_min, b_max))) => Some((a_min.min(b_min), a_max.max(b_max))),
+        (range, None) | (None, range) => range,
+    }
+}
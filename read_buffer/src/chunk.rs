@@ -5,7 +5,7 @@ use crate::row_group::{ColumnName, Predicate};
 use crate::schema::AggregateType;
 use crate::table;
 use crate::table::{ColumnSelection, Table};
-use crate::Error;
+use crate::{ColumnEncoding, Error};
 
 type TableName = String;
 
@@ -64,6 +64,47 @@ impl Chunk {
         self.tables.contains_key(table_name)
     }
 
+    /// The number of rows in the table and their time range. A table that
+    /// doesn't exist in the chunk has no rows.
+    pub fn table_rows(&self, table_name: &str) -> (u64, Option<(i64, i64)>) {
+        self.tables
+            .get(table_name)
+            .map_or((0, None), |table| (table.rows(), table.time_range()))
+    }
+
+    /// The encoding of each column of each table in the chunk.
+    pub fn column_encodings(&self) -> Vec<ColumnEncoding> {
+        self.tables
+            .values()
+            .flat_map(|table| table.column_encodings())
+            .collect()
+    }
+
+    /// The distinct values of each tag column of each table in the chunk,
+    /// keyed by table name and then tag key.
+    pub fn all_tag_values(&self) -> BTreeMap<String, BTreeMap<String, BTreeSet<String>>> {
+        self.tables
+            .iter()
+            .map(|(name, table)| (name.clone(), table.all_tag_values()))
+            .collect()
+    }
+
+    /// The distinct values of the tag column `column` of each table in the
+    /// chunk that start with `prefix`, keyed by table name. Tables without
+    /// the tag column are left out.
+    pub fn tag_values_with_prefix(
+        &self,
+        column: &str,
+        prefix: &str,
+    ) -> BTreeMap<String, BTreeSet<String>> {
+        self.tables
+            .iter()
+            .filter_map(|(name, table)| {
+                Some((name.clone(), table.tag_values_with_prefix(column, prefix)?))
+            })
+            .collect()
+    }
+
     /// Returns true if there are no tables under this chunk.
     pub fn is_empty(&self) -> bool {
         self.tables() == 0
@@ -25,6 +25,25 @@ use crate::schema::{AggregateType, LogicalDataType};
 // compression is worth the memory and compute costs to work on it.
 pub const TEMP_CARDINALITY_DICTIONARY_ENCODING_LIMIT: usize = 100_000;
 
+// String columns with up to this many distinct values are always RLE encoded.
+pub const LOW_CARDINALITY_RLE_LIMIT: usize = 1_000;
+
+// Above `LOW_CARDINALITY_RLE_LIMIT` distinct values a string column is only RLE
+// encoded if its runs of repeated values are at least this long on average.
+// Shorter runs cost more to store and scan than a plain dictionary encoding.
+pub const MIN_AVERAGE_RLE_RUN_LENGTH: usize = 4;
+
+// Determines whether a string column with the provided cardinality, number of
+// rows and number of runs of repeated values should be RLE encoded rather than
+// plain dictionary encoded.
+fn use_rle_dictionary(cardinality: usize, rows: usize, runs: usize) -> bool {
+    if cardinality > TEMP_CARDINALITY_DICTIONARY_ENCODING_LIMIT {
+        return false;
+    }
+
+    cardinality <= LOW_CARDINALITY_RLE_LIMIT || rows >= runs * MIN_AVERAGE_RLE_RUN_LENGTH
+}
+
 /// The possible logical types that column values can have. All values in a
 /// column have the same physical type.
 pub enum Column {
@@ -80,8 +99,28 @@ impl Column {
         }
     }
 
+    /// The total size in bytes of the encoded column data.
     pub fn size(&self) -> u64 {
-        0
+        match &self {
+            Column::String(_, data) => data.size(),
+            Column::Float(_, data) => data.size(),
+            Column::Integer(_, data) => data.size(),
+            Column::Unsigned(_, data) => data.size(),
+            Column::Bool => 0,
+            Column::ByteArray(_, data) => data.size(),
+        }
+    }
+
+    /// The name of the encoding the column data was stored with.
+    pub fn encoding_name(&self) -> &'static str {
+        match &self {
+            Column::String(_, data) => data.name(),
+            Column::Float(_, data) => data.name(),
+            Column::Integer(_, data) => data.name(),
+            Column::Unsigned(_, data) => data.name(),
+            Column::Bool => "BOOL",
+            Column::ByteArray(_, data) => data.name(),
+        }
     }
 
     /// Returns the (min, max)  values stored in this column
@@ -197,6 +236,25 @@ impl Column {
         }
     }
 
+    /// The distinct non-null values of a dictionary encoded column, or
+    /// `None` if the column isn't dictionary encoded.
+    pub fn dictionary(&self) -> Option<Vec<&String>> {
+        match &self {
+            Column::String(_, data) => Some(data.dictionary()),
+            _ => None,
+        }
+    }
+
+    /// The distinct non-null values of a dictionary encoded column that
+    /// start with `prefix`, or `None` if the column isn't dictionary
+    /// encoded.
+    pub fn dictionary_with_prefix(&self, prefix: &str) -> Option<Vec<&String>> {
+        match &self {
+            Column::String(_, data) => Some(data.dictionary_with_prefix(prefix)),
+            _ => None,
+        }
+    }
+
     /// The value present at the provided logical row id.
     pub fn decode_id(&self, encoded_id: u32) -> Value<'_> {
         match &self {
@@ -703,6 +761,22 @@ pub enum StringEncoding {
 /// This implementation is concerned with how to produce string columns with
 /// different encodings.
 impl StringEncoding {
+    /// The total size in bytes of the encoded data.
+    pub fn size(&self) -> u64 {
+        match &self {
+            Self::RLEDictionary(c) => c.size(),
+            Self::Dictionary(c) => c.size(),
+        }
+    }
+
+    /// The name of the encoding.
+    pub fn name(&self) -> &'static str {
+        match &self {
+            Self::RLEDictionary(_) => "RLE_DICTIONARY",
+            Self::Dictionary(_) => "DICTIONARY",
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         match &self {
@@ -745,6 +819,23 @@ impl StringEncoding {
         }
     }
 
+    /// The distinct non-null values in the column's dictionary.
+    pub fn dictionary(&self) -> Vec<&String> {
+        match &self {
+            Self::RLEDictionary(c) => c.dictionary(),
+            Self::Dictionary(c) => c.dictionary(),
+        }
+    }
+
+    /// The distinct non-null values in the column's dictionary that start
+    /// with `prefix`, in order.
+    pub fn dictionary_with_prefix(&self, prefix: &str) -> Vec<&String> {
+        match &self {
+            Self::RLEDictionary(c) => c.dictionary_with_prefix(prefix),
+            Self::Dictionary(c) => c.dictionary_with_prefix(prefix),
+        }
+    }
+
     /// Returns the logical value for the specified encoded representation.
     pub fn decode_id(&self, encoded_id: u32) -> Value<'_> {
         match &self {
@@ -826,20 +917,32 @@ impl StringEncoding {
     }
 
     fn from_arrow_string_array(arr: &arrow::array::StringArray) -> Self {
-        // build a sorted dictionary.
+        // build a sorted dictionary, counting the runs of repeated values
+        // (including NULLs) an RLE encoding would store.
         let mut dictionary = BTreeSet::new();
+        let mut runs = 0;
 
+        let value = |i: usize| {
+            if arr.is_null(i) {
+                None
+            } else {
+                Some(arr.value(i))
+            }
+        };
         for i in 0..arr.len() {
-            if !arr.is_null(i) {
-                dictionary.insert(arr.value(i).to_string());
+            if let Some(v) = value(i) {
+                dictionary.insert(v.to_string());
+            }
+            if i == 0 || value(i - 1) != value(i) {
+                runs += 1;
             }
         }
 
         let mut data: dictionary::Encoding =
-            if dictionary.len() > TEMP_CARDINALITY_DICTIONARY_ENCODING_LIMIT {
-                dictionary::Encoding::Plain(dictionary::Plain::with_dictionary(dictionary))
-            } else {
+            if use_rle_dictionary(dictionary.len(), arr.len(), runs) {
                 dictionary::Encoding::RLE(dictionary::RLE::with_dictionary(dictionary))
+            } else {
+                dictionary::Encoding::Plain(dictionary::Plain::with_dictionary(dictionary))
             };
 
         let mut prev = if !arr.is_null(0) {
@@ -1068,6 +1171,45 @@ pub enum IntegerEncoding {
 }
 
 impl IntegerEncoding {
+    /// The total size in bytes of the encoded data.
+    pub fn size(&self) -> u64 {
+        match &self {
+            Self::I64I64(c) => c.size(),
+            Self::I64I32(c) => c.size(),
+            Self::I64U32(c) => c.size(),
+            Self::I64I16(c) => c.size(),
+            Self::I64U16(c) => c.size(),
+            Self::I64I8(c) => c.size(),
+            Self::I64U8(c) => c.size(),
+            Self::U64U64(c) => c.size(),
+            Self::U64U32(c) => c.size(),
+            Self::U64U16(c) => c.size(),
+            Self::U64U8(c) => c.size(),
+            Self::I64I64N(c) => c.size(),
+            Self::U64U64N(c) => c.size(),
+        }
+    }
+
+    /// The name of the encoding, including the physical type the values
+    /// are stored as.
+    pub fn name(&self) -> &'static str {
+        match &self {
+            Self::I64I64(_) => "FIXED_I64",
+            Self::I64I32(_) => "FIXED_I32",
+            Self::I64U32(_) => "FIXED_U32",
+            Self::I64I16(_) => "FIXED_I16",
+            Self::I64U16(_) => "FIXED_U16",
+            Self::I64I8(_) => "FIXED_I8",
+            Self::I64U8(_) => "FIXED_U8",
+            Self::U64U64(_) => "FIXED_U64",
+            Self::U64U32(_) => "FIXED_U32",
+            Self::U64U16(_) => "FIXED_U16",
+            Self::U64U8(_) => "FIXED_U8",
+            Self::I64I64N(_) => "FIXED_NULL_I64",
+            Self::U64U64N(_) => "FIXED_NULL_U64",
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         if let Self::I64I64N(c) = &self {
@@ -1376,6 +1518,22 @@ pub enum FloatEncoding {
 }
 
 impl FloatEncoding {
+    /// The total size in bytes of the encoded data.
+    pub fn size(&self) -> u64 {
+        match &self {
+            Self::Fixed64(c) => c.size(),
+            Self::FixedNull64(c) => c.size(),
+        }
+    }
+
+    /// The name of the encoding.
+    pub fn name(&self) -> &'static str {
+        match &self {
+            Self::Fixed64(_) => "FIXED_F64",
+            Self::FixedNull64(_) => "FIXED_NULL_F64",
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         // TODO(edd): when adding the nullable columns then ask the nullable
@@ -1483,9 +1641,9 @@ impl FloatEncoding {
     }
 }
 
-// Converts an Arrow `StringArray` into a column, currently using the RLE
-// encoding scheme. Other encodings can be supported and added to this
-// implementation.
+// Converts an Arrow `StringArray` into a column, using the RLE encoding scheme
+// unless the column has many distinct values in short runs, which are stored
+// in a plain dictionary encoding instead.
 //
 // Note: this currently runs through the array and builds the dictionary before
 // creating the encoding. There is room for performance improvement here but
@@ -3163,6 +3321,31 @@ mod test {
         }
     }
 
+    #[test]
+    fn from_arrow_string_array_encoding() {
+        // many distinct values in short runs are plain dictionary encoded.
+        let values = (0..2000).map(|i| format!("host-{}", i)).collect::<Vec<_>>();
+        let arr = StringArray::from(values.iter().map(|v| v.as_str()).collect::<Vec<_>>());
+        let col = Column::from(arr);
+        assert_eq!(col.encoding_name(), "DICTIONARY");
+        assert!(col.size() > 0);
+
+        // the same values in long runs are RLE encoded.
+        let arr = StringArray::from(
+            values
+                .iter()
+                .flat_map(|v| std::iter::repeat(v.as_str()).take(10))
+                .collect::<Vec<_>>(),
+        );
+        let col = Column::from(arr);
+        assert_eq!(col.encoding_name(), "RLE_DICTIONARY");
+
+        // as are low cardinality columns, however short their runs.
+        let arr = StringArray::from(vec![Some("a"), None, Some("b"), Some("a")]);
+        let col = Column::from(arr);
+        assert_eq!(col.encoding_name(), "RLE_DICTIONARY");
+    }
+
     #[test]
     fn from_strs() {
         let arr = vec!["world", "hello"];
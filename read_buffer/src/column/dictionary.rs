@@ -135,6 +135,15 @@ impl Encoding {
         }
     }
 
+    /// The distinct non-null values in the dictionary that start with
+    /// `prefix`, in order.
+    pub fn dictionary_with_prefix(&self, prefix: &str) -> Vec<&String> {
+        match self {
+            Encoding::RLE(enc) => enc.dictionary_with_prefix(prefix),
+            Encoding::Plain(enc) => enc.dictionary_with_prefix(prefix),
+        }
+    }
+
     /// Returns the logical value present at the provided row id.
     ///
     /// N.B right now this doesn't discern between an invalid row id and a NULL
@@ -812,6 +821,39 @@ mod test {
         );
     }
 
+    #[test]
+    fn dictionary_with_prefix() {
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for enc in encodings {
+            _dictionary_with_prefix(enc);
+        }
+    }
+
+    fn _dictionary_with_prefix(mut enc: Encoding) {
+        let name = enc.debug_name();
+        assert!(enc.dictionary_with_prefix("").is_empty());
+
+        enc.push_additional(Some("east".to_string()), 2);
+        enc.push_none();
+        enc.push_additional(Some("west".to_string()), 1);
+        enc.push_additional(Some("westerly".to_string()), 1);
+        enc.push_additional(Some("zoo".to_string()), 1);
+
+        assert_eq!(
+            enc.dictionary_with_prefix("west"),
+            vec![&"west".to_string(), &"westerly".to_string()],
+            "{}",
+            name
+        );
+        assert_eq!(enc.dictionary_with_prefix("").len(), 4, "{}", name);
+        assert!(enc.dictionary_with_prefix("north").is_empty(), "{}", name);
+        assert!(enc.dictionary_with_prefix("zoos").is_empty(), "{}", name);
+    }
+
     #[test]
     fn value() {
         let encodings = vec![
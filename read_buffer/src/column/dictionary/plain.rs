@@ -502,6 +502,22 @@ impl Plain {
             .collect()
     }
 
+    /// The distinct non-null values in the dictionary that start with
+    /// `prefix`, in order. The first match is found with a binary search of
+    /// the sorted entries, so only the matching entries are visited.
+    pub fn dictionary_with_prefix(&self, prefix: &str) -> Vec<&String> {
+        let entries = &self.entries[1..];
+        let start = match entries.binary_search_by(|entry| entry.as_deref().cmp(&Some(prefix))) {
+            Ok(idx) | Err(idx) => idx,
+        };
+
+        entries[start..]
+            .iter()
+            .filter_map(|v| v.as_ref())
+            .take_while(|entry| entry.starts_with(prefix))
+            .collect()
+    }
+
     /// Returns the logical value present at the provided row id. Panics if the
     /// encoding doesn't have a logical row at the id.
     pub fn value(&self, row_id: u32) -> Option<&String> {
@@ -2,6 +2,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::convert::From;
 use std::iter;
 use std::mem::size_of;
+use std::ops::Bound;
 
 use croaring::Bitmap;
 
@@ -394,6 +395,17 @@ impl RLE {
         self.index_entries.iter().skip(1).collect()
     }
 
+    /// The distinct non-null values in the dictionary that start with
+    /// `prefix`, in order. The entry index is sorted, so only the matching
+    /// entries are visited.
+    pub fn dictionary_with_prefix(&self, prefix: &str) -> Vec<&String> {
+        self.entry_index
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(entry, _)| entry)
+            .take_while(|entry| entry.starts_with(prefix))
+            .collect()
+    }
+
     /// Returns the logical value present at the provided row id.
     ///
     /// N.B right now this doesn't discern between an invalid row id and a NULL
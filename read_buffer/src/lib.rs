@@ -55,6 +55,22 @@ pub enum Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The encoding a column of a row group was stored with when it was
+/// converted into the read buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnEncoding {
+    pub table_name: String,
+    /// The position of the row group in the table
+    pub row_group: usize,
+    pub column_name: String,
+    /// The name of the encoding, e.g. `RLE_DICTIONARY`
+    pub encoding: &'static str,
+    /// The number of rows in the row group
+    pub rows: u32,
+    /// The size in bytes of the encoded column data
+    pub size: u64,
+}
+
 // A database is scoped to a single tenant. Within a database there exists
 // partitions, chunks, tables and row groups.
 #[derive(Default)]
@@ -159,6 +175,15 @@ impl Database {
         self.size
     }
 
+    /// Returns the size in bytes of the specified chunk, or `None` if it
+    /// doesn't exist
+    pub fn chunk_size(&self, partition_key: &str, chunk_id: u32) -> Option<u64> {
+        self.partitions
+            .get(partition_key)
+            .and_then(|partition| partition.chunks.get(&chunk_id))
+            .map(Chunk::size)
+    }
+
     pub fn rows(&self) -> u64 {
         self.rows
     }
@@ -405,6 +430,101 @@ impl Database {
         str_iter_to_batch(TABLE_NAMES_COLUMN_NAME, names).context(ArrowError)
     }
 
+    /// Returns the number of rows of the table in the specified chunk and
+    /// their time range, from the chunk's meta data. Returns zero rows if
+    /// the chunk has no data for the table.
+    pub fn table_rows(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+        table_name: &str,
+    ) -> Result<(u64, Option<(i64, i64)>)> {
+        let partition = self
+            .partitions
+            .get(partition_key)
+            .ok_or(Error::PartitionNotFound {
+                key: partition_key.to_owned(),
+            })?;
+
+        let chunk = partition
+            .chunks
+            .get(&chunk_id)
+            .context(ChunkNotFound { id: chunk_id })?;
+
+        Ok(chunk.table_rows(table_name))
+    }
+
+    /// Returns the distinct values of every tag column of every table in the
+    /// specified chunk, keyed by table name and then tag key. The values are
+    /// read from the column dictionaries, without decoding any rows.
+    pub fn all_tag_values(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<BTreeMap<String, BTreeMap<String, BTreeSet<String>>>> {
+        let partition = self
+            .partitions
+            .get(partition_key)
+            .ok_or(Error::PartitionNotFound {
+                key: partition_key.to_owned(),
+            })?;
+
+        let chunk = partition
+            .chunks
+            .get(&chunk_id)
+            .context(ChunkNotFound { id: chunk_id })?;
+
+        Ok(chunk.all_tag_values())
+    }
+
+    /// Returns the distinct values of the tag column `column` of each table
+    /// in the specified chunk that start with `prefix`, keyed by table name.
+    /// The column dictionaries are sorted, so only the matching values are
+    /// visited.
+    pub fn tag_values_with_prefix(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+        column: &str,
+        prefix: &str,
+    ) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let partition = self
+            .partitions
+            .get(partition_key)
+            .ok_or(Error::PartitionNotFound {
+                key: partition_key.to_owned(),
+            })?;
+
+        let chunk = partition
+            .chunks
+            .get(&chunk_id)
+            .context(ChunkNotFound { id: chunk_id })?;
+
+        Ok(chunk.tag_values_with_prefix(column, prefix))
+    }
+
+    /// Returns the encoding chosen for each column of each row group of each
+    /// table in the specified chunk, with the size of the encoded data.
+    pub fn column_encodings(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<Vec<ColumnEncoding>> {
+        let partition = self
+            .partitions
+            .get(partition_key)
+            .ok_or(Error::PartitionNotFound {
+                key: partition_key.to_owned(),
+            })?;
+
+        let chunk = partition
+            .chunks
+            .get(&chunk_id)
+            .context(ChunkNotFound { id: chunk_id })?;
+
+        Ok(chunk.column_encodings())
+    }
+
     /// Returns the distinct set of column names (tag keys) that satisfy the
     /// provided predicate.
     pub fn column_names(
@@ -937,6 +1057,82 @@ mod test {
         );
     }
 
+    #[test]
+    fn all_tag_values() {
+        let mut db = Database::new();
+
+        db.upsert_partition("hour_1", 22, "Coolverine", gen_recordbatch());
+        db.upsert_partition("hour_1", 22, "20 Size", gen_recordbatch());
+
+        let tag_values = db.all_tag_values("hour_1", 22).unwrap();
+        let regions: BTreeSet<_> = vec!["east".to_string(), "west".to_string()]
+            .into_iter()
+            .collect();
+        assert_eq!(tag_values.len(), 2);
+        assert_eq!(tag_values["Coolverine"]["region"], regions);
+        assert_eq!(tag_values["20 Size"]["region"], regions);
+
+        assert!(matches!(
+            db.all_tag_values("hour_1", 2),
+            Err(Error::ChunkNotFound { id: 2 })
+        ));
+    }
+
+    #[test]
+    fn tag_values_with_prefix() {
+        let mut db = Database::new();
+
+        db.upsert_partition("hour_1", 22, "Coolverine", gen_recordbatch());
+        db.upsert_partition("hour_1", 22, "20 Size", gen_recordbatch());
+
+        let tag_values = db
+            .tag_values_with_prefix("hour_1", 22, "region", "w")
+            .unwrap();
+        let regions: BTreeSet<_> = vec!["west".to_string()].into_iter().collect();
+        assert_eq!(tag_values.len(), 2);
+        assert_eq!(tag_values["Coolverine"], regions);
+        assert_eq!(tag_values["20 Size"], regions);
+
+        // fields aren't tag columns
+        let tag_values = db
+            .tag_values_with_prefix("hour_1", 22, "counter", "")
+            .unwrap();
+        assert!(tag_values.is_empty());
+    }
+
+    #[test]
+    fn column_encodings() {
+        let mut db = Database::new();
+
+        db.upsert_partition("hour_1", 22, "Coolverine", gen_recordbatch());
+        db.upsert_partition("hour_1", 22, "Coolverine", gen_recordbatch());
+
+        let encodings = db.column_encodings("hour_1", 22).unwrap();
+        assert_eq!(encodings.len(), 6);
+
+        let first_row_group: Vec<_> = encodings
+            .iter()
+            .filter(|enc| enc.row_group == 0)
+            .map(|enc| (enc.column_name.as_str(), enc.encoding, enc.rows))
+            .collect();
+        assert_eq!(
+            first_row_group,
+            vec![
+                ("counter", "FIXED_F64", 3),
+                ("region", "RLE_DICTIONARY", 3),
+                ("time", "FIXED_U32", 3),
+            ]
+        );
+        assert!(encodings
+            .iter()
+            .all(|enc| enc.table_name == "Coolverine" && enc.size > 0));
+
+        assert!(matches!(
+            db.column_encodings("hour_1", 2),
+            Err(Error::ChunkNotFound { id: 2 })
+        ));
+    }
+
     #[test]
     fn read_filter_single_chunk() {
         let mut db = Database::new();
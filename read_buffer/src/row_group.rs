@@ -176,6 +176,42 @@ impl RowGroup {
         self.meta.time_range
     }
 
+    /// The name, encoding name and size in bytes of each column.
+    pub fn column_encodings(&self) -> Vec<(&str, &'static str, u64)> {
+        self.all_columns_by_name
+            .iter()
+            .map(|(name, &idx)| {
+                let column = &self.columns[idx];
+                (name.as_str(), column.encoding_name(), column.size())
+            })
+            .collect()
+    }
+
+    /// The distinct values of each tag column, taken from the column
+    /// dictionaries.
+    pub fn all_tag_values(&self) -> BTreeMap<&str, Vec<&String>> {
+        self.meta
+            .columns
+            .iter()
+            .filter(|(_, meta)| matches!(meta.typ, schema::ColumnType::Tag(_)))
+            .filter_map(|(name, _)| {
+                let column = &self.columns[*self.all_columns_by_name.get(name)?];
+                Some((name.as_str(), column.dictionary()?))
+            })
+            .collect()
+    }
+
+    /// The distinct values of the tag column `column` that start with
+    /// `prefix`, taken from the column's dictionary, or `None` if the row
+    /// group has no such tag column.
+    pub fn tag_values_with_prefix(&self, column: &str, prefix: &str) -> Option<Vec<&String>> {
+        match self.meta.columns.get(column) {
+            Some(meta) if matches!(meta.typ, schema::ColumnType::Tag(_)) => {}
+            _ => return None,
+        }
+        self.columns[*self.all_columns_by_name.get(column)?].dictionary_with_prefix(prefix)
+    }
+
     /// Efficiently determines if the provided set of binary expressions could
     /// all be satisfied by the `RowGroup` when conjunctively applied.
     pub fn could_satisfy_conjunctive_binary_expressions<'a>(
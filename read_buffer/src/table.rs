@@ -5,6 +5,7 @@ use std::slice::Iter;
 use crate::column::{AggregateResult, Scalar, Value};
 use crate::row_group::{self, ColumnName, GroupKey, Predicate, RowGroup};
 use crate::schema::{AggregateType, ColumnType, LogicalDataType, ResultSchema};
+use crate::ColumnEncoding;
 
 /// A Table represents data for a single measurement.
 ///
@@ -87,6 +88,56 @@ impl Table {
         self.meta.time_range
     }
 
+    /// The encoding of each column in each of the table's row groups.
+    pub fn column_encodings(&self) -> Vec<ColumnEncoding> {
+        self.row_groups
+            .iter()
+            .enumerate()
+            .flat_map(|(row_group, rg)| {
+                let rows = rg.rows();
+                rg.column_encodings()
+                    .into_iter()
+                    .map(move |(column_name, encoding, size)| ColumnEncoding {
+                        table_name: self.name.clone(),
+                        row_group,
+                        column_name: column_name.to_owned(),
+                        encoding,
+                        rows,
+                        size,
+                    })
+            })
+            .collect()
+    }
+
+    /// The distinct values of each tag column across all row groups.
+    pub fn all_tag_values(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut tag_values: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for rg in &self.row_groups {
+            for (name, values) in rg.all_tag_values() {
+                tag_values
+                    .entry(name.to_owned())
+                    .or_default()
+                    .extend(values.into_iter().cloned());
+            }
+        }
+        tag_values
+    }
+
+    /// The distinct values of the tag column `column` that start with
+    /// `prefix` across all row groups, or `None` if the table has no such
+    /// tag column.
+    pub fn tag_values_with_prefix(&self, column: &str, prefix: &str) -> Option<BTreeSet<String>> {
+        let mut tag_values: Option<BTreeSet<String>> = None;
+        for rg in &self.row_groups {
+            if let Some(values) = rg.tag_values_with_prefix(column, prefix) {
+                tag_values
+                    .get_or_insert_with(BTreeSet::new)
+                    .extend(values.into_iter().cloned());
+            }
+        }
+        tag_values
+    }
+
     // Identify set of row groups that might satisfy the predicate.
     fn filter_row_groups(&self, predicate: &Predicate) -> Vec<&RowGroup> {
         let mut rgs = Vec::with_capacity(self.row_groups.len());
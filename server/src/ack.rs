@@ -0,0 +1,90 @@
+//! This module contains the acknowledgement levels of writes, which let
+//! clients with different durability needs share a database: a client
+//! can have its writes acknowledged as soon as they are buffered, while
+//! another waits for them to be replicated.
+
+use std::str::FromStr;
+
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Unknown write acknowledgement level '{}': expected buffered, wal or replicated",
+        level
+    ))]
+    UnknownAckLevel { level: String },
+}
+
+/// What a write has to reach before it is acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteAckLevel {
+    /// The write is acknowledged once it is buffered. Replication to the
+    /// host groups of the database continues in the background.
+    Buffered,
+    /// Like `Buffered`, but the WAL segment holding the write must also
+    /// have been persisted. The segment is closed right after the write is
+    /// appended to it rather than once it fills up, and writes to
+    /// databases that don't persist their WAL buffer fail.
+    Wal,
+    /// The write is acknowledged once it has also been replicated to
+    /// every host group of the database
+    Replicated,
+}
+
+impl Default for WriteAckLevel {
+    fn default() -> Self {
+        Self::Replicated
+    }
+}
+
+impl FromStr for WriteAckLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buffered" => Ok(Self::Buffered),
+            "wal" => Ok(Self::Wal),
+            "replicated" => Ok(Self::Replicated),
+            _ => UnknownAckLevel { level: s }.fail(),
+        }
+    }
+}
+
+/// The durability a write had reached when it was acknowledged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteAck {
+    /// Whether the write was stored in the mutable buffer
+    pub buffered: bool,
+    /// Whether the WAL segment holding the write was persisted
+    pub wal: bool,
+    /// The number of host groups the write was replicated to
+    pub replicated: usize,
+    /// The number of host groups the write is still being replicated to
+    /// in the background
+    pub replicating: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ack_levels() {
+        assert_eq!(
+            "buffered".parse::<WriteAckLevel>().unwrap(),
+            WriteAckLevel::Buffered
+        );
+        assert_eq!("wal".parse::<WriteAckLevel>().unwrap(), WriteAckLevel::Wal);
+        assert_eq!(
+            "replicated".parse::<WriteAckLevel>().unwrap(),
+            WriteAckLevel::Replicated
+        );
+
+        let err = "synced".parse::<WriteAckLevel>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown write acknowledgement level 'synced': expected buffered, wal or replicated"
+        );
+    }
+}
@@ -0,0 +1,481 @@
+//! This module contains the audit log, which records the queries and
+//! administrative actions run by a server for compliance. Each entry says
+//! who did what, to which database, when, and whether it succeeded.
+//!
+//! Entries are only ever appended: to a local file as JSON lines, or to
+//! the object store of the server as one object per entry. An action whose
+//! entry can't be appended fails, so nothing runs unrecorded. Statements
+//! can contain sensitive values, so their literals are redacted unless
+//! configured otherwise, or they can be left out entirely.
+
+use std::{
+    fmt::Display,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use object_store::{path::ObjectStorePath, ObjectStore};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::error;
+
+/// The error a sink failed to append an entry with
+pub type SinkError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Unknown audit log redaction '{}': expected none, literals or statements",
+        redaction
+    ))]
+    UnknownRedaction { redaction: String },
+
+    #[snafu(display("Unable to append to the audit log: {}", source))]
+    Appending { source: SinkError },
+}
+
+/// An entry of the audit log
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// When the action finished
+    pub time: DateTime<Utc>,
+
+    /// The id of the server that ran the action, if it was set
+    pub writer_id: Option<u32>,
+
+    /// The principal the action was run for, if known
+    pub principal: Option<String>,
+
+    /// What was done, such as `query` or `create_database`
+    pub action: String,
+
+    /// The database the action was run against, if any
+    pub database: Option<String>,
+
+    /// The statement run, such as the SQL of a query, after redaction
+    pub statement: Option<String>,
+
+    /// Whether the action succeeded
+    pub status: AuditStatus,
+
+    /// Why the action failed, after redaction
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+    Succeeded,
+    Failed,
+}
+
+/// An action to record in the audit log
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AuditAction<'a> {
+    pub action: &'a str,
+    pub principal: Option<&'a str>,
+    pub database: Option<&'a str>,
+    pub statement: Option<&'a str>,
+}
+
+impl<'a> AuditAction<'a> {
+    pub fn new(action: &'a str) -> Self {
+        Self {
+            action,
+            ..Default::default()
+        }
+    }
+
+    pub fn principal(self, principal: Option<&'a str>) -> Self {
+        Self { principal, ..self }
+    }
+
+    pub fn database(self, database: &'a str) -> Self {
+        Self {
+            database: Some(database),
+            ..self
+        }
+    }
+
+    pub fn statement(self, statement: &'a str) -> Self {
+        Self {
+            statement: Some(statement),
+            ..self
+        }
+    }
+}
+
+/// What is left out of the statements and errors of entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditRedaction {
+    /// Statements are recorded as they were run
+    None,
+    /// The string and number literals of statements are replaced by `?`
+    Literals,
+    /// Statements and errors aren't recorded at all
+    Statements,
+}
+
+impl Default for AuditRedaction {
+    fn default() -> Self {
+        Self::Literals
+    }
+}
+
+impl FromStr for AuditRedaction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "literals" => Ok(Self::Literals),
+            "statements" => Ok(Self::Statements),
+            _ => UnknownRedaction { redaction: s }.fail(),
+        }
+    }
+}
+
+impl AuditRedaction {
+    fn redact(&self, text: Option<&str>) -> Option<String> {
+        match self {
+            Self::None => text.map(ToString::to_string),
+            Self::Literals => text.map(redact_literals),
+            Self::Statements => None,
+        }
+    }
+}
+
+/// Replaces the string literals (`'...'`) and number literals of
+/// `statement` by `?`. Quoted identifiers (`"..."`) are kept.
+pub fn redact_literals(statement: &str) -> String {
+    let mut redacted = String::with_capacity(statement.len());
+    let mut chars = statement.chars().peekable();
+    let mut in_identifier = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // '' escapes a quote inside a string literal
+                loop {
+                    match chars.next() {
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                        }
+                        Some('\'') | None => break,
+                        Some(_) => {}
+                    }
+                }
+                redacted.push_str("'?'");
+                in_identifier = false;
+            }
+            '"' => {
+                redacted.push(c);
+                for c in &mut chars {
+                    redacted.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                in_identifier = false;
+            }
+            c if c.is_ascii_digit() && !in_identifier => {
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                redacted.push('?');
+            }
+            c => {
+                in_identifier = c.is_alphanumeric() || c == '_';
+                redacted.push(c);
+            }
+        }
+    }
+    redacted
+}
+
+/// Where the entries of an audit log are appended
+#[async_trait]
+pub trait AuditSink: std::fmt::Debug + Send + Sync {
+    /// Durably appends `entry`
+    async fn append(&self, entry: &AuditEntry) -> Result<(), SinkError>;
+}
+
+/// Appends entries to a local file, one JSON object per line
+#[derive(Debug)]
+pub struct FileAuditSink {
+    path: PathBuf,
+    /// Held while appending, so concurrent entries aren't interleaved
+    append: Mutex<()>,
+}
+
+impl FileAuditSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            append: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileAuditSink {
+    async fn append(&self, entry: &AuditEntry) -> Result<(), SinkError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let _append = self.append.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+
+        Ok(())
+    }
+}
+
+/// Writes each entry as an object of an object store, under
+/// `<writer id>/audit/` (or `audit/` before the id of the server is set).
+/// Objects are named after the time of their entry, so they list in order.
+#[derive(Debug)]
+pub struct ObjectStoreAuditSink {
+    store: Arc<ObjectStore>,
+    /// Tells apart entries recorded at the same time
+    sequence: AtomicU64,
+}
+
+impl ObjectStoreAuditSink {
+    pub fn new(store: Arc<ObjectStore>) -> Self {
+        Self {
+            store,
+            sequence: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for ObjectStoreAuditSink {
+    async fn append(&self, entry: &AuditEntry) -> Result<(), SinkError> {
+        let mut location = ObjectStorePath::default();
+        if let Some(writer_id) = entry.writer_id {
+            location.push_dir(writer_id.to_string());
+        }
+        location.push_dir("audit");
+        location.set_file_name(format!(
+            "{}-{:010}.json",
+            entry.time.format("%Y%m%dT%H%M%S%.9fZ"),
+            self.sequence.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let data = Bytes::from(serde_json::to_vec(entry)?);
+        let len = data.len();
+        self.store
+            .put(
+                &location,
+                futures::stream::once(async move { std::io::Result::Ok(data) }),
+                len,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Records actions in an `AuditSink`, redacting their statements
+#[derive(Debug)]
+pub struct AuditLog {
+    sink: Arc<dyn AuditSink>,
+    redaction: AuditRedaction,
+}
+
+impl AuditLog {
+    pub fn new(sink: Arc<dyn AuditSink>, redaction: AuditRedaction) -> Self {
+        Self { sink, redaction }
+    }
+
+    /// Records that `action` ended with `result`. Callers must fail the
+    /// action if the entry can't be appended, rather than return its
+    /// result unrecorded.
+    pub async fn record<T, E: Display>(
+        &self,
+        writer_id: Option<u32>,
+        action: AuditAction<'_>,
+        result: &Result<T, E>,
+    ) -> Result<(), Error> {
+        let (status, error) = match result {
+            Ok(_) => (AuditStatus::Succeeded, None),
+            Err(e) => (AuditStatus::Failed, Some(e.to_string())),
+        };
+        let entry = AuditEntry {
+            time: Utc::now(),
+            writer_id,
+            principal: action.principal.map(ToString::to_string),
+            action: action.action.to_string(),
+            database: action.database.map(ToString::to_string),
+            statement: self.redaction.redact(action.statement),
+            status,
+            error: self.redaction.redact(error.as_deref()),
+        };
+
+        self.sink
+            .append(&entry)
+            .await
+            .map_err(|e| {
+                error!(?entry, "failed to append to the audit log: {}", e);
+                e
+            })
+            .context(Appending)
+    }
+}
+
+/// Records actions served outside of the server, such as the queries of
+/// the storage gRPC API, in the audit log of the server
+#[async_trait]
+pub trait Auditor: std::fmt::Debug + Send + Sync {
+    /// Records that `action` succeeded, or failed with `error`
+    async fn audit_action(&self, action: AuditAction<'_>, error: Option<&str>)
+        -> Result<(), Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[test]
+    fn literals_are_redacted() {
+        assert_eq!(
+            redact_literals("select * from cpu where host = 'a''b' and usage > 1.5e3"),
+            "select * from cpu where host = '?' and usage > ?"
+        );
+        assert_eq!(
+            redact_literals(r#"select "2020 'totals'" from h2o_temperature2 limit 10"#),
+            r#"select "2020 'totals'" from h2o_temperature2 limit ?"#
+        );
+        assert_eq!(redact_literals("where a = 'unterminated"), "where a = '?'");
+    }
+
+    #[test]
+    fn redaction_is_parsed() {
+        assert_eq!(AuditRedaction::default(), AuditRedaction::Literals);
+        assert_eq!(
+            "literals".parse::<AuditRedaction>().unwrap(),
+            AuditRedaction::Literals
+        );
+        assert!("all".parse::<AuditRedaction>().is_err());
+    }
+
+    #[tokio::test]
+    async fn entries_are_appended_to_files() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let path = dir.path().join("audit.log");
+        let log = AuditLog::new(
+            Arc::new(FileAuditSink::new(&path)),
+            AuditRedaction::Literals,
+        );
+
+        let action = AuditAction::new("query")
+            .principal(Some("acme"))
+            .database("my_db")
+            .statement("select * from cpu where host = 'a'");
+        log.record(Some(1), action, &Ok::<_, String>(())).await?;
+        log.record(None, action, &Err::<(), _>("no table 'cpu'"))
+            .await?;
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].writer_id, Some(1));
+        assert_eq!(entries[0].principal.as_deref(), Some("acme"));
+        assert_eq!(entries[0].database.as_deref(), Some("my_db"));
+        assert_eq!(
+            entries[0].statement.as_deref(),
+            Some("select * from cpu where host = '?'")
+        );
+        assert_eq!(entries[0].status, AuditStatus::Succeeded);
+        assert_eq!(entries[1].status, AuditStatus::Failed);
+        assert_eq!(entries[1].error.as_deref(), Some("no table '?'"));
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct FailingSink;
+
+    #[async_trait]
+    impl AuditSink for FailingSink {
+        async fn append(&self, _entry: &AuditEntry) -> Result<(), SinkError> {
+            Err("disk full".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn failing_to_append_is_an_error() {
+        let log = AuditLog::new(Arc::new(FailingSink), AuditRedaction::default());
+
+        let action = AuditAction::new("query").statement("select 1");
+        let err = log
+            .record(None, action, &Ok::<_, String>(()))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unable to append to the audit log: disk full"
+        );
+    }
+
+    #[tokio::test]
+    async fn entries_are_objects() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let log = AuditLog::new(
+            Arc::new(ObjectStoreAuditSink::new(Arc::clone(&store))),
+            AuditRedaction::Statements,
+        );
+
+        let action = AuditAction::new("delete_database").database("my_db");
+        log.record(Some(1), action, &Ok::<_, String>(())).await?;
+        log.record(Some(1), action, &Err::<(), _>("not found"))
+            .await?;
+
+        let mut prefix = ObjectStorePath::default();
+        prefix.push_all_dirs(&["1", "audit"]);
+        let paths: Vec<Vec<_>> = store.list(Some(&prefix)).await?.try_collect().await?;
+        let paths: Vec<_> = paths.into_iter().flatten().collect();
+        assert_eq!(paths.len(), 2);
+
+        for path in &paths {
+            let bytes: Vec<u8> = store
+                .get(path)
+                .await?
+                .map_ok(|bytes| bytes.to_vec())
+                .try_concat()
+                .await?;
+            let entry: AuditEntry = serde_json::from_slice(&bytes)?;
+            assert_eq!(entry.action, "delete_database");
+            assert_eq!(entry.statement, None);
+            assert_eq!(entry.error, None);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+//! This module contains the authorization of requests. Before the HTTP
+//! and gRPC APIs act on a request, they ask the `Authorizer` of the server
+//! whether the principal that sent it may perform the action on the
+//! database. Deployments can plug in their own `Authorizer`, for example
+//! one asking LDAP groups or an OPA policy, without changing the APIs.
+//!
+//! The default authorizer allows everything. `TokenScopes` is an
+//! authorizer that allows the actions each token is scoped to.
+
+use std::{fmt, path::Path};
+
+use async_trait::async_trait;
+use data_types::access_policy::token_sha256;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading token scopes from {:?}: {}", path, source))]
+    ReadingTokenScopes {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error parsing token scopes from {:?}: {}", path, source))]
+    ParsingTokenScopes {
+        path: std::path::PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What a request does to a database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Querying the data or reading the configuration of a database
+    Read,
+    /// Writing data to a database
+    Write,
+    /// Creating, changing, deleting and maintaining databases, and
+    /// configuring the server
+    Admin,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Who sent a request, as far as the server can tell
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Principal<'a> {
+    /// The token the request was sent with, if any
+    pub token: Option<&'a str>,
+}
+
+impl<'a> Principal<'a> {
+    pub fn new(token: Option<&'a str>) -> Self {
+        Self { token }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+/// Decides whether requests are allowed
+#[async_trait]
+pub trait Authorizer: fmt::Debug + Send + Sync {
+    /// Returns whether `principal` may perform `action` on `database`,
+    /// which is `None` for actions on the server itself
+    async fn check(
+        &self,
+        principal: Principal<'_>,
+        action: Action,
+        database: Option<&str>,
+    ) -> Decision;
+}
+
+/// Allows every request, for servers that don't restrict access
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AllowAll;
+
+#[async_trait]
+impl Authorizer for AllowAll {
+    async fn check(&self, _: Principal<'_>, _: Action, _: Option<&str>) -> Decision {
+        Decision::Allow
+    }
+}
+
+/// Allows the actions each token is scoped to, and denies requests sent
+/// without a token
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenScopes {
+    pub scopes: Vec<TokenScope>,
+}
+
+/// The actions a token allows
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenScope {
+    /// The hex encoded SHA-256 of the token, so the scopes don't reveal
+    /// the token
+    pub token_sha256: String,
+
+    /// The actions allowed. `admin` allows every action.
+    pub actions: Vec<Action>,
+
+    /// The databases the actions are allowed on, or all databases and the
+    /// server itself if empty
+    #[serde(default)]
+    pub databases: Vec<String>,
+}
+
+impl TokenScopes {
+    /// Reads the scopes from the JSON file `path`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).context(ReadingTokenScopes { path })?;
+        serde_json::from_slice(&data).context(ParsingTokenScopes { path })
+    }
+}
+
+impl TokenScope {
+    fn allows(&self, action: Action, database: Option<&str>) -> bool {
+        let action_allowed = self
+            .actions
+            .iter()
+            .any(|&allowed| allowed == action || allowed == Action::Admin);
+        let database_allowed = self.databases.is_empty()
+            || database.map_or(false, |database| {
+                self.databases.iter().any(|allowed| allowed == database)
+            });
+        action_allowed && database_allowed
+    }
+}
+
+#[async_trait]
+impl Authorizer for TokenScopes {
+    async fn check(
+        &self,
+        principal: Principal<'_>,
+        action: Action,
+        database: Option<&str>,
+    ) -> Decision {
+        let token_sha256 = match principal.token {
+            Some(token) => token_sha256(token),
+            None => return Decision::Deny,
+        };
+        let allowed = self.scopes.iter().any(|scope| {
+            scope.token_sha256.eq_ignore_ascii_case(&token_sha256) && scope.allows(action, database)
+        });
+
+        if allowed {
+            Decision::Allow
+        } else {
+            Decision::Deny
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn scopes() -> TokenScopes {
+        TokenScopes {
+            scopes: vec![
+                TokenScope {
+                    token_sha256: token_sha256("reader"),
+                    actions: vec![Action::Read],
+                    databases: vec!["mydb".to_string()],
+                },
+                TokenScope {
+                    token_sha256: token_sha256("operator"),
+                    actions: vec![Action::Admin],
+                    databases: vec![],
+                },
+            ],
+        }
+    }
+
+    async fn check(token: Option<&str>, action: Action, database: Option<&str>) -> Decision {
+        scopes()
+            .check(Principal::new(token), action, database)
+            .await
+    }
+
+    #[tokio::test]
+    async fn tokens_are_scoped() {
+        assert_eq!(
+            check(Some("reader"), Action::Read, Some("mydb")).await,
+            Decision::Allow
+        );
+        assert_eq!(
+            check(Some("reader"), Action::Write, Some("mydb")).await,
+            Decision::Deny
+        );
+        assert_eq!(
+            check(Some("reader"), Action::Read, Some("other")).await,
+            Decision::Deny
+        );
+        assert_eq!(
+            check(Some("reader"), Action::Read, None).await,
+            Decision::Deny
+        );
+
+        // admin allows every action, on every database and the server
+        assert_eq!(
+            check(Some("operator"), Action::Write, Some("other")).await,
+            Decision::Allow
+        );
+        assert_eq!(
+            check(Some("operator"), Action::Admin, None).await,
+            Decision::Allow
+        );
+
+        assert_eq!(
+            check(Some("unknown"), Action::Read, Some("mydb")).await,
+            Decision::Deny
+        );
+        assert_eq!(
+            check(None, Action::Read, Some("mydb")).await,
+            Decision::Deny
+        );
+    }
+
+    #[test]
+    fn scopes_are_read_from_files() -> TestResult {
+        let dir = test_helpers::tmp_dir()?;
+        let path = dir.path().join("scopes.json");
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{"scopes": [{{"token_sha256": "{}", "actions": ["read", "write"]}}]}}"#,
+                token_sha256("writer")
+            ),
+        )?;
+
+        let scopes = TokenScopes::from_file(&path)?;
+        assert_eq!(scopes.scopes.len(), 1);
+        assert_eq!(scopes.scopes[0].actions, vec![Action::Read, Action::Write]);
+        assert!(scopes.scopes[0].databases.is_empty());
+
+        std::fs::write(&path, "not json")?;
+        let err = TokenScopes::from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::ParsingTokenScopes { .. }));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,9 @@
 //! This module contains code for managing the WAL buffer
 
+pub mod fan_in;
+pub mod store;
+pub mod stored;
+
 use data_types::{
     data::ReplicatedWrite,
     database_rules::{WalBufferRollover, WriterId},
@@ -68,6 +72,64 @@ pub enum Error {
 
     #[snafu(display("the flatbuffers Segment is invalid"))]
     InvalidFlatbuffersSegment,
+
+    #[snafu(display("the flatbuffers Segment failed verification: {}", source))]
+    UnverifiedFlatbuffersSegment {
+        source: data_types::verification::Error,
+    },
+
+    #[snafu(display(
+        "replicated write {} of the segment failed verification: {}",
+        index,
+        source
+    ))]
+    UnverifiedReplicatedWrite {
+        index: usize,
+        source: data_types::verification::Error,
+    },
+
+    #[snafu(display("the segment contains an invalid replicated write: {}", source))]
+    InvalidReplicatedWrite { source: data_types::data::Error },
+
+    #[snafu(display("unable to list segments: {}", source))]
+    UnableToListSegments { source: object_store::Error },
+
+    #[snafu(display("unable to read segment {}: {}", location, source))]
+    UnableToReadSegment {
+        location: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("unable to decode segment {}: {}", location, source))]
+    UnableToDecodeSegment {
+        location: String,
+        #[snafu(source(from(Error, Box::new)))]
+        source: Box<Error>,
+    },
+
+    #[snafu(display(
+        "unable to replay write {} from writer {}: {}",
+        sequence,
+        writer,
+        source
+    ))]
+    UnableToReplayWrite {
+        writer: WriterId,
+        sequence: u64,
+        source: crate::db::Error,
+    },
+
+    #[snafu(display(
+        "unable to replay deletion {} from writer {}: {}",
+        sequence,
+        writer,
+        source
+    ))]
+    UnableToReplayDelete {
+        writer: WriterId,
+        sequence: u64,
+        source: crate::db::delete::Error,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -163,6 +225,37 @@ impl Buffer {
         Ok(closed_segment)
     }
 
+    /// Closes the open segment if `write` is the last write appended to it,
+    /// returning the segment, so it can be persisted without waiting for
+    /// it to fill up
+    pub fn close_segment_of(&mut self, write: &ReplicatedWrite) -> Option<Arc<Segment>> {
+        let (writer, sequence) = write.writer_and_sequence();
+        let last = self.open_segment.writes.last()?;
+        if !last.equal_to_writer_and_sequence(writer, sequence) {
+            return None;
+        }
+
+        let next_id = self.open_segment.id + 1;
+        let segment = Arc::new(mem::replace(&mut self.open_segment, Segment::new(next_id)));
+        self.closed_segments.push(Arc::clone(&segment));
+        Some(segment)
+    }
+
+    /// Numbers the segments closed from now on after `segment_id`, for
+    /// example after the segments up to it were replayed, so they don't
+    /// overwrite them when persisted. Does nothing once writes were
+    /// appended to the open segment.
+    pub fn start_after_segment(&mut self, segment_id: u64) {
+        if self.open_segment.writes.is_empty() && self.open_segment.id <= segment_id {
+            self.open_segment = Segment::new(segment_id + 1);
+        }
+    }
+
+    /// Returns the closed segments still in the buffer, oldest first
+    pub fn closed_segments(&self) -> &[Arc<Segment>] {
+        &self.closed_segments
+    }
+
     /// Returns the current size of the buffer.
     pub fn size(&self) -> u64 {
         self.current_size
@@ -256,6 +349,22 @@ impl From<&WalBufferConfig> for Buffer {
     }
 }
 
+/// What to do with a replicated write of a segment read from a file that
+/// fails verification
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InvalidWrites {
+    /// Fail to read the segment
+    Fail,
+    /// Skip the write, keeping the valid writes of the segment
+    Skip,
+}
+
+impl Default for InvalidWrites {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
 /// Segment is a collection of replicated writes that can be persisted to
 /// object store.
 #[derive(Debug)]
@@ -263,6 +372,9 @@ pub struct Segment {
     pub(crate) id: u64,
     size: u64,
     pub writes: Vec<Arc<ReplicatedWrite>>,
+    /// The number of writes skipped because they failed verification when
+    /// the segment was read from a file
+    pub skipped_writes: usize,
     writers: BTreeMap<WriterId, WriterSummary>,
     // If set, this is the time at which this segment was persisted
     persisted: Mutex<Option<DateTime<Utc>>>,
@@ -274,6 +386,7 @@ impl Segment {
             id,
             size: 0,
             writes: vec![],
+            skipped_writes: 0,
             writers: BTreeMap::new(),
             persisted: Mutex::new(None),
         }
@@ -284,6 +397,7 @@ impl Segment {
             id,
             size: 0,
             writes: Vec::with_capacity(capacity),
+            skipped_writes: 0,
             writers: BTreeMap::new(),
             persisted: Mutex::new(None),
         }
@@ -340,6 +454,17 @@ impl Segment {
         Ok(())
     }
 
+    /// returns the id of the segment
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// returns the sequence numbers of the writes in the segment, by
+    /// writer
+    pub fn writers(&self) -> &BTreeMap<WriterId, WriterSummary> {
+        &self.writers
+    }
+
     /// sets the time this segment was persisted at
     pub fn set_persisted_at(&self, time: DateTime<Utc>) {
         let mut persisted = self.persisted.lock().expect("mutex poisoned");
@@ -411,8 +536,18 @@ impl Segment {
     }
 
     /// checks the crc32 for the compressed data, decompresses it and
-    /// deserializes it into a Segment struct.
+    /// deserializes it into a Segment struct, failing if any of its
+    /// replicated writes is invalid.
     pub fn from_file_bytes(data: &[u8]) -> Result<Self> {
+        Self::from_file_bytes_with(data, InvalidWrites::Fail)
+    }
+
+    /// Like `from_file_bytes`, but replicated writes that fail
+    /// verification are handled as `invalid_writes` says. The checksum
+    /// only catches corruption after the segment was written, so writes
+    /// that were encoded wrongly still need to be verified before they are
+    /// read.
+    pub fn from_file_bytes_with(data: &[u8], invalid_writes: InvalidWrites) -> Result<Self> {
         if data.len() < std::mem::size_of::<u32>() {
             return Err(Error::InvalidFlatbuffersSegment);
         }
@@ -432,15 +567,36 @@ impl Segment {
             .decompress_vec(data)
             .context(UnableToDecompressData)?;
 
+        data_types::verification::verify_segment(&data).context(UnverifiedFlatbuffersSegment)?;
         let fb_segment = flatbuffers::get_root::<wal::Segment<'_>>(&data);
 
         let writes = fb_segment.writes().context(InvalidFlatbuffersSegment)?;
         let mut segment = Self::new_with_capacity(fb_segment.id(), writes.len());
-        for w in writes {
+        for (index, w) in writes.iter().enumerate() {
             let data = w.payload().context(InvalidFlatbuffersSegment)?;
             let rw = ReplicatedWrite {
                 data: data.to_vec(),
             };
+
+            if let Err(source) = rw.verify() {
+                match invalid_writes {
+                    InvalidWrites::Fail => {
+                        return Err(Error::UnverifiedReplicatedWrite { index, source })
+                    }
+                    InvalidWrites::Skip => {
+                        warn!(
+                            "skipping replicated write {} of segment {}: {}",
+                            index,
+                            fb_segment.id(),
+                            source
+                        );
+                        segment.skipped_writes += 1;
+                        continue;
+                    }
+                }
+            }
+
+            rw.check_version().context(InvalidReplicatedWrite)?;
             segment.append(Arc::new(rw))?;
         }
 
@@ -449,11 +605,11 @@ impl Segment {
 }
 
 /// The summary information for a writer that has data in a segment
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct WriterSummary {
-    start_sequence: u64,
-    end_sequence: u64,
-    missing_sequence: bool,
+    pub start_sequence: u64,
+    pub end_sequence: u64,
+    pub missing_sequence: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -462,7 +618,7 @@ pub struct WriterSequence {
     pub sequence: u64,
 }
 
-const WAL_DIR: &str = "wal";
+pub(crate) const WAL_DIR: &str = "wal";
 const MAX_SEGMENT_ID: u64 = 999_999_999;
 const SEGMENT_FILE_EXTENSION: &str = ".segment";
 
@@ -570,6 +726,22 @@ mod tests {
         assert_eq!(3, buf.closed_segments[1].id);
     }
 
+    #[test]
+    fn numbers_segments_after_replayed_ones() {
+        let mut buf = Buffer::new(u64::MAX, 0, WalBufferRollover::ReturnError, false);
+        buf.start_after_segment(41);
+
+        let write = lp_to_replicated_write(1, 1, "cpu val=1 10");
+        let segment = buf.append(write).unwrap().unwrap();
+        assert_eq!(42, segment.id);
+
+        // segments already closed keep their numbering
+        buf.start_after_segment(7);
+        let write = lp_to_replicated_write(1, 2, "cpu val=1 10");
+        let segment = buf.append(write).unwrap().unwrap();
+        assert_eq!(43, segment.id);
+    }
+
     #[test]
     fn drops_old_segment_even_if_not_persisted() {
         let max = 600;
@@ -858,6 +1030,37 @@ mod tests {
         assert_eq!(segment.writes, recovered_segment.writes);
     }
 
+    #[test]
+    fn invalid_writes_fail_or_are_skipped() {
+        let mut segment = Segment::new(1);
+        let writer_id = 2;
+        segment
+            .append(lp_to_replicated_write(writer_id, 0, "foo val=1 123"))
+            .unwrap();
+        // the root table offset points past the end of the write
+        segment.writes.push(Arc::new(ReplicatedWrite {
+            data: vec![0xff, 0, 0, 0],
+        }));
+        segment
+            .append(lp_to_replicated_write(writer_id, 1, "foo val=2 124"))
+            .unwrap();
+        let data = segment.to_file_bytes(writer_id).unwrap();
+
+        let err = Segment::from_file_bytes(&data).unwrap_err();
+        assert!(
+            matches!(err, Error::UnverifiedReplicatedWrite { index: 1, .. }),
+            "{}",
+            err
+        );
+
+        let recovered = Segment::from_file_bytes_with(&data, InvalidWrites::Skip).unwrap();
+        assert_eq!(recovered.skipped_writes, 1);
+        assert_eq!(
+            recovered.writes,
+            vec![segment.writes[0].clone(), segment.writes[2].clone()]
+        );
+    }
+
     fn lp_to_replicated_write(
         writer_id: u32,
         sequence_number: u64,
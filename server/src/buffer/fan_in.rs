@@ -0,0 +1,708 @@
+//! This module contains code for recovering a single database from the
+//! WALs of several ingesters, for example after the number of ingest
+//! nodes was scaled down and the data they buffered has to be served by
+//! the remaining ones.
+//!
+//! Each WAL is replayed in order into the mutable buffer of the
+//! database, where the writes of every WAL end up in the same partitions.
+//! Writes are identified by their writer and sequence number, and the
+//! database records those of every write it stored, so a write that was
+//! replicated to more than one of the ingesters, or was already stored,
+//! is only applied once. Deletions logged in the WALs are applied in
+//! order with the writes.
+
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use data_types::database_rules::WriterId;
+use futures::{stream, StreamExt};
+use object_store::{path::ObjectStorePath, ObjectStore};
+use query::Database;
+use serde::Serialize;
+use snafu::ResultExt;
+use tokio::sync::mpsc;
+
+use super::{
+    stored::{read_segment, segment_paths, StoredSegment},
+    InvalidWrites, Result, Segment, UnableToDecodeSegment, UnableToReplayDelete,
+    UnableToReplayWrite,
+};
+use crate::db::Db;
+
+/// The number of segments of a stored WAL read ahead of its replay
+pub const SEGMENTS_READ_AHEAD: usize = 4;
+
+/// What was replayed into the database
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct FanInSummary {
+    /// The number of WALs replayed
+    pub wals: usize,
+    /// The number of segments replayed
+    pub segments: usize,
+    /// The number of replicated writes applied to the database
+    pub writes: usize,
+    /// The number of replicated writes not applied because the database
+    /// already stored a write with the same writer and sequence number
+    pub duplicate_writes: usize,
+    /// The number of replicated writes the segments skipped because they
+    /// failed verification
+    pub skipped_writes: usize,
+    /// The largest id of the segments replayed
+    pub last_segment_id: Option<u64>,
+    /// The time spent reading and decoding stored segments, in
+    /// milliseconds. Segments are read ahead of their replay, so this
+    /// overlaps with `apply_ms`.
+    pub read_ms: u64,
+    /// The time the replay waited for stored segments to be read and
+    /// decoded, in milliseconds
+    pub read_wait_ms: u64,
+    /// The time spent applying segments to the database, in milliseconds
+    pub apply_ms: u64,
+}
+
+/// The writes a database stored, by writer, so a write replayed from a
+/// WAL is only stored once. The sequence numbers of a writer are mostly
+/// consecutive, so they are kept as ranges.
+#[derive(Debug, Default)]
+pub(crate) struct StoredWrites(Mutex<BTreeMap<WriterId, BTreeMap<u64, u64>>>);
+
+impl StoredWrites {
+    /// Returns true if the write `sequence` of `writer` was recorded
+    pub(crate) fn contains(&self, writer: WriterId, sequence: u64) -> bool {
+        let stored = self.0.lock().expect("mutex poisoned");
+        stored
+            .get(&writer)
+            .and_then(|ranges| ranges.range(..=sequence).next_back())
+            .map_or(false, |(_, end)| sequence <= *end)
+    }
+
+    /// Records the write `sequence` of `writer`
+    pub(crate) fn insert(&self, writer: WriterId, sequence: u64) {
+        let mut stored = self.0.lock().expect("mutex poisoned");
+        let ranges = stored.entry(writer).or_default();
+
+        let mut start = sequence;
+        if let Some((previous_start, previous_end)) = ranges.range(..=sequence).next_back() {
+            if sequence <= *previous_end {
+                return;
+            }
+            if *previous_end + 1 == sequence {
+                start = *previous_start;
+            }
+        }
+        let end = sequence
+            .checked_add(1)
+            .and_then(|next| ranges.remove(&next))
+            .unwrap_or(sequence);
+        ranges.insert(start, end);
+    }
+}
+
+/// Replays the WALs of several ingesters into one database
+#[derive(Debug)]
+pub struct FanIn<'a> {
+    db: &'a Db,
+    writer_id: WriterId,
+    summary: FanInSummary,
+}
+
+impl<'a> FanIn<'a> {
+    /// Replays WALs into `db`, which is written to by the writer
+    /// `writer_id`, the id of this server
+    pub fn new(db: &'a Db, writer_id: WriterId) -> Self {
+        Self {
+            db,
+            writer_id,
+            summary: FanInSummary::default(),
+        }
+    }
+
+    /// Replays the segments of one WAL, which must be given in the order
+    /// they were written
+    pub async fn add_wal(&mut self, segments: impl IntoIterator<Item = Segment>) -> Result<()> {
+        for segment in segments {
+            self.apply_segment(&segment).await?;
+        }
+        self.summary.wals += 1;
+
+        Ok(())
+    }
+
+    /// Replays the WAL whose segments are stored under `prefix`. Fails if
+    /// one of the segments can't be decoded, as the writes after it
+    /// would be replayed without the writes it contains.
+    ///
+    /// The segments are read and decoded ahead of the replay, which only
+    /// waits for them once it has applied the segments before. At most
+    /// `SEGMENTS_READ_AHEAD` segments are read concurrently and as many
+    /// decoded segments are buffered, so a replay that falls behind
+    /// holds up the reads rather than filling memory. How long the reads
+    /// and the replay took, and how long the replay waited for the reads,
+    /// is added to the summary.
+    pub async fn add_stored_wal(
+        &mut self,
+        store: &ObjectStore,
+        prefix: &ObjectStorePath,
+        invalid_writes: InvalidWrites,
+    ) -> Result<()> {
+        let paths = segment_paths(store, prefix).await?;
+        let (tx, rx) = mpsc::channel(SEGMENTS_READ_AHEAD);
+
+        let ((), result) = futures::join!(
+            read_segments(store, paths, invalid_writes, tx),
+            self.replay_segments(rx)
+        );
+        result?;
+        self.summary.wals += 1;
+
+        Ok(())
+    }
+
+    /// Replays the segments received from `segments` until the sender is
+    /// dropped. The receiver is dropped on the first error, so the sender
+    /// stops reading segments.
+    async fn replay_segments(
+        &mut self,
+        mut segments: mpsc::Receiver<(Result<StoredSegment>, Duration)>,
+    ) -> Result<()> {
+        loop {
+            let waiting = Instant::now();
+            let (segment, read) = match segments.recv().await {
+                Some(segment) => segment,
+                None => return Ok(()),
+            };
+            self.summary.read_wait_ms += elapsed_ms(waiting);
+            self.summary.read_ms += read.as_millis() as u64;
+
+            let StoredSegment { location, segment } = segment?;
+            let segment = segment.context(UnableToDecodeSegment { location })?;
+            self.apply_segment(&segment).await?;
+        }
+    }
+
+    /// Returns what was replayed into the database
+    pub fn finish(self) -> FanInSummary {
+        self.summary
+    }
+
+    /// Applies `segment` to the database, adding the time it took to the
+    /// summary
+    async fn apply_segment(&mut self, segment: &Segment) -> Result<()> {
+        let applying = Instant::now();
+        let applied = self.add_segment(segment).await;
+        self.summary.apply_ms += elapsed_ms(applying);
+        applied
+    }
+
+    async fn add_segment(&mut self, segment: &Segment) -> Result<()> {
+        for write in &segment.writes {
+            let (writer, sequence) = write.writer_and_sequence();
+            if self.db.write_stored(writer, sequence) {
+                self.summary.duplicate_writes += 1;
+                continue;
+            }
+
+            if write.deletes().is_empty() {
+                self.db
+                    .store_replicated_write(write)
+                    .await
+                    .context(UnableToReplayWrite { writer, sequence })?;
+                self.db.record_stored_write(write).await;
+            } else {
+                self.db
+                    .apply_delete_write(write)
+                    .await
+                    .context(UnableToReplayDelete { writer, sequence })?;
+            }
+
+            // writes of this server made after the recovery must not reuse
+            // the sequence numbers of its writes replayed; those of other
+            // writers are numbered independently
+            if writer == self.writer_id {
+                self.db.advance_sequence(sequence + 1);
+            }
+            self.summary.writes += 1;
+        }
+
+        self.summary.segments += 1;
+        self.summary.skipped_writes += segment.skipped_writes;
+        self.summary.last_segment_id = self.summary.last_segment_id.max(Some(segment.id));
+
+        Ok(())
+    }
+}
+
+/// Reads and decodes the segments at `paths` in order, sending them and
+/// how long each took to read to `segments` until one of them can't be
+/// read or the receiver is dropped
+async fn read_segments(
+    store: &ObjectStore,
+    paths: Vec<(String, ObjectStorePath)>,
+    invalid_writes: InvalidWrites,
+    mut segments: mpsc::Sender<(Result<StoredSegment>, Duration)>,
+) {
+    let mut read = stream::iter(paths)
+        .map(move |(location, path)| async move {
+            let reading = Instant::now();
+            let segment = read_segment(store, location, &path, invalid_writes).await;
+            (segment, reading.elapsed())
+        })
+        .buffered(SEGMENTS_READ_AHEAD);
+
+    while let Some((segment, read)) = read.next().await {
+        let failed = segment.is_err();
+        if segments.send((segment, read)).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{object_store_path_for_segment, Buffer};
+    use super::*;
+    use crate::db::delete::{SeriesTombstone, TagMatcher};
+    use arrow_deps::{
+        arrow::record_batch::RecordBatch, assert_table_eq, datafusion::physical_plan::collect,
+    };
+    use data_types::{
+        data::{lines_to_replicated_write, ReplicatedWrite},
+        database_rules::{
+            DatabaseRules, PartitionTemplate, TemplatePart, WalBufferConfig, WalBufferRollover,
+            WalSegmentStorage,
+        },
+    };
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use object_store::memory::InMemory;
+    use query::{exec::Executor, frontend::sql::SQLQueryPlanner};
+    use read_buffer::Database as ReadBufferDb;
+    use std::sync::Arc;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn rules() -> DatabaseRules {
+        DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn make_db() -> Db {
+        Db::new(
+            rules(),
+            Some(MutableBufferDb::new("fan_in")),
+            ReadBufferDb::new(),
+            None,
+        )
+    }
+
+    /// Returns the segments of a WAL holding `writes`, each a writer,
+    /// sequence number and line protocol, one segment per write
+    fn wal(writes: &[(WriterId, u64, &str)]) -> TestResult<Vec<Segment>> {
+        // a segment size of zero closes the segment on every append
+        let mut buffer = Buffer::new(u64::MAX, 0, WalBufferRollover::ReturnError, false);
+
+        let mut segments = Vec::with_capacity(writes.len());
+        for (writer, sequence, lp) in writes {
+            let write = replicated_write(*writer, *sequence, lp);
+            let segment = buffer.append(Arc::new(write))?.expect("segment closed");
+            segments.push(Segment::from_file_bytes(&segment.to_file_bytes(*writer)?)?);
+        }
+
+        Ok(segments)
+    }
+
+    fn replicated_write(writer: WriterId, sequence: u64, lp: &str) -> ReplicatedWrite {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(writer, sequence, &lines, &rules())
+    }
+
+    async fn run_query(db: &Db, query: &str) -> Vec<RecordBatch> {
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let physical_plan = planner.query(db, query, &executor).await.unwrap();
+        collect(physical_plan).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn merges_wals_without_duplicates() -> TestResult {
+        let db = make_db();
+        let first = wal(&[
+            (1, 1, "cpu,host=a usage=1 10"),
+            (1, 2, "cpu,host=a usage=2 20\nmem,host=a free=1i 20"),
+        ])?;
+        // the second ingester also received the first write of writer 1
+        let second = wal(&[
+            (1, 1, "cpu,host=a usage=1 10"),
+            (2, 1, "cpu,host=b usage=3 30"),
+        ])?;
+
+        let mut fan_in = FanIn::new(&db, 1);
+        fan_in.add_wal(first).await?;
+        fan_in.add_wal(second).await?;
+        let summary = fan_in.finish();
+        assert_eq!(
+            summary,
+            FanInSummary {
+                wals: 2,
+                segments: 4,
+                writes: 3,
+                duplicate_writes: 1,
+                skipped_writes: 0,
+                last_segment_id: Some(2),
+                // segments given directly aren't read
+                read_ms: 0,
+                read_wait_ms: 0,
+                ..summary
+            }
+        );
+
+        let batches = run_query(&db, "select host, usage, time from cpu order by time").await;
+        let expected = vec![
+            "+------+-------+------+",
+            "| host | usage | time |",
+            "+------+-------+------+",
+            "| a    | 1     | 10   |",
+            "| a    | 2     | 20   |",
+            "| b    | 3     | 30   |",
+            "+------+-------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        assert_eq!(db.partition_keys().await?, vec!["cpu", "mem"]);
+
+        // new writes are sequenced after the replayed ones of this writer
+        assert_eq!(db.next_sequence(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn skips_writes_already_stored() -> TestResult {
+        let db = make_db();
+        let replayed = || {
+            wal(&[
+                (1, 1, "cpu,host=a usage=1 10"),
+                (2, 7, "cpu,host=b usage=2 20"),
+            ])
+        };
+
+        let mut fan_in = FanIn::new(&db, 1);
+        fan_in.add_wal(replayed()?).await?;
+        assert_eq!(fan_in.finish().writes, 2);
+
+        // writes replayed before, or stored since, aren't stored again
+        let write = replicated_write(1, 2, "cpu,host=a usage=3 30");
+        db.store_replicated_write(&write).await?;
+        db.record_stored_write(&write).await;
+        let mut fan_in = FanIn::new(&db, 1);
+        fan_in.add_wal(replayed()?).await?;
+        fan_in
+            .add_wal(wal(&[(1, 2, "cpu,host=a usage=3 30")])?)
+            .await?;
+        let summary = fan_in.finish();
+        assert_eq!(summary.writes, 0);
+        assert_eq!(summary.duplicate_writes, 3);
+
+        let batches = run_query(&db, "select count(*) from cpu").await;
+        let expected = vec![
+            "+-----------------+",
+            "| COUNT(UInt8(1)) |",
+            "+-----------------+",
+            "| 3               |",
+            "+-----------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // the sequence numbers of other writers aren't taken
+        assert_eq!(db.next_sequence(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replays_deletions_in_order() -> TestResult {
+        let db = make_db();
+        let tombstone = SeriesTombstone {
+            table: "cpu".to_string(),
+            tag_matchers: vec![TagMatcher::new("host", "a")],
+        };
+        let writes = vec![
+            replicated_write(1, 1, "cpu,host=a usage=1 10"),
+            tombstone.to_replicated_write(1, 2)?,
+            replicated_write(1, 3, "cpu,host=a usage=3 30"),
+        ];
+
+        // the deletion goes through a persisted segment like the writes
+        let mut buffer = Buffer::new(u64::MAX, 0, WalBufferRollover::ReturnError, false);
+        let mut segments = Vec::with_capacity(writes.len());
+        for write in writes {
+            let segment = buffer.append(Arc::new(write))?.expect("segment closed");
+            segments.push(Segment::from_file_bytes(&segment.to_file_bytes(1)?)?);
+        }
+
+        let mut fan_in = FanIn::new(&db, 1);
+        fan_in.add_wal(segments).await?;
+        assert_eq!(fan_in.finish().writes, 3);
+
+        // only the point written after the deletion is left
+        let batches = run_query(&db, "select usage from cpu").await;
+        let expected = vec![
+            "+-------+",
+            "| usage |",
+            "+-------+",
+            "| 3     |",
+            "+-------+",
+        ];
+        assert_table_eq!(expected, &batches);
+        assert!(db.write_stored(1, 2));
+        assert_eq!(db.next_sequence(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stored_writes_merge_ranges() {
+        let stored = StoredWrites::default();
+        for sequence in &[1, 2, 5, 4, 3, 9] {
+            stored.insert(1, *sequence);
+        }
+        stored.insert(2, 6);
+
+        let ranges = stored.0.lock().unwrap();
+        assert_eq!(
+            ranges[&1].iter().map(|(s, e)| (*s, *e)).collect::<Vec<_>>(),
+            vec![(1, 5), (9, 9)]
+        );
+        drop(ranges);
+        assert!(stored.contains(1, 4));
+        assert!(!stored.contains(1, 6));
+        assert!(stored.contains(2, 6));
+        assert!(!stored.contains(2, 5));
+    }
+
+    #[tokio::test]
+    async fn replays_stored_wals() -> TestResult {
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let wals = [
+            ("ingester_1", wal(&[(1, 1, "cpu,host=a usage=1 10")])?),
+            (
+                "ingester_2",
+                wal(&[
+                    (1, 1, "cpu,host=a usage=1 10"),
+                    (2, 1, "cpu,host=b usage=2 20"),
+                ])?,
+            ),
+        ];
+
+        let mut prefixes = Vec::new();
+        for (name, segments) in &wals {
+            let mut prefix = ObjectStorePath::default();
+            prefix.push_all_dirs(&[*name, "mydb"]);
+            for segment in segments {
+                let (writer, _) = segment.writes[0].writer_and_sequence();
+                let data = segment.to_file_bytes(writer)?;
+                let len = data.len();
+                let location = object_store_path_for_segment(&prefix, segment.id)?;
+                store
+                    .put(
+                        &location,
+                        futures::stream::once(async move { Ok(data) }),
+                        len,
+                    )
+                    .await?;
+            }
+            prefixes.push(prefix);
+        }
+
+        let db = make_db();
+        let mut fan_in = FanIn::new(&db, 1);
+        for prefix in &prefixes {
+            fan_in
+                .add_stored_wal(&store, prefix, InvalidWrites::Fail)
+                .await?;
+        }
+        let summary = fan_in.finish();
+        assert_eq!(summary.wals, 2);
+        assert_eq!(summary.segments, 3);
+        assert_eq!(summary.writes, 2);
+        assert_eq!(summary.duplicate_writes, 1);
+
+        let batches = run_query(&db, "select host, usage from cpu order by host").await;
+        let expected = vec![
+            "+------+-------+",
+            "| host | usage |",
+            "+------+-------+",
+            "| a    | 1     |",
+            "| b    | 2     |",
+            "+------+-------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replays_stored_wals_longer_than_the_read_ahead() -> TestResult {
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let lines: Vec<_> = (1..=3 * SEGMENTS_READ_AHEAD as u64)
+            .map(|i| format!("cpu,host=a usage={} {}", i, i))
+            .collect();
+        let writes: Vec<_> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, lp)| (1, i as u64 + 1, lp.as_str()))
+            .collect();
+
+        let mut prefix = ObjectStorePath::default();
+        prefix.push_all_dirs(&["ingester_1", "mydb"]);
+        let segments = wal(&writes)?;
+        for segment in &segments {
+            let data = segment.to_file_bytes(1)?;
+            let len = data.len();
+            let location = object_store_path_for_segment(&prefix, segment.id)?;
+            store
+                .put(
+                    &location,
+                    futures::stream::once(async move { Ok(data) }),
+                    len,
+                )
+                .await?;
+        }
+
+        // a segment that can't be decoded stops the replay after the
+        // segments before it were applied
+        let data = bytes::Bytes::from("not a segment");
+        let len = data.len();
+        let location = object_store_path_for_segment(&prefix, segments.len() as u64 + 1)?;
+        store
+            .put(
+                &location,
+                futures::stream::once(async move { Ok(data) }),
+                len,
+            )
+            .await?;
+
+        let db = make_db();
+        let mut fan_in = FanIn::new(&db, 1);
+        let err = fan_in
+            .add_stored_wal(&store, &prefix, InvalidWrites::Fail)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            super::super::Error::UnableToDecodeSegment { .. }
+        ));
+
+        let summary = fan_in.finish();
+        assert_eq!(summary.wals, 0);
+        assert_eq!(summary.segments, segments.len());
+        assert_eq!(summary.writes, segments.len());
+
+        let batches = run_query(&db, "select count(*), max(usage) from cpu").await;
+        let expected = vec![
+            "+-----------------+------------+",
+            "| COUNT(UInt8(1)) | MAX(usage) |",
+            "+-----------------+------------+",
+            "| 12              | 12         |",
+            "+-----------------+------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+        assert_eq!(db.next_sequence(), segments.len() as u64 + 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restores_partitions_skipping_invalid_writes() -> TestResult {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let mut root = ObjectStorePath::default();
+        root.push_all_dirs(&["1", "mydb"]);
+
+        let mut segment = Segment::new(1);
+        segment.append(Arc::new(replicated_write(1, 1, "cpu,host=a usage=1 10")))?;
+        // the root table offset points past the end of the write
+        segment.writes.push(Arc::new(ReplicatedWrite {
+            data: vec![0xff, 0, 0, 0],
+        }));
+        segment.append(Arc::new(replicated_write(1, 2, "cpu,host=b usage=2 20")))?;
+        let data = segment.to_file_bytes(1)?;
+        let len = data.len();
+        store
+            .put(
+                &object_store_path_for_segment(&root, segment.id)?,
+                futures::stream::once(async move { Ok(data) }),
+                len,
+            )
+            .await?;
+
+        let restore = |skip_invalid_writes| {
+            let rules = DatabaseRules {
+                wal_buffer_config: Some(WalBufferConfig {
+                    buffer_size: 1_000_000,
+                    segment_size: 0,
+                    buffer_rollover: WalBufferRollover::ReturnError,
+                    store_segments: true,
+                    close_segment_after: None,
+                    segment_storage: WalSegmentStorage::ObjectStore,
+                    skip_invalid_writes,
+                }),
+                ..rules()
+            };
+            let buffer = Buffer::new(1_000_000, 0, WalBufferRollover::ReturnError, true);
+            Db::new(
+                rules,
+                Some(MutableBufferDb::new("fan_in")),
+                ReadBufferDb::new(),
+                Some(buffer),
+            )
+            .with_object_store(Arc::clone(&store), root.clone())
+        };
+
+        // by default an invalid write fails the replay
+        let db = restore(false);
+        let err = db.restore_partitions_from_wal(1).await.unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::UnableToDecodeSegment { .. }),
+            "{}",
+            err
+        );
+
+        let db = restore(true);
+        let summary = db.restore_partitions_from_wal(1).await?;
+        assert_eq!(summary.writes, 2);
+        assert_eq!(summary.skipped_writes, 1);
+        assert_eq!(summary.last_segment_id, Some(1));
+
+        let batches = run_query(&db, "select host, usage from cpu order by host").await;
+        let expected = vec![
+            "+------+-------+",
+            "| host | usage |",
+            "+------+-------+",
+            "| a    | 1     |",
+            "| b    | 2     |",
+            "+------+-------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // the next segment doesn't overwrite the replayed one
+        let write = Arc::new(replicated_write(1, 3, "cpu,host=c usage=3 30"));
+        let wal_buffer = db.wal_buffer.as_ref().expect("wal buffer");
+        let closed = wal_buffer.lock().expect("mutex poisoned").append(write)?;
+        assert_eq!(closed.expect("segment closed").id, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! This module contains the backends closed WAL segments are written to.
+//! A database writes its segments to the object store of the server
+//! unless its `WalBufferConfig` names another backend registered with the
+//! server, so ingesters can keep their WAL on local disk, in per-segment
+//! objects of another store or in an external log service.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use data_types::DatabaseName;
+use object_store::{path::file::FileConverter, ObjectStore};
+
+use super::object_store_path_for_segment;
+use crate::{database_object_store_path, db::Db, latency::escape_label_value};
+
+/// The error a backend failed to store a segment with
+pub type StoreError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// A backend durably storing the closed WAL segments of databases
+#[async_trait]
+pub trait SegmentStore: std::fmt::Debug + Send + Sync {
+    /// Stores `data`, the file bytes of the segment `segment_id` of the
+    /// database `db_name` on the server `writer_id`. The segment counts as
+    /// persisted once this returns successfully; failed writes are retried.
+    async fn store_segment(
+        &self,
+        writer_id: u32,
+        db_name: &DatabaseName<'_>,
+        segment_id: u64,
+        data: Bytes,
+    ) -> Result<(), StoreError>;
+}
+
+/// Writes each segment as an object of an object store, under the
+/// directory of its database
+#[derive(Debug)]
+pub struct ObjectStoreSegments {
+    store: Arc<ObjectStore>,
+}
+
+impl ObjectStoreSegments {
+    pub fn new(store: Arc<ObjectStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl SegmentStore for ObjectStoreSegments {
+    async fn store_segment(
+        &self,
+        writer_id: u32,
+        db_name: &DatabaseName<'_>,
+        segment_id: u64,
+        data: Bytes,
+    ) -> Result<(), StoreError> {
+        let location = database_object_store_path(writer_id, db_name);
+        let location = object_store_path_for_segment(&location, segment_id)?;
+        let len = data.len();
+
+        self.store
+            .put(
+                &location,
+                futures::stream::once(async move { std::io::Result::Ok(data) }),
+                len,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Writes each segment as a file in a directory of the local file system,
+/// laid out like the segments in an object store
+#[derive(Debug)]
+pub struct FileSegments {
+    root: PathBuf,
+}
+
+impl FileSegments {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl SegmentStore for FileSegments {
+    async fn store_segment(
+        &self,
+        writer_id: u32,
+        db_name: &DatabaseName<'_>,
+        segment_id: u64,
+        data: Bytes,
+    ) -> Result<(), StoreError> {
+        let location = database_object_store_path(writer_id, db_name);
+        let location = object_store_path_for_segment(&location, segment_id)?;
+        let path = self.root.join(FileConverter::convert(&location));
+
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, data).await?;
+
+        Ok(())
+    }
+}
+
+/// Returns the bytes free for unprivileged users on the file system of the
+/// directory `dir`, or of its closest ancestor that exists, as directories
+/// are only created when the first segment is stored in them
+pub fn available_space(dir: &Path) -> std::io::Result<u64> {
+    let existing = dir
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or_else(|| Path::new("."));
+    fs2::available_space(existing)
+}
+
+/// The free space of the WAL directory of a database, for databases
+/// writing their segments to a directory of the local file system
+#[derive(Debug, Default)]
+pub struct WalMetrics {
+    /// The bytes free on the file system of the directory when writes
+    /// last checked
+    pub available_bytes: AtomicU64,
+    /// The number of writes rejected because the file system was nearly
+    /// full
+    pub disk_full_rejections: AtomicU64,
+    /// When the free space was last read from the file system
+    checked_at: Mutex<Option<Instant>>,
+}
+
+impl WalMetrics {
+    /// Returns the bytes free on the file system of the directory `dir`,
+    /// reading them from the file system only if they were last read more
+    /// than `max_age` ago, so writes don't each pay for the system call
+    pub fn available_space(&self, dir: &Path, max_age: Duration) -> std::io::Result<u64> {
+        let mut checked_at = self.checked_at.lock().expect("mutex poisoned");
+        if let Some(checked_at) = *checked_at {
+            if checked_at.elapsed() < max_age {
+                return Ok(self.available_bytes.load(Ordering::Relaxed));
+            }
+        }
+
+        let available = available_space(dir)?;
+        self.available_bytes.store(available, Ordering::Relaxed);
+        *checked_at = Some(Instant::now());
+        Ok(available)
+    }
+}
+
+/// Renders the free space of the WAL directory of each of the databases
+/// `dbs` writing their segments to one, and the writes rejected for the
+/// lack of it, in the Prometheus text format
+pub(crate) fn render(dbs: &[(DatabaseName<'static>, Arc<Db>)]) -> String {
+    let available = "iox_wal_available_bytes";
+    let rejections = "iox_wal_disk_full_rejections_total";
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# HELP {} Bytes free on the file system of the WAL directory of the database",
+        available
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {} gauge", available).unwrap();
+    writeln!(
+        out,
+        "# HELP {} Writes rejected because the WAL directory of the database was nearly full",
+        rejections
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {} counter", rejections).unwrap();
+
+    for (db_name, db) in dbs {
+        if db.wal_directory().is_none() {
+            continue;
+        }
+        let metrics = db.wal_metrics();
+        let db_name = escape_label_value(db_name);
+        writeln!(
+            out,
+            "{}{{db_name=\"{}\"}} {}",
+            available,
+            db_name,
+            metrics.available_bytes.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{}{{db_name=\"{}\"}} {}",
+            rejections,
+            db_name,
+            metrics.disk_full_rejections.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn file_segments_are_laid_out_like_objects() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let segments = FileSegments::new(dir.path());
+        let db_name = DatabaseName::new("my_db")?;
+
+        segments
+            .store_segment(1, &db_name, 1_002_003, Bytes::from("segment"))
+            .await?;
+
+        let path = dir.path().join("1/my_db/wal/001/002/003.segment");
+        assert_eq!(std::fs::read(path)?, b"segment");
+
+        Ok(())
+    }
+
+    #[test]
+    fn available_space_is_read_again_once_stale() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let metrics = WalMetrics::default();
+
+        let available = metrics.available_space(dir.path(), Duration::from_secs(3600))?;
+        assert!(available > 0);
+
+        // a recent reading is returned without reading the file system
+        metrics.available_bytes.store(42, Ordering::Relaxed);
+        assert_eq!(
+            metrics.available_space(dir.path(), Duration::from_secs(3600))?,
+            42
+        );
+        assert!(metrics.available_space(dir.path(), Duration::from_secs(0))? > 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn available_space_of_missing_directories() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        assert!(available_space(dir.path())? > 0);
+
+        // the space of the closest directory that exists is returned
+        let missing = dir.path().join("wal/my_db");
+        assert!(available_space(&missing)? > 0);
+
+        Ok(())
+    }
+}
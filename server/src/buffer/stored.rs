@@ -0,0 +1,164 @@
+//! This module contains code for reading the WAL buffer segments that
+//! were persisted to object storage, one segment at a time, for replaying
+//! them and for tools that inspect them.
+
+use futures::stream::TryStreamExt;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use snafu::ResultExt;
+
+use super::{
+    InvalidWrites, Result, Segment, UnableToListSegments, UnableToReadSegment,
+    SEGMENT_FILE_EXTENSION,
+};
+
+/// A segment found in object storage
+#[derive(Debug)]
+pub struct StoredSegment {
+    /// Where the segment is stored
+    pub location: String,
+    /// The decoded segment, or why it could not be decoded
+    pub segment: Result<Segment>,
+}
+
+/// Lists the segments stored under `prefix`, in order of their location
+pub async fn segment_paths(
+    store: &ObjectStore,
+    prefix: &ObjectStorePath,
+) -> Result<Vec<(String, ObjectStorePath)>> {
+    let paths: Vec<Vec<_>> = store
+        .list(Some(prefix))
+        .await
+        .context(UnableToListSegments)?
+        .try_collect()
+        .await
+        .context(UnableToListSegments)?;
+
+    let mut paths: Vec<_> = paths
+        .into_iter()
+        .flatten()
+        .map(|path| (store.convert_path(&path), path))
+        .filter(|(location, _)| location.ends_with(SEGMENT_FILE_EXTENSION))
+        .collect();
+    paths.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(paths)
+}
+
+/// Reads the segment stored at `path` and decodes it on a blocking
+/// thread, so decoding doesn't hold up other tasks such as the reads of
+/// the next segments
+pub async fn read_segment(
+    store: &ObjectStore,
+    location: String,
+    path: &ObjectStorePath,
+    invalid_writes: InvalidWrites,
+) -> Result<StoredSegment> {
+    let data = store
+        .get(path)
+        .await
+        .context(UnableToReadSegment {
+            location: &location,
+        })?
+        .map_ok(|b| bytes::BytesMut::from(&b[..]))
+        .try_concat()
+        .await
+        .context(UnableToReadSegment {
+            location: &location,
+        })?;
+
+    let segment =
+        tokio::task::spawn_blocking(move || Segment::from_file_bytes_with(&data, invalid_writes))
+            .await
+            .expect("segment decoding panicked");
+
+    Ok(StoredSegment { location, segment })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{object_store_path_for_segment, Buffer};
+    use super::*;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{DatabaseRules, PartitionTemplate, TemplatePart, WalBufferRollover},
+    };
+    use influxdb_line_protocol::parse_lines;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn reads_stored_segments_in_order() -> TestResult {
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut root = ObjectStorePath::default();
+        root.push_all_dirs(&["1", "mydb"]);
+
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // a segment size of zero closes the segment on every append
+        let mut buffer = Buffer::new(u64::MAX, 0, WalBufferRollover::ReturnError, false);
+        let writes = [
+            "cpu,host=a usage=0.5 10\ncpu,host=b usage=0.7 30",
+            "cpu,host=a usage=0.9 20\nmem,host=a free=10i,ok=true,msg=\"a \\\"b\\\"\" 20",
+        ];
+        for (sequence, lp) in writes.iter().enumerate() {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let write = lines_to_replicated_write(1, sequence as u64 + 1, &lines, &rules);
+            let segment = buffer.append(Arc::new(write))?.expect("segment closed");
+
+            let data = segment.to_file_bytes(1)?;
+            let len = data.len();
+            let location = object_store_path_for_segment(&root, segment.id)?;
+            store
+                .put(
+                    &location,
+                    futures::stream::once(async move { Ok(data) }),
+                    len,
+                )
+                .await?;
+        }
+
+        // something that isn't a segment is skipped
+        let mut other = root.clone();
+        other.set_file_name("rules.json");
+        store
+            .put(
+                &other,
+                futures::stream::once(async { Ok(bytes::Bytes::from("{}")) }),
+                2,
+            )
+            .await?;
+
+        let paths = segment_paths(&store, &root).await?;
+        let locations: Vec<_> = paths
+            .iter()
+            .map(|(location, _)| location.as_str())
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                "1/mydb/wal/000/000/001.segment",
+                "1/mydb/wal/000/000/002.segment"
+            ]
+        );
+
+        let mut ids = Vec::new();
+        for (location, path) in paths {
+            let stored = read_segment(&store, location, &path, InvalidWrites::Fail).await?;
+            let segment = stored.segment?;
+            assert_eq!(segment.writes.len(), 1);
+            ids.push(segment.id());
+        }
+        assert_eq!(ids, vec![1, 2]);
+
+        Ok(())
+    }
+}
@@ -1,12 +1,14 @@
 /// This module contains code for managing the configuration of the server.
-use crate::{db::Db, Error, Result};
+use crate::{db::Db, Error, InvalidTableWriteRules, Result};
+use chrono::{DateTime, Utc};
 use data_types::{
     database_rules::{DatabaseRules, HostGroup, HostGroupId},
     DatabaseName,
 };
 use mutable_buffer::MutableBufferDb;
-use object_store::path::ObjectStorePath;
+use object_store::{path::ObjectStorePath, ObjectStore};
 use read_buffer::Database as ReadBufferDb;
+use snafu::ResultExt;
 
 use std::{
     collections::{BTreeMap, BTreeSet},
@@ -14,6 +16,8 @@ use std::{
 };
 
 pub(crate) const DB_RULES_FILE_NAME: &str = "rules.json";
+pub(crate) const DB_TOMBSTONE_FILE_NAME: &str = "tombstone.json";
+pub(crate) const CONTINUOUS_QUERY_DIR: &str = "continuous_queries";
 
 /// The Config tracks the configuration od databases and their rules along
 /// with host groups for replication. It is used as an in-memory structure
@@ -24,10 +28,14 @@ pub(crate) struct Config {
 }
 
 impl Config {
+    /// Reserves the name of a new database. If `object_store` is given,
+    /// the database lists what it stores under its directory, the root
+    /// given with the store.
     pub(crate) fn create_db(
         &self,
         name: DatabaseName<'static>,
         rules: DatabaseRules,
+        object_store: Option<(Arc<ObjectStore>, ObjectStorePath)>,
     ) -> Result<CreateDatabaseHandle<'_>> {
         let mut state = self.state.write().expect("mutex poisoned");
         if state.reservations.contains(&name) || state.databases.contains_key(&name) {
@@ -35,17 +43,17 @@ impl Config {
                 db_name: name.to_string(),
             });
         }
+        if state.deleted.contains_key(&name) {
+            return Err(Error::DatabaseDeleted {
+                db_name: name.to_string(),
+            });
+        }
+        rules
+            .table_write_rules
+            .filter()
+            .context(InvalidTableWriteRules)?;
 
-        let mutable_buffer = if rules.store_locally {
-            Some(MutableBufferDb::new(name.to_string()))
-        } else {
-            None
-        };
-
-        let read_buffer = ReadBufferDb::new();
-
-        let wal_buffer = rules.wal_buffer_config.as_ref().map(Into::into);
-        let db = Arc::new(Db::new(rules, mutable_buffer, read_buffer, wal_buffer));
+        let db = new_db(&name, rules, object_store);
 
         state.reservations.insert(name.clone());
         Ok(CreateDatabaseHandle {
@@ -60,6 +68,99 @@ impl Config {
         state.databases.get(name).cloned()
     }
 
+    /// Returns the names and databases of all (not deleted) databases
+    pub(crate) fn dbs(&self) -> Vec<(DatabaseName<'static>, Arc<Db>)> {
+        let state = self.state.read().expect("mutex poisoned");
+        state
+            .databases
+            .iter()
+            .map(|(name, db)| (name.clone(), Arc::clone(db)))
+            .collect()
+    }
+
+    /// Stops serving the database `name`, keeping it aside so it can
+    /// be restored until it is purged
+    pub(crate) fn delete_db(
+        &self,
+        name: &DatabaseName<'static>,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut state = self.state.write().expect("mutex poisoned");
+        let db = state
+            .databases
+            .remove(name)
+            .ok_or_else(|| Error::DatabaseNotFound {
+                db_name: name.to_string(),
+            })?;
+
+        state
+            .deleted
+            .insert(name.clone(), DeletedDatabase { db, deleted_at });
+        Ok(())
+    }
+
+    /// Adds a database that was already deleted, e.g. when loading a
+    /// tombstoned database from object storage
+    pub(crate) fn add_deleted_db(
+        &self,
+        name: DatabaseName<'static>,
+        rules: DatabaseRules,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut state = self.state.write().expect("mutex poisoned");
+        if state.reservations.contains(&name)
+            || state.databases.contains_key(&name)
+            || state.deleted.contains_key(&name)
+        {
+            return Err(Error::DatabaseAlreadyExists {
+                db_name: name.to_string(),
+            });
+        }
+
+        let db = new_db(&name, rules, None);
+        state
+            .deleted
+            .insert(name, DeletedDatabase { db, deleted_at });
+        Ok(())
+    }
+
+    /// Returns true if `name` has been deleted but not yet purged
+    pub(crate) fn is_deleted(&self, name: &DatabaseName<'_>) -> bool {
+        let state = self.state.read().expect("mutex poisoned");
+        state.deleted.contains_key(name)
+    }
+
+    /// Serves the deleted database `name` again
+    pub(crate) fn restore_db(&self, name: &DatabaseName<'static>) -> Result<()> {
+        let mut state = self.state.write().expect("mutex poisoned");
+        let deleted = state
+            .deleted
+            .remove(name)
+            .ok_or_else(|| Error::DeletedDatabaseNotFound {
+                db_name: name.to_string(),
+            })?;
+
+        assert!(state.databases.insert(name.clone(), deleted.db).is_none());
+        Ok(())
+    }
+
+    /// Returns the names of the databases deleted before `cutoff`
+    pub(crate) fn deleted_before(&self, cutoff: DateTime<Utc>) -> Vec<DatabaseName<'static>> {
+        let state = self.state.read().expect("mutex poisoned");
+        state
+            .deleted
+            .iter()
+            .filter(|(_, deleted)| deleted.deleted_at < cutoff)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Forgets the deleted database `name` for good
+    pub(crate) fn purge_db(&self, name: &DatabaseName<'_>) {
+        let mut state = self.state.write().expect("mutex poisoned");
+        state.deleted.remove(name);
+    }
+
     pub(crate) fn create_host_group(&self, host_group: HostGroup) {
         let mut state = self.state.write().expect("mutex poisoned");
         state
@@ -87,6 +188,35 @@ impl Config {
     }
 }
 
+fn new_db(
+    name: &DatabaseName<'static>,
+    rules: DatabaseRules,
+    object_store: Option<(Arc<ObjectStore>, ObjectStorePath)>,
+) -> Arc<Db> {
+    let mutable_buffer = if rules.store_locally {
+        let mutable_buffer = MutableBufferDb::new(name.to_string())
+            .with_dictionary_limits((&rules.chunk_dictionary).into())
+            .with_chunk_sizing(rules.chunk_sizing)
+            .with_tag_orders(rules.tag_orders.clone());
+        Some(if rules.chunk_dictionary.shared {
+            mutable_buffer.with_shared_dictionaries()
+        } else {
+            mutable_buffer
+        })
+    } else {
+        None
+    };
+
+    let read_buffer = ReadBufferDb::new();
+
+    let wal_buffer = rules.wal_buffer_config.as_ref().map(Into::into);
+    let db = Db::new(rules, mutable_buffer, read_buffer, wal_buffer);
+    Arc::new(match object_store {
+        Some((store, root)) => db.with_object_store(store, root),
+        None => db,
+    })
+}
+
 pub fn object_store_path_for_database_config(
     root: &ObjectStorePath,
     name: &DatabaseName<'_>,
@@ -97,13 +227,43 @@ pub fn object_store_path_for_database_config(
     path
 }
 
+pub fn object_store_path_for_continuous_query(
+    root: &ObjectStorePath,
+    name: &DatabaseName<'_>,
+    query_name: &str,
+) -> ObjectStorePath {
+    let mut path = root.clone();
+    path.push_dir(name.to_string());
+    path.push_dir(CONTINUOUS_QUERY_DIR);
+    path.set_file_name(format!("{}.json", query_name));
+    path
+}
+
+pub fn object_store_path_for_database_tombstone(
+    root: &ObjectStorePath,
+    name: &DatabaseName<'_>,
+) -> ObjectStorePath {
+    let mut path = root.clone();
+    path.push_dir(name.to_string());
+    path.set_file_name(DB_TOMBSTONE_FILE_NAME);
+    path
+}
+
 #[derive(Default, Debug)]
 struct ConfigState {
     reservations: BTreeSet<DatabaseName<'static>>,
     databases: BTreeMap<DatabaseName<'static>, Arc<Db>>,
+    deleted: BTreeMap<DatabaseName<'static>, DeletedDatabase>,
     host_groups: BTreeMap<HostGroupId, Arc<HostGroup>>,
 }
 
+/// A database that has been deleted but not yet purged
+#[derive(Debug)]
+struct DeletedDatabase {
+    db: Arc<Db>,
+    deleted_at: DateTime<Utc>,
+}
+
 /// CreateDatabaseHandle is retunred when a call is made to `create_db` on
 /// the Config struct. The handle can be used to hold a reservation for the
 /// database name. Calling `commit` on the handle will consume the struct
@@ -145,16 +305,75 @@ mod test {
         let rules = DatabaseRules::default();
 
         {
-            let _db_reservation = config.create_db(name.clone(), rules.clone()).unwrap();
-            let err = config.create_db(name.clone(), rules.clone()).unwrap_err();
-            assert!(matches!(err, Error::DatabaseAlreadyExists{ .. }));
+            let _db_reservation = config.create_db(name.clone(), rules.clone(), None).unwrap();
+            let err = config
+                .create_db(name.clone(), rules.clone(), None)
+                .unwrap_err();
+            assert!(matches!(err, Error::DatabaseAlreadyExists { .. }));
         }
 
-        let db_reservation = config.create_db(name.clone(), rules).unwrap();
+        let db_reservation = config.create_db(name.clone(), rules, None).unwrap();
         db_reservation.commit();
         assert!(config.db(&name).is_some());
     }
 
+    #[test]
+    fn create_db_with_shared_dictionaries() {
+        let config = Config::default();
+        let mut rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+
+        let name = DatabaseName::new("private").unwrap();
+        config
+            .create_db(name.clone(), rules.clone(), None)
+            .unwrap()
+            .commit();
+        let db = config.db(&name).unwrap();
+        assert!(db.mutable_buffer.as_ref().unwrap().string_pool().is_none());
+
+        rules.chunk_dictionary.shared = true;
+        let name = DatabaseName::new("shared").unwrap();
+        config
+            .create_db(name.clone(), rules, None)
+            .unwrap()
+            .commit();
+        let db = config.db(&name).unwrap();
+        assert!(db.mutable_buffer.as_ref().unwrap().string_pool().is_some());
+    }
+
+    #[test]
+    fn delete_and_restore_db() {
+        let name = DatabaseName::new("foo").unwrap();
+        let config = Config::default();
+        config
+            .create_db(name.clone(), DatabaseRules::default(), None)
+            .unwrap()
+            .commit();
+
+        let deleted_at = Utc::now();
+        config.delete_db(&name, deleted_at).unwrap();
+        assert!(config.db(&name).is_none());
+        assert!(config.is_deleted(&name));
+
+        let err = config
+            .create_db(name.clone(), DatabaseRules::default(), None)
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseDeleted { .. }));
+
+        assert!(config.deleted_before(deleted_at).is_empty());
+        let cutoff = deleted_at + chrono::Duration::seconds(1);
+        assert_eq!(config.deleted_before(cutoff), vec![name.clone()]);
+
+        config.restore_db(&name).unwrap();
+        assert!(config.db(&name).is_some());
+        assert!(!config.is_deleted(&name));
+
+        let err = config.restore_db(&name).unwrap_err();
+        assert!(matches!(err, Error::DeletedDatabaseNotFound { .. }));
+    }
+
     #[test]
     fn object_store_path_for_database_config() {
         let path = ObjectStorePath::from_cloud_unchecked("1");
@@ -0,0 +1,708 @@
+//! This module contains the code that runs the continuous queries
+//! defined in the rules of each database. A continuous query aggregates
+//! its source table one closed window of time at a time, and writes one
+//! row per group and window (timestamped with the start of the window)
+//! into its target table.
+//!
+//! How far each query has run is kept in object storage, so windows
+//! that closed while the server was down are caught up on.
+
+use std::{collections::BTreeMap, convert::TryFrom, fmt};
+
+use arrow_deps::arrow::{
+    array::{Array, Float64Array, Int64Array, StringArray},
+    datatypes::DataType,
+    record_batch::RecordBatch,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use data_types::{
+    database_rules::{AggregateFunction, ContinuousQuery, ContinuousQueryAggregate},
+    DatabaseName, TIME_COLUMN_NAME,
+};
+use influxdb_line_protocol::{escape, parse_lines, LinePart};
+use query::{Database, PartitionChunk};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::{error, info};
+
+use crate::{
+    config::object_store_path_for_continuous_query, db::Db, get_optional_store_bytes,
+    put_store_bytes, server_object_store_path, ConnectionManager, ContinuousQueryError,
+    ErrorDeserializing, ErrorSerializing, Server,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("continuous query {} has a zero length window", name))]
+    ZeroWindow { name: String },
+
+    #[snafu(display("continuous query {} has a window that is too long", name))]
+    WindowTooLong { name: String },
+
+    #[snafu(display(
+        "continuous query {} reads from a database that does not store data locally",
+        name
+    ))]
+    SourceNotStoredLocally { name: String },
+
+    #[snafu(display("error reading source of continuous query {}: {}", name, source))]
+    ReadingSource {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display(
+        "continuous query {} can not compute the {} of {:?} field {}",
+        name,
+        function,
+        data_type,
+        field
+    ))]
+    UnsupportedFieldType {
+        name: String,
+        function: AggregateFunction,
+        field: String,
+        data_type: DataType,
+    },
+
+    #[snafu(display(
+        "continuous query {} requires tag {} to be a string, not {:?}",
+        name,
+        tag,
+        data_type
+    ))]
+    UnsupportedTagType {
+        name: String,
+        tag: String,
+        data_type: DataType,
+    },
+
+    #[snafu(display("error converting results of continuous query {}: {}", name, source))]
+    InvalidResults {
+        name: String,
+        source: influxdb_line_protocol::Error,
+    },
+
+    #[snafu(display("error writing results of continuous query {}: {}", name, source))]
+    WritingResults {
+        name: String,
+        source: Box<crate::Error>,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The most windows of a continuous query aggregated from one scan of its
+/// source table, which bounds the groups held in memory while catching up
+const MAX_WINDOWS_PER_SCAN: i64 = 64;
+
+/// How far a continuous query has run, as stored in object storage
+#[derive(Debug, Serialize, Deserialize)]
+struct Progress {
+    /// The end of the last window aggregated, in nanoseconds since the
+    /// epoch
+    completed_until: i64,
+}
+
+impl<M: ConnectionManager> Server<M> {
+    /// Runs the continuous queries of all databases for every window
+    /// that has closed by `now` and not been run yet, returning the
+    /// number of windows run.
+    ///
+    /// A query that fails is logged and retried from the window that
+    /// failed on the next call, without stopping the other queries.
+    pub async fn run_continuous_queries(&self, now: DateTime<Utc>) -> crate::Result<usize> {
+        let id = self.require_id()?;
+
+        let mut windows = 0;
+        for (db_name, db) in self.config.dbs() {
+            for query in &db.rules.continuous_queries {
+                match self
+                    .catch_up_continuous_query(id, &db_name, &db, query, now)
+                    .await
+                {
+                    Ok(n) => windows += n,
+                    Err(e) => error!(
+                        "error running continuous query {} of database {}: {}",
+                        query.name, db_name, e
+                    ),
+                }
+            }
+        }
+
+        Ok(windows)
+    }
+
+    async fn catch_up_continuous_query(
+        &self,
+        id: u32,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        query: &ContinuousQuery,
+        now: DateTime<Utc>,
+    ) -> crate::Result<usize> {
+        let every = window_nanos(query).context(ContinuousQueryError)?;
+        let now = now.timestamp_nanos();
+        let location = object_store_path_for_continuous_query(
+            &server_object_store_path(id),
+            db_name,
+            &query.name,
+        );
+
+        let mut completed_until = match db.continuous_query_progress(&query.name) {
+            Some(completed_until) => completed_until,
+            None => match get_optional_store_bytes(&location, &self.store).await? {
+                Some(data) => {
+                    let progress: Progress =
+                        serde_json::from_slice(&data).context(ErrorDeserializing)?;
+                    progress.completed_until
+                }
+                None => {
+                    // A new query starts with the window that is open now.
+                    // Its progress is stored right away so that windows
+                    // closing while the server is down are caught up on.
+                    let start = now - now.rem_euclid(every);
+                    self.store_continuous_query_progress(&location, db, query, start)
+                        .await?;
+                    start
+                }
+            },
+        };
+
+        let mut windows = 0;
+        while now - completed_until >= every {
+            // Catching up on many windows aggregates them from a single
+            // scan of the source table, a batch of windows at a time.
+            let closed = (now - completed_until) / every;
+            let end = completed_until + every * closed.min(MAX_WINDOWS_PER_SCAN);
+            let groups = self
+                .aggregate_continuous_query(db, query, completed_until, end, every)
+                .await
+                .context(ContinuousQueryError)?;
+
+            while completed_until < end {
+                let window_end = completed_until + every;
+                self.write_continuous_query_window(db_name, query, &groups, completed_until)
+                    .await
+                    .context(ContinuousQueryError)?;
+                self.store_continuous_query_progress(&location, db, query, window_end)
+                    .await?;
+
+                completed_until = window_end;
+                windows += 1;
+            }
+        }
+
+        if windows > 0 {
+            info!(
+                "ran {} windows of continuous query {} of database {}",
+                windows, query.name, db_name
+            );
+        }
+
+        Ok(windows)
+    }
+
+    async fn store_continuous_query_progress(
+        &self,
+        location: &object_store::path::ObjectStorePath,
+        db: &Db,
+        query: &ContinuousQuery,
+        completed_until: i64,
+    ) -> crate::Result<()> {
+        let data = serde_json::to_vec(&Progress { completed_until }).context(ErrorSerializing)?;
+        put_store_bytes(location, &self.store, Bytes::from(data)).await?;
+        db.set_continuous_query_progress(&query.name, completed_until);
+        Ok(())
+    }
+
+    /// Aggregates the rows of the source table with times in
+    /// `[start, end)` into windows of `every` nanoseconds
+    async fn aggregate_continuous_query<'a>(
+        &self,
+        db: &Db,
+        query: &'a ContinuousQuery,
+        start: i64,
+        end: i64,
+        every: i64,
+    ) -> Result<Groups<'a>> {
+        ensure!(
+            db.mutable_buffer.is_some(),
+            SourceNotStoredLocally { name: &query.name }
+        );
+
+        let mut groups = Groups::new(query, start, end, every);
+        let partition_keys = db
+            .partition_keys()
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(ReadingSource { name: &query.name })?;
+        for partition_key in &partition_keys {
+            for chunk in db.chunks(partition_key).await {
+                let mut batches = Vec::new();
+                chunk
+                    .table_to_arrow(&mut batches, &query.source_table, &[])
+                    .map_err(|e| Box::new(e) as _)
+                    .context(ReadingSource { name: &query.name })?;
+
+                for batch in &batches {
+                    groups.add_batch(batch)?;
+                }
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Writes the results of the window starting at `start` to the
+    /// target table
+    async fn write_continuous_query_window(
+        &self,
+        db_name: &DatabaseName<'_>,
+        query: &ContinuousQuery,
+        groups: &Groups<'_>,
+        start: i64,
+    ) -> Result<()> {
+        let lp = groups.to_line_protocol(start);
+        if lp.is_empty() {
+            return Ok(());
+        }
+
+        let lines = parse_lines(&lp)
+            .collect::<Result<Vec<_>, _>>()
+            .context(InvalidResults { name: &query.name })?;
+        let target = query
+            .target_database
+            .as_deref()
+            .unwrap_or_else(|| db_name.as_str());
+        self.write_lines(target, &lines)
+            .await
+            .map_err(Box::new)
+            .context(WritingResults { name: &query.name })
+    }
+}
+
+fn window_nanos(query: &ContinuousQuery) -> Result<i64> {
+    let every = i64::try_from(query.every.as_nanos())
+        .ok()
+        .context(WindowTooLong { name: &query.name })?;
+    ensure!(every > 0, ZeroWindow { name: &query.name });
+    Ok(every)
+}
+
+/// The aggregates of a continuous query, by the start of their window and
+/// the values of its group by tags
+#[derive(Debug)]
+struct Groups<'a> {
+    query: &'a ContinuousQuery,
+    start: i64,
+    end: i64,
+    every: i64,
+    groups: BTreeMap<(i64, Vec<Option<String>>), Vec<Accumulator>>,
+}
+
+impl<'a> Groups<'a> {
+    /// Creates the groups of the windows of `every` nanoseconds in
+    /// `[start, end)`
+    fn new(query: &'a ContinuousQuery, start: i64, end: i64, every: i64) -> Self {
+        Self {
+            query,
+            start,
+            end,
+            every,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the rows of `batch` with times in `[start, end)` to their
+    /// windows
+    fn add_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        let name = &self.query.name;
+        let schema = batch.schema();
+        let column = |name: &str| schema.index_of(name).ok().map(|i| batch.column(i));
+
+        let times = match column(TIME_COLUMN_NAME) {
+            Some(times) => times,
+            None => return Ok(()),
+        };
+        let times = times
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("time column is i64");
+
+        let tags = self
+            .query
+            .group_by_tags
+            .iter()
+            .map(|tag| match column(tag) {
+                None => Ok(None),
+                Some(array) => match array.as_any().downcast_ref::<StringArray>() {
+                    Some(array) => Ok(Some(array)),
+                    None => UnsupportedTagType {
+                        name,
+                        tag,
+                        data_type: array.data_type().clone(),
+                    }
+                    .fail(),
+                },
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let fields = self
+            .query
+            .aggregates
+            .iter()
+            .map(|aggregate| {
+                column(&aggregate.field)
+                    .map(|array| FieldValues::new(name, aggregate, array.as_ref()))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for row in 0..batch.num_rows() {
+            if times.is_null(row) {
+                continue;
+            }
+            let time = times.value(row);
+            if time < self.start || time >= self.end {
+                continue;
+            }
+            let window = time - (time - self.start).rem_euclid(self.every);
+
+            let tags = tags
+                .iter()
+                .map(|tag| {
+                    tag.filter(|tag| !tag.is_null(row))
+                        .map(|tag| tag.value(row).to_string())
+                })
+                .collect();
+
+            let aggregates = &self.query.aggregates;
+            let accumulators = self.groups.entry((window, tags)).or_insert_with(|| {
+                aggregates
+                    .iter()
+                    .map(|aggregate| Accumulator::new(aggregate.function))
+                    .collect()
+            });
+
+            for (accumulator, values) in accumulators.iter_mut().zip(&fields) {
+                if let Some(values) = values {
+                    values.add_to(accumulator, row);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the results of the window starting at `window` as line
+    /// protocol for the target table, with one line per group
+    fn to_line_protocol(&self, window: i64) -> String {
+        let mut lp = String::new();
+
+        let groups = self
+            .groups
+            .range((window, Vec::new())..(window + 1, Vec::new()));
+        for ((_, key), accumulators) in groups {
+            let fields: Vec<_> = self
+                .query
+                .aggregates
+                .iter()
+                .zip(accumulators)
+                .filter_map(|(aggregate, accumulator)| {
+                    accumulator.result().map(|value| {
+                        format!(
+                            "{}={}",
+                            escape(&aggregate.target_field(), LinePart::FieldKey),
+                            value
+                        )
+                    })
+                })
+                .collect();
+            // a line needs at least one field
+            if fields.is_empty() {
+                continue;
+            }
+
+            lp.push_str(&escape(&self.query.target_table, LinePart::Measurement));
+            for (tag, value) in self.query.group_by_tags.iter().zip(key) {
+                if let Some(value) = value {
+                    lp.push(',');
+                    lp.push_str(&escape(tag, LinePart::TagKey));
+                    lp.push('=');
+                    lp.push_str(&escape(value, LinePart::TagValue));
+                }
+            }
+            lp.push(' ');
+            lp.push_str(&fields.join(","));
+            lp.push_str(&format!(" {}\n", window));
+        }
+
+        lp
+    }
+}
+
+/// The values of a field column that an aggregate reads
+#[derive(Debug)]
+enum FieldValues<'a> {
+    /// Only whether values are null matters, for counting
+    Any(&'a dyn Array),
+    I64(&'a Int64Array),
+    F64(&'a Float64Array),
+}
+
+impl<'a> FieldValues<'a> {
+    fn new(name: &str, aggregate: &ContinuousQueryAggregate, array: &'a dyn Array) -> Result<Self> {
+        if let AggregateFunction::Count = aggregate.function {
+            return Ok(Self::Any(array));
+        }
+
+        if let Some(array) = array.as_any().downcast_ref::<Int64Array>() {
+            Ok(Self::I64(array))
+        } else if let Some(array) = array.as_any().downcast_ref::<Float64Array>() {
+            Ok(Self::F64(array))
+        } else {
+            UnsupportedFieldType {
+                name,
+                function: aggregate.function,
+                field: &aggregate.field,
+                data_type: array.data_type().clone(),
+            }
+            .fail()
+        }
+    }
+
+    fn add_to(&self, accumulator: &mut Accumulator, row: usize) {
+        match self {
+            Self::Any(array) if !array.is_null(row) => accumulator.count(),
+            Self::I64(array) if !array.is_null(row) => {
+                accumulator.update(Number::I64(array.value(row)))
+            }
+            Self::F64(array) if !array.is_null(row) => {
+                accumulator.update(Number::F64(array.value(row)))
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    I64(i64),
+    F64(f64),
+}
+
+impl Number {
+    fn as_f64(self) -> f64 {
+        match self {
+            Self::I64(v) => v as f64,
+            Self::F64(v) => v,
+        }
+    }
+
+    fn lt(self, other: Self) -> bool {
+        match (self, other) {
+            (Self::I64(a), Self::I64(b)) => a < b,
+            (a, b) => a.as_f64() < b.as_f64(),
+        }
+    }
+}
+
+/// Formats the number as a line protocol field value
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::I64(v) => write!(f, "{}i", v),
+            Self::F64(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// The state of an aggregate of one group
+#[derive(Debug, Clone, Copy)]
+enum Accumulator {
+    Count(i64),
+    Sum(Option<Number>),
+    Min(Option<Number>),
+    Max(Option<Number>),
+    Mean { sum: f64, count: u64 },
+}
+
+impl Accumulator {
+    fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => Self::Count(0),
+            AggregateFunction::Sum => Self::Sum(None),
+            AggregateFunction::Min => Self::Min(None),
+            AggregateFunction::Max => Self::Max(None),
+            AggregateFunction::Mean => Self::Mean { sum: 0.0, count: 0 },
+        }
+    }
+
+    fn count(&mut self) {
+        if let Self::Count(count) = self {
+            *count += 1;
+        }
+    }
+
+    fn update(&mut self, value: Number) {
+        match self {
+            Self::Count(count) => *count += 1,
+            Self::Sum(sum) => {
+                *sum = Some(match (*sum, value) {
+                    (None, value) => value,
+                    (Some(Number::I64(a)), Number::I64(b)) => Number::I64(a.wrapping_add(b)),
+                    (Some(a), b) => Number::F64(a.as_f64() + b.as_f64()),
+                })
+            }
+            Self::Min(min) => {
+                if min.map_or(true, |min| value.lt(min)) {
+                    *min = Some(value)
+                }
+            }
+            Self::Max(max) => {
+                if max.map_or(true, |max| max.lt(value)) {
+                    *max = Some(value)
+                }
+            }
+            Self::Mean { sum, count } => {
+                *sum += value.as_f64();
+                *count += 1;
+            }
+        }
+    }
+
+    fn result(&self) -> Option<Number> {
+        match *self {
+            Self::Count(count) => Some(Number::I64(count)),
+            Self::Sum(value) | Self::Min(value) | Self::Max(value) => value,
+            Self::Mean { count: 0, .. } => None,
+            Self::Mean { sum, count } => Some(Number::F64(sum / count as f64)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+    use chrono::TimeZone;
+    use data_types::database_rules::DatabaseRules;
+    use object_store::{memory::InMemory, ObjectStore};
+    use query::{frontend::sql::SQLQueryPlanner, DatabaseStore};
+
+    use super::*;
+    use crate::ConnectionManagerImpl;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn continuous_queries_catch_up() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = new_server(&store);
+
+        let query = ContinuousQuery {
+            name: "downsample".to_string(),
+            source_table: "cpu".to_string(),
+            every: Duration::from_nanos(10),
+            group_by_tags: vec!["host".to_string()],
+            aggregates: vec![
+                aggregate(AggregateFunction::Count),
+                aggregate(AggregateFunction::Max),
+                aggregate(AggregateFunction::Mean),
+            ],
+            target_database: Some("dst".to_string()),
+            target_table: "cpu_10ns".to_string(),
+        };
+        let rules = DatabaseRules {
+            store_locally: true,
+            continuous_queries: vec![query],
+            ..Default::default()
+        };
+        server.create_database("src", rules).await?;
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("dst", rules).await?;
+
+        // the first run only starts the open window
+        assert_eq!(server.run_continuous_queries(at(5)).await?, 0);
+
+        write(
+            &server,
+            "cpu,host=a usage=1 1\ncpu,host=a usage=3 2\ncpu,host=b usage=5 3\ncpu,host=a usage=10 11",
+        )
+        .await?;
+        assert_eq!(server.run_continuous_queries(at(20)).await?, 2);
+        assert_eq!(server.run_continuous_queries(at(20)).await?, 0);
+
+        let expected = vec![
+            "+-------------+------+-----------+------------+------+",
+            "| count_usage | host | max_usage | mean_usage | time |",
+            "+-------------+------+-----------+------------+------+",
+            "| 2           | a    | 3         | 2          | 0    |",
+            "| 1           | b    | 5         | 5          | 0    |",
+            "| 1           | a    | 10        | 10         | 10   |",
+            "+-------------+------+-----------+------------+------+",
+        ];
+        assert_table_eq!(expected, &query_dst(&server).await?);
+
+        // after a restart only the windows that have not run yet are run
+        let server = new_server(&store);
+        server.load_database_configs().await?;
+        write(&server, "cpu,host=b usage=7 25").await?;
+        assert_eq!(server.run_continuous_queries(at(30)).await?, 1);
+
+        let expected = vec![
+            "+-------------+------+-----------+------------+------+",
+            "| count_usage | host | max_usage | mean_usage | time |",
+            "+-------------+------+-----------+------------+------+",
+            "| 1           | b    | 7         | 7          | 20   |",
+            "+-------------+------+-----------+------------+------+",
+        ];
+        assert_table_eq!(expected, &query_dst(&server).await?);
+
+        Ok(())
+    }
+
+    fn new_server(store: &Arc<ObjectStore>) -> Server<ConnectionManagerImpl> {
+        let server = Server::new(ConnectionManagerImpl {}, Arc::clone(store));
+        server.set_id(1);
+        server
+    }
+
+    fn aggregate(function: AggregateFunction) -> ContinuousQueryAggregate {
+        ContinuousQueryAggregate {
+            function,
+            field: "usage".to_string(),
+            target_field: None,
+        }
+    }
+
+    fn at(nanos: i64) -> DateTime<Utc> {
+        Utc.timestamp_nanos(nanos)
+    }
+
+    async fn write(server: &Server<ConnectionManagerImpl>, lp: &str) -> Result {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        server.write_lines("src", &lines).await?;
+        Ok(())
+    }
+
+    async fn query_dst(server: &Server<ConnectionManagerImpl>) -> Result<Vec<RecordBatch>> {
+        let db = server.db(&DatabaseName::new("dst")?).await.unwrap();
+        let planner = SQLQueryPlanner::default();
+        let physical_plan = planner
+            .query(
+                db.as_ref(),
+                "select * from cpu_10ns",
+                server.executor().as_ref(),
+            )
+            .await?;
+        Ok(collect(physical_plan).await?)
+    }
+}
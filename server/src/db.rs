@@ -2,7 +2,7 @@
 //! instances of the mutable buffer, read buffer, and object store
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc, Mutex, RwLock,
@@ -10,18 +10,52 @@ use std::{
 };
 
 use async_trait::async_trait;
-use data_types::{data::ReplicatedWrite, database_rules::DatabaseRules};
+use chrono::{DateTime, Utc};
+use data_types::{
+    access_policy::{self, ColumnFilter, Principal},
+    data::{ReplicatedWrite, TableBatch},
+    database_rules::{DatabaseRules, TableWriteFilter, WalSegmentStorage, WriterId},
+};
+use influxdb_line_protocol::ParsedLine;
 use mutable_buffer::MutableBufferDb;
-use query::{Database, PartitionChunk};
+use object_store::{path::ObjectStorePath, ObjectStore};
+use query::{
+    concurrency::QueryGuard, lifecycle::LifecycleLog, system_tables::SeriesOverlap, Database,
+    DatabaseError, DatabaseErrorKind, LifecycleEvent, LifecycleEventKind, PartitionChunk,
+    StoredObject,
+};
 use read_buffer::Database as ReadBufferDb;
 use serde::{Deserialize, Serialize};
 use snafu::{OptionExt, ResultExt, Snafu};
-
-use crate::buffer::Buffer;
+use tokio::sync::oneshot;
+use tracing::error;
+
+use crate::{
+    buffer::{
+        self,
+        fan_in::{FanIn, FanInSummary, StoredWrites},
+        store::WalMetrics,
+        Buffer, InvalidWrites, WAL_DIR,
+    },
+    quota::{self, QuotaMetrics, QuotaTracker, WriteCharge},
+    schema_history::{self, SchemaChange, SchemaHistory},
+    snapshot::{self, Snapshot},
+    summary::{DatabaseSummary, PartitionSummaries, StorageSummary, TableRows},
+};
 
 mod chunk;
 use chunk::DBChunk;
+pub mod autocomplete;
+pub mod copy_partition;
+pub mod delete;
+pub mod lifecycle;
+pub mod metrics;
 pub mod pred;
+pub mod quarantine;
+pub mod retention;
+pub mod scrub;
+pub mod sort_key;
+pub mod stored_objects;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -61,9 +95,57 @@ pub enum Error {
 
     #[snafu(display("Error dropping data from read buffer: {}", source))]
     ReadBufferDrop { source: read_buffer::Error },
+
+    #[snafu(display("Query rejected: {}", source))]
+    QueryQuotaExceeded { source: quota::Error },
+
+    #[snafu(display("Error reading the series keys of a chunk: {}", source))]
+    ReadingSeriesKeys { source: chunk::Error },
+
+    #[snafu(display("Error listing the objects of the database: {}", source))]
+    ListingStoredObjects { source: stored_objects::Error },
+
+    #[snafu(display("Error sorting table {} by its series keys: {}", table_name, source))]
+    SortingTable {
+        table_name: String,
+        source: arrow_deps::arrow::error::ArrowError,
+    },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+impl Error {
+    /// Classifies this error for callers that need to react to the
+    /// cause of the error (e.g. to choose a response code)
+    pub fn kind(&self) -> DatabaseErrorKind {
+        match self {
+            Self::MutableBufferDrop { source }
+            | Self::RollingPartition { source }
+            | Self::MutableBufferRead { source }
+            | Self::MutableBufferWrite { source } => source.kind(),
+            Self::UnknownMutableBufferChunk { .. } => DatabaseErrorKind::NotFound,
+            Self::QueryQuotaExceeded { .. } => DatabaseErrorKind::ResourceExhausted,
+            Self::ReadBufferDrop { source } => match source {
+                read_buffer::Error::PartitionNotFound { .. }
+                | read_buffer::Error::ChunkNotFound { .. }
+                | read_buffer::Error::TableNotFound { .. } => DatabaseErrorKind::NotFound,
+                _ => DatabaseErrorKind::Internal,
+            },
+            Self::MutableBufferChunk { .. }
+            | Self::ReadingSeriesKeys { .. }
+            | Self::ListingStoredObjects { .. }
+            | Self::SortingTable { .. }
+            | Self::DatatbaseNotWriteable {}
+            | Self::DatabaseNotReadable {} => DatabaseErrorKind::Internal,
+        }
+    }
+}
+
+impl From<Error> for DatabaseError {
+    fn from(e: Error) -> Self {
+        Self::new(e.kind(), e)
+    }
+}
+
 const STARTING_SEQUENCE: u64 = 1;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -94,6 +176,73 @@ pub struct Db {
 
     #[serde(skip)]
     sequence: AtomicU64,
+
+    #[serde(skip)]
+    /// The object store the database keeps its configuration and
+    /// snapshots in, with the directory of its configuration
+    object_store: Option<(Arc<ObjectStore>, ObjectStorePath)>,
+
+    #[serde(skip)]
+    /// The time (in nanoseconds since the epoch) up to which each
+    /// continuous query of the database has run, by query name
+    continuous_query_progress: Mutex<BTreeMap<String, i64>>,
+
+    #[serde(skip)]
+    /// The use of the resources limited by the quotas in the rules
+    quotas: QuotaTracker,
+
+    #[serde(skip)]
+    /// When the tables and columns of the database were first written
+    schema_history: SchemaHistory,
+
+    #[serde(skip)]
+    /// What the partitions of the database contain
+    partition_summaries: PartitionSummaries,
+
+    #[serde(skip)]
+    /// The series deleted from the database
+    series_tombstones: RwLock<Vec<delete::SeriesTombstone>>,
+
+    #[serde(skip)]
+    /// The most recent lifecycle events of the partitions of the database
+    lifecycle: Arc<LifecycleLog>,
+
+    #[serde(skip)]
+    /// The queue of the writes enqueued by `Server::write_lines_async`
+    pub(crate) write_queue: crate::write_queue::WriteQueue,
+
+    #[serde(skip)]
+    /// The free space of the directory WAL segments are written to, if any
+    wal_metrics: WalMetrics,
+
+    #[serde(skip)]
+    /// The points quarantined by the future time cap
+    quarantine_metrics: quarantine::QuarantineMetrics,
+
+    #[serde(skip)]
+    /// The `table_write_rules` of the rules, compiled once
+    table_write_filter: TableWriteFilter,
+
+    #[serde(skip)]
+    /// The writer and sequence number of every write stored in the
+    /// mutable buffer, so writes replayed from WALs are only stored once
+    stored_writes: StoredWrites,
+
+    #[serde(skip)]
+    /// The snapshots `warm` loaded into the read buffer
+    warmed_snapshots: crate::warm::WarmedSnapshots,
+
+    #[serde(skip)]
+    /// The chunks snapshotted to object storage, by partition key and
+    /// chunk id
+    snapshotted_chunks: Mutex<BTreeSet<(String, u32)>>,
+
+    #[serde(skip)]
+    /// Held while the snapshots of the database are rewritten by
+    /// retention, deletes or scrubbing, so none of them undoes the changes
+    /// of another to the metadata of a snapshot, and while partitions are
+    /// copied from or to the database
+    snapshot_rewrites: tokio::sync::Mutex<()>,
 }
 impl Db {
     pub fn new(
@@ -102,25 +251,77 @@ impl Db {
         read_buffer: ReadBufferDb,
         wal_buffer: Option<Buffer>,
     ) -> Self {
+        let lifecycle = Arc::new(LifecycleLog::default());
+        let mutable_buffer = mutable_buffer.map(|mb| mb.with_lifecycle_log(Arc::clone(&lifecycle)));
         let wal_buffer = wal_buffer.map(Mutex::new);
         let read_buffer = Arc::new(RwLock::new(read_buffer));
+        // `Config::create_db` refuses rules that don't compile, so they
+        // can only fail here for databases made directly
+        let table_write_filter = rules.table_write_rules.filter().unwrap_or_else(|e| {
+            error!("rejecting all writes to database {}: {}", rules.name, e);
+            TableWriteFilter::deny_all()
+        });
         Self {
             rules,
             mutable_buffer,
             read_buffer,
             wal_buffer,
             sequence: AtomicU64::new(STARTING_SEQUENCE),
+            object_store: None,
+            continuous_query_progress: Default::default(),
+            quotas: Default::default(),
+            schema_history: Default::default(),
+            partition_summaries: Default::default(),
+            series_tombstones: Default::default(),
+            lifecycle,
+            write_queue: Default::default(),
+            wal_metrics: Default::default(),
+            quarantine_metrics: Default::default(),
+            table_write_filter,
+            stored_writes: Default::default(),
+            warmed_snapshots: Default::default(),
+            snapshotted_chunks: Default::default(),
+            snapshot_rewrites: Default::default(),
+        }
+    }
+
+    /// Sets the object store the database keeps its configuration and
+    /// snapshots in, and `root`, the directory of its configuration, so
+    /// the objects it keeps there can be listed
+    pub fn with_object_store(self, store: Arc<ObjectStore>, root: ObjectStorePath) -> Self {
+        Self {
+            object_store: Some((store, root)),
+            ..self
         }
     }
 
+    /// Returns the time up to which the continuous query `name` has run,
+    /// if known
+    pub(crate) fn continuous_query_progress(&self, name: &str) -> Option<i64> {
+        let progress = self
+            .continuous_query_progress
+            .lock()
+            .expect("mutex poisoned");
+        progress.get(name).copied()
+    }
+
+    /// Records that the continuous query `name` has run up to `until`
+    pub(crate) fn set_continuous_query_progress(&self, name: &str, until: i64) {
+        let mut progress = self
+            .continuous_query_progress
+            .lock()
+            .expect("mutex poisoned");
+        progress.insert(name.to_string(), until);
+    }
+
     /// Rolls over the active chunk in the database's specified partition
     pub async fn rollover_partition(&self, partition_key: &str) -> Result<Arc<DBChunk>> {
         if let Some(local_store) = self.mutable_buffer.as_ref() {
-            local_store
+            let chunk = local_store
                 .rollover_partition(partition_key)
                 .await
-                .context(RollingPartition)
-                .map(DBChunk::new_mb)
+                .context(RollingPartition)?;
+            Ok(DBChunk::new_mb(chunk))
         } else {
             DatatbaseNotWriteable {}.fail()
         }
@@ -160,13 +361,29 @@ impl Db {
         partition_key: &str,
         chunk_id: u32,
     ) -> Result<Arc<DBChunk>> {
-        self.mutable_buffer
+        let chunk = self
+            .mutable_buffer
             .as_ref()
             .context(DatatbaseNotWriteable)?
             .drop_chunk(partition_key, chunk_id)
             .await
-            .map(DBChunk::new_mb)
-            .context(MutableBufferDrop)
+            .context(MutableBufferDrop)?;
+
+        // the data of chunks that were loaded into the read buffer is kept
+        let kind = if self
+            .read_buffer_chunk_size(partition_key, chunk_id)
+            .is_some()
+        {
+            LifecycleEventKind::Evicted
+        } else {
+            self.partition_summaries
+                .remove_chunk(partition_key, chunk_id);
+            LifecycleEventKind::Dropped
+        };
+        self.record_lifecycle_event(partition_key, Some(chunk_id), kind, chunk.size() as u64);
+        self.release_partition_quota(partition_key).await;
+
+        Ok(DBChunk::new_mb(chunk))
     }
 
     /// Drops the specified chunk from the read buffer, returning
@@ -176,11 +393,33 @@ impl Db {
         partition_key: &str,
         chunk_id: u32,
     ) -> Result<Arc<DBChunk>> {
-        self.read_buffer
-            .write()
-            .expect("mutex poisoned")
-            .drop_chunk(partition_key, chunk_id)
-            .context(ReadBufferDrop)?;
+        let size = {
+            let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+            let size = read_buffer.chunk_size(partition_key, chunk_id);
+            read_buffer
+                .drop_chunk(partition_key, chunk_id)
+                .context(ReadBufferDrop)?;
+            size.unwrap_or(0)
+        };
+        self.record_lifecycle_event(
+            partition_key,
+            Some(chunk_id),
+            LifecycleEventKind::Dropped,
+            size,
+        );
+        // the chunk is still summarized while the mutable buffer holds it
+        let in_mutable_buffer = match &self.mutable_buffer {
+            Some(mutable_buffer) => mutable_buffer
+                .get_chunk(partition_key, chunk_id)
+                .await
+                .is_some(),
+            None => false,
+        };
+        if !in_mutable_buffer {
+            self.partition_summaries
+                .remove_chunk(partition_key, chunk_id);
+        }
+        self.release_partition_quota(partition_key).await;
 
         Ok(DBChunk::new_rb(
             self.read_buffer.clone(),
@@ -221,6 +460,11 @@ impl Db {
                 .table_to_arrow(&mut batches, &stats.name, &[])
                 .unwrap();
             for batch in batches.drain(..) {
+                let batch =
+                    sort_key::sort_by_series_key(batch, &stats.name, &self.rules.tag_orders)
+                        .context(SortingTable {
+                            table_name: &stats.name,
+                        })?;
                 // As implemented now, taking this write lock will wait
                 // until all reads to the read buffer to complete and
                 // then will block all reads while the insert is occuring
@@ -228,6 +472,13 @@ impl Db {
                 read_buffer.upsert_partition(partition_key, mb_chunk.id(), &stats.name, batch)
             }
         }
+        self.record_lifecycle_event(
+            partition_key,
+            Some(mb_chunk.id()),
+            LifecycleEventKind::Compacted,
+            self.read_buffer_chunk_size(partition_key, mb_chunk.id())
+                .unwrap_or(0),
+        );
 
         Ok(DBChunk::new_rb(
             self.read_buffer.clone(),
@@ -236,10 +487,426 @@ impl Db {
         ))
     }
 
+    /// Starts snapshotting `chunk` of the partition `partition_key` to
+    /// `store`. Once the metadata of the snapshot is written, the chunk is
+    /// marked as snapshotted and recorded as persisted.
+    pub fn snapshot_chunk(
+        self: &Arc<Self>,
+        store: Arc<ObjectStore>,
+        partition_key: &str,
+        chunk: Arc<DBChunk>,
+    ) -> snapshot::Result<Arc<Snapshot<DBChunk>>> {
+        let (metadata_path, mut data_path) = snapshot::snapshot_paths(&self.rules.name);
+        data_path.push_dir(partition_key);
+
+        let (tx, rx) = oneshot::channel();
+        let db = Arc::clone(self);
+        let key = partition_key.to_string();
+        let (chunk_id, size) = (chunk.id(), chunk.size());
+        tokio::task::spawn(async move {
+            if rx.await.is_ok() {
+                db.mark_chunk_snapshotted(&key, chunk_id);
+                db.record_lifecycle_event(
+                    &key,
+                    Some(chunk_id),
+                    LifecycleEventKind::Persisted,
+                    size,
+                );
+            }
+        });
+
+        snapshot::snapshot_chunk(
+            metadata_path,
+            data_path,
+            store,
+            partition_key,
+            chunk,
+            &self.rules.parquet_config,
+            &self.rules.tag_orders,
+            Some(tx),
+        )
+    }
+
+    /// Records that chunk `chunk_id` of the partition `partition_key` was
+    /// snapshotted to object storage, so queries selecting persisted data
+    /// read it
+    pub(crate) fn mark_chunk_snapshotted(&self, partition_key: &str, chunk_id: u32) {
+        self.snapshotted_chunks
+            .lock()
+            .expect("mutex poisoned")
+            .insert((partition_key.to_string(), chunk_id));
+    }
+
     /// Returns the next write sequence number
     pub fn next_sequence(&self) -> u64 {
         self.sequence.fetch_add(1, Ordering::SeqCst)
     }
+
+    /// Makes sure the next write sequence number is at least `next`, for
+    /// example after writes with earlier sequence numbers were replayed
+    pub fn advance_sequence(&self, next: u64) {
+        self.sequence.fetch_max(next, Ordering::SeqCst);
+    }
+
+    /// Returns true if the write `sequence` of `writer` was stored in the
+    /// mutable buffer
+    pub(crate) fn write_stored(&self, writer: WriterId, sequence: u64) -> bool {
+        self.stored_writes.contains(writer, sequence)
+    }
+
+    /// Returns the principal whose token is `token`. A known token is
+    /// required if the database has an access policy, otherwise there
+    /// are no principals.
+    pub fn authenticate(
+        &self,
+        token: Option<&str>,
+    ) -> Result<Option<&Principal>, access_policy::Error> {
+        self.rules
+            .access_policy
+            .as_ref()
+            .map(|policy| policy.authenticate(token))
+            .transpose()
+    }
+
+    /// Returns the filters the rows seen by the queries of `principal`
+    /// must pass, which are none unless the database has an access policy
+    pub fn column_filters(
+        &self,
+        principal: Option<&Principal>,
+    ) -> Result<Vec<ColumnFilter>, access_policy::Error> {
+        match (&self.rules.access_policy, principal) {
+            (Some(policy), Some(principal)) => policy.column_filters(principal),
+            (Some(_), None) => Err(access_policy::Error::MissingToken),
+            (None, _) => Ok(vec![]),
+        }
+    }
+
+    /// Checks that the principal whose token is `token` sees every row
+    /// of the database, which is required to read what is gathered from
+    /// all rows, such as its summary and schema. Anyone does unless the
+    /// database has an access policy.
+    pub fn check_unrestricted(&self, token: Option<&str>) -> Result<(), access_policy::Error> {
+        match &self.rules.access_policy {
+            Some(policy) => policy.check_unrestricted(policy.authenticate(token)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the write of `lines` against the quotas of the database.
+    /// If it is accepted, the write is charged to the quotas; the charge
+    /// must be committed once the write has succeeded.
+    pub async fn check_write_quotas(
+        &self,
+        lines: &[ParsedLine<'_>],
+    ) -> Result<WriteCharge<'_>, quota::Error> {
+        let quotas = &self.rules.quotas;
+        let mutable_buffer_size = self.mutable_buffer_size_for_quotas();
+
+        // lines without a timestamp are partitioned by the time they are
+        // written, which is close enough to now to count their partitions
+        let partition_keys: Vec<_> = match quotas.max_partitions {
+            Some(_) => {
+                let default_time = Utc::now();
+                let mut generator = self.rules.partition_key_generator(&default_time);
+                lines
+                    .iter()
+                    .map(|line| generator.partition_key(line))
+                    .collect::<Result<_, _>>()
+                    .context(quota::PartitionKey)?
+            }
+            None => vec![],
+        };
+
+        self.quotas
+            .check_write(quotas, lines, &partition_keys, mutable_buffer_size)
+    }
+
+    /// Checks the write of the points of `batch` against the quotas of the
+    /// database like `check_write_quotas`. The points are only made into
+    /// lines if a quota counts their series, lines or bytes.
+    pub async fn check_batch_write_quotas(
+        &self,
+        batch: &TableBatch,
+    ) -> Result<WriteCharge<'_>, quota::Error> {
+        let quotas = &self.rules.quotas;
+        let mutable_buffer_size = self.mutable_buffer_size_for_quotas();
+
+        let partition_keys = match quotas.max_partitions {
+            Some(_) => batch
+                .partition_keys(&self.rules)
+                .context(quota::PartitionKey)?,
+            None => vec![],
+        };
+        let counts_lines = quotas.max_series.is_some()
+            || quotas.max_lines_per_second.is_some()
+            || quotas.max_bytes_per_second.is_some();
+        let lines = if counts_lines { batch.lines() } else { vec![] };
+
+        self.quotas
+            .check_write(quotas, &lines, &partition_keys, mutable_buffer_size)
+    }
+
+    /// The size of the mutable buffer, if the quotas limit it
+    fn mutable_buffer_size_for_quotas(&self) -> usize {
+        match (&self.mutable_buffer, self.rules.quotas.max_memory_bytes) {
+            (Some(mutable_buffer), Some(_)) => mutable_buffer.size(),
+            _ => 0,
+        }
+    }
+
+    /// Stops counting the partition `partition_key` against the quota of
+    /// partitions if it no longer holds data in the mutable buffer or the
+    /// read buffer, such as after its chunks were dropped
+    pub(crate) async fn release_partition_quota(&self, partition_key: &str) {
+        if self.rules.quotas.max_partitions.is_none() {
+            return;
+        }
+
+        let in_read_buffer = !self
+            .read_buffer
+            .read()
+            .expect("mutex poisoned")
+            .chunk_ids(partition_key)
+            .is_empty();
+        if in_read_buffer {
+            return;
+        }
+        if let Some(mutable_buffer) = &self.mutable_buffer {
+            if !mutable_buffer.partition_is_empty(partition_key).await {
+                return;
+            }
+        }
+        self.quotas.release_partition(partition_key);
+    }
+
+    /// Releases the partitions counted against the quota of partitions
+    /// that no longer hold data, after data was dropped from many of them
+    pub(crate) async fn release_partition_quotas(&self) {
+        for partition_key in self.quotas.partitions() {
+            self.release_partition_quota(&partition_key).await;
+        }
+    }
+
+    /// The number of writes and queries rejected by the quotas of the
+    /// database
+    pub fn quota_metrics(&self) -> &QuotaMetrics {
+        self.quotas.metrics()
+    }
+
+    /// The free space of the directory the database writes its WAL
+    /// segments to, and the number of writes rejected for the lack of it
+    pub fn wal_metrics(&self) -> &WalMetrics {
+        &self.wal_metrics
+    }
+
+    /// The directory the database writes its WAL segments to and the bytes
+    /// that must be left free on its file system, if it writes them to a
+    /// directory of the local file system
+    pub fn wal_directory(&self) -> Option<(&str, u64)> {
+        match &self.rules.wal_buffer_config {
+            Some(config) if config.store_segments => match &config.segment_storage {
+                WalSegmentStorage::Directory {
+                    path,
+                    min_free_bytes,
+                } => Some((path.as_str(), *min_free_bytes)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The filter of the tables the database's rules allow writes to
+    pub fn table_write_filter(&self) -> &TableWriteFilter {
+        &self.table_write_filter
+    }
+
+    /// Returns the tables and columns added to the database at or after
+    /// `since` (or ever, if None), oldest first
+    pub fn schema_changes(&self, since: Option<DateTime<Utc>>) -> Vec<SchemaChange> {
+        self.schema_history.changes_since(since)
+    }
+
+    /// Records `write`, which was stored in the mutable buffer: records
+    /// its writer and sequence number, adds its rows to the summaries of
+    /// the partitions, counts those quarantined and adds its new tables
+    /// and columns to the schema history.
+    ///
+    /// The schema history is persisted whenever it changes. The write is
+    /// stored by then, so a failure to persist it is only logged; the
+    /// next change persists the whole history again.
+    pub(crate) async fn record_stored_write(&self, write: &ReplicatedWrite) {
+        let (writer, sequence) = write.writer_and_sequence();
+        self.stored_writes.insert(writer, sequence);
+        for (partition_key, _) in self.partition_summaries.record_columns(write) {
+            self.summarize_mutable_buffer_chunks(&partition_key).await;
+        }
+        self.quarantine_metrics.record(write);
+
+        if self.schema_history.record_write(write, Utc::now()) {
+            if let Some((store, root)) = &self.object_store {
+                if let Err(e) = self.schema_history.persist(store, root).await {
+                    error!(
+                        "error persisting the schema history of database {}: {}",
+                        self.rules.name, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Sets the summaries of the mutable buffer chunks of the partition
+    /// `partition_key` from their table statistics
+    async fn summarize_mutable_buffer_chunks(&self, partition_key: &str) {
+        let mutable_buffer = match &self.mutable_buffer {
+            Some(mutable_buffer) => mutable_buffer,
+            None => return,
+        };
+
+        for chunk in mutable_buffer.chunks(partition_key).await {
+            let stats = match chunk.table_stats() {
+                Ok(stats) => stats,
+                Err(e) => {
+                    error!(
+                        "error summarizing chunk {} of partition {} of database {}: {}",
+                        chunk.id(),
+                        partition_key,
+                        self.rules.name,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let tables = stats.into_iter().map(|table| {
+                let rows = table.columns.iter().map(|c| c.count()).max().unwrap_or(0);
+                let rows = TableRows {
+                    rows: rows as u64,
+                    time_range: table.time_range,
+                };
+                (table.name, rows)
+            });
+            self.partition_summaries
+                .set_chunk(partition_key, chunk.id(), tables);
+        }
+    }
+
+    /// Sets the summary of chunk `chunk_id` of the partition
+    /// `partition_key` from the read buffer after it was loaded or
+    /// rewritten there, or forgets the chunk if the read buffer no longer
+    /// holds it
+    pub(crate) fn summarize_read_buffer_chunk(
+        &self,
+        read_buffer: &ReadBufferDb,
+        partition_key: &str,
+        chunk_id: u32,
+    ) {
+        if read_buffer.chunk_size(partition_key, chunk_id).is_none() {
+            self.partition_summaries
+                .remove_chunk(partition_key, chunk_id);
+            return;
+        }
+
+        let tables = self
+            .partition_summaries
+            .table_names(partition_key)
+            .into_iter()
+            .filter_map(|table_name| {
+                let (rows, time_range) = read_buffer
+                    .table_rows(partition_key, chunk_id, &table_name)
+                    .ok()?;
+                Some((table_name, TableRows { rows, time_range }))
+            });
+        self.partition_summaries
+            .set_chunk(partition_key, chunk_id, tables);
+    }
+
+    /// Loads the schema history persisted in object storage, if any
+    pub(crate) async fn load_schema_history(&self) -> Result<(), schema_history::Error> {
+        match &self.object_store {
+            Some((store, root)) => self.schema_history.load(store, root).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Replays the WAL segments the database persisted to its object
+    /// store into the mutable buffer, so the writes acknowledged before
+    /// the server stopped are served again. The segments closed from
+    /// then on are numbered after the replayed ones, so they don't
+    /// overwrite them.
+    ///
+    /// Every write is verified before it is read. With
+    /// `skip_invalid_writes` set in the WAL buffer config, the writes that
+    /// fail verification are skipped and counted in the summary;
+    /// otherwise the first one fails the replay. New writes of
+    /// `writer_id`, the id of this server, are sequenced after its writes
+    /// replayed.
+    pub(crate) async fn restore_partitions_from_wal(
+        &self,
+        writer_id: WriterId,
+    ) -> Result<FanInSummary, buffer::Error> {
+        let ((store, root), config) = match (&self.object_store, &self.rules.wal_buffer_config) {
+            (Some(object_store), Some(config))
+                if config.store_segments
+                    && config.segment_storage == WalSegmentStorage::ObjectStore =>
+            {
+                (object_store, config)
+            }
+            _ => return Ok(FanInSummary::default()),
+        };
+        let invalid_writes = if config.skip_invalid_writes {
+            InvalidWrites::Skip
+        } else {
+            InvalidWrites::Fail
+        };
+
+        let mut prefix = root.clone();
+        prefix.push_dir(WAL_DIR);
+        let mut fan_in = FanIn::new(self, writer_id);
+        fan_in
+            .add_stored_wal(store, &prefix, invalid_writes)
+            .await?;
+        let summary = fan_in.finish();
+
+        if let (Some(wal_buffer), Some(segment_id)) = (&self.wal_buffer, summary.last_segment_id) {
+            wal_buffer
+                .lock()
+                .expect("mutex poisoned")
+                .start_after_segment(segment_id);
+        }
+
+        Ok(summary)
+    }
+
+    /// Returns what the database contains: the tables written to its
+    /// partitions, and the memory used by each storage tier. The summaries
+    /// and the sizes are kept up to date as the data changes, so no data
+    /// is scanned.
+    pub async fn summary(&self) -> DatabaseSummary {
+        let mutable_buffer_bytes = match &self.mutable_buffer {
+            Some(mutable_buffer) => mutable_buffer.size() as u64,
+            None => 0,
+        };
+        let read_buffer_bytes = self.read_buffer.read().expect("mutex poisoned").size();
+
+        self.partition_summaries.summary(StorageSummary {
+            mutable_buffer_bytes,
+            read_buffer_bytes,
+        })
+    }
+
+    /// Returns how many series are in more than one chunk of each
+    /// partition. Queries merge the rows of those series across chunks,
+    /// so compacting the partitions with the most overlap first saves
+    /// queries the most work.
+    pub async fn series_overlap(&self) -> Result<SeriesOverlap> {
+        let mut series_overlap = SeriesOverlap::default();
+        for partition_key in self.partition_keys().await? {
+            for chunk in self.chunks(&partition_key).await {
+                let series_keys = chunk.series_keys().context(ReadingSeriesKeys)?;
+                series_overlap.add(&partition_key, series_keys);
+            }
+        }
+        Ok(series_overlap)
+    }
 }
 
 impl PartialEq for Db {
@@ -254,6 +921,10 @@ impl Database for Db {
     type Error = Error;
     type Chunk = DBChunk;
 
+    fn name(&self) -> &str {
+        &self.rules.name
+    }
+
     /// Return a covering set of chunks for a particular partition
     async fn chunks(&self, partition_key: &str) -> Vec<Arc<Self::Chunk>> {
         // return a coverting set of chunks. TODO include read buffer
@@ -273,6 +944,16 @@ impl Database for Db {
         chunks.into_iter().map(|(_id, chunk)| chunk).collect()
     }
 
+    fn start_query(&self) -> Result<QueryGuard, Self::Error> {
+        self.quotas
+            .start_query(&self.rules.quotas)
+            .context(QueryQuotaExceeded)
+    }
+
+    fn restricts_rows(&self) -> bool {
+        self.rules.access_policy.is_some()
+    }
+
     // Note that most of the functions below will eventually be removed from
     // this trait. For now, pass them directly on to the local store
 
@@ -292,7 +973,7 @@ impl Database for Db {
         self.mutable_buffer
             .as_ref()
             .context(DatabaseNotReadable)?
-            .tag_column_names(predicate)
+            .tag_column_names(self.retained(predicate))
             .await
             .context(MutableBufferRead)
     }
@@ -304,7 +985,7 @@ impl Database for Db {
         self.mutable_buffer
             .as_ref()
             .context(DatabaseNotReadable)?
-            .field_column_names(predicate)
+            .field_column_names(self.retained(predicate))
             .await
             .context(MutableBufferRead)
     }
@@ -317,7 +998,7 @@ impl Database for Db {
         self.mutable_buffer
             .as_ref()
             .context(DatabaseNotReadable)?
-            .column_values(column_name, predicate)
+            .column_values(column_name, self.retained(predicate))
             .await
             .context(MutableBufferRead)
     }
@@ -329,7 +1010,7 @@ impl Database for Db {
         self.mutable_buffer
             .as_ref()
             .context(DatabaseNotReadable)?
-            .query_series(predicate)
+            .query_series(self.retained(predicate))
             .await
             .context(MutableBufferRead)
     }
@@ -342,18 +1023,63 @@ impl Database for Db {
         self.mutable_buffer
             .as_ref()
             .context(DatabaseNotReadable)?
-            .query_groups(predicate, gby_agg)
+            .query_groups(self.retained(predicate), gby_agg)
             .await
             .context(MutableBufferRead)
     }
 
+    /// Return the keys of the partitions in the mutable buffer and in the
+    /// read buffer, such as partitions loaded from snapshots by `warm`
     async fn partition_keys(&self) -> Result<Vec<String>, Self::Error> {
-        self.mutable_buffer
-            .as_ref()
-            .context(DatabaseNotReadable)?
+        let mut partition_keys: BTreeSet<String> = self
+            .read_buffer
+            .read()
+            .expect("mutex poisoned")
             .partition_keys()
+            .into_iter()
+            .cloned()
+            .collect();
+
+        if let Some(mutable_buffer) = &self.mutable_buffer {
+            let keys = mutable_buffer
+                .partition_keys()
+                .await
+                .context(MutableBufferRead)?;
+            partition_keys.extend(keys);
+        }
+
+        Ok(partition_keys.into_iter().collect())
+    }
+
+    fn retention_boundary(&self, table: Option<&str>) -> Option<i64> {
+        let now = Utc::now().timestamp_nanos();
+        match table {
+            Some(table) => self.rules.retention.boundary(table, now),
+            None => self.rules.retention.default_boundary(now),
+        }
+    }
+
+    fn tables_with_own_retention(&self) -> Vec<String> {
+        self.rules.retention.tables.keys().cloned().collect()
+    }
+
+    fn chunk_persisted(&self, partition_key: &str, chunk: &DBChunk) -> bool {
+        chunk.is_persisted()
+            || self
+                .snapshotted_chunks
+                .lock()
+                .expect("mutex poisoned")
+                .contains(&(partition_key.to_string(), chunk.id()))
+    }
+
+    fn lifecycle_events(&self) -> Vec<LifecycleEvent> {
+        self.lifecycle.events()
+    }
+
+    async fn stored_objects(&self) -> Result<Vec<StoredObject>, Self::Error> {
+        self.list_stored_objects()
             .await
-            .context(MutableBufferRead)
+            .context(ListingStoredObjects)
     }
 }
 
@@ -380,8 +1106,14 @@ mod tests {
     use arrow_deps::{
         arrow::record_batch::RecordBatch, assert_table_eq, datafusion::physical_plan::collect,
     };
+    use data_types::data::lines_to_replicated_write;
+    use influxdb_line_protocol::parse_lines;
     use query::{
-        exec::Executor, frontend::sql::SQLQueryPlanner, test::TestLPWriter, PartitionChunk,
+        exec::{Executor, QueryMetrics},
+        frontend::sql::SQLQueryPlanner,
+        predicate::{Predicate, PredicateBuilder},
+        test::TestLPWriter,
+        PartitionChunk,
     };
     use test_helpers::assert_contains;
 
@@ -462,6 +1194,384 @@ mod tests {
         assert_table_eq!(expected, &batches);
     }
 
+    #[tokio::test]
+    async fn sql_query_metrics() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+        writer
+            .write_lp_string(&db, "cpu bar=2 20\ncpu bar=3 30")
+            .await
+            .unwrap();
+
+        let metrics = Arc::new(QueryMetrics::default());
+        let planner = SQLQueryPlanner::default().with_metrics(Arc::clone(&metrics));
+        let physical_plan = planner
+            .query(&db, "select * from cpu where bar > 1", &Executor::new())
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        metrics.add_batches(&batches);
+
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        assert_eq!(get(&metrics.partitions_considered), 1);
+        assert_eq!(get(&metrics.chunks_considered), 2);
+        assert_eq!(get(&metrics.chunks_pruned), 0);
+        assert_eq!(get(&metrics.rows_scanned), 3);
+        assert_eq!(get(&metrics.batches_produced), batches.len() as u64);
+        assert_eq!(get(&metrics.rows_produced), 2);
+    }
+
+    #[tokio::test]
+    async fn limit_stops_reading_chunks() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+        writer.write_lp_string(&db, "cpu bar=2 20").await.unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+        writer.write_lp_string(&db, "cpu bar=3 30").await.unwrap();
+
+        let chunks_considered = |sql: &'static str| {
+            let metrics = Arc::new(QueryMetrics::default());
+            let planner = SQLQueryPlanner::default().with_metrics(Arc::clone(&metrics));
+            let db = &db;
+            async move {
+                let physical_plan = planner.query(db, sql, &Executor::new()).await.unwrap();
+                let batches = collect(physical_plan).await.unwrap();
+                let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+                (rows, metrics.chunks_considered.load(Ordering::Relaxed))
+            }
+        };
+
+        assert_eq!(chunks_considered("select * from cpu limit 0").await, (0, 1));
+        assert_eq!(chunks_considered("select * from cpu limit 1").await, (1, 1));
+        assert_eq!(chunks_considered("select * from cpu limit 2").await, (2, 2));
+        assert_eq!(chunks_considered("select * from cpu").await, (3, 3));
+        // filters need to see every row
+        assert_eq!(
+            chunks_considered("select * from cpu where bar > 2 limit 1").await,
+            (1, 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn count_uses_stored_row_counts() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        let partition_key = "1970-01-01T00";
+        writer
+            .write_lp_string(&db, "cpu bar=1 10\ncpu bar=2 20")
+            .await
+            .unwrap();
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        writer
+            .write_lp_string(&db, "cpu bar=3 30\ncpu bar=4 40")
+            .await
+            .unwrap();
+
+        let count = |sql: &'static str| {
+            let metrics = Arc::new(QueryMetrics::default());
+            let planner = SQLQueryPlanner::default().with_metrics(Arc::clone(&metrics));
+            let db = &db;
+            async move {
+                let physical_plan = planner.query(db, sql, &Executor::new()).await.unwrap();
+                let batches = collect(physical_plan).await.unwrap();
+                (batches, metrics.rows_scanned.load(Ordering::Relaxed))
+            }
+        };
+
+        let (batches, rows_scanned) = count("select count(*) from cpu").await;
+        let expected = vec![
+            "+-----------------+",
+            "| COUNT(UInt8(1)) |",
+            "+-----------------+",
+            "| 4               |",
+            "+-----------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+        assert_eq!(rows_scanned, 0);
+
+        let expected_2 = vec!["+---+", "| n |", "+---+", "| 2 |", "+---+"];
+        let expected_3 = vec!["+---+", "| n |", "+---+", "| 3 |", "+---+"];
+
+        // the read buffer chunk is entirely before the range, the mutable
+        // buffer chunk entirely within it
+        let (batches, rows_scanned) = count("select count(*) as n from cpu where time >= 30").await;
+        assert_table_eq!(expected_2, &batches);
+        assert_eq!(rows_scanned, 0);
+
+        // only some rows of the read buffer chunk are in the range
+        let (batches, rows_scanned) = count("select count(*) as n from cpu where time >= 20").await;
+        assert_table_eq!(expected_3, &batches);
+        assert_eq!(rows_scanned, 4);
+
+        // other predicates need the data
+        let (batches, rows_scanned) = count("select count(*) as n from cpu where bar > 1").await;
+        assert_table_eq!(expected_3, &batches);
+        assert_eq!(rows_scanned, 4);
+    }
+
+    #[tokio::test]
+    async fn tag_cardinality() {
+        let db = make_db();
+        let executor = Executor::new();
+        let mut writer = TestLPWriter::default();
+        let partition_key = "1970-01-01T00";
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,host=a,region=west usage=1 10\ncpu,host=b,region=west usage=2 20",
+            )
+            .await
+            .unwrap();
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        writer
+            .write_lp_string(&db, "cpu,host=b usage=3 30\nmem,host=a free=1i 30")
+            .await
+            .unwrap();
+
+        let query = |sql: &'static str| {
+            let planner = SQLQueryPlanner::default();
+            let db = &db;
+            let executor = &executor;
+            async move {
+                let physical_plan = planner.query(db, sql, executor).await.unwrap();
+                collect(physical_plan).await.unwrap()
+            }
+        };
+
+        let batches = query("select * from system.tag_cardinality").await;
+        let expected = vec![
+            "+------------+--------+-------------+",
+            "| table_name | tag    | cardinality |",
+            "+------------+--------+-------------+",
+            "| cpu        | host   | 2           |",
+            "| cpu        | region | 1           |",
+            "| mem        | host   | 1           |",
+            "+------------+--------+-------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        let cardinality = "select cardinality('cpu', 'host') as n from cpu limit 1";
+        let expected = vec!["+---+", "| n |", "+---+", "| 2 |", "+---+"];
+        assert_table_eq!(expected, &query(cardinality).await);
+
+        // the function must not answer from a previous query's values
+        writer
+            .write_lp_string(&db, "cpu,host=c usage=4 40")
+            .await
+            .unwrap();
+        let expected = vec!["+---+", "| n |", "+---+", "| 3 |", "+---+"];
+        assert_table_eq!(expected, &query(cardinality).await);
+    }
+
+    #[tokio::test]
+    async fn column_encodings() {
+        let db = make_db();
+        let executor = Executor::new();
+        let mut writer = TestLPWriter::default();
+        let partition_key = "1970-01-01T00";
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,host=a,region=west usage=1 10\ncpu,host=b,region=west usage=2 20",
+            )
+            .await
+            .unwrap();
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        // the open mutable buffer chunk doesn't encode its columns
+        writer
+            .write_lp_string(&db, "cpu,host=c usage=3 30")
+            .await
+            .unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let sql = "select chunk_id, table_name, column_name, encoding, rows \
+                   from system.column_encodings";
+        let physical_plan = planner.query(&db, sql, &executor).await.unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+
+        let expected = vec![
+            "+----------+------------+-------------+----------------+------+",
+            "| chunk_id | table_name | column_name | encoding       | rows |",
+            "+----------+------------+-------------+----------------+------+",
+            "| 0        | cpu        | host        | RLE_DICTIONARY | 2    |",
+            "| 0        | cpu        | region      | RLE_DICTIONARY | 2    |",
+            "| 0        | cpu        | time        | FIXED_U8       | 2    |",
+            "| 0        | cpu        | usage       | FIXED_F64      | 2    |",
+            "+----------+------------+-------------+----------------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn series_overlap() {
+        let db = make_db();
+        let executor = Executor::new();
+        let mut writer = TestLPWriter::default();
+        let partition_key = "1970-01-01T00";
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,host=a,region=west usage=1 10
+cpu,host=b usage=2 20
+mem,host=a free=1i 20",
+            )
+            .await
+            .unwrap();
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,host=b usage=3 30
+cpu,host=c usage=4 40",
+            )
+            .await
+            .unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let physical_plan = planner
+            .query(&db, "select * from system.series_overlap", &executor)
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+
+        let expected = vec![
+            "+---------------+------------+--------+--------+--------------------+------------------+",
+            "| partition_key | table_name | chunks | series | overlapping_series | duplicate_series |",
+            "+---------------+------------+--------+--------+--------------------+------------------+",
+            "| 1970-01-01T00 | cpu        | 2      | 3      | 1                  | 1                |",
+            "| 1970-01-01T00 | mem        | 1      | 1      | 0                  | 0                |",
+            "+---------------+------------+--------+--------+--------------------+------------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        let series_overlap = db.series_overlap().await.unwrap();
+        assert_eq!(
+            series_overlap.partitions_by_overlap(),
+            vec![(partition_key.to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn group_by_aggregates_each_chunk() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west usage=1 10\ncpu,region=east usage=5 10",
+            )
+            .await
+            .unwrap();
+        db.rollover_partition("1970-01-01T00").await.unwrap();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,region=west usage=3 20\ncpu,region=west usage=2 30",
+            )
+            .await
+            .unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let physical_plan = planner
+            .query(
+                &db,
+                "select region, count(*) as n, max(usage) as max_usage \
+                 from cpu group by region order by region",
+                &Executor::new(),
+            )
+            .await
+            .unwrap();
+
+        // each chunk is aggregated separately before the partial
+        // results are combined
+        let plan = format!("{:?}", physical_plan);
+        assert_contains!(&plan, "Partial");
+        assert_contains!(&plan, "Final");
+
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+--------+---+-----------+",
+            "| region | n | max_usage |",
+            "+--------+---+-----------+",
+            "| east   | 1 | 5         |",
+            "| west   | 3 | 3         |",
+            "+--------+---+-----------+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn sql_plans_are_cached_until_the_schema_changes() {
+        let db = make_db();
+        let executor = Executor::new();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+
+        let query = |sql: &'static str| {
+            let metrics = Arc::new(QueryMetrics::default());
+            let planner = SQLQueryPlanner::default().with_metrics(Arc::clone(&metrics));
+            let db = &db;
+            let executor = &executor;
+            async move {
+                let physical_plan = planner.query(db, sql, executor).await.unwrap();
+                let batches = collect(physical_plan).await.unwrap();
+                (batches, metrics.plan_cache_hits.load(Ordering::Relaxed))
+            }
+        };
+
+        let (_, hits) = query("select * from cpu").await;
+        assert_eq!(hits, 0);
+
+        // the same statement, formatted differently, over new data
+        writer.write_lp_string(&db, "cpu bar=2 20").await.unwrap();
+        let (batches, hits) = query("SELECT *\n  FROM cpu").await;
+        assert_eq!(hits, 1);
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "| 2   | 20   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // a new column invalidates the plan
+        writer
+            .write_lp_string(&db, "cpu bar=3,baz=4 30")
+            .await
+            .unwrap();
+        let (batches, hits) = query("select * from cpu").await;
+        assert_eq!(hits, 0);
+        let expected = vec![
+            "+-----+-----+------+",
+            "| bar | baz | time |",
+            "+-----+-----+------+",
+            "| 1   |     | 10   |",
+            "| 2   |     | 20   |",
+            "| 3   | 4   | 30   |",
+            "+-----+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+        assert_eq!(executor.plan_cache().len(), 1);
+    }
+
     #[tokio::test]
     async fn read_from_read_buffer() {
         // Test that data can be loaded into the ReadBuffer
@@ -557,6 +1667,49 @@ mod tests {
         assert_eq!(read_buffer_chunk_ids(&db, partition_key).await, vec![1]);
     }
 
+    #[tokio::test]
+    async fn read_buffer_chunks_are_sorted_by_tag_order() {
+        let mut rules = DatabaseRules::default();
+        rules
+            .tag_orders
+            .tables
+            .insert("cpu".to_string(), vec!["region".to_string()]);
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None,
+        );
+
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,host=a,region=west usage=1 10\n\
+                 cpu,host=b,region=east usage=2 20\n\
+                 cpu,host=a,region=east usage=3 30",
+            )
+            .await
+            .unwrap();
+        let partition_key = "1970-01-01T00";
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        let rb_chunk = db
+            .load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        let mut batches = Vec::new();
+        rb_chunk.table_to_arrow(&mut batches, "cpu", &[]).unwrap();
+        let usage = batches[0]
+            .column(batches[0].schema().index_of("usage").unwrap())
+            .as_any()
+            .downcast_ref::<arrow_deps::arrow::array::Float64Array>()
+            .unwrap();
+        let usage: Vec<_> = (0..usage.len()).map(|i| usage.value(i)).collect();
+        // sorted by region, then host, then time
+        assert_eq!(usage, vec![3.0, 2.0, 1.0]);
+    }
+
     // run a sql query against the database, returning the results as record batches
     async fn run_query(db: &Db, query: &str) -> Vec<RecordBatch> {
         let planner = SQLQueryPlanner::default();
@@ -637,6 +1790,184 @@ mod test_influxrpc {
         }
     }
 
+    #[tokio::test]
+    async fn queries_leave_out_expired_rows() {
+        let mut rules = DatabaseRules::default();
+        rules.retention.period = Some(std::time::Duration::from_secs(3600));
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None,
+        );
+
+        let now = Utc::now().timestamp_nanos();
+        let hour = 3_600_000_000_000;
+        let lp = format!(
+            "cpu usage=1 10\ncpu usage=2 {}\ncpu usage=3 {}",
+            now - 2 * hour,
+            now
+        );
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &db.rules);
+        db.store_replicated_write(&write).await.unwrap();
+
+        // a chunk holding only expired rows is not read at all
+        let partition_key = db.partition_keys().await.unwrap().remove(0);
+        db.rollover_partition(&partition_key).await.unwrap();
+        let lines: Vec<_> = parse_lines("cpu usage=4 20").map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 2, &lines, &db.rules);
+        db.store_replicated_write(&write).await.unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let plan = planner
+            .query(&db, "select usage from cpu", &executor)
+            .await
+            .unwrap();
+        let expected = vec![
+            "+-------+",
+            "| usage |",
+            "+-------+",
+            "| 3     |",
+            "+-------+",
+        ];
+        assert_table_eq!(expected, &collect(plan).await.unwrap());
+
+        // the expired rows are not counted either
+        let plan = planner
+            .query(&db, "select count(*) as n from cpu", &executor)
+            .await
+            .unwrap();
+        let expected = vec!["+---+", "| n |", "+---+", "| 1 |", "+---+"];
+        assert_table_eq!(expected, &collect(plan).await.unwrap());
+
+        // the time ranges of the predicates of other queries are clamped
+        let range = db.retained(Predicate::default()).range.unwrap();
+        assert!(range.start >= now - hour);
+        let predicate = PredicateBuilder::default().timestamp_range(0, now).build();
+        let range = db.retained(predicate).range.unwrap();
+        assert!(range.start >= now - hour);
+        assert_eq!(range.end, now);
+    }
+
+    #[tokio::test]
+    async fn retention_boundary_of_each_table() {
+        let mut rules = DatabaseRules::default();
+        rules.retention.period = Some(std::time::Duration::from_secs(3600));
+        rules
+            .retention
+            .tables
+            .insert("archive".to_string(), std::time::Duration::from_secs(86400));
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None,
+        );
+
+        let now = Utc::now().timestamp_nanos();
+        let hour = 3_600_000_000_000;
+        let lp = format!(
+            "cpu,host=a usage=1 {}\narchive,host=a usage=1 {}",
+            now - 2 * hour,
+            now - 2 * hour
+        );
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &db.rules);
+        db.store_replicated_write(&write).await.unwrap();
+
+        let planner = InfluxRPCPlanner::new();
+        let executor = Executor::new();
+
+        // the rows of `archive` are kept for a day, those of `cpu` for an
+        // hour, whether the tables are named or not
+        let plan = planner
+            .table_names(&db, Predicate::default())
+            .await
+            .unwrap();
+        let names = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(names, to_stringset(&["archive"]));
+
+        let predicate = PredicateBuilder::default()
+            .table("cpu")
+            .table("archive")
+            .build();
+        let plan = planner.table_names(&db, predicate).await.unwrap();
+        let names = executor.to_string_set(plan).await.unwrap();
+        assert_eq!(names, to_stringset(&["archive"]));
+
+        // selections without any retained rows are empty rather than
+        // errors
+        let predicate = PredicateBuilder::default().table("cpu").build();
+        let plan = planner.table_names(&db, predicate).await.unwrap();
+        let names = executor.to_string_set(plan).await.unwrap();
+        assert!(names.is_empty());
+
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(0, now - 2 * 86400 * 1_000_000_000)
+            .build();
+        let plan = db.tag_column_names(predicate).await.unwrap();
+        let names = executor.to_string_set(plan).await.unwrap();
+        assert!(names.is_empty());
+    }
+
+    #[tokio::test]
+    async fn time_ranges_from_chunk_statistics() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,host=a usage=1 10
+                 cpu,host=a usage=2 20
+                 mem,host=a free=1i 15
+                 cpu,host=b usage=3 3600000000010",
+            )
+            .await
+            .unwrap();
+
+        // the first partition is split across a read buffer chunk and a
+        // mutable buffer chunk
+        let chunk = db.rollover_partition("1970-01-01T00").await.unwrap();
+        db.load_chunk_to_read_buffer("1970-01-01T00", chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk("1970-01-01T00", chunk.id())
+            .await
+            .unwrap();
+        writer
+            .write_lp_string(&db, "cpu,host=a usage=4 5")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db.partition_time_range("1970-01-01T00", "cpu")
+                .await
+                .unwrap(),
+            Some((5, 20))
+        );
+        assert_eq!(
+            db.partition_time_range("1970-01-01T01", "cpu")
+                .await
+                .unwrap(),
+            Some((3600000000010, 3600000000010))
+        );
+        assert_eq!(
+            db.partition_time_range("1970-01-01T01", "mem")
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            db.time_range("cpu").await.unwrap(),
+            Some((5, 3600000000010))
+        );
+        assert_eq!(db.time_range("mem").await.unwrap(), Some((15, 15)));
+        assert_eq!(db.time_range("disk").await.unwrap(), None);
+    }
+
     #[tokio::test]
     async fn list_table_names() {
         let empty_predicate = Predicate::default();
@@ -0,0 +1,306 @@
+//! This module contains the lookup of the values of a tag column that
+//! start with a prefix, for autocompletion.
+//!
+//! The prefix is searched for in the tag dictionaries of each chunk. Read
+//! buffer dictionaries are sorted, so only the values that match are
+//! visited; mutable buffer values are compared in place and only the
+//! matches are copied. Rows are only read for the tables of chunks whose
+//! rows are partly in the requested time range, which is narrowed to the
+//! retention period of each table, and for tables with deleted series,
+//! whose rows are read in full so the deletions can be applied.
+
+use std::collections::BTreeSet;
+
+use arrow_deps::arrow::{
+    array::{Array, Int64Array, StringArray},
+    record_batch::RecordBatch,
+};
+use data_types::TIME_COLUMN_NAME;
+use query::{predicate::TimestampRange, Database, PartitionChunk, TableRowCount};
+use snafu::{ResultExt, Snafu};
+
+use super::{chunk, chunk::DBChunk, delete, Db};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error listing the partitions of the database: {}", source))]
+    ListingPartitions { source: super::Error },
+
+    #[snafu(display(
+        "Error reading the values of chunk {} of partition {}: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    ReadingChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: chunk::Error,
+    },
+
+    #[snafu(display("Error removing the deleted rows of table {}: {}", table, source))]
+    RemovingDeletedRows {
+        table: String,
+        source: delete::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Db {
+    /// Returns the first `limit` values, in order, of the tag column
+    /// `column` of any table that start with `prefix`. If `range` is set,
+    /// only the values of rows with a timestamp in it are returned. Rows
+    /// past the retention period of their table and deleted series are
+    /// left out.
+    ///
+    /// The access policy of the database is not applied, so callers
+    /// serving principals must check `restricts_rows` first.
+    pub async fn column_values_with_prefix(
+        &self,
+        column: &str,
+        prefix: &str,
+        limit: usize,
+        range: Option<TimestampRange>,
+    ) -> Result<Vec<String>> {
+        let mut values = BTreeSet::new();
+        for partition_key in self.partition_keys().await.context(ListingPartitions)? {
+            for chunk in self.chunks(&partition_key).await {
+                let chunk_values = self.chunk_values_with_prefix(
+                    &partition_key,
+                    &chunk,
+                    column,
+                    prefix,
+                    limit,
+                    range,
+                )?;
+                values.extend(chunk_values);
+
+                // values past the first `limit` can't be returned
+                if values.len() > limit {
+                    values = values.into_iter().take(limit).collect();
+                }
+            }
+        }
+
+        Ok(values.into_iter().collect())
+    }
+
+    /// Returns the first `limit` values of the tag column `column` of the
+    /// tables of `chunk` that start with `prefix`, from rows in `range`
+    fn chunk_values_with_prefix(
+        &self,
+        partition_key: &str,
+        chunk: &DBChunk,
+        column: &str,
+        prefix: &str,
+        limit: usize,
+        range: Option<TimestampRange>,
+    ) -> Result<BTreeSet<String>> {
+        let context = || ReadingChunk {
+            partition_key,
+            chunk_id: chunk.id(),
+        };
+
+        let mut values = BTreeSet::new();
+        for (table_name, dictionary) in chunk
+            .tag_values_with_prefix(column, prefix)
+            .context(context())?
+        {
+            let range = match (range, self.retention_boundary(Some(&table_name))) {
+                (range, None) => range,
+                (Some(range), Some(boundary)) => {
+                    Some(TimestampRange::new(range.start.max(boundary), range.end))
+                }
+                (None, Some(boundary)) => Some(TimestampRange::new(boundary, i64::MAX)),
+            };
+
+            let time_range = chunk
+                .table_row_count(&table_name)
+                .context(context())?
+                .and_then(|TableRowCount { time_range, .. }| time_range);
+            let all_in_range = match (range, time_range) {
+                (None, _) => Some(true),
+                (Some(range), _) if range.start >= range.end => Some(false),
+                (Some(range), Some((min, max))) if range.contains(min) && range.contains(max) => {
+                    Some(true)
+                }
+                (Some(range), Some((min, max))) if max < range.start || min >= range.end => {
+                    Some(false)
+                }
+                _ => None,
+            };
+
+            match all_in_range {
+                Some(false) => {}
+                Some(true) if !self.has_deleted_series(&table_name) => {
+                    values.extend(dictionary.into_iter().take(limit));
+                }
+                _ => {
+                    // the rows are read to tell which values they have. The
+                    // rows of tables with deleted series are read in full, so
+                    // the tag columns of the deletions can be matched
+                    let mut batches = Vec::new();
+                    if self.has_deleted_series(&table_name) {
+                        chunk
+                            .table_to_arrow(&mut batches, &table_name, &[])
+                            .context(context())?;
+                    } else {
+                        chunk
+                            .table_to_arrow(&mut batches, &table_name, &[column, TIME_COLUMN_NAME])
+                            .context(context())?;
+                    }
+                    for batch in batches {
+                        let batch = self
+                            .remove_deleted_rows(&table_name, batch)
+                            .context(RemovingDeletedRows { table: &table_name })?;
+                        values.extend(batch_values_with_prefix(&batch, column, prefix, range));
+                    }
+                }
+            }
+        }
+
+        Ok(values.into_iter().take(limit).collect())
+    }
+}
+
+/// Returns the values of the tag column `column` of `batch` that start
+/// with `prefix`, from rows in `range`
+fn batch_values_with_prefix(
+    batch: &RecordBatch,
+    column: &str,
+    prefix: &str,
+    range: Option<TimestampRange>,
+) -> BTreeSet<String> {
+    let schema = batch.schema();
+    let tags = match schema.index_of(column) {
+        Ok(idx) => batch
+            .column(idx)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("tag columns are strings"),
+        Err(_) => return BTreeSet::new(),
+    };
+    let times = schema.index_of(TIME_COLUMN_NAME).ok().map(|idx| {
+        batch
+            .column(idx)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("time column is i64")
+    });
+
+    (0..batch.num_rows())
+        .filter(|&row| !tags.is_null(row) && tags.value(row).starts_with(prefix))
+        .filter(|&row| match (range, times) {
+            (None, _) => true,
+            (Some(range), Some(times)) => !times.is_null(row) && range.contains(times.value(row)),
+            (Some(_), None) => false,
+        })
+        .map(|row| tags.value(row).to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{delete::TagMatcher, test_util::make_db};
+    use data_types::database_rules::DatabaseRules;
+    use mutable_buffer::MutableBufferDb;
+    use query::test::TestLPWriter;
+    use read_buffer::Database as ReadBufferDb;
+
+    const LP: &str = "cpu,host=server01,region=west usage=1 10\n\
+                      cpu,host=server02,region=west usage=2 20\n\
+                      cpu,host=client01,region=east usage=3 30\n\
+                      mem,host=server03 free=1i 40\n\
+                      mem,host=server04 free=2i 3600000000000";
+
+    #[tokio::test]
+    async fn column_values_with_prefix() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, LP).await.unwrap();
+
+        // the first partition is moved to the read buffer
+        let partition_key = "1970-01-01T00";
+        let chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, chunk.id())
+            .await
+            .unwrap();
+
+        let values = db
+            .column_values_with_prefix("host", "server", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["server01", "server02", "server03", "server04"]);
+
+        let values = db
+            .column_values_with_prefix("host", "server", 2, None)
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["server01", "server02"]);
+
+        // the rows at 10 and 40 are read to find which are in the range
+        let range = TimestampRange::new(15, 40);
+        let values = db
+            .column_values_with_prefix("host", "", 10, Some(range))
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["client01", "server02"]);
+
+        let range = TimestampRange::new(0, 3600000000001);
+        let values = db
+            .column_values_with_prefix("host", "server0", 10, Some(range))
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["server01", "server02", "server03", "server04"]);
+
+        let values = db
+            .column_values_with_prefix("region", "w", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["west"]);
+
+        let values = db
+            .column_values_with_prefix("zone", "", 10, None)
+            .await
+            .unwrap();
+        assert!(values.is_empty());
+    }
+
+    #[tokio::test]
+    async fn column_values_with_prefix_leave_out_expired_and_deleted_rows() {
+        let mut rules = DatabaseRules::default();
+        rules
+            .retention
+            .tables
+            .insert("mem".to_string(), std::time::Duration::from_secs(3600));
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None,
+        );
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, LP).await.unwrap();
+
+        // the rows of mem are past its retention period
+        let values = db
+            .column_values_with_prefix("host", "server", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["server01", "server02"]);
+
+        db.delete_series("cpu", &[TagMatcher::new("host", "server01")])
+            .await
+            .unwrap();
+        let values = db
+            .column_values_with_prefix("host", "server", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["server02"]);
+    }
+}
@@ -1,11 +1,12 @@
 use arrow_deps::{
-    arrow::record_batch::RecordBatch, datafusion::logical_plan::LogicalPlan,
+    arrow::record_batch::RecordBatch,
+    datafusion::logical_plan::{Expr, LogicalPlan},
     util::str_iter_to_batch,
 };
 use query::{
     predicate::{Predicate, PredicateBuilder},
     util::make_scan_plan,
-    PartitionChunk,
+    ColumnEncoding, PartitionChunk, TableRowCount, TableSeriesKeys, TableTagValues, TableValues,
 };
 use read_buffer::{ColumnSelection, Database as ReadBufferDb};
 use snafu::{ResultExt, Snafu};
@@ -13,6 +14,7 @@ use snafu::{ResultExt, Snafu};
 use std::sync::{Arc, RwLock};
 
 use super::pred::to_read_buffer_predicate;
+use crate::warm::SNAPSHOT_CHUNK_ID;
 use async_trait::async_trait;
 
 #[derive(Debug, Snafu)]
@@ -74,6 +76,23 @@ impl DBChunk {
             partition_key,
         })
     }
+
+    /// Returns the size of the data of the chunk in memory, in bytes
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::MutableBuffer { chunk } => chunk.size() as u64,
+            Self::ReadBuffer {
+                db,
+                partition_key,
+                chunk_id,
+            } => db
+                .read()
+                .expect("mutex poisoned")
+                .chunk_size(partition_key, *chunk_id)
+                .unwrap_or_default(),
+            Self::ParquetFile => 0,
+        }
+    }
 }
 
 #[async_trait]
@@ -88,6 +107,12 @@ impl PartitionChunk for DBChunk {
         }
     }
 
+    fn supports_expr(expr: &Expr) -> bool {
+        // the read buffer is queried without predicates, which are applied
+        // to its rows afterwards, so only the mutable buffer restricts them
+        mutable_buffer::chunk::supports_expr(expr)
+    }
+
     fn table_stats(&self) -> Result<Vec<data_types::partition_metadata::Table>, Self::Error> {
         match self {
             Self::MutableBuffer { chunk } => chunk.table_stats().context(MutableBufferChunk),
@@ -145,6 +170,115 @@ impl PartitionChunk for DBChunk {
         Ok(())
     }
 
+    fn table_row_count(&self, table_name: &str) -> Result<Option<TableRowCount>, Self::Error> {
+        let (rows, time_range) = match self {
+            Self::MutableBuffer { chunk } => {
+                let (rows, time_range) = chunk
+                    .table_row_count(table_name)
+                    .context(MutableBufferChunk)?;
+                (rows as u64, time_range)
+            }
+            Self::ReadBuffer {
+                db,
+                partition_key,
+                chunk_id,
+            } => db
+                .read()
+                .unwrap()
+                .table_rows(partition_key, *chunk_id, table_name)
+                .context(ReadBufferChunk)?,
+            Self::ParquetFile => return Ok(None),
+        };
+
+        Ok(Some(TableRowCount { rows, time_range }))
+    }
+
+    fn is_persisted(&self) -> bool {
+        match self {
+            Self::MutableBuffer { .. } => false,
+            // snapshots are loaded into the read buffer as their own chunk
+            Self::ReadBuffer { chunk_id, .. } => *chunk_id == SNAPSHOT_CHUNK_ID,
+            Self::ParquetFile => true,
+        }
+    }
+
+    fn all_tag_values(&self) -> Result<TableTagValues, Self::Error> {
+        match self {
+            Self::MutableBuffer { chunk } => chunk.all_tag_values().context(MutableBufferChunk),
+            Self::ReadBuffer {
+                db,
+                partition_key,
+                chunk_id,
+            } => db
+                .read()
+                .unwrap()
+                .all_tag_values(partition_key, *chunk_id)
+                .context(ReadBufferChunk),
+            Self::ParquetFile => unimplemented!("parquet file not implemented"),
+        }
+    }
+
+    fn tag_values_with_prefix(
+        &self,
+        column: &str,
+        prefix: &str,
+    ) -> Result<TableValues, Self::Error> {
+        match self {
+            Self::MutableBuffer { chunk } => chunk
+                .tag_values_with_prefix(column, prefix)
+                .context(MutableBufferChunk),
+            Self::ReadBuffer {
+                db,
+                partition_key,
+                chunk_id,
+            } => db
+                .read()
+                .unwrap()
+                .tag_values_with_prefix(partition_key, *chunk_id, column, prefix)
+                .context(ReadBufferChunk),
+            Self::ParquetFile => unimplemented!("parquet file not implemented"),
+        }
+    }
+
+    fn series_keys(&self) -> Result<TableSeriesKeys, Self::Error> {
+        match self {
+            Self::MutableBuffer { chunk } => chunk.series_keys().context(MutableBufferChunk),
+            Self::ReadBuffer { .. } => query::exec::series_key::chunk_series_keys(self),
+            Self::ParquetFile => unimplemented!("parquet file not implemented"),
+        }
+    }
+
+    fn column_encodings(&self) -> Result<Vec<ColumnEncoding>, Self::Error> {
+        match self {
+            // the mutable buffer doesn't encode its columns
+            Self::MutableBuffer { .. } => Ok(vec![]),
+            Self::ReadBuffer {
+                db,
+                partition_key,
+                chunk_id,
+            } => {
+                let encodings = db
+                    .read()
+                    .unwrap()
+                    .column_encodings(partition_key, *chunk_id)
+                    .context(ReadBufferChunk)?;
+
+                Ok(encodings
+                    .into_iter()
+                    .map(|encoding| ColumnEncoding {
+                        table_name: encoding.table_name,
+                        row_group: encoding.row_group as u64,
+                        column_name: encoding.column_name,
+                        encoding: encoding.encoding.to_string(),
+                        rows: encoding.rows.into(),
+                        size: encoding.size,
+                    })
+                    .collect())
+            }
+            Self::ParquetFile => unimplemented!("parquet file not implemented"),
+        }
+    }
+
     async fn table_names(&self, predicate: &Predicate) -> Result<LogicalPlan, Self::Error> {
         match self {
             Self::MutableBuffer { chunk } => {
@@ -0,0 +1,913 @@
+//! This module contains the copying of a partition from one database to
+//! another, such as to give a tenant a database of its own, or to
+//! reprocess a partition out of band. The partition keeps its key, and
+//! both its data in memory and its snapshot are copied.
+//!
+//! The tables of the snapshot of the partition, and those of the chunks in
+//! the mutable buffer and the read buffer the snapshot doesn't hold, are
+//! loaded into one chunk of the read buffer of the target database,
+//! `COPIED_CHUNK_ID`, so no row is copied twice. That chunk is then
+//! snapshotted in the target database, so the copy is as durable as the
+//! partition was before the copy returns.
+//!
+//! Moving a partition copies it and then drops it from the source
+//! database, deleting the metadata of its snapshot before the files, once
+//! the copy is snapshotted. Writes to the partition while it is moved go
+//! to a new open chunk of the source database and stay there.
+//!
+//! A copy holds the locks both databases take to rewrite their snapshots,
+//! so copies into the same database, and rewrites of the snapshots being
+//! copied, wait for each other: a partition can't be copied into a
+//! database twice.
+
+use std::sync::Arc;
+
+use arrow_deps::arrow::{array::StringArray, record_batch::RecordBatch};
+use data_types::partition_metadata::{self, Partition as PartitionMeta};
+use futures::TryStreamExt;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use query::{Database, LifecycleEventKind};
+use read_buffer::{ColumnSelection, Predicate};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::info;
+
+use super::{chunk::DBChunk, scrub::table_path, Db};
+use crate::{
+    snapshot::{self, snapshot_paths},
+    warm::{get_bytes, read_parquet, SNAPSHOT_CHUNK_ID},
+};
+
+/// The id of the read buffer chunk the data of a partition copied from
+/// another database is loaded into. The mutable buffer numbers its chunks
+/// from zero and snapshots are loaded into `warm::SNAPSHOT_CHUNK_ID`, so
+/// this doesn't clash with either.
+pub const COPIED_CHUNK_ID: u32 = u32::MAX - 1;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Can't copy a partition of database {} to itself", db_name))]
+    SameDatabase { db_name: String },
+
+    #[snafu(display("Partition {} not found in database {}", partition_key, db_name))]
+    PartitionNotFound {
+        db_name: String,
+        partition_key: String,
+    },
+
+    #[snafu(display("Partition {} already exists in database {}", partition_key, db_name))]
+    PartitionExists {
+        db_name: String,
+        partition_key: String,
+    },
+
+    #[snafu(display(
+        "Can't copy a partition to database {}: it has no object store to snapshot it to",
+        db_name
+    ))]
+    NoObjectStore { db_name: String },
+
+    #[snafu(display("Error listing partitions of database {}: {}", db_name, source))]
+    ListingPartitions {
+        db_name: String,
+        source: super::Error,
+    },
+
+    #[snafu(display("Error listing partitions of the mutable buffer: {}", source))]
+    ListingMutableBuffer {
+        source: mutable_buffer::database::Error,
+    },
+
+    #[snafu(display("Error rolling over partition {}: {}", partition_key, source))]
+    RollingOver {
+        partition_key: String,
+        source: super::Error,
+    },
+
+    #[snafu(display(
+        "Error reading chunk {} of partition {} of the mutable buffer: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    ReadingMutableBufferChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: mutable_buffer::chunk::Error,
+    },
+
+    #[snafu(display(
+        "Error reading chunk {} of partition {} of the read buffer: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    ReadingReadBufferChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: read_buffer::Error,
+    },
+
+    #[snafu(display(
+        "Error dropping chunk {} of partition {}: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    DroppingChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: super::Error,
+    },
+
+    #[snafu(display("Error listing snapshots in object store: {}", source))]
+    ListingSnapshots { source: object_store::Error },
+
+    #[snafu(display("Error reading snapshot: {}", source))]
+    ReadingSnapshot { source: crate::warm::Error },
+
+    #[snafu(display("Error decoding snapshot metadata {}: {}", path, source))]
+    DecodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Can't copy snapshot {}: {}", path, source))]
+    UnsupportedFormat {
+        path: String,
+        source: partition_metadata::Error,
+    },
+
+    #[snafu(display("Error removing deleted series from {}: {}", path, source))]
+    RemovingDeletedSeries {
+        path: String,
+        source: super::delete::Error,
+    },
+
+    #[snafu(display("Error snapshotting partition {}: {}", partition_key, source))]
+    Snapshotting {
+        partition_key: String,
+        source: snapshot::Error,
+    },
+
+    #[snafu(display(
+        "Snapshot of partition {} of database {} failed; see the log for its error",
+        partition_key,
+        db_name
+    ))]
+    SnapshotFailed {
+        db_name: String,
+        partition_key: String,
+    },
+
+    #[snafu(display("Error deleting {}: {}", path, source))]
+    DeletingObject {
+        path: String,
+        source: object_store::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Whether `Db::copy_partition_to` keeps the partition in the source
+/// database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyMode {
+    /// The partition is kept in both databases
+    Copy,
+    /// The partition is dropped from the source database once copied
+    Move,
+}
+
+impl Default for CopyMode {
+    fn default() -> Self {
+        Self::Copy
+    }
+}
+
+/// What `Db::copy_partition_to` copied
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
+pub struct CopySummary {
+    /// The chunks of the mutable buffer and read buffer copied, from
+    /// memory or from the snapshot holding them
+    pub chunks: u64,
+    /// The rows copied
+    pub rows: u64,
+    /// The objects of the snapshot of the partition in the target
+    /// database
+    pub objects: Vec<String>,
+}
+
+/// The data of the chunks of a partition in memory
+#[derive(Debug, Default)]
+struct PartitionData {
+    /// The ids of the chunks read from the mutable buffer
+    mutable_buffer_chunks: Vec<u32>,
+    /// The ids of the chunks read from the read buffer
+    read_buffer_chunks: Vec<u32>,
+    /// The record batches of each table of those chunks, except the
+    /// chunks the snapshot of the partition holds
+    tables: Vec<(String, Vec<RecordBatch>)>,
+}
+
+/// The snapshot of a partition in object storage
+#[derive(Debug)]
+struct Snapshot {
+    store: Arc<ObjectStore>,
+    /// The path of the metadata of the snapshot
+    path: ObjectStorePath,
+    meta: PartitionMeta,
+}
+
+impl Db {
+    /// Copies the partition `partition_key`, both the chunks in memory and
+    /// the snapshot, to the database `target` under the same key, and then
+    /// drops it from this database if `mode` is `CopyMode::Move`. The
+    /// partition must not exist in `target` yet. Returns once the copy is
+    /// snapshotted in `target`.
+    pub async fn copy_partition_to(
+        &self,
+        target: &Self,
+        partition_key: &str,
+        mode: CopyMode,
+    ) -> Result<CopySummary> {
+        ensure!(
+            self.rules.name != target.rules.name,
+            SameDatabase {
+                db_name: &self.rules.name
+            }
+        );
+        let (target_store, _) = target.object_store.as_ref().context(NoObjectStore {
+            db_name: &target.rules.name,
+        })?;
+
+        // the locks are taken in the order of the names of the databases,
+        // so copies in opposite directions don't deadlock
+        let (first, second) = if self.rules.name < target.rules.name {
+            (self, target)
+        } else {
+            (target, self)
+        };
+        let _first = first.snapshot_rewrites.lock().await;
+        let _second = second.snapshot_rewrites.lock().await;
+
+        ensure!(
+            !target.has_partition_in_memory(partition_key).await?
+                && target.find_snapshot(partition_key).await?.is_none(),
+            PartitionExists {
+                db_name: &target.rules.name,
+                partition_key,
+            }
+        );
+
+        let in_memory = self.has_partition_in_memory(partition_key).await?;
+        let snapshot = self.find_snapshot(partition_key).await?;
+        ensure!(
+            in_memory || snapshot.is_some(),
+            PartitionNotFound {
+                db_name: &self.rules.name,
+                partition_key,
+            }
+        );
+
+        let mut data = if in_memory {
+            self.read_partition(partition_key, snapshot.is_some())
+                .await?
+        } else {
+            PartitionData::default()
+        };
+        if let Some(snapshot) = &snapshot {
+            let tables = self.read_snapshot(partition_key, snapshot).await?;
+            data.tables.extend(tables);
+        }
+
+        let chunks = (data.mutable_buffer_chunks.len() + data.read_buffer_chunks.len()) as u64;
+        let rows = target.load_copied_tables(partition_key, std::mem::take(&mut data.tables));
+        let objects = if rows > 0 {
+            match target
+                .snapshot_copied_chunk(target_store, partition_key)
+                .await
+            {
+                Ok(objects) => objects,
+                Err(e) => {
+                    // the copy is only kept once it is durable
+                    let mut read_buffer = target.read_buffer.write().expect("mutex poisoned");
+                    let _ = read_buffer.drop_chunk(partition_key, COPIED_CHUNK_ID);
+                    return Err(e);
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let summary = CopySummary {
+            chunks,
+            rows,
+            objects,
+        };
+
+        if let Some(tables) = self.partition_summaries.partition(partition_key) {
+            target
+                .partition_summaries
+                .insert_columns(partition_key, &tables);
+        }
+        target.summarize_read_buffer_chunk(
+            &target.read_buffer.read().expect("mutex poisoned"),
+            partition_key,
+            COPIED_CHUNK_ID,
+        );
+        target.record_lifecycle_event(partition_key, None, LifecycleEventKind::Created, 0);
+
+        if mode == CopyMode::Move {
+            self.drop_copied_partition(partition_key, &data, snapshot.as_ref())
+                .await?;
+        }
+
+        info!(
+            db_name = self.rules.name.as_str(),
+            target = target.rules.name.as_str(),
+            partition_key,
+            ?mode,
+            chunks = summary.chunks,
+            rows = summary.rows,
+            objects = summary.objects.len(),
+            "Copied partition"
+        );
+        Ok(summary)
+    }
+
+    /// Returns true if the mutable buffer or the read buffer hold chunks
+    /// of the partition `partition_key`
+    async fn has_partition_in_memory(&self, partition_key: &str) -> Result<bool> {
+        let partition_keys = self.partition_keys().await.context(ListingPartitions {
+            db_name: &self.rules.name,
+        })?;
+        Ok(partition_keys.iter().any(|key| key == partition_key))
+    }
+
+    /// Returns the snapshot of the partition `partition_key`, if it was
+    /// snapshotted
+    async fn find_snapshot(&self, partition_key: &str) -> Result<Option<Snapshot>> {
+        let store = match &self.object_store {
+            Some((store, _)) => store,
+            None => return Ok(None),
+        };
+        let (metadata_path, _) = snapshot_paths(&self.rules.name);
+        let mut path = metadata_path.clone();
+        path.set_file_name(format!("{}.json", partition_key));
+        let meta_path = store.convert_path(&path);
+
+        let paths: Vec<Vec<_>> = store
+            .list(Some(&metadata_path))
+            .await
+            .context(ListingSnapshots)?
+            .try_collect()
+            .await
+            .context(ListingSnapshots)?;
+        if !paths
+            .iter()
+            .flatten()
+            .any(|listed| store.convert_path(listed) == meta_path)
+        {
+            return Ok(None);
+        }
+
+        let data = get_bytes(store, &path).await.context(ReadingSnapshot)?;
+        let meta: PartitionMeta =
+            serde_json::from_slice(&data).context(DecodingMetadata { path: &meta_path })?;
+        // snapshots of a newer minor version keep their tables where this
+        // version expects them
+        meta.format_version
+            .check_readable()
+            .context(UnsupportedFormat { path: &meta_path })?;
+        Ok(Some(Snapshot {
+            store: Arc::clone(store),
+            path,
+            meta,
+        }))
+    }
+
+    /// Reads the tables of `snapshot`, the snapshot of the partition
+    /// `partition_key`, without the series deleted since it was written
+    async fn read_snapshot(
+        &self,
+        partition_key: &str,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(String, Vec<RecordBatch>)>> {
+        let store = &snapshot.store;
+        let (_, data_path) = snapshot_paths(&self.rules.name);
+
+        let mut tables = Vec::with_capacity(snapshot.meta.tables.len());
+        for table in &snapshot.meta.tables {
+            let location = table_path(&data_path, partition_key, &table.name);
+            let path = store.convert_path(&location);
+            let data = get_bytes(store, &location).await.context(ReadingSnapshot)?;
+            let batches = read_parquet(data, &path)
+                .context(ReadingSnapshot)?
+                .into_iter()
+                .map(|batch| self.remove_deleted_rows(&table.name, batch))
+                .collect::<Result<Vec<_>, _>>()
+                .context(RemovingDeletedSeries { path: &path })?;
+            tables.push((table.name.clone(), batches));
+        }
+        Ok(tables)
+    }
+
+    /// Reads the chunks of the partition `partition_key` in the mutable
+    /// buffer and the read buffer. The open chunk of the mutable buffer is
+    /// closed first, so all of the data written so far is read. If the
+    /// partition `has_snapshot`, the tables of the chunks it holds are
+    /// left out.
+    async fn read_partition(
+        &self,
+        partition_key: &str,
+        has_snapshot: bool,
+    ) -> Result<PartitionData> {
+        let mut data = PartitionData::default();
+
+        if let Some(mutable_buffer) = &self.mutable_buffer {
+            let in_mutable_buffer = mutable_buffer
+                .partition_keys()
+                .await
+                .context(ListingMutableBuffer)?
+                .iter()
+                .any(|key| key == partition_key);
+
+            if in_mutable_buffer {
+                self.rollover_partition(partition_key)
+                    .await
+                    .context(RollingOver { partition_key })?;
+
+                for chunk in mutable_buffer.chunks(partition_key).await {
+                    if chunk.time_closed.is_none() {
+                        continue;
+                    }
+                    data.mutable_buffer_chunks.push(chunk.id());
+                    if has_snapshot && self.snapshot_holds(partition_key, chunk.id()) {
+                        continue;
+                    }
+                    let context = || ReadingMutableBufferChunk {
+                        partition_key,
+                        chunk_id: chunk.id(),
+                    };
+                    for stats in chunk.table_stats().context(context())? {
+                        let mut batches = Vec::new();
+                        chunk
+                            .table_to_arrow(&mut batches, &stats.name, &[])
+                            .context(context())?;
+                        data.tables.push((stats.name, batches));
+                    }
+                }
+            }
+        }
+
+        self.read_read_buffer_chunks(partition_key, has_snapshot, &mut data)?;
+        Ok(data)
+    }
+
+    /// Returns true if the snapshot of the partition `partition_key` holds
+    /// the rows of its chunk `chunk_id`: the chunk was loaded from the
+    /// snapshot, or snapshotted
+    fn snapshot_holds(&self, partition_key: &str, chunk_id: u32) -> bool {
+        chunk_id == SNAPSHOT_CHUNK_ID
+            || self
+                .snapshotted_chunks
+                .lock()
+                .expect("mutex poisoned")
+                .contains(&(partition_key.to_string(), chunk_id))
+    }
+
+    /// Reads the chunks of the partition `partition_key` in the read
+    /// buffer into `data`, except those already read from the mutable
+    /// buffer, leaving out the tables of the chunks its snapshot holds if
+    /// it `has_snapshot`
+    fn read_read_buffer_chunks(
+        &self,
+        partition_key: &str,
+        has_snapshot: bool,
+        data: &mut PartitionData,
+    ) -> Result<()> {
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+
+        for chunk_id in read_buffer.chunk_ids(partition_key) {
+            if data.mutable_buffer_chunks.contains(&chunk_id) {
+                continue;
+            }
+            data.read_buffer_chunks.push(chunk_id);
+            if has_snapshot && self.snapshot_holds(partition_key, chunk_id) {
+                continue;
+            }
+            let context = || ReadingReadBufferChunk {
+                partition_key,
+                chunk_id,
+            };
+            let names = read_buffer
+                .table_names(partition_key, &[chunk_id], Predicate::default())
+                .context(context())?;
+            let names = names
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .expect("table names are strings");
+
+            for row in 0..names.len() {
+                let table_name = names.value(row).to_string();
+                let batches: Vec<RecordBatch> = read_buffer
+                    .read_filter(
+                        partition_key,
+                        &table_name,
+                        &[chunk_id],
+                        Predicate::default(),
+                        ColumnSelection::All,
+                    )
+                    .context(context())?
+                    .collect();
+                data.tables.push((table_name, batches));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the record batches of `tables`, copied from another database,
+    /// into the chunk `COPIED_CHUNK_ID` of the partition `partition_key`
+    /// of the read buffer, returning the number of rows loaded
+    fn load_copied_tables(
+        &self,
+        partition_key: &str,
+        tables: Vec<(String, Vec<RecordBatch>)>,
+    ) -> u64 {
+        let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+
+        let mut rows = 0;
+        for (table_name, batches) in tables {
+            for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+                rows += batch.num_rows() as u64;
+                read_buffer.upsert_partition(partition_key, COPIED_CHUNK_ID, &table_name, batch);
+            }
+        }
+        rows
+    }
+
+    /// Snapshots the chunk `COPIED_CHUNK_ID` of the partition
+    /// `partition_key` to `store`, waiting for the snapshot to be written,
+    /// and returns the paths of its objects
+    async fn snapshot_copied_chunk(
+        &self,
+        store: &Arc<ObjectStore>,
+        partition_key: &str,
+    ) -> Result<Vec<String>> {
+        let chunk = DBChunk::new_rb(
+            Arc::clone(&self.read_buffer),
+            partition_key,
+            COPIED_CHUNK_ID,
+        );
+        let size = chunk.size();
+        let (metadata_path, data_path) = snapshot_paths(&self.rules.name);
+        let mut partition_path = data_path.clone();
+        partition_path.push_dir(partition_key);
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let snapshot = snapshot::snapshot_chunk(
+            metadata_path.clone(),
+            partition_path,
+            Arc::clone(store),
+            partition_key,
+            chunk,
+            &self.rules.parquet_config,
+            &self.rules.tag_orders,
+            Some(tx),
+        )
+        .context(Snapshotting { partition_key })?;
+        // the sender is dropped without notifying if the snapshot fails
+        rx.await.ok().context(SnapshotFailed {
+            db_name: &self.rules.name,
+            partition_key,
+        })?;
+        self.mark_chunk_snapshotted(partition_key, COPIED_CHUNK_ID);
+        self.record_lifecycle_event(
+            partition_key,
+            Some(COPIED_CHUNK_ID),
+            LifecycleEventKind::Persisted,
+            size,
+        );
+
+        let mut table_names: Vec<_> = snapshot
+            .partition_meta
+            .tables
+            .iter()
+            .map(|table| table.name.as_str())
+            .collect();
+        table_names.sort_unstable();
+        let mut objects: Vec<_> = table_names
+            .into_iter()
+            .map(|table_name| {
+                store.convert_path(&table_path(&data_path, partition_key, table_name))
+            })
+            .collect();
+        let mut meta_path = metadata_path;
+        meta_path.set_file_name(format!("{}.json", partition_key));
+        objects.push(store.convert_path(&meta_path));
+
+        Ok(objects)
+    }
+
+    /// Drops the chunks of `data` and deletes `snapshot`, once the
+    /// partition `partition_key` was copied to another database
+    async fn drop_copied_partition(
+        &self,
+        partition_key: &str,
+        data: &PartitionData,
+        snapshot: Option<&Snapshot>,
+    ) -> Result<()> {
+        // the chunks loaded into the read buffer from the mutable buffer
+        // are dropped from the read buffer first, so their data isn't kept
+        let loaded = self
+            .read_buffer
+            .read()
+            .expect("mutex poisoned")
+            .chunk_ids(partition_key);
+        for &chunk_id in data.read_buffer_chunks.iter().chain(
+            data.mutable_buffer_chunks
+                .iter()
+                .filter(|chunk_id| loaded.contains(*chunk_id)),
+        ) {
+            self.drop_read_buffer_chunk(partition_key, chunk_id)
+                .await
+                .context(DroppingChunk {
+                    partition_key,
+                    chunk_id,
+                })?;
+        }
+        for &chunk_id in &data.mutable_buffer_chunks {
+            self.drop_mutable_buffer_chunk(partition_key, chunk_id)
+                .await
+                .context(DroppingChunk {
+                    partition_key,
+                    chunk_id,
+                })?;
+        }
+        self.partition_summaries.remove_partition(partition_key);
+
+        if let Some(snapshot) = snapshot {
+            let store = &snapshot.store;
+            let (_, data_path) = snapshot_paths(&self.rules.name);
+            let mut paths = vec![snapshot.path.clone()];
+            paths.extend(
+                snapshot
+                    .meta
+                    .tables
+                    .iter()
+                    .map(|table| table_path(&data_path, partition_key, &table.name)),
+            );
+            for path in &paths {
+                store.delete(path).await.context(DeletingObject {
+                    path: store.convert_path(path),
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::snapshot_chunk;
+    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+    use data_types::{
+        database_rules::{
+            DatabaseRules, ParquetConfig, PartitionTemplate, TagOrders, TemplatePart,
+        },
+        DatabaseName,
+    };
+    use influxdb_line_protocol::parse_lines;
+    use object_store::memory::InMemory;
+    use query::{exec::Executor, frontend::sql::SQLQueryPlanner};
+
+    type TestServer = crate::Server<crate::ConnectionManagerImpl>;
+
+    async fn run_query(db: &Db, query: &str) -> Vec<RecordBatch> {
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let physical_plan = planner.query(db, query, &executor).await.unwrap();
+        collect(physical_plan).await.unwrap()
+    }
+
+    /// Creates a server with the databases `src`, holding a snapshotted
+    /// partition 1970, and `dst`
+    async fn make_server(store: &Arc<ObjectStore>) -> TestServer {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y".to_string())],
+                ..Default::default()
+            },
+            store_locally: true,
+            ..Default::default()
+        };
+        let server = crate::Server::new(crate::ConnectionManagerImpl {}, Arc::clone(store));
+        server.set_id(1);
+        server.create_database("src", rules.clone()).await.unwrap();
+        server.create_database("dst", rules).await.unwrap();
+
+        let lines: Vec<_> = parse_lines("cpu usage=1 10\nmem free=2i 20")
+            .map(|l| l.unwrap())
+            .collect();
+        server.write_lines("src", &lines).await.unwrap();
+        let src = db(&server, "src").await;
+
+        let (metadata_path, mut partition_path) = snapshot_paths("src");
+        partition_path.push_dir("1970");
+        let chunk = src.rollover_partition("1970").await.unwrap();
+        let chunk_id = chunk.id();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        snapshot_chunk(
+            metadata_path,
+            partition_path,
+            Arc::clone(store),
+            "1970",
+            chunk,
+            &ParquetConfig::default(),
+            &TagOrders::default(),
+            Some(tx),
+        )
+        .unwrap();
+        rx.await.unwrap();
+        src.mark_chunk_snapshotted("1970", chunk_id);
+
+        // written after the snapshot, so only in memory
+        let lines: Vec<_> = parse_lines("cpu usage=3 30").map(|l| l.unwrap()).collect();
+        server.write_lines("src", &lines).await.unwrap();
+        server
+    }
+
+    async fn db(server: &TestServer, name: &str) -> Arc<Db> {
+        server.db(&DatabaseName::new(name).unwrap()).await.unwrap()
+    }
+
+    fn snapshot_objects(db_name: &str) -> Vec<ObjectStorePath> {
+        let (mut meta_path, data_path) = snapshot_paths(db_name);
+        meta_path.set_file_name("1970.json");
+        vec![
+            table_path(&data_path, "1970", "cpu"),
+            table_path(&data_path, "1970", "mem"),
+            meta_path,
+        ]
+    }
+
+    #[tokio::test]
+    async fn copies_partition() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = make_server(&store).await;
+        let (src, dst) = (db(&server, "src").await, db(&server, "dst").await);
+
+        let summary = src
+            .copy_partition_to(&dst, "1970", CopyMode::Copy)
+            .await
+            .unwrap();
+        // the chunk that was snapshotted and the one written after it
+        assert_eq!(summary.chunks, 2);
+        assert_eq!(summary.rows, 3);
+        let objects: Vec<_> = snapshot_objects("dst")
+            .iter()
+            .map(|path| store.convert_path(path))
+            .collect();
+        assert_eq!(summary.objects, objects);
+
+        let expected = vec![
+            "+-------+------+",
+            "| usage | time |",
+            "+-------+------+",
+            "| 1     | 10   |",
+            "| 3     | 30   |",
+            "+-------+------+",
+        ];
+        for db in &[&src, &dst] {
+            let batches = run_query(db, "select usage, time from cpu order by time").await;
+            assert_table_eq!(expected, &batches);
+        }
+        assert_eq!(dst.read_buffer_chunks("1970").await.len(), 1);
+        assert_eq!(dst.summary().await.tables["cpu"].rows, 2);
+
+        // the snapshot is in both databases
+        for path in snapshot_objects("src")
+            .iter()
+            .chain(&snapshot_objects("dst"))
+        {
+            store.head(path).await.unwrap();
+        }
+        let meta: PartitionMeta = serde_json::from_slice(
+            &get_bytes(&store, &snapshot_objects("dst")[2])
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(meta.key, "1970");
+        assert_eq!(meta.tables.len(), 2);
+
+        // the copy is snapshotted, with the rows written after the snapshot
+        // of the source, and without those of the snapshotted chunk twice
+        let path = &snapshot_objects("dst")[0];
+        let batches = read_parquet(
+            get_bytes(&store, path).await.unwrap(),
+            &store.convert_path(path),
+        )
+        .unwrap();
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 2);
+        assert!(dst
+            .chunks("1970")
+            .await
+            .iter()
+            .all(|chunk| dst.chunk_persisted("1970", chunk)));
+
+        // the partition can't be copied again
+        let err = src
+            .copy_partition_to(&dst, "1970", CopyMode::Copy)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Partition 1970 already exists in database dst"
+        );
+    }
+
+    #[tokio::test]
+    async fn moves_partition() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = make_server(&store).await;
+        let (src, dst) = (db(&server, "src").await, db(&server, "dst").await);
+
+        // chunks in the read buffer are moved too
+        src.load_chunk_to_read_buffer("1970", 0).await.unwrap();
+
+        let summary = src
+            .copy_partition_to(&dst, "1970", CopyMode::Move)
+            .await
+            .unwrap();
+        assert_eq!(summary.rows, 3);
+
+        let batches = run_query(&dst, "select usage, time from cpu order by time").await;
+        let expected = vec![
+            "+-------+------+",
+            "| usage | time |",
+            "+-------+------+",
+            "| 1     | 10   |",
+            "| 3     | 30   |",
+            "+-------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // only the empty open chunk left by closing the chunks is kept
+        assert!(src.read_buffer_chunks("1970").await.is_empty());
+        assert_eq!(src.mutable_buffer_chunks("1970").await.len(), 1);
+        assert!(src.summary().await.tables.is_empty());
+
+        for path in &snapshot_objects("src") {
+            assert!(store.head(path).await.is_err());
+        }
+        for path in &snapshot_objects("dst") {
+            store.head(path).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn copies_partition_once() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = make_server(&store).await;
+        let (src, dst) = (db(&server, "src").await, db(&server, "dst").await);
+
+        let (first, second) = tokio::join!(
+            src.copy_partition_to(&dst, "1970", CopyMode::Copy),
+            src.copy_partition_to(&dst, "1970", CopyMode::Copy)
+        );
+        let errors: Vec<_> = vec![first, second]
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], Error::PartitionExists { .. }));
+
+        let batches = run_query(&dst, "select usage, time from cpu order by time").await;
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_partitions() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = make_server(&store).await;
+        let (src, dst) = (db(&server, "src").await, db(&server, "dst").await);
+
+        let err = src
+            .copy_partition_to(&dst, "1971", CopyMode::Move)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::PartitionNotFound { .. }));
+
+        let err = src
+            .copy_partition_to(&src, "1970", CopyMode::Copy)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::SameDatabase { .. }));
+    }
+}
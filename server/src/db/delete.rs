@@ -0,0 +1,1011 @@
+//! This module contains the deletion of series: all of the points of a
+//! table whose tags have given values, for example to honour a request
+//! to forget a user who is identified by a tag.
+//!
+//! A deletion is recorded as a tombstone, and every chunk holding points
+//! of the series is compacted without them. Closed mutable buffer chunks
+//! are moved to the read buffer. Read buffer chunks are rebuilt, and the
+//! Parquet files of the snapshots of the database are rewritten along
+//! with the statistics of their metadata. The tombstones are also applied
+//! to snapshots loaded into the read buffer later, in case one was being
+//! written while its series were deleted.
+//!
+//! Deletions are logged in the WAL as replicated writes holding a delete
+//! entry, whose predicate is the JSON of the tag matchers, so they are
+//! replicated and replayed in order with the writes.
+
+use std::sync::Arc;
+
+use arrow_deps::arrow::{
+    array::{Array, BooleanArray, Float64Array, Int64Array, StringArray, UInt64Array},
+    compute::kernels::filter::filter_record_batch,
+    datatypes::DataType,
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use bytes::Bytes;
+use data_types::{
+    data::{delete_to_replicated_write, ReplicatedWrite},
+    database_rules::WriterId,
+    partition_metadata::{self, Column, Partition as PartitionMeta, Statistics, Table},
+    TIME_COLUMN_NAME,
+};
+use futures::TryStreamExt;
+use mutable_buffer::{chunk::Chunk as MBChunk, MutableBufferDb};
+use query::Database;
+use read_buffer::{ColumnSelection, Predicate};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::info;
+
+use super::{sort_key::sort_by_series_key, Db};
+use crate::{
+    snapshot::{parquet_bytes, snapshot_paths},
+    warm::{get_bytes, read_parquet},
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("At least one tag is required to delete series of {}", table))]
+    NoTagMatchers { table: String },
+
+    #[snafu(display("Error listing partitions of the mutable buffer: {}", source))]
+    ListingPartitions {
+        source: mutable_buffer::database::Error,
+    },
+
+    #[snafu(display(
+        "Error compacting partition {} of the mutable buffer: {}",
+        partition_key,
+        source
+    ))]
+    CompactingMutableBuffer {
+        partition_key: String,
+        source: mutable_buffer::database::Error,
+    },
+
+    #[snafu(display(
+        "Error reading chunk {} of partition {} of the mutable buffer: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    ReadingMutableBufferChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: mutable_buffer::chunk::Error,
+    },
+
+    #[snafu(display(
+        "Error reading chunk {} of partition {} of the read buffer: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    ReadingReadBufferChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: read_buffer::Error,
+    },
+
+    #[snafu(display("Column {} of table {} is not a tag column", column, table))]
+    InvalidTagColumn { table: String, column: String },
+
+    #[snafu(display("Error removing rows of table {}: {}", table, source))]
+    RemovingRows { table: String, source: ArrowError },
+
+    #[snafu(display("Error sorting table {} by its series keys: {}", table, source))]
+    SortingRows { table: String, source: ArrowError },
+
+    #[snafu(display("Error listing snapshots in object store: {}", source))]
+    ListingSnapshots { source: object_store::Error },
+
+    #[snafu(display("Error reading snapshot: {}", source))]
+    ReadingSnapshot { source: crate::warm::Error },
+
+    #[snafu(display("Error decoding snapshot metadata {}: {}", path, source))]
+    DecodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Can't rewrite snapshot {}: {}", path, source))]
+    UnsupportedFormat {
+        path: String,
+        source: partition_metadata::Error,
+    },
+
+    #[snafu(display("Error encoding snapshot {}: {}", path, source))]
+    EncodingSnapshot {
+        path: String,
+        source: crate::snapshot::Error,
+    },
+
+    #[snafu(display("Error writing snapshot {}: {}", path, source))]
+    WritingSnapshot {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error encoding snapshot metadata {}: {}", path, source))]
+    EncodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error encoding the deletion of series of {}: {}", table, source))]
+    EncodingTombstone {
+        table: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error decoding the deletion of series of {}: {}", table, source))]
+    DecodingTombstone {
+        table: String,
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Matches the points whose tag `tag` has the value `value`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagMatcher {
+    pub tag: String,
+    pub value: String,
+}
+
+impl TagMatcher {
+    pub fn new(tag: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            tag: tag.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// Records that the points of `table` matching all of `tag_matchers` were
+/// deleted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeriesTombstone {
+    pub table: String,
+    pub tag_matchers: Vec<TagMatcher>,
+}
+
+impl SeriesTombstone {
+    /// Returns the replicated write logging the deletion, the write
+    /// `sequence` of `writer`
+    pub fn to_replicated_write(&self, writer: WriterId, sequence: u64) -> Result<ReplicatedWrite> {
+        let predicate = serde_json::to_string(&self.tag_matchers)
+            .context(EncodingTombstone { table: &self.table })?;
+        Ok(delete_to_replicated_write(
+            writer,
+            sequence,
+            &self.table,
+            &predicate,
+        ))
+    }
+
+    /// Decodes the deletion of a delete entry of a replicated write
+    fn from_delete(table: &str, predicate: &str) -> Result<Self> {
+        let tag_matchers = serde_json::from_str(predicate).context(DecodingTombstone { table })?;
+        Ok(Self {
+            table: table.to_string(),
+            tag_matchers,
+        })
+    }
+
+    /// Returns the rows of `batch` of the table `table_name` that the
+    /// tombstone doesn't match, and the number of rows it matched. Rows
+    /// without a value for one of the tags are not matched.
+    pub fn remove_rows(
+        &self,
+        table_name: &str,
+        batch: &RecordBatch,
+    ) -> Result<(RecordBatch, usize)> {
+        if table_name != self.table {
+            return Ok((batch.clone(), 0));
+        }
+
+        let mut matched = vec![true; batch.num_rows()];
+        for matcher in &self.tag_matchers {
+            let index = match batch.schema().index_of(&matcher.tag) {
+                Ok(index) => index,
+                Err(_) => return Ok((batch.clone(), 0)),
+            };
+            let column = batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .context(InvalidTagColumn {
+                    table: &self.table,
+                    column: &matcher.tag,
+                })?;
+
+            for (row, matched) in matched.iter_mut().enumerate() {
+                *matched = *matched && column.is_valid(row) && column.value(row) == matcher.value;
+            }
+        }
+
+        let removed = matched.iter().filter(|&&matched| matched).count();
+        if removed == 0 {
+            return Ok((batch.clone(), 0));
+        }
+        let keep: BooleanArray = matched.into_iter().map(|matched| Some(!matched)).collect();
+        let batch =
+            filter_record_batch(batch, &keep).context(RemovingRows { table: &self.table })?;
+        Ok((batch, removed))
+    }
+}
+
+/// The number of points `Db::delete_series` removed from each place
+/// the database keeps them
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct DeleteSummary {
+    pub mutable_buffer_rows: u64,
+    pub read_buffer_rows: u64,
+    pub snapshot_rows: u64,
+}
+
+impl Db {
+    /// Deletes every point of `table` whose tags match all of
+    /// `tag_matchers` from the mutable buffer, the read buffer and the
+    /// snapshots of the database
+    pub async fn delete_series(
+        &self,
+        table: &str,
+        tag_matchers: &[TagMatcher],
+    ) -> Result<DeleteSummary> {
+        ensure!(!tag_matchers.is_empty(), NoTagMatchers { table });
+
+        let tombstone = SeriesTombstone {
+            table: table.to_string(),
+            tag_matchers: tag_matchers.to_vec(),
+        };
+        self.series_tombstones
+            .write()
+            .expect("mutex poisoned")
+            .push(tombstone.clone());
+
+        // The read buffer goes first, so the mutable buffer chunks moved
+        // into it aren't counted twice
+        let summary = DeleteSummary {
+            read_buffer_rows: self.delete_from_read_buffer(&tombstone)?,
+            mutable_buffer_rows: self.delete_from_mutable_buffer(&tombstone).await?,
+            snapshot_rows: self.delete_from_snapshots(&tombstone).await?,
+        };
+        self.release_partition_quotas().await;
+
+        info!(
+            db_name = self.rules.name.as_str(),
+            table,
+            mutable_buffer_rows = summary.mutable_buffer_rows,
+            read_buffer_rows = summary.read_buffer_rows,
+            snapshot_rows = summary.snapshot_rows,
+            "Deleted series"
+        );
+        Ok(summary)
+    }
+
+    /// Returns the replicated write deleting every point of `table` whose
+    /// tags match all of `tag_matchers`, sequenced as the next write of
+    /// `writer`. Applying it to the database calls `delete_series`.
+    pub(crate) fn delete_series_write(
+        &self,
+        writer: WriterId,
+        table: &str,
+        tag_matchers: &[TagMatcher],
+    ) -> Result<ReplicatedWrite> {
+        ensure!(!tag_matchers.is_empty(), NoTagMatchers { table });
+
+        let tombstone = SeriesTombstone {
+            table: table.to_string(),
+            tag_matchers: tag_matchers.to_vec(),
+        };
+        tombstone.to_replicated_write(writer, self.next_sequence())
+    }
+
+    /// Applies the deletions of the replicated write `write`, which was
+    /// received or replayed from a WAL, and records it as stored
+    pub(crate) async fn apply_delete_write(
+        &self,
+        write: &ReplicatedWrite,
+    ) -> Result<DeleteSummary> {
+        let mut summary = DeleteSummary::default();
+        for (table, predicate) in write.deletes() {
+            let tombstone = SeriesTombstone::from_delete(table, predicate)?;
+            let deleted = self
+                .delete_series(&tombstone.table, &tombstone.tag_matchers)
+                .await?;
+            summary.mutable_buffer_rows += deleted.mutable_buffer_rows;
+            summary.read_buffer_rows += deleted.read_buffer_rows;
+            summary.snapshot_rows += deleted.snapshot_rows;
+        }
+
+        let (writer, sequence) = write.writer_and_sequence();
+        self.stored_writes.insert(writer, sequence);
+        Ok(summary)
+    }
+
+    /// Returns the rows of `batch` of the table `table_name` that were
+    /// not deleted by `delete_series`
+    pub(crate) fn remove_deleted_rows(
+        &self,
+        table_name: &str,
+        batch: RecordBatch,
+    ) -> Result<RecordBatch> {
+        let tombstones = self.series_tombstones.read().expect("mutex poisoned");
+        tombstones.iter().try_fold(batch, |batch, tombstone| {
+            Ok(tombstone.remove_rows(table_name, &batch)?.0)
+        })
+    }
+
+    /// Returns true if series of the table `table_name` were deleted by
+    /// `delete_series`
+    pub(crate) fn has_deleted_series(&self, table_name: &str) -> bool {
+        self.series_tombstones
+            .read()
+            .expect("mutex poisoned")
+            .iter()
+            .any(|tombstone| tombstone.table == table_name)
+    }
+
+    /// Rebuilds the read buffer chunks holding rows `tombstone` matches
+    /// without them
+    fn delete_from_read_buffer(&self, tombstone: &SeriesTombstone) -> Result<u64> {
+        let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+        let partition_keys: Vec<_> = read_buffer.partition_keys().into_iter().cloned().collect();
+
+        let mut deleted = 0;
+        for partition_key in &partition_keys {
+            for chunk_id in read_buffer.chunk_ids(partition_key) {
+                let context = || ReadingReadBufferChunk {
+                    partition_key,
+                    chunk_id,
+                };
+                let (rows, _) = read_buffer
+                    .table_rows(partition_key, chunk_id, &tombstone.table)
+                    .context(context())?;
+                if rows == 0 {
+                    continue;
+                }
+
+                // the read buffer can't remove rows from a chunk, so all of
+                // its tables are read and loaded into a new one
+                let names = read_buffer
+                    .table_names(partition_key, &[chunk_id], Predicate::default())
+                    .context(context())?;
+                let names = names
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("table names are strings");
+
+                let mut tables = Vec::with_capacity(names.len());
+                let mut removed = 0;
+                for row in 0..names.len() {
+                    let table_name = names.value(row).to_string();
+                    let mut batches = Vec::new();
+                    for batch in read_buffer
+                        .read_filter(
+                            partition_key,
+                            &table_name,
+                            &[chunk_id],
+                            Predicate::default(),
+                            ColumnSelection::All,
+                        )
+                        .context(context())?
+                    {
+                        let (batch, rows) = tombstone.remove_rows(&table_name, &batch)?;
+                        removed += rows;
+                        batches.push(batch);
+                    }
+                    tables.push((table_name, batches));
+                }
+                if removed == 0 {
+                    continue;
+                }
+
+                let size_before = read_buffer
+                    .chunk_size(partition_key, chunk_id)
+                    .unwrap_or_default();
+                read_buffer
+                    .drop_chunk(partition_key, chunk_id)
+                    .context(context())?;
+                for (table_name, batches) in tables {
+                    for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+                        read_buffer.upsert_partition(partition_key, chunk_id, &table_name, batch);
+                    }
+                }
+                self.record_read_buffer_rewrite(&read_buffer, partition_key, chunk_id, size_before);
+                deleted += removed as u64;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Moves the mutable buffer chunks holding rows `tombstone` matches to
+    /// the read buffer without them, closing the open chunk first if it
+    /// holds any
+    async fn delete_from_mutable_buffer(&self, tombstone: &SeriesTombstone) -> Result<u64> {
+        let mutable_buffer = match &self.mutable_buffer {
+            Some(mutable_buffer) => mutable_buffer,
+            None => return Ok(0),
+        };
+        let partition_keys = mutable_buffer
+            .partition_keys()
+            .await
+            .context(ListingPartitions)?;
+
+        let mut deleted = 0;
+        for partition_key in &partition_keys {
+            let mut matching = false;
+            for chunk in mutable_buffer.chunks(partition_key).await {
+                let (_, removed) = remove_chunk_rows(partition_key, &chunk, tombstone)?;
+                matching = matching || removed > 0;
+            }
+            if !matching {
+                continue;
+            }
+
+            // Only the chunks up to the one just closed are compacted. The
+            // points of the series written after the deletion are kept.
+            let closed = mutable_buffer
+                .rollover_partition(partition_key)
+                .await
+                .context(CompactingMutableBuffer { partition_key })?;
+            for chunk in mutable_buffer.chunks(partition_key).await {
+                if chunk.id() > closed.id() {
+                    continue;
+                }
+                deleted += self
+                    .compact_mutable_buffer_chunk(mutable_buffer, partition_key, &chunk, tombstone)
+                    .await?;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Replaces the closed mutable buffer chunk `chunk` by a read buffer
+    /// chunk without the rows `tombstone` matches, if it has any
+    async fn compact_mutable_buffer_chunk(
+        &self,
+        mutable_buffer: &MutableBufferDb,
+        partition_key: &str,
+        chunk: &Arc<MBChunk>,
+        tombstone: &SeriesTombstone,
+    ) -> Result<u64> {
+        let (tables, removed) = remove_chunk_rows(partition_key, chunk, tombstone)?;
+        if removed == 0 {
+            return Ok(0);
+        }
+        let tables = tables
+            .into_iter()
+            .map(|(table_name, batches)| {
+                let batches = batches
+                    .into_iter()
+                    .map(|batch| sort_by_series_key(batch, &table_name, &self.rules.tag_orders))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context(SortingRows { table: &table_name })?;
+                Ok((table_name, batches))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        {
+            let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+            // the chunk may have been loaded into the read buffer already
+            if read_buffer.chunk_ids(partition_key).contains(&chunk.id()) {
+                read_buffer.drop_chunk(partition_key, chunk.id()).context(
+                    ReadingReadBufferChunk {
+                        partition_key,
+                        chunk_id: chunk.id(),
+                    },
+                )?;
+            }
+            for (table_name, batches) in tables {
+                for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+                    read_buffer.upsert_partition(partition_key, chunk.id(), &table_name, batch);
+                }
+            }
+        }
+
+        mutable_buffer
+            .drop_chunk(partition_key, chunk.id())
+            .await
+            .context(CompactingMutableBuffer { partition_key })?;
+        self.record_mutable_buffer_compaction(partition_key, chunk);
+
+        Ok(removed as u64)
+    }
+
+    /// Rewrites the Parquet files of the snapshots holding rows
+    /// `tombstone` matches without them. The statistics of the table in
+    /// the metadata of the snapshots are computed again from the rows that
+    /// are left, so they don't hold the values of the deleted series.
+    async fn delete_from_snapshots(&self, tombstone: &SeriesTombstone) -> Result<u64> {
+        let store = match &self.object_store {
+            Some((store, _)) => store,
+            None => return Ok(0),
+        };
+        let (metadata_path, data_path) = snapshot_paths(&self.rules.name);
+        let _rewrite = self.snapshot_rewrites.lock().await;
+
+        let paths: Vec<Vec<_>> = store
+            .list(Some(&metadata_path))
+            .await
+            .context(ListingSnapshots)?
+            .try_collect()
+            .await
+            .context(ListingSnapshots)?;
+
+        let mut deleted = 0;
+        for meta_path in paths.into_iter().flatten() {
+            let data = get_bytes(store, &meta_path)
+                .await
+                .context(ReadingSnapshot)?;
+            let mut meta: PartitionMeta =
+                serde_json::from_slice(&data).context(DecodingMetadata {
+                    path: store.convert_path(&meta_path),
+                })?;
+            meta.format_version
+                .check_rewritable()
+                .context(UnsupportedFormat {
+                    path: store.convert_path(&meta_path),
+                })?;
+            if !meta
+                .tables
+                .iter()
+                .any(|table| table.name == tombstone.table)
+            {
+                continue;
+            }
+
+            let mut location = data_path.clone();
+            location.push_dir(&meta.key);
+            location.set_file_name(format!("{}.parquet", tombstone.table));
+            let path = store.convert_path(&location);
+            let data = get_bytes(store, &location).await.context(ReadingSnapshot)?;
+            let batches = read_parquet(data, &path).context(ReadingSnapshot)?;
+
+            let mut removed = 0;
+            let mut kept = Vec::with_capacity(batches.len());
+            for batch in &batches {
+                let (batch, rows) = tombstone.remove_rows(&tombstone.table, batch)?;
+                removed += rows;
+                kept.push(batch);
+            }
+            if removed == 0 {
+                continue;
+            }
+
+            let table_meta = table_meta(&tombstone.table, &kept);
+            let data = Bytes::from(
+                parquet_bytes(kept, &self.rules.parquet_config)
+                    .context(EncodingSnapshot { path: &path })?,
+            );
+            let checksum = crc32fast::hash(&data);
+            let len = data.len();
+            store
+                .put(
+                    &location,
+                    futures::stream::once(async move { Ok(data) }),
+                    len,
+                )
+                .await
+                .context(WritingSnapshot { path: &path })?;
+
+            // the rewritten file has new statistics and a new checksum
+            for table in meta
+                .tables
+                .iter_mut()
+                .filter(|table| table.name == tombstone.table)
+            {
+                *table = table_meta.clone();
+            }
+            if meta.checksums.contains_key(&tombstone.table) {
+                meta.checksums.insert(tombstone.table.clone(), checksum);
+            }
+            let path = store.convert_path(&meta_path);
+            let data =
+                Bytes::from(serde_json::to_vec(&meta).context(EncodingMetadata { path: &path })?);
+            let len = data.len();
+            store
+                .put(
+                    &meta_path,
+                    futures::stream::once(async move { Ok(data) }),
+                    len,
+                )
+                .await
+                .context(WritingSnapshot { path })?;
+            deleted += removed as u64;
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// Returns the metadata of the table `name` stored as `batches`: the
+/// statistics of its columns, in the order of the columns of the batches,
+/// and its time range. A column left without values has a count of zero,
+/// and the default value as its minimum and maximum.
+fn table_meta(name: &str, batches: &[RecordBatch]) -> Table {
+    let mut columns: Vec<Option<Column>> = Vec::new();
+    let mut rows = 0;
+    for batch in batches {
+        rows += batch.num_rows();
+        columns.resize(batch.num_columns(), None);
+        for (index, stats) in columns.iter_mut().enumerate() {
+            add_column_stats(stats, batch.column(index).as_ref());
+        }
+    }
+
+    let time_range = batches.first().and_then(|batch| {
+        let index = batch.schema().index_of(TIME_COLUMN_NAME).ok()?;
+        match columns.get(index)? {
+            Some(Column::I64(stats)) if stats.count as usize == rows => {
+                Some((stats.min, stats.max))
+            }
+            _ => None,
+        }
+    });
+
+    let types = batches
+        .first()
+        .map(|batch| {
+            let schema = batch.schema();
+            schema
+                .fields()
+                .iter()
+                .map(|field| field.data_type().clone())
+                .collect()
+        })
+        .unwrap_or_else(Vec::new);
+    let columns = columns
+        .into_iter()
+        .zip(types)
+        .map(|(stats, data_type)| stats.unwrap_or_else(|| empty_column_stats(&data_type)))
+        .collect();
+
+    Table {
+        name: name.to_string(),
+        columns,
+        time_range,
+    }
+}
+
+/// Adds the non-null values of `array` to `stats`
+fn add_column_stats(stats: &mut Option<Column>, array: &dyn Array) {
+    fn add<T, A>(stats: &mut Option<Statistics<T>>, array: &A, value: impl Fn(&A, usize) -> T)
+    where
+        T: PartialEq + PartialOrd + std::fmt::Debug + std::fmt::Display + Clone,
+        A: Array,
+    {
+        for row in (0..array.len()).filter(|&row| array.is_valid(row)) {
+            let value = value(array, row);
+            match stats {
+                Some(stats) => stats.update(value),
+                None => *stats = Some(Statistics::new(value)),
+            }
+        }
+    }
+
+    let any = array.as_any();
+    match array.data_type() {
+        DataType::Utf8 => {
+            let array = any.downcast_ref::<StringArray>().expect("utf8 array");
+            let mut column = match stats.take() {
+                Some(Column::String(stats)) => Some(stats),
+                _ => None,
+            };
+            add(&mut column, array, |a, row| a.value(row).to_string());
+            *stats = column.map(Column::String);
+        }
+        DataType::Int64 => {
+            let array = any.downcast_ref::<Int64Array>().expect("int64 array");
+            let mut column = match stats.take() {
+                Some(Column::I64(stats)) => Some(stats),
+                _ => None,
+            };
+            add(&mut column, array, |a, row| a.value(row));
+            *stats = column.map(Column::I64);
+        }
+        DataType::UInt64 => {
+            let array = any.downcast_ref::<UInt64Array>().expect("uint64 array");
+            let mut column = match stats.take() {
+                Some(Column::U64(stats)) => Some(stats),
+                _ => None,
+            };
+            add(&mut column, array, |a, row| a.value(row));
+            *stats = column.map(Column::U64);
+        }
+        DataType::Float64 => {
+            let array = any.downcast_ref::<Float64Array>().expect("float64 array");
+            let mut column = match stats.take() {
+                Some(Column::F64(stats)) => Some(stats),
+                _ => None,
+            };
+            add(&mut column, array, |a, row| a.value(row));
+            *stats = column.map(Column::F64);
+        }
+        DataType::Boolean => {
+            let array = any.downcast_ref::<BooleanArray>().expect("boolean array");
+            let mut column = match stats.take() {
+                Some(Column::Bool(stats)) => Some(stats),
+                _ => None,
+            };
+            add(&mut column, array, |a, row| a.value(row));
+            *stats = column.map(Column::Bool);
+        }
+        // the mutable buffer doesn't store other types
+        _ => {}
+    }
+}
+
+/// Returns the statistics of a column of type `data_type` without values
+fn empty_column_stats(data_type: &DataType) -> Column {
+    fn empty<T>(value: T) -> Statistics<T>
+    where
+        T: PartialEq + PartialOrd + std::fmt::Debug + std::fmt::Display + Clone,
+    {
+        Statistics {
+            min: value.clone(),
+            max: value,
+            count: 0,
+        }
+    }
+
+    match data_type {
+        DataType::Int64 => Column::I64(empty(0)),
+        DataType::UInt64 => Column::U64(empty(0)),
+        DataType::Float64 => Column::F64(empty(0.0)),
+        DataType::Boolean => Column::Bool(empty(false)),
+        _ => Column::String(empty(String::new())),
+    }
+}
+
+/// Returns the tables of the mutable buffer chunk `chunk` without the rows
+/// `tombstone` matches, and the number of rows it matched
+#[allow(clippy::type_complexity)]
+fn remove_chunk_rows(
+    partition_key: &str,
+    chunk: &MBChunk,
+    tombstone: &SeriesTombstone,
+) -> Result<(Vec<(String, Vec<RecordBatch>)>, usize)> {
+    let context = || ReadingMutableBufferChunk {
+        partition_key,
+        chunk_id: chunk.id(),
+    };
+
+    let mut tables = Vec::new();
+    let mut removed = 0;
+    for stats in chunk.table_stats().context(context())? {
+        let mut batches = Vec::new();
+        chunk
+            .table_to_arrow(&mut batches, &stats.name, &[])
+            .context(context())?;
+        let batches = batches
+            .iter()
+            .map(|batch| {
+                let (batch, rows) = tombstone.remove_rows(&stats.name, batch)?;
+                removed += rows;
+                Ok(batch)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        tables.push((stats.name, batches));
+    }
+
+    Ok((tables, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::test_util::make_db, snapshot::snapshot_chunk};
+    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+    use data_types::{
+        database_rules::{
+            DatabaseRules, ParquetConfig, PartitionTemplate, TagOrders, TemplatePart,
+        },
+        DatabaseName,
+    };
+    use influxdb_line_protocol::parse_lines;
+    use object_store::{memory::InMemory, ObjectStore};
+    use query::{
+        exec::Executor, frontend::sql::SQLQueryPlanner, test::TestLPWriter, PartitionChunk,
+    };
+    use read_buffer::Database as ReadBufferDb;
+
+    const LP: &str = "cpu,customer=alice,host=a usage=1 10\n\
+                      cpu,customer=bob,host=a usage=2 20\n\
+                      cpu,customer=alice,host=b usage=3 30\n\
+                      mem,customer=alice free=4i 40";
+
+    async fn run_query(db: &Db, query: &str) -> Vec<RecordBatch> {
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let physical_plan = planner.query(db, query, &executor).await.unwrap();
+        collect(physical_plan).await.unwrap()
+    }
+
+    async fn customers(db: &Db) -> Vec<RecordBatch> {
+        run_query(db, "select customer, host, usage from cpu order by time").await
+    }
+
+    #[tokio::test]
+    async fn deletes_series_from_memory() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, LP).await.unwrap();
+
+        // one chunk in the read buffer, and an open one in the mutable buffer
+        let partition_key = "1970-01-01T00";
+        let chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, chunk.id())
+            .await
+            .unwrap();
+        writer.write_lp_string(&db, LP).await.unwrap();
+
+        let summary = db
+            .delete_series("cpu", &[TagMatcher::new("customer", "alice")])
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            DeleteSummary {
+                mutable_buffer_rows: 2,
+                read_buffer_rows: 2,
+                snapshot_rows: 0,
+            }
+        );
+
+        let expected = vec![
+            "+----------+------+-------+",
+            "| customer | host | usage |",
+            "+----------+------+-------+",
+            "| bob      | a    | 2     |",
+            "| bob      | a    | 2     |",
+            "+----------+------+-------+",
+        ];
+        assert_table_eq!(expected, &customers(&db).await);
+        assert_eq!(db.summary().await.tables["cpu"].rows, 2);
+
+        // other tables are left alone
+        let batches = run_query(&db, "select count(*) as n from mem").await;
+        let expected = vec!["+---+", "| n |", "+---+", "| 2 |", "+---+"];
+        assert_table_eq!(expected, &batches);
+
+        // points of the series written after the deletion are kept
+        writer
+            .write_lp_string(&db, "cpu,customer=alice,host=c usage=5 50")
+            .await
+            .unwrap();
+        let batches = run_query(
+            &db,
+            "select count(*) as n from cpu where customer = 'alice'",
+        )
+        .await;
+        let expected = vec!["+---+", "| n |", "+---+", "| 1 |", "+---+"];
+        assert_table_eq!(expected, &batches);
+
+        let err = db.delete_series("cpu", &[]).await.unwrap_err();
+        assert!(matches!(err, Error::NoTagMatchers { .. }));
+    }
+
+    #[tokio::test]
+    async fn deletes_series_from_snapshots() {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y".to_string())],
+                ..Default::default()
+            },
+            store_locally: true,
+            ..Default::default()
+        };
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = crate::Server::new(crate::ConnectionManagerImpl {}, Arc::clone(&store));
+        server.set_id(1);
+        server.create_database("mydb", rules).await.unwrap();
+        let lines: Vec<_> = parse_lines(LP).map(|l| l.unwrap()).collect();
+        server.write_lines("mydb", &lines).await.unwrap();
+        let db = server
+            .db(&DatabaseName::new("mydb").unwrap())
+            .await
+            .unwrap();
+
+        let (metadata_path, data_path) = snapshot_paths("mydb");
+        let mut partition_path = data_path.clone();
+        partition_path.push_dir("1970");
+        let chunk = db.rollover_partition("1970").await.unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        snapshot_chunk(
+            metadata_path.clone(),
+            partition_path,
+            Arc::clone(&store),
+            "1970",
+            chunk,
+            &ParquetConfig::default(),
+            &TagOrders::default(),
+            Some(tx),
+        )
+        .unwrap();
+        rx.await.unwrap();
+
+        let summary = db
+            .delete_series(
+                "cpu",
+                &[
+                    TagMatcher::new("customer", "alice"),
+                    TagMatcher::new("host", "a"),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(summary.mutable_buffer_rows, 1);
+        assert_eq!(summary.snapshot_rows, 1);
+
+        let expected = vec![
+            "+----------+------+-------+",
+            "| customer | host | usage |",
+            "+----------+------+-------+",
+            "| bob      | a    | 2     |",
+            "| alice    | b    | 3     |",
+            "+----------+------+-------+",
+        ];
+        assert_table_eq!(expected, &customers(&db).await);
+
+        // the statistics of the snapshot no longer hold the series
+        let mut meta_path = metadata_path.clone();
+        meta_path.set_file_name("1970.json");
+        let data = get_bytes(&store, &meta_path).await.unwrap();
+        let meta: PartitionMeta = serde_json::from_slice(&data).unwrap();
+        let cpu = meta.tables.iter().find(|t| t.name == "cpu").unwrap();
+        assert_eq!(cpu.time_range, Some((20, 30)));
+        assert!(cpu.columns.contains(&Column::F64(Statistics {
+            min: 2.0,
+            max: 3.0,
+            count: 2,
+        })));
+
+        // a replica warmed from the snapshots doesn't see the series either
+        let replica = Db::new(DatabaseRules::default(), None, ReadBufferDb::new(), None);
+        replica
+            .warm(&store, &metadata_path, &data_path, None, &[])
+            .await
+            .unwrap();
+        assert_table_eq!(expected, &customers(&replica).await);
+    }
+
+    #[tokio::test]
+    async fn applies_delete_writes() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer.write_lp_string(&db, LP).await.unwrap();
+
+        let write = db
+            .delete_series_write(7, "cpu", &[TagMatcher::new("customer", "alice")])
+            .unwrap();
+        assert_eq!(write.deletes().len(), 1);
+        assert!(!db.write_stored(7, write.writer_and_sequence().1));
+
+        let summary = db.apply_delete_write(&write).await.unwrap();
+        assert_eq!(summary.mutable_buffer_rows, 2);
+        assert!(db.write_stored(7, write.writer_and_sequence().1));
+
+        let expected = vec![
+            "+----------+------+-------+",
+            "| customer | host | usage |",
+            "+----------+------+-------+",
+            "| bob      | a    | 2     |",
+            "+----------+------+-------+",
+        ];
+        assert_table_eq!(expected, &customers(&db).await);
+
+        let err = db.delete_series_write(7, "cpu", &[]).unwrap_err();
+        assert!(matches!(err, Error::NoTagMatchers { .. }));
+    }
+}
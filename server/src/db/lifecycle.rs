@@ -0,0 +1,168 @@
+//! This module contains the methods of `Db` recording the lifecycle events
+//! of its partitions, for the `system.lifecycle_events` table: when
+//! partitions were created, and when their chunks were closed, persisted,
+//! compacted, evicted or dropped, with the size of the chunks at the time.
+//! The mutable buffer records the creation of partitions and the closing
+//! of chunks itself, in the `LifecycleLog` it shares with the `Db`.
+
+use mutable_buffer::chunk::Chunk as MBChunk;
+use query::LifecycleEventKind;
+
+use super::Db;
+
+impl Db {
+    /// Records that `kind` happened to chunk `chunk_id` of the partition
+    /// `partition_key` (or to the whole partition if `chunk_id` is not
+    /// set), whose size was then `size` bytes
+    pub fn record_lifecycle_event(
+        &self,
+        partition_key: &str,
+        chunk_id: Option<u32>,
+        kind: LifecycleEventKind,
+        size: u64,
+    ) {
+        self.lifecycle
+            .record_now(partition_key, chunk_id, kind, size)
+    }
+
+    /// Returns the size in bytes of chunk `chunk_id` of the partition
+    /// `partition_key` in the read buffer, if it is loaded there
+    pub(crate) fn read_buffer_chunk_size(&self, partition_key: &str, chunk_id: u32) -> Option<u64> {
+        self.read_buffer
+            .read()
+            .expect("mutex poisoned")
+            .chunk_size(partition_key, chunk_id)
+    }
+
+    /// Records that chunk `chunk_id` of the partition `partition_key` was
+    /// rewritten in the read buffer, or dropped if none of its data was
+    /// kept
+    pub(crate) fn record_read_buffer_rewrite(
+        &self,
+        read_buffer: &read_buffer::Database,
+        partition_key: &str,
+        chunk_id: u32,
+        size_before: u64,
+    ) {
+        self.summarize_read_buffer_chunk(read_buffer, partition_key, chunk_id);
+        match read_buffer.chunk_size(partition_key, chunk_id) {
+            Some(size) => self.record_lifecycle_event(
+                partition_key,
+                Some(chunk_id),
+                LifecycleEventKind::Compacted,
+                size,
+            ),
+            None => self.record_lifecycle_event(
+                partition_key,
+                Some(chunk_id),
+                LifecycleEventKind::Dropped,
+                size_before,
+            ),
+        }
+    }
+
+    /// Records that the mutable buffer chunk `chunk` of the partition
+    /// `partition_key` was dropped after the data it kept was moved to
+    /// the read buffer
+    pub(crate) fn record_mutable_buffer_compaction(&self, partition_key: &str, chunk: &MBChunk) {
+        let size = chunk.size() as u64;
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+        self.summarize_read_buffer_chunk(&read_buffer, partition_key, chunk.id());
+        match read_buffer.chunk_size(partition_key, chunk.id()) {
+            Some(compacted) => {
+                self.record_lifecycle_event(
+                    partition_key,
+                    Some(chunk.id()),
+                    LifecycleEventKind::Compacted,
+                    compacted,
+                );
+                self.record_lifecycle_event(
+                    partition_key,
+                    Some(chunk.id()),
+                    LifecycleEventKind::Evicted,
+                    size,
+                );
+            }
+            None => self.record_lifecycle_event(
+                partition_key,
+                Some(chunk.id()),
+                LifecycleEventKind::Dropped,
+                size,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{DatabaseRules, PartitionTemplate, TemplatePart},
+    };
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use query::{exec::Executor, frontend::sql::SQLQueryPlanner, Database};
+    use read_buffer::Database as ReadBufferDb;
+
+    #[tokio::test]
+    async fn records_partition_lifecycle() {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Column("region".to_string())],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("lifecycle")),
+            ReadBufferDb::new(),
+            None,
+        );
+
+        let lines: Vec<_> = parse_lines("cpu,region=west usage=1 10")
+            .map(|l| l.unwrap())
+            .collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &db.rules);
+        db.store_replicated_write(&write).await.unwrap();
+        db.record_stored_write(&write).await;
+
+        let partition_key = "region_west";
+        let chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, chunk.id())
+            .await
+            .unwrap();
+        db.drop_read_buffer_chunk(partition_key, chunk.id())
+            .await
+            .unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let query = "select partition_key, chunk_id, event from system.lifecycle_events";
+        let plan = planner.query(&db, query, &executor).await.unwrap();
+        let batches = collect(plan).await.unwrap();
+
+        let expected = vec![
+            "+---------------+----------+-----------+",
+            "| partition_key | chunk_id | event     |",
+            "+---------------+----------+-----------+",
+            "| region_west   |          | created   |",
+            "| region_west   | 0        | closed    |",
+            "| region_west   | 0        | compacted |",
+            "| region_west   | 0        | evicted   |",
+            "| region_west   | 0        | dropped   |",
+            "+---------------+----------+-----------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // every event but the creation of the partition is about a chunk
+        // holding the row written
+        let events = db.lifecycle_events();
+        assert!(events[1..].iter().all(|event| event.size > 0));
+    }
+}
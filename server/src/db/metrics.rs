@@ -0,0 +1,103 @@
+//! This module renders the metrics of the mutable buffers of the
+//! databases of a server in the Prometheus text format: how often the
+//! open chunk of a partition was closed, by the reason it was closed. A
+//! growing count of dictionary rollovers usually means a tag of high
+//! cardinality fills the dictionaries of the chunks.
+
+use std::{fmt::Write, sync::Arc};
+
+use data_types::DatabaseName;
+
+use super::Db;
+use crate::latency::escape_label_value;
+
+/// Renders the number of chunk rollovers of the mutable buffers of the
+/// databases `dbs` as a counter labelled by the reason of the rollover
+pub(crate) async fn render(dbs: &[(DatabaseName<'static>, Arc<Db>)]) -> String {
+    let name = "iox_mutable_buffer_chunk_rollovers_total";
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# HELP {} Open chunks of the mutable buffer closed to make room for writes",
+        name
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+
+    for (db_name, db) in dbs {
+        let mutable_buffer = match db.mutable_buffer.as_ref() {
+            Some(mutable_buffer) => mutable_buffer,
+            None => continue,
+        };
+
+        let rollovers = [
+            ("dictionary", mutable_buffer.dictionary_rollovers().await),
+            ("size", mutable_buffer.size_rollovers().await),
+        ];
+        for (reason, count) in &rollovers {
+            writeln!(
+                out,
+                "{}{{db_name=\"{}\",reason=\"{}\"}} {}",
+                name,
+                escape_label_value(db_name),
+                reason,
+                count
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{ChunkDictionaryConfig, DatabaseRules},
+    };
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use read_buffer::Database as ReadBufferDb;
+
+    #[tokio::test]
+    async fn renders_dictionary_rollovers() {
+        let rules = DatabaseRules {
+            chunk_dictionary: ChunkDictionaryConfig {
+                max_entries: Some(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mutable_buffer = MutableBufferDb::new("rollovers")
+            .with_dictionary_limits((&rules.chunk_dictionary).into());
+        let db = Arc::new(Db::new(
+            rules,
+            Some(mutable_buffer),
+            ReadBufferDb::new(),
+            None,
+        ));
+
+        let lines = [
+            "cpu,host=a,region=west user=1 10",
+            "cpu,host=b,region=east user=1 20",
+            "cpu,host=c,region=north user=1 30",
+        ];
+        // the third write finds the dictionary of the open chunk near
+        // capacity and closes it
+        for (sequence, line) in lines.iter().enumerate() {
+            let lines: Vec<_> = parse_lines(line).map(|l| l.unwrap()).collect();
+            let write = lines_to_replicated_write(1, sequence as u64, &lines, &db.rules);
+            db.store_replicated_write(&write).await.unwrap();
+        }
+
+        let dbs = vec![(DatabaseName::new("rollovers").unwrap(), db)];
+        let rendered = render(&dbs).await;
+        assert!(rendered.contains(
+            "iox_mutable_buffer_chunk_rollovers_total{db_name=\"rollovers\",reason=\"dictionary\"} 1\n"
+        ), "{}", rendered);
+        assert!(rendered.contains(
+            "iox_mutable_buffer_chunk_rollovers_total{db_name=\"rollovers\",reason=\"size\"} 0\n"
+        ), "{}", rendered);
+    }
+}
@@ -0,0 +1,177 @@
+//! This module contains the metrics of the points quarantined by the
+//! future time cap of a database: points with timestamps too far ahead of
+//! the server's clock, which are written to the partition
+//! `QUARANTINE_PARTITION_KEY` rather than to partitions for times that
+//! haven't come yet. A growing count usually means a client's clock is
+//! skewed.
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use data_types::{data::ReplicatedWrite, database_rules::QUARANTINE_PARTITION_KEY, DatabaseName};
+
+use super::Db;
+use crate::latency::escape_label_value;
+
+/// The number of points and writes quarantined
+#[derive(Debug, Default)]
+pub struct QuarantineMetrics {
+    /// The number of points (rows) quarantined
+    pub rows: AtomicU64,
+    /// The number of writes with at least one point quarantined
+    pub writes: AtomicU64,
+}
+
+impl QuarantineMetrics {
+    /// Counts the rows of `write` in the quarantine partition
+    pub(crate) fn record(&self, write: &ReplicatedWrite) {
+        let rows: usize = write
+            .write_buffer_batch()
+            .and_then(|batch| batch.entries())
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.partition_key() == Some(QUARANTINE_PARTITION_KEY))
+            .flat_map(|entry| entry.table_batches().into_iter().flatten())
+            .map(|table| table.rows().map_or(0, |rows| rows.len()))
+            .sum();
+
+        if rows > 0 {
+            self.rows.fetch_add(rows as u64, Ordering::Relaxed);
+            self.writes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Db {
+    /// The number of points and writes quarantined by the future time cap
+    /// of the database
+    pub fn quarantine_metrics(&self) -> &QuarantineMetrics {
+        &self.quarantine_metrics
+    }
+}
+
+/// Renders the metrics of the databases `dbs` as counters in the
+/// Prometheus text format
+pub(crate) fn render(dbs: &[(DatabaseName<'static>, Arc<Db>)]) -> String {
+    let counters: [(&str, &str, fn(&QuarantineMetrics) -> &AtomicU64); 2] = [
+        (
+            "rows",
+            "Points quarantined by the future time cap",
+            |metrics| &metrics.rows,
+        ),
+        (
+            "writes",
+            "Writes with points quarantined by the future time cap",
+            |metrics| &metrics.writes,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, counter) in &counters {
+        let name = format!("iox_quarantined_{}_total", name);
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} counter", name).unwrap();
+        for (db_name, db) in dbs {
+            writeln!(
+                out,
+                "{}{{db_name=\"{}\"}} {}",
+                name,
+                escape_label_value(db_name),
+                counter(db.quarantine_metrics()).load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_util::make_db;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{DatabaseRules, PartitionTemplate, TemplatePart},
+    };
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use query::Database;
+    use read_buffer::Database as ReadBufferDb;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn counts_quarantined_rows() {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y-%m-%d".to_string())],
+                ..Default::default()
+            },
+            future_time_cap: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("quarantine")),
+            ReadBufferDb::new(),
+            None,
+        );
+
+        // a point a year ahead, one from the past and one without a
+        // timestamp, written at the server's time
+        let future = chrono::Utc::now().timestamp_nanos() + 365 * 24 * 3600 * 1_000_000_000;
+        let lp = format!(
+            "cpu bar=1 {}\ncpu bar=2 {}\ncpu bar=3 10\ncpu bar=4",
+            future,
+            future + 1
+        );
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &db.rules);
+        db.store_replicated_write(&write).await.unwrap();
+        db.record_stored_write(&write).await;
+
+        let metrics = db.quarantine_metrics();
+        assert_eq!(metrics.rows.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.writes.load(Ordering::Relaxed), 1);
+
+        let partition_keys = db.partition_keys().await.unwrap();
+        assert_eq!(partition_keys.len(), 3);
+        assert!(partition_keys.contains(&QUARANTINE_PARTITION_KEY.to_string()));
+        assert!(partition_keys.contains(&"1970-01-01".to_string()));
+
+        // writes without quarantined points aren't counted
+        let db = make_db();
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &db.rules);
+        db.record_stored_write(&write).await;
+        assert_eq!(db.quarantine_metrics().writes.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn renders_counters_by_database() {
+        let db = Arc::new(make_db());
+        db.quarantine_metrics().rows.store(5, Ordering::Relaxed);
+        db.quarantine_metrics().writes.store(2, Ordering::Relaxed);
+        let dbs = vec![
+            (DatabaseName::new("db1").unwrap(), db),
+            (DatabaseName::new("db2").unwrap(), Arc::new(make_db())),
+        ];
+
+        let rendered = render(&dbs);
+        assert_eq!(
+            rendered,
+            "# HELP iox_quarantined_rows_total Points quarantined by the future time cap\n\
+             # TYPE iox_quarantined_rows_total counter\n\
+             iox_quarantined_rows_total{db_name=\"db1\"} 5\n\
+             iox_quarantined_rows_total{db_name=\"db2\"} 0\n\
+             # HELP iox_quarantined_writes_total Writes with points quarantined by the future time cap\n\
+             # TYPE iox_quarantined_writes_total counter\n\
+             iox_quarantined_writes_total{db_name=\"db1\"} 2\n\
+             iox_quarantined_writes_total{db_name=\"db2\"} 0\n"
+        );
+    }
+}
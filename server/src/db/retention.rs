@@ -0,0 +1,551 @@
+//! This module contains the enforcement of the retention rules of a
+//! database. Retention works on chunks: the data of a table in a chunk is
+//! dropped once the time statistics of the table in the chunk say that
+//! its newest row has expired, so no rows are scanned.
+//!
+//! Closed mutable buffer chunks holding expired tables are moved to the
+//! read buffer without them, and read buffer chunks are rebuilt without
+//! them. The open chunk of a partition is only dropped once all of its
+//! tables have expired. The expired tables of snapshots are removed from
+//! their metadata and their Parquet files deleted, unless the snapshots
+//! were written in a format version that can't be rewritten.
+
+use std::sync::Arc;
+
+use arrow_deps::arrow::{array::StringArray, error::ArrowError, record_batch::RecordBatch};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use data_types::partition_metadata::Partition as PartitionMeta;
+use futures::TryStreamExt;
+use mutable_buffer::{chunk::Chunk as MBChunk, MutableBufferDb};
+use query::Database;
+use read_buffer::{ColumnSelection, Predicate};
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use tracing::{info, warn};
+
+use super::{sort_key::sort_by_series_key, Db};
+use crate::{snapshot::snapshot_paths, warm::get_bytes};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error listing partitions of the mutable buffer: {}", source))]
+    ListingPartitions {
+        source: mutable_buffer::database::Error,
+    },
+
+    #[snafu(display(
+        "Error dropping expired data from partition {} of the mutable buffer: {}",
+        partition_key,
+        source
+    ))]
+    DroppingMutableBufferChunk {
+        partition_key: String,
+        source: mutable_buffer::database::Error,
+    },
+
+    #[snafu(display(
+        "Error reading chunk {} of partition {} of the mutable buffer: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    ReadingMutableBufferChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: mutable_buffer::chunk::Error,
+    },
+
+    #[snafu(display(
+        "Error reading chunk {} of partition {} of the read buffer: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    ReadingReadBufferChunk {
+        partition_key: String,
+        chunk_id: u32,
+        source: read_buffer::Error,
+    },
+
+    #[snafu(display("Error sorting table {} by its series keys: {}", table, source))]
+    SortingRows { table: String, source: ArrowError },
+
+    #[snafu(display("Error listing snapshots in object store: {}", source))]
+    ListingSnapshots { source: object_store::Error },
+
+    #[snafu(display("Error reading snapshot: {}", source))]
+    ReadingSnapshot { source: crate::warm::Error },
+
+    #[snafu(display("Error decoding snapshot metadata {}: {}", path, source))]
+    DecodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error encoding snapshot metadata {}: {}", path, source))]
+    EncodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error updating snapshot {}: {}", path, source))]
+    UpdatingSnapshot {
+        path: String,
+        source: object_store::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The number of tables of chunks `Db::enforce_retention` dropped from
+/// each place the database keeps them
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct RetentionSummary {
+    pub mutable_buffer_tables: u64,
+    pub read_buffer_tables: u64,
+    pub snapshot_tables: u64,
+}
+
+impl Db {
+    /// Drops the data of the tables of chunks that has expired at `now`
+    /// under the retention rules of the database from the mutable buffer,
+    /// the read buffer and the snapshots of the database
+    pub async fn enforce_retention(&self, now: DateTime<Utc>) -> Result<RetentionSummary> {
+        let now = now.timestamp_nanos();
+
+        // The read buffer goes first, so the mutable buffer chunks moved
+        // into it aren't counted twice
+        let summary = RetentionSummary {
+            read_buffer_tables: self.expire_read_buffer(now)?,
+            mutable_buffer_tables: self.expire_mutable_buffer(now).await?,
+            snapshot_tables: self.expire_snapshots(now).await?,
+        };
+        self.release_partition_quotas().await;
+
+        if summary != RetentionSummary::default() {
+            info!(
+                db_name = self.rules.name.as_str(),
+                mutable_buffer_tables = summary.mutable_buffer_tables,
+                read_buffer_tables = summary.read_buffer_tables,
+                snapshot_tables = summary.snapshot_tables,
+                "Dropped expired data"
+            );
+        }
+        Ok(summary)
+    }
+
+    /// Rebuilds the read buffer chunks holding expired tables without them
+    fn expire_read_buffer(&self, now: i64) -> Result<u64> {
+        let chunks: Vec<(String, u32)> = {
+            let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+            read_buffer
+                .partition_keys()
+                .into_iter()
+                .flat_map(|partition_key| {
+                    read_buffer
+                        .chunk_ids(partition_key)
+                        .into_iter()
+                        .map(move |chunk_id| (partition_key.clone(), chunk_id))
+                })
+                .collect()
+        };
+
+        let mut dropped = 0;
+        for (partition_key, chunk_id) in &chunks {
+            dropped += self.expire_read_buffer_chunk(partition_key, *chunk_id, now)?;
+        }
+
+        Ok(dropped)
+    }
+
+    /// Rebuilds the read buffer chunk `chunk_id` without its expired
+    /// tables, returning how many it held. The tables that are kept are
+    /// read while holding only the read lock of the read buffer, so queries
+    /// aren't blocked while the chunk is rebuilt.
+    fn expire_read_buffer_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+        now: i64,
+    ) -> Result<u64> {
+        let context = || ReadingReadBufferChunk {
+            partition_key,
+            chunk_id,
+        };
+
+        let (expired, tables, size_before) = {
+            let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+            let size_before = match read_buffer.chunk_size(partition_key, chunk_id) {
+                Some(size) => size,
+                None => return Ok(0),
+            };
+            let names = read_buffer
+                .table_names(partition_key, &[chunk_id], Predicate::default())
+                .context(context())?;
+            let names = names
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .expect("table names are strings");
+
+            let mut kept = Vec::with_capacity(names.len());
+            let mut expired = 0;
+            for row in 0..names.len() {
+                let table_name = names.value(row).to_string();
+                let (_, time_range) = read_buffer
+                    .table_rows(partition_key, chunk_id, &table_name)
+                    .context(context())?;
+                if self
+                    .rules
+                    .retention
+                    .is_expired(&table_name, time_range, now)
+                {
+                    expired += 1;
+                } else {
+                    kept.push(table_name);
+                }
+            }
+            if expired == 0 {
+                return Ok(0);
+            }
+
+            // the read buffer can't drop a table from a chunk, so the
+            // tables that are kept are read and loaded into a new one
+            let mut tables = Vec::with_capacity(kept.len());
+            for table_name in kept {
+                let batches: Vec<RecordBatch> = read_buffer
+                    .read_filter(
+                        partition_key,
+                        &table_name,
+                        &[chunk_id],
+                        Predicate::default(),
+                        ColumnSelection::All,
+                    )
+                    .context(context())?
+                    .collect();
+                tables.push((table_name, batches));
+            }
+            (expired, tables, size_before)
+        };
+
+        let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+        // the chunk may have been rewritten since it was read, for example
+        // by a deletion, in which case it is left to the next run
+        if read_buffer.chunk_size(partition_key, chunk_id) != Some(size_before) {
+            return Ok(0);
+        }
+        read_buffer
+            .drop_chunk(partition_key, chunk_id)
+            .context(context())?;
+        for (table_name, batches) in tables {
+            for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+                read_buffer.upsert_partition(partition_key, chunk_id, &table_name, batch);
+            }
+        }
+        self.record_read_buffer_rewrite(&read_buffer, partition_key, chunk_id, size_before);
+
+        Ok(expired)
+    }
+
+    /// Moves the closed mutable buffer chunks holding expired tables to
+    /// the read buffer without them, and drops the chunks whose tables
+    /// have all expired
+    async fn expire_mutable_buffer(&self, now: i64) -> Result<u64> {
+        let mutable_buffer = match &self.mutable_buffer {
+            Some(mutable_buffer) => mutable_buffer,
+            None => return Ok(0),
+        };
+        let partition_keys = mutable_buffer
+            .partition_keys()
+            .await
+            .context(ListingPartitions)?;
+
+        let mut dropped = 0;
+        for partition_key in &partition_keys {
+            for chunk in mutable_buffer.chunks(partition_key).await {
+                dropped += self
+                    .expire_mutable_buffer_chunk(mutable_buffer, partition_key, chunk, now)
+                    .await?;
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    /// Drops the expired tables of the mutable buffer chunk `chunk`,
+    /// returning how many it held
+    async fn expire_mutable_buffer_chunk(
+        &self,
+        mutable_buffer: &MutableBufferDb,
+        partition_key: &str,
+        chunk: Arc<MBChunk>,
+        now: i64,
+    ) -> Result<u64> {
+        let mut chunk = chunk;
+        let (expired, kept) = loop {
+            let (expired, kept) = self.expired_tables(partition_key, &chunk, now)?;
+            if expired == 0 {
+                return Ok(0);
+            }
+            if chunk.time_closed.is_some() {
+                break (expired, kept);
+            }
+
+            // Writes still go to the open chunk, so it is only closed once
+            // none of its data is kept. Rows written between the check and
+            // the rollover are in the closed chunk, so it is checked again.
+            if !kept.is_empty() {
+                return Ok(0);
+            }
+            chunk = mutable_buffer
+                .rollover_partition(partition_key)
+                .await
+                .context(DroppingMutableBufferChunk { partition_key })?;
+        };
+        let context = || ReadingMutableBufferChunk {
+            partition_key,
+            chunk_id: chunk.id(),
+        };
+
+        if !kept.is_empty() {
+            let mut tables = Vec::with_capacity(kept.len());
+            for table_name in kept {
+                let mut batches = Vec::new();
+                chunk
+                    .table_to_arrow(&mut batches, &table_name, &[])
+                    .context(context())?;
+                let batches = batches
+                    .into_iter()
+                    .map(|batch| sort_by_series_key(batch, &table_name, &self.rules.tag_orders))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context(SortingRows { table: &table_name })?;
+                tables.push((table_name, batches));
+            }
+
+            let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+            // the chunk may have been loaded into the read buffer already
+            if read_buffer.chunk_ids(partition_key).contains(&chunk.id()) {
+                read_buffer.drop_chunk(partition_key, chunk.id()).context(
+                    ReadingReadBufferChunk {
+                        partition_key,
+                        chunk_id: chunk.id(),
+                    },
+                )?;
+            }
+            for (table_name, batches) in tables {
+                for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+                    read_buffer.upsert_partition(partition_key, chunk.id(), &table_name, batch);
+                }
+            }
+        }
+
+        mutable_buffer
+            .drop_chunk(partition_key, chunk.id())
+            .await
+            .context(DroppingMutableBufferChunk { partition_key })?;
+        self.record_mutable_buffer_compaction(partition_key, &chunk);
+
+        Ok(expired)
+    }
+
+    /// Returns how many tables of the mutable buffer chunk `chunk` have
+    /// expired at `now`, and the names of the tables that are kept
+    fn expired_tables(
+        &self,
+        partition_key: &str,
+        chunk: &MBChunk,
+        now: i64,
+    ) -> Result<(u64, Vec<String>)> {
+        let context = || ReadingMutableBufferChunk {
+            partition_key,
+            chunk_id: chunk.id(),
+        };
+
+        let mut kept = Vec::new();
+        let mut expired = 0;
+        for stats in chunk.table_stats().context(context())? {
+            let (_, time_range) = chunk.table_row_count(&stats.name).context(context())?;
+            if self
+                .rules
+                .retention
+                .is_expired(&stats.name, time_range, now)
+            {
+                expired += 1;
+            } else {
+                kept.push(stats.name);
+            }
+        }
+
+        Ok((expired, kept))
+    }
+
+    /// Removes the expired tables of the snapshots of the database from
+    /// their metadata, and then deletes their Parquet files. Metadata left
+    /// without tables is deleted.
+    async fn expire_snapshots(&self, now: i64) -> Result<u64> {
+        let store = match &self.object_store {
+            Some((store, _)) => store,
+            None => return Ok(0),
+        };
+        let (metadata_path, data_path) = snapshot_paths(&self.rules.name);
+        let _rewrite = self.snapshot_rewrites.lock().await;
+
+        let paths: Vec<Vec<_>> = store
+            .list(Some(&metadata_path))
+            .await
+            .context(ListingSnapshots)?
+            .try_collect()
+            .await
+            .context(ListingSnapshots)?;
+
+        let mut dropped = 0;
+        for path in paths.into_iter().flatten() {
+            let meta_path = store.convert_path(&path);
+            let data = get_bytes(store, &path).await.context(ReadingSnapshot)?;
+            let mut meta: PartitionMeta =
+                serde_json::from_slice(&data).context(DecodingMetadata { path: &meta_path })?;
+            // the expired data is dropped once a version of IOx that can
+            // rewrite the snapshot enforces retention
+            if let Err(e) = meta.format_version.check_rewritable() {
+                warn!(path = meta_path.as_str(), error = %e, "Skipping snapshot");
+                continue;
+            }
+
+            let (expired, kept) = meta.tables.into_iter().partition::<Vec<_>, _>(|table| {
+                self.rules
+                    .retention
+                    .is_expired(&table.name, table.time_range, now)
+            });
+            meta.tables = kept;
+            if expired.is_empty() {
+                continue;
+            }
+            for table in &expired {
+                meta.checksums.remove(&table.name);
+            }
+
+            if meta.tables.is_empty() {
+                store
+                    .delete(&path)
+                    .await
+                    .context(UpdatingSnapshot { path: &meta_path })?;
+            } else {
+                let data = Bytes::from(
+                    serde_json::to_vec(&meta).context(EncodingMetadata { path: &meta_path })?,
+                );
+                let len = data.len();
+                store
+                    .put(&path, futures::stream::once(async move { Ok(data) }), len)
+                    .await
+                    .context(UpdatingSnapshot { path: &meta_path })?;
+            }
+
+            for table in &expired {
+                let mut location = data_path.clone();
+                location.push_dir(&meta.key);
+                location.set_file_name(format!("{}.parquet", table.name));
+                store.delete(&location).await.context(UpdatingSnapshot {
+                    path: store.convert_path(&location),
+                })?;
+            }
+            dropped += expired.len() as u64;
+        }
+
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use data_types::database_rules::DatabaseRules;
+    use query::{test::TestLPWriter, PartitionChunk};
+    use read_buffer::Database as ReadBufferDb;
+
+    /// Counts the rows of `table` the chunks hold, including the expired
+    /// rows queries leave out
+    async fn count(db: &Db, table: &str) -> u64 {
+        let mut rows = 0;
+        for partition_key in db.partition_keys().await.unwrap() {
+            for chunk in db.chunks(&partition_key).await {
+                if let Some(count) = chunk.table_row_count(table).unwrap() {
+                    rows += count.rows;
+                }
+            }
+        }
+        rows
+    }
+
+    #[tokio::test]
+    async fn drops_expired_tables_from_memory() {
+        let mut rules = DatabaseRules::default();
+        rules.retention.period = Some(std::time::Duration::from_secs(3600));
+        rules
+            .retention
+            .tables
+            .insert("debug".to_string(), std::time::Duration::from_nanos(100));
+        let db = Db::new(
+            rules,
+            Some(MutableBufferDb::new("test_db")),
+            ReadBufferDb::new(),
+            None,
+        );
+
+        // one chunk in the read buffer, one closed and one open chunk in
+        // the mutable buffer
+        let partition_key = "1970-01-01T00";
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(&db, "cpu usage=1 10\ndebug value=1 10")
+            .await
+            .unwrap();
+        let chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, chunk.id())
+            .await
+            .unwrap();
+        writer
+            .write_lp_string(&db, "cpu usage=2 20\ndebug value=2 20")
+            .await
+            .unwrap();
+        db.rollover_partition(partition_key).await.unwrap();
+        writer
+            .write_lp_string(&db, "debug value=3 30")
+            .await
+            .unwrap();
+
+        // nothing has expired yet
+        let summary = db
+            .enforce_retention(Utc.timestamp_nanos(100))
+            .await
+            .unwrap();
+        assert_eq!(summary, RetentionSummary::default());
+
+        let summary = db
+            .enforce_retention(Utc.timestamp_nanos(1_000))
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            RetentionSummary {
+                mutable_buffer_tables: 2,
+                read_buffer_tables: 1,
+                snapshot_tables: 0,
+            }
+        );
+
+        // the tables with a longer retention period are kept
+        assert_eq!(count(&db, "cpu").await, 2);
+
+        // only the points written since are left of the expired table
+        writer
+            .write_lp_string(&db, "debug value=4 950")
+            .await
+            .unwrap();
+        assert_eq!(count(&db, "debug").await, 1);
+    }
+}
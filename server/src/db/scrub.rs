@@ -0,0 +1,437 @@
+//! This module contains the scrubbing of the snapshots of a database: the
+//! Parquet files of their tables are read back, at a limited rate so
+//! queries keep the bandwidth of the object store, and checked against the
+//! checksums recorded in their metadata when they were written.
+//!
+//! A file whose checksum doesn't match is moved out of the way, to the
+//! directory returned by `corrupted_path`, and its table is removed from the
+//! metadata of the snapshot, so neither queries nor the warming of a read
+//! buffer stumble over it. The files are read without holding up the other
+//! rewrites of the snapshots, so a snapshot rewritten while its files were
+//! read is left for the next scrub rather than updated. Tables of snapshots written before checksums
+//! were recorded can't be verified and are left as they are, and snapshots
+//! of a format version this version of IOx can't rewrite are skipped.
+
+use std::{
+    num::NonZeroU64,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use data_types::partition_metadata::Partition as PartitionMeta;
+use futures::TryStreamExt;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use tracing::{error, info, warn};
+
+use super::Db;
+use crate::{snapshot::snapshot_paths, warm::get_bytes};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error listing snapshots in object store: {}", source))]
+    ListingSnapshots { source: object_store::Error },
+
+    #[snafu(display("Error reading snapshot: {}", source))]
+    ReadingSnapshot { source: crate::warm::Error },
+
+    #[snafu(display("Error decoding snapshot metadata {}: {}", path, source))]
+    DecodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error encoding snapshot metadata {}: {}", path, source))]
+    EncodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error moving corrupted file {}: {}", path, source))]
+    MovingCorruptedFile {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error updating snapshot {}: {}", path, source))]
+    UpdatingSnapshot {
+        path: String,
+        source: object_store::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What `Db::scrub_snapshots` read and found
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
+pub struct ScrubSummary {
+    /// The Parquet files whose checksum was verified
+    pub files_verified: u64,
+    /// The Parquet files without a recorded checksum
+    pub files_unverified: u64,
+    /// The snapshots skipped because they were written in a version of
+    /// the format that can't be rewritten
+    pub snapshots_unsupported: u64,
+    /// The bytes of the Parquet files read
+    pub bytes_read: u64,
+    /// The Parquet files whose checksum didn't match, which were moved to
+    /// the directory of corrupted files
+    pub corrupted: Vec<String>,
+}
+
+impl Db {
+    /// Reads back the Parquet files of the snapshots of the database, at
+    /// most `bytes_per_second` on average, and verifies them against the
+    /// checksums in the metadata of the snapshots. The files that don't
+    /// match are moved to the directory of corrupted files and their tables
+    /// removed from the metadata.
+    pub async fn scrub_snapshots(&self, bytes_per_second: NonZeroU64) -> Result<ScrubSummary> {
+        let mut summary = ScrubSummary::default();
+        let store = match &self.object_store {
+            Some((store, _)) => store,
+            None => return Ok(summary),
+        };
+        let (metadata_path, data_path) = snapshot_paths(&self.rules.name);
+        let mut rate = RateLimiter::new(bytes_per_second);
+
+        let paths: Vec<Vec<_>> = store
+            .list(Some(&metadata_path))
+            .await
+            .context(ListingSnapshots)?
+            .try_collect()
+            .await
+            .context(ListingSnapshots)?;
+
+        for path in paths.into_iter().flatten() {
+            let meta_path = store.convert_path(&path);
+            let meta_data = get_bytes(store, &path).await.context(ReadingSnapshot)?;
+            let mut meta: PartitionMeta = serde_json::from_slice(&meta_data)
+                .context(DecodingMetadata { path: &meta_path })?;
+            if let Err(e) = meta.format_version.check_rewritable() {
+                warn!(path = meta_path.as_str(), error = %e, "Skipping snapshot");
+                summary.snapshots_unsupported += 1;
+                continue;
+            }
+
+            let mut corrupted = vec![];
+            for table in &meta.tables {
+                let expected = match meta.checksums.get(&table.name) {
+                    Some(&checksum) => checksum,
+                    None => {
+                        summary.files_unverified += 1;
+                        continue;
+                    }
+                };
+
+                let location = table_path(&data_path, &meta.key, &table.name);
+                let data = get_bytes(store, &location).await.context(ReadingSnapshot)?;
+                summary.bytes_read += data.len() as u64;
+                summary.files_verified += 1;
+                if crc32fast::hash(&data) != expected {
+                    corrupted.push(table.name.clone());
+                }
+                rate.consume(data.len() as u64).await;
+            }
+            if corrupted.is_empty() {
+                continue;
+            }
+
+            // files rewritten by retention or deletes while they were read
+            // don't match the metadata read before, so the snapshot is only
+            // updated if its metadata hasn't changed since
+            let _rewrite = self.snapshot_rewrites.lock().await;
+            match get_bytes(store, &path).await {
+                Ok(current) if current == meta_data => {}
+                Ok(_) => {
+                    info!(
+                        path = meta_path.as_str(),
+                        "Snapshot rewritten while scrubbed, leaving it for the next scrub"
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    warn!(
+                        path = meta_path.as_str(),
+                        error = %e,
+                        "Snapshot unreadable once scrubbed, leaving it for the next scrub"
+                    );
+                    continue;
+                }
+            }
+
+            // the tables are removed from the metadata first, so the files
+            // are never referenced once they have been moved
+            meta.tables.retain(|table| !corrupted.contains(&table.name));
+            for table_name in &corrupted {
+                meta.checksums.remove(table_name);
+            }
+            if meta.tables.is_empty() {
+                store
+                    .delete(&path)
+                    .await
+                    .context(UpdatingSnapshot { path: &meta_path })?;
+            } else {
+                let data = Bytes::from(
+                    serde_json::to_vec(&meta).context(EncodingMetadata { path: &meta_path })?,
+                );
+                let len = data.len();
+                store
+                    .put(&path, futures::stream::once(async move { Ok(data) }), len)
+                    .await
+                    .context(UpdatingSnapshot { path: &meta_path })?;
+            }
+
+            let corrupted_dir = corrupted_path(&self.rules.name);
+            for table_name in &corrupted {
+                let from = table_path(&data_path, &meta.key, table_name);
+                let to = table_path(&corrupted_dir, &meta.key, table_name);
+                let file_path = store.convert_path(&from);
+                store
+                    .rename(&from, &to)
+                    .await
+                    .context(MovingCorruptedFile { path: &file_path })?;
+                error!(
+                    db_name = self.rules.name.as_str(),
+                    path = file_path.as_str(),
+                    moved_to = store.convert_path(&to).as_str(),
+                    "Checksum mismatch: moved corrupted snapshot file"
+                );
+                summary.corrupted.push(file_path);
+            }
+        }
+
+        info!(
+            db_name = self.rules.name.as_str(),
+            files_verified = summary.files_verified,
+            files_unverified = summary.files_unverified,
+            snapshots_unsupported = summary.snapshots_unsupported,
+            bytes_read = summary.bytes_read,
+            corrupted = summary.corrupted.len(),
+            "Scrubbed snapshots"
+        );
+        Ok(summary)
+    }
+}
+
+/// Returns the directory the corrupted Parquet files of the snapshots of
+/// the database `db_name` are moved to, with one directory per partition
+pub fn corrupted_path(db_name: &str) -> ObjectStorePath {
+    let mut path = ObjectStorePath::default();
+    path.push_all_dirs(&[db_name, "corrupted"]);
+    path
+}
+
+/// Returns the path of the Parquet file of the table `table_name` of the
+/// partition `partition_key` under `dir`
+pub(super) fn table_path(
+    dir: &ObjectStorePath,
+    partition_key: &str,
+    table_name: &str,
+) -> ObjectStorePath {
+    let mut path = dir.clone();
+    path.push_dir(partition_key);
+    path.set_file_name(format!("{}.parquet", table_name));
+    path
+}
+
+/// Spaces out reads so that they average at most a number of bytes per
+/// second
+#[derive(Debug)]
+struct RateLimiter {
+    bytes_per_second: NonZeroU64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_second: NonZeroU64) -> Self {
+        Self {
+            bytes_per_second,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Counts `bytes` as read, waiting until reading them is within the
+    /// rate
+    async fn consume(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_second.get() as f64);
+        let elapsed = self.start.elapsed();
+        if due > elapsed {
+            tokio::time::delay_for(due - elapsed).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::snapshot_chunk;
+    use data_types::{
+        database_rules::{
+            DatabaseRules, ParquetConfig, PartitionTemplate, TagOrders, TemplatePart,
+        },
+        DatabaseName,
+    };
+    use influxdb_line_protocol::parse_lines;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    fn rate() -> NonZeroU64 {
+        NonZeroU64::new(u64::MAX).unwrap()
+    }
+
+    /// Returns a database whose partition "1970" is snapshotted, with the
+    /// tables cpu and mem, in `store`
+    async fn snapshotted_db(store: &Arc<ObjectStore>) -> Arc<Db> {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y".to_string())],
+                ..Default::default()
+            },
+            store_locally: true,
+            ..Default::default()
+        };
+        let server = crate::Server::new(crate::ConnectionManagerImpl {}, Arc::clone(store));
+        server.set_id(1);
+        server.create_database("mydb", rules).await.unwrap();
+        let lines: Vec<_> = parse_lines("cpu usage=1 10\nmem free=2i 20")
+            .map(|l| l.unwrap())
+            .collect();
+        server.write_lines("mydb", &lines).await.unwrap();
+        let db = server
+            .db(&DatabaseName::new("mydb").unwrap())
+            .await
+            .unwrap();
+
+        let (metadata_path, data_path) = snapshot_paths("mydb");
+        let mut partition_path = data_path;
+        partition_path.push_dir("1970");
+        let chunk = db.rollover_partition("1970").await.unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        snapshot_chunk(
+            metadata_path,
+            partition_path,
+            Arc::clone(store),
+            "1970",
+            chunk,
+            &ParquetConfig::default(),
+            &TagOrders::default(),
+            Some(tx),
+        )
+        .unwrap();
+        rx.await.unwrap();
+        db
+    }
+
+    /// Flips the bits of the object at `location`, returning its length
+    async fn corrupt(store: &ObjectStore, location: &ObjectStorePath) -> usize {
+        let data: Vec<u8> = get_bytes(store, location)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|b| !b)
+            .collect();
+        put_bytes(store, location, data).await
+    }
+
+    /// Puts `data` at `location`, returning its length
+    async fn put_bytes(store: &ObjectStore, location: &ObjectStorePath, data: Vec<u8>) -> usize {
+        let len = data.len();
+        let data = Bytes::from(data);
+        store
+            .put(
+                location,
+                futures::stream::once(async move { Ok(data) }),
+                len,
+            )
+            .await
+            .unwrap();
+        len
+    }
+
+    #[tokio::test]
+    async fn moves_corrupted_files() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let db = snapshotted_db(&store).await;
+        let (metadata_path, data_path) = snapshot_paths("mydb");
+
+        let summary = db.scrub_snapshots(rate()).await.unwrap();
+        assert_eq!(summary.files_verified, 2);
+        assert_eq!(summary.files_unverified, 0);
+        assert!(summary.bytes_read > 0);
+        assert!(summary.corrupted.is_empty());
+
+        // flip the bytes of one of the files
+        let location = table_path(&data_path, "1970", "cpu");
+        let len = corrupt(&store, &location).await;
+
+        let summary = db.scrub_snapshots(rate()).await.unwrap();
+        assert_eq!(summary.files_verified, 2);
+        assert_eq!(summary.corrupted, vec![store.convert_path(&location)]);
+
+        // the file was moved and its table removed from the metadata
+        assert!(store.head(&location).await.is_err());
+        let moved = table_path(&corrupted_path("mydb"), "1970", "cpu");
+        assert_eq!(store.head(&moved).await.unwrap().size, len);
+        let mut meta_path = metadata_path;
+        meta_path.set_file_name("1970.json");
+        let meta: PartitionMeta =
+            serde_json::from_slice(&get_bytes(&store, &meta_path).await.unwrap()).unwrap();
+        let tables: Vec<_> = meta
+            .tables
+            .iter()
+            .map(|table| table.name.as_str())
+            .collect();
+        assert_eq!(tables, vec!["mem"]);
+        assert!(!meta.checksums.contains_key("cpu"));
+
+        // the remaining file is still verified
+        let summary = db.scrub_snapshots(rate()).await.unwrap();
+        assert_eq!(summary.files_verified, 1);
+        assert!(summary.corrupted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaves_snapshots_rewritten_while_scrubbed() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let db = snapshotted_db(&store).await;
+        let (mut meta_path, data_path) = snapshot_paths("mydb");
+        meta_path.set_file_name("1970.json");
+        let location = table_path(&data_path, "1970", "cpu");
+        corrupt(&store, &location).await;
+
+        // the snapshot is rewritten while the scrub reads its files, which
+        // it finds corrupted
+        let rewrite = db.snapshot_rewrites.lock().await;
+        let scrub = tokio::spawn({
+            let db = Arc::clone(&db);
+            async move { db.scrub_snapshots(rate()).await }
+        });
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        let mut meta: serde_json::Value =
+            serde_json::from_slice(&get_bytes(&store, &meta_path).await.unwrap()).unwrap();
+        meta["checksums"]["cpu"] = serde_json::json!(0);
+        put_bytes(&store, &meta_path, serde_json::to_vec(&meta).unwrap()).await;
+        drop(rewrite);
+
+        // so it is left as rewritten
+        let summary = scrub.await.unwrap().unwrap();
+        assert!(summary.corrupted.is_empty());
+        assert!(store.head(&location).await.is_ok());
+        let meta: PartitionMeta =
+            serde_json::from_slice(&get_bytes(&store, &meta_path).await.unwrap()).unwrap();
+        assert_eq!(meta.checksums.get("cpu"), Some(&0));
+    }
+
+    #[tokio::test]
+    async fn limits_the_read_rate() {
+        let mut rate = RateLimiter::new(NonZeroU64::new(1000).unwrap());
+        let start = Instant::now();
+        rate.consume(100).await;
+        rate.consume(100).await;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
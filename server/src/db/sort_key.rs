@@ -0,0 +1,115 @@
+//! This module contains code to sort the rows of a table by its series
+//! keys, in the tag order configured by the `TagOrders` of the database,
+//! before they are stored in the read buffer or written to snapshots.
+
+use arrow_deps::arrow::{
+    array::ArrayRef,
+    compute::kernels::{
+        sort::{lexsort_to_indices, SortColumn, SortOptions},
+        take::take,
+    },
+    error::Result,
+    record_batch::RecordBatch,
+};
+use data_types::{
+    database_rules::TagOrders,
+    schema::{InfluxColumnType, Schema},
+};
+use std::convert::TryFrom;
+
+/// Returns the rows of `batch`, a batch of the table `table_name`, sorted
+/// by their tags in the order set by `tag_orders` and then by time.
+/// Batches without an IOx schema are returned as they are.
+pub fn sort_by_series_key(
+    batch: RecordBatch,
+    table_name: &str,
+    tag_orders: &TagOrders,
+) -> Result<RecordBatch> {
+    let schema = match Schema::try_from(batch.schema()) {
+        Ok(schema) => schema,
+        Err(_) => return Ok(batch),
+    };
+
+    let mut tags = Vec::new();
+    let mut time = None;
+    for (idx, (column_type, field)) in schema.iter().enumerate() {
+        match column_type {
+            Some(InfluxColumnType::Tag) => tags.push((field.name().as_str(), idx)),
+            Some(InfluxColumnType::Timestamp) => time = Some(idx),
+            _ => {}
+        }
+    }
+    tags.sort_by_key(|(name, _)| tag_orders.sort_key(table_name, *name));
+
+    let sort_columns: Vec<_> = tags
+        .into_iter()
+        .map(|(_, idx)| idx)
+        .chain(time)
+        .map(|idx| SortColumn {
+            values: ArrayRef::clone(batch.column(idx)),
+            options: Some(SortOptions {
+                descending: false,
+                nulls_first: true,
+            }),
+        })
+        .collect();
+    if sort_columns.is_empty() || batch.num_rows() < 2 {
+        return Ok(batch);
+    }
+
+    let indices = lexsort_to_indices(&sort_columns)?;
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column, &indices, None))
+        .collect::<Result<Vec<_>>>()?;
+
+    RecordBatch::try_new(batch.schema(), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::array::{Int64Array, StringArray};
+    use data_types::schema::builder::SchemaBuilder;
+    use std::sync::Arc;
+
+    #[test]
+    fn sorts_by_tags_in_order_then_time() {
+        let schema = SchemaBuilder::new()
+            .tag("host")
+            .tag("region")
+            .timestamp()
+            .build()
+            .unwrap();
+        let batch = RecordBatch::try_new(
+            schema.into(),
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b", "a", "b"])),
+                Arc::new(StringArray::from(vec!["west", "east", "east", "east"])),
+                Arc::new(Int64Array::from(vec![1, 4, 3, 2])),
+            ],
+        )
+        .unwrap();
+
+        let times = |batch: &RecordBatch| {
+            let times = batch
+                .column(2)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            (0..times.len()).map(|i| times.value(i)).collect::<Vec<_>>()
+        };
+
+        // tags are ordered by name by default: host, then region
+        let sorted = sort_by_series_key(batch.clone(), "cpu", &TagOrders::default()).unwrap();
+        assert_eq!(times(&sorted), vec![3, 1, 2, 4]);
+
+        let mut tag_orders = TagOrders::default();
+        tag_orders
+            .tables
+            .insert("cpu".to_string(), vec!["region".to_string()]);
+        let sorted = sort_by_series_key(batch, "cpu", &tag_orders).unwrap();
+        assert_eq!(times(&sorted), vec![3, 2, 4, 1]);
+    }
+}
@@ -0,0 +1,240 @@
+//! This module contains the listing of the objects a database keeps in
+//! object storage, for the `system.object_store` table. Each object is
+//! flagged with whether the catalog of the database refers to it, so
+//! objects left over (for example by a snapshot that didn't finish) can
+//! be found and deleted.
+
+use std::collections::BTreeSet;
+
+use data_types::partition_metadata::Partition as PartitionMeta;
+use futures::TryStreamExt;
+use object_store::{path::ObjectStorePath, ObjectMeta, ObjectStore, MAX_LIST_RESULTS};
+use query::StoredObject;
+use snafu::{ResultExt, Snafu};
+
+use super::Db;
+use crate::{
+    buffer::WAL_DIR,
+    config::{CONTINUOUS_QUERY_DIR, DB_RULES_FILE_NAME, DB_TOMBSTONE_FILE_NAME},
+    snapshot::snapshot_paths,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error listing objects under {}: {}", prefix, source))]
+    ListingObjects {
+        prefix: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error reading {} from object store: {}", path, source))]
+    ReadingObject {
+        path: String,
+        source: object_store::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Db {
+    /// Lists the objects the database keeps in object storage: those
+    /// under the directory of its configuration, and the snapshots of its
+    /// partitions.
+    ///
+    /// The objects the catalog refers to are the configuration, WAL
+    /// segments and continuous queries of the database, the metadata of
+    /// its snapshots, and the Parquet files of the tables that metadata
+    /// lists. Databases without an object store have no objects.
+    pub async fn list_stored_objects(&self) -> Result<Vec<StoredObject>> {
+        let (store, root) = match &self.object_store {
+            Some((store, root)) => (store.as_ref(), root),
+            None => return Ok(vec![]),
+        };
+        let (metadata_path, data_path) = snapshot_paths(&self.rules.name);
+
+        let mut catalog = vec![file_path(root, DB_RULES_FILE_NAME)];
+        catalog.push(file_path(root, DB_TOMBSTONE_FILE_NAME));
+        let mut catalog_dirs = vec![dir_path(root, WAL_DIR)];
+        catalog_dirs.push(dir_path(root, CONTINUOUS_QUERY_DIR));
+
+        let mut objects = list_all(store, root).await?;
+
+        // The metadata of a snapshot refers to the Parquet file of each of
+        // its tables. Metadata that can't be decoded refers to nothing, so
+        // it shows up as left over itself.
+        for meta in list_all(store, &metadata_path).await? {
+            let data = get_bytes(store, &meta.location).await?;
+            if let Ok(partition) = serde_json::from_slice::<PartitionMeta>(&data) {
+                for table in &partition.tables {
+                    let mut path = dir_path(&data_path, &partition.key);
+                    path.set_file_name(format!("{}.parquet", table.name));
+                    catalog.push(path);
+                }
+                catalog.push(meta.location.clone());
+            }
+            objects.push(meta);
+        }
+
+        objects.extend(list_all(store, &data_path).await?);
+
+        Ok(objects
+            .into_iter()
+            .map(|object| {
+                let referenced = catalog.contains(&object.location)
+                    || catalog_dirs
+                        .iter()
+                        .any(|dir| object.location.prefix_matches(dir));
+                StoredObject {
+                    path: store.convert_path(&object.location),
+                    size: object.size as u64,
+                    last_modified: object.last_modified.timestamp_nanos(),
+                    referenced,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Lists all objects under `prefix`, one directory (and page) at a time
+async fn list_all(store: &ObjectStore, prefix: &ObjectStorePath) -> Result<Vec<ObjectMeta>> {
+    let mut objects = vec![];
+    // a common prefix can be in more than one page of some stores
+    let mut listed = BTreeSet::new();
+    let mut prefixes = vec![(prefix.clone(), None)];
+    while let Some((prefix, token)) = prefixes.pop() {
+        let list = store
+            .list_with_delimiter_paginated(&prefix, token.as_deref(), MAX_LIST_RESULTS)
+            .await
+            .context(ListingObjects {
+                prefix: store.convert_path(&prefix),
+            })?;
+        objects.extend(list.objects);
+        for common_prefix in list.common_prefixes {
+            if listed.insert(store.convert_path(&common_prefix)) {
+                prefixes.push((common_prefix, None));
+            }
+        }
+        if let Some(token) = list.next_token {
+            prefixes.push((prefix, Some(token)));
+        }
+    }
+    Ok(objects)
+}
+
+async fn get_bytes(store: &ObjectStore, path: &ObjectStorePath) -> Result<Vec<u8>> {
+    let path_string = store.convert_path(path);
+    store
+        .get(path)
+        .await
+        .context(ReadingObject { path: &path_string })?
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await
+        .context(ReadingObject { path: &path_string })
+}
+
+fn dir_path(parent: &ObjectStorePath, dir: &str) -> ObjectStorePath {
+    let mut path = parent.clone();
+    path.push_dir(dir);
+    path
+}
+
+fn file_path(dir: &ObjectStorePath, file_name: &str) -> ObjectStorePath {
+    let mut path = dir.clone();
+    path.set_file_name(file_name);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{snapshot::snapshot_chunk, ConnectionManagerImpl, Server};
+    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+    use bytes::Bytes;
+    use data_types::{
+        database_rules::{
+            DatabaseRules, ParquetConfig, PartitionTemplate, TagOrders, TemplatePart,
+        },
+        DatabaseName,
+    };
+    use influxdb_line_protocol::parse_lines;
+    use object_store::memory::InMemory;
+    use query::{exec::Executor, frontend::sql::SQLQueryPlanner};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn lists_objects_with_references() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(ConnectionManagerImpl {}, Arc::clone(&store));
+        server.set_id(1);
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y".to_string())],
+                ..Default::default()
+            },
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("mydb", rules).await.unwrap();
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10")
+            .map(|l| l.unwrap())
+            .collect();
+        server.write_lines("mydb", &lines).await.unwrap();
+        let db = server
+            .db(&DatabaseName::new("mydb").unwrap())
+            .await
+            .unwrap();
+
+        let (metadata_path, mut data_path) = snapshot_paths("mydb");
+        data_path.push_dir("1970");
+        let chunk = db.rollover_partition("1970").await.unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        snapshot_chunk(
+            metadata_path,
+            data_path.clone(),
+            Arc::clone(&store),
+            "1970",
+            chunk,
+            &ParquetConfig::default(),
+            &TagOrders::default(),
+            Some(tx),
+        )
+        .unwrap();
+        rx.await.unwrap();
+
+        // a table file no snapshot metadata refers to
+        let data = Bytes::from("not parquet");
+        let len = data.len();
+        store
+            .put(
+                &file_path(&data_path, "mem.parquet"),
+                futures::stream::once(async move { Ok(data) }),
+                len,
+            )
+            .await
+            .unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let physical_plan = planner
+            .query(
+                db.as_ref(),
+                "select path, referenced from system.object_store",
+                &executor,
+            )
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+------------------------------+------------+",
+            "| path                         | referenced |",
+            "+------------------------------+------------+",
+            "| 1/mydb/rules.json            | true       |",
+            "| mydb/data/1970/cpu.parquet   | true       |",
+            "| mydb/data/1970/mem.parquet   | false      |",
+            "| mydb/meta/1970.json          | true       |",
+            "+------------------------------+------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+    }
+}
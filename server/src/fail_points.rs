@@ -0,0 +1,42 @@
+//! This module contains the names of the fail points in the write, WAL
+//! and snapshot paths. Crash consistency tests configure them with the
+//! `fail` crate to panic, which stops the server at that point as if the
+//! process had been killed.
+//!
+//! The fail points are compiled out unless the `failpoints` feature is
+//! enabled.
+
+/// Before a write is stored in the mutable buffer
+pub const BEFORE_MUTABLE_BUFFER_WRITE: &str = "write::before_mutable_buffer";
+
+/// After a write is stored in the mutable buffer, before it is appended
+/// to the WAL buffer
+pub const BEFORE_WAL_APPEND: &str = "write::before_wal_append";
+
+/// After a write is appended to the WAL buffer, before a segment it
+/// closed is persisted
+pub const AFTER_WAL_APPEND: &str = "write::after_wal_append";
+
+/// Before a closed segment is written to its segment store
+pub const BEFORE_SEGMENT_STORE: &str = "wal::before_segment_store";
+
+/// After a closed segment is written to its segment store, before it is
+/// marked as persisted
+pub const AFTER_SEGMENT_STORE: &str = "wal::after_segment_store";
+
+/// Before the Parquet file of a table of a snapshot is written
+pub const BEFORE_SNAPSHOT_TABLE: &str = "snapshot::before_table";
+
+/// After the tables of a snapshot are written, before its metadata is
+pub const BEFORE_SNAPSHOT_METADATA: &str = "snapshot::before_metadata";
+
+/// Every fail point, in the order a write reaches them
+pub const ALL: &[&str] = &[
+    BEFORE_MUTABLE_BUFFER_WRITE,
+    BEFORE_WAL_APPEND,
+    AFTER_WAL_APPEND,
+    BEFORE_SEGMENT_STORE,
+    AFTER_SEGMENT_STORE,
+    BEFORE_SNAPSHOT_TABLE,
+    BEFORE_SNAPSHOT_METADATA,
+];
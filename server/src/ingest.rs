@@ -0,0 +1,539 @@
+//! This module contains the ingestion of the data of an InfluxDB server
+//! into a database, to migrate it to IOx: either a directory of line
+//! protocol files, such as the files `influx_inspect export` writes, or
+//! the TSM files of a shard.
+//!
+//! Line protocol files are read and parsed concurrently, a batch of lines
+//! at a time, and files ending in `.gz` are decompressed as they are read.
+//! Each batch goes through the write path of the server, like the lines of
+//! a write request, so it is checked against the rules of the database,
+//! logged in the WAL and replicated. A file that can't be read or written
+//! doesn't stop the others: the summary of the ingestion lists what went
+//! wrong with each file.
+//!
+//! TSM files are converted to tables, one per measurement, whose rows are
+//! then written as lines like the lines of a line protocol file.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use data_types::{database_rules::WriterId, DatabaseName};
+use futures::{stream, StreamExt};
+use influxdb_line_protocol::parse_lines;
+use ingest::{
+    line_protocol::{ConvertedTable, LineProtocolWriterSource},
+    TSMFileConverter,
+};
+use serde::Serialize;
+use snafu::{ensure, ResultExt, Snafu};
+use tracing::{info, warn};
+
+use crate::{ack::WriteAckLevel, ConnectionManager, Server};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid database name: {}", source))]
+    InvalidDatabaseName {
+        source: data_types::DatabaseNameError,
+    },
+
+    #[snafu(display("{}", source))]
+    DatabaseUnavailable { source: crate::Error },
+
+    #[snafu(display("Error listing directory {:?}: {}", path, source))]
+    ListingDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error reading {:?}: {}", path, source))]
+    ReadingFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error writing lines of {:?}: {}", path, source))]
+    WritingLines { path: PathBuf, source: crate::Error },
+
+    #[snafu(display("At least one TSM file is required"))]
+    NoTsmFiles,
+
+    #[snafu(display("Error converting TSM files: {}", message))]
+    ConvertingTsm { message: String },
+
+    #[snafu(display("Error writing measurement {} of TSM files: {}", measurement, source))]
+    WritingMeasurement {
+        measurement: String,
+        source: crate::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The writer id of the writes stored in a database directly rather than
+/// through a server, such as record batches. Servers never have this id,
+/// so these writes can be told apart from the writes of servers.
+pub const INGEST_WRITER_ID: WriterId = 0;
+
+/// The number of lines of a file parsed and written at a time
+const BATCH_LINES: usize = 10_000;
+
+/// What `Server::ingest_directory` ingested
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
+pub struct IngestSummary {
+    /// The number of lines written
+    pub lines: u64,
+    /// The number of lines that couldn't be parsed and were skipped
+    pub invalid_lines: u64,
+    /// The number of files that couldn't be read or written completely
+    pub failed_files: usize,
+    /// What was ingested from each file, in the order of their paths
+    pub files: Vec<FileSummary>,
+}
+
+/// What `Server::ingest_directory` ingested from one file
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    /// The number of lines written
+    pub lines: u64,
+    /// The number of lines that couldn't be parsed and were skipped
+    pub invalid_lines: u64,
+    /// Why the rest of the file wasn't ingested, if it wasn't
+    pub error: Option<String>,
+}
+
+/// What `Server::import_tsm` imported
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct TsmImportSummary {
+    /// The number of TSM files read
+    pub files: usize,
+    /// The number of measurements written
+    pub measurements: usize,
+    /// The number of points written
+    pub lines: u64,
+}
+
+impl<M: ConnectionManager> Server<M> {
+    /// Writes the line protocol files in the directory `path` whose names
+    /// match `glob` to the database `db_name`, reading up to `parallelism`
+    /// files at a time. `*` in `glob` matches any characters and `?` any
+    /// one character. Files ending in `.gz` are decompressed.
+    ///
+    /// Lines that can't be parsed are skipped. The others are written
+    /// like `write_lines_with_ack` writes them, a batch at a time, each
+    /// batch reaching the acknowledgement level `ack` before the next one
+    /// is read. Ingesting a file stops at the first error reading or
+    /// writing it, and the lines written before the error are kept.
+    pub async fn ingest_directory(
+        &self,
+        db_name: &str,
+        path: impl AsRef<Path>,
+        glob: &str,
+        parallelism: usize,
+        ack: WriteAckLevel,
+    ) -> Result<IngestSummary> {
+        // fail early if the database can't be written to at all
+        let name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        self.require_db(&name).context(DatabaseUnavailable)?;
+
+        let path = path.as_ref();
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(path).context(ListingDirectory { path })? {
+            let entry = entry.context(ListingDirectory { path })?;
+            let file_type = entry.file_type().context(ListingDirectory { path })?;
+            let matched = entry
+                .file_name()
+                .to_str()
+                .map_or(false, |name| glob_matches(glob, name));
+            if file_type.is_file() && matched {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+
+        let total = paths.len();
+        let mut done = 0;
+        let mut files: Vec<_> = stream::iter(paths)
+            .map(|path| self.ingest_file(db_name, path, ack))
+            .buffer_unordered(parallelism.max(1))
+            .inspect(|file| {
+                done += 1;
+                info!(
+                    db_name,
+                    path = ?file.path,
+                    lines = file.lines,
+                    done,
+                    total,
+                    "Ingested file"
+                );
+            })
+            .collect()
+            .await;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let summary = IngestSummary {
+            lines: files.iter().map(|file| file.lines).sum(),
+            invalid_lines: files.iter().map(|file| file.invalid_lines).sum(),
+            failed_files: files.iter().filter(|file| file.error.is_some()).count(),
+            files,
+        };
+        info!(
+            db_name,
+            ?path,
+            lines = summary.lines,
+            invalid_lines = summary.invalid_lines,
+            failed_files = summary.failed_files,
+            "Ingested directory"
+        );
+        Ok(summary)
+    }
+
+    async fn ingest_file(&self, db_name: &str, path: PathBuf, ack: WriteAckLevel) -> FileSummary {
+        let mut summary = FileSummary {
+            path,
+            ..Default::default()
+        };
+        if let Err(e) = self.ingest_lines(db_name, &mut summary, ack).await {
+            warn!(path = ?summary.path, error = %e, "Error ingesting file");
+            summary.error = Some(e.to_string());
+        }
+        summary
+    }
+
+    /// Writes the lines of the file `summary.path`, a batch at a time,
+    /// counting them in `summary`
+    async fn ingest_lines(
+        &self,
+        db_name: &str,
+        summary: &mut FileSummary,
+        ack: WriteAckLevel,
+    ) -> Result<()> {
+        let path = summary.path.clone();
+        let mut reader = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || open(&path))
+                .await
+                .expect("reading file panicked")?
+        };
+
+        loop {
+            // the file is read on a thread that may block, a batch at a time
+            let (returned, batch) = tokio::task::spawn_blocking(move || {
+                let batch = read_batch(reader.as_mut());
+                (reader, batch)
+            })
+            .await
+            .expect("reading file panicked");
+            reader = returned;
+            let batch = batch.context(ReadingFile { path: &path })?;
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            let mut lines = Vec::new();
+            for line in parse_lines(&batch) {
+                match line {
+                    Ok(line) => lines.push(line),
+                    Err(_) => summary.invalid_lines += 1,
+                }
+            }
+            if lines.is_empty() {
+                continue;
+            }
+
+            self.write_lines_with_ack(db_name, &lines, ack)
+                .await
+                .context(WritingLines { path: &path })?;
+            summary.lines += lines.len() as u64;
+        }
+    }
+
+    /// Writes the points of the TSM files `paths`, such as the files of a
+    /// shard of an InfluxDB 1.x or 2.x server, to the database `db_name`,
+    /// keeping their tags, fields and timestamps. Where several files hold
+    /// the same point, the value of the file whose name sorts last, the one
+    /// of the latest generation, is written.
+    ///
+    /// The points are written like `write_lines_with_ack` writes lines, a
+    /// batch at a time, so they are subject to the rules of the database,
+    /// such as how it treats non-finite floats.
+    pub async fn import_tsm(
+        &self,
+        db_name: &str,
+        paths: &[PathBuf],
+        ack: WriteAckLevel,
+    ) -> Result<TsmImportSummary> {
+        ensure!(!paths.is_empty(), NoTsmFiles);
+        let name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        self.require_db(&name).context(DatabaseUnavailable)?;
+
+        let mut paths = paths.to_vec();
+        paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+        let mut summary = TsmImportSummary {
+            files: paths.len(),
+            ..Default::default()
+        };
+        let tables = tokio::task::spawn_blocking(move || convert_tsm(&paths))
+            .await
+            .expect("converting TSM files panicked")?;
+
+        for table in &tables {
+            for lines in table.lines().chunks(BATCH_LINES) {
+                self.write_lines_with_ack(db_name, lines, ack)
+                    .await
+                    .context(WritingMeasurement {
+                        measurement: table.measurement(),
+                    })?;
+                summary.lines += lines.len() as u64;
+            }
+            summary.measurements += 1;
+        }
+
+        info!(
+            db_name,
+            files = summary.files,
+            measurements = summary.measurements,
+            lines = summary.lines,
+            "Imported TSM files"
+        );
+        Ok(summary)
+    }
+}
+
+/// Converts the TSM files `paths` to tables, one per measurement
+fn convert_tsm(paths: &[PathBuf]) -> Result<Vec<ConvertedTable>> {
+    let mut index_readers = Vec::with_capacity(paths.len());
+    let mut block_readers = Vec::with_capacity(paths.len());
+    for path in paths {
+        let index_file = File::open(path).context(ReadingFile { path })?;
+        let len = index_file.metadata().context(ReadingFile { path })?.len();
+        let block_file = File::open(path).context(ReadingFile { path })?;
+        index_readers.push((BufReader::new(index_file), len as usize));
+        block_readers.push(BufReader::new(block_file));
+    }
+
+    let source = LineProtocolWriterSource::new();
+    TSMFileConverter::new(Box::new(source.clone()))
+        .convert(index_readers, block_readers)
+        .map_err(|e| Error::ConvertingTsm {
+            message: e.to_string(),
+        })?;
+    Ok(source.take_tables())
+}
+
+/// Opens the file `path`, decompressing it if its name ends in `.gz`
+fn open(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path).context(ReadingFile { path })?;
+    Ok(if path.extension().map_or(false, |ext| ext == "gz") {
+        Box::new(BufReader::new(flate2::read::GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
+
+/// Reads up to `BATCH_LINES` lines, returning an empty string at the end
+/// of the file
+fn read_batch(reader: &mut dyn BufRead) -> std::io::Result<String> {
+    let mut batch = String::new();
+    for _ in 0..BATCH_LINES {
+        if reader.read_line(&mut batch)? == 0 {
+            break;
+        }
+    }
+    Ok(batch)
+}
+
+/// Returns true if `name` matches the pattern `glob`, in which `*` matches
+/// any characters and `?` any one character
+fn glob_matches(glob: &str, name: &str) -> bool {
+    let glob: Vec<_> = glob.chars().collect();
+    let name: Vec<_> = name.chars().collect();
+
+    // the positions in `glob` and `name` after the last `*`, to backtrack
+    // to when the rest of the name doesn't match
+    let mut star = None;
+    let (mut g, mut n) = (0, 0);
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g + 1, n));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    star = Some((star_g, star_n + 1));
+                    g = star_g;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConnectionManagerImpl;
+    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+    use data_types::database_rules::DatabaseRules;
+    use flate2::{write::GzEncoder, Compression};
+    use object_store::{memory::InMemory, ObjectStore};
+    use query::{exec::Executor, frontend::sql::SQLQueryPlanner};
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[test]
+    fn globs_match() {
+        assert!(glob_matches("*.lp", "cpu.lp"));
+        assert!(glob_matches("*.lp*", "cpu.lp.gz"));
+        assert!(glob_matches("export-??.lp", "export-01.lp"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "abxbc"));
+        assert!(!glob_matches("*.lp", "cpu.lp.gz"));
+        assert!(!glob_matches("export-?.lp", "export-01.lp"));
+        assert!(!glob_matches("a*b*c", "abxbd"));
+    }
+
+    #[tokio::test]
+    async fn ingests_matching_files() -> TestResult {
+        let dir = test_helpers::tmp_dir()?;
+        std::fs::write(
+            dir.path().join("a.lp"),
+            "cpu,host=a usage=1 10\ncpu,host=a usage=2 20\n",
+        )?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"# comment\ncpu,host=b usage=3 30\nnot line protocol\n")?;
+        std::fs::write(dir.path().join("b.lp.gz"), encoder.finish()?)?;
+        std::fs::write(dir.path().join("c.lp.gz"), "not gzip")?;
+        std::fs::write(dir.path().join("d.txt"), "cpu,host=d usage=4 40\n")?;
+        std::fs::create_dir(dir.path().join("e.lp"))?;
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(ConnectionManagerImpl {}, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("mydb", rules).await?;
+        let db = server.db(&DatabaseName::new("mydb")?).await.unwrap();
+
+        let summary = server
+            .ingest_directory("mydb", dir.path(), "*.lp*", 2, WriteAckLevel::Buffered)
+            .await?;
+        assert_eq!(summary.lines, 3);
+        assert_eq!(summary.invalid_lines, 1);
+        assert_eq!(summary.failed_files, 1);
+        let names: Vec<_> = summary
+            .files
+            .iter()
+            .map(|file| file.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a.lp", "b.lp.gz", "c.lp.gz"]);
+        assert!(summary.files[2].error.is_some());
+
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let physical_plan = planner
+            .query(&*db, "select host, usage from cpu order by time", &executor)
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+------+-------+",
+            "| host | usage |",
+            "+------+-------+",
+            "| a    | 1     |",
+            "| a    | 2     |",
+            "| b    | 3     |",
+            "+------+-------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // the ingested lines are sequenced like other writes of the server
+        assert_eq!(db.next_sequence(), 3);
+
+        let err = server
+            .ingest_directory(
+                "mydb",
+                dir.path().join("missing"),
+                "*",
+                1,
+                WriteAckLevel::Buffered,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ListingDirectory { .. }));
+        let err = server
+            .ingest_directory("otherdb", dir.path(), "*", 1, WriteAckLevel::Buffered)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseUnavailable { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn imports_tsm_files() -> TestResult {
+        let dir = test_helpers::tmp_dir()?;
+        let mut data = Vec::new();
+        flate2::read::GzDecoder::new(File::open("../tests/fixtures/merge-tsm/merge_a.tsm.gz")?)
+            .read_to_end(&mut data)?;
+        let path = dir.path().join("000000001-000000001.tsm");
+        std::fs::write(&path, data)?;
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(ConnectionManagerImpl {}, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("mydb", rules).await?;
+        let db = server.db(&DatabaseName::new("mydb")?).await.unwrap();
+
+        let summary = server
+            .import_tsm("mydb", &[path], WriteAckLevel::Buffered)
+            .await?;
+        assert_eq!(
+            summary,
+            TsmImportSummary {
+                files: 1,
+                measurements: 2,
+                lines: 121,
+            }
+        );
+
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let physical_plan = planner
+            .query(&*db, "select count(*) as n from disk", &executor)
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec!["+----+", "| n  |", "+----+", "| 36 |", "+----+"];
+        assert_table_eq!(expected, &batches);
+
+        let err = server
+            .import_tsm("mydb", &[], WriteAckLevel::Buffered)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NoTsmFiles));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+//! This module contains code for writing query results (such as the record
+//! batches returned by `table_to_arrow`) in the Arrow IPC file or stream
+//! format, either to object storage or to a local file, so they can be
+//! handed to other Arrow tooling without converting them to Parquet first.
+use arrow_deps::arrow::{
+    error::ArrowError,
+    ipc::writer::{FileWriter, StreamWriter},
+    record_batch::RecordBatch,
+};
+use object_store::{path::ObjectStorePath, ObjectStore};
+
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use snafu::{ensure, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Can't write Arrow IPC without any record batches"))]
+    NoRecordBatches,
+
+    #[snafu(display("Error writing Arrow IPC: {}", source))]
+    WritingIpc { source: ArrowError },
+
+    #[snafu(display("Error writing to object store: {}", source))]
+    WritingToObjectStore { source: object_store::Error },
+
+    #[snafu(display("Error writing to file {:?}: {}", path, source))]
+    WritingToFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The Arrow IPC formats batches can be written in
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IpcFormat {
+    /// The random access file format, with a footer locating each batch
+    File,
+    /// The streaming format, which can be read without seeking
+    Stream,
+}
+
+/// Serializes `batches` to the IPC `format`. The schema is taken from the
+/// first batch, so all batches must share it and there must be at least
+/// one.
+pub fn to_ipc_bytes(batches: &[RecordBatch], format: IpcFormat) -> Result<Vec<u8>> {
+    ensure!(!batches.is_empty(), NoRecordBatches);
+    let schema = batches[0].schema();
+
+    let mut data = Vec::new();
+    match format {
+        IpcFormat::File => {
+            let mut writer = FileWriter::try_new(&mut data, &schema).context(WritingIpc)?;
+            for batch in batches {
+                writer.write(batch).context(WritingIpc)?;
+            }
+            writer.finish().context(WritingIpc)?;
+        }
+        IpcFormat::Stream => {
+            let mut writer = StreamWriter::try_new(&mut data, &schema).context(WritingIpc)?;
+            for batch in batches {
+                writer.write(batch).context(WritingIpc)?;
+            }
+            writer.finish().context(WritingIpc)?;
+        }
+    }
+
+    Ok(data)
+}
+
+/// Serializes `batches` to the IPC `format` and stores them at `location`
+pub async fn write_ipc_to_object_store(
+    batches: &[RecordBatch],
+    format: IpcFormat,
+    store: &ObjectStore,
+    location: &ObjectStorePath,
+) -> Result<()> {
+    let data = Bytes::from(to_ipc_bytes(batches, format)?);
+    let len = data.len();
+
+    store
+        .put(
+            location,
+            futures::stream::once(async move { Ok(data) }),
+            len,
+        )
+        .await
+        .context(WritingToObjectStore)
+}
+
+/// Serializes `batches` to the IPC `format` and writes them to the file at
+/// `path`, replacing it if it exists
+pub async fn write_ipc_to_file(
+    batches: &[RecordBatch],
+    format: IpcFormat,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let data = to_ipc_bytes(batches, format)?;
+
+    tokio::fs::write(path, data)
+        .await
+        .context(WritingToFile { path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::{
+        arrow::{
+            array::{Float64Array, Int64Array, StringArray},
+            datatypes::{DataType, Field, Schema},
+            ipc::reader::{FileReader, StreamReader},
+        },
+        assert_table_eq,
+    };
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+    use std::{io::Cursor, sync::Arc};
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn batches() -> Vec<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("usage", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+
+        let batch = |hosts: Vec<Option<&str>>, usages: Vec<Option<f64>>, times: Vec<i64>| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(StringArray::from(hosts)),
+                    Arc::new(Float64Array::from(usages)),
+                    Arc::new(Int64Array::from(times)),
+                ],
+            )
+            .unwrap()
+        };
+
+        vec![
+            batch(
+                vec![Some("a"), None],
+                vec![Some(0.5), Some(0.7)],
+                vec![10, 20],
+            ),
+            batch(vec![Some("b")], vec![None], vec![30]),
+        ]
+    }
+
+    fn read_file(data: Vec<u8>) -> Vec<RecordBatch> {
+        FileReader::try_new(Cursor::new(data))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn read_stream(data: Vec<u8>) -> Vec<RecordBatch> {
+        StreamReader::try_new(Cursor::new(data))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    const EXPECTED: [&str; 7] = [
+        "+------+-------+------+",
+        "| host | usage | time |",
+        "+------+-------+------+",
+        "| a    | 0.5   | 10   |",
+        "|      | 0.7   | 20   |",
+        "| b    |       | 30   |",
+        "+------+-------+------+",
+    ];
+
+    #[test]
+    fn ipc_formats_round_trip() -> TestResult {
+        let batches = batches();
+
+        let file = read_file(to_ipc_bytes(&batches, IpcFormat::File)?);
+        assert_table_eq!(EXPECTED, &file);
+
+        let stream = read_stream(to_ipc_bytes(&batches, IpcFormat::Stream)?);
+        assert_table_eq!(EXPECTED, &stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ipc_requires_batches() {
+        let err = to_ipc_bytes(&[], IpcFormat::File).unwrap_err();
+        assert!(matches!(err, Error::NoRecordBatches));
+    }
+
+    #[tokio::test]
+    async fn writes_ipc_to_object_store() -> TestResult {
+        let batches = batches();
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut location = ObjectStorePath::default();
+        location.push_dir("results");
+        location.set_file_name("cpu.arrow");
+
+        write_ipc_to_object_store(&batches, IpcFormat::File, &store, &location).await?;
+
+        let data = store
+            .get(&location)
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        assert_table_eq!(EXPECTED, &read_file(data.to_vec()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_ipc_to_file() -> TestResult {
+        let batches = batches();
+        let dir = test_helpers::tmp_dir()?;
+        let path = dir.path().join("cpu.arrows");
+
+        write_ipc_to_file(&batches, IpcFormat::Stream, &path).await?;
+
+        let data = std::fs::read(&path)?;
+        assert_table_eq!(EXPECTED, &read_stream(data));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+//! This module contains the histograms of the latency of the requests
+//! served, broken down by database and by the kind of operation, so the
+//! latency of metadata queries (such as `TagKeys` or `SHOW` statements)
+//! can be told apart from that of queries reading data.
+//!
+//! The histograms are rendered in the Prometheus text format.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// The name of the rendered histograms
+const METRIC_NAME: &str = "iox_request_duration_seconds";
+
+/// The upper bounds, in seconds, of the buckets of the histograms. Slower
+/// requests are only counted in the implicit `+Inf` bucket.
+pub const BUCKET_BOUNDS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The kinds of requests whose latency is measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OperationKind {
+    Write,
+    ReadFilter,
+    ReadGroup,
+    ReadWindowAggregate,
+    TagKeys,
+    TagValues,
+    MeasurementNames,
+    MeasurementTagKeys,
+    MeasurementTagValues,
+    MeasurementFields,
+    MeasurementTimeRange,
+    SqlSelect,
+    SqlShow,
+}
+
+impl OperationKind {
+    /// Returns the kind of the SQL statement `query`: `SHOW` statements
+    /// list metadata, any other statement is counted as a select
+    pub fn of_sql(query: &str) -> Self {
+        let mut query = query.trim_start();
+
+        // skip the comments (and hints) before the statement
+        while let Some(comment) = query.strip_prefix("/*") {
+            query = match comment.find("*/") {
+                Some(end) => comment[end + 2..].trim_start(),
+                None => "",
+            };
+        }
+
+        let keyword = query.split_whitespace().next().unwrap_or_default();
+        if keyword.eq_ignore_ascii_case("show") {
+            Self::SqlShow
+        } else {
+            Self::SqlSelect
+        }
+    }
+
+    /// The value of the `operation` label of the histograms
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Write => "write",
+            Self::ReadFilter => "read_filter",
+            Self::ReadGroup => "read_group",
+            Self::ReadWindowAggregate => "read_window_aggregate",
+            Self::TagKeys => "tag_keys",
+            Self::TagValues => "tag_values",
+            Self::MeasurementNames => "measurement_names",
+            Self::MeasurementTagKeys => "measurement_tag_keys",
+            Self::MeasurementTagValues => "measurement_tag_values",
+            Self::MeasurementFields => "measurement_fields",
+            Self::MeasurementTimeRange => "measurement_time_range",
+            Self::SqlSelect => "sql_select",
+            Self::SqlShow => "sql_show",
+        }
+    }
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The number of requests that took up to each bound of `BUCKET_BOUNDS`
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    /// The requests that took longer than the previous bound, up to each
+    /// bound, with the requests slower than all bounds last
+    buckets: [AtomicU64; BUCKET_BOUNDS.len() + 1],
+    /// The sum of the latencies, in microseconds
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = BUCKET_BOUNDS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the number of requests that took up to each bound of
+    /// `BUCKET_BOUNDS`, followed by the number of all requests
+    pub fn cumulative_counts(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .scan(0, |count, bucket| {
+                *count += bucket.load(Ordering::Relaxed);
+                Some(*count)
+            })
+            .collect()
+    }
+
+    /// Returns the sum of the latencies of the requests
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+}
+
+/// Records the latency of a request when dropped
+#[derive(Debug)]
+pub struct LatencyTimer {
+    histogram: Arc<LatencyHistogram>,
+    start: Instant,
+}
+
+impl Drop for LatencyTimer {
+    fn drop(&mut self) {
+        self.histogram.record(self.start.elapsed())
+    }
+}
+
+/// The latency histograms of the requests served, by database and kind of
+/// operation
+#[derive(Debug, Default)]
+pub struct LatencyMetrics {
+    histograms: RwLock<BTreeMap<(String, OperationKind), Arc<LatencyHistogram>>>,
+}
+
+impl LatencyMetrics {
+    /// Returns the histogram of the requests of kind `kind` to the
+    /// database `db_name`
+    pub fn histogram(&self, db_name: &str, kind: OperationKind) -> Arc<LatencyHistogram> {
+        let key = (db_name.to_string(), kind);
+        if let Some(histogram) = self.histograms.read().expect("mutex poisoned").get(&key) {
+            return Arc::clone(histogram);
+        }
+
+        let mut histograms = self.histograms.write().expect("mutex poisoned");
+        Arc::clone(histograms.entry(key).or_default())
+    }
+
+    /// Starts timing a request of kind `kind` to the database `db_name`,
+    /// whose latency is recorded when the returned timer is dropped
+    pub fn start(&self, db_name: &str, kind: OperationKind) -> LatencyTimer {
+        LatencyTimer {
+            histogram: self.histogram(db_name, kind),
+            start: Instant::now(),
+        }
+    }
+
+    /// Renders the histograms in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "# HELP {} Latency of the requests served, by database and operation",
+            METRIC_NAME
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {} histogram", METRIC_NAME).unwrap();
+
+        let histograms = self.histograms.read().expect("mutex poisoned");
+        for ((db_name, kind), histogram) in &*histograms {
+            let labels = format!(
+                "db_name=\"{}\",operation=\"{}\"",
+                escape_label_value(db_name),
+                kind
+            );
+
+            let counts = histogram.cumulative_counts();
+            let bounds = BUCKET_BOUNDS
+                .iter()
+                .map(|bound| bound.to_string())
+                .chain(std::iter::once("+Inf".to_string()));
+            for (bound, count) in bounds.zip(&counts) {
+                writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    METRIC_NAME, labels, bound, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "{}_sum{{{}}} {}",
+                METRIC_NAME,
+                labels,
+                histogram.sum().as_secs_f64()
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}_count{{{}}} {}",
+                METRIC_NAME,
+                labels,
+                counts.last().expect("histograms have buckets")
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+/// Escapes the backslashes, double quotes and line feeds of a label value
+pub(crate) fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_helpers::assert_contains;
+
+    #[test]
+    fn sql_statement_kinds() {
+        assert_eq!(
+            OperationKind::of_sql("select * from cpu"),
+            OperationKind::SqlSelect
+        );
+        assert_eq!(
+            OperationKind::of_sql("  SHOW TABLES"),
+            OperationKind::SqlShow
+        );
+        assert_eq!(
+            OperationKind::of_sql("/*+ in_memory */ show columns from cpu"),
+            OperationKind::SqlShow
+        );
+        assert_eq!(
+            OperationKind::of_sql("/* shows */ select 1 /* show */"),
+            OperationKind::SqlSelect
+        );
+        assert_eq!(
+            OperationKind::of_sql("/* unterminated"),
+            OperationKind::SqlSelect
+        );
+        assert_eq!(OperationKind::of_sql(""), OperationKind::SqlSelect);
+    }
+
+    #[test]
+    fn counts_latencies_in_buckets() {
+        let histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_micros(500));
+        histogram.record(Duration::from_millis(1));
+        histogram.record(Duration::from_millis(30));
+        histogram.record(Duration::from_secs(60));
+
+        let counts = histogram.cumulative_counts();
+        assert_eq!(counts.len(), BUCKET_BOUNDS.len() + 1);
+        assert_eq!(counts[0], 2);
+        assert_eq!(counts[4], 2);
+        assert_eq!(counts[5], 3);
+        assert_eq!(counts[BUCKET_BOUNDS.len() - 1], 3);
+        assert_eq!(counts[BUCKET_BOUNDS.len()], 4);
+        assert_eq!(histogram.sum(), Duration::from_micros(60_031_500));
+    }
+
+    #[test]
+    fn renders_histograms_by_database_and_operation() {
+        let metrics = LatencyMetrics::default();
+        metrics
+            .histogram("db1", OperationKind::TagKeys)
+            .record(Duration::from_millis(2));
+        metrics
+            .histogram("db1", OperationKind::ReadFilter)
+            .record(Duration::from_millis(200));
+        drop(metrics.start("db\"2", OperationKind::SqlShow));
+
+        let rendered = metrics.render();
+        assert_contains!(&rendered, "# TYPE iox_request_duration_seconds histogram");
+        assert_contains!(
+            &rendered,
+            "iox_request_duration_seconds_bucket{db_name=\"db1\",operation=\"tag_keys\",le=\"0.001\"} 0\n\
+             iox_request_duration_seconds_bucket{db_name=\"db1\",operation=\"tag_keys\",le=\"0.0025\"} 1\n"
+        );
+        assert_contains!(
+            &rendered,
+            "iox_request_duration_seconds_bucket{db_name=\"db1\",operation=\"read_filter\",le=\"+Inf\"} 1\n\
+             iox_request_duration_seconds_sum{db_name=\"db1\",operation=\"read_filter\"} 0.2\n\
+             iox_request_duration_seconds_count{db_name=\"db1\",operation=\"read_filter\"} 1\n"
+        );
+        assert_contains!(
+            &rendered,
+            "iox_request_duration_seconds_count{db_name=\"db\\\"2\",operation=\"sql_show\"} 1\n"
+        );
+    }
+}
@@ -66,46 +66,106 @@
     clippy::use_self
 )]
 
+pub mod ack;
+pub mod audit;
+pub mod authz;
 pub mod buffer;
 mod config;
+pub mod continuous_query;
 pub mod db;
+pub mod fail_points;
+pub mod ingest;
+pub mod ipc;
+pub mod latency;
+mod namespace;
+pub mod quota;
+pub mod recovery;
+pub mod schema_history;
 pub mod snapshot;
-
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+pub mod summary;
+pub mod warm;
+pub mod write_queue;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use crate::{
-    config::{object_store_path_for_database_config, Config, DB_RULES_FILE_NAME},
-    db::Db,
+    ack::{WriteAck, WriteAckLevel},
+    audit::{AuditAction, AuditLog, Auditor},
+    authz::{Action, AllowAll, Authorizer, Decision, Principal},
+    buffer::{
+        fan_in::{FanIn, FanInSummary},
+        store::{FileSegments, ObjectStoreSegments, SegmentStore},
+        InvalidWrites, Segment, WAL_DIR,
+    },
+    config::{
+        object_store_path_for_database_config, object_store_path_for_database_tombstone, Config,
+        DB_RULES_FILE_NAME, DB_TOMBSTONE_FILE_NAME,
+    },
+    db::{
+        copy_partition::{CopyMode, CopySummary},
+        delete::TagMatcher,
+        Db,
+    },
+    latency::LatencyMetrics,
+    namespace::{object_store_path_for_namespaces, Namespace, Namespaces},
+    quota::WriteCharge,
+    recovery::{RecoveryState, RecoveryTracker, DEFAULT_RECOVERY_CONCURRENCY},
 };
+use arrow_deps::arrow::record_batch::RecordBatch;
 use data_types::{
-    data::{lines_to_replicated_write, ReplicatedWrite},
-    database_rules::{DatabaseRules, HostGroup, HostGroupId, MatchTables},
-    {DatabaseName, DatabaseNameError},
+    data::{lines_to_replicated_write, ReplicatedWrite, TableBatch},
+    database_rules::{
+        DatabaseRules, HostGroup, HostGroupId, MatchTables, NonFiniteFloats, TableWriteRejection,
+        WalSegmentStorage, WriterId,
+    },
+    names::{org_and_bucket_to_database, OrgBucketMappingError},
+    {DatabaseName, DatabaseNameError, INGEST_TIME_COLUMN_NAME},
 };
-use influxdb_line_protocol::ParsedLine;
-use object_store::{path::ObjectStorePath, ObjectStore};
+use influxdb_line_protocol::{EscapedStr, FieldValue, ParsedLine};
+use object_store::{path::ObjectStorePath, ObjectStore, MAX_LIST_RESULTS};
 use query::{exec::Executor, Database, DatabaseStore};
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::TryStreamExt;
-use snafu::{OptionExt, ResultExt, Snafu};
-use tracing::{error, info};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use fail::fail_point;
+use futures::stream::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::{error, info, warn};
 
 type DatabaseError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 /// A server ID of 0 is reserved and indicates no ID has been configured.
 const SERVER_ID_NOT_SET: u32 = 0;
 
+/// The most writes replicated in the background at once. Writes beyond it
+/// are replicated before they are acknowledged, whatever their
+/// acknowledgement level, so writers can't outpace the replication.
+const MAX_BACKGROUND_REPLICATIONS: usize = 1024;
+
+/// How long a deleted database can be restored before it may be purged,
+/// unless configured with `Server::with_restore_window`
+pub const DEFAULT_RESTORE_WINDOW_HOURS: i64 = 7 * 24;
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Server error: {}", source))]
     ServerError { source: std::io::Error },
     #[snafu(display("database not found: {}", db_name))]
     DatabaseNotFound { db_name: String },
+    #[snafu(display("database {} is not ready: {}", db_name, state))]
+    DatabaseNotReady {
+        db_name: String,
+        state: RecoveryState,
+    },
     #[snafu(display("invalid database: {}", source))]
     InvalidDatabaseName { source: DatabaseNameError },
     #[snafu(display("database error: {}", source))]
@@ -133,12 +193,166 @@ pub enum Error {
     StoreError { source: object_store::Error },
     #[snafu(display("database already exists"))]
     DatabaseAlreadyExists { db_name: String },
+    #[snafu(display(
+        "database {} is deleted and can be restored until it is purged",
+        db_name
+    ))]
+    DatabaseDeleted { db_name: String },
+    #[snafu(display("no deleted database {} to restore", db_name))]
+    DeletedDatabaseNotFound { db_name: String },
     #[snafu(display("error appending to wal buffer: {}", source))]
     WalError { source: buffer::Error },
+    #[snafu(display("error replaying the WAL of writer {}: {}", writer_id, source))]
+    FanningInWal {
+        writer_id: WriterId,
+        source: buffer::Error,
+    },
+    #[snafu(display(
+        "writes to {} can't be acknowledged at the wal level: it doesn't persist a WAL buffer",
+        db_name
+    ))]
+    NoWalBuffer { db_name: String },
+    #[snafu(display("the write to {} was abandoned before it was stored", db_name))]
+    WriteAbandoned { db_name: String },
+    #[snafu(display("error waiting for the WAL segment of a write to persist: {}", source))]
+    PersistingSegment { source: tokio::task::JoinError },
+    #[snafu(display("no WAL backend registered as {}", name))]
+    UnknownWalBackend { name: String },
+    #[snafu(display("invalid replicated write: {}", source))]
+    InvalidReplicatedWrite { source: data_types::data::Error },
+    #[snafu(display("error writing record batch to table {}: {}", table_name, source))]
+    WritingTableBatch {
+        table_name: String,
+        source: data_types::data::Error,
+    },
+    #[snafu(display("error deleting series: {}", source))]
+    DeletingSeries { source: db::delete::Error },
+    #[snafu(display("error running continuous query: {}", source))]
+    ContinuousQueryError { source: continuous_query::Error },
+    #[snafu(display("invalid table write rules: {}", source))]
+    InvalidTableWriteRules {
+        source: data_types::database_rules::Error,
+    },
+    #[snafu(display(
+        "write to {} rejected by table write rules: {}",
+        db_name,
+        display_rejected_lines(lines)
+    ))]
+    TableWriteRejected {
+        db_name: String,
+        lines: Vec<RejectedLine>,
+    },
+    #[snafu(display(
+        "write to {} rejected for NaN or infinite float values: {}",
+        db_name,
+        display_non_finite_lines(lines)
+    ))]
+    NonFiniteFloatsRejected {
+        db_name: String,
+        lines: Vec<NonFiniteLine>,
+    },
+    #[snafu(display(
+        "write to {} rejected: {} bytes free for its WAL in {}, below the minimum of {}",
+        db_name,
+        available,
+        path,
+        min_free_bytes
+    ))]
+    WalDiskFull {
+        db_name: String,
+        path: String,
+        available: u64,
+        min_free_bytes: u64,
+    },
+    #[snafu(display("write to {} rejected: {}", db_name, source))]
+    WriteQuotaExceeded {
+        db_name: String,
+        source: quota::Error,
+    },
+    #[snafu(display("error copying partition: {}", source))]
+    CopyingPartition { source: db::copy_partition::Error },
+    #[snafu(display("not authorized to {} {}", action, display_database(db_name)))]
+    NotAuthorized {
+        action: Action,
+        db_name: Option<String>,
+    },
+    #[snafu(display("unable to audit the action: {}", source))]
+    Auditing { source: audit::Error },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// A line of a write that the database's table write rules reject
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RejectedLine {
+    /// The (1 based) number of the line in the write
+    pub line: usize,
+    pub table: String,
+    pub reason: TableWriteRejection,
+}
+
+impl std::fmt::Display for RejectedLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: table {} {}",
+            self.line, self.table, self.reason
+        )
+    }
+}
+
+/// A line of a write with float fields whose values are NaN or infinite,
+/// which the database's rules reject
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NonFiniteLine {
+    /// The (1 based) number of the line in the write
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+impl std::fmt::Display for NonFiniteLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: fields {}", self.line, self.fields.join(" "))
+    }
+}
+
+/// Sets the `_ingest_time` field of every line to `now`, replacing any
+/// value written by the client
+fn add_ingest_time(lines: &mut [ParsedLine<'_>], now: DateTime<Utc>) {
+    let ingest_time = now.timestamp_nanos();
+    for line in lines {
+        line.field_set
+            .retain(|(name, _)| name.as_str() != INGEST_TIME_COLUMN_NAME);
+        line.field_set.push((
+            EscapedStr::from(INGEST_TIME_COLUMN_NAME),
+            FieldValue::I64(ingest_time),
+        ));
+    }
+}
+
+fn display_database(db_name: &Option<String>) -> String {
+    match db_name {
+        Some(db_name) => format!("database {}", db_name),
+        None => "the server".to_string(),
+    }
+}
+
+fn display_rejected_lines(lines: &[RejectedLine]) -> String {
+    lines
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn display_non_finite_lines(lines: &[NonFiniteLine]) -> String {
+    lines
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 /// `Server` is the container struct for how servers store data internally, as
 /// well as how they communicate with other servers. Each server will have one
 /// of these structs, which keeps track of all replication and query rules.
@@ -149,6 +363,24 @@ pub struct Server<M: ConnectionManager> {
     connection_manager: Arc<M>,
     pub store: Arc<ObjectStore>,
     executor: Arc<Executor>,
+    restore_window: Duration,
+    namespaces: Namespaces,
+    /// Held while registering a namespace, so the namespaces are
+    /// persisted in the order they are assigned ids
+    namespace_registration: tokio::sync::Mutex<()>,
+    recovery: Arc<RecoveryTracker>,
+    recovery_concurrency: usize,
+    /// The backends databases may write their WAL segments to instead of
+    /// the object store of the server, by name
+    wal_backends: BTreeMap<String, Arc<dyn SegmentStore>>,
+    /// Records the queries and administrative actions run, if set
+    audit_log: Option<AuditLog>,
+    /// Decides which requests of the HTTP and gRPC APIs are allowed
+    authorizer: Arc<dyn Authorizer>,
+    /// The latency of the requests of the HTTP and gRPC APIs
+    latency: Arc<LatencyMetrics>,
+    /// The number of writes being replicated in the background
+    background_replications: Arc<AtomicUsize>,
 }
 
 impl<M: ConnectionManager> Server<M> {
@@ -159,6 +391,125 @@ impl<M: ConnectionManager> Server<M> {
             store,
             connection_manager: Arc::new(connection_manager),
             executor: Arc::new(Executor::new()),
+            restore_window: Duration::hours(DEFAULT_RESTORE_WINDOW_HOURS),
+            namespaces: Namespaces::default(),
+            namespace_registration: Default::default(),
+            recovery: Default::default(),
+            recovery_concurrency: DEFAULT_RECOVERY_CONCURRENCY,
+            wal_backends: BTreeMap::new(),
+            audit_log: None,
+            authorizer: Arc::new(AllowAll),
+            latency: Default::default(),
+            background_replications: Default::default(),
+        }
+    }
+
+    /// Use `executor` to run queries against the databases of this
+    /// server, rather than one with the default configuration
+    pub fn with_executor(self, executor: Arc<Executor>) -> Self {
+        Self { executor, ..self }
+    }
+
+    /// Keep deleted databases restorable for `restore_window` before
+    /// `purge_deleted_databases` removes them
+    pub fn with_restore_window(self, restore_window: Duration) -> Self {
+        Self {
+            restore_window,
+            ..self
+        }
+    }
+
+    /// Recover at most `recovery_concurrency` databases at the same time
+    /// in `load_database_configs`
+    pub fn with_recovery_concurrency(self, recovery_concurrency: usize) -> Self {
+        Self {
+            recovery_concurrency: recovery_concurrency.max(1),
+            ..self
+        }
+    }
+
+    /// Registers `backend` as `name`, so databases can write their WAL
+    /// segments to it with `WalSegmentStorage::Backend`
+    pub fn with_wal_backend(
+        mut self,
+        name: impl Into<String>,
+        backend: Arc<dyn SegmentStore>,
+    ) -> Self {
+        self.wal_backends.insert(name.into(), backend);
+        self
+    }
+
+    /// Record the queries and administrative actions run by this server in
+    /// `audit_log`
+    pub fn with_audit_log(self, audit_log: AuditLog) -> Self {
+        Self {
+            audit_log: Some(audit_log),
+            ..self
+        }
+    }
+
+    /// Use `authorizer` to decide which requests are allowed, rather than
+    /// allowing all of them
+    pub fn with_authorizer(self, authorizer: Arc<dyn Authorizer>) -> Self {
+        Self { authorizer, ..self }
+    }
+
+    /// Returns the authorizer of the server, for APIs that serve requests
+    /// without going through the server
+    pub fn authorizer(&self) -> Arc<dyn Authorizer> {
+        Arc::clone(&self.authorizer)
+    }
+
+    /// Returns the latency histograms of the requests served by the HTTP
+    /// and gRPC APIs
+    pub fn latency_metrics(&self) -> Arc<LatencyMetrics> {
+        Arc::clone(&self.latency)
+    }
+
+    /// Checks that `principal` may perform `action` on the database
+    /// `db_name`, or on the server itself if it is `None`
+    pub async fn authorize(
+        &self,
+        principal: Principal<'_>,
+        action: Action,
+        db_name: Option<&str>,
+    ) -> Result<()> {
+        match self.authorizer.check(principal, action, db_name).await {
+            Decision::Allow => Ok(()),
+            Decision::Deny => NotAuthorized {
+                action,
+                db_name: db_name.map(ToString::to_string),
+            }
+            .fail(),
+        }
+    }
+
+    /// Records that `action` ended with `result` in the audit log of the
+    /// server, if it has one. Callers must fail the action if this fails,
+    /// rather than return its result unrecorded.
+    pub async fn audit<T, E: std::fmt::Display>(
+        &self,
+        action: AuditAction<'_>,
+        result: &std::result::Result<T, E>,
+    ) -> Result<()> {
+        let error = result.as_ref().err().map(ToString::to_string);
+        self.audit_action(action, error.as_deref())
+            .await
+            .context(Auditing)
+    }
+
+    /// Returns the backend WAL segments are written to for `storage`
+    fn segment_store(&self, storage: &WalSegmentStorage) -> Result<Arc<dyn SegmentStore>> {
+        match storage {
+            WalSegmentStorage::ObjectStore => {
+                Ok(Arc::new(ObjectStoreSegments::new(Arc::clone(&self.store))))
+            }
+            WalSegmentStorage::Backend { name } => self
+                .wal_backends
+                .get(name)
+                .cloned()
+                .context(UnknownWalBackend { name }),
+            WalSegmentStorage::Directory { path, .. } => Ok(Arc::new(FileSegments::new(path))),
         }
     }
 
@@ -191,90 +542,348 @@ impl<M: ConnectionManager> Server<M> {
         let name = db_name.into();
         let db_name = DatabaseName::new(name.clone()).context(InvalidDatabaseName)?;
         rules.name = name;
+        if let Some(wal_buffer_config) = &rules.wal_buffer_config {
+            self.segment_store(&wal_buffer_config.segment_storage)?;
+        }
+
+        // a database that is still being recovered exists in object
+        // storage, and is added to the config once its recovery finishes
+        match self.recovery.get(&db_name) {
+            Some(state @ RecoveryState::Pending) | Some(state @ RecoveryState::Recovering) => {
+                return DatabaseNotReady {
+                    db_name: &*db_name,
+                    state,
+                }
+                .fail()
+            }
+            _ => {}
+        }
 
-        let db_reservation = self.config.create_db(db_name, rules)?;
+        let root = database_object_store_path(id, &db_name);
+        let db_reservation =
+            self.config
+                .create_db(db_name, rules, Some((Arc::clone(&self.store), root)))?;
 
         let data =
             Bytes::from(serde_json::to_vec(&db_reservation.db.rules).context(ErrorSerializing)?);
-        let len = data.len();
         let location = object_store_path_for_database_config(
             &server_object_store_path(id),
             &db_reservation.name,
         );
+        put_store_bytes(&location, &self.store, data).await?;
 
-        let stream_data = std::io::Result::Ok(data);
-        self.store
-            .put(
-                &location,
-                futures::stream::once(async move { stream_data }),
-                len,
-            )
-            .await
-            .context(StoreError)?;
-
+        // the database replaces the one whose recovery failed, if any
+        if self.recovery.get(&db_reservation.name).is_some() {
+            self.recovery
+                .set(&db_reservation.name, RecoveryState::Ready);
+        }
         db_reservation.commit();
 
         Ok(())
     }
 
+    /// Registers the InfluxDB 2.x `org` and `bucket` with the server and
+    /// creates the database storing their data, returning its name. Writes
+    /// and queries for the org and bucket go to that database from then on.
+    ///
+    /// If the org and bucket were written to before they were registered,
+    /// their namespace keeps the database named after them by
+    /// `org_and_bucket_to_database`, so its data stays visible.
+    pub async fn create_namespace(
+        &self,
+        org: &str,
+        bucket: &str,
+        rules: DatabaseRules,
+    ) -> Result<DatabaseName<'static>> {
+        let id = self.require_id()?;
+
+        // registrations are serialised so that each is assigned its own id
+        // and the namespaces are persisted one registration at a time
+        let _registration = self.namespace_registration.lock().await;
+
+        if let Some(namespace) = self.namespaces.get(org, bucket) {
+            let db_name = namespace.db_name();
+            self.create_database(db_name.to_string(), rules).await?;
+            return Ok(db_name);
+        }
+
+        let mut namespace = self.namespaces.next(org, bucket);
+        let legacy_db_name = org_and_bucket_to_database(org, bucket).ok();
+        match legacy_db_name {
+            Some(legacy_db_name) if self.db(&legacy_db_name).await.is_some() => {
+                namespace.database = Some(legacy_db_name.to_string());
+            }
+            // the database may be left by a registration that failed to
+            // persist the namespaces, in which case it is reused
+            _ if self.db(&namespace.db_name()).await.is_some() => {}
+            _ => {
+                self.create_database(namespace.db_name().to_string(), rules)
+                    .await?
+            }
+        }
+
+        // the namespace is persisted only once its database exists, so
+        // requests are never routed to a database that isn't there
+        let mut namespaces = self.namespaces.all();
+        namespaces.push(namespace.clone());
+        let data = Bytes::from(serde_json::to_vec(&namespaces).context(ErrorSerializing)?);
+        let location = object_store_path_for_namespaces(&server_object_store_path(id));
+        put_store_bytes(&location, &self.store, data).await?;
+
+        let db_name = namespace.db_name();
+        self.namespaces.insert(namespace);
+
+        Ok(db_name)
+    }
+
+    /// Deletes a database. The database stops accepting writes and
+    /// queries, but its data and objects in storage are kept so that it
+    /// can be brought back with `restore_database` until it is purged
+    /// by `purge_deleted_databases`.
+    pub async fn delete_database(&self, db_name: &str) -> Result<()> {
+        let id = self.require_id()?;
+
+        let db_name = DatabaseName::new(db_name.to_string()).context(InvalidDatabaseName)?;
+        self.require_db(&db_name)?;
+
+        // Write the tombstone before forgetting the database so a
+        // restart can't bring it back to life
+        let deleted_at = Utc::now();
+        let tombstone = Tombstone {
+            deleted_at: deleted_at.timestamp_nanos(),
+        };
+        let data = Bytes::from(serde_json::to_vec(&tombstone).context(ErrorSerializing)?);
+        let location =
+            object_store_path_for_database_tombstone(&server_object_store_path(id), &db_name);
+        put_store_bytes(&location, &self.store, data).await?;
+
+        self.config.delete_db(&db_name, deleted_at)
+    }
+
+    /// Restores a database deleted with `delete_database` that has not
+    /// yet been purged
+    pub async fn restore_database(&self, db_name: &str) -> Result<()> {
+        let id = self.require_id()?;
+
+        let db_name = DatabaseName::new(db_name.to_string()).context(InvalidDatabaseName)?;
+        if !self.config.is_deleted(&db_name) {
+            return DeletedDatabaseNotFound { db_name: &*db_name }.fail();
+        }
+
+        let location =
+            object_store_path_for_database_tombstone(&server_object_store_path(id), &db_name);
+        self.store.delete(&location).await.context(StoreError)?;
+
+        self.config.restore_db(&db_name)
+    }
+
+    /// Permanently removes the databases deleted longer than the restore
+    /// window ago, including all of their objects in storage. Returns
+    /// the names of the purged databases.
+    pub async fn purge_deleted_databases(&self) -> Result<Vec<String>> {
+        let id = self.require_id()?;
+        let cutoff = Utc::now() - self.restore_window;
+
+        let mut purged = vec![];
+        for db_name in self.config.deleted_before(cutoff) {
+            let db_path = database_object_store_path(id, &db_name);
+            delete_database_objects(&db_path, &self.store).await?;
+
+            self.config.purge_db(&db_name);
+            self.recovery.remove(&db_name);
+            info!("purged deleted database {}", db_name);
+            purged.push(db_name.to_string());
+        }
+
+        Ok(purged)
+    }
+
+    /// Copies the partition `partition_key` of the database `db_name`,
+    /// including its snapshot, to the database `target`, and drops it from
+    /// `db_name` if `mode` is `CopyMode::Move`. See `db::copy_partition`.
+    pub async fn copy_partition(
+        &self,
+        db_name: &str,
+        target: &str,
+        partition_key: &str,
+        mode: CopyMode,
+    ) -> Result<CopySummary> {
+        let mut dbs = Vec::with_capacity(2);
+        for name in &[db_name, target] {
+            let name = DatabaseName::new(name.to_string()).context(InvalidDatabaseName)?;
+            dbs.push(self.require_db(&name)?);
+        }
+
+        dbs[0]
+            .copy_partition_to(&dbs[1], partition_key, mode)
+            .await
+            .context(CopyingPartition)
+    }
+
+    /// Replays the WALs the servers `writer_ids` persisted to object
+    /// storage for the database `db_name` into it, for example after the
+    /// ingesters they ran were scaled down. Writes the database already
+    /// stored are skipped. The writes replayed are only in the mutable
+    /// buffer, so those WALs must be kept until their partitions are
+    /// snapshotted.
+    pub async fn fan_in_wals(
+        &self,
+        db_name: &str,
+        writer_ids: &[WriterId],
+    ) -> Result<FanInSummary> {
+        let id = self.require_id()?;
+        let db_name = DatabaseName::new(db_name.to_string()).context(InvalidDatabaseName)?;
+        let db = self.require_db(&db_name)?;
+        let invalid_writes = match &db.rules.wal_buffer_config {
+            Some(config) if config.skip_invalid_writes => InvalidWrites::Skip,
+            _ => InvalidWrites::Fail,
+        };
+
+        let mut fan_in = FanIn::new(&db, id);
+        for &writer_id in writer_ids {
+            let mut prefix = database_object_store_path(writer_id, &db_name);
+            prefix.push_dir(WAL_DIR);
+            fan_in
+                .add_stored_wal(&self.store, &prefix, invalid_writes)
+                .await
+                .context(FanningInWal { writer_id })?;
+        }
+        let summary = fan_in.finish();
+        info!("replayed WALs into database {}: {:?}", db_name, summary);
+
+        Ok(summary)
+    }
+
+    /// Renders the number of points and writes quarantined by the future
+    /// time cap of each database, in the Prometheus text format
+    pub fn render_quarantine_metrics(&self) -> String {
+        db::quarantine::render(&self.config.dbs())
+    }
+
+    /// Renders the number of times the open chunks of the mutable buffers
+    /// were closed to make room for writes, in the Prometheus text format
+    pub async fn render_mutable_buffer_metrics(&self) -> String {
+        db::metrics::render(&self.config.dbs()).await
+    }
+
+    /// Renders the number of writes and queries rejected by the quotas of
+    /// each database, in the Prometheus text format
+    pub fn render_quota_metrics(&self) -> String {
+        quota::render(&self.config.dbs())
+    }
+
+    /// Renders the free space of the WAL directory of each database writing
+    /// its segments to one, and the writes rejected for the lack of it, in
+    /// the Prometheus text format
+    pub fn render_wal_metrics(&self) -> String {
+        buffer::store::render(&self.config.dbs())
+    }
+
+    /// Drops the data that has expired at `now` under the retention rules
+    /// of each database. A database that fails is logged without stopping
+    /// the others, and retried on the next call.
+    pub async fn enforce_retention(&self, now: DateTime<Utc>) {
+        for (db_name, db) in self.config.dbs() {
+            if let Err(e) = db.enforce_retention(now).await {
+                error!("error enforcing retention of database {}: {}", db_name, e);
+            }
+        }
+    }
+
+    /// Verifies the snapshots of each database against their checksums,
+    /// reading at most `bytes_per_second` on average, and moves the
+    /// corrupted files out of the way. A database that fails is logged
+    /// without stopping the others.
+    pub async fn scrub_snapshots(&self, bytes_per_second: NonZeroU64) {
+        for (db_name, db) in self.config.dbs() {
+            if let Err(e) = db.scrub_snapshots(bytes_per_second).await {
+                error!("error scrubbing snapshots of database {}: {}", db_name, e);
+            }
+        }
+    }
+
     /// Loads the database configurations based on the databases in the
     /// object store. Any databases in the config already won't be
     /// replaced.
+    ///
+    /// The databases are recovered by a bounded number of concurrent
+    /// workers and each is served as soon as it has been recovered; their
+    /// progress is reported by `recovery_states`.
     pub async fn load_database_configs(&self) -> Result<()> {
         let id = self.require_id()?;
         let root_path = server_object_store_path(id);
 
-        // get the database names from the object store prefixes
-        // TODO: update object store to pull back all common prefixes by
-        //       following the next tokens.
-        let list_result = self
-            .store
-            .list_with_delimiter(&root_path)
-            .await
-            .context(StoreError)?;
-
-        let handles: Vec<_> = list_result
-            .common_prefixes
-            .into_iter()
-            .map(|mut path| {
-                let store = self.store.clone();
-                let config = self.config.clone();
+        let namespaces_path = object_store_path_for_namespaces(&root_path);
+        if let Some(data) = get_optional_store_bytes(&namespaces_path, &self.store).await? {
+            let namespaces: Vec<Namespace> =
+                serde_json::from_slice(&data).context(ErrorDeserializing)?;
+            self.namespaces.load(namespaces);
+        }
 
-                path.set_file_name(DB_RULES_FILE_NAME);
+        // get the database names from the object store prefixes, following
+        // the tokens of the pages of results
+        let mut common_prefixes = vec![];
+        let mut token = None;
+        loop {
+            let list_result = self
+                .store
+                .list_with_delimiter_paginated(&root_path, token.as_deref(), MAX_LIST_RESULTS)
+                .await
+                .context(StoreError)?;
+            common_prefixes.extend(list_result.common_prefixes);
+            token = list_result.next_token;
+            if token.is_none() {
+                break;
+            }
+        }
+        // stores that group the objects of each page by themselves can
+        // list a prefix in consecutive pages
+        common_prefixes.dedup();
 
-                tokio::task::spawn(async move {
-                    let mut res = get_store_bytes(&path, &store).await;
-                    while let Err(e) = &res {
-                        error!(
-                            "error getting database config {:?} from object store: {}",
-                            path, e
-                        );
-                        tokio::time::delay_for(tokio::time::Duration::from_secs(
-                            STORE_ERROR_PAUSE_SECONDS,
-                        ))
-                        .await;
-                        res = get_store_bytes(&path, &store).await;
-                    }
+        let wal_backends: Arc<BTreeSet<String>> =
+            Arc::new(self.wal_backends.keys().cloned().collect());
 
-                    let res = res.unwrap();
+        // every database is pending before any is recovered, so the
+        // databases still to be recovered can always be told apart
+        let recoveries: Vec<_> = common_prefixes
+            .into_iter()
+            .filter_map(|path| {
+                let name = std::path::Path::new(&self.store.convert_path(&path))
+                    .file_name()?
+                    .to_string_lossy()
+                    .to_string();
+                self.recovery.set(&name, RecoveryState::Pending);
 
-                    match serde_json::from_slice::<DatabaseRules>(&res) {
-                        Err(e) => {
-                            error!("error parsing database config {:?} from store: {}", path, e)
-                        }
-                        Ok(rules) => match DatabaseName::new(rules.name.clone()) {
-                            Err(e) => error!("error parsing name {} from rules: {}", rules.name, e),
-                            Ok(name) => match config.create_db(name, rules) {
-                                Err(e) => error!("error adding database to config: {}", e),
-                                Ok(handle) => handle.commit(),
+                let store = self.store.clone();
+                let config = self.config.clone();
+                let recovery = self.recovery.clone();
+                let wal_backends = Arc::clone(&wal_backends);
+                Some(async move {
+                    let task = tokio::task::spawn(recover_database(
+                        id,
+                        name.clone(),
+                        path,
+                        store,
+                        config,
+                        recovery.clone(),
+                        wal_backends,
+                    ));
+                    if let Err(e) = task.await {
+                        error!("error recovering database {}: {}", name, e);
+                        recovery.set(
+                            &name,
+                            RecoveryState::Error {
+                                message: e.to_string(),
                             },
-                        },
+                        );
                     }
                 })
             })
             .collect();
 
-        futures::future::join_all(handles).await;
+        futures::stream::iter(recoveries)
+            .for_each_concurrent(self.recovery_concurrency, |recovery| recovery)
+            .await;
 
         Ok(())
     }
@@ -295,43 +904,352 @@ impl<M: ConnectionManager> Server<M> {
     /// `ReplicatedWrite`, which is then replicated to other servers based
     /// on the configuration of the `db`. This is step #1 from the crate
     /// level documentation.
+    ///
+    /// If the database's table write rules reject any of the lines, none
+    /// of them are written and the error lists every rejected line.
+    /// Otherwise the database's write transforms are applied before the
+    /// lines are checked against the database's quotas and partitioned.
     pub async fn write_lines(&self, db_name: &str, lines: &[ParsedLine<'_>]) -> Result<()> {
-        let id = self.require_id()?;
+        self.write_lines_with_ack(db_name, lines, WriteAckLevel::default())
+            .await?;
+        Ok(())
+    }
 
+    /// Writes `lines` like `write_lines`, returning once the write has
+    /// reached the acknowledgement level `ack`. Returns the durability
+    /// the write had reached by then.
+    pub async fn write_lines_with_ack(
+        &self,
+        db_name: &str,
+        lines: &[ParsedLine<'_>],
+        ack: WriteAckLevel,
+    ) -> Result<WriteAck> {
         let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
-        let db = self
-            .config
-            .db(&db_name)
-            .context(DatabaseNotFound { db_name: &*db_name })?;
+        let db = self.require_db(&db_name)?;
+
+        let (write, quota_charge) = self.prepare_write(&db_name, &db, lines).await?;
+        let write_ack = self
+            .handle_replicated_write(&db_name, &db, write, ack)
+            .await?;
+        quota_charge.commit();
+
+        Ok(write_ack)
+    }
+
+    /// Checks `lines` against the table write rules, the handling of
+    /// non-finite floats and the quotas of `db`, applies its write
+    /// transforms and encodes them as the next write of this server. The
+    /// returned quota charge is to be committed once the write is stored.
+    async fn prepare_write<'a>(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &'a Db,
+        lines: &[ParsedLine<'_>],
+    ) -> Result<(ReplicatedWrite, WriteCharge<'a>)> {
+        let id = self.require_id()?;
+
+        let filter = db.table_write_filter();
+        let rejected: Vec<_> = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let table = line.series.measurement.as_str();
+                filter.check(table).map(|reason| RejectedLine {
+                    line: i + 1,
+                    table: table.to_string(),
+                    reason,
+                })
+            })
+            .collect();
+        ensure!(
+            rejected.is_empty(),
+            TableWriteRejected {
+                db_name: db_name.as_str(),
+                lines: rejected
+            }
+        );
+
+        let finite;
+        let lines = match db.rules.non_finite_floats {
+            NonFiniteFloats::Keep => lines,
+            NonFiniteFloats::Reject => {
+                let rejected: Vec<_> = lines
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, line)| {
+                        let fields = NonFiniteFloats::fields(line);
+                        if fields.is_empty() {
+                            return None;
+                        }
+                        Some(NonFiniteLine {
+                            line: i + 1,
+                            fields: fields.into_iter().map(ToString::to_string).collect(),
+                        })
+                    })
+                    .collect();
+                ensure!(
+                    rejected.is_empty(),
+                    NonFiniteFloatsRejected {
+                        db_name: db_name.as_str(),
+                        lines: rejected
+                    }
+                );
+                lines
+            }
+            NonFiniteFloats::Null => {
+                finite = {
+                    let mut lines = lines.to_vec();
+                    NonFiniteFloats::remove(&mut lines);
+                    lines
+                };
+                &finite
+            }
+        };
+
+        let transformed;
+        let lines = if db.rules.write_transforms.is_empty() {
+            lines
+        } else {
+            transformed = {
+                let mut lines = lines.to_vec();
+                db.rules.write_transforms.apply(&mut lines);
+                lines
+            };
+            &transformed
+        };
+
+        let quota_charge = db
+            .check_write_quotas(lines)
+            .await
+            .context(WriteQuotaExceeded {
+                db_name: db_name.as_str(),
+            })?;
+
+        let with_ingest_time;
+        let lines = if db.rules.record_ingest_time {
+            with_ingest_time = {
+                let mut lines = lines.to_vec();
+                add_ingest_time(&mut lines, Utc::now());
+                lines
+            };
+            &with_ingest_time
+        } else {
+            lines
+        };
 
         let sequence = db.next_sequence();
         let write = lines_to_replicated_write(id, sequence, lines, &db.rules);
 
-        self.handle_replicated_write(&db_name, &db, write).await?;
+        Ok((write, quota_charge))
+    }
 
-        Ok(())
+    /// Writes the rows of `batch` to the table `table_name` of the database
+    /// `db_name`, for writers that already have columnar data, returning
+    /// once the write has reached the acknowledgement level `ack`.
+    ///
+    /// The rows are checked against the rules and quotas of the database
+    /// and transformed like lines with the same values, but they are
+    /// encoded a column at a time rather than formatted and parsed as line
+    /// protocol. See `TableBatch` for how the columns of `batch` are
+    /// mapped to the table.
+    pub async fn write_table_batch(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        batch: &RecordBatch,
+        ack: WriteAckLevel,
+    ) -> Result<WriteAck> {
+        let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        let db = self.require_db(&db_name)?;
+
+        let (write, quota_charge) = self
+            .prepare_table_batch_write(&db_name, &db, table_name, batch)
+            .await?;
+        let write_ack = self
+            .handle_replicated_write(&db_name, &db, write, ack)
+            .await?;
+        quota_charge.commit();
+
+        Ok(write_ack)
+    }
+
+    /// Checks the rows of `batch` against the rules and quotas of `db`
+    /// like `prepare_write` checks lines, the row numbers standing in for
+    /// line numbers, and encodes them as the next write of this server
+    async fn prepare_table_batch_write<'a>(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &'a Db,
+        table_name: &str,
+        batch: &RecordBatch,
+    ) -> Result<(ReplicatedWrite, WriteCharge<'a>)> {
+        let id = self.require_id()?;
+        let mut batch =
+            TableBatch::try_new(table_name, batch).context(WritingTableBatch { table_name })?;
+
+        // all the rows are written to the same table, so they are all
+        // rejected or none is
+        if let Some(reason) = db.table_write_filter().check(table_name) {
+            let lines = (1..=batch.num_rows())
+                .map(|line| RejectedLine {
+                    line,
+                    table: table_name.to_string(),
+                    reason,
+                })
+                .collect();
+            return TableWriteRejected {
+                db_name: db_name.as_str(),
+                lines,
+            }
+            .fail();
+        }
+
+        match db.rules.non_finite_floats {
+            NonFiniteFloats::Keep => {}
+            NonFiniteFloats::Reject => {
+                let rejected: Vec<_> = batch
+                    .non_finite_fields()
+                    .into_iter()
+                    .map(|(row, fields)| NonFiniteLine {
+                        line: row + 1,
+                        fields: fields.into_iter().map(ToString::to_string).collect(),
+                    })
+                    .collect();
+                ensure!(
+                    rejected.is_empty(),
+                    NonFiniteFloatsRejected {
+                        db_name: db_name.as_str(),
+                        lines: rejected
+                    }
+                );
+            }
+            NonFiniteFloats::Null => batch.remove_non_finite(),
+        }
+
+        if !db.rules.write_transforms.is_empty() {
+            batch.apply_transforms(&db.rules.write_transforms);
+        }
+
+        let quota_charge =
+            db.check_batch_write_quotas(&batch)
+                .await
+                .context(WriteQuotaExceeded {
+                    db_name: db_name.as_str(),
+                })?;
+
+        if db.rules.record_ingest_time {
+            batch.set_ingest_time(Utc::now().timestamp_nanos());
+        }
+
+        let sequence = db.next_sequence();
+        let write = batch
+            .to_replicated_write(id, sequence, &db.rules)
+            .context(WritingTableBatch { table_name })?;
+
+        Ok((write, quota_charge))
+    }
+
+    /// Deletes every point of `table` of the database `db_name` whose tags
+    /// match all of `tag_matchers`. The deletion is logged in the WAL and
+    /// replicated like a write, so it is replayed in order with the writes
+    /// on recovery and applied by the other servers too.
+    pub async fn delete_series(
+        &self,
+        db_name: &str,
+        table: &str,
+        tag_matchers: &[TagMatcher],
+        ack: WriteAckLevel,
+    ) -> Result<WriteAck> {
+        let id = self.require_id()?;
+
+        let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        let db = self.require_db(&db_name)?;
+
+        let write = db
+            .delete_series_write(id, table, tag_matchers)
+            .context(DeletingSeries)?;
+        self.handle_replicated_write(&db_name, &db, write, ack)
+            .await
     }
 
+    /// Stores `write` in `db` and replicates it to the host groups of
+    /// `db`, waiting for the replication only if `ack` is
+    /// `WriteAckLevel::Replicated`, and for the WAL segment holding the
+    /// write to be persisted only if `ack` is `WriteAckLevel::Wal`
     pub async fn handle_replicated_write(
         &self,
         db_name: &DatabaseName<'_>,
         db: &Db,
         write: ReplicatedWrite,
-    ) -> Result<()> {
-        if let Some(buf) = &db.mutable_buffer {
+        ack: WriteAckLevel,
+    ) -> Result<WriteAck> {
+        let stored = self.store_write(db_name, db, write, ack).await?;
+        self.acknowledge_write(db_name, db, stored, ack).await
+    }
+
+    /// Stores `write` in `db` and appends it to the WAL buffer of `db`,
+    /// starting to persist the WAL segment holding it if the segment was
+    /// closed. Writes are stored in the order this is called.
+    async fn store_write(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        write: ReplicatedWrite,
+        ack: WriteAckLevel,
+    ) -> Result<StoredWrite> {
+        let mut write_ack = WriteAck::default();
+
+        // Refuse writes encoded by builds with an incompatible format before
+        // they are stored or passed along to other servers
+        write.check_version().context(InvalidReplicatedWrite)?;
+        check_wal_space(db_name, db)?;
+
+        // the backend closed segments are persisted to is resolved before
+        // the write is stored, so a write that couldn't be persisted fails
+        // without leaving its rows in the mutable buffer
+        let persist_to = match &db.wal_buffer {
+            Some(wal_buffer) if wal_buffer.lock().expect("mutex poisoned").persist => {
+                let storage = db
+                    .rules
+                    .wal_buffer_config
+                    .as_ref()
+                    .map(|config| config.segment_storage.clone())
+                    .unwrap_or_default();
+                Some((self.require_id()?, self.segment_store(&storage)?))
+            }
+            _ => None,
+        };
+        let persist_wal = ack == WriteAckLevel::Wal;
+        ensure!(
+            !persist_wal || persist_to.is_some(),
+            NoWalBuffer {
+                db_name: db_name.to_string()
+            }
+        );
+
+        fail_point!(crate::fail_points::BEFORE_MUTABLE_BUFFER_WRITE);
+        if !write.deletes().is_empty() {
+            // deletions are applied to every tier rather than stored
+            db.apply_delete_write(&write)
+                .await
+                .context(DeletingSeries)?;
+            write_ack.buffered = db.mutable_buffer.is_some();
+        } else if let Some(buf) = &db.mutable_buffer {
             buf.store_replicated_write(&write)
                 .await
-                .map_err(|e| Box::new(e) as DatabaseError)
+                .map_err(|e| Box::new(query::DatabaseError::from(e)) as DatabaseError)
                 .context(UnknownDatabaseError {})?;
+            db.record_stored_write(&write).await;
+            write_ack.buffered = true;
         }
 
         let write = Arc::new(write);
 
+        fail_point!(crate::fail_points::BEFORE_WAL_APPEND);
+        let mut persisted = None;
         if let Some(wal_buffer) = &db.wal_buffer {
-            let persist;
             let segment = {
                 let mut wal_buffer = wal_buffer.lock().expect("mutex poisoned");
-                persist = wal_buffer.persist;
 
                 // TODO: address this issue?
                 // the mutable buffer and the wal buffer have different locking mechanisms,
@@ -339,50 +1257,101 @@ impl<M: ConnectionManager> Server<M> {
                 // succeed while a WAL buffer write fails, which would then
                 // return an error. A single lock is probably undesirable, but
                 // we need to figure out what semantics we want.
-                wal_buffer.append(write.clone()).context(WalError)?
-            };
-
-            if let Some(segment) = segment {
-                if persist {
-                    let writer_id = self.require_id()?;
-                    let data = segment.to_file_bytes(writer_id).context(WalError)?;
-                    let store = self.store.clone();
-                    let location = database_object_store_path(writer_id, db_name);
-                    let location = buffer::object_store_path_for_segment(&location, segment.id)
-                        .context(WalError)?;
-                    persist_bytes_in_background(data, store, location);
+                let closed = wal_buffer.append(write.clone()).context(WalError)?;
+                // the segment holding a write acknowledged at the wal level
+                // is closed, so it is persisted right away
+                match closed {
+                    None if persist_wal => wal_buffer.close_segment_of(&write),
+                    closed => closed,
                 }
+            };
+            fail_point!(crate::fail_points::AFTER_WAL_APPEND);
+
+            if let (Some(segment), Some((writer_id, backend))) = (segment, persist_to) {
+                let data = segment.to_file_bytes(writer_id).context(WalError)?;
+                let db_name =
+                    DatabaseName::new(db_name.to_string()).context(InvalidDatabaseName)?;
+                persisted = Some(persist_segment_in_background(
+                    segment, data, backend, writer_id, db_name,
+                ));
             }
         }
 
-        for host_group_id in &db.rules.replication {
-            self.replicate_to_host_group(host_group_id, db_name, &write)
-                .await?;
+        Ok(StoredWrite {
+            write,
+            write_ack,
+            persisted,
+        })
+    }
+
+    /// Waits for the WAL segment holding the stored write `stored` to be
+    /// persisted if `ack` is `WriteAckLevel::Wal`, and replicates it to the
+    /// host groups of `db`, waiting for the replication only if `ack` is
+    /// `WriteAckLevel::Replicated`
+    async fn acknowledge_write(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        stored: StoredWrite,
+        ack: WriteAckLevel,
+    ) -> Result<WriteAck> {
+        let StoredWrite {
+            write,
+            mut write_ack,
+            persisted,
+        } = stored;
+
+        if let (Some(persisted), WriteAckLevel::Wal) = (persisted, ack) {
+            persisted.await.context(PersistingSegment)?;
+            write_ack.wal = true;
+        }
+
+        let wait = ack == WriteAckLevel::Replicated;
+        for host_group_id in &db.rules.replication {
+            if self
+                .replicate_to_host_group(host_group_id, db_name, &write, wait)
+                .await?
+            {
+                write_ack.replicated += 1;
+            } else {
+                write_ack.replicating += 1;
+            }
         }
 
         for subscription in &db.rules.subscriptions {
             match subscription.matcher.tables {
                 MatchTables::All => {
-                    self.replicate_to_host_group(&subscription.host_group_id, db_name, &write)
-                        .await?
+                    self.replicate_to_host_group(
+                        &subscription.host_group_id,
+                        db_name,
+                        &write,
+                        wait,
+                    )
+                    .await?;
                 }
                 MatchTables::Table(_) => unimplemented!(),
                 MatchTables::Regex(_) => unimplemented!(),
             }
         }
 
-        Ok(())
+        Ok(write_ack)
     }
 
     // replicates to a single host in the group based on hashing rules. If that host
     // is unavailable an error will be returned. The request may still succeed
     // if enough of the other host groups have returned a success.
+    //
+    // If `wait` is false, the write is sent to the host in the background and
+    // errors sending it are only logged, unless `MAX_BACKGROUND_REPLICATIONS`
+    // writes are already being sent in the background. Returns true if the
+    // write was sent before returning.
     async fn replicate_to_host_group(
         &self,
         host_group_id: &str,
         db_name: &DatabaseName<'_>,
-        write: &ReplicatedWrite,
-    ) -> Result<()> {
+        write: &Arc<ReplicatedWrite>,
+        wait: bool,
+    ) -> Result<bool> {
         let group = self
             .config
             .host_group(host_group_id)
@@ -402,24 +1371,103 @@ impl<M: ConnectionManager> Server<M> {
             .map_err(|e| Box::new(e) as DatabaseError)
             .context(UnableToGetConnection { server: host })?;
 
+        if !wait {
+            let in_flight = Arc::clone(&self.background_replications);
+            if in_flight.fetch_add(1, Ordering::SeqCst) < MAX_BACKGROUND_REPLICATIONS {
+                let host = host.clone();
+                let db_name = db_name.to_string();
+                let write = Arc::clone(write);
+                tokio::task::spawn(async move {
+                    if let Err(e) = connection.replicate(&db_name, &write).await {
+                        error!("error replicating write to {} of {}: {}", host, db_name, e);
+                    }
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                });
+                return Ok(false);
+            }
+            // writers outpacing the replication wait for it
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+
         connection
             .replicate(db_name, write)
             .await
             .map_err(|e| Box::new(e) as DatabaseError)
             .context(ErrorReplicating {})?;
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Returns the recovery states of the databases found in object
+    /// storage by `load_database_configs`, by database name
+    pub fn recovery_states(&self) -> BTreeMap<String, RecoveryState> {
+        self.recovery.states()
     }
 
     pub async fn db(&self, name: &DatabaseName<'_>) -> Option<Arc<Db>> {
         self.config.db(name)
     }
 
+    /// Returns the recovery state of the database `name` if it was found
+    /// in object storage but isn't served yet, because it is still being
+    /// recovered or its recovery failed
+    pub fn db_not_ready(&self, name: &DatabaseName<'_>) -> Option<RecoveryState> {
+        if self.config.db(name).is_some() {
+            return None;
+        }
+        self.recovery
+            .get(name)
+            .filter(|state| *state != RecoveryState::Ready)
+    }
+
+    /// Returns the database `name`, failing with `DatabaseNotReady` rather
+    /// than `DatabaseNotFound` if it hasn't been recovered yet
+    pub fn require_db(&self, name: &DatabaseName<'_>) -> Result<Arc<Db>> {
+        if let Some(db) = self.config.db(name) {
+            return Ok(db);
+        }
+        match self.db_not_ready(name) {
+            Some(state) => DatabaseNotReady {
+                db_name: name.as_str(),
+                state,
+            }
+            .fail(),
+            None => DatabaseNotFound {
+                db_name: name.as_str(),
+            }
+            .fail(),
+        }
+    }
+
     pub async fn db_rules(&self, name: &DatabaseName<'_>) -> Option<DatabaseRules> {
         self.config.db(name).map(|d| d.rules.clone())
     }
 }
 
+#[async_trait]
+impl<M> Auditor for Server<M>
+where
+    M: ConnectionManager + std::fmt::Debug + Send + Sync,
+{
+    async fn audit_action(
+        &self,
+        action: AuditAction<'_>,
+        error: Option<&str>,
+    ) -> Result<(), audit::Error> {
+        let result = match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+        match &self.audit_log {
+            Some(audit_log) => {
+                let writer_id = self.require_id().ok();
+                audit_log.record(writer_id, action, &result).await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
 #[async_trait]
 impl<M> DatabaseStore for Server<M>
 where
@@ -436,6 +1484,11 @@ where
         None
     }
 
+    fn db_not_ready(&self, name: &str) -> Option<String> {
+        let name = DatabaseName::new(name).ok()?;
+        self.db_not_ready(&name).map(|state| state.to_string())
+    }
+
     // TODO: refactor usages of this to use the Server rather than this trait and to
     //       explicitly create a database.
     async fn db_or_create(&self, name: &str) -> Result<Arc<Self::Database>, Self::Error> {
@@ -457,6 +1510,23 @@ where
         Ok(db)
     }
 
+    /// Returns the name of the database storing the data of the InfluxDB
+    /// 2.x `org` and `bucket`: the database of their namespace if they
+    /// were registered with `create_namespace`, otherwise the database
+    /// named after them by `org_and_bucket_to_database`.
+    ///
+    /// Both the HTTP and gRPC APIs resolve orgs and buckets this way.
+    async fn namespace_db_name(
+        &self,
+        org: &str,
+        bucket: &str,
+    ) -> Result<DatabaseName<'static>, OrgBucketMappingError> {
+        match self.namespaces.get(org, bucket) {
+            Some(namespace) => Ok(namespace.db_name()),
+            None => org_and_bucket_to_database(org, bucket),
+        }
+    }
+
     fn executor(&self) -> Arc<Executor> {
         self.executor.clone()
     }
@@ -536,29 +1606,341 @@ fn server_object_store_path(writer_id: u32) -> ObjectStorePath {
 
 const STORE_ERROR_PAUSE_SECONDS: u64 = 100;
 
-/// Spawns a tokio task that will continuously try to persist the bytes to the
-/// given object store location.
-fn persist_bytes_in_background(data: Bytes, store: Arc<ObjectStore>, location: ObjectStorePath) {
-    let len = data.len();
-    let mut stream_data = std::io::Result::Ok(data.clone());
+/// A write stored in a database and appended to its WAL buffer, waiting
+/// to be acknowledged by `Server::acknowledge_write`
+#[derive(Debug)]
+struct StoredWrite {
+    write: Arc<ReplicatedWrite>,
+    write_ack: WriteAck,
+    /// The task persisting the WAL segment holding the write, if the
+    /// write closed it
+    persisted: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// How long the free space of the WAL directory of a database read by a
+/// write is reused by the writes after it
+const WAL_SPACE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
 
+/// Rejects writes to `db` while the file system of the directory it writes
+/// its WAL segments to, if any, has less space free than its rules require
+fn check_wal_space(db_name: &DatabaseName<'_>, db: &Db) -> Result<()> {
+    let (path, min_free_bytes) = match db.wal_directory() {
+        Some(directory) => directory,
+        None => return Ok(()),
+    };
+
+    let metrics = db.wal_metrics();
+    let available =
+        match metrics.available_space(std::path::Path::new(path), WAL_SPACE_CHECK_INTERVAL) {
+            Ok(available) => available,
+            Err(e) => {
+                // writes aren't rejected just because the free space is unknown
+                warn!(
+                    "error checking the free space for the WAL of {} in {}: {}",
+                    db_name, path, e
+                );
+                return Ok(());
+            }
+        };
+
+    if available < min_free_bytes {
+        metrics.disk_full_rejections.fetch_add(1, Ordering::Relaxed);
+        return WalDiskFull {
+            db_name: db_name.to_string(),
+            path,
+            available,
+            min_free_bytes,
+        }
+        .fail();
+    }
+
+    Ok(())
+}
+
+/// Spawns a tokio task that will continuously try to write the segment to
+/// the given backend, marking it as persisted once it has been written.
+/// The returned handle resolves once the segment is persisted.
+fn persist_segment_in_background(
+    segment: Arc<Segment>,
+    data: Bytes,
+    backend: Arc<dyn SegmentStore>,
+    writer_id: u32,
+    db_name: DatabaseName<'static>,
+) -> tokio::task::JoinHandle<()> {
     tokio::task::spawn(async move {
-        while let Err(err) = store
-            .put(
-                &location,
-                futures::stream::once(async move { stream_data }),
-                len,
-            )
+        fail_point!(crate::fail_points::BEFORE_SEGMENT_STORE);
+        while let Err(err) = backend
+            .store_segment(writer_id, &db_name, segment.id, data.clone())
             .await
         {
-            error!("error writing bytes to store: {}", err);
+            error!(
+                "error writing segment {} of {} to {:?}: {}",
+                segment.id, db_name, backend, err
+            );
             tokio::time::delay_for(tokio::time::Duration::from_secs(STORE_ERROR_PAUSE_SECONDS))
                 .await;
-            stream_data = std::io::Result::Ok(data.clone());
         }
+        fail_point!(crate::fail_points::AFTER_SEGMENT_STORE);
 
-        info!("persisted data to {}", store.convert_path(&location));
+        segment.set_persisted_at(Utc::now());
+        info!("persisted segment {} of {}", segment.id, db_name);
+    })
+}
+
+/// Marks a database as deleted in object storage
+#[derive(Debug, Serialize, Deserialize)]
+struct Tombstone {
+    /// When the database was deleted, in nanoseconds since the epoch
+    deleted_at: i64,
+}
+
+/// Loads the configuration of the database `name` stored under `path`
+/// into `config`, retrying object store errors, and tracks its progress
+/// in `recovery`. A database writing its WAL to a backend missing from
+/// `wal_backends` fails to recover, as none of its writes could succeed.
+/// `writer_id` is the id of this server.
+async fn recover_database(
+    writer_id: WriterId,
+    name: String,
+    mut path: ObjectStorePath,
+    store: Arc<ObjectStore>,
+    config: Arc<Config>,
+    recovery: Arc<RecoveryTracker>,
+    wal_backends: Arc<BTreeSet<String>>,
+) {
+    recovery.set(&name, RecoveryState::Recovering);
+
+    let root = path.clone();
+    let mut tombstone_path = path.clone();
+    tombstone_path.set_file_name(DB_TOMBSTONE_FILE_NAME);
+    path.set_file_name(DB_RULES_FILE_NAME);
+
+    let mut deleted_at = get_tombstone(&tombstone_path, &store).await;
+    while let Err(e) = &deleted_at {
+        error!(
+            "error getting database tombstone {:?} from object store: {}",
+            tombstone_path, e
+        );
+        recovery.set(
+            &name,
+            RecoveryState::Error {
+                message: e.to_string(),
+            },
+        );
+        tokio::time::delay_for(tokio::time::Duration::from_secs(STORE_ERROR_PAUSE_SECONDS)).await;
+        deleted_at = get_tombstone(&tombstone_path, &store).await;
+    }
+
+    if let Some(deleted_at) = deleted_at.unwrap() {
+        load_deleted_database(&root, &path, deleted_at, &store, &config).await;
+        recovery.set(&name, RecoveryState::Ready);
+        return;
+    }
+
+    let mut res = get_store_bytes(&path, &store).await;
+    while let Err(e) = &res {
+        error!(
+            "error getting database config {:?} from object store: {}",
+            path, e
+        );
+        recovery.set(
+            &name,
+            RecoveryState::Error {
+                message: e.to_string(),
+            },
+        );
+        tokio::time::delay_for(tokio::time::Duration::from_secs(STORE_ERROR_PAUSE_SECONDS)).await;
+        res = get_store_bytes(&path, &store).await;
+    }
+
+    let res = res.unwrap();
+
+    let message = match serde_json::from_slice::<DatabaseRules>(&res) {
+        Err(e) => format!("error parsing database config {:?} from store: {}", path, e),
+        Ok(rules) => match DatabaseName::new(rules.name.clone()) {
+            Err(e) => format!("error parsing name {} from rules: {}", rules.name, e),
+            Ok(db_name) => match unknown_wal_backend(&rules, &wal_backends) {
+                Some(backend) => format!(
+                    "database {} writes its WAL to unknown backend {}",
+                    db_name, backend
+                ),
+                None => match config.create_db(db_name, rules, Some((store, root))) {
+                    // the database was created while it was being recovered,
+                    // so it is served with the rules it was created with
+                    Err(Error::DatabaseAlreadyExists { db_name }) => {
+                        info!("database {} was created during its recovery", db_name);
+                        recovery.set(&name, RecoveryState::Ready);
+                        return;
+                    }
+                    Err(e) => format!("error adding database to config: {}", e),
+                    Ok(handle) => {
+                        if let Err(e) = handle.db.load_schema_history().await {
+                            error!("error loading schema history of database {}: {}", name, e);
+                        }
+                        // the database is only served once its WAL is
+                        // replayed, so no acknowledged write is missing
+                        // from its queries
+                        let replaying = std::time::Instant::now();
+                        match handle.db.restore_partitions_from_wal(writer_id).await {
+                            Ok(summary) => {
+                                info!(
+                                    "replayed the WAL of database {} in {:?}: {:?}",
+                                    name,
+                                    replaying.elapsed(),
+                                    summary
+                                );
+                                handle.commit();
+                                recovery.set(&name, RecoveryState::Ready);
+                                return;
+                            }
+                            Err(e) => {
+                                format!("error replaying the WAL of database {}: {}", name, e)
+                            }
+                        }
+                    }
+                },
+            },
+        },
+    };
+
+    error!("{}", message);
+    recovery.set(&name, RecoveryState::Error { message });
+}
+
+/// Returns the name of the backend `rules` write WAL segments to, if it
+/// isn't one of `wal_backends`
+fn unknown_wal_backend(rules: &DatabaseRules, wal_backends: &BTreeSet<String>) -> Option<String> {
+    match &rules.wal_buffer_config.as_ref()?.segment_storage {
+        WalSegmentStorage::Backend { name } if !wal_backends.contains(name) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+async fn load_deleted_database(
+    db_path: &ObjectStorePath,
+    rules_path: &ObjectStorePath,
+    deleted_at: DateTime<Utc>,
+    store: &ObjectStore,
+    config: &Config,
+) {
+    // a purge removes the rules first and the tombstone last, so missing
+    // rules mean a purge was interrupted, which is finished here
+    let rules = match get_optional_store_bytes(rules_path, store).await {
+        Ok(Some(rules)) => rules,
+        Ok(None) => {
+            info!("finishing the purge of deleted database {:?}", db_path);
+            if let Err(e) = delete_database_objects(db_path, store).await {
+                error!("error purging deleted database {:?}: {}", db_path, e);
+            }
+            return;
+        }
+        Err(e) => {
+            warn!("skipping deleted database {:?}: {}", rules_path, e);
+            return;
+        }
+    };
+
+    match serde_json::from_slice::<DatabaseRules>(&rules) {
+        Err(e) => error!(
+            "error parsing database config {:?} from store: {}",
+            rules_path, e
+        ),
+        Ok(rules) => match DatabaseName::new(rules.name.clone()) {
+            Err(e) => error!("error parsing name {} from rules: {}", rules.name, e),
+            Ok(name) => {
+                if let Err(e) = config.add_deleted_db(name, rules, deleted_at) {
+                    error!("error adding deleted database to config: {}", e)
+                }
+            }
+        },
+    }
+}
+
+/// Deletes all objects in the directory `db_path` of a deleted database.
+/// Its rules are deleted first, so an interrupted purge can't bring the
+/// database back, and its tombstone last, so the purge is finished when
+/// the server restarts.
+async fn delete_database_objects(db_path: &ObjectStorePath, store: &ObjectStore) -> Result<()> {
+    let mut rules = db_path.clone();
+    rules.set_file_name(DB_RULES_FILE_NAME);
+    let rules = store.convert_path(&rules);
+    let mut tombstone = db_path.clone();
+    tombstone.set_file_name(DB_TOMBSTONE_FILE_NAME);
+    let tombstone = store.convert_path(&tombstone);
+
+    // The listing prefix also matches databases whose names start with
+    // this one, so only keep objects inside its directory
+    let db_dir = store.convert_path(db_path);
+    let mut paths: Vec<_> = list_store_paths(db_path, store)
+        .await?
+        .into_iter()
+        .map(|path| (store.convert_path(&path), path))
+        .filter(|(converted, _)| std::path::Path::new(converted).starts_with(&db_dir))
+        .collect();
+
+    paths.sort_by_key(|(converted, _)| {
+        if *converted == rules {
+            0
+        } else if *converted == tombstone {
+            2
+        } else {
+            1
+        }
     });
+    for (_, path) in &paths {
+        store.delete(path).await.context(StoreError)?;
+    }
+
+    Ok(())
+}
+
+// returns when the database was deleted if there is a tombstone at the
+// location in object store
+async fn get_tombstone(
+    location: &ObjectStorePath,
+    store: &ObjectStore,
+) -> Result<Option<DateTime<Utc>>> {
+    let data = match get_optional_store_bytes(location, store).await? {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    let tombstone: Tombstone = serde_json::from_slice(&data).context(ErrorDeserializing)?;
+
+    Ok(Some(Utc.timestamp_nanos(tombstone.deleted_at)))
+}
+
+// list all the objects in object store with the given prefix
+async fn list_store_paths(
+    prefix: &ObjectStorePath,
+    store: &ObjectStore,
+) -> Result<Vec<ObjectStorePath>> {
+    let paths: Vec<Vec<_>> = store
+        .list(Some(prefix))
+        .await
+        .context(StoreError)?
+        .try_collect()
+        .await
+        .context(StoreError)?;
+
+    Ok(paths.into_iter().flatten().collect())
+}
+
+// put bytes at the location in object store
+async fn put_store_bytes(
+    location: &ObjectStorePath,
+    store: &ObjectStore,
+    data: Bytes,
+) -> Result<()> {
+    let len = data.len();
+    let stream_data = std::io::Result::Ok(data);
+    store
+        .put(
+            location,
+            futures::stream::once(async move { stream_data }),
+            len,
+        )
+        .await
+        .context(StoreError)
 }
 
 // get bytes from the location in object store
@@ -578,22 +1960,45 @@ async fn get_store_bytes(
     Ok(b)
 }
 
+// get bytes from the location in object store, or None if there is no
+// object at the location
+async fn get_optional_store_bytes(
+    location: &ObjectStorePath,
+    store: &ObjectStore,
+) -> Result<Option<bytes::BytesMut>> {
+    let expected = store.convert_path(location);
+    let exists = list_store_paths(location, store)
+        .await?
+        .iter()
+        .any(|path| store.convert_path(path) == expected);
+    if !exists {
+        return Ok(None);
+    }
+
+    get_store_bytes(location, store).await.map(Some)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::buffer::Segment;
-    use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+    use arrow_deps::{
+        arrow::{
+            array::{ArrayRef, Float64Array, Int64Array, StringArray},
+            datatypes::{DataType, Field, Schema as ArrowSchema},
+        },
+        assert_table_eq,
+        datafusion::physical_plan::collect,
+    };
     use async_trait::async_trait;
     use data_types::database_rules::{
-        MatchTables, Matcher, PartitionTemplate, Subscription, TemplatePart, WalBufferConfig,
-        WalBufferRollover,
+        DatabaseQuotas, MatchTables, Matcher, PartitionTemplate, Subscription, TableWriteRules,
+        TemplatePart, WalBufferConfig, WalBufferRollover, WriteTransforms,
     };
     use futures::TryStreamExt;
     use influxdb_line_protocol::parse_lines;
     use object_store::memory::InMemory;
-    use query::frontend::sql::SQLQueryPlanner;
+    use query::{frontend::sql::SQLQueryPlanner, PartitionChunk};
     use snafu::Snafu;
-    use std::collections::BTreeMap;
     use std::sync::Mutex;
 
     type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -634,6 +2039,7 @@ mod tests {
         let rules = DatabaseRules {
             partition_template: PartitionTemplate {
                 parts: vec![TemplatePart::TimeFormat("YYYY-MM".to_string())],
+                ..Default::default()
             },
             name: name.to_string(),
             ..Default::default()
@@ -682,6 +2088,533 @@ mod tests {
         let _ = server2.db(&DatabaseName::new(name).unwrap()).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn delete_restore_and_purge_database() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone())
+            .with_restore_window(Duration::zero());
+        server.set_id(1);
+
+        let name = DatabaseName::new("bananas")?;
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database(name.as_str(), rules).await?;
+        server
+            .create_database("bananas_split", DatabaseRules::default())
+            .await?;
+        server
+            .write_lines(name.as_str(), &parsed_lines("cpu foo=1 10"))
+            .await?;
+
+        server.delete_database(name.as_str()).await?;
+        assert!(server.db(&name).await.is_none());
+        let err = server
+            .write_lines(name.as_str(), &parsed_lines("cpu foo=2 20"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotFound { .. }));
+        let err = server
+            .create_database(name.as_str(), DatabaseRules::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseDeleted { .. }));
+
+        // a restart still knows the database is deleted
+        let server2 = Server::new(TestConnectionManager::new(), store.clone());
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert!(server2.db(&name).await.is_none());
+        assert!(server2
+            .db(&DatabaseName::new("bananas_split")?)
+            .await
+            .is_some());
+
+        // restoring brings back the data written before the delete
+        server.restore_database(name.as_str()).await?;
+        let db = server.db(&name).await.expect("database restored");
+        let planner = SQLQueryPlanner::default();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", server.executor().as_ref())
+            .await?;
+        let batches = collect(physical_plan).await?;
+        let expected = vec![
+            "+-----+------+",
+            "| foo | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        let err = server.restore_database(name.as_str()).await.unwrap_err();
+        assert!(matches!(err, Error::DeletedDatabaseNotFound { .. }));
+
+        // once purged, only the other database's objects are left
+        server.delete_database(name.as_str()).await?;
+        let purged = server.purge_deleted_databases().await?;
+        assert_eq!(purged, vec!["bananas".to_string()]);
+
+        let remaining: Vec<_> = list_store_paths(&ObjectStorePath::default(), &store)
+            .await?
+            .iter()
+            .map(|path| store.convert_path(path))
+            .collect();
+        assert_eq!(remaining, vec!["1/bananas_split/rules.json"]);
+
+        server
+            .create_database(name.as_str(), DatabaseRules::default())
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn interrupted_purge_is_finished_on_restart() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+
+        let name = DatabaseName::new("bananas")?;
+        server
+            .create_database(name.as_str(), DatabaseRules::default())
+            .await?;
+        server.delete_database(name.as_str()).await?;
+
+        // a purge that stopped after deleting the rules
+        let mut rules = database_object_store_path(1, &name);
+        rules.set_file_name(DB_RULES_FILE_NAME);
+        store.delete(&rules).await.context(StoreError)?;
+
+        let server2 = Server::new(TestConnectionManager::new(), store.clone());
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert!(server2.db(&name).await.is_none());
+
+        let remaining = list_store_paths(&ObjectStorePath::default(), &store).await?;
+        assert!(remaining.is_empty(), "{:?}", remaining);
+
+        // the database can be created again
+        server2
+            .create_database(name.as_str(), DatabaseRules::default())
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn namespaces_are_persisted() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+
+        let cpu = server
+            .create_namespace("MyOrg", "cpu", DatabaseRules::default())
+            .await?;
+        let mem = server
+            .create_namespace("MyOrg", "mem", DatabaseRules::default())
+            .await?;
+        assert_eq!(cpu.as_str(), "ns-0000000000000001");
+        assert_eq!(mem.as_str(), "ns-0000000000000002");
+        assert_eq!(server.namespace_db_name("MyOrg", "cpu").await?, cpu);
+        assert!(server.db(&cpu).await.is_some());
+
+        // pairs that weren't registered keep their derived database name
+        assert_eq!(
+            server.namespace_db_name("MyOrg", "disk").await?.as_str(),
+            "MyOrg_disk"
+        );
+
+        let err = server
+            .create_namespace("MyOrg", "cpu", DatabaseRules::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseAlreadyExists { .. }));
+
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert_eq!(server2.namespace_db_name("MyOrg", "cpu").await?, cpu);
+        assert_eq!(server2.namespace_db_name("MyOrg", "mem").await?, mem);
+        assert!(server2.db(&mem).await.is_some());
+
+        let disk = server2
+            .create_namespace("MyOrg", "disk", DatabaseRules::default())
+            .await?;
+        assert_eq!(disk.as_str(), "ns-0000000000000003");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn namespaces_are_registered_once_their_database_exists() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+
+        // the database can't be created, so the namespace isn't registered
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 500,
+                segment_size: 10,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+                segment_storage: WalSegmentStorage::Backend {
+                    name: "s3".to_string(),
+                },
+                skip_invalid_writes: false,
+            }),
+            ..Default::default()
+        };
+        let err = server
+            .create_namespace("MyOrg", "cpu", rules)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::UnknownWalBackend { .. }));
+        assert_eq!(
+            server.namespace_db_name("MyOrg", "cpu").await?.as_str(),
+            "MyOrg_cpu"
+        );
+        let namespaces_path = object_store_path_for_namespaces(&server_object_store_path(1));
+        assert!(get_optional_store_bytes(&namespaces_path, &store)
+            .await?
+            .is_none());
+
+        // an org and bucket written to before being registered keep their
+        // database
+        server
+            .create_database("MyOrg_mem", DatabaseRules::default())
+            .await?;
+        let mem = server
+            .create_namespace("MyOrg", "mem", DatabaseRules::default())
+            .await?;
+        assert_eq!(mem.as_str(), "MyOrg_mem");
+
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert_eq!(server2.namespace_db_name("MyOrg", "mem").await?, mem);
+
+        let cpu = server2
+            .create_namespace("MyOrg", "cpu", DatabaseRules::default())
+            .await?;
+        assert_eq!(cpu.as_str(), "ns-0000000000000002");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_exceeding_quotas_are_rejected() -> Result {
+        let server = Server::new(
+            TestConnectionManager::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        server.set_id(1);
+
+        let rules = DatabaseRules {
+            store_locally: true,
+            quotas: DatabaseQuotas {
+                max_series: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        server.create_database("limited", rules).await?;
+        server
+            .create_database("unlimited", DatabaseRules::default())
+            .await?;
+
+        let lines = parsed_lines("cpu,host=a foo=1 10\ncpu,host=b foo=1 10");
+        let err = server.write_lines("limited", &lines).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::WriteQuotaExceeded {
+                source: quota::Error::SeriesExceeded { .. },
+                ..
+            }
+        ));
+        server.write_lines("limited", &lines[..1]).await?;
+        server.write_lines("unlimited", &lines).await?;
+
+        let db = server.db(&DatabaseName::new("limited")?).await.unwrap();
+        assert_eq!(
+            db.quota_metrics().series_rejections.load(Ordering::Relaxed),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn partitions_by_tag_value_are_limited() -> Result {
+        let server = Server::new(
+            TestConnectionManager::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        server.set_id(1);
+
+        let rules = DatabaseRules {
+            store_locally: true,
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y-%m-%d_{region}".to_string())],
+                ..Default::default()
+            },
+            quotas: DatabaseQuotas {
+                max_partitions: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        server.create_database("regions", rules.clone()).await?;
+
+        let lines = parsed_lines(
+            "cpu,region=west foo=1 10
+cpu foo=2 20",
+        );
+        server.write_lines("regions", &lines).await?;
+
+        let lines = parsed_lines(
+            "cpu,region=west foo=3 30
+cpu,region=east foo=4 40",
+        );
+        let err = server.write_lines("regions", &lines).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::WriteQuotaExceeded {
+                source: quota::Error::PartitionsExceeded {
+                    new_partitions: 1,
+                    max: 2
+                },
+                ..
+            }
+        ));
+        server.write_lines("regions", &lines[..1]).await?;
+
+        let db = server.db(&DatabaseName::new("regions")?).await.unwrap();
+        let mut partition_keys = db.partition_keys().await?;
+        partition_keys.sort();
+        assert_eq!(partition_keys, vec!["1970-01-01_", "1970-01-01_west"]);
+
+        // dropping the data of a partition frees its place in the quota
+        let chunk = db.rollover_partition("1970-01-01_").await?;
+        db.drop_mutable_buffer_chunk("1970-01-01_", chunk.id())
+            .await?;
+        server.write_lines("regions", &lines).await?;
+
+        // lines that can't be partitioned are rejected rather than panicking
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![serde_json::from_str(
+                    r#"{"RegexCapture": {"column": "region", "regex": ".*"}}"#,
+                )
+                .unwrap()],
+                ..Default::default()
+            },
+            ..rules
+        };
+        server.create_database("unpartitioned", rules).await?;
+        let err = server
+            .write_lines("unpartitioned", &lines)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::WriteQuotaExceeded {
+                source: quota::Error::PartitionKey { .. },
+                ..
+            }
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_summary() -> Result {
+        let server = Server::new(
+            TestConnectionManager::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        server.set_id(1);
+
+        let rules = DatabaseRules {
+            store_locally: true,
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Column("region".to_string())],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        server.create_database("summarized", rules).await?;
+
+        let lines = parsed_lines(
+            "cpu,region=west foo=1 10
+cpu,region=east foo=2 20",
+        );
+        server.write_lines("summarized", &lines).await?;
+        let lines = parsed_lines("cpu,region=west foo=3 5");
+        server.write_lines("summarized", &lines).await?;
+
+        let db = server.db(&DatabaseName::new("summarized")?).await.unwrap();
+        let chunk = db.rollover_partition("region_west").await?;
+        db.load_chunk_to_read_buffer("region_west", chunk.id())
+            .await?;
+
+        let summary = db.summary().await;
+        assert_eq!(summary.partitions, 2);
+        let cpu = &summary.tables["cpu"];
+        assert_eq!(cpu.partitions, 2);
+        assert_eq!(cpu.rows, 3);
+        assert_eq!((cpu.min_time, cpu.max_time), (Some(5), Some(20)));
+        assert!(summary.storage.mutable_buffer_bytes > 0);
+        assert!(summary.storage.read_buffer_bytes > 0);
+
+        // the rows stay summarized while either buffer holds the chunk
+        db.drop_mutable_buffer_chunk("region_west", chunk.id())
+            .await?;
+        assert_eq!(db.summary().await.tables["cpu"].rows, 3);
+        db.drop_read_buffer_chunk("region_west", chunk.id()).await?;
+        let summary = db.summary().await;
+        assert_eq!(summary.partitions, 1);
+        let cpu = &summary.tables["cpu"];
+        assert_eq!(cpu.rows, 1);
+        assert_eq!((cpu.min_time, cpu.max_time), (Some(20), Some(20)));
+
+        // as do the rows deleted
+        db.delete_series("cpu", &[TagMatcher::new("region", "east")])
+            .await?;
+        assert!(db.summary().await.tables.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn databases_are_recovered_concurrently() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+        for name in &["apples", "bananas", "cherries", "dates"] {
+            server
+                .create_database(*name, DatabaseRules::default())
+                .await?;
+        }
+        server.delete_database("dates").await?;
+
+        let corrupt = Bytes::from("not json");
+        put_store_bytes(
+            &ObjectStorePath::from_cloud_unchecked("1/figs/rules.json"),
+            &store,
+            corrupt,
+        )
+        .await?;
+
+        let server2 = Server::new(TestConnectionManager::new(), store).with_recovery_concurrency(2);
+        server2.set_id(1);
+        assert!(server2.recovery_states().is_empty());
+        server2.load_database_configs().await?;
+
+        let states = server2.recovery_states();
+        let names: Vec<_> = states.keys().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            vec!["apples", "bananas", "cherries", "dates", "figs"]
+        );
+        for name in &["apples", "bananas", "cherries", "dates"] {
+            assert_eq!(states[*name], RecoveryState::Ready);
+        }
+        assert!(matches!(&states["figs"], RecoveryState::Error { .. }));
+
+        server2
+            .write_lines("apples", &parsed_lines("cpu foo=1 10"))
+            .await?;
+        let err = server2
+            .write_lines("figs", &parsed_lines("cpu foo=1 10"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotReady { .. }));
+        let err = server2.delete_database("figs").await.unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotReady { .. }));
+        let err = server2
+            .copy_partition("apples", "figs", "1970-01-01T00", CopyMode::Copy)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotReady { .. }));
+        let err = server2
+            .write_lines("grapes", &parsed_lines("cpu foo=1 10"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotFound { .. }));
+
+        // a database whose recovery failed can be created again
+        server2
+            .create_database("figs", DatabaseRules::default())
+            .await?;
+        assert_eq!(server2.recovery_states()["figs"], RecoveryState::Ready);
+        server2
+            .write_lines("figs", &parsed_lines("cpu foo=1 10"))
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn schema_history_survives_restarts() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+        server
+            .create_database("apples", DatabaseRules::default())
+            .await?;
+        server
+            .write_lines("apples", &parsed_lines("cpu,host=a foo=1 10"))
+            .await?;
+        let db_name = DatabaseName::new("apples")?;
+        let changes = server.db(&db_name).await.unwrap().schema_changes(None);
+        assert_eq!(changes.len(), 3);
+
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        server2
+            .write_lines("apples", &parsed_lines("cpu,host=b foo=2,bar=3 20"))
+            .await?;
+
+        let changes2 = server2.db(&db_name).await.unwrap().schema_changes(None);
+        assert_eq!(changes2[..3], changes[..]);
+        assert!(matches!(
+            &changes2[3..],
+            [schema_history::SchemaChange::ColumnAdded { column_name, .. }] if column_name == "bar"
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_created_during_recovery_is_ready() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+        server
+            .create_database("apples", DatabaseRules::default())
+            .await?;
+
+        // the database is created again before the recovery gets to it
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2
+            .create_database("apples", DatabaseRules::default())
+            .await?;
+        server2.load_database_configs().await?;
+
+        assert_eq!(server2.recovery_states()["apples"], RecoveryState::Ready);
+        server2
+            .write_lines("apples", &parsed_lines("cpu foo=1 10"))
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn duplicate_database_name_rejected() -> Result {
         // Covers #643
@@ -705,78 +2638,418 @@ mod tests {
             .await
             .unwrap_err();
 
-        if !matches!(got, Error::DatabaseAlreadyExists {..}) {
-            panic!("expected already exists error");
-        }
+        if !matches!(got, Error::DatabaseAlreadyExists {..}) {
+            panic!("expected already exists error");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_name_validation() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let reject = vec![
+            "bananas\t",
+            "bananas\"are\u{0099}\"great",
+            "bananas\nfoster",
+        ];
+
+        for name in reject {
+            let rules = DatabaseRules {
+                store_locally: true,
+                ..Default::default()
+            };
+            let got = server.create_database(name, rules).await.unwrap_err();
+            if !matches!(got, Error::InvalidDatabaseName { .. }) {
+                panic!("expected invalid name error");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_local() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let line = "cpu bar=1 10";
+        let lines: Vec<_> = parse_lines(line).map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await.unwrap();
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+
+        let buff = db.mutable_buffer.as_ref().unwrap();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+        let physical_plan = planner
+            .query(buff, "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn table_write_rules() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let rules = DatabaseRules {
+            store_locally: true,
+            table_write_rules: TableWriteRules {
+                allow: vec![MatchTables::Regex("team_a_.*".to_string())],
+                deny: vec![MatchTables::Table("team_a_private".to_string())],
+            },
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        // a single rejected line rejects the whole write
+        let lines = parsed_lines("team_a_cpu bar=1 10\nteam_a_private bar=1 10\ncpu bar=1 10");
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        match err {
+            Error::TableWriteRejected { lines, .. } => assert_eq!(
+                lines,
+                vec![
+                    RejectedLine {
+                        line: 2,
+                        table: "team_a_private".to_string(),
+                        reason: TableWriteRejection::Denied,
+                    },
+                    RejectedLine {
+                        line: 3,
+                        table: "cpu".to_string(),
+                        reason: TableWriteRejection::NotAllowed,
+                    },
+                ]
+            ),
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let db = server.db(&DatabaseName::new("foo")?).await.unwrap();
+        assert!(db.mutable_buffer.as_ref().unwrap().is_empty().await);
+
+        server
+            .write_lines("foo", &parsed_lines("team_a_cpu bar=1 10"))
+            .await?;
+        assert!(!db.mutable_buffer.as_ref().unwrap().is_empty().await);
+
+        // invalid rules are refused up front
+        let rules = DatabaseRules {
+            table_write_rules: TableWriteRules {
+                deny: vec![MatchTables::Regex("(".to_string())],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let err = server.create_database("bar", rules).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidTableWriteRules { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_transforms() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let mut write_transforms = WriteTransforms::default();
+        write_transforms
+            .inject_tags
+            .insert("source".to_string(), "router-1".to_string());
+        write_transforms
+            .rename_tags
+            .insert("hostname".to_string(), "host".to_string());
+        write_transforms.drop_fields.insert("debug".to_string());
+        let rules = DatabaseRules {
+            store_locally: true,
+            write_transforms,
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let lines = parsed_lines("cpu,hostname=a bar=1,debug=true 10\ncpu,host=b bar=2 20");
+        server.write_lines("foo", &lines).await?;
+
+        let db = server.db(&DatabaseName::new("foo")?).await.unwrap();
+        let buff = db.mutable_buffer.as_ref().unwrap();
+        let planner = SQLQueryPlanner::default();
+        let physical_plan = planner
+            .query(buff, "select * from cpu", server.executor().as_ref())
+            .await?;
+
+        let batches = collect(physical_plan).await?;
+        let expected = vec![
+            "+-----+------+----------+------+",
+            "| bar | host | source   | time |",
+            "+-----+------+----------+------+",
+            "| 1   | a    | router-1 | 10   |",
+            "| 2   | b    | router-1 | 20   |",
+            "+-----+------+----------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn non_finite_floats() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        for (name, non_finite_floats) in &[
+            ("rejecting", NonFiniteFloats::Reject),
+            ("nulling", NonFiniteFloats::Null),
+        ] {
+            let rules = DatabaseRules {
+                store_locally: true,
+                non_finite_floats: *non_finite_floats,
+                ..Default::default()
+            };
+            server.create_database(*name, rules).await?;
+        }
+
+        // line protocol has no literals for NaN or infinity, so they are set
+        // on the parsed lines
+        let mut lines = parsed_lines("cpu bar=1,baz=1 10\ncpu bar=2 20\ncpu baz=3 30");
+        lines[0].field_set[1].1 = FieldValue::F64(f64::NAN);
+        lines[2].field_set[0].1 = FieldValue::F64(f64::INFINITY);
+
+        let err = server.write_lines("rejecting", &lines).await.unwrap_err();
+        match err {
+            Error::NonFiniteFloatsRejected { lines, .. } => assert_eq!(
+                lines,
+                vec![
+                    NonFiniteLine {
+                        line: 1,
+                        fields: vec!["baz".to_string()],
+                    },
+                    NonFiniteLine {
+                        line: 3,
+                        fields: vec!["baz".to_string()],
+                    },
+                ]
+            ),
+            e => panic!("unexpected error: {}", e),
+        }
+        let db = server.db(&DatabaseName::new("rejecting")?).await.unwrap();
+        assert!(db.mutable_buffer.as_ref().unwrap().is_empty().await);
+
+        server.write_lines("nulling", &lines).await?;
+        let db = server.db(&DatabaseName::new("nulling")?).await.unwrap();
+        let buff = db.mutable_buffer.as_ref().unwrap();
+        let planner = SQLQueryPlanner::default();
+        let physical_plan = planner
+            .query(buff, "select * from cpu", server.executor().as_ref())
+            .await?;
+
+        let batches = collect(physical_plan).await?;
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "| 2   | 20   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn database_name_validation() -> Result {
-        let manager = TestConnectionManager::new();
+    async fn table_batches_are_written_through_the_write_path() -> Result {
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
-        let server = Server::new(manager, store);
+        let server = Server::new(TestConnectionManager::new(), store.clone());
         server.set_id(1);
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 500_000,
+                segment_size: 100_000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+                segment_storage: WalSegmentStorage::ObjectStore,
+                skip_invalid_writes: false,
+            }),
+            store_locally: true,
+            table_write_rules: TableWriteRules {
+                deny: vec![MatchTables::Table("private".to_string())],
+                ..Default::default()
+            },
+            non_finite_floats: NonFiniteFloats::Reject,
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
 
-        let reject = vec![
-            "bananas\t",
-            "bananas\"are\u{0099}\"great",
-            "bananas\nfoster",
-        ];
+        let schema = Arc::new(ArrowSchema::new(vec![
+            Field::new("host", DataType::Utf8, false),
+            Field::new("usage", DataType::Float64, false),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let batch = |usage: Vec<f64>| {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from(vec!["a", "b"])),
+                Arc::new(Float64Array::from(usage)),
+                Arc::new(Int64Array::from(vec![10, 20])),
+            ];
+            RecordBatch::try_new(Arc::clone(&schema), columns).unwrap()
+        };
 
-        for name in reject {
-            let rules = DatabaseRules {
-                store_locally: true,
-                ..Default::default()
-            };
-            let got = server.create_database(name, rules).await.unwrap_err();
-            if !matches!(got, Error::InvalidDatabaseName { .. }) {
-                panic!("expected invalid name error");
-            }
+        let err = server
+            .write_table_batch(
+                "foo",
+                "private",
+                &batch(vec![1.0, 2.0]),
+                WriteAckLevel::Buffered,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TableWriteRejected { .. }));
+
+        let err = server
+            .write_table_batch(
+                "foo",
+                "cpu",
+                &batch(vec![1.0, f64::NAN]),
+                WriteAckLevel::Buffered,
+            )
+            .await
+            .unwrap_err();
+        match err {
+            Error::NonFiniteFloatsRejected { lines, .. } => assert_eq!(
+                lines,
+                vec![NonFiniteLine {
+                    line: 2,
+                    fields: vec!["usage".to_string()],
+                }]
+            ),
+            e => panic!("unexpected error: {}", e),
         }
+        let db = server.db(&DatabaseName::new("foo")?).await.unwrap();
+        assert!(db.mutable_buffer.as_ref().unwrap().is_empty().await);
+
+        // the write is appended to the WAL like the writes of lines
+        let ack = server
+            .write_table_batch("foo", "cpu", &batch(vec![1.0, 2.0]), WriteAckLevel::Wal)
+            .await?;
+        assert!(ack.buffered);
+        assert!(ack.wal);
+        let path = ObjectStorePath::from_cloud_unchecked("1/foo/wal/000/000/001.segment");
+        let data = store
+            .get(&path)
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        let segment = Segment::from_file_bytes(&data)?;
+        assert_eq!(segment.writes.len(), 1);
+
+        let buff = db.mutable_buffer.as_ref().unwrap();
+        let physical_plan = SQLQueryPlanner::default()
+            .query(
+                buff,
+                "select host, usage, time from cpu",
+                server.executor().as_ref(),
+            )
+            .await?;
+        let batches = collect(physical_plan).await?;
+        let expected = vec![
+            "+------+-------+------+",
+            "| host | usage | time |",
+            "+------+-------+------+",
+            "| a    | 1     | 10   |",
+            "| b    | 2     | 20   |",
+            "+------+-------+------+",
+        ];
+        assert_table_eq!(expected, &batches);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn writes_local() -> Result {
+    async fn ingest_time_is_recorded() -> Result {
         let manager = TestConnectionManager::new();
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
         let server = Server::new(manager, store);
         server.set_id(1);
         let rules = DatabaseRules {
             store_locally: true,
+            record_ingest_time: true,
             ..Default::default()
         };
         server.create_database("foo", rules).await?;
 
-        let line = "cpu bar=1 10";
-        let lines: Vec<_> = parse_lines(line).map(|l| l.unwrap()).collect();
-        server.write_lines("foo", &lines).await.unwrap();
-
-        let db_name = DatabaseName::new("foo").unwrap();
-        let db = server.db(&db_name).await.unwrap();
+        let before = Utc::now().timestamp_nanos();
+        // an ingest time written by the client is replaced
+        let lines = parsed_lines("cpu bar=1 10\ncpu bar=2,_ingest_time=5i 10");
+        server.write_lines("foo", &lines).await?;
+        let after = Utc::now().timestamp_nanos();
 
+        let db = server.db(&DatabaseName::new("foo")?).await.unwrap();
         let buff = db.mutable_buffer.as_ref().unwrap();
-
         let planner = SQLQueryPlanner::default();
-        let executor = server.executor();
+        let query = format!(
+            "select count(*) as rows from cpu where _ingest_time >= {} and _ingest_time <= {}",
+            before, after
+        );
         let physical_plan = planner
-            .query(buff, "select * from cpu", executor.as_ref())
-            .await
-            .unwrap();
+            .query(buff, &query, server.executor().as_ref())
+            .await?;
 
-        let batches = collect(physical_plan).await.unwrap();
-        let expected = vec![
-            "+-----+------+",
-            "| bar | time |",
-            "+-----+------+",
-            "| 1   | 10   |",
-            "+-----+------+",
-        ];
+        let batches = collect(physical_plan).await?;
+        let expected = vec!["+------+", "| rows |", "+------+", "| 2    |", "+------+"];
         assert_table_eq!(expected, &batches);
 
+        // the ingest time is only returned when asked for by name
+        let physical_plan = planner
+            .query(buff, "select * from cpu", server.executor().as_ref())
+            .await?;
+        let batches = collect(physical_plan).await?;
+        assert!(batches[0]
+            .schema()
+            .field_with_name(INGEST_TIME_COLUMN_NAME)
+            .is_err());
+        let physical_plan = planner
+            .query(
+                buff,
+                "select bar, _ingest_time from cpu",
+                server.executor().as_ref(),
+            )
+            .await?;
+        let batches = collect(physical_plan).await?;
+        assert_eq!(batches[0].num_columns(), 2);
+
         Ok(())
     }
 
@@ -839,6 +3112,202 @@ partition_key:
         Ok(())
     }
 
+    #[tokio::test]
+    async fn acknowledges_writes_at_requested_level() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        let remote_id = "serverA";
+        manager
+            .remotes
+            .insert(remote_id.to_string(), remote.clone());
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let host_group_id = "az1".to_string();
+        let rules = DatabaseRules {
+            store_locally: true,
+            replication: vec![host_group_id.clone()],
+            replication_count: 1,
+            ..Default::default()
+        };
+        server
+            .create_host_group(host_group_id.clone(), vec![remote_id.to_string()])
+            .await
+            .unwrap();
+        server.create_database("foo", rules).await.unwrap();
+
+        let lines = parsed_lines("cpu bar=1 10");
+        let ack = server
+            .write_lines_with_ack("foo", &lines, WriteAckLevel::Replicated)
+            .await?;
+        assert_eq!(
+            ack,
+            WriteAck {
+                buffered: true,
+                wal: false,
+                replicated: 1,
+                replicating: 0,
+            }
+        );
+
+        let ack = server
+            .write_lines_with_ack("foo", &lines, WriteAckLevel::Buffered)
+            .await?;
+        assert_eq!(
+            ack,
+            WriteAck {
+                buffered: true,
+                wal: false,
+                replicated: 0,
+                replicating: 1,
+            }
+        );
+
+        // the second write is replicated in the background
+        while remote.writes.lock().unwrap().get("foo").unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // the database has no WAL buffer to acknowledge writes from
+        let err = server
+            .write_lines_with_ack("foo", &lines, WriteAckLevel::Wal)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NoWalBuffer { .. }));
+        assert_eq!(remote.writes.lock().unwrap().get("foo").unwrap().len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wal_acks_wait_for_the_segment_to_persist() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 500_000,
+                segment_size: 100_000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+                segment_storage: WalSegmentStorage::ObjectStore,
+                skip_invalid_writes: false,
+            }),
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        // the segment isn't full, so it's left open
+        let lines = parsed_lines("cpu bar=1 10");
+        let ack = server
+            .write_lines_with_ack("foo", &lines, WriteAckLevel::Buffered)
+            .await?;
+        assert!(ack.buffered);
+        assert!(!ack.wal);
+
+        // the segment holding the write is closed and persisted before the
+        // write is acknowledged
+        let ack = server
+            .write_lines_with_ack("foo", &lines, WriteAckLevel::Wal)
+            .await?;
+        assert!(ack.wal);
+        let path = ObjectStorePath::from_cloud_unchecked("1/foo/wal/000/000/001.segment");
+        let data = store
+            .get(&path)
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        let segment = Segment::from_file_bytes(&data)?;
+        assert_eq!(segment.writes.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn enqueued_writes_are_stored_in_order_and_acknowledged() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(TestConnectionManager::new(), store.clone());
+        server.set_id(1);
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 500_000,
+                segment_size: 100_000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+                segment_storage: WalSegmentStorage::ObjectStore,
+                skip_invalid_writes: false,
+            }),
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+        server
+            .create_database("bar", DatabaseRules::default())
+            .await?;
+        let server = Arc::new(server);
+
+        let mut handles = Vec::new();
+        for i in 0..10 {
+            let lp = format!("cpu bar={} {}", i, i);
+            let lines = parsed_lines(&lp);
+            handles.push(
+                server
+                    .write_lines_async("foo", &lines, WriteAckLevel::Buffered)
+                    .await?,
+            );
+        }
+        let lines = parsed_lines("cpu bar=10 10");
+        let wal_handle = server
+            .write_lines_async("foo", &lines, WriteAckLevel::Wal)
+            .await?;
+
+        // the handles resolve independently of each other
+        for handle in handles.into_iter().rev() {
+            let ack = handle.await?;
+            assert!(ack.buffered);
+            assert!(!ack.wal);
+        }
+
+        // the write acknowledged at the wal level waits for its segment,
+        // holding every write before it, to be persisted
+        let ack = wal_handle.await?;
+        assert!(ack.buffered && ack.wal);
+        let path = ObjectStorePath::from_cloud_unchecked("1/foo/wal/000/000/001.segment");
+        let data = store
+            .get(&path)
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        let segment = Segment::from_file_bytes(&data)?;
+        let sequences: Vec<_> = segment
+            .writes
+            .iter()
+            .map(|w| w.writer_and_sequence().1)
+            .collect();
+        let mut sorted = sequences.clone();
+        sorted.sort_unstable();
+        assert_eq!(sequences.len(), 11);
+        assert_eq!(sequences, sorted);
+
+        // a write that can't be acknowledged at the requested level fails
+        // once it is dequeued
+        let err = server
+            .write_lines_async("bar", &lines, WriteAckLevel::Wal)
+            .await?
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NoWalBuffer { .. }));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn sends_all_to_subscriber() -> Result {
         let mut manager = TestConnectionManager::new();
@@ -919,6 +3388,8 @@ partition_key:
                 buffer_rollover: WalBufferRollover::ReturnError,
                 store_segments: true,
                 close_segment_after: None,
+                segment_storage: WalSegmentStorage::ObjectStore,
+                skip_invalid_writes: false,
             }),
             ..Default::default()
         };
@@ -951,6 +3422,202 @@ partition_key:
         assert_eq!(segment.writes[0].to_string(), write);
     }
 
+    #[tokio::test]
+    async fn wals_of_other_servers_are_fanned_in() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let db_name = "my_db";
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 500,
+                segment_size: 10,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+                segment_storage: WalSegmentStorage::ObjectStore,
+                skip_invalid_writes: false,
+            }),
+            store_locally: true,
+            ..Default::default()
+        };
+
+        let ingester = Server::new(TestConnectionManager::new(), store.clone());
+        ingester.set_id(2);
+        ingester.create_database(db_name, rules.clone()).await?;
+        let lines = parsed_lines("disk,host=a used=10.1 12");
+        ingester.write_lines(db_name, &lines).await?;
+        // the write closed a segment, which is persisted in the background
+        tokio::task::yield_now().await;
+
+        let server = Server::new(TestConnectionManager::new(), store);
+        server.set_id(1);
+        server.create_database(db_name, rules).await?;
+        let summary = server.fan_in_wals(db_name, &[2]).await?;
+        assert_eq!(summary.wals, 1);
+        assert_eq!(summary.writes, 1);
+
+        let db = server
+            .db(&DatabaseName::new(db_name).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(db.partition_keys().await.unwrap().len(), 1);
+        // the writes of the ingester don't take the sequence numbers of
+        // this server
+        assert_eq!(db.next_sequence(), 1);
+
+        // fanning in again doesn't store the writes twice
+        let summary = server.fan_in_wals(db_name, &[2]).await?;
+        assert_eq!(summary.writes, 0);
+        assert_eq!(summary.duplicate_writes, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn segments_are_written_to_registered_backend() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let dir = test_helpers::tmp_dir()?;
+
+        let server = Server::new(manager, store.clone())
+            .with_wal_backend("local", Arc::new(FileSegments::new(dir.path())));
+        server.set_id(1);
+        let wal_buffer_config = |name: &str| WalBufferConfig {
+            buffer_size: 500,
+            segment_size: 10,
+            buffer_rollover: WalBufferRollover::ReturnError,
+            store_segments: true,
+            close_segment_after: None,
+            segment_storage: WalSegmentStorage::Backend {
+                name: name.to_string(),
+            },
+            skip_invalid_writes: false,
+        };
+
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(wal_buffer_config("s3")),
+            ..Default::default()
+        };
+        let err = server.create_database("my_db", rules).await.unwrap_err();
+        assert!(matches!(err, Error::UnknownWalBackend { name } if name == "s3"));
+
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(wal_buffer_config("local")),
+            ..Default::default()
+        };
+        server.create_database("my_db", rules).await?;
+
+        let lines = parsed_lines("disk,host=a used=10.1 12");
+        server.write_lines("my_db", &lines).await?;
+
+        // wait for the segment to be persisted in the background
+        let path = dir.path().join("1/my_db/wal/000/000/001.segment");
+        let mut tries = 0;
+        let segment = loop {
+            let segment = std::fs::read(&path)
+                .ok()
+                .and_then(|data| Segment::from_file_bytes(&data).ok());
+            match segment {
+                Some(segment) => break segment,
+                None if tries < 100 => {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(10)).await;
+                    tries += 1;
+                }
+                None => panic!("segment wasn't persisted to {:?}", path),
+            }
+        };
+        assert_eq!(segment.writes.len(), 1);
+
+        // nothing was written to the object store of the server
+        let segment_path = ObjectStorePath::from_cloud_unchecked("1/my_db/wal/000/000/001.segment");
+        assert!(store.get(&segment_path).await.is_err());
+
+        // a server without the backend can't recover the database
+        let server2 = Server::new(TestConnectionManager::new(), store.clone());
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert!(matches!(
+            &server2.recovery_states()["my_db"],
+            RecoveryState::Error { message } if message.contains("unknown backend local")
+        ));
+        let err = server2.write_lines("my_db", &lines).await.unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotReady { .. }));
+
+        let server3 = Server::new(TestConnectionManager::new(), store)
+            .with_wal_backend("local", Arc::new(FileSegments::new(dir.path())));
+        server3.set_id(1);
+        server3.load_database_configs().await?;
+        assert_eq!(server3.recovery_states()["my_db"], RecoveryState::Ready);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_are_rejected_when_wal_directory_is_full() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let dir = test_helpers::tmp_dir()?;
+        let wal_dir = dir.path().join("wal").to_string_lossy().into_owned();
+
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let wal_buffer_config = |min_free_bytes| WalBufferConfig {
+            buffer_size: 500,
+            segment_size: 10,
+            buffer_rollover: WalBufferRollover::ReturnError,
+            store_segments: true,
+            close_segment_after: None,
+            segment_storage: WalSegmentStorage::Directory {
+                path: wal_dir.clone(),
+                min_free_bytes,
+            },
+            skip_invalid_writes: false,
+        };
+
+        // no file system has this much space free
+        let rules = DatabaseRules {
+            store_locally: true,
+            wal_buffer_config: Some(wal_buffer_config(u64::MAX)),
+            ..Default::default()
+        };
+        server.create_database("full", rules).await?;
+
+        let lines = parsed_lines("disk,host=a used=10.1 12");
+        let err = server.write_lines("full", &lines).await.unwrap_err();
+        assert!(
+            matches!(err, Error::WalDiskFull { min_free_bytes, .. } if min_free_bytes == u64::MAX)
+        );
+
+        let db = server.db(&DatabaseName::new("full")?).await.unwrap();
+        assert!(db.mutable_buffer.as_ref().unwrap().is_empty().await);
+        let metrics = db.wal_metrics();
+        assert_eq!(metrics.disk_full_rejections.load(Ordering::Relaxed), 1);
+        assert!(metrics.available_bytes.load(Ordering::Relaxed) > 0);
+        let rendered = server.render_wal_metrics();
+        assert!(
+            rendered.contains("iox_wal_disk_full_rejections_total{db_name=\"full\"} 1"),
+            "{}",
+            rendered
+        );
+
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(wal_buffer_config(0)),
+            ..Default::default()
+        };
+        server.create_database("my_db", rules).await?;
+        server.write_lines("my_db", &lines).await?;
+
+        // wait for the segment to be persisted in the background
+        let path = std::path::Path::new(&wal_dir).join("1/my_db/wal/000/000/001.segment");
+        let mut tries = 0;
+        while !path.exists() {
+            assert!(tries < 100, "segment wasn't persisted to {:?}", path);
+            tokio::time::delay_for(tokio::time::Duration::from_millis(10)).await;
+            tries += 1;
+        }
+
+        Ok(())
+    }
+
     #[derive(Snafu, Debug, Clone)]
     enum TestClusterError {
         #[snafu(display("Test cluster error:  {}", message))]
@@ -0,0 +1,155 @@
+//! This module contains the mapping of InfluxDB 2.x orgs and buckets to
+//! the databases that store their data.
+//!
+//! Each (org, bucket) pair registered with the server is assigned an id,
+//! and its data is kept in a database named after that id rather than
+//! after the org and bucket, so the names tenants pick can't collide with
+//! each other or with databases created directly. The assignments are
+//! stored next to the database configurations in object storage.
+use data_types::DatabaseName;
+use object_store::path::ObjectStorePath;
+use serde::{Deserialize, Serialize};
+
+use std::{collections::BTreeMap, sync::RwLock};
+
+pub(crate) const NAMESPACES_FILE_NAME: &str = "namespaces.json";
+
+/// An org and bucket, and the id of the database storing their data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Namespace {
+    pub org: String,
+    pub bucket: String,
+    pub id: u64,
+    /// The database of an org and bucket that had data before they were
+    /// registered, which keeps storing their data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+}
+
+impl Namespace {
+    /// The name of the database of the namespace. `-` is percent encoded
+    /// in the names `org_and_bucket_to_database` derives from orgs and
+    /// buckets, so those never take the `ns-<id>` form.
+    pub(crate) fn db_name(&self) -> DatabaseName<'static> {
+        let name = match &self.database {
+            Some(database) => database.clone(),
+            None => format!("ns-{:016x}", self.id),
+        };
+        DatabaseName::new(name).expect("namespace names are valid")
+    }
+}
+
+/// The namespaces registered with the server, by org and bucket
+#[derive(Debug, Default)]
+pub(crate) struct Namespaces {
+    state: RwLock<BTreeMap<(String, String), Namespace>>,
+}
+
+impl Namespaces {
+    pub(crate) fn get(&self, org: &str, bucket: &str) -> Option<Namespace> {
+        let state = self.state.read().expect("mutex poisoned");
+        state.get(&(org.to_string(), bucket.to_string())).cloned()
+    }
+
+    /// Returns a namespace for `org` and `bucket` with the next unused
+    /// id. It is not registered until it is passed to `insert`.
+    pub(crate) fn next(&self, org: &str, bucket: &str) -> Namespace {
+        let state = self.state.read().expect("mutex poisoned");
+        let next_id = state.values().map(|namespace| namespace.id + 1).max();
+
+        Namespace {
+            org: org.to_string(),
+            bucket: bucket.to_string(),
+            id: next_id.unwrap_or(1),
+            database: None,
+        }
+    }
+
+    /// Registers `namespace`, replacing any namespace of its org and
+    /// bucket
+    pub(crate) fn insert(&self, namespace: Namespace) {
+        let mut state = self.state.write().expect("mutex poisoned");
+        state.insert((namespace.org.clone(), namespace.bucket.clone()), namespace);
+    }
+
+    /// Returns all the registered namespaces
+    pub(crate) fn all(&self) -> Vec<Namespace> {
+        let state = self.state.read().expect("mutex poisoned");
+        state.values().cloned().collect()
+    }
+
+    /// Registers namespaces loaded from object storage. Namespaces that
+    /// are already registered are kept.
+    pub(crate) fn load(&self, namespaces: Vec<Namespace>) {
+        let mut state = self.state.write().expect("mutex poisoned");
+        for namespace in namespaces {
+            state
+                .entry((namespace.org.clone(), namespace.bucket.clone()))
+                .or_insert(namespace);
+        }
+    }
+}
+
+pub(crate) fn object_store_path_for_namespaces(root: &ObjectStorePath) -> ObjectStorePath {
+    let mut path = root.clone();
+    path.set_file_name(NAMESPACES_FILE_NAME);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::path::cloud::CloudConverter;
+
+    #[test]
+    fn ids_are_assigned_once_registered() {
+        let namespaces = Namespaces::default();
+        assert!(namespaces.get("org", "bucket").is_none());
+
+        let a = namespaces.next("org", "bucket");
+        assert_eq!(a.id, 1);
+        assert_eq!(a.db_name().as_str(), "ns-0000000000000001");
+        // nothing is registered until the namespace is inserted
+        assert_eq!(namespaces.next("org", "other_bucket").id, 1);
+        namespaces.insert(a.clone());
+        assert_eq!(namespaces.all(), vec![a.clone()]);
+
+        let b = namespaces.next("org", "other_bucket");
+        assert_eq!(b.id, 2);
+        namespaces.insert(b.clone());
+        assert_eq!(namespaces.all().len(), 2);
+
+        assert_eq!(namespaces.get("org", "bucket"), Some(a));
+        assert_eq!(namespaces.get("org", "other_bucket"), Some(b));
+    }
+
+    #[test]
+    fn loaded_namespaces_keep_their_ids_and_databases() {
+        let namespaces = Namespaces::default();
+        let loaded: Vec<Namespace> = serde_json::from_str(
+            r#"[
+                {"org": "org", "bucket": "bucket", "id": 7},
+                {"org": "org", "bucket": "legacy", "id": 8, "database": "org_legacy"}
+            ]"#,
+        )
+        .unwrap();
+        namespaces.load(loaded);
+
+        assert_eq!(
+            namespaces.get("org", "bucket").unwrap().db_name().as_str(),
+            "ns-0000000000000007"
+        );
+        assert_eq!(
+            namespaces.get("org", "legacy").unwrap().db_name().as_str(),
+            "org_legacy"
+        );
+        assert_eq!(namespaces.next("org", "new").id, 9);
+    }
+
+    #[test]
+    fn namespaces_path() {
+        let root = ObjectStorePath::from_cloud_unchecked("1");
+        let path = object_store_path_for_namespaces(&root);
+        assert_eq!(CloudConverter::convert(&path), "1/namespaces.json");
+    }
+}
@@ -0,0 +1,751 @@
+//! This module contains the enforcement of the quotas configured for a
+//! database (`DatabaseQuotas`), which keep one database from starving the
+//! others on a shared server.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt::{self, Write},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use data_types::{database_rules::DatabaseQuotas, DatabaseName};
+use influxdb_line_protocol::ParsedLine;
+use query::concurrency::{QueryConcurrency, QueryGuard};
+use snafu::{ensure, OptionExt, Snafu};
+
+use crate::{db::Db, latency::escape_label_value};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "writing {} lines exceeds the quota of {} lines per second",
+        lines,
+        max
+    ))]
+    LineRateExceeded { lines: u64, max: u64 },
+
+    #[snafu(display(
+        "writing {} bytes exceeds the quota of {} bytes per second",
+        bytes,
+        max
+    ))]
+    ByteRateExceeded { bytes: u64, max: u64 },
+
+    #[snafu(display(
+        "writing {} new series exceeds the quota of {} series",
+        new_series,
+        max
+    ))]
+    SeriesExceeded { new_series: usize, max: u64 },
+
+    #[snafu(display(
+        "writing {} new partitions exceeds the quota of {} partitions",
+        new_partitions,
+        max
+    ))]
+    PartitionsExceeded { new_partitions: usize, max: u64 },
+
+    #[snafu(display(
+        "the mutable buffer uses {} bytes, exceeding the quota of {} bytes",
+        size,
+        max
+    ))]
+    MemoryExceeded { size: usize, max: u64 },
+
+    #[snafu(display("already running the quota of {} concurrent queries", max))]
+    ConcurrentQueriesExceeded { max: usize },
+
+    #[snafu(display("unable to compute the partition key of a line: {}", source))]
+    PartitionKey {
+        source: data_types::database_rules::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The period over which the write rates are measured
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The number of writes and queries each quota has rejected
+#[derive(Debug, Default)]
+pub struct QuotaMetrics {
+    pub line_rate_rejections: AtomicU64,
+    pub byte_rate_rejections: AtomicU64,
+    pub series_rejections: AtomicU64,
+    pub partition_rejections: AtomicU64,
+    pub memory_rejections: AtomicU64,
+    pub query_rejections: AtomicU64,
+}
+
+impl QuotaMetrics {
+    /// The number of rejections of each quota, labelled by the quota
+    pub fn rejections(&self) -> [(&'static str, u64); 6] {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        [
+            ("line_rate", load(&self.line_rate_rejections)),
+            ("byte_rate", load(&self.byte_rate_rejections)),
+            ("series", load(&self.series_rejections)),
+            ("partitions", load(&self.partition_rejections)),
+            ("memory", load(&self.memory_rejections)),
+            ("concurrent_queries", load(&self.query_rejections)),
+        ]
+    }
+
+    fn record(&self, err: &Error) {
+        let counter = match err {
+            Error::LineRateExceeded { .. } => &self.line_rate_rejections,
+            Error::ByteRateExceeded { .. } => &self.byte_rate_rejections,
+            Error::SeriesExceeded { .. } => &self.series_rejections,
+            Error::PartitionsExceeded { .. } => &self.partition_rejections,
+            Error::MemoryExceeded { .. } => &self.memory_rejections,
+            Error::ConcurrentQueriesExceeded { .. } => &self.query_rejections,
+            // not a rejection by a quota, the write is invalid
+            Error::PartitionKey { .. } => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Tracks the use of the resources limited by the quotas of a database
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    window: Mutex<RateWindow>,
+    /// Hashes of the series written, only tracked if the number of
+    /// series is limited
+    series: Mutex<HashMap<u64, KeyUse>>,
+    /// The partition keys written, only tracked if the number of
+    /// partitions is limited
+    partitions: Mutex<HashMap<String, KeyUse>>,
+    queries: QueryConcurrency,
+    metrics: QuotaMetrics,
+}
+
+/// The lines and bytes written in the current rate window
+#[derive(Debug, Default)]
+struct RateWindow {
+    start: Option<Instant>,
+    lines: u64,
+    bytes: u64,
+}
+
+/// How a series or partition counted against a quota is used: it stays
+/// counted once a committed write has used it, or while writes that
+/// aren't committed yet use it
+#[derive(Debug, Default, Clone, Copy)]
+struct KeyUse {
+    pending: usize,
+    committed: bool,
+}
+
+/// Charges a write for the series or partitions `keys`, counting those
+/// that weren't counted yet
+fn charge_keys<K: Hash + Eq + Clone>(counted: &mut HashMap<K, KeyUse>, keys: &[K]) {
+    for key in keys {
+        counted.entry(key.clone()).or_default().pending += 1;
+    }
+}
+
+/// Ends the charge of a write for `keys`. If the write wasn't committed,
+/// the keys that no other write uses aren't counted anymore.
+fn settle_keys<K: Hash + Eq>(counted: &mut HashMap<K, KeyUse>, keys: &[K], committed: bool) {
+    for key in keys {
+        let unused = match counted.get_mut(key) {
+            Some(key_use) => {
+                key_use.pending = key_use.pending.saturating_sub(1);
+                key_use.committed |= committed;
+                key_use.pending == 0 && !key_use.committed
+            }
+            None => false,
+        };
+        if unused {
+            counted.remove(key);
+        }
+    }
+}
+
+/// A write accepted by the quotas. The write is accounted for while the
+/// charge is alive, so concurrent writes can't exceed the quotas
+/// together, but it is refunded when the charge is dropped unless it was
+/// committed: writes that fail after passing the quotas don't use them
+/// up. Series and partitions that other writes use stay counted.
+#[derive(Debug)]
+#[must_use = "the write is refunded unless the charge is committed"]
+pub struct WriteCharge<'a> {
+    tracker: &'a QuotaTracker,
+    window_start: Option<Instant>,
+    lines: u64,
+    bytes: u64,
+    /// The series and partitions of the write, whether they were new or
+    /// not
+    series: Vec<u64>,
+    partitions: Vec<String>,
+    committed: bool,
+}
+
+impl WriteCharge<'_> {
+    /// Keeps the write accounted for, once it has succeeded
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for WriteCharge<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let mut window = self.tracker.window.lock().expect("mutex poisoned");
+            if window.start == self.window_start {
+                window.lines = window.lines.saturating_sub(self.lines);
+                window.bytes = window.bytes.saturating_sub(self.bytes);
+            }
+        }
+
+        let mut series = self.tracker.series.lock().expect("mutex poisoned");
+        settle_keys(&mut series, &self.series, self.committed);
+        drop(series);
+
+        let mut partitions = self.tracker.partitions.lock().expect("mutex poisoned");
+        settle_keys(&mut partitions, &self.partitions, self.committed);
+    }
+}
+
+impl QuotaTracker {
+    /// Checks the write of `lines` against `quotas`, given the current
+    /// size of the mutable buffer and the partition keys of the lines
+    /// (which are only needed if the number of partitions is limited).
+    /// If it is accepted, the write is charged to the quotas until the
+    /// returned charge is dropped or committed.
+    pub fn check_write(
+        &self,
+        quotas: &DatabaseQuotas,
+        lines: &[ParsedLine<'_>],
+        partition_keys: &[String],
+        mutable_buffer_size: usize,
+    ) -> Result<WriteCharge<'_>> {
+        self.check_write_at(
+            quotas,
+            lines,
+            partition_keys,
+            mutable_buffer_size,
+            Instant::now(),
+        )
+    }
+
+    fn check_write_at(
+        &self,
+        quotas: &DatabaseQuotas,
+        lines: &[ParsedLine<'_>],
+        partition_keys: &[String],
+        mutable_buffer_size: usize,
+        now: Instant,
+    ) -> Result<WriteCharge<'_>> {
+        let result = self.try_write(quotas, lines, partition_keys, mutable_buffer_size, now);
+        if let Err(e) = &result {
+            self.metrics.record(e);
+        }
+        result
+    }
+
+    fn try_write(
+        &self,
+        quotas: &DatabaseQuotas,
+        lines: &[ParsedLine<'_>],
+        partition_keys: &[String],
+        mutable_buffer_size: usize,
+        now: Instant,
+    ) -> Result<WriteCharge<'_>> {
+        if let Some(max) = quotas.max_memory_bytes {
+            ensure!(
+                (mutable_buffer_size as u64) < max,
+                MemoryExceeded {
+                    size: mutable_buffer_size,
+                    max
+                }
+            );
+        }
+
+        let mut series = self.series.lock().expect("mutex poisoned");
+        let write_series = match quotas.max_series {
+            Some(max) => {
+                let write_series: HashSet<_> = lines.iter().map(series_hash).collect();
+                let new_series = write_series
+                    .iter()
+                    .filter(|hash| !series.contains_key(*hash))
+                    .count();
+                ensure!(
+                    (series.len() + new_series) as u64 <= max,
+                    SeriesExceeded { new_series, max }
+                );
+                write_series
+            }
+            None => HashSet::new(),
+        };
+
+        let mut partitions = self.partitions.lock().expect("mutex poisoned");
+        let write_partitions = match quotas.max_partitions {
+            Some(max) => {
+                let write_partitions: HashSet<_> = partition_keys.iter().collect();
+                let new_partitions = write_partitions
+                    .iter()
+                    .filter(|key| !partitions.contains_key(**key))
+                    .count();
+                ensure!(
+                    (partitions.len() + new_partitions) as u64 <= max,
+                    PartitionsExceeded {
+                        new_partitions,
+                        max
+                    }
+                );
+                write_partitions
+            }
+            None => HashSet::new(),
+        };
+
+        let lines_written = lines.len() as u64;
+        let bytes_written = match quotas.max_bytes_per_second {
+            Some(_) => lines.iter().map(line_bytes).sum(),
+            None => 0,
+        };
+
+        let mut window = self.window.lock().expect("mutex poisoned");
+        let expired = window.start.map_or(true, |start| {
+            now.saturating_duration_since(start) >= RATE_WINDOW
+        });
+        if expired {
+            *window = RateWindow {
+                start: Some(now),
+                lines: 0,
+                bytes: 0,
+            };
+        }
+        if let Some(max) = quotas.max_lines_per_second {
+            ensure!(
+                window.lines + lines_written <= max,
+                LineRateExceeded {
+                    lines: lines_written,
+                    max
+                }
+            );
+        }
+        if let Some(max) = quotas.max_bytes_per_second {
+            ensure!(
+                window.bytes + bytes_written <= max,
+                ByteRateExceeded {
+                    bytes: bytes_written,
+                    max
+                }
+            );
+        }
+
+        window.lines += lines_written;
+        window.bytes += bytes_written;
+        let write_series: Vec<_> = write_series.into_iter().collect();
+        charge_keys(&mut series, &write_series);
+        let write_partitions: Vec<_> = write_partitions.into_iter().cloned().collect();
+        charge_keys(&mut partitions, &write_partitions);
+
+        Ok(WriteCharge {
+            tracker: self,
+            window_start: window.start,
+            lines: lines_written,
+            bytes: bytes_written,
+            series: write_series,
+            partitions: write_partitions,
+            committed: false,
+        })
+    }
+
+    /// Returns the partition keys counted against the quota of partitions
+    pub fn partitions(&self) -> Vec<String> {
+        let partitions = self.partitions.lock().expect("mutex poisoned");
+        partitions.keys().cloned().collect()
+    }
+
+    /// Stops counting the partition `partition_key` against the quota of
+    /// partitions, once it holds no data
+    pub fn release_partition(&self, partition_key: &str) {
+        let mut partitions = self.partitions.lock().expect("mutex poisoned");
+        partitions.remove(partition_key);
+    }
+
+    /// Registers the start of a query, unless the quota of concurrent
+    /// queries is in use
+    pub fn start_query(&self, quotas: &DatabaseQuotas) -> Result<QueryGuard> {
+        let max = quotas.max_concurrent_queries;
+        let result = self
+            .queries
+            .try_start(max)
+            .context(ConcurrentQueriesExceeded {
+                max: max.unwrap_or_default(),
+            });
+        if let Err(e) = &result {
+            self.metrics.record(e);
+        }
+        result
+    }
+
+    /// The number of writes and queries rejected so far
+    pub fn metrics(&self) -> &QuotaMetrics {
+        &self.metrics
+    }
+}
+
+/// Counts the bytes written to it, to measure how long the line protocol
+/// of a line is without building it
+#[derive(Debug, Default)]
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len() as u64;
+        Ok(())
+    }
+}
+
+/// The length of the line protocol of `line` and its newline, in bytes
+fn line_bytes(line: &ParsedLine<'_>) -> u64 {
+    let mut counter = ByteCounter::default();
+    write!(counter, "{}", line).expect("counting bytes can not fail");
+    counter.0 + 1
+}
+
+/// Renders the number of writes and queries rejected by each quota of
+/// the databases `dbs`, in the Prometheus text format
+pub(crate) fn render(dbs: &[(DatabaseName<'static>, Arc<Db>)]) -> String {
+    let name = "iox_quota_rejections_total";
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# HELP {} Writes and queries rejected by the quotas of the database",
+        name
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+
+    for (db_name, db) in dbs {
+        for (quota, count) in &db.quota_metrics().rejections() {
+            writeln!(
+                out,
+                "{}{{db_name=\"{}\",quota=\"{}\"}} {}",
+                name,
+                escape_label_value(db_name),
+                quota,
+                count
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+/// Identifies the series of `line` by a hash of its table name and its
+/// tags, in tag key order
+fn series_hash(line: &ParsedLine<'_>) -> u64 {
+    let mut tags: Vec<_> = line
+        .series
+        .tag_set
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    tags.sort_unstable();
+
+    let mut hasher = DefaultHasher::new();
+    line.series.measurement.as_str().hash(&mut hasher);
+    tags.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::database_rules::DatabaseRules;
+    use influxdb_line_protocol::parse_lines;
+    use read_buffer::Database as ReadBufferDb;
+
+    fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
+        parse_lines(lp).map(|l| l.unwrap()).collect()
+    }
+
+    fn rejections(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn write_rates_are_limited_per_second() {
+        let tracker = QuotaTracker::default();
+        let quotas = DatabaseQuotas {
+            max_lines_per_second: Some(3),
+            max_bytes_per_second: Some(40),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let two_lines = parsed_lines("cpu a=1 1\ncpu a=2 2");
+
+        tracker
+            .check_write_at(&quotas, &two_lines, &[], 0, start)
+            .unwrap()
+            .commit();
+        let err = tracker
+            .check_write_at(&quotas, &two_lines, &[], 0, start)
+            .unwrap_err();
+        assert!(matches!(err, Error::LineRateExceeded { lines: 2, max: 3 }));
+
+        // the rejected write didn't count towards the rate
+        tracker
+            .check_write_at(&quotas, &two_lines[..1], &[], 0, start)
+            .unwrap()
+            .commit();
+
+        let later = start + RATE_WINDOW;
+        tracker
+            .check_write_at(&quotas, &two_lines, &[], 0, later)
+            .unwrap()
+            .commit();
+        let long_line = parsed_lines("cpu,host=a-very-long-host-name a=1 1");
+        let err = tracker
+            .check_write_at(&quotas, &long_line, &[], 0, later)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ByteRateExceeded { bytes: 37, max: 40 }
+        ));
+
+        assert_eq!(rejections(&tracker.metrics().line_rate_rejections), 1);
+        assert_eq!(rejections(&tracker.metrics().byte_rate_rejections), 1);
+    }
+
+    #[test]
+    fn series_are_limited() {
+        let tracker = QuotaTracker::default();
+        let quotas = DatabaseQuotas {
+            max_series: Some(2),
+            ..Default::default()
+        };
+
+        // tag order doesn't change the series
+        let lines = parsed_lines("cpu,host=a,region=west a=1 1\ncpu,region=west,host=a a=2 2");
+        tracker
+            .check_write(&quotas, &lines, &[], 0)
+            .unwrap()
+            .commit();
+
+        let lines = parsed_lines("cpu,host=b a=1 1\nmem,host=a a=1 1");
+        let err = tracker.check_write(&quotas, &lines, &[], 0).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::SeriesExceeded {
+                new_series: 2,
+                max: 2
+            }
+        ));
+
+        let lines = parsed_lines("cpu,host=b a=1 1\ncpu,host=a,region=west a=3 3");
+        tracker
+            .check_write(&quotas, &lines, &[], 0)
+            .unwrap()
+            .commit();
+        tracker
+            .check_write(&quotas, &lines, &[], 0)
+            .unwrap()
+            .commit();
+
+        assert_eq!(rejections(&tracker.metrics().series_rejections), 1);
+    }
+
+    #[test]
+    fn partitions_are_limited() {
+        let tracker = QuotaTracker::default();
+        let quotas = DatabaseQuotas {
+            max_partitions: Some(2),
+            ..Default::default()
+        };
+        let lines = parsed_lines("cpu a=1 1");
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+
+        tracker
+            .check_write(&quotas, &lines, &keys(&["west", "west"]), 0)
+            .unwrap()
+            .commit();
+        let err = tracker
+            .check_write(&quotas, &lines, &keys(&["east", "north"]), 0)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::PartitionsExceeded {
+                new_partitions: 2,
+                max: 2
+            }
+        ));
+        tracker
+            .check_write(&quotas, &lines, &keys(&["west", "east"]), 0)
+            .unwrap()
+            .commit();
+
+        assert_eq!(rejections(&tracker.metrics().partition_rejections), 1);
+    }
+
+    #[test]
+    fn failed_writes_are_refunded() {
+        let tracker = QuotaTracker::default();
+        let quotas = DatabaseQuotas {
+            max_lines_per_second: Some(2),
+            max_bytes_per_second: Some(100),
+            max_series: Some(1),
+            max_partitions: Some(1),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let lines = parsed_lines("cpu,host=a a=1 1\ncpu,host=a a=2 2");
+        let keys = vec!["west".to_string()];
+
+        // a write that fails after passing the quotas is refunded
+        let charge = tracker
+            .check_write_at(&quotas, &lines, &keys, 0, start)
+            .unwrap();
+        let err = tracker
+            .check_write_at(&quotas, &lines, &keys, 0, start)
+            .unwrap_err();
+        assert!(matches!(err, Error::LineRateExceeded { .. }));
+        drop(charge);
+
+        // so the same write fits once more
+        tracker
+            .check_write_at(&quotas, &lines, &keys, 0, start)
+            .unwrap()
+            .commit();
+
+        // but not after that one was committed
+        let other = parsed_lines("mem,host=b a=1 1");
+        let err = tracker
+            .check_write_at(&quotas, &other, &[], 0, start + RATE_WINDOW)
+            .unwrap_err();
+        assert!(matches!(err, Error::SeriesExceeded { .. }));
+        let err = tracker
+            .check_write_at(&quotas, &lines, &["east".to_string()], 0, start)
+            .unwrap_err();
+        assert!(matches!(err, Error::PartitionsExceeded { .. }));
+    }
+
+    #[test]
+    fn overlapping_writes_keep_shared_series() {
+        let tracker = QuotaTracker::default();
+        let quotas = DatabaseQuotas {
+            max_series: Some(1),
+            max_partitions: Some(1),
+            ..Default::default()
+        };
+        let lines = parsed_lines("cpu,host=a a=1 1");
+        let keys = vec!["west".to_string()];
+
+        // the first write adds the series and the partition, the second
+        // one uses them while the first isn't committed yet
+        let first = tracker.check_write(&quotas, &lines, &keys, 0).unwrap();
+        tracker
+            .check_write(&quotas, &lines, &keys, 0)
+            .unwrap()
+            .commit();
+
+        // refunding the first write keeps them counted for the second
+        drop(first);
+        let other = parsed_lines("mem,host=b a=1 1");
+        let err = tracker.check_write(&quotas, &other, &[], 0).unwrap_err();
+        assert!(matches!(err, Error::SeriesExceeded { .. }));
+        let err = tracker
+            .check_write(&quotas, &lines, &["east".to_string()], 0)
+            .unwrap_err();
+        assert!(matches!(err, Error::PartitionsExceeded { .. }));
+
+        // but those only used by writes that were refunded aren't
+        let tracker = QuotaTracker::default();
+        let first = tracker.check_write(&quotas, &lines, &keys, 0).unwrap();
+        let second = tracker.check_write(&quotas, &lines, &keys, 0).unwrap();
+        drop(first);
+        drop(second);
+        tracker
+            .check_write(&quotas, &other, &["east".to_string()], 0)
+            .unwrap()
+            .commit();
+    }
+
+    #[test]
+    fn line_bytes_match_line_protocol() {
+        let lp = r#"cpu,host=a\ b usage=0.5,status="ok",count=3i 10"#;
+        let lines = parsed_lines(lp);
+        assert_eq!(line_bytes(&lines[0]), lines[0].to_string().len() as u64 + 1);
+    }
+
+    #[test]
+    fn memory_is_limited() {
+        let tracker = QuotaTracker::default();
+        let quotas = DatabaseQuotas {
+            max_memory_bytes: Some(1000),
+            ..Default::default()
+        };
+        let lines = parsed_lines("cpu a=1 1");
+
+        tracker
+            .check_write(&quotas, &lines, &[], 999)
+            .unwrap()
+            .commit();
+        let err = tracker.check_write(&quotas, &lines, &[], 1000).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MemoryExceeded {
+                size: 1000,
+                max: 1000
+            }
+        ));
+        assert_eq!(rejections(&tracker.metrics().memory_rejections), 1);
+    }
+
+    #[tokio::test]
+    async fn renders_rejections() {
+        let rules = DatabaseRules {
+            quotas: DatabaseQuotas {
+                max_lines_per_second: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let db = Arc::new(Db::new(rules, None, ReadBufferDb::new(), None));
+        let lines = parsed_lines("cpu a=1 1");
+        db.check_write_quotas(&lines).await.unwrap_err();
+
+        let dbs = vec![(DatabaseName::new("quotas").unwrap(), db)];
+        let rendered = render(&dbs);
+        assert!(
+            rendered
+                .contains("iox_quota_rejections_total{db_name=\"quotas\",quota=\"line_rate\"} 1\n"),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered
+                .contains("iox_quota_rejections_total{db_name=\"quotas\",quota=\"memory\"} 0\n"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn concurrent_queries_are_limited() {
+        let tracker = QuotaTracker::default();
+        let quotas = DatabaseQuotas {
+            max_concurrent_queries: Some(1),
+            ..Default::default()
+        };
+
+        let query = tracker.start_query(&quotas).unwrap();
+        let err = tracker.start_query(&quotas).unwrap_err();
+        assert!(matches!(err, Error::ConcurrentQueriesExceeded { max: 1 }));
+        drop(query);
+        tracker.start_query(&quotas).unwrap();
+
+        assert_eq!(rejections(&tracker.metrics().query_rejections), 1);
+    }
+}
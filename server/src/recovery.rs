@@ -0,0 +1,66 @@
+//! This module contains the tracking of the recovery of the databases of
+//! a server from object storage at startup, so clients can tell which
+//! databases are being served while the others are still loading.
+
+use std::{collections::BTreeMap, sync::RwLock};
+
+use serde::Serialize;
+
+/// How many databases are recovered at the same time, unless configured
+/// with `Server::with_recovery_concurrency`
+pub const DEFAULT_RECOVERY_CONCURRENCY: usize = 10;
+
+/// The progress of the recovery of a database found in object storage
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RecoveryState {
+    /// Waiting for a recovery worker
+    Pending,
+    /// Its configuration is being loaded
+    Recovering,
+    /// The database is being served (or, if it was deleted, can be
+    /// restored)
+    Ready,
+    /// The recovery failed. Object store errors are retried, so the
+    /// database may still become ready.
+    Error { message: String },
+}
+
+impl std::fmt::Display for RecoveryState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::Recovering => write!(f, "recovering"),
+            Self::Ready => write!(f, "ready"),
+            Self::Error { message } => write!(f, "error: {}", message),
+        }
+    }
+}
+
+/// The recovery states of the databases found in object storage at
+/// startup, by the name of their directory
+#[derive(Debug, Default)]
+pub(crate) struct RecoveryTracker {
+    states: RwLock<BTreeMap<String, RecoveryState>>,
+}
+
+impl RecoveryTracker {
+    pub(crate) fn set(&self, name: &str, state: RecoveryState) {
+        let mut states = self.states.write().expect("mutex poisoned");
+        states.insert(name.to_string(), state);
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<RecoveryState> {
+        let states = self.states.read().expect("mutex poisoned");
+        states.get(name).cloned()
+    }
+
+    pub(crate) fn remove(&self, name: &str) {
+        let mut states = self.states.write().expect("mutex poisoned");
+        states.remove(name);
+    }
+
+    pub(crate) fn states(&self) -> BTreeMap<String, RecoveryState> {
+        self.states.read().expect("mutex poisoned").clone()
+    }
+}
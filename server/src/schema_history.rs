@@ -0,0 +1,431 @@
+//! This module contains the history of the schema of a database: when each
+//! of its tables and columns first appeared in a write. Downstream jobs can
+//! ask for the changes since they last looked instead of diffing full
+//! schema dumps.
+//!
+//! The history is recorded from every write stored in the mutable buffer,
+//! and persisted next to the configuration of the database whenever it
+//! changes, so it survives restarts.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    sync::Mutex,
+};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use data_types::{
+    data::ReplicatedWrite,
+    schema::{InfluxColumnType, InfluxFieldType},
+    TIME_COLUMN_NAME,
+};
+use futures::TryStreamExt;
+use generated_types::wal as wb;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use snafu::{ResultExt, Snafu};
+
+/// The name of the file the history of a database is persisted to, in the
+/// directory of its configuration
+pub const SCHEMA_HISTORY_FILE_NAME: &str = "schema_history.json";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading schema history {}: {}", path, source))]
+    ReadingHistory {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error writing schema history {}: {}", path, source))]
+    WritingHistory {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error decoding schema history {}: {}", path, source))]
+    DecodingHistory {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error encoding schema history {}: {}", path, source))]
+    EncodingHistory {
+        path: String,
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A table or a column added to the schema of a database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum SchemaChange {
+    /// The first write to the table
+    TableAdded {
+        table_name: String,
+        added_at: DateTime<Utc>,
+    },
+    /// The first write of a tag or field of the table
+    ColumnAdded {
+        table_name: String,
+        column_name: String,
+        #[serde(
+            serialize_with = "serialize_column_type",
+            deserialize_with = "deserialize_column_type"
+        )]
+        column_type: InfluxColumnType,
+        added_at: DateTime<Utc>,
+    },
+}
+
+impl SchemaChange {
+    /// When the table or column was first written
+    pub fn added_at(&self) -> DateTime<Utc> {
+        match self {
+            Self::TableAdded { added_at, .. } | Self::ColumnAdded { added_at, .. } => *added_at,
+        }
+    }
+}
+
+fn serialize_column_type<S: Serializer>(
+    column_type: &InfluxColumnType,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let column_type: &str = column_type.into();
+    serializer.serialize_str(column_type)
+}
+
+fn deserialize_column_type<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<InfluxColumnType, D::Error> {
+    let column_type = String::deserialize(deserializer)?;
+    InfluxColumnType::try_from(column_type.as_str()).map_err(serde::de::Error::custom)
+}
+
+/// The tables and columns written to a database, and when they were added
+#[derive(Debug, Default)]
+pub struct SchemaHistory {
+    inner: Mutex<Inner>,
+    /// The number of changes last persisted. Persisting is serialised so
+    /// an older history never overwrites a newer one.
+    persisted: tokio::sync::Mutex<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The names of the columns of each table, by table name
+    columns: BTreeMap<String, BTreeSet<String>>,
+    /// The changes in the order they were made
+    changes: Vec<SchemaChange>,
+}
+
+impl SchemaHistory {
+    /// Records the tables and columns of `write` that weren't written
+    /// before as added at `now`. Returns true if any were.
+    pub fn record_write(&self, write: &ReplicatedWrite, now: DateTime<Utc>) -> bool {
+        let mut inner = self.inner.lock().expect("mutex poisoned");
+        let recorded = inner.changes.len();
+
+        let table_batches = write
+            .write_buffer_batch()
+            .and_then(|batch| batch.entries())
+            .into_iter()
+            .flatten()
+            .flat_map(|entry| entry.table_batches().into_iter().flatten());
+        for table_batch in table_batches {
+            let columns = table_batch
+                .rows()
+                .into_iter()
+                .flatten()
+                .flat_map(|row| row.values().into_iter().flatten())
+                .filter_map(|value| Some((value.column()?, column_type(&value)?)));
+
+            inner.add(table_batch.name().unwrap_or(""), columns, now);
+        }
+
+        inner.changes.len() > recorded
+    }
+
+    /// Writes the changes to `SCHEMA_HISTORY_FILE_NAME` in `root`, the
+    /// directory of the configuration of the database, unless they were
+    /// already written
+    pub async fn persist(&self, store: &ObjectStore, root: &ObjectStorePath) -> Result<()> {
+        let mut persisted = self.persisted.lock().await;
+        let changes = self.changes_since(None);
+        if changes.len() <= *persisted {
+            return Ok(());
+        }
+
+        let location = history_path(root);
+        let path = store.convert_path(&location);
+        let data =
+            Bytes::from(serde_json::to_vec(&changes).context(EncodingHistory { path: &path })?);
+        let len = data.len();
+        store
+            .put(
+                &location,
+                futures::stream::once(async move { Ok(data) }),
+                len,
+            )
+            .await
+            .context(WritingHistory { path })?;
+
+        *persisted = changes.len();
+        Ok(())
+    }
+
+    /// Adds the changes persisted by `persist` in `root` to the history,
+    /// if there are any
+    pub async fn load(&self, store: &ObjectStore, root: &ObjectStorePath) -> Result<()> {
+        let location = history_path(root);
+        let path = store.convert_path(&location);
+
+        let found = store
+            .list(Some(&location))
+            .await
+            .context(ReadingHistory { path: &path })?
+            .try_collect::<Vec<_>>()
+            .await
+            .context(ReadingHistory { path: &path })?
+            .into_iter()
+            .flatten()
+            .any(|found| store.convert_path(&found) == path);
+        if !found {
+            return Ok(());
+        }
+
+        let data = store
+            .get(&location)
+            .await
+            .context(ReadingHistory { path: &path })?
+            .map_ok(|bytes| bytes.to_vec())
+            .try_concat()
+            .await
+            .context(ReadingHistory { path: &path })?;
+        let changes: Vec<SchemaChange> =
+            serde_json::from_slice(&data).context(DecodingHistory { path })?;
+
+        let mut persisted = self.persisted.lock().await;
+        *persisted = changes.len();
+        let mut inner = self.inner.lock().expect("mutex poisoned");
+        for change in changes {
+            inner.load(change);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the changes made at or after `since`, oldest first, or all
+    /// of them if `since` is None
+    pub fn changes_since(&self, since: Option<DateTime<Utc>>) -> Vec<SchemaChange> {
+        let inner = self.inner.lock().expect("mutex poisoned");
+        inner
+            .changes
+            .iter()
+            .filter(|change| since.map_or(true, |since| change.added_at() >= since))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Inner {
+    /// Adds `change`, loaded from a persisted history, unless the table or
+    /// column was already recorded
+    fn load(&mut self, change: SchemaChange) {
+        let added = match &change {
+            SchemaChange::TableAdded { table_name, .. } => {
+                if self.columns.contains_key(table_name) {
+                    false
+                } else {
+                    self.columns.insert(table_name.clone(), Default::default());
+                    true
+                }
+            }
+            SchemaChange::ColumnAdded {
+                table_name,
+                column_name,
+                ..
+            } => self
+                .columns
+                .entry(table_name.clone())
+                .or_default()
+                .insert(column_name.clone()),
+        };
+        if added {
+            self.changes.push(change);
+        }
+    }
+
+    /// Records the table `table_name` and its tag and field `columns` that
+    /// weren't written before as added at `now`
+    fn add<'c>(
+        &mut self,
+        table_name: &str,
+        columns: impl Iterator<Item = (&'c str, InfluxColumnType)>,
+        now: DateTime<Utc>,
+    ) {
+        let Self {
+            columns: tables,
+            changes,
+        } = self;
+        let table_columns = match tables.get_mut(table_name) {
+            Some(table_columns) => table_columns,
+            None => {
+                changes.push(SchemaChange::TableAdded {
+                    table_name: table_name.to_string(),
+                    added_at: now,
+                });
+                tables.entry(table_name.to_string()).or_default()
+            }
+        };
+
+        for (column_name, column_type) in columns {
+            if !table_columns.contains(column_name) {
+                table_columns.insert(column_name.to_string());
+                changes.push(SchemaChange::ColumnAdded {
+                    table_name: table_name.to_string(),
+                    column_name: column_name.to_string(),
+                    column_type,
+                    added_at: now,
+                });
+            }
+        }
+    }
+}
+
+/// The location of the history of the database whose configuration is in
+/// `root`
+fn history_path(root: &ObjectStorePath) -> ObjectStorePath {
+    let mut path = root.clone();
+    path.set_file_name(SCHEMA_HISTORY_FILE_NAME);
+    path
+}
+
+/// Returns the type of the tag or field column of `value`, or None for the
+/// time column
+fn column_type(value: &wb::Value<'_>) -> Option<InfluxColumnType> {
+    let field_type = match value.value_type() {
+        wb::ColumnValue::TagValue => return Some(InfluxColumnType::Tag),
+        wb::ColumnValue::I64Value if value.column() == Some(TIME_COLUMN_NAME) => return None,
+        wb::ColumnValue::I64Value => InfluxFieldType::Integer,
+        wb::ColumnValue::U64Value => InfluxFieldType::UInteger,
+        wb::ColumnValue::F64Value => InfluxFieldType::Float,
+        wb::ColumnValue::BoolValue => InfluxFieldType::Boolean,
+        wb::ColumnValue::StringValue => InfluxFieldType::String,
+        wb::ColumnValue::NONE => return None,
+    };
+    Some(InfluxColumnType::Field(field_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use influxdb_line_protocol::parse_lines;
+    use object_store::memory::InMemory;
+
+    fn write(lp: &str) -> ReplicatedWrite {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default())
+    }
+
+    #[test]
+    fn records_new_tables_and_columns() {
+        let history = SchemaHistory::default();
+        let first = Utc.timestamp(10, 0);
+        let second = Utc.timestamp(20, 0);
+
+        assert!(history.record_write(&write("cpu,host=a usage=1 1\ncpu,host=b usage=2 2"), first));
+        assert!(history.record_write(
+            &write("cpu,host=a,region=west usage=1,user=\"x\" 3\nmem free=4i 4"),
+            second,
+        ));
+        assert!(!history.record_write(&write("cpu,host=c usage=3 5"), second));
+
+        let column = |table_name: &str, column_name: &str, column_type, added_at| {
+            SchemaChange::ColumnAdded {
+                table_name: table_name.to_string(),
+                column_name: column_name.to_string(),
+                column_type,
+                added_at,
+            }
+        };
+        let added_later = vec![
+            column("cpu", "region", InfluxColumnType::Tag, second),
+            column(
+                "cpu",
+                "user",
+                InfluxColumnType::Field(InfluxFieldType::String),
+                second,
+            ),
+            SchemaChange::TableAdded {
+                table_name: "mem".to_string(),
+                added_at: second,
+            },
+            column(
+                "mem",
+                "free",
+                InfluxColumnType::Field(InfluxFieldType::Integer),
+                second,
+            ),
+        ];
+        assert_eq!(
+            history.changes_since(Some(Utc.timestamp(15, 0))),
+            added_later
+        );
+
+        let all = history.changes_since(None);
+        assert_eq!(all.len(), 7);
+        assert_eq!(
+            all[0],
+            SchemaChange::TableAdded {
+                table_name: "cpu".to_string(),
+                added_at: first,
+            }
+        );
+        assert_eq!(all[3..], added_later[..]);
+    }
+
+    #[test]
+    fn serialize_changes() {
+        let change = SchemaChange::ColumnAdded {
+            table_name: "cpu".to_string(),
+            column_name: "usage".to_string(),
+            column_type: InfluxColumnType::Field(InfluxFieldType::Float),
+            added_at: Utc.timestamp(10, 0),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&change).unwrap(),
+            r#"{"change":"column_added","table_name":"cpu","column_name":"usage","column_type":"iox::column_type::field::float","added_at":"1970-01-01T00:00:10Z"}"#
+        );
+        let json = serde_json::to_string(&change).unwrap();
+        assert_eq!(serde_json::from_str::<SchemaChange>(&json).unwrap(), change);
+    }
+
+    #[tokio::test]
+    async fn persists_and_loads_changes() {
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let root = ObjectStorePath::from_cloud_unchecked("1/my_db/");
+
+        let history = SchemaHistory::default();
+        history.load(&store, &root).await.unwrap();
+        assert!(history.changes_since(None).is_empty());
+
+        history.record_write(&write("cpu,host=a usage=1 1"), Utc.timestamp(10, 0));
+        history.persist(&store, &root).await.unwrap();
+        history.record_write(&write("cpu,host=a usage=1,user=2 2"), Utc.timestamp(20, 0));
+        history.persist(&store, &root).await.unwrap();
+
+        // a history loaded after a restart keeps when the columns were
+        // added, whatever is written again
+        let loaded = SchemaHistory::default();
+        loaded.load(&store, &root).await.unwrap();
+        assert!(!loaded.record_write(&write("cpu,host=a usage=1,user=2 3"), Utc.timestamp(30, 0)));
+        assert_eq!(loaded.changes_since(None), history.changes_since(None));
+    }
+}
@@ -1,17 +1,31 @@
 //! This module contains code for snapshotting a database chunk to Parquet
 //! files in object storage.
 use arrow_deps::{
-    arrow::record_batch::RecordBatch,
-    parquet::{self, arrow::ArrowWriter, file::writer::TryClone},
+    arrow::{error::ArrowError, record_batch::RecordBatch},
+    parquet::{
+        self,
+        arrow::ArrowWriter,
+        basic::Compression,
+        file::{metadata::KeyValue, properties::WriterProperties, writer::TryClone},
+    },
+};
+use data_types::{
+    database_rules::{ParquetCompression, ParquetConfig, TagOrders},
+    partition_metadata::{
+        FormatVersion, Partition as PartitionMeta, Table, FORMAT_VERSION_METADATA_KEY,
+    },
 };
-use data_types::partition_metadata::{Partition as PartitionMeta, Table};
 use object_store::{path::ObjectStorePath, ObjectStore};
 use query::PartitionChunk;
 
+use crate::db::sort_key::sort_by_series_key;
+
+use std::collections::{BTreeMap, HashMap};
 use std::io::{Cursor, Seek, SeekFrom, Write};
 use std::sync::{Arc, Mutex};
 
 use bytes::Bytes;
+use fail::fail_point;
 use snafu::{ResultExt, Snafu};
 use tokio::sync::oneshot;
 use tracing::{error, info};
@@ -45,6 +59,12 @@ pub enum Error {
         source: parquet::errors::ParquetError,
     },
 
+    #[snafu(display("Error sorting table {} by its series keys: {}", table_name, source))]
+    SortingTable {
+        table_name: String,
+        source: ArrowError,
+    },
+
     #[snafu(display("Error writing to object store: {}", source))]
     WritingToObjectStore { source: object_store::Error },
 
@@ -65,6 +85,8 @@ where
     pub data_path: ObjectStorePath,
     store: Arc<ObjectStore>,
     partition: Arc<T>,
+    parquet_config: ParquetConfig,
+    tag_orders: TagOrders,
     status: Mutex<Status>,
 }
 
@@ -79,6 +101,8 @@ where
         store: Arc<ObjectStore>,
         partition: Arc<T>,
         tables: Vec<Table>,
+        parquet_config: &ParquetConfig,
+        tag_orders: &TagOrders,
     ) -> Self {
         let table_states = vec![TableState::NotStarted; tables.len()];
 
@@ -92,11 +116,15 @@ where
             partition_meta: PartitionMeta {
                 key: partition_key.into(),
                 tables,
+                checksums: BTreeMap::new(),
+                format_version: FormatVersion::CURRENT,
             },
             metadata_path,
             data_path,
             store,
             partition,
+            parquet_config: parquet_config.clone(),
+            tag_orders: tag_orders.clone(),
             status: Mutex::new(status),
         }
     }
@@ -119,11 +147,13 @@ where
             })
     }
 
-    fn mark_table_finished(&self, position: usize) {
+    fn mark_table_finished(&self, position: usize, checksum: u32) {
         let mut status = self.status.lock().expect("mutex poisoned");
 
         if status.table_states.len() > position {
             status.table_states[position] = TableState::Finished;
+            let table_name = self.partition_meta.tables[position].name.clone();
+            status.checksums.insert(table_name, checksum);
         }
     }
 
@@ -153,22 +183,38 @@ where
                 .table_to_arrow(&mut batches, table_name, &[])
                 .map_err(|e| Box::new(e) as _)
                 .context(PartitionError)?;
+            let batches = batches
+                .into_iter()
+                .map(|batch| sort_by_series_key(batch, table_name, &self.tag_orders))
+                .collect::<Result<Vec<_>, _>>()
+                .context(SortingTable { table_name })?;
 
+            fail_point!(crate::fail_points::BEFORE_SNAPSHOT_TABLE);
             let mut location = self.data_path.clone();
             let file_name = format!("{}.parquet", table_name);
             location.set_file_name(&file_name);
-            self.write_batches(batches, &location).await?;
-            self.mark_table_finished(pos);
+            let checksum = self.write_batches(batches, &location).await?;
+            self.mark_table_finished(pos, checksum);
 
             if self.should_stop() {
                 return StoppedEarly.fail();
             }
         }
 
+        fail_point!(crate::fail_points::BEFORE_SNAPSHOT_METADATA);
         let mut partition_meta_path = self.metadata_path.clone();
         let key = format!("{}.json", &self.partition_meta.key);
         partition_meta_path.set_file_name(&key);
-        let json_data = serde_json::to_vec(&self.partition_meta).context(JsonGenerationError)?;
+        // the metadata records the checksums of the Parquet files, so they
+        // can be verified when read
+        let mut partition_meta = self.partition_meta.clone();
+        partition_meta.checksums = self
+            .status
+            .lock()
+            .expect("mutex poisoned")
+            .checksums
+            .clone();
+        let json_data = serde_json::to_vec(&partition_meta).context(JsonGenerationError)?;
         let data = Bytes::from(json_data);
         let len = data.len();
         let stream_data = std::io::Result::Ok(data);
@@ -192,24 +238,15 @@ where
         Ok(())
     }
 
+    /// Writes `batches` to the Parquet file `file_name`, returning its
+    /// checksum
     async fn write_batches(
         &self,
         batches: Vec<RecordBatch>,
         file_name: &ObjectStorePath,
-    ) -> Result<()> {
-        let mem_writer = MemWriter::default();
-        {
-            let mut writer = ArrowWriter::try_new(mem_writer.clone(), batches[0].schema(), None)
-                .context(OpeningParquetWriter)?;
-            for batch in batches.into_iter() {
-                writer.write(&batch).context(WritingParquetToMemory)?;
-            }
-            writer.close().context(ClosingParquetWriter)?;
-        } // drop the reference to the MemWriter that the SerializedFileWriter has
-
-        let data = mem_writer
-            .into_inner()
-            .expect("Nothing else should have a reference here");
+    ) -> Result<u32> {
+        let data = parquet_bytes(batches, &self.parquet_config)?;
+        let checksum = crc32fast::hash(&data);
 
         let len = data.len();
         let data = Bytes::from(data);
@@ -222,7 +259,8 @@ where
                 len,
             )
             .await
-            .context(WritingToObjectStore)
+            .context(WritingToObjectStore)?;
+        Ok(checksum)
     }
 
     fn set_error(&self, e: Error) {
@@ -241,17 +279,37 @@ pub enum TableState {
 #[derive(Debug, Default)]
 pub struct Status {
     table_states: Vec<TableState>,
+    /// The checksums of the Parquet files written, by table name
+    checksums: BTreeMap<String, u32>,
     meta_written: bool,
     stop_on_next_update: bool,
     error: Option<Error>,
 }
 
+/// Returns the directories the snapshots of the database `db_name` are
+/// written to: its metadata directory, with one file per partition, and
+/// its data directory, with one directory of Parquet files per partition.
+pub fn snapshot_paths(db_name: &str) -> (ObjectStorePath, ObjectStorePath) {
+    let mut metadata_path = ObjectStorePath::default();
+    metadata_path.push_dir(db_name);
+    let mut data_path = metadata_path.clone();
+    metadata_path.push_dir("meta");
+    data_path.push_dir("data");
+    (metadata_path, data_path)
+}
+
+/// Starts writing `chunk` to Parquet files under `data_path`, one per
+/// table, as configured by `parquet_config`, followed by its metadata under
+/// `metadata_path`. The rows of each table are sorted by their series keys,
+/// in the tag order set by `tag_orders`.
 pub fn snapshot_chunk<T>(
     metadata_path: ObjectStorePath,
     data_path: ObjectStorePath,
     store: Arc<ObjectStore>,
     partition_key: &str,
     chunk: Arc<T>,
+    parquet_config: &ParquetConfig,
+    tag_orders: &TagOrders,
     notify: Option<oneshot::Sender<()>>,
 ) -> Result<Arc<Snapshot<T>>>
 where
@@ -269,6 +327,8 @@ where
         store,
         chunk,
         table_stats,
+        parquet_config,
+        tag_orders,
     );
     let snapshot = Arc::new(snapshot);
 
@@ -289,6 +349,66 @@ where
     Ok(return_snapshot)
 }
 
+/// Encodes `batches`, which must not be empty and must all have the same
+/// schema, as a Parquet file
+pub(crate) fn parquet_bytes(
+    batches: Vec<RecordBatch>,
+    parquet_config: &ParquetConfig,
+) -> Result<Vec<u8>> {
+    let mem_writer = MemWriter::default();
+    {
+        let schema = batches[0].schema();
+        let properties = writer_properties(parquet_config, schema.metadata());
+        let mut writer = ArrowWriter::try_new(mem_writer.clone(), schema, Some(properties))
+            .context(OpeningParquetWriter)?;
+        for batch in batches.into_iter() {
+            writer.write(&batch).context(WritingParquetToMemory)?;
+        }
+        writer.close().context(ClosingParquetWriter)?;
+    } // drop the reference to the MemWriter that the SerializedFileWriter has
+
+    Ok(mem_writer
+        .into_inner()
+        .expect("Nothing else should have a reference here"))
+}
+
+/// Returns the properties of the Parquet writer configured by `config`.
+/// The metadata of the schema of the batches written, which holds the IOx
+/// column types, is kept in the key-value metadata of the file, along with
+/// the version of the format the file is written in.
+fn writer_properties(
+    config: &ParquetConfig,
+    schema_metadata: &HashMap<String, String>,
+) -> WriterProperties {
+    let compression = match config.compression {
+        ParquetCompression::Uncompressed => Compression::UNCOMPRESSED,
+        ParquetCompression::Snappy => Compression::SNAPPY,
+        ParquetCompression::Gzip => Compression::GZIP,
+        ParquetCompression::Brotli => Compression::BROTLI,
+        ParquetCompression::Lz4 => Compression::LZ4,
+        ParquetCompression::Zstd => Compression::ZSTD,
+    };
+
+    let mut builder = WriterProperties::builder().set_compression(compression);
+    if let Some(dictionary_enabled) = config.dictionary_enabled {
+        builder = builder.set_dictionary_enabled(dictionary_enabled);
+    }
+    if let Some(data_page_size) = config.data_page_size {
+        builder = builder.set_data_pagesize_limit(data_page_size.max(1));
+    }
+    let key_value_metadata = schema_metadata
+        .iter()
+        .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+        .chain(std::iter::once(KeyValue::new(
+            FORMAT_VERSION_METADATA_KEY.to_string(),
+            FormatVersion::CURRENT.to_string(),
+        )))
+        .collect();
+    builder
+        .set_key_value_metadata(Some(key_value_metadata))
+        .build()
+}
+
 #[derive(Debug, Default, Clone)]
 struct MemWriter {
     mem: Arc<Mutex<Cursor<Vec<u8>>>>,
@@ -335,6 +455,13 @@ impl TryClone for MemWriter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use arrow_deps::parquet::{
+        basic::Encoding,
+        file::{
+            reader::{FileReader, SerializedFileReader},
+            serialized_reader::SliceableCursor,
+        },
+    };
     use data_types::data::lines_to_replicated_write;
     use data_types::database_rules::DatabaseRules;
     use futures::TryStreamExt;
@@ -370,10 +497,12 @@ mem,host=A,region=west used=45 1
 
         let snapshot = snapshot_chunk(
             metadata_path.clone(),
-            data_path,
+            data_path.clone(),
             store.clone(),
             "testaroo",
             chunk.clone(),
+            &ParquetConfig::default(),
+            &TagOrders::default(),
             Some(tx),
         )
         .unwrap();
@@ -393,7 +522,90 @@ mem,host=A,region=west used=45 1
             .unwrap();
 
         let meta: PartitionMeta = serde_json::from_slice(&*summary).unwrap();
-        assert_eq!(meta, snapshot.partition_meta);
+        assert_eq!(meta.key, snapshot.partition_meta.key);
+        assert_eq!(meta.tables, snapshot.partition_meta.tables);
+        assert_eq!(meta.format_version, FormatVersion::CURRENT);
+
+        // the checksum of the Parquet file of each table is recorded
+        assert_eq!(meta.checksums.len(), 2);
+        for (table_name, checksum) in &meta.checksums {
+            let mut location = data_path.clone();
+            location.set_file_name(format!("{}.parquet", table_name));
+            let data = store
+                .get(&location)
+                .await
+                .unwrap()
+                .map_ok(|b| b.to_vec())
+                .try_concat()
+                .await
+                .unwrap();
+            assert_eq!(*checksum, crc32fast::hash(&data));
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_with_parquet_config() {
+        let lp = "cpu,host=A usage=0.5 1\ncpu,host=A usage=0.7 2\ncpu,host=B usage=0.1 3";
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &DatabaseRules::default());
+        let mut chunk = ChunkWB::new(11);
+        for e in write.write_buffer_batch().unwrap().entries().unwrap() {
+            chunk.write_entry(&e).unwrap();
+        }
+
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let mut data_path = ObjectStorePath::default();
+        data_path.push_dir("data");
+        let parquet_config = ParquetConfig {
+            compression: ParquetCompression::Zstd,
+            dictionary_enabled: Some(false),
+            data_page_size: Some(1024),
+        };
+
+        snapshot_chunk(
+            ObjectStorePath::default(),
+            data_path.clone(),
+            store.clone(),
+            "testaroo",
+            Arc::new(chunk),
+            &parquet_config,
+            &TagOrders::default(),
+            Some(tx),
+        )
+        .unwrap();
+        rx.await.unwrap();
+
+        let mut location = data_path;
+        location.set_file_name("cpu.parquet");
+        let data = store
+            .get(&location)
+            .await
+            .unwrap()
+            .map_ok(|b| b.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+
+        let reader = SerializedFileReader::new(SliceableCursor::new(data)).unwrap();
+        let version = reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .iter()
+            .flatten()
+            .find(|kv| kv.key == FORMAT_VERSION_METADATA_KEY)
+            .and_then(|kv| kv.value.clone());
+        assert_eq!(version, Some(FormatVersion::CURRENT.to_string()));
+
+        let row_groups = reader.metadata().row_groups();
+        assert!(!row_groups.is_empty());
+        for column in row_groups.iter().flat_map(|row_group| row_group.columns()) {
+            assert_eq!(column.compression(), Compression::ZSTD);
+            let encodings = column.encodings();
+            assert!(!encodings.contains(&Encoding::PLAIN_DICTIONARY));
+            assert!(!encodings.contains(&Encoding::RLE_DICTIONARY));
+        }
     }
 
     #[test]
@@ -402,14 +614,17 @@ mem,host=A,region=west used=45 1
             Table {
                 name: "foo".to_string(),
                 columns: vec![],
+                time_range: None,
             },
             Table {
                 name: "bar".to_string(),
                 columns: vec![],
+                time_range: None,
             },
             Table {
                 name: "asdf".to_string(),
                 columns: vec![],
+                time_range: None,
             },
         ];
 
@@ -421,7 +636,16 @@ mem,host=A,region=west used=45 1
         let mut data_path = ObjectStorePath::default();
         data_path.push_dir("data");
 
-        let snapshot = Snapshot::new("testaroo", metadata_path, data_path, store, chunk, tables);
+        let snapshot = Snapshot::new(
+            "testaroo",
+            metadata_path,
+            data_path,
+            store,
+            chunk,
+            tables,
+            &ParquetConfig::default(),
+            &TagOrders::default(),
+        );
 
         let (pos, name) = snapshot.next_table().unwrap();
         assert_eq!(0, pos);
@@ -431,7 +655,7 @@ mem,host=A,region=west used=45 1
         assert_eq!(1, pos);
         assert_eq!("bar", name);
 
-        snapshot.mark_table_finished(1);
+        snapshot.mark_table_finished(1, 0);
         assert!(!snapshot.finished());
 
         let (pos, name) = snapshot.next_table().unwrap();
@@ -441,8 +665,8 @@ mem,host=A,region=west used=45 1
         assert!(snapshot.next_table().is_none());
         assert!(!snapshot.finished());
 
-        snapshot.mark_table_finished(0);
-        snapshot.mark_table_finished(2);
+        snapshot.mark_table_finished(0, 0);
+        snapshot.mark_table_finished(2, 0);
         assert!(snapshot.finished());
     }
 }
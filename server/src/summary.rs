@@ -0,0 +1,401 @@
+//! This module contains the summary of what a database contains: its
+//! tables, their columns, rows and time ranges, and how much memory each
+//! storage tier uses. Monitoring dashboards poll it, so the partitions
+//! are summarized from the statistics of their chunks as chunks are
+//! written, rewritten and dropped, rather than by scanning the data when
+//! asked.
+//!
+//! The summary covers the chunks held in memory. The column types of the
+//! tables come from the writes this server has applied since the
+//! database was loaded, like the schema history.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::Mutex,
+};
+
+use data_types::{
+    data::ReplicatedWrite,
+    schema::{InfluxColumnType, InfluxFieldType},
+    TIME_COLUMN_NAME,
+};
+use generated_types::wal as wb;
+use serde::{Serialize, Serializer};
+
+/// What a database contains, rolled up from the summaries of its
+/// partitions
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DatabaseSummary {
+    /// The number of partitions written
+    pub partitions: usize,
+    /// The tables written, by name
+    pub tables: BTreeMap<String, TableSummary>,
+    /// The memory used by each storage tier
+    pub storage: StorageSummary,
+}
+
+/// What a table contains across the partitions of a database
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TableSummary {
+    /// The number of partitions the table was written to
+    pub partitions: usize,
+    /// The number of rows written
+    pub rows: u64,
+    /// The smallest time of the rows, if any have a time
+    pub min_time: Option<i64>,
+    /// The largest time of the rows, if any have a time
+    pub max_time: Option<i64>,
+    /// The type of each column, by column name
+    #[serde(serialize_with = "serialize_column_types")]
+    pub columns: BTreeMap<String, InfluxColumnType>,
+}
+
+impl TableSummary {
+    /// Adds the summary of the table in another partition
+    fn merge(&mut self, other: &Self) {
+        self.partitions += other.partitions;
+        self.rows += other.rows;
+        self.min_time = min_time(self.min_time, other.min_time);
+        self.max_time = self.max_time.max(other.max_time);
+        for (name, column_type) in &other.columns {
+            self.columns.entry(name.clone()).or_insert(*column_type);
+        }
+    }
+}
+
+/// The approximate memory used by the data of each storage tier, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StorageSummary {
+    pub mutable_buffer_bytes: u64,
+    pub read_buffer_bytes: u64,
+}
+
+fn serialize_column_types<S: Serializer>(
+    columns: &BTreeMap<String, InfluxColumnType>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(columns.iter().map(|(name, column_type)| {
+        let column_type: &str = column_type.into();
+        (name, column_type)
+    }))
+}
+
+fn min_time(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The summaries of the tables of each partition of a database, kept up
+/// to date as writes are stored and chunks are rewritten or dropped
+#[derive(Debug, Default)]
+pub struct PartitionSummaries {
+    /// partition key -> summary
+    partitions: Mutex<BTreeMap<String, PartitionSummary>>,
+}
+
+/// What the chunks of a partition contain
+#[derive(Debug, Default)]
+struct PartitionSummary {
+    /// table name -> column name -> type, of the tables written
+    columns: BTreeMap<String, BTreeMap<String, InfluxColumnType>>,
+    /// chunk id -> table name -> rows
+    chunks: BTreeMap<u32, BTreeMap<String, TableRows>>,
+}
+
+/// The rows of a table in a chunk, from the statistics of the chunk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableRows {
+    pub rows: u64,
+    /// The smallest and largest times of the rows, if any have a time
+    pub time_range: Option<(i64, i64)>,
+}
+
+impl PartitionSummary {
+    /// The summaries of the tables with rows in any chunk
+    fn tables(&self) -> BTreeMap<String, TableSummary> {
+        let mut tables: BTreeMap<String, TableSummary> = BTreeMap::new();
+        for (name, rows) in self.chunks.values().flatten() {
+            let table = tables.entry(name.clone()).or_insert_with(|| TableSummary {
+                partitions: 1,
+                columns: self.columns.get(name).cloned().unwrap_or_default(),
+                ..Default::default()
+            });
+            table.rows += rows.rows;
+            if let Some((min, max)) = rows.time_range {
+                table.min_time = min_time(table.min_time, Some(min));
+                table.max_time = table.max_time.max(Some(max));
+            }
+        }
+        tables
+    }
+}
+
+impl PartitionSummaries {
+    /// Adds the columns of the tables of `write` to the summaries of their
+    /// partitions, returning the keys of the partitions written and
+    /// whether each was written to for the first time. The rows are
+    /// counted from the chunks they are stored in, by `set_chunk`.
+    pub fn record_columns(&self, write: &ReplicatedWrite) -> Vec<(String, bool)> {
+        let entries = write
+            .write_buffer_batch()
+            .and_then(|batch| batch.entries())
+            .into_iter()
+            .flatten();
+
+        let mut partitions = self.partitions.lock().expect("mutex poisoned");
+        let mut written = Vec::new();
+        for entry in entries {
+            let partition = match partitions.entry(entry.partition_key().unwrap_or("").to_string())
+            {
+                Entry::Occupied(entry) => {
+                    written.push((entry.key().clone(), false));
+                    entry.into_mut()
+                }
+                Entry::Vacant(entry) => {
+                    written.push((entry.key().clone(), true));
+                    entry.insert(Default::default())
+                }
+            };
+
+            for table in entry.table_batches().into_iter().flatten() {
+                let columns = partition
+                    .columns
+                    .entry(table.name().unwrap_or("").to_string())
+                    .or_default();
+
+                for row in table.rows().into_iter().flatten() {
+                    for value in row.values().into_iter().flatten() {
+                        record_column(columns, &value);
+                    }
+                }
+            }
+        }
+        written
+    }
+
+    /// Returns the names of the tables written to the partition
+    /// `partition_key`
+    pub fn table_names(&self, partition_key: &str) -> Vec<String> {
+        let partitions = self.partitions.lock().expect("mutex poisoned");
+        partitions
+            .get(partition_key)
+            .map(|partition| partition.columns.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Sets the rows of the tables of chunk `chunk_id` of the partition
+    /// `partition_key`, after the chunk was written or rewritten
+    pub fn set_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+        tables: impl IntoIterator<Item = (String, TableRows)>,
+    ) {
+        let mut partitions = self.partitions.lock().expect("mutex poisoned");
+        let partition = partitions.entry(partition_key.to_string()).or_default();
+        partition.chunks.insert(
+            chunk_id,
+            tables
+                .into_iter()
+                .filter(|(_, rows)| rows.rows > 0)
+                .collect(),
+        );
+    }
+
+    /// Forgets chunk `chunk_id` of the partition `partition_key` after it
+    /// was dropped, and the partition once it has no chunks left
+    pub fn remove_chunk(&self, partition_key: &str, chunk_id: u32) {
+        let mut partitions = self.partitions.lock().expect("mutex poisoned");
+        if let Some(partition) = partitions.get_mut(partition_key) {
+            partition.chunks.remove(&chunk_id);
+            if partition.chunks.is_empty() {
+                partitions.remove(partition_key);
+            }
+        }
+    }
+
+    /// Returns the summaries of the tables of the partition
+    /// `partition_key`, if it holds any rows
+    pub fn partition(&self, partition_key: &str) -> Option<BTreeMap<String, TableSummary>> {
+        let partitions = self.partitions.lock().expect("mutex poisoned");
+        partitions.get(partition_key).map(PartitionSummary::tables)
+    }
+
+    /// Adds the columns of `tables` to the partition `partition_key`, such
+    /// as when it was copied from another database
+    pub fn insert_columns(&self, partition_key: &str, tables: &BTreeMap<String, TableSummary>) {
+        let mut partitions = self.partitions.lock().expect("mutex poisoned");
+        let partition = partitions.entry(partition_key.to_string()).or_default();
+        for (name, table) in tables {
+            let columns = partition.columns.entry(name.clone()).or_default();
+            for (column, column_type) in &table.columns {
+                columns.entry(column.clone()).or_insert(*column_type);
+            }
+        }
+    }
+
+    /// Forgets the partition `partition_key`, such as when it was moved to
+    /// another database
+    pub fn remove_partition(&self, partition_key: &str) {
+        let mut partitions = self.partitions.lock().expect("mutex poisoned");
+        partitions.remove(partition_key);
+    }
+
+    /// Rolls the summaries of the partitions holding rows up into a
+    /// summary of the database
+    pub fn summary(&self, storage: StorageSummary) -> DatabaseSummary {
+        let partitions = self.partitions.lock().expect("mutex poisoned");
+
+        let mut count = 0;
+        let mut tables: BTreeMap<String, TableSummary> = BTreeMap::new();
+        for partition in partitions.values() {
+            let partition_tables = partition.tables();
+            if partition_tables.is_empty() {
+                continue;
+            }
+            count += 1;
+            for (name, table) in &partition_tables {
+                tables.entry(name.clone()).or_default().merge(table);
+            }
+        }
+
+        DatabaseSummary {
+            partitions: count,
+            tables,
+            storage,
+        }
+    }
+}
+
+fn record_column(columns: &mut BTreeMap<String, InfluxColumnType>, value: &wb::Value<'_>) {
+    let column = value.column().unwrap_or("");
+    if columns.contains_key(column) {
+        return;
+    }
+
+    let column_type = match value.value_type() {
+        wb::ColumnValue::I64Value if column == TIME_COLUMN_NAME => InfluxColumnType::Timestamp,
+        wb::ColumnValue::TagValue => InfluxColumnType::Tag,
+        wb::ColumnValue::I64Value => InfluxColumnType::Field(InfluxFieldType::Integer),
+        wb::ColumnValue::U64Value => InfluxColumnType::Field(InfluxFieldType::UInteger),
+        wb::ColumnValue::F64Value => InfluxColumnType::Field(InfluxFieldType::Float),
+        wb::ColumnValue::BoolValue => InfluxColumnType::Field(InfluxFieldType::Boolean),
+        wb::ColumnValue::StringValue => InfluxColumnType::Field(InfluxFieldType::String),
+        wb::ColumnValue::NONE => return,
+    };
+    columns.insert(column.to_string(), column_type);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{DatabaseRules, PartitionTemplate, TemplatePart},
+    };
+    use influxdb_line_protocol::parse_lines;
+
+    fn write(lp: &str) -> ReplicatedWrite {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Column("region".to_string())],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(1, 1, &lines, &rules)
+    }
+
+    fn rows(rows: u64, min: i64, max: i64) -> TableRows {
+        TableRows {
+            rows,
+            time_range: Some((min, max)),
+        }
+    }
+
+    #[test]
+    fn partitions_are_rolled_up() {
+        let summaries = PartitionSummaries::default();
+        let mut written = summaries.record_columns(&write(
+            "cpu,region=west usage=0.5 10\ncpu,region=east usage=0.9,cores=4i 30",
+        ));
+        written.sort();
+        assert_eq!(
+            written,
+            vec![("east".to_string(), true), ("west".to_string(), true)]
+        );
+        let written = summaries.record_columns(&write(
+            "cpu,region=west usage=0.1 5\nmem,region=west free=1i 20",
+        ));
+        assert_eq!(written, vec![("west".to_string(), false)]);
+
+        // the rows are counted from the chunks
+        summaries.set_chunk(
+            "west",
+            0,
+            vec![
+                ("cpu".to_string(), rows(1, 10, 10)),
+                ("mem".to_string(), rows(1, 20, 20)),
+            ],
+        );
+        summaries.set_chunk("west", 1, vec![("cpu".to_string(), rows(1, 5, 5))]);
+        summaries.set_chunk("east", 0, vec![("cpu".to_string(), rows(1, 30, 30))]);
+
+        let storage = StorageSummary {
+            mutable_buffer_bytes: 100,
+            read_buffer_bytes: 50,
+        };
+        let summary = summaries.summary(storage);
+        assert_eq!(summary.partitions, 2);
+        assert_eq!(summary.storage, storage);
+
+        let cpu = &summary.tables["cpu"];
+        assert_eq!(cpu.partitions, 2);
+        assert_eq!(cpu.rows, 3);
+        assert_eq!((cpu.min_time, cpu.max_time), (Some(5), Some(30)));
+        let columns: Vec<_> = cpu.columns.iter().collect();
+        assert_eq!(
+            columns,
+            vec![
+                (
+                    &"cores".to_string(),
+                    &InfluxColumnType::Field(InfluxFieldType::Integer)
+                ),
+                (&"region".to_string(), &InfluxColumnType::Tag),
+                (&"time".to_string(), &InfluxColumnType::Timestamp),
+                (
+                    &"usage".to_string(),
+                    &InfluxColumnType::Field(InfluxFieldType::Float)
+                ),
+            ]
+        );
+
+        let mem = &summary.tables["mem"];
+        assert_eq!(mem.partitions, 1);
+        assert_eq!(mem.rows, 1);
+        assert_eq!(
+            serde_json::to_string(mem).unwrap(),
+            r#"{"partitions":1,"rows":1,"min_time":20,"max_time":20,"columns":{"free":"iox::column_type::field::integer","region":"iox::column_type::tag","time":"iox::column_type::timestamp"}}"#
+        );
+
+        // dropped and rewritten chunks no longer count
+        summaries.remove_chunk("east", 0);
+        summaries.set_chunk("west", 0, vec![("mem".to_string(), rows(1, 20, 20))]);
+        let summary = summaries.summary(storage);
+        assert_eq!(summary.partitions, 1);
+        let cpu = &summary.tables["cpu"];
+        assert_eq!((cpu.partitions, cpu.rows), (1, 1));
+        assert_eq!((cpu.min_time, cpu.max_time), (Some(5), Some(5)));
+
+        // a partition without chunks is forgotten, so writing to it again
+        // creates it again
+        summaries.remove_chunk("west", 0);
+        summaries.remove_chunk("west", 1);
+        assert_eq!(summaries.summary(storage).partitions, 0);
+        let written = summaries.record_columns(&write("cpu,region=west usage=0.5 10"));
+        assert_eq!(written, vec![("west".to_string(), true)]);
+    }
+}
@@ -0,0 +1,724 @@
+//! This module contains the warming of the read buffer of a database with
+//! the snapshots of its partitions in object storage. A query node that
+//! was just started can load the data its first queries will read, such
+//! as the last day of the tables of a dashboard, rather than have those
+//! queries wait on object storage.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    rc::Rc,
+    sync::Arc,
+};
+
+use arrow_deps::{
+    arrow::{datatypes::Schema, error::ArrowError, record_batch::RecordBatch},
+    parquet::{
+        self,
+        arrow::{ArrowReader, ParquetFileArrowReader},
+        file::{
+            reader::{FileReader, SerializedFileReader},
+            serialized_reader::SliceableCursor,
+        },
+    },
+};
+use data_types::partition_metadata::{
+    self, FormatVersion, Partition as PartitionMeta, Table, FORMAT_VERSION_METADATA_KEY,
+};
+use futures::TryStreamExt;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use query::predicate::TimestampRange;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use tracing::{info, warn};
+
+use crate::db::Db;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error listing snapshots in object store: {}", source))]
+    ListingSnapshots { source: object_store::Error },
+
+    #[snafu(display("Error reading {} from object store: {}", path, source))]
+    ReadingObject {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error decoding snapshot metadata {}: {}", path, source))]
+    DecodingMetadata {
+        path: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error reading Parquet file {}: {}", path, source))]
+    ReadingParquet {
+        path: String,
+        source: parquet::errors::ParquetError,
+    },
+
+    #[snafu(display("Error decoding Parquet file {}: {}", path, source))]
+    DecodingParquet { path: String, source: ArrowError },
+
+    #[snafu(display("Can't read Parquet file {}: {}", path, source))]
+    UnsupportedFormat {
+        path: String,
+        source: partition_metadata::Error,
+    },
+
+    #[snafu(display("Error removing deleted series from {}: {}", path, source))]
+    RemovingDeletedSeries {
+        path: String,
+        source: crate::db::delete::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The id of the read buffer chunk the snapshot of a partition is loaded
+/// into. The mutable buffer numbers its chunks from zero, so this doesn't
+/// clash with the chunks loaded from it.
+pub const SNAPSHOT_CHUNK_ID: u32 = u32::MAX;
+
+/// The number of rows of the record batches read from Parquet files
+const BATCH_SIZE: usize = 8 * 1024;
+
+/// The key-value metadata Parquet files written from Arrow hold their
+/// Arrow schema in
+const ARROW_SCHEMA_KEY: &str = "ARROW:schema";
+
+/// What `Db::warm` loaded into the read buffer
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct WarmSummary {
+    /// The number of partitions tables were loaded for
+    pub partitions: usize,
+    /// The number of tables loaded
+    pub tables: usize,
+    /// The number of tables skipped because they were already loaded
+    pub tables_already_loaded: usize,
+    /// The number of partitions whose tables were loaded again because
+    /// they were snapshotted since they were last loaded
+    pub partitions_reloaded: usize,
+    /// The number of partitions skipped because their snapshots were
+    /// written in a version of the format that can't be read
+    pub partitions_unsupported: usize,
+    /// The number of rows loaded
+    pub rows: u64,
+}
+
+/// The snapshots `Db::warm` loaded tables of into the read buffer, by
+/// partition key. It is locked for the whole of `Db::warm`, so concurrent
+/// warms don't load the same table twice.
+#[derive(Debug, Default)]
+pub(crate) struct WarmedSnapshots(tokio::sync::Mutex<HashMap<String, WarmedSnapshot>>);
+
+/// The snapshot of a partition tables were loaded from
+#[derive(Debug)]
+struct WarmedSnapshot {
+    /// The checksum of the metadata of the snapshot. The metadata is
+    /// rewritten whenever the partition is snapshotted, so this tells
+    /// a newer snapshot from the one loaded.
+    checksum: u32,
+    /// The names of the tables loaded
+    tables: BTreeSet<String>,
+}
+
+impl Db {
+    /// Loads the tables of the snapshots of the partitions of this database
+    /// into the read buffer, so queries of them don't read object storage.
+    /// The metadata of the snapshots is read from `metadata_path`, and the
+    /// Parquet files of partition `p` from `p` under `data_path`.
+    ///
+    /// Only the tables named in `tables` are loaded, or all of them if it
+    /// is empty, and only if their rows might be in `range`. Tables that
+    /// were already loaded from the same snapshot are skipped, so warming
+    /// can be repeated as new partitions are snapshotted. When a partition
+    /// was snapshotted again, the tables loaded from its previous snapshot
+    /// are replaced by those of the new one.
+    pub async fn warm(
+        &self,
+        store: &ObjectStore,
+        metadata_path: &ObjectStorePath,
+        data_path: &ObjectStorePath,
+        range: Option<TimestampRange>,
+        tables: &[String],
+    ) -> Result<WarmSummary> {
+        let mut summary = WarmSummary::default();
+
+        let paths: Vec<Vec<_>> = store
+            .list(Some(metadata_path))
+            .await
+            .context(ListingSnapshots)?
+            .try_collect()
+            .await
+            .context(ListingSnapshots)?;
+
+        let mut warmed = self.warmed_snapshots.0.lock().await;
+
+        for path in paths.into_iter().flatten() {
+            let path_string = store.convert_path(&path);
+            let meta_bytes = get_bytes(store, &path).await?;
+            let meta: PartitionMeta = serde_json::from_slice(&meta_bytes)
+                .context(DecodingMetadata { path: &path_string })?;
+            if let Err(e) = meta.format_version.check_readable() {
+                warn!(path = path_string.as_str(), error = %e, "Skipping snapshot");
+                summary.partitions_unsupported += 1;
+                continue;
+            }
+
+            let checksum = crc32fast::hash(&meta_bytes);
+            let snapshotted_again = warmed
+                .get(&meta.key)
+                .map_or(false, |previous| previous.checksum != checksum);
+            // the tables loaded from the previous snapshot are loaded again
+            // from the new one, whatever the tables and range asked for
+            let reload = if snapshotted_again {
+                self.drop_snapshot_chunk(&meta.key);
+                summary.partitions_reloaded += 1;
+                warmed
+                    .remove(&meta.key)
+                    .map(|previous| previous.tables)
+                    .unwrap_or_default()
+            } else {
+                BTreeSet::new()
+            };
+            let loaded_tables = &mut warmed
+                .entry(meta.key.clone())
+                .or_insert_with(|| WarmedSnapshot {
+                    checksum,
+                    tables: BTreeSet::new(),
+                })
+                .tables;
+
+            let mut loaded = false;
+            for table in &meta.tables {
+                if !reload.contains(&table.name) && !should_warm(table, range, tables) {
+                    continue;
+                }
+                if loaded_tables.contains(&table.name)
+                    && self.snapshot_table_loaded(&meta.key, &table.name)
+                {
+                    summary.tables_already_loaded += 1;
+                    continue;
+                }
+
+                let mut location = data_path.clone();
+                location.push_dir(&meta.key);
+                location.set_file_name(format!("{}.parquet", table.name));
+                let path = store.convert_path(&location);
+                let batches = read_parquet(get_bytes(store, &location).await?, &path)?;
+                // the snapshot may have been written before series of the
+                // table were deleted
+                let batches = batches
+                    .into_iter()
+                    .map(|batch| self.remove_deleted_rows(&table.name, batch))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context(RemovingDeletedSeries { path: &path })?;
+
+                {
+                    let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+                    for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+                        summary.rows += batch.num_rows() as u64;
+                        read_buffer.upsert_partition(
+                            &meta.key,
+                            SNAPSHOT_CHUNK_ID,
+                            &table.name,
+                            batch,
+                        );
+                    }
+                }
+                loaded_tables.insert(table.name.clone());
+                summary.tables += 1;
+                loaded = true;
+            }
+
+            if loaded {
+                summary.partitions += 1;
+            }
+            if loaded_tables.is_empty() {
+                warmed.remove(&meta.key);
+            }
+        }
+
+        info!(
+            db_name = self.rules.name.as_str(),
+            partitions = summary.partitions,
+            tables = summary.tables,
+            rows = summary.rows,
+            "Warmed read buffer from snapshots"
+        );
+        Ok(summary)
+    }
+
+    /// Returns true if the snapshot of table `table_name` of the partition
+    /// `partition_key` is in the read buffer
+    fn snapshot_table_loaded(&self, partition_key: &str, table_name: &str) -> bool {
+        let read_buffer = self.read_buffer.read().expect("mutex poisoned");
+        matches!(
+            read_buffer.table_rows(partition_key, SNAPSHOT_CHUNK_ID, table_name),
+            Ok((rows, _)) if rows > 0
+        )
+    }
+
+    /// Removes the chunk the snapshot of the partition `partition_key` was
+    /// loaded into from the read buffer, if it is there
+    fn drop_snapshot_chunk(&self, partition_key: &str) {
+        let mut read_buffer = self.read_buffer.write().expect("mutex poisoned");
+        // the chunk is gone if everything in it was deleted or expired
+        let _ = read_buffer.drop_chunk(partition_key, SNAPSHOT_CHUNK_ID);
+    }
+}
+
+/// Returns true if `table` is one of `tables` (or `tables` is empty), and
+/// its rows might be in `range`
+fn should_warm(table: &Table, range: Option<TimestampRange>, tables: &[String]) -> bool {
+    if !tables.is_empty() && !tables.contains(&table.name) {
+        return false;
+    }
+    match (range, table.time_range) {
+        (Some(range), Some((min, max))) => min < range.end && range.start <= max,
+        _ => true,
+    }
+}
+
+pub(crate) async fn get_bytes(store: &ObjectStore, path: &ObjectStorePath) -> Result<Vec<u8>> {
+    let path_string = store.convert_path(path);
+    store
+        .get(path)
+        .await
+        .context(ReadingObject { path: &path_string })?
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await
+        .context(ReadingObject { path: &path_string })
+}
+
+/// Decodes the Parquet file `data`, restoring the metadata of its schema,
+/// which holds the IOx column types the read buffer needs, from the
+/// key-value metadata of the file. Files written in a version of the
+/// format that can't be read are refused; those written before versions
+/// were recorded are read as `FormatVersion::INITIAL`.
+pub(crate) fn read_parquet(data: Vec<u8>, path: &str) -> Result<Vec<RecordBatch>> {
+    let reader =
+        SerializedFileReader::new(SliceableCursor::new(data)).context(ReadingParquet { path })?;
+    let mut metadata: HashMap<_, _> = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .iter()
+        .flatten()
+        .filter(|kv| kv.key != ARROW_SCHEMA_KEY)
+        .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+        .collect();
+
+    let version = match metadata.remove(FORMAT_VERSION_METADATA_KEY) {
+        Some(version) => version.parse().context(UnsupportedFormat { path })?,
+        None => FormatVersion::INITIAL,
+    };
+    version
+        .check_readable()
+        .context(UnsupportedFormat { path })?;
+
+    let mut reader = ParquetFileArrowReader::new(Rc::new(reader));
+    let batches = reader
+        .get_record_reader(BATCH_SIZE)
+        .context(ReadingParquet { path })?
+        .collect::<Result<Vec<_>, _>>()
+        .context(DecodingParquet { path })?;
+
+    batches
+        .into_iter()
+        .map(|batch| {
+            let schema =
+                Schema::new_with_metadata(batch.schema().fields().clone(), metadata.clone());
+            RecordBatch::try_new(Arc::new(schema), batch.columns().to_vec())
+                .context(DecodingParquet { path })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::snapshot_chunk;
+    use arrow_deps::{
+        arrow::{
+            array::{ArrayRef, Float64Array},
+            datatypes::{DataType, Field},
+        },
+        datafusion::physical_plan::collect,
+    };
+    use data_types::database_rules::{
+        DatabaseRules, ParquetConfig, PartitionTemplate, TagOrders, TemplatePart,
+    };
+    use data_types::DatabaseName;
+    use influxdb_line_protocol::parse_lines;
+    use mutable_buffer::MutableBufferDb;
+    use object_store::memory::InMemory;
+    use query::{
+        exec::Executor,
+        frontend::sql::{ChunkSelection, SQLQueryPlanner},
+        test::TestLPWriter,
+        Database, PartitionChunk,
+    };
+    use read_buffer::Database as ReadBufferDb;
+
+    fn paths() -> (ObjectStorePath, ObjectStorePath) {
+        crate::snapshot::snapshot_paths("warm")
+    }
+
+    /// Writes `lp` to a database and snapshots each of its partitions
+    async fn snapshot(store: &Arc<ObjectStore>, lp: &str) {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y".to_string())],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let server = crate::Server::new(crate::ConnectionManagerImpl {}, Arc::clone(store));
+        server.set_id(1);
+        server.create_database("warm", rules).await.unwrap();
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        server.write_lines("warm", &lines).await.unwrap();
+
+        let db = server
+            .db(&DatabaseName::new("warm").unwrap())
+            .await
+            .unwrap();
+        let (metadata_path, data_path) = paths();
+        for partition_key in db.partition_keys().await.unwrap() {
+            let chunk = db.rollover_partition(&partition_key).await.unwrap();
+            let mut data_path = data_path.clone();
+            data_path.push_dir(&partition_key);
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            snapshot_chunk(
+                metadata_path.clone(),
+                data_path,
+                Arc::clone(store),
+                &partition_key,
+                chunk,
+                &ParquetConfig::default(),
+                &TagOrders::default(),
+                Some(tx),
+            )
+            .unwrap();
+            rx.await.unwrap();
+        }
+    }
+
+    /// A read replica: a database without a mutable buffer
+    fn replica() -> Db {
+        Db::new(DatabaseRules::default(), None, ReadBufferDb::new(), None)
+    }
+
+    #[tokio::test]
+    async fn warm_loads_snapshots_into_read_buffer() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        snapshot(
+            &store,
+            "cpu,host=a usage=1 10\ncpu,host=b usage=2 20\nmem,host=a free=1i 20",
+        )
+        .await;
+
+        let db = replica();
+        let (metadata_path, data_path) = paths();
+        let summary = db
+            .warm(
+                &store,
+                &metadata_path,
+                &data_path,
+                None,
+                &["cpu".to_string()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            WarmSummary {
+                partitions: 1,
+                tables: 1,
+                tables_already_loaded: 0,
+                partitions_reloaded: 0,
+                partitions_unsupported: 0,
+                rows: 2,
+            }
+        );
+
+        let partition_keys = db.partition_keys().await.unwrap();
+        let chunks = db.chunks(&partition_keys[0]).await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].id(), SNAPSHOT_CHUNK_ID);
+
+        let planner = SQLQueryPlanner::default();
+        let executor = Executor::new();
+        let physical_plan = planner
+            .query(&db, "select host, usage from cpu order by host", &executor)
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+------+-------+",
+            "| host | usage |",
+            "+------+-------+",
+            "| a    | 1     |",
+            "| b    | 2     |",
+            "+------+-------+",
+        ];
+        arrow_deps::assert_table_eq!(expected, &batches);
+
+        // warming again only loads what wasn't loaded yet
+        let summary = db
+            .warm(&store, &metadata_path, &data_path, None, &[])
+            .await
+            .unwrap();
+        assert_eq!(summary.tables, 1);
+        assert_eq!(summary.tables_already_loaded, 1);
+    }
+
+    #[tokio::test]
+    async fn warm_reloads_partitions_snapshotted_again() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        snapshot(&store, "cpu,host=a usage=1 10\nmem,host=a free=1i 20").await;
+
+        let db = replica();
+        let (metadata_path, data_path) = paths();
+        db.warm(
+            &store,
+            &metadata_path,
+            &data_path,
+            None,
+            &["cpu".to_string()],
+        )
+        .await
+        .unwrap();
+
+        // a newer snapshot of the partition replaces the one loaded
+        snapshot(
+            &store,
+            "cpu,host=a usage=1 10\ncpu,host=b usage=2 30\nmem,host=a free=1i 20",
+        )
+        .await;
+        let summary = db
+            .warm(
+                &store,
+                &metadata_path,
+                &data_path,
+                None,
+                &["mem".to_string()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(summary.partitions_reloaded, 1);
+        assert_eq!(summary.tables_already_loaded, 0);
+        // cpu was loaded from the previous snapshot, so it is loaded again
+        assert_eq!(summary.tables, 2);
+        assert_eq!(summary.rows, 3);
+
+        let batches = run_query(
+            &db,
+            SQLQueryPlanner::default(),
+            "select host, usage from cpu order by host",
+        )
+        .await;
+        let expected = vec![
+            "+------+-------+",
+            "| host | usage |",
+            "+------+-------+",
+            "| a    | 1     |",
+            "| b    | 2     |",
+            "+------+-------+",
+        ];
+        arrow_deps::assert_table_eq!(expected, &batches);
+
+        // the same snapshot isn't loaded twice
+        let summary = db
+            .warm(&store, &metadata_path, &data_path, None, &[])
+            .await
+            .unwrap();
+        assert_eq!(summary.partitions_reloaded, 0);
+        assert_eq!(summary.tables, 0);
+        assert_eq!(summary.tables_already_loaded, 2);
+    }
+
+    async fn run_query(db: &Db, planner: SQLQueryPlanner, query: &str) -> Vec<RecordBatch> {
+        let executor = Executor::new();
+        let physical_plan = planner.query(db, query, &executor).await.unwrap();
+        collect(physical_plan).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn queries_select_persisted_or_in_memory_chunks() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        snapshot(&store, "cpu,host=a usage=1 10").await;
+
+        let db = Db::new(
+            DatabaseRules::default(),
+            Some(MutableBufferDb::new("warm")),
+            ReadBufferDb::new(),
+            None,
+        );
+        let (metadata_path, data_path) = paths();
+        db.warm(&store, &metadata_path, &data_path, None, &[])
+            .await
+            .unwrap();
+        TestLPWriter::default()
+            .write_lp_string(&db, "cpu,host=b usage=2 20")
+            .await
+            .unwrap();
+
+        let query = "select host from cpu order by host";
+
+        let expected = vec![
+            "+------+", "| host |", "+------+", "| a    |", "| b    |", "+------+",
+        ];
+        let batches = run_query(&db, SQLQueryPlanner::default(), query).await;
+        arrow_deps::assert_table_eq!(expected, &batches);
+
+        let expected = vec!["+------+", "| host |", "+------+", "| b    |", "+------+"];
+        let planner = SQLQueryPlanner::default().with_chunk_selection(ChunkSelection::InMemory);
+        let batches = run_query(&db, planner, query).await;
+        arrow_deps::assert_table_eq!(expected, &batches);
+
+        // a hint in the query overrides the planner's selection
+        let expected = vec!["+------+", "| host |", "+------+", "| a    |", "+------+"];
+        let planner = SQLQueryPlanner::default().with_chunk_selection(ChunkSelection::InMemory);
+        let batches = run_query(&db, planner, "/*+ persisted */ select host from cpu").await;
+        arrow_deps::assert_table_eq!(expected, &batches);
+
+        // a table with no persisted data has no rows, rather than failing
+        TestLPWriter::default()
+            .write_lp_string(&db, "mem,host=b free=1i 20")
+            .await
+            .unwrap();
+        let batches = run_query(
+            &db,
+            SQLQueryPlanner::default(),
+            "/*+ persisted */ select * from mem",
+        )
+        .await;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+
+        // chunks still in memory are persisted once they are snapshotted
+        let partition_key = "1970-01-01T00";
+        let chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.mark_chunk_snapshotted(partition_key, chunk.id());
+        let expected = vec![
+            "+------+", "| host |", "+------+", "| a    |", "| b    |", "+------+",
+        ];
+        let planner = SQLQueryPlanner::default().with_chunk_selection(ChunkSelection::Persisted);
+        let batches = run_query(&db, planner, query).await;
+        arrow_deps::assert_table_eq!(expected, &batches);
+    }
+
+    /// Replaces the metadata of the snapshot of partition 1970 with the
+    /// result of `update`, as a newer or older version of IOx could write it
+    async fn rewrite_metadata(
+        store: &ObjectStore,
+        update: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>),
+    ) {
+        let (mut path, _) = paths();
+        path.set_file_name("1970.json");
+        let mut meta: serde_json::Value =
+            serde_json::from_slice(&get_bytes(store, &path).await.unwrap()).unwrap();
+        update(meta.as_object_mut().unwrap());
+
+        let data = bytes::Bytes::from(serde_json::to_vec(&meta).unwrap());
+        let len = data.len();
+        store
+            .put(&path, futures::stream::once(async move { Ok(data) }), len)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn warm_checks_format_versions() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        snapshot(&store, "cpu,host=a usage=1 10").await;
+        let (metadata_path, data_path) = paths();
+
+        // a major version this version can't read is skipped
+        rewrite_metadata(&store, |meta| {
+            meta.insert(
+                "format_version".to_string(),
+                serde_json::json!({"major": 2, "minor": 0}),
+            );
+        })
+        .await;
+        let db = replica();
+        let summary = db
+            .warm(&store, &metadata_path, &data_path, None, &[])
+            .await
+            .unwrap();
+        assert_eq!(summary.partitions_unsupported, 1);
+        assert_eq!(summary.tables, 0);
+
+        // what a newer minor version added is ignored
+        rewrite_metadata(&store, |meta| {
+            meta.insert(
+                "format_version".to_string(),
+                serde_json::json!({"major": 1, "minor": 9}),
+            );
+            meta.insert("added".to_string(), serde_json::json!({"a": 1}));
+        })
+        .await;
+        let summary = db
+            .warm(&store, &metadata_path, &data_path, None, &[])
+            .await
+            .unwrap();
+        assert_eq!(summary.partitions_unsupported, 0);
+        assert_eq!(summary.rows, 1);
+
+        // metadata written before versions were recorded is read as the
+        // initial version
+        rewrite_metadata(&store, |meta| {
+            meta.remove("format_version");
+        })
+        .await;
+        let db = replica();
+        let summary = db
+            .warm(&store, &metadata_path, &data_path, None, &[])
+            .await
+            .unwrap();
+        assert_eq!(summary.rows, 1);
+    }
+
+    #[test]
+    fn read_parquet_strips_format_version() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "usage",
+            DataType::Float64,
+            false,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Float64Array::from(vec![1.0])) as ArrayRef],
+        )
+        .unwrap();
+
+        let data = crate::snapshot::parquet_bytes(vec![batch], &ParquetConfig::default()).unwrap();
+        let batches = read_parquet(data, "current.parquet").unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+        // the version isn't part of the metadata of the schema
+        assert!(batches[0]
+            .schema()
+            .metadata()
+            .get(FORMAT_VERSION_METADATA_KEY)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn warm_skips_tables_outside_range() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        snapshot(&store, "cpu,host=a usage=1 10\nmem,host=a free=1i 100").await;
+
+        let db = replica();
+        let (metadata_path, data_path) = paths();
+        let range = TimestampRange::new(50, 200);
+        let summary = db
+            .warm(&store, &metadata_path, &data_path, Some(range), &[])
+            .await
+            .unwrap();
+        assert_eq!(summary.tables, 1);
+        assert_eq!(summary.rows, 1);
+    }
+}
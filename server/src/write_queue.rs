@@ -0,0 +1,172 @@
+//! This module contains the queues of the writes of the databases of a
+//! server, which let clients pipeline batches of lines: each batch is
+//! checked and enqueued at once, and the handle returned for it resolves
+//! once the batch has reached the requested acknowledgement level, so a
+//! client learns the outcome of every batch without waiting for one to be
+//! durable before sending the next.
+//!
+//! The batches of a database are stored and appended to its WAL buffer one
+//! at a time, in the order they were enqueued, by a task started on the
+//! first write. Waiting for the WAL segment of a batch to be persisted and
+//! for its replication is done for each batch on its own, so a batch
+//! doesn't wait for the durability of the batches before it.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+};
+
+use data_types::{data::ReplicatedWrite, DatabaseName};
+use influxdb_line_protocol::ParsedLine;
+use snafu::{OptionExt, ResultExt};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    ack::{WriteAck, WriteAckLevel},
+    db::Db,
+    ConnectionManager, InvalidDatabaseName, Result, Server, WriteAbandoned,
+};
+
+/// The number of writes that can wait in the queue of a database before
+/// enqueuing more waits for room
+pub const WRITE_QUEUE_CAPACITY: usize = 1024;
+
+/// A write waiting to be stored
+#[derive(Debug)]
+struct QueuedWrite {
+    write: ReplicatedWrite,
+    ack: WriteAckLevel,
+    done: oneshot::Sender<Result<WriteAck>>,
+}
+
+/// The queue of the writes of a database, which is started by the first
+/// write enqueued
+#[derive(Debug, Default)]
+pub struct WriteQueue {
+    sender: Mutex<Option<mpsc::Sender<QueuedWrite>>>,
+}
+
+impl WriteQueue {
+    /// Returns a sender for the queue, calling `start` with its receiving
+    /// end if the queue wasn't started yet
+    fn sender(&self, start: impl FnOnce(mpsc::Receiver<QueuedWrite>)) -> mpsc::Sender<QueuedWrite> {
+        let mut sender = self.sender.lock().expect("mutex poisoned");
+        sender
+            .get_or_insert_with(|| {
+                let (sender, receiver) = mpsc::channel(WRITE_QUEUE_CAPACITY);
+                start(receiver);
+                sender
+            })
+            .clone()
+    }
+}
+
+/// Resolves to how durable the write of a batch enqueued by
+/// `Server::write_lines_async` was when it reached the acknowledgement
+/// level it was enqueued with
+#[derive(Debug)]
+pub struct WriteHandle {
+    db_name: String,
+    done: oneshot::Receiver<Result<WriteAck>>,
+}
+
+impl Future for WriteHandle {
+    type Output = Result<WriteAck>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = match Pin::new(&mut self.done).poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        Poll::Ready(result.unwrap_or_else(|_| {
+            WriteAbandoned {
+                db_name: &self.db_name,
+            }
+            .fail()
+        }))
+    }
+}
+
+impl<M> Server<M>
+where
+    M: ConnectionManager + Send + Sync + 'static,
+{
+    /// Checks `lines` like `write_lines_with_ack` and enqueues their write
+    /// to the database `db_name`, waiting while its queue is full. Returns
+    /// a handle that resolves once the write has reached the
+    /// acknowledgement level `ack`. The writes of a database are stored in
+    /// the order they were enqueued.
+    ///
+    /// The quotas of the database are charged for the write once it is
+    /// enqueued.
+    pub async fn write_lines_async(
+        self: &Arc<Self>,
+        db_name: &str,
+        lines: &[ParsedLine<'_>],
+        ack: WriteAckLevel,
+    ) -> Result<WriteHandle> {
+        let db_name = DatabaseName::new(db_name.to_string()).context(InvalidDatabaseName)?;
+        let db = self.require_db(&db_name)?;
+
+        let (write, quota_charge) = self.prepare_write(&db_name, &db, lines).await?;
+
+        let mut sender = db.write_queue.sender(|receiver| {
+            tokio::task::spawn(run_write_queue(
+                Arc::downgrade(self),
+                Arc::downgrade(&db),
+                db_name.clone(),
+                receiver,
+            ));
+        });
+        let (done, receiver) = oneshot::channel();
+        sender
+            .send(QueuedWrite { write, ack, done })
+            .await
+            .ok()
+            .context(WriteAbandoned {
+                db_name: db_name.as_str(),
+            })?;
+        quota_charge.commit();
+
+        Ok(WriteHandle {
+            db_name: db_name.to_string(),
+            done: receiver,
+        })
+    }
+}
+
+/// Stores the writes of `queue` in `db` one at a time, until the server or
+/// the database is dropped, and acknowledges each of them in its own task
+async fn run_write_queue<M>(
+    server: Weak<Server<M>>,
+    db: Weak<Db>,
+    db_name: DatabaseName<'static>,
+    mut queue: mpsc::Receiver<QueuedWrite>,
+) where
+    M: ConnectionManager + Send + Sync + 'static,
+{
+    while let Some(QueuedWrite { write, ack, done }) = queue.recv().await {
+        let (server, db) = match (server.upgrade(), db.upgrade()) {
+            (Some(server), Some(db)) => (server, db),
+            _ => return,
+        };
+
+        match server.store_write(&db_name, &db, write, ack).await {
+            Ok(stored) => {
+                let db_name = db_name.clone();
+                tokio::task::spawn(async move {
+                    let result = server.acknowledge_write(&db_name, &db, stored, ack).await;
+
+                    // the handle may have been dropped by a client not
+                    // interested in the outcome
+                    let _ = done.send(result);
+                });
+            }
+            Err(e) => {
+                let _ = done.send(Err(e));
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Stops a server at each of the fail points of the write, WAL and
+//! snapshot paths, as if its process had been killed there, then restarts
+//! it from what reached object storage and checks that:
+//!
+//! * no acknowledged write is lost: a write counts as acknowledged once
+//!   `write_lines` returned and the WAL segment holding it was persisted
+//! * no write is half visible: either all of its rows are recovered or
+//!   none are
+//! * a snapshot's metadata is only written once all of its tables are
+//!
+//! Restarting loads the database configurations like a server starting
+//! does, which replays the persisted WAL segments of each database before
+//! serving it.
+//!
+//! These tests need the `failpoints` feature:
+//!
+//! ```shell
+//! cargo test -p server --features failpoints --test crash_consistency
+//! ```
+#![cfg(feature = "failpoints")]
+
+use std::{collections::BTreeSet, sync::Arc};
+
+use arrow_deps::{
+    arrow::array::{Array, StringArray},
+    datafusion::physical_plan::collect,
+};
+use data_types::{
+    database_rules::{
+        DatabaseRules, PartitionTemplate, TemplatePart, WalBufferConfig, WalBufferRollover,
+        WalSegmentStorage,
+    },
+    partition_metadata::Partition as PartitionMeta,
+    DatabaseName,
+};
+use fail::FailScenario;
+use futures::TryStreamExt;
+use influxdb_line_protocol::parse_lines;
+use object_store::{memory::InMemory, path::ObjectStorePath, ObjectStore};
+use query::{frontend::sql::SQLQueryPlanner, Database};
+use server::{
+    buffer::Segment, db::Db, fail_points, recovery::RecoveryState, snapshot::snapshot_chunk,
+    ConnectionManagerImpl, Server,
+};
+
+type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+const WRITER_ID: u32 = 1;
+const DB_NAME: &str = "crash";
+
+/// The writes of each run, in order. The first is acknowledged before any
+/// fail point is configured, so both tables always exist after a restart.
+const WRITES: &[&str] = &[
+    "cpu,dc=east,host=a usage=0.1 10\nmem,dc=east,host=a free=1i 10",
+    "cpu,dc=east,host=b usage=0.2 20\ncpu,dc=east,host=c usage=0.3 30",
+    "cpu,dc=east,host=d usage=0.4 40\nmem,dc=east,host=d free=4i 40",
+];
+
+/// Crash the first time a fail point is reached, or the second time
+const ACTIONS: &[&str] = &["panic", "1*off->panic"];
+
+#[tokio::test]
+async fn no_acknowledged_write_is_lost() -> Result {
+    let scenario = FailScenario::setup();
+
+    for name in fail_points::ALL {
+        for action in ACTIONS {
+            crash_and_restart(name, action)
+                .await
+                .map_err(|e| format!("fail point {} ({}): {}", name, action, e))?;
+        }
+    }
+
+    scenario.teardown();
+    Ok(())
+}
+
+/// Runs the writes and a snapshot with the fail point `name` configured
+/// with `action`, restarts the server and checks what it recovered
+async fn crash_and_restart(name: &str, action: &str) -> Result {
+    let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+    let db_name = DatabaseName::new(DB_NAME)?;
+
+    let server = Arc::new(Server::new(ConnectionManagerImpl {}, Arc::clone(&store)));
+    server.set_id(WRITER_ID);
+    let rules = DatabaseRules {
+        partition_template: PartitionTemplate {
+            parts: vec![TemplatePart::Column("dc".to_string())],
+            ..Default::default()
+        },
+        wal_buffer_config: Some(WalBufferConfig {
+            buffer_size: 1_000_000,
+            // close a segment after every write
+            segment_size: 0,
+            buffer_rollover: WalBufferRollover::ReturnError,
+            store_segments: true,
+            close_segment_after: None,
+            segment_storage: WalSegmentStorage::ObjectStore,
+            skip_invalid_writes: false,
+        }),
+        ..Default::default()
+    };
+    server.create_database(DB_NAME, rules).await?;
+
+    let mut acknowledged = vec![];
+    let mut crashed = false;
+    for (i, &lp) in WRITES.iter().enumerate() {
+        if i == 1 {
+            fail::cfg(name, action)?;
+        }
+
+        // a fail point panics the task writing, as if the process died
+        let writer = Arc::clone(&server);
+        let written = tokio::spawn(async move {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            writer.write_lines(DB_NAME, &lines).await
+        })
+        .await;
+        match written {
+            Ok(result) => result?,
+            Err(_) => {
+                crashed = true;
+                break;
+            }
+        }
+
+        // every write closes a segment, which is persisted in the background
+        let db = server.db(&db_name).await.expect("database exists");
+        let segment = db
+            .wal_buffer
+            .as_ref()
+            .and_then(|wal_buffer| {
+                let wal_buffer = wal_buffer.lock().expect("mutex poisoned");
+                wal_buffer.closed_segments().last().cloned()
+            })
+            .expect("the write closed a segment");
+        if segment_persisted(segment).await {
+            acknowledged.push(i);
+        }
+    }
+
+    if !crashed {
+        let db = server.db(&db_name).await.expect("database exists");
+        let partition_keys = db.partition_keys().await?;
+        let chunk = db.rollover_partition(&partition_keys[0]).await?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        snapshot_chunk(
+            db_path(&["meta"]),
+            db_path(&["data"]),
+            Arc::clone(&store),
+            &partition_keys[0],
+            chunk,
+            &db.rules.parquet_config,
+            &db.rules.tag_orders,
+            Some(tx),
+        )?;
+        // the sender is dropped without sending if the snapshot panics
+        let _ = rx.await;
+    }
+
+    fail::remove(name);
+    drop(server);
+
+    // restart from object storage
+    let server = Server::new(ConnectionManagerImpl {}, Arc::clone(&store));
+    server.set_id(WRITER_ID);
+    server.load_database_configs().await?;
+    assert_eq!(
+        server.recovery_states().get(DB_NAME),
+        Some(&RecoveryState::Ready)
+    );
+    let db = server.db(&db_name).await.expect("database recovered");
+
+    let recovered: BTreeSet<_> = rows(&server, &db, "cpu")
+        .await?
+        .into_iter()
+        .map(|host| ("cpu", host))
+        .chain(
+            rows(&server, &db, "mem")
+                .await?
+                .into_iter()
+                .map(|host| ("mem", host)),
+        )
+        .collect();
+
+    for (i, lp) in WRITES.iter().enumerate() {
+        let write_rows: BTreeSet<_> = lp
+            .lines()
+            .map(|line| {
+                let table = if line.starts_with("cpu") {
+                    "cpu"
+                } else {
+                    "mem"
+                };
+                let host = line.split(&[',', ' '][..]).nth(2).unwrap();
+                (table, host.trim_start_matches("host=").to_string())
+            })
+            .collect();
+        let visible = write_rows.intersection(&recovered).count();
+
+        if acknowledged.contains(&i) {
+            assert_eq!(
+                visible,
+                write_rows.len(),
+                "acknowledged write {} was lost",
+                i
+            );
+        } else {
+            assert!(
+                visible == 0 || visible == write_rows.len(),
+                "write {} is half visible: {:?}",
+                i,
+                recovered
+            );
+        }
+    }
+
+    check_snapshot(&store).await
+}
+
+/// Returns the path of `dirs` in the directory of the database
+fn db_path(dirs: &[&str]) -> ObjectStorePath {
+    let mut path = ObjectStorePath::default();
+    path.push_all_dirs(&[&WRITER_ID.to_string(), DB_NAME]);
+    path.push_all_dirs(dirs);
+    path
+}
+
+async fn list_paths(store: &ObjectStore, prefix: &ObjectStorePath) -> Result<Vec<ObjectStorePath>> {
+    let paths: Vec<Vec<_>> = store.list(Some(prefix)).await?.try_collect().await?;
+    let mut paths: Vec<_> = paths.into_iter().flatten().collect();
+    paths.sort_by_key(|path| store.convert_path(path));
+    Ok(paths)
+}
+
+async fn get_bytes(store: &ObjectStore, path: &ObjectStorePath) -> Result<Vec<u8>> {
+    Ok(store
+        .get(path)
+        .await?
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await?)
+}
+
+/// Waits for the background task persisting `segment` to finish,
+/// returning whether it persisted the segment. The task holds a reference
+/// to the segment until it finishes, whether it succeeds or panics at a
+/// fail point, and the WAL buffer holds another.
+async fn segment_persisted(segment: Arc<Segment>) -> bool {
+    while segment.persisted_at().is_none() && Arc::strong_count(&segment) > 2 {
+        tokio::task::yield_now().await;
+    }
+    segment.persisted_at().is_some()
+}
+
+/// Returns the hosts of the rows of `table`
+async fn rows(server: &Server<ConnectionManagerImpl>, db: &Db, table: &str) -> Result<Vec<String>> {
+    let planner = SQLQueryPlanner::default();
+    let physical_plan = planner
+        .query(
+            db,
+            &format!("select host from {}", table),
+            server.executor().as_ref(),
+        )
+        .await?;
+
+    let mut hosts = vec![];
+    for batch in collect(physical_plan).await? {
+        let column = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("host is a string column");
+        hosts.extend((0..column.len()).map(|i| column.value(i).to_string()));
+    }
+    Ok(hosts)
+}
+
+/// Checks that if the metadata of the snapshot was written, the Parquet
+/// files of all of its tables were too
+async fn check_snapshot(store: &ObjectStore) -> Result {
+    let data_files: Vec<_> = list_paths(store, &db_path(&["data"]))
+        .await?
+        .iter()
+        .map(|path| store.convert_path(path))
+        .collect();
+
+    for path in list_paths(store, &db_path(&["meta"])).await? {
+        let meta: PartitionMeta = serde_json::from_slice(&get_bytes(store, &path).await?)?;
+        for table in &meta.tables {
+            let file = format!("{}/{}/data/{}.parquet", WRITER_ID, DB_NAME, table.name);
+            assert!(
+                data_files.contains(&file),
+                "snapshot metadata written without {}: {:?}",
+                file,
+                data_files
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Property tests that generate line protocol, write it through the WAL
+//! segment format and replay it into a mutable buffer, checking that what
+//! comes back out via `table_to_arrow` is exactly what went in.
+
+use arrow_deps::arrow::{
+    array::{Array, BooleanArray, Float64Array, Int64Array, StringArray},
+    record_batch::RecordBatch,
+};
+use data_types::{
+    data::lines_to_replicated_write,
+    database_rules::{DatabaseRules, WalBufferRollover},
+};
+use influxdb_line_protocol::{escape, parse_lines, LinePart};
+use mutable_buffer::MutableBufferDb;
+use proptest::prelude::*;
+use query::Database;
+use server::buffer::{Buffer, Segment};
+
+const WRITER_ID: u32 = 1;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Tag,
+    F64,
+    I64,
+    Bool,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Tag(String),
+    F64(f64),
+    I64(i64),
+    Bool(bool),
+    String(String),
+}
+
+/// A single table worth of rows. `values[row][col]` is the value of
+/// `columns[col]` in that row, or `None` if the line omits it.
+#[derive(Debug, Clone)]
+struct Table {
+    measurement: String,
+    columns: Vec<(String, Kind)>,
+    values: Vec<Vec<Option<Value>>>,
+    times: Vec<i64>,
+}
+
+impl Table {
+    fn to_line_protocol(&self) -> String {
+        let mut lp = String::new();
+
+        for (row, time) in self.values.iter().zip(&self.times) {
+            lp.push_str(&escape(&self.measurement, LinePart::Measurement));
+
+            for ((name, _), value) in self.columns.iter().zip(row) {
+                if let Some(Value::Tag(v)) = value {
+                    lp.push(',');
+                    lp.push_str(&escape(name, LinePart::TagKey));
+                    lp.push('=');
+                    lp.push_str(&escape(v, LinePart::TagValue));
+                }
+            }
+
+            let fields: Vec<_> = self
+                .columns
+                .iter()
+                .zip(row)
+                .filter_map(|((name, _), value)| {
+                    let value = match value.as_ref()? {
+                        Value::Tag(_) => return None,
+                        Value::F64(v) => v.to_string(),
+                        Value::I64(v) => format!("{}i", v),
+                        Value::Bool(v) => v.to_string(),
+                        Value::String(v) => {
+                            format!("\"{}\"", escape(v, LinePart::FieldStringValue))
+                        }
+                    };
+                    Some(format!("{}={}", escape(name, LinePart::FieldKey), value))
+                })
+                .collect();
+
+            lp.push(' ');
+            lp.push_str(&fields.join(","));
+            lp.push_str(&format!(" {}\n", time));
+        }
+
+        lp
+    }
+}
+
+/// Measurement, tag and field names: any printable unicode, including
+/// characters that need escaping. The parser rejects identifiers ending in
+/// a backslash even when it is escaped, a leading `#` on the measurement
+/// makes the line a comment, and `time` is reserved for the timestamp.
+fn name() -> impl Strategy<Value = String> {
+    "\\PC{1,8}".prop_filter("unrepresentable name", |s| {
+        !s.starts_with('#') && !s.ends_with('\\') && s != "time"
+    })
+}
+
+fn kind() -> impl Strategy<Value = Kind> {
+    prop_oneof![
+        Just(Kind::Tag),
+        Just(Kind::F64),
+        Just(Kind::I64),
+        Just(Kind::Bool),
+        Just(Kind::String),
+    ]
+}
+
+fn value(kind: Kind) -> BoxedStrategy<Value> {
+    use proptest::num::f64::{NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO};
+
+    match kind {
+        Kind::Tag => name().prop_map(Value::Tag).boxed(),
+        Kind::F64 => (POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO)
+            .prop_map(Value::F64)
+            .boxed(),
+        Kind::I64 => any::<i64>().prop_map(Value::I64).boxed(),
+        Kind::Bool => any::<bool>().prop_map(Value::Bool).boxed(),
+        Kind::String => "\\PC*".prop_map(Value::String).boxed(),
+    }
+}
+
+fn table() -> impl Strategy<Value = Table> {
+    let columns = (
+        prop::collection::btree_set(name(), 1..6),
+        prop::collection::vec(kind(), 6),
+    )
+        .prop_map(|(names, mut kinds)| {
+            // every line needs at least one field
+            if kinds
+                .iter()
+                .take(names.len())
+                .all(|k| matches!(k, Kind::Tag))
+            {
+                kinds[0] = Kind::F64;
+            }
+            names.into_iter().zip(kinds).collect::<Vec<_>>()
+        });
+
+    (name(), columns).prop_flat_map(|(measurement, columns)| {
+        let row: Vec<_> = columns
+            .iter()
+            .map(|&(_, kind)| prop::option::of(value(kind)))
+            .collect();
+        let first_field = columns
+            .iter()
+            .position(|(_, kind)| !matches!(kind, Kind::Tag))
+            .expect("at least one field column");
+        let fallback = value(columns[first_field].1);
+
+        let rows = prop::collection::vec((row, fallback, any::<i64>()), 1..20);
+
+        (Just(measurement), Just(columns), rows).prop_map(move |(measurement, columns, rows)| {
+            let mut values = Vec::with_capacity(rows.len());
+            let mut times = Vec::with_capacity(rows.len());
+
+            for (mut row, fallback, time) in rows {
+                let has_field = row
+                    .iter()
+                    .any(|v| matches!(v, Some(v) if !matches!(v, Value::Tag(_))));
+                if !has_field {
+                    row[first_field] = Some(fallback);
+                }
+                values.push(row);
+                times.push(time);
+            }
+
+            Table {
+                measurement,
+                columns,
+                values,
+                times,
+            }
+        })
+    })
+}
+
+/// Writes `lp` into a WAL segment, round trips the segment through its
+/// file format and replays every write into a fresh mutable buffer
+fn write_and_replay(lp: &str) -> MutableBufferDb {
+    let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+    let write = lines_to_replicated_write(WRITER_ID, 1, &lines, &DatabaseRules::default());
+
+    // a segment size of zero closes the segment on the first append
+    let mut buffer = Buffer::new(u64::MAX, 0, WalBufferRollover::ReturnError, false);
+    let segment = buffer
+        .append(write.into())
+        .unwrap()
+        .expect("segment closed");
+
+    let bytes = segment.to_file_bytes(WRITER_ID).unwrap();
+    let segment = Segment::from_file_bytes(&bytes).unwrap();
+
+    let db = MutableBufferDb::new("roundtrip");
+    futures::executor::block_on(async {
+        for write in &segment.writes {
+            db.store_replicated_write(write).await.unwrap();
+        }
+    });
+    db
+}
+
+fn read_table(db: &MutableBufferDb, table: &Table) -> (Vec<Vec<Option<Value>>>, Vec<i64>) {
+    // columns that never have a value are never created
+    let present: Vec<_> = (0..table.columns.len())
+        .filter(|&col| table.values.iter().any(|row| row[col].is_some()))
+        .collect();
+    let mut names: Vec<_> = present
+        .iter()
+        .map(|&col| table.columns[col].0.as_str())
+        .collect();
+    names.push("time");
+
+    let chunk = futures::executor::block_on(db.get_chunk("", 0)).expect("chunk exists");
+    let mut batches = Vec::new();
+    chunk
+        .table_to_arrow(&mut batches, &table.measurement, &names)
+        .unwrap();
+    assert_eq!(batches.len(), 1);
+    let batch: &RecordBatch = &batches[0];
+
+    let num_rows = batch.num_rows();
+    let mut values = vec![vec![None; table.columns.len()]; num_rows];
+    for (i, &col) in present.iter().enumerate() {
+        let array = batch.column(i).as_ref();
+        for (row, values) in values.iter_mut().enumerate() {
+            if array.is_null(row) {
+                continue;
+            }
+            values[col] = Some(match table.columns[col].1 {
+                Kind::Tag => Value::Tag(as_array::<StringArray>(array).value(row).to_string()),
+                Kind::F64 => Value::F64(as_array::<Float64Array>(array).value(row)),
+                Kind::I64 => Value::I64(as_array::<Int64Array>(array).value(row)),
+                Kind::Bool => Value::Bool(as_array::<BooleanArray>(array).value(row)),
+                Kind::String => {
+                    Value::String(as_array::<StringArray>(array).value(row).to_string())
+                }
+            });
+        }
+    }
+
+    let time = as_array::<Int64Array>(batch.column(present.len()).as_ref());
+    let times = (0..num_rows).map(|row| time.value(row)).collect();
+
+    (values, times)
+}
+
+fn as_array<T: 'static>(array: &dyn Array) -> &T {
+    array
+        .as_any()
+        .downcast_ref::<T>()
+        .expect("unexpected array type")
+}
+
+proptest! {
+    #[test]
+    fn line_protocol_round_trips(table in table()) {
+        let lp = table.to_line_protocol();
+        let db = write_and_replay(&lp);
+
+        let (values, times) = read_table(&db, &table);
+
+        prop_assert_eq!(values, table.values, "line protocol:\n{}", lp);
+        prop_assert_eq!(times, table.times, "line protocol:\n{}", lp);
+    }
+}
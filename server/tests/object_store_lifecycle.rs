@@ -0,0 +1,236 @@
+//! Runs the lifecycle of a database against a real object store: the
+//! shared object store tests, then writing, querying, persisting WAL
+//! segments and snapshots, and loading the database on another server.
+//! Providers differ in how they list, overwrite and delete objects, which
+//! the in-memory store used by the unit tests can't show.
+//!
+//! These tests only run with the `object_store_integration` feature, and
+//! skip themselves unless the store is configured in the environment (or a
+//! `.env` file):
+//!
+//! * `IOX_TEST_OBJECT_STORE`: one of `s3`, `gcs` or `azure`
+//! * for `s3`: `AWS_DEFAULT_REGION` and `AWS_S3_BUCKET_NAME`, plus
+//!   `AWS_ENDPOINT` to use an S3 compatible store such as MinIO
+//! * for `gcs`: `GCS_BUCKET_NAME`
+//! * for `azure`: `AZURE_STORAGE_ACCOUNT`, `AZURE_STORAGE_MASTER_KEY` and
+//!   `AZURE_STORAGE_CONTAINER`
+//!
+//! If `TEST_INTEGRATION` is set, a missing configuration fails the tests
+//! instead. The bucket must be empty: the shared tests list all of it.
+#![cfg(feature = "object_store_integration")]
+
+use std::{env, sync::Arc};
+
+use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
+use data_types::{
+    database_rules::{
+        DatabaseRules, PartitionTemplate, TemplatePart, WalBufferConfig, WalBufferRollover,
+        WalSegmentStorage,
+    },
+    DatabaseName,
+};
+use futures::TryStreamExt;
+use influxdb_line_protocol::{parse_lines, ParsedLine};
+use object_store::{
+    aws::AmazonS3,
+    azure::MicrosoftAzure,
+    gcp::GoogleCloudStorage,
+    path::ObjectStorePath,
+    tests::{list_with_delimiter, put_get_delete_list},
+    ObjectStore,
+};
+use query::{frontend::sql::SQLQueryPlanner, DatabaseStore};
+use server::{snapshot::snapshot_chunk, ConnectionManagerImpl, Server};
+
+type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+/// Returns the object store named by `IOX_TEST_OBJECT_STORE`, or None if
+/// the test should be skipped
+fn configured_store() -> Result<Option<ObjectStore>> {
+    dotenv::dotenv().ok();
+
+    let kind = match env::var("IOX_TEST_OBJECT_STORE") {
+        Ok(kind) => kind,
+        Err(_) if env::var("TEST_INTEGRATION").is_ok() => {
+            panic!("TEST_INTEGRATION is set, but IOX_TEST_OBJECT_STORE is not")
+        }
+        Err(_) => {
+            eprintln!("skipping integration test - set IOX_TEST_OBJECT_STORE to run");
+            return Ok(None);
+        }
+    };
+    let var = |name: &str| {
+        env::var(name)
+            .map_err(|_| format!("IOX_TEST_OBJECT_STORE is {}, but {} is not set", kind, name))
+    };
+
+    let store = match kind.as_str() {
+        "s3" => {
+            let bucket_name = var("AWS_S3_BUCKET_NAME")?;
+            let region = var("AWS_DEFAULT_REGION")?;
+            let region = match env::var("AWS_ENDPOINT") {
+                Ok(endpoint) => rusoto_core::Region::Custom {
+                    name: region,
+                    endpoint,
+                },
+                Err(_) => region.parse()?,
+            };
+            ObjectStore::new_amazon_s3(AmazonS3::new(region, bucket_name))
+        }
+        "gcs" => {
+            ObjectStore::new_google_cloud_storage(GoogleCloudStorage::new(var("GCS_BUCKET_NAME")?))
+        }
+        "azure" => ObjectStore::new_microsoft_azure(MicrosoftAzure::new_from_env(var(
+            "AZURE_STORAGE_CONTAINER",
+        )?)),
+        other => return Err(format!("unknown IOX_TEST_OBJECT_STORE {:?}", other).into()),
+    };
+
+    Ok(Some(store))
+}
+
+fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
+    parse_lines(lp).map(|l| l.unwrap()).collect()
+}
+
+async fn list_paths(store: &ObjectStore, prefix: &ObjectStorePath) -> Result<Vec<String>> {
+    let paths: Vec<Vec<_>> = store.list(Some(prefix)).await?.try_collect().await?;
+    let mut paths: Vec<_> = paths
+        .into_iter()
+        .flatten()
+        .map(|path| store.convert_path(&path))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+#[tokio::test]
+async fn shared_object_store_tests() -> Result {
+    let store = match configured_store()? {
+        Some(store) => store,
+        None => return Ok(()),
+    };
+
+    put_get_delete_list(&store).await?;
+    list_with_delimiter(&store).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn write_persist_and_reload() -> Result {
+    let store = match configured_store()? {
+        Some(store) => Arc::new(store),
+        None => return Ok(()),
+    };
+
+    // a writer id of its own keeps the objects of every run apart
+    let writer_id = std::process::id();
+    let mut server_path = ObjectStorePath::default();
+    server_path.push_dir(writer_id.to_string());
+
+    let server = Server::new(ConnectionManagerImpl {}, Arc::clone(&store));
+    server.set_id(writer_id);
+    let db_name = DatabaseName::new("lifecycle")?;
+    let rules = DatabaseRules {
+        name: db_name.to_string(),
+        partition_template: PartitionTemplate {
+            parts: vec![TemplatePart::Table],
+            ..Default::default()
+        },
+        wal_buffer_config: Some(WalBufferConfig {
+            buffer_size: 500,
+            segment_size: 10,
+            buffer_rollover: WalBufferRollover::ReturnError,
+            store_segments: true,
+            close_segment_after: None,
+            segment_storage: WalSegmentStorage::ObjectStore,
+            skip_invalid_writes: false,
+        }),
+        ..Default::default()
+    };
+    server
+        .create_database(db_name.as_str(), rules.clone())
+        .await?;
+
+    let lines = parsed_lines("cpu,host=a usage=0.5 10\ncpu,host=b usage=0.9 20");
+    server.write_lines(db_name.as_str(), &lines).await?;
+
+    let db = server.db(&db_name).await.expect("database exists");
+    let planner = SQLQueryPlanner::default();
+    let physical_plan = planner
+        .query(
+            db.as_ref(),
+            "select host, usage from cpu order by host",
+            server.executor().as_ref(),
+        )
+        .await?;
+    let expected = vec![
+        "+------+-------+",
+        "| host | usage |",
+        "+------+-------+",
+        "| a    | 0.5   |",
+        "| b    | 0.9   |",
+        "+------+-------+",
+    ];
+    assert_table_eq!(expected, &collect(physical_plan).await?);
+
+    // snapshot the partition, laid out like the snapshots of the HTTP API
+    let mut metadata_path = server_path.clone();
+    metadata_path.push_dir(db_name.as_str());
+    let mut data_path = metadata_path.clone();
+    metadata_path.push_dir("meta");
+    data_path.push_all_dirs(&["data", "cpu"]);
+
+    let chunk = db.rollover_partition("cpu").await?;
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    snapshot_chunk(
+        metadata_path,
+        data_path,
+        Arc::clone(&store),
+        "cpu",
+        chunk,
+        &db.rules.parquet_config,
+        &db.rules.tag_orders,
+        Some(tx),
+    )?;
+    rx.await?;
+
+    // the segment is persisted in the background
+    let segment = format!("{}/lifecycle/wal/000/000/001.segment", writer_id);
+    let mut tries = 0;
+    let paths = loop {
+        let paths = list_paths(&store, &server_path).await?;
+        if paths.contains(&segment) || tries == 100 {
+            break paths;
+        }
+        tokio::time::delay_for(tokio::time::Duration::from_millis(100)).await;
+        tries += 1;
+    };
+    let expected: Vec<_> = [
+        "lifecycle/data/cpu/cpu.parquet",
+        "lifecycle/meta/cpu.json",
+        "lifecycle/rules.json",
+        "lifecycle/wal/000/000/001.segment",
+    ]
+    .iter()
+    .map(|path| format!("{}/{}", writer_id, path))
+    .collect();
+    assert_eq!(paths, expected);
+
+    // another server with the same id finds the database in the store
+    let server2 = Server::new(ConnectionManagerImpl {}, Arc::clone(&store));
+    server2.set_id(writer_id);
+    server2.load_database_configs().await?;
+    let loaded = server2.db_rules(&db_name).await.expect("database loaded");
+    assert_eq!(loaded, rules);
+
+    let paths: Vec<Vec<_>> = store.list(Some(&server_path)).await?.try_collect().await?;
+    for path in paths.into_iter().flatten() {
+        store.delete(&path).await?;
+    }
+    assert!(list_paths(&store, &server_path).await?.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,399 @@
+//! Writes to, queries and moves the chunks of a database concurrently for a
+//! while, and checks that:
+//!
+//! * no task panics or returns an error
+//! * no acknowledged write is lost: once `write_lines` returned, the rows
+//!   of the write are returned by every query of their partition, until the
+//!   partition is dropped
+//! * the number of rows returned for a partition never decreases while it
+//!   isn't dropped, and no row is returned twice, even while its chunk is
+//!   both in the mutable buffer and the read buffer
+//!
+//! The writers write to the partition of the current epoch, one at a time.
+//! A lifecycle task repeatedly closes the open chunk of each partition,
+//! snapshots it to object storage, loads it into the read buffer and drops
+//! it from the mutable buffer. At the end of each epoch it drops the
+//! partition of the epoch before last, which may still receive late writes.
+//!
+//! These tests need the `stress` feature, and run for
+//! `IOX_STRESS_SECONDS` seconds (30 by default):
+//!
+//! ```shell
+//! IOX_STRESS_SECONDS=300 cargo test -p server --features stress --test write_query_stress
+//! ```
+#![cfg(feature = "stress")]
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use arrow_deps::{
+    arrow::array::{ArrayRef, StringArray},
+    datafusion::physical_plan::collect,
+};
+use data_types::{
+    database_rules::{DatabaseRules, PartitionTemplate, TemplatePart},
+    DatabaseName,
+};
+use influxdb_line_protocol::parse_lines;
+use object_store::{memory::InMemory, ObjectStore};
+use query::{frontend::sql::SQLQueryPlanner, Database, PartitionChunk};
+use server::{
+    db::Db,
+    snapshot::{snapshot_chunk, snapshot_paths},
+    ConnectionManagerImpl, Server,
+};
+
+type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+const WRITER_ID: u32 = 1;
+const DB_NAME: &str = "stress";
+
+const WRITERS: usize = 4;
+const QUERIERS: usize = 2;
+const ROWS_PER_WRITE: usize = 10;
+
+/// How long the writers write to the partition of an epoch
+const EPOCH_DURATION: Duration = Duration::from_secs(2);
+
+/// How long the lifecycle task waits between moving the chunks of all
+/// partitions
+const LIFECYCLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The default number of seconds the tasks run for
+const DEFAULT_SECONDS: u64 = 30;
+
+/// The state shared by the tasks
+#[derive(Debug, Default)]
+struct State {
+    /// The partition written to, which is advanced by the lifecycle task
+    epoch: AtomicUsize,
+    /// The rows of the acknowledged writes, by partition key. The rows of
+    /// the dropped partitions are forgotten.
+    acknowledged: Mutex<BTreeMap<String, BTreeSet<String>>>,
+    /// The keys of the partitions being, or that were, dropped
+    dropped: Mutex<BTreeSet<String>>,
+    stopped: AtomicBool,
+}
+
+impl State {
+    fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    fn acknowledged(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.acknowledged.lock().expect("mutex poisoned").clone()
+    }
+
+    fn dropped(&self) -> BTreeSet<String> {
+        self.dropped.lock().expect("mutex poisoned").clone()
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn concurrent_writes_queries_and_lifecycle() -> Result {
+    let seconds = std::env::var("IOX_STRESS_SECONDS")
+        .map(|s| {
+            s.parse()
+                .expect("IOX_STRESS_SECONDS is a number of seconds")
+        })
+        .unwrap_or(DEFAULT_SECONDS);
+
+    let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+    let server = Arc::new(Server::new(ConnectionManagerImpl {}, Arc::clone(&store)));
+    server.set_id(WRITER_ID);
+    let rules = DatabaseRules {
+        partition_template: PartitionTemplate {
+            parts: vec![TemplatePart::Column("epoch".to_string())],
+            ..Default::default()
+        },
+        store_locally: true,
+        ..Default::default()
+    };
+    server.create_database(DB_NAME, rules).await?;
+    let db = server
+        .db(&DatabaseName::new(DB_NAME)?)
+        .await
+        .expect("database exists");
+
+    let state = Arc::new(State::default());
+    let mut tasks = vec![];
+    for writer in 0..WRITERS {
+        let server = Arc::clone(&server);
+        let state = Arc::clone(&state);
+        tasks.push(tokio::spawn(async move {
+            write_rows(&server, &state, writer).await
+        }));
+    }
+    for _ in 0..QUERIERS {
+        let server = Arc::clone(&server);
+        let db = Arc::clone(&db);
+        let state = Arc::clone(&state);
+        tasks.push(tokio::spawn(async move {
+            query_rows(&server, &db, &state).await
+        }));
+    }
+    {
+        let db = Arc::clone(&db);
+        let state = Arc::clone(&state);
+        tasks.push(tokio::spawn(async move {
+            run_lifecycle(&db, &store, &state).await
+        }));
+    }
+
+    tokio::time::delay_for(Duration::from_secs(seconds)).await;
+    state.stopped.store(true, Ordering::SeqCst);
+
+    // a panic in a task is returned as an error when it is joined
+    for task in tasks {
+        task.await??;
+    }
+
+    // once quiet, every acknowledged row of the partitions that weren't
+    // dropped is returned exactly once
+    let visible = match visible_rows(&server, &db).await? {
+        Some(visible) => visible,
+        None => return Err("a row was returned twice".into()),
+    };
+    let dropped = state.dropped();
+    let acknowledged = state.acknowledged();
+    assert!(
+        !acknowledged.is_empty(),
+        "no write was acknowledged in {} seconds",
+        seconds
+    );
+    for (partition_key, rows) in acknowledged {
+        if dropped.contains(&partition_key) {
+            continue;
+        }
+        assert_eq!(
+            visible.get(&partition_key),
+            Some(&rows),
+            "rows of partition {}",
+            partition_key
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes batches of unique rows to the partition of the current epoch
+/// until stopped, recording the rows of each acknowledged write
+async fn write_rows(
+    server: &Server<ConnectionManagerImpl>,
+    state: &State,
+    writer: usize,
+) -> Result {
+    let mut sequence = 0;
+    while !state.stopped() {
+        let epoch = state.epoch.load(Ordering::SeqCst);
+        let rows: Vec<_> = (sequence..sequence + ROWS_PER_WRITE)
+            .map(|i| format!("w{}_{}", writer, i))
+            .collect();
+        let lp = rows
+            .iter()
+            .map(|row| format!("cpu,epoch={},row={} value=1i {}", epoch, row, sequence))
+            .collect::<Vec<_>>()
+            .join("\n");
+        sequence += ROWS_PER_WRITE;
+
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+        server.write_lines(DB_NAME, &lines).await?;
+
+        let partition_key = partition_key(epoch);
+        let dropped = state.dropped.lock().expect("mutex poisoned");
+        if !dropped.contains(&partition_key) {
+            state
+                .acknowledged
+                .lock()
+                .expect("mutex poisoned")
+                .entry(partition_key)
+                .or_default()
+                .extend(rows);
+        }
+        drop(dropped);
+
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
+
+/// Queries the rows of all partitions until stopped, checking them against
+/// the rows acknowledged before each query and returned by the previous one
+async fn query_rows(server: &Server<ConnectionManagerImpl>, db: &Db, state: &State) -> Result {
+    let mut last_counts: BTreeMap<String, usize> = BTreeMap::new();
+    while !state.stopped() {
+        let dropped_before = state.dropped();
+        let acknowledged = state.acknowledged();
+        let visible = visible_rows(server, db).await;
+        let dropped = state.dropped();
+
+        // a chunk dropped during the query can fail it, or hide its rows
+        let visible = match visible {
+            Ok(Some(visible)) => visible,
+            Ok(None) if dropped != dropped_before => continue,
+            Ok(None) => return Err("a row was returned twice".into()),
+            Err(_) if dropped != dropped_before => continue,
+            Err(e) => return Err(e),
+        };
+
+        for (partition_key, rows) in &visible {
+            if dropped.contains(partition_key) {
+                continue;
+            }
+            let last_count = last_counts.entry(partition_key.clone()).or_default();
+            assert!(
+                rows.len() >= *last_count,
+                "partition {} returned {} rows, after {}",
+                partition_key,
+                rows.len(),
+                last_count
+            );
+            *last_count = rows.len();
+        }
+
+        let empty = BTreeSet::new();
+        for (partition_key, rows) in &acknowledged {
+            if dropped.contains(partition_key) {
+                continue;
+            }
+            let visible = visible.get(partition_key).unwrap_or(&empty);
+            let lost: Vec<_> = rows.difference(visible).collect();
+            assert!(
+                lost.is_empty(),
+                "acknowledged rows of partition {} were lost: {:?}",
+                partition_key,
+                lost
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Moves the chunks of the partitions until stopped, and drops the
+/// partition of the epoch before last at the end of each epoch
+async fn run_lifecycle(db: &Db, store: &Arc<ObjectStore>, state: &State) -> Result {
+    let mut epoch_start = Instant::now();
+    while !state.stopped() {
+        let dropped = state.dropped();
+        for partition_key in db.partition_keys().await? {
+            if !dropped.contains(&partition_key) {
+                persist_and_compact(db, store, &partition_key).await?;
+            }
+        }
+
+        if epoch_start.elapsed() >= EPOCH_DURATION {
+            let epoch = state.epoch.fetch_add(1, Ordering::SeqCst) + 1;
+            if epoch >= 2 {
+                drop_partition(db, state, &partition_key(epoch - 2)).await?;
+            }
+            epoch_start = Instant::now();
+        }
+
+        tokio::time::delay_for(LIFECYCLE_INTERVAL).await;
+    }
+    Ok(())
+}
+
+/// Closes the open chunk of the partition `partition_key`, snapshots it,
+/// loads it into the read buffer and drops it from the mutable buffer
+async fn persist_and_compact(db: &Db, store: &Arc<ObjectStore>, partition_key: &str) -> Result {
+    let chunk = db.rollover_partition(partition_key).await?;
+    let (metadata_path, data_path) = snapshot_paths(DB_NAME);
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    snapshot_chunk(
+        metadata_path,
+        data_path,
+        Arc::clone(store),
+        partition_key,
+        Arc::clone(&chunk),
+        &db.rules.parquet_config,
+        &db.rules.tag_orders,
+        Some(tx),
+    )?;
+    // the sender is dropped without sending if the snapshot fails
+    rx.await
+        .map_err(|_| format!("snapshot of partition {} failed", partition_key))?;
+
+    db.load_chunk_to_read_buffer(partition_key, chunk.id())
+        .await?;
+    db.drop_mutable_buffer_chunk(partition_key, chunk.id())
+        .await?;
+    Ok(())
+}
+
+/// Drops all the chunks of the partition `partition_key`, after which the
+/// queries no longer check it
+async fn drop_partition(db: &Db, state: &State, partition_key: &str) -> Result {
+    state
+        .dropped
+        .lock()
+        .expect("mutex poisoned")
+        .insert(partition_key.to_string());
+    state
+        .acknowledged
+        .lock()
+        .expect("mutex poisoned")
+        .remove(partition_key);
+
+    // the open chunk can't be dropped, so it is closed first. The new open
+    // chunk only holds late writes.
+    let closed = db.rollover_partition(partition_key).await?;
+    for chunk in db.mutable_buffer_chunks(partition_key).await {
+        if chunk.id() <= closed.id() {
+            db.drop_mutable_buffer_chunk(partition_key, chunk.id())
+                .await?;
+        }
+    }
+    for chunk in db.read_buffer_chunks(partition_key).await {
+        db.drop_read_buffer_chunk(partition_key, chunk.id()).await?;
+    }
+    Ok(())
+}
+
+/// Returns the rows of all partitions, by partition key, or `None` if a
+/// row was returned twice
+async fn visible_rows(
+    server: &Server<ConnectionManagerImpl>,
+    db: &Db,
+) -> Result<Option<BTreeMap<String, BTreeSet<String>>>> {
+    let planner = SQLQueryPlanner::default();
+    let physical_plan = planner
+        .query(db, "select epoch, row from cpu", server.executor().as_ref())
+        .await?;
+
+    let mut rows: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for batch in collect(physical_plan).await? {
+        let epochs = string_column(batch.column(0));
+        let row_ids = string_column(batch.column(1));
+        for i in 0..batch.num_rows() {
+            let epoch: usize = epochs.value(i).parse()?;
+            let inserted = rows
+                .entry(partition_key(epoch))
+                .or_default()
+                .insert(row_ids.value(i).to_string());
+            if !inserted {
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(rows))
+}
+
+fn string_column(column: &ArrayRef) -> &StringArray {
+    column
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("tags are string columns")
+}
+
+/// The key of the partition of the rows written during `epoch`
+fn partition_key(epoch: usize) -> String {
+    format!("epoch_{}", epoch)
+}
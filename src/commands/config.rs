@@ -4,6 +4,7 @@
 use std::{net::SocketAddr, path::PathBuf};
 
 use lazy_static::lazy_static;
+use server::audit::AuditRedaction;
 use structopt::StructOpt;
 
 /// The default bind address for the HTTP API.
@@ -93,6 +94,128 @@ pub struct Config {
     #[structopt(long = "--data-dir", env = "INFLUXDB_IOX_DB_DIR")]
     pub database_directory: Option<PathBuf>,
 
+    /// The maximum number of rows in each record batch produced when
+    /// running queries.
+    #[structopt(
+        long = "--query-batch-rows",
+        env = "INFLUXDB_IOX_QUERY_BATCH_ROWS",
+        default_value = "1000"
+    )]
+    pub query_batch_rows: usize,
+
+    /// If set, the approximate maximum size in bytes of each record batch
+    /// produced when running queries. Fewer rows are placed in each batch
+    /// for tables with wide rows so that batches stay under this size.
+    #[structopt(long = "--query-batch-bytes", env = "INFLUXDB_IOX_QUERY_BATCH_BYTES")]
+    pub query_batch_bytes: Option<usize>,
+
+    /// The maximum size in bytes of the body of an HTTP request, both as
+    /// sent and after decompression. Larger requests are rejected while
+    /// they are read.
+    #[structopt(
+        long = "--max-http-request-size",
+        env = "INFLUXDB_IOX_MAX_HTTP_REQUEST_SIZE",
+        default_value = "10485760"
+    )]
+    pub max_http_request_size: usize,
+
+    /// The maximum number of lines of line protocol in a write request.
+    #[structopt(
+        long = "--max-write-lines",
+        env = "INFLUXDB_IOX_MAX_WRITE_LINES",
+        default_value = "1000000"
+    )]
+    pub max_write_lines: usize,
+
+    /// The maximum length in bytes of a SQL query.
+    #[structopt(
+        long = "--max-sql-query-size",
+        env = "INFLUXDB_IOX_MAX_SQL_QUERY_SIZE",
+        default_value = "65536"
+    )]
+    pub max_sql_query_size: usize,
+
+    /// The maximum number of series a single read_filter or read_group
+    /// request may select. Queries selecting more fail with a resource
+    /// exhausted error.
+    #[structopt(
+        long = "--max-read-series",
+        env = "INFLUXDB_IOX_MAX_READ_SERIES",
+        default_value = "1000000"
+    )]
+    pub max_read_series: usize,
+
+    /// The maximum number of frames (series, group and points frames) in
+    /// the response to a single read_filter or read_group request.
+    #[structopt(
+        long = "--max-read-frames",
+        env = "INFLUXDB_IOX_MAX_READ_FRAMES",
+        default_value = "10000000"
+    )]
+    pub max_read_frames: usize,
+
+    /// The number of hours a deleted database can still be restored.
+    /// After that, it is purged along with all of its objects in storage.
+    #[structopt(
+        long = "--database-restore-window-hours",
+        env = "INFLUXDB_IOX_DATABASE_RESTORE_WINDOW_HOURS",
+        default_value = "168"
+    )]
+    pub database_restore_window_hours: u32,
+
+    /// The number of databases recovered from object storage at the same
+    /// time when the server starts. Each database is served as soon as it
+    /// has been recovered.
+    #[structopt(
+        long = "--database-recovery-concurrency",
+        env = "INFLUXDB_IOX_DATABASE_RECOVERY_CONCURRENCY",
+        default_value = "10"
+    )]
+    pub database_recovery_concurrency: usize,
+
+    /// The maximum rate, in bytes per second, at which the snapshots of the
+    /// databases are read back to verify their checksums. Corrupted files
+    /// are moved out of the way before queries read them. 0 disables
+    /// scrubbing.
+    #[structopt(
+        long = "--scrub-bytes-per-second",
+        env = "INFLUXDB_IOX_SCRUB_BYTES_PER_SECOND",
+        default_value = "10485760"
+    )]
+    pub scrub_bytes_per_second: u64,
+
+    /// If set, databases can write their WAL segments to files in this
+    /// directory instead of the object store, by setting the segment
+    /// storage of their WAL buffer to the backend named "file".
+    #[structopt(long = "--wal-segment-dir", env = "INFLUXDB_IOX_WAL_SEGMENT_DIR")]
+    pub wal_segment_directory: Option<PathBuf>,
+
+    /// If set, the queries and administrative actions run by the server
+    /// are recorded in this audit log: `object_store` writes each entry as
+    /// an object of the object store of the server, anything else is the
+    /// path of a local file entries are appended to as JSON lines. Actions
+    /// whose entry can't be recorded fail.
+    #[structopt(long = "--audit-log", env = "INFLUXDB_IOX_AUDIT_LOG")]
+    pub audit_log: Option<String>,
+
+    /// What the audit log leaves out of the statements and errors it
+    /// records: `none`, `literals` (string and number literals are
+    /// replaced by `?`, the default) or `statements` (they aren't
+    /// recorded).
+    #[structopt(
+        long = "--audit-log-redaction",
+        env = "INFLUXDB_IOX_AUDIT_LOG_REDACTION",
+        default_value = "literals"
+    )]
+    pub audit_log_redaction: AuditRedaction,
+
+    /// If set, requests are only allowed if the token they are sent with
+    /// is scoped to them in this JSON file, which lists the SHA-256 of each
+    /// token with the actions (`read`, `write` or `admin`) and databases it
+    /// allows. All requests are allowed if not set.
+    #[structopt(long = "--token-scopes-file", env = "INFLUXDB_IOX_TOKEN_SCOPES_FILE")]
+    pub token_scopes_file: Option<PathBuf>,
+
     /// If using Google Cloud Storage for the object store, this item, as well
     /// as SERVICE_ACCOUNT must be set.
     #[structopt(long = "--gcp-bucket", env = "INFLUXDB_IOX_GCP_BUCKET")]
@@ -116,6 +239,29 @@ pub struct Config {
         env = "OTEL_EXPORTER_JAEGER_AGENT_HOST"
     )]
     pub jaeger_host: Option<String>,
+
+    /// What to run instead of the server, if anything
+    #[structopt(subcommand)]
+    pub command: Option<ServerCommand>,
+}
+
+/// The commands run with the configuration of the server instead of the
+/// server itself
+#[derive(Debug, StructOpt)]
+pub enum ServerCommand {
+    /// Validates the configuration end-to-end without serving: writes,
+    /// reads back and deletes an object in the object store, writes and
+    /// syncs a file in the WAL segment directory, binds the HTTP and gRPC
+    /// addresses and loads the credentials. Prints a report of the checks
+    /// and exits with a non-zero status if any failed.
+    ///
+    /// The options of the server must be set before `check` or with their
+    /// environment variables.
+    Check {
+        /// Print the report as JSON
+        #[structopt(long = "--json")]
+        json: bool,
+    },
 }
 
 /// Load the config if `server` was not specified on the command line
@@ -0,0 +1,400 @@
+//! This module contains code to inspect the WAL segments a server
+//! persisted to disk, to debug recovery problems. It decodes each segment
+//! and summarizes what it contains (writers, partitions, tables, rows and
+//! time ranges), and can dump its writes as line protocol.
+//!
+//! Segments are read and printed one at a time, so only one segment is
+//! held in memory however many are found.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use data_types::{data::ReplicatedWrite, database_rules::WriterId, TIME_COLUMN_NAME};
+use generated_types::wal as wb;
+use influxdb_line_protocol::{escape, LinePart};
+use object_store::{disk::File, path::ObjectStorePath, ObjectStore};
+use server::buffer::{
+    stored::{read_segment, segment_paths},
+    InvalidWrites, Segment, WriterSummary,
+};
+use snafu::{ResultExt, Snafu};
+use tracing::info;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to inspect WAL segments: {}", source))]
+    Inspect { source: server::buffer::Error },
+
+    #[snafu(display("Found no WAL segments in {}", input_path))]
+    NoSegments { input_path: String },
+
+    #[snafu(display("Unable to print WAL segment: {}", source))]
+    Print { source: io::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Describes what to inspect
+#[derive(Debug)]
+pub struct WalConfig {
+    /// The directory to search for segments, e.g. the object store
+    /// directory of a server or a database within it
+    pub input_path: String,
+
+    /// Should the writes of each segment be printed as line protocol?
+    pub dump: bool,
+
+    /// Should writes that fail verification be skipped, rather than
+    /// failing to decode their segment?
+    pub skip_invalid_writes: bool,
+}
+
+/// Print a summary of every WAL segment found under `input_path`, in
+/// order of their location.
+///
+/// Segments that can't be decoded are printed with the decoding error
+/// rather than failing the inspection, as those are usually the ones
+/// worth looking at.
+pub async fn inspect_wal(config: &WalConfig) -> Result<()> {
+    info!("wal inspection starting for {:?}", config);
+
+    let store = ObjectStore::new_file(File::new(&config.input_path));
+    let invalid_writes = if config.skip_invalid_writes {
+        InvalidWrites::Skip
+    } else {
+        InvalidWrites::Fail
+    };
+    let paths = segment_paths(&store, &ObjectStorePath::default())
+        .await
+        .context(Inspect)?;
+
+    if paths.is_empty() {
+        return NoSegments {
+            input_path: &config.input_path,
+        }
+        .fail();
+    }
+
+    let stdout = io::stdout();
+    for (location, path) in paths {
+        let stored = read_segment(&store, location, &path, invalid_writes)
+            .await
+            .context(Inspect)?;
+
+        let mut out = stdout.lock();
+        writeln!(out, "Segment {}", stored.location).context(Print)?;
+        match stored.segment {
+            Ok(segment) => {
+                print_summary(&mut out, &SegmentSummary::new(&segment)).context(Print)?;
+                if config.dump {
+                    write_line_protocol(&mut out, &segment).context(Print)?;
+                }
+            }
+            Err(e) => writeln!(out, "  unable to decode: {}", e).context(Print)?,
+        }
+        writeln!(out).context(Print)?;
+    }
+
+    Ok(())
+}
+
+/// What a segment contains
+#[derive(Debug, Default, PartialEq)]
+struct SegmentSummary {
+    id: u64,
+    /// The number of replicated writes
+    writes: usize,
+    /// The number of replicated writes skipped because they failed
+    /// verification
+    skipped_writes: usize,
+    /// The number of write buffer entries (one per partition of a write)
+    entries: usize,
+    /// The sequence numbers of the writes, by writer
+    writers: BTreeMap<WriterId, WriterSummary>,
+    partitions: BTreeMap<String, PartitionSummary>,
+}
+
+/// What the writes in a segment contain for a partition
+#[derive(Debug, Default, PartialEq)]
+struct PartitionSummary {
+    tables: BTreeMap<String, TableSummary>,
+}
+
+/// What the writes in a segment contain for a table of a partition
+#[derive(Debug, Default, PartialEq)]
+struct TableSummary {
+    rows: usize,
+    /// The smallest and largest times of the rows, if any have a time
+    time_range: Option<(i64, i64)>,
+}
+
+impl SegmentSummary {
+    /// Summarizes the contents of `segment`
+    fn new(segment: &Segment) -> Self {
+        let mut summary = Self {
+            id: segment.id(),
+            writes: segment.writes.len(),
+            skipped_writes: segment.skipped_writes,
+            writers: segment.writers().clone(),
+            ..Default::default()
+        };
+
+        for entry in segment.writes.iter().flat_map(|write| entries(write)) {
+            summary.entries += 1;
+            if entry.delete().is_some() {
+                continue;
+            }
+            let partition = summary
+                .partitions
+                .entry(entry.partition_key().unwrap_or("").to_string())
+                .or_default();
+
+            for table in entry.table_batches().into_iter().flatten() {
+                let table_summary = partition
+                    .tables
+                    .entry(table.name().unwrap_or("").to_string())
+                    .or_default();
+
+                for row in table.rows().into_iter().flatten() {
+                    table_summary.rows += 1;
+                    if let Some(time) = row_time(&row) {
+                        table_summary.time_range = Some(match table_summary.time_range {
+                            Some((min, max)) => (min.min(time), max.max(time)),
+                            None => (time, time),
+                        });
+                    }
+                }
+            }
+        }
+
+        summary
+    }
+}
+
+fn print_summary(out: &mut impl Write, summary: &SegmentSummary) -> io::Result<()> {
+    writeln!(out, "  id: {}", summary.id)?;
+    writeln!(out, "  writes: {}", summary.writes)?;
+    if summary.skipped_writes > 0 {
+        writeln!(out, "  skipped invalid writes: {}", summary.skipped_writes)?;
+    }
+    writeln!(out, "  entries: {}", summary.entries)?;
+
+    for (writer, w) in &summary.writers {
+        writeln!(
+            out,
+            "  writer {}: sequences {}..={}{}",
+            writer,
+            w.start_sequence,
+            w.end_sequence,
+            if w.missing_sequence {
+                " (some missing)"
+            } else {
+                ""
+            }
+        )?;
+    }
+
+    for (key, partition) in &summary.partitions {
+        writeln!(out, "  partition {:?}", key)?;
+        for (name, table) in &partition.tables {
+            match table.time_range {
+                Some((min, max)) => writeln!(
+                    out,
+                    "    table {}: {} rows, time {}..={}",
+                    name, table.rows, min, max
+                )?,
+                None => writeln!(out, "    table {}: {} rows", name, table.rows)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the writes of `segment` as line protocol. Each write and
+/// partition starts with a comment naming it, so the output can be
+/// written back as is. Deletions are written as comments too.
+fn write_line_protocol(out: &mut impl Write, segment: &Segment) -> io::Result<()> {
+    for write in &segment.writes {
+        let (writer, sequence) = write.writer_and_sequence();
+        writeln!(out, "# writer {} sequence {}", writer, sequence)?;
+        for (table_name, predicate) in write.deletes() {
+            writeln!(out, "# delete {} {}", table_name, predicate)?;
+        }
+
+        for entry in entries(write).filter(|entry| entry.delete().is_none()) {
+            writeln!(out, "# partition {}", entry.partition_key().unwrap_or(""))?;
+
+            for table in entry.table_batches().into_iter().flatten() {
+                let table_name = table.name().unwrap_or("");
+                for row in table.rows().into_iter().flatten() {
+                    writeln!(out, "{}", row_to_line_protocol(table_name, &row))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The write buffer entries (one per partition) of `write`
+fn entries(write: &ReplicatedWrite) -> impl Iterator<Item = wb::WriteBufferEntry<'_>> {
+    write
+        .write_buffer_batch()
+        .and_then(|batch| batch.entries())
+        .into_iter()
+        .flatten()
+}
+
+fn row_time(row: &wb::Row<'_>) -> Option<i64> {
+    row.values()?
+        .into_iter()
+        .filter(|value| value.column() == Some(TIME_COLUMN_NAME))
+        .find_map(|value| value.value_as_i64value())
+        .map(|value| value.value())
+}
+
+fn row_to_line_protocol(table_name: &str, row: &wb::Row<'_>) -> String {
+    let mut tags = String::new();
+    let mut fields = Vec::new();
+    let mut time = None;
+
+    for value in row.values().into_iter().flatten() {
+        let column = value.column().unwrap_or("");
+        let field = match value.value_type() {
+            wb::ColumnValue::TagValue => {
+                let tag = value.value_as_tag_value().and_then(|v| v.value());
+                tags.push(',');
+                tags.push_str(&escape(column, LinePart::TagKey));
+                tags.push('=');
+                tags.push_str(&escape(tag.unwrap_or(""), LinePart::TagValue));
+                continue;
+            }
+            wb::ColumnValue::I64Value if column == TIME_COLUMN_NAME => {
+                time = value.value_as_i64value().map(|v| v.value());
+                continue;
+            }
+            wb::ColumnValue::I64Value => {
+                value.value_as_i64value().map(|v| format!("{}i", v.value()))
+            }
+            wb::ColumnValue::U64Value => {
+                value.value_as_u64value().map(|v| format!("{}u", v.value()))
+            }
+            wb::ColumnValue::F64Value => value.value_as_f64value().map(|v| v.value().to_string()),
+            wb::ColumnValue::BoolValue => {
+                value.value_as_bool_value().map(|v| v.value().to_string())
+            }
+            wb::ColumnValue::StringValue => value.value_as_string_value().map(|v| {
+                format!(
+                    "\"{}\"",
+                    escape(v.value().unwrap_or(""), LinePart::FieldStringValue)
+                )
+            }),
+            wb::ColumnValue::NONE => None,
+        };
+
+        if let Some(field) = field {
+            fields.push(format!("{}={}", escape(column, LinePart::FieldKey), field));
+        }
+    }
+
+    let mut line = escape(table_name, LinePart::Measurement);
+    line.push_str(&tags);
+    line.push(' ');
+    line.push_str(&fields.join(","));
+    if let Some(time) = time {
+        line.push_str(&format!(" {}", time));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::{
+        data::lines_to_replicated_write,
+        database_rules::{DatabaseRules, PartitionTemplate, TemplatePart, WalBufferRollover},
+    };
+    use influxdb_line_protocol::parse_lines;
+    use server::buffer::Buffer;
+    use std::sync::Arc;
+
+    #[test]
+    fn summarizes_and_dumps_segments() {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // a segment size of zero closes the segment on every append
+        let mut buffer = Buffer::new(u64::MAX, 0, WalBufferRollover::ReturnError, false);
+        let writes = [
+            "cpu,host=a usage=0.5 10\ncpu,host=b usage=0.7 30",
+            "cpu,host=a usage=0.9 20\nmem,host=a free=10i,ok=true,msg=\"a \\\"b\\\"\" 20",
+        ];
+        let segments: Vec<_> = writes
+            .iter()
+            .enumerate()
+            .map(|(sequence, lp)| {
+                let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+                let write = lines_to_replicated_write(1, sequence as u64 + 1, &lines, &rules);
+                buffer
+                    .append(Arc::new(write))
+                    .unwrap()
+                    .expect("segment closed")
+            })
+            .collect();
+
+        let summary = SegmentSummary::new(&segments[1]);
+        assert_eq!(summary.id, 2);
+        assert_eq!(summary.writes, 1);
+        assert_eq!(summary.entries, 2);
+        assert_eq!(
+            summary.writers.get(&1),
+            Some(&WriterSummary {
+                start_sequence: 2,
+                end_sequence: 2,
+                missing_sequence: false,
+            })
+        );
+
+        assert_eq!(
+            summary.partitions["cpu"].tables["cpu"],
+            TableSummary {
+                rows: 1,
+                time_range: Some((20, 20)),
+            }
+        );
+        assert_eq!(
+            summary.partitions["mem"].tables["mem"],
+            TableSummary {
+                rows: 1,
+                time_range: Some((20, 20)),
+            }
+        );
+
+        let first = SegmentSummary::new(&segments[0]);
+        assert_eq!(
+            first.partitions["cpu"].tables["cpu"],
+            TableSummary {
+                rows: 2,
+                time_range: Some((10, 30)),
+            }
+        );
+
+        let mut lp = Vec::new();
+        write_line_protocol(&mut lp, &segments[1]).unwrap();
+        let expected = r#"# writer 1 sequence 2
+# partition cpu
+cpu,host=a usage=0.9 20
+# partition mem
+mem,host=a free=10i,ok=true,msg="a \"b\"" 20
+"#;
+        assert_eq!(String::from_utf8(lp).unwrap(), expected);
+    }
+}
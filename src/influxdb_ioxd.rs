@@ -2,16 +2,27 @@ use tracing::{error, info, warn};
 
 use std::fs;
 use std::net::SocketAddr;
+use std::num::NonZeroU64;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+pub mod allocator;
+pub mod check;
 pub mod http_routes;
 pub mod rpc;
-
-use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+#[cfg(test)]
+pub mod test_server;
+
+use server::{
+    audit::{AuditLog, AuditSink, FileAuditSink, ObjectStoreAuditSink},
+    authz::TokenScopes,
+    buffer::store::FileSegments,
+    ConnectionManagerImpl as ConnectionManager, Server as AppServer,
+};
 
 use hyper::Server;
 use object_store::{self, gcp::GoogleCloudStorage, ObjectStore};
+use query::exec::{batch_size::BatchSizeConfig, Executor};
 
 use snafu::{ResultExt, Snafu};
 
@@ -62,6 +73,9 @@ pub enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Unable to load token scopes: {}", source))]
+    LoadingTokenScopes { source: server::authz::Error },
+
     #[snafu(display("Error serving HTTP: {}", source))]
     ServingHttp { source: hyper::error::Error },
 
@@ -71,6 +85,27 @@ pub enum Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// How often to look for deleted databases whose restore window has passed
+const PURGE_DELETED_DATABASES_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(60 * 60);
+
+/// How often to run continuous queries for the windows that have closed
+const CONTINUOUS_QUERY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often to drop the data that has outlived the retention rules of
+/// its database
+const RETENTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How often to verify the snapshots of the databases against their
+/// checksums
+const SCRUB_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// The name of the WAL backend writing segments to `--wal-segment-dir`
+const FILE_WAL_BACKEND: &str = "file";
+
+/// The `--audit-log` writing entries to the object store of the server
+const OBJECT_STORE_AUDIT_LOG: &str = "object_store";
+
 /// This is the entry point for the IOx server. `config` represents
 /// command line arguments, if any
 ///
@@ -95,38 +130,127 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
     let f = SendPanicsToTracing::new();
     std::mem::forget(f);
 
-    let db_dir = &config.database_directory;
+    let object_storage = Arc::new(make_object_store(&config)?);
 
-    let object_store = if let Some(bucket_name) = &config.gcp_bucket {
-        info!("Using GCP bucket {} for storage", bucket_name);
-        ObjectStore::new_google_cloud_storage(GoogleCloudStorage::new(bucket_name))
-    } else if let Some(db_dir) = db_dir {
-        info!("Using local dir {:?} for storage", db_dir);
-        fs::create_dir_all(db_dir).context(CreatingDatabaseDirectory { path: db_dir })?;
-        ObjectStore::new_file(object_store::disk::File::new(&db_dir))
-    } else {
-        warn!("NO PERSISTENCE: using memory for object storage");
-        ObjectStore::new_in_memory(object_store::memory::InMemory::new())
-    };
-    let object_storage = Arc::new(object_store);
+    let mut batch_config = BatchSizeConfig::new(config.query_batch_rows);
+    if let Some(query_batch_bytes) = config.query_batch_bytes {
+        batch_config = batch_config.with_max_bytes(query_batch_bytes);
+    }
+    let executor = Arc::new(Executor::new_with_batch_config(batch_config));
 
     let connection_manager = ConnectionManager {};
-    let app_server = Arc::new(AppServer::new(connection_manager, object_storage));
+    let restore_window = chrono::Duration::hours(config.database_restore_window_hours.into());
+    let mut app_server = AppServer::new(connection_manager, Arc::clone(&object_storage))
+        .with_executor(executor)
+        .with_restore_window(restore_window)
+        .with_recovery_concurrency(config.database_recovery_concurrency);
+    if let Some(wal_segment_dir) = &config.wal_segment_directory {
+        info!("WAL segments can be written to {:?}", wal_segment_dir);
+        app_server = app_server.with_wal_backend(
+            FILE_WAL_BACKEND,
+            Arc::new(FileSegments::new(wal_segment_dir)),
+        );
+    }
+    if let Some(audit_log) = &config.audit_log {
+        info!(
+            "Recording queries and administrative actions in the audit log {}",
+            audit_log
+        );
+        let sink: Arc<dyn AuditSink> = if audit_log == OBJECT_STORE_AUDIT_LOG {
+            Arc::new(ObjectStoreAuditSink::new(object_storage))
+        } else {
+            Arc::new(FileAuditSink::new(audit_log))
+        };
+        app_server = app_server.with_audit_log(AuditLog::new(sink, config.audit_log_redaction));
+    }
+    if let Some(token_scopes_file) = &config.token_scopes_file {
+        info!(
+            "Authorizing requests with the token scopes {:?}",
+            token_scopes_file
+        );
+        let token_scopes = TokenScopes::from_file(token_scopes_file).context(LoadingTokenScopes)?;
+        app_server = app_server.with_authorizer(Arc::new(token_scopes));
+    }
+    let app_server = Arc::new(app_server);
 
     // if this ID isn't set the server won't be usable until this is set via an API
     // call
     if let Some(id) = config.writer_id {
         app_server.set_id(id);
-        if let Err(e) = app_server.load_database_configs().await {
-            error!(
-                "unable to load database configurations from object storage: {}",
-                e
-            )
-        }
+
+        // Serve requests while the databases are recovered, so each
+        // database is available as soon as it has been loaded
+        let recovery_server = app_server.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = recovery_server.load_database_configs().await {
+                error!(
+                    "unable to load database configurations from object storage: {}",
+                    e
+                )
+            }
+        });
     } else {
         warn!("server ID not set. ID must be set via the INFLUXDB_IOX_ID config or API before writing or querying data.");
     }
 
+    // Purge deleted databases once they can no longer be restored
+    let purge_server = app_server.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_DELETED_DATABASES_INTERVAL);
+        loop {
+            interval.tick().await;
+            match purge_server.purge_deleted_databases().await {
+                // nothing can be deleted until the ID is set
+                Ok(_) | Err(server::Error::IdNotSet) => {}
+                Err(e) => error!("error purging deleted databases: {}", e),
+            }
+        }
+    });
+
+    // Run continuous queries as their windows close
+    let continuous_query_server = app_server.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(CONTINUOUS_QUERY_INTERVAL);
+        loop {
+            interval.tick().await;
+            match continuous_query_server
+                .run_continuous_queries(chrono::Utc::now())
+                .await
+            {
+                // queries can't run until the ID is set
+                Ok(_) | Err(server::Error::IdNotSet) => {}
+                Err(e) => error!("error running continuous queries: {}", e),
+            }
+        }
+    });
+
+    // Drop expired data
+    let retention_server = app_server.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            retention_server.enforce_retention(chrono::Utc::now()).await;
+        }
+    });
+
+    // Verify the checksums of the snapshots
+    if let Some(bytes_per_second) = NonZeroU64::new(config.scrub_bytes_per_second) {
+        let scrub_server = app_server.clone();
+        tokio::task::spawn(async move {
+            // the first scrub is a whole interval after startup, so
+            // restarts don't each read back every snapshot
+            let mut interval = tokio::time::interval_at(
+                tokio::time::Instant::now() + SCRUB_INTERVAL,
+                SCRUB_INTERVAL,
+            );
+            loop {
+                interval.tick().await;
+                scrub_server.scrub_snapshots(bytes_per_second).await;
+            }
+        });
+    }
+
     // Construct and start up gRPC server
 
     let grpc_bind_addr = config.grpc_bind_address;
@@ -134,13 +258,29 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
         .await
         .context(StartListeningGrpc { grpc_bind_addr })?;
 
-    let grpc_server = self::rpc::service::make_server(socket, app_server.clone());
+    let grpc_server = self::rpc::service::make_server(
+        socket,
+        app_server.clone(),
+        app_server.authorizer(),
+        app_server.clone(),
+        app_server.latency_metrics(),
+        self::rpc::service::ReadLimits {
+            max_series: config.max_read_series,
+            max_frames: config.max_read_frames,
+        },
+        Some(app_server.clone()),
+    );
 
     info!(bind_address=?grpc_bind_addr, "gRPC server listening");
 
     // Construct and start up HTTP server
 
-    let router_service = http_routes::router_service(app_server.clone());
+    let limits = http_routes::RequestLimits {
+        max_body_bytes: config.max_http_request_size,
+        max_write_lines: config.max_write_lines,
+        max_sql_bytes: config.max_sql_query_size,
+    };
+    let router_service = http_routes::router_service(app_server.clone(), limits);
 
     let bind_addr = config.http_bind_address;
     let http_server = Server::try_bind(&bind_addr)
@@ -158,3 +298,26 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
 
     Ok(())
 }
+
+/// Creates the object store the server is configured to persist its data
+/// to: a GCP bucket, a local directory (created if needed) or, if neither
+/// is set, memory
+fn make_object_store(config: &Config) -> Result<ObjectStore> {
+    if let Some(bucket_name) = &config.gcp_bucket {
+        info!("Using GCP bucket {} for storage", bucket_name);
+        Ok(ObjectStore::new_google_cloud_storage(
+            GoogleCloudStorage::new(bucket_name),
+        ))
+    } else if let Some(db_dir) = &config.database_directory {
+        info!("Using local dir {:?} for storage", db_dir);
+        fs::create_dir_all(db_dir).context(CreatingDatabaseDirectory { path: db_dir })?;
+        Ok(ObjectStore::new_file(object_store::disk::File::new(
+            &db_dir,
+        )))
+    } else {
+        warn!("NO PERSISTENCE: using memory for object storage");
+        Ok(ObjectStore::new_in_memory(
+            object_store::memory::InMemory::new(),
+        ))
+    }
+}
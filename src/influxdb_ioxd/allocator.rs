@@ -0,0 +1,223 @@
+//! This module contains the statistics and heap profiles of the memory
+//! allocator, which let memory growth be investigated beyond the resident
+//! set size of the process.
+//!
+//! They are only available when the server is built with the `jemalloc`
+//! feature, which makes jemalloc the global allocator. Heap profiles also
+//! require profiling to be enabled when the server starts, with
+//! `_RJEM_MALLOC_CONF=prof:true`.
+
+// only `NotBuiltWithJemalloc` is used without jemalloc
+#![cfg_attr(not(feature = "jemalloc"), allow(dead_code))]
+
+use std::{fmt::Write, path::PathBuf};
+
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "The server was built without jemalloc: rebuild it with the jemalloc feature"
+    ))]
+    NotBuiltWithJemalloc,
+
+    #[snafu(display(
+        "Heap profiling is not enabled: start the server with _RJEM_MALLOC_CONF=prof:true"
+    ))]
+    ProfilingNotEnabled,
+
+    #[snafu(display("Error calling jemalloc mallctl {}: {}", name, source))]
+    Mallctl {
+        name: &'static str,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Error reading heap profile {:?}: {}", path, source))]
+    ReadingProfile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The statistics of the allocator, in bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// Allocated by the server
+    pub allocated: u64,
+    /// In the pages holding allocations, so at least `allocated`
+    pub active: u64,
+    /// Used by the allocator for its own metadata
+    pub metadata: u64,
+    /// In the pages mapped by the allocator that are resident in memory
+    pub resident: u64,
+    /// In the extents mapped by the allocator that are in use
+    pub mapped: u64,
+    /// Kept mapped by the allocator rather than returned to the operating
+    /// system
+    pub retained: u64,
+}
+
+impl AllocatorStats {
+    /// Reads the current statistics of the allocator
+    #[cfg(feature = "jemalloc")]
+    pub fn read() -> Result<Self> {
+        use jemalloc_ctl::{epoch, stats};
+
+        // the statistics are cached by jemalloc, and only refreshed when
+        // the epoch is advanced
+        mallctl("epoch", epoch::advance())?;
+
+        Ok(Self {
+            allocated: mallctl("stats.allocated", stats::allocated::read())? as u64,
+            active: mallctl("stats.active", stats::active::read())? as u64,
+            metadata: mallctl("stats.metadata", stats::metadata::read())? as u64,
+            resident: mallctl("stats.resident", stats::resident::read())? as u64,
+            mapped: mallctl("stats.mapped", stats::mapped::read())? as u64,
+            retained: mallctl("stats.retained", stats::retained::read())? as u64,
+        })
+    }
+
+    /// Reads the current statistics of the allocator
+    #[cfg(not(feature = "jemalloc"))]
+    pub fn read() -> Result<Self> {
+        NotBuiltWithJemalloc.fail()
+    }
+
+    /// Renders the statistics as gauges in the Prometheus text format
+    pub fn render(&self) -> String {
+        let gauges = [
+            ("allocated", "Bytes allocated by the server", self.allocated),
+            (
+                "active",
+                "Bytes in the pages holding allocations",
+                self.active,
+            ),
+            ("metadata", "Bytes of allocator metadata", self.metadata),
+            (
+                "resident",
+                "Bytes in resident pages mapped by the allocator",
+                self.resident,
+            ),
+            (
+                "mapped",
+                "Bytes in extents in use mapped by the allocator",
+                self.mapped,
+            ),
+            (
+                "retained",
+                "Bytes kept mapped rather than returned to the OS",
+                self.retained,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, help, value) in &gauges {
+            let name = format!("iox_jemalloc_{}_bytes", name);
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+        out
+    }
+}
+
+/// Returns a profile of the memory allocated by the server, in the format
+/// read by `jeprof`
+#[cfg(feature = "jemalloc")]
+pub fn dump_heap_profile() -> Result<Vec<u8>> {
+    use jemalloc_ctl::raw;
+    use snafu::{ensure, ResultExt};
+    use std::{
+        ffi::CString,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    // distinguishes the files of concurrent dumps
+    static DUMPS: AtomicU64 = AtomicU64::new(0);
+
+    // safety: `opt.prof` is a bool
+    let enabled: bool = mallctl("opt.prof", unsafe { raw::read(b"opt.prof\0") })?;
+    ensure!(enabled, ProfilingNotEnabled);
+
+    // jemalloc can only dump a profile to a file
+    let path = std::env::temp_dir().join(format!(
+        "influxdb_iox.{}.{}.heap",
+        std::process::id(),
+        DUMPS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let c_path = CString::new(path.to_string_lossy().into_owned())
+        .expect("temporary paths have no NUL bytes");
+    // safety: `prof.dump` takes a NUL terminated path, which outlives the
+    // call
+    mallctl("prof.dump", unsafe {
+        raw::write(b"prof.dump\0", c_path.as_ptr())
+    })?;
+
+    let profile = std::fs::read(&path).context(ReadingProfile { path: &path });
+    let _ = std::fs::remove_file(&path);
+    profile
+}
+
+/// Returns a profile of the memory allocated by the server, in the format
+/// read by `jeprof`
+#[cfg(not(feature = "jemalloc"))]
+pub fn dump_heap_profile() -> Result<Vec<u8>> {
+    NotBuiltWithJemalloc.fail()
+}
+
+/// Converts the error of the mallctl `name`
+#[cfg(feature = "jemalloc")]
+fn mallctl<T>(name: &'static str, result: Result<T, jemalloc_ctl::Error>) -> Result<T> {
+    use snafu::ResultExt;
+
+    result
+        .map_err(|e| Box::new(e) as _)
+        .context(Mallctl { name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_gauges() {
+        let stats = AllocatorStats {
+            allocated: 100,
+            active: 4096,
+            ..Default::default()
+        };
+
+        let rendered = stats.render();
+        assert!(rendered.contains(
+            "# HELP iox_jemalloc_allocated_bytes Bytes allocated by the server\n\
+             # TYPE iox_jemalloc_allocated_bytes gauge\n\
+             iox_jemalloc_allocated_bytes 100\n"
+        ));
+        assert!(rendered.contains("iox_jemalloc_active_bytes 4096\n"));
+        assert!(rendered.contains("iox_jemalloc_retained_bytes 0\n"));
+    }
+
+    #[cfg(feature = "jemalloc")]
+    #[test]
+    fn reads_jemalloc_stats() {
+        let data = vec![0_u8; 1_000_000];
+        let stats = AllocatorStats::read().unwrap();
+        assert!(stats.allocated >= data.len() as u64);
+        assert!(stats.active >= stats.allocated);
+    }
+
+    #[cfg(not(feature = "jemalloc"))]
+    #[test]
+    fn unavailable_without_jemalloc() {
+        assert!(matches!(
+            AllocatorStats::read(),
+            Err(Error::NotBuiltWithJemalloc)
+        ));
+        assert!(matches!(
+            dump_heap_profile(),
+            Err(Error::NotBuiltWithJemalloc)
+        ));
+    }
+}
@@ -0,0 +1,498 @@
+//! This module contains the self-test run by `server check`: the
+//! configuration of the server is validated end-to-end, without serving,
+//! and the outcome of each check is printed as a report so deployment
+//! pipelines can gate rollouts on it.
+//!
+//! The checks have no lasting effects: the object written to the object
+//! store and the file written to the WAL segment directory are deleted
+//! again, and the addresses bound are released.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use object_store::{path::ObjectStorePath, ObjectStore};
+use serde::Serialize;
+use server::authz::TokenScopes;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+use crate::commands::config::Config;
+
+/// The contents of the object and file written by the checks
+const CHECK_DATA: &[u8] = b"influxdb_iox server check";
+
+/// The fields a service account file needs for the GCP client to sign
+/// requests with it
+const SERVICE_ACCOUNT_FIELDS: &[&str] = &["client_email", "private_key"];
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "The SERVICE_ACCOUNT environment variable must be set to use the GCP bucket {}",
+        bucket
+    ))]
+    MissingServiceAccount { bucket: String },
+
+    #[snafu(display("Unable to read service account file {:?}: {}", path, source))]
+    ReadingServiceAccount { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to parse service account file {:?}: {}", path, source))]
+    ParsingServiceAccount {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Service account file {:?} has no {}", path, field))]
+    IncompleteServiceAccount { path: PathBuf, field: String },
+
+    #[snafu(display("Unable to load token scopes: {}", source))]
+    LoadingTokenScopes { source: server::authz::Error },
+
+    #[snafu(display("Unable to create object store: {}", source))]
+    CreatingObjectStore { source: super::Error },
+
+    #[snafu(display("Unable to write object {}: {}", path, source))]
+    WritingObject {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Unable to read back object {}: {}", path, source))]
+    ReadingObject {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Object {} read back differs from what was written", path))]
+    ObjectMismatch { path: String },
+
+    #[snafu(display("Unable to delete object {}: {}", path, source))]
+    DeletingObject {
+        path: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Unable to create directory {:?}: {}", path, source))]
+    CreatingDirectory { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to write file {:?}: {}", path, source))]
+    WritingFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to sync file {:?}: {}", path, source))]
+    SyncingFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to remove file {:?}: {}", path, source))]
+    RemovingFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to bind to {}: {}", addr, source))]
+    BindingAddress { addr: SocketAddr, source: io::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The outcome of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    /// The check doesn't apply to the configuration, or depends on one
+    /// that failed
+    Skipped,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Passed => "PASS",
+            Self::Failed => "FAIL",
+            Self::Skipped => "SKIP",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// A check run by `server check` and its outcome
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    /// What was checked, e.g. `object_store`
+    pub name: String,
+    pub status: CheckStatus,
+    /// What was verified, or why the check failed or was skipped
+    pub detail: String,
+    /// How long the check took, in milliseconds
+    pub duration_ms: u64,
+}
+
+/// The outcome of all the checks run by `server check`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl CheckReport {
+    /// Returns true if no check failed
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed)
+    }
+
+    /// Returns the status of the check `name`, if it was run
+    pub fn status(&self, name: &str) -> Option<CheckStatus> {
+        self.checks
+            .iter()
+            .find(|check| check.name == name)
+            .map(|check| check.status)
+    }
+
+    fn record(&mut self, name: &str, start: Instant, outcome: Result<Outcome>) {
+        let (status, detail) = match outcome {
+            Ok(Outcome::Passed(detail)) => (CheckStatus::Passed, detail),
+            Ok(Outcome::Skipped(detail)) => (CheckStatus::Skipped, detail),
+            Err(e) => (CheckStatus::Failed, e.to_string()),
+        };
+        self.checks.push(CheckResult {
+            name: name.to_string(),
+            status,
+            detail,
+            duration_ms: start.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(
+                f,
+                "{} {:<16} {} ({}ms)",
+                check.status, check.name, check.detail, check.duration_ms
+            )?;
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+            .count();
+        if failed == 0 {
+            writeln!(f, "All checks passed")
+        } else {
+            writeln!(f, "{} of {} checks failed", failed, self.checks.len())
+        }
+    }
+}
+
+/// The outcome of a check that didn't fail
+#[derive(Debug)]
+enum Outcome {
+    Passed(String),
+    Skipped(String),
+}
+
+/// Runs the checks of `config`, prints their report (as JSON if `json` is
+/// set) and returns true if none failed
+pub async fn main(config: &Config, json: bool) -> bool {
+    let report = run(config, std::env::var("SERVICE_ACCOUNT").ok()).await;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("reports can be serialized")
+        );
+    } else {
+        print!("{}", report);
+    }
+    report.passed()
+}
+
+/// Runs the checks of `config`, with the GCP credentials read from the
+/// service account file `service_account`
+pub async fn run(config: &Config, service_account: Option<String>) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    let start = Instant::now();
+    let outcome = check_gcp_credentials(config, service_account.as_deref());
+    let credentials_valid = outcome.is_ok();
+    report.record("gcp_credentials", start, outcome);
+
+    let start = Instant::now();
+    report.record("token_scopes", start, check_token_scopes(config));
+
+    // the GCP client panics on the first request if its credentials are
+    // missing or invalid
+    let start = Instant::now();
+    let outcome = if credentials_valid {
+        check_object_store(config).await
+    } else {
+        Ok(Outcome::Skipped(
+            "the GCP credentials are invalid".to_string(),
+        ))
+    };
+    report.record("object_store", start, outcome);
+
+    let start = Instant::now();
+    let outcome = match &config.wal_segment_directory {
+        Some(dir) => check_directory(dir),
+        None => Ok(Outcome::Skipped(
+            "no WAL segment directory configured".to_string(),
+        )),
+    };
+    report.record("wal_segment_dir", start, outcome);
+
+    let start = Instant::now();
+    report.record("http_bind", start, check_bind(config.http_bind_address));
+
+    let start = Instant::now();
+    report.record("grpc_bind", start, check_bind(config.grpc_bind_address));
+
+    report
+}
+
+/// Checks the service account file used to access the GCP bucket has the
+/// fields needed to sign requests
+fn check_gcp_credentials(config: &Config, service_account: Option<&str>) -> Result<Outcome> {
+    let bucket = match &config.gcp_bucket {
+        Some(bucket) => bucket,
+        None => return Ok(Outcome::Skipped("no GCP bucket configured".to_string())),
+    };
+    let path = PathBuf::from(service_account.context(MissingServiceAccount { bucket })?);
+
+    let data = fs::read(&path).context(ReadingServiceAccount { path: &path })?;
+    let account: serde_json::Value =
+        serde_json::from_slice(&data).context(ParsingServiceAccount { path: &path })?;
+    for field in SERVICE_ACCOUNT_FIELDS {
+        ensure!(
+            account.get(field).map_or(false, |value| value.is_string()),
+            IncompleteServiceAccount {
+                path: &path,
+                field: *field
+            }
+        );
+    }
+
+    Ok(Outcome::Passed(format!(
+        "service account {} in {:?}",
+        account["client_email"].as_str().unwrap_or_default(),
+        path
+    )))
+}
+
+/// Checks the token scopes file, if any, can be loaded
+fn check_token_scopes(config: &Config) -> Result<Outcome> {
+    let path = match &config.token_scopes_file {
+        Some(path) => path,
+        None => {
+            return Ok(Outcome::Skipped(
+                "no token scopes file configured: all requests are allowed".to_string(),
+            ))
+        }
+    };
+
+    let token_scopes = TokenScopes::from_file(path).context(LoadingTokenScopes)?;
+    Ok(Outcome::Passed(format!(
+        "{} token scopes in {:?}",
+        token_scopes.scopes.len(),
+        path
+    )))
+}
+
+/// Checks an object can be written to, read back from and deleted from
+/// the object store
+async fn check_object_store(config: &Config) -> Result<Outcome> {
+    if config.gcp_bucket.is_none() && config.database_directory.is_none() {
+        return Ok(Outcome::Skipped(
+            "no object store configured: data is only kept in memory".to_string(),
+        ));
+    }
+
+    let store = super::make_object_store(config).context(CreatingObjectStore)?;
+    let mut path = ObjectStorePath::default();
+    path.set_file_name(check_file_name());
+    let location = store.convert_path(&path);
+
+    let data = Bytes::from_static(CHECK_DATA);
+    store
+        .put(
+            &path,
+            futures::stream::once(async move { Ok(data) }),
+            CHECK_DATA.len(),
+        )
+        .await
+        .context(WritingObject { path: &location })?;
+
+    let read = read_object(&store, &path).await;
+    store
+        .delete(&path)
+        .await
+        .context(DeletingObject { path: &location })?;
+    let read = read.context(ReadingObject { path: &location })?;
+    ensure!(read == CHECK_DATA, ObjectMismatch { path: location });
+
+    Ok(Outcome::Passed(format!(
+        "wrote, read back and deleted {}",
+        location
+    )))
+}
+
+async fn read_object(
+    store: &ObjectStore,
+    path: &ObjectStorePath,
+) -> Result<Vec<u8>, object_store::Error> {
+    store
+        .get(path)
+        .await?
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await
+}
+
+/// Checks a file can be written to and synced in `dir`, as WAL segments
+/// are
+fn check_directory(dir: &Path) -> Result<Outcome> {
+    fs::create_dir_all(dir).context(CreatingDirectory { path: dir })?;
+
+    let path = dir.join(check_file_name());
+    let mut file = File::create(&path).context(WritingFile { path: &path })?;
+    file.write_all(CHECK_DATA)
+        .context(WritingFile { path: &path })?;
+    file.sync_all().context(SyncingFile { path: &path })?;
+    fs::remove_file(&path).context(RemovingFile { path: &path })?;
+
+    Ok(Outcome::Passed(format!("wrote and synced {:?}", path)))
+}
+
+/// Checks `addr` can be bound, so the server will be able to listen on it
+fn check_bind(addr: SocketAddr) -> Result<Outcome> {
+    let listener = TcpListener::bind(addr).context(BindingAddress { addr })?;
+    drop(listener);
+    Ok(Outcome::Passed(format!("bound {}", addr)))
+}
+
+/// The name of the object and file written by the checks, unique so that
+/// checks run at the same time don't interfere
+fn check_file_name() -> String {
+    format!(
+        "iox_check_{}_{}.txt",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn config(args: &[&str]) -> Config {
+        // unless set, bind to ports picked by the OS, so the checks pass
+        // while a server is running
+        let mut all_args = vec!["server"];
+        for flag in &["--api-bind", "--grpc-bind"] {
+            if !args.contains(flag) {
+                all_args.extend_from_slice(&[*flag, "127.0.0.1:0"]);
+            }
+        }
+        all_args.extend_from_slice(args);
+        Config::from_iter_safe(all_args).unwrap()
+    }
+
+    #[tokio::test]
+    async fn checks_the_configured_resources() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let wal_dir = data_dir.path().join("wal");
+        let config = config(&[
+            "--data-dir",
+            data_dir.path().to_str().unwrap(),
+            "--wal-segment-dir",
+            wal_dir.to_str().unwrap(),
+        ]);
+
+        let report = run(&config, None).await;
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.status("gcp_credentials"), Some(CheckStatus::Skipped));
+        assert_eq!(report.status("token_scopes"), Some(CheckStatus::Skipped));
+        assert_eq!(report.status("object_store"), Some(CheckStatus::Passed));
+        assert_eq!(report.status("wal_segment_dir"), Some(CheckStatus::Passed));
+        assert_eq!(report.status("http_bind"), Some(CheckStatus::Passed));
+        assert_eq!(report.status("grpc_bind"), Some(CheckStatus::Passed));
+
+        // the checks leave nothing behind
+        assert_eq!(fs::read_dir(&wal_dir).unwrap().count(), 0);
+        let entries: Vec<_> = fs::read_dir(data_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["wal"]);
+    }
+
+    #[tokio::test]
+    async fn reports_failed_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_scopes = dir.path().join("scopes.json");
+        fs::write(&token_scopes, "not json").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = config(&[
+            "--gcp-bucket",
+            "mybucket",
+            "--token-scopes-file",
+            token_scopes.to_str().unwrap(),
+            "--api-bind",
+            &addr,
+        ]);
+
+        let report = run(&config, None).await;
+        assert!(!report.passed());
+        let failed: Vec<_> = report
+            .checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["gcp_credentials", "token_scopes", "http_bind"]);
+        assert_eq!(report.status("object_store"), Some(CheckStatus::Skipped));
+        assert_eq!(report.status("grpc_bind"), Some(CheckStatus::Passed));
+
+        let text = report.to_string();
+        assert!(text.contains(
+            "FAIL gcp_credentials  The SERVICE_ACCOUNT environment variable must be set to use the GCP bucket mybucket"
+        ), "{}", text);
+        assert!(text.ends_with("3 of 6 checks failed\n"), "{}", text);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["checks"][0]["name"], "gcp_credentials");
+        assert_eq!(json["checks"][0]["status"], "failed");
+    }
+
+    #[test]
+    fn validates_service_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("account.json");
+        let config = config(&["--gcp-bucket", "mybucket"]);
+
+        fs::write(&path, r#"{"client_email": "iox@example.com"}"#).unwrap();
+        let err = check_gcp_credentials(&config, path.to_str()).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::IncompleteServiceAccount { field, .. } if field == "private_key"
+        ));
+
+        fs::write(
+            &path,
+            r#"{"client_email": "iox@example.com", "private_key": "key"}"#,
+        )
+        .unwrap();
+        let outcome = check_gcp_credentials(&config, path.to_str()).unwrap();
+        assert!(matches!(
+            outcome,
+            Outcome::Passed(detail) if detail.starts_with("service account iox@example.com")
+        ));
+    }
+}
@@ -10,29 +10,48 @@
 //! database names and may remove this quasi /v2 API.
 
 // Influx crates
-use arrow_deps::{arrow, datafusion::physical_plan::collect};
+use arrow_deps::datafusion::physical_plan::collect;
 use data_types::{
-    database_rules::DatabaseRules,
-    names::{org_and_bucket_to_database, OrgBucketMappingError},
-    DatabaseName,
+    access_policy, database_rules::DatabaseRules, names::OrgBucketMappingError,
+    time_zone::TimeZone, DatabaseName,
 };
 use influxdb_line_protocol::parse_lines;
-use object_store::path::ObjectStorePath;
-use query::{frontend::sql::SQLQueryPlanner, Database, DatabaseStore};
-use server::{ConnectionManager, Server as AppServer};
+use query::{
+    exec::{batch_size::BatchSizeConfig, QueryMetrics},
+    frontend::sql::{ChunkSelection, SQLQueryPlanner},
+    output::QueryOutputFormat,
+    predicate::TimestampRange,
+    Database, DatabaseErrorKind, DatabaseStore,
+};
+use server::{
+    ack::{WriteAck, WriteAckLevel},
+    audit::AuditAction,
+    authz::{Action, Principal},
+    db::{
+        copy_partition::{self, CopyMode},
+        delete::{self, TagMatcher},
+    },
+    latency::OperationKind,
+    quota,
+    recovery::RecoveryState,
+    ConnectionManager, Server as AppServer,
+};
 
 // External crates
 use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
 use futures::{self, StreamExt};
-use http::header::CONTENT_ENCODING;
+use http::header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH};
 use hyper::{Body, Method, Request, Response, StatusCode};
 use routerify::{prelude::*, Middleware, RequestInfo, Router, RouterService};
 use serde::{Deserialize, Serialize};
-use snafu::{OptionExt, ResultExt, Snafu};
-use tracing::{debug, error, info};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::{debug, error, info, warn};
 
 use std::{fmt::Debug, str, sync::Arc};
 
+use super::allocator::{self, AllocatorStats};
+
 #[derive(Debug, Snafu)]
 pub enum ApplicationError {
     // Internal (unexpected) errors
@@ -51,7 +70,7 @@ pub enum ApplicationError {
     BucketMappingError { source: OrgBucketMappingError },
 
     #[snafu(display(
-        "Internal error writing points into org {}, bucket {}:  {}",
+        "Error writing points into org {}, bucket {}:  {}",
         org,
         bucket_name,
         source
@@ -59,7 +78,7 @@ pub enum ApplicationError {
     WritingPoints {
         org: String,
         bucket_name: String,
-        source: Box<dyn std::error::Error + Send + Sync>,
+        source: server::Error,
     },
 
     #[snafu(display("Error planning query {}: {}", query, source))]
@@ -75,12 +94,19 @@ pub enum ApplicationError {
     },
 
     // Application level errors
-    #[snafu(display("Bucket {} not found in org {}", bucket, org))]
-    BucketNotFound { org: String, bucket: String },
-
     #[snafu(display("Body exceeds limit of {} bytes", max_body_size))]
     RequestSizeExceeded { max_body_size: usize },
 
+    #[snafu(display("Write exceeds limit of {} lines", max_lines))]
+    TooManyLines { max_lines: usize },
+
+    #[snafu(display(
+        "SQL query of {} bytes exceeds limit of {} bytes",
+        length,
+        max_sql_bytes
+    ))]
+    SqlQueryTooLong { length: usize, max_sql_bytes: usize },
+
     #[snafu(display("Expected query string in request, but none was provided"))]
     ExpectedQueryString {},
 
@@ -98,6 +124,23 @@ pub enum ApplicationError {
     #[snafu(display("Invalid request body: {}", source))]
     InvalidRequestBody { source: serde_json::error::Error },
 
+    #[snafu(display("Invalid write acknowledgement level: {}", source))]
+    InvalidWriteAckLevel { source: server::ack::Error },
+
+    #[snafu(display("Invalid chunk selection: {}", source))]
+    InvalidChunkSelection { source: query::frontend::sql::Error },
+
+    #[snafu(display("Invalid query output format: {}", source))]
+    InvalidQueryOutputFormat { source: query::output::Error },
+
+    #[snafu(display("Invalid time zone: {}", source))]
+    InvalidTimeZone {
+        source: data_types::time_zone::Error,
+    },
+
+    #[snafu(display("Error formatting query results: {}", source))]
+    FormattingQueryResults { source: query::output::Error },
+
     #[snafu(display("Invalid content encoding: {}", content_encoding))]
     InvalidContentEncoding { content_encoding: String },
 
@@ -136,29 +179,104 @@ pub enum ApplicationError {
     #[snafu(display("Error creating database: {}", source))]
     ErrorCreatingDatabase { source: server::Error },
 
+    #[snafu(display("Error deleting database: {}", source))]
+    ErrorDeletingDatabase { source: server::Error },
+
+    #[snafu(display("Error creating namespace: {}", source))]
+    ErrorCreatingNamespace { source: server::Error },
+
+    #[snafu(display("Error restoring database: {}", source))]
+    ErrorRestoringDatabase { source: server::Error },
+
+    #[snafu(display("Error copying partition: {}", source))]
+    ErrorCopyingPartition { source: server::Error },
+
+    #[snafu(display("Error replaying WALs: {}", source))]
+    ErrorFanningInWals { source: server::Error },
+
+    #[snafu(display("Error deleting series: {}", source))]
+    ErrorDeletingSeries { source: server::Error },
+
+    #[snafu(display("Error ingesting files: {}", source))]
+    ErrorIngestingFiles { source: server::ingest::Error },
+
+    #[snafu(display("Invalid writer id {}: {}", writer_id, source))]
+    InvalidWriterId {
+        writer_id: String,
+        source: std::num::ParseIntError,
+    },
+
     #[snafu(display("Invalid database name: {}", source))]
     DatabaseNameError {
         source: data_types::DatabaseNameError,
     },
 
-    #[snafu(display("Database {} not found", name))]
-    DatabaseNotFound { name: String },
+    #[snafu(display("{}", source))]
+    DatabaseUnavailable { source: server::Error },
+
+    #[snafu(display("Error starting query: {}", source))]
+    StartingQuery { source: server::db::Error },
+
+    #[snafu(display("Query of database {} not authorized: {}", db_name, source))]
+    QueryNotAuthorized {
+        db_name: String,
+        source: access_policy::Error,
+    },
+
+    #[snafu(display("Error rolling over partition {}: {}", partition_key, source))]
+    RollingPartition {
+        partition_key: String,
+        source: server::db::Error,
+    },
+
+    #[snafu(display("Error snapshotting partition {}: {}", partition_key, source))]
+    SnapshottingPartition {
+        partition_key: String,
+        source: server::snapshot::Error,
+    },
+
+    #[snafu(display("Error listing tag values of database {}: {}", db_name, source))]
+    ListingTagValues {
+        db_name: String,
+        source: server::db::autocomplete::Error,
+    },
+
+    #[snafu(display("Error warming database {}: {}", db_name, source))]
+    WarmingDatabase {
+        db_name: String,
+        source: server::warm::Error,
+    },
+
+    #[snafu(display("{}", source))]
+    NotAuthorized { source: server::Error },
+
+    #[snafu(display("Error dumping heap profile: {}", source))]
+    DumpingHeapProfile { source: allocator::Error },
+
+    #[snafu(display("{}", source))]
+    Auditing { source: server::Error },
 }
 
 impl ApplicationError {
     pub fn response(&self) -> Result<Response<Body>, Self> {
         Ok(match self {
-            Self::BucketByName { .. } => self.internal_error(),
+            Self::BucketByName { source, .. } => self.database_error(source.as_ref()),
             Self::BucketMappingError { .. } => self.internal_error(),
-            Self::WritingPoints { .. } => self.internal_error(),
+            Self::WritingPoints { source, .. } => self.server_error(source),
             Self::PlanningSQLQuery { .. } => self.bad_request(),
             Self::Query { .. } => self.internal_error(),
             Self::QueryError { .. } => self.bad_request(),
-            Self::BucketNotFound { .. } => self.not_found(),
-            Self::RequestSizeExceeded { .. } => self.bad_request(),
+            Self::RequestSizeExceeded { .. } => self.payload_too_large(),
+            Self::TooManyLines { .. } => self.payload_too_large(),
+            Self::SqlQueryTooLong { .. } => self.payload_too_large(),
             Self::ExpectedQueryString { .. } => self.bad_request(),
             Self::InvalidQueryString { .. } => self.bad_request(),
             Self::InvalidRequestBody { .. } => self.bad_request(),
+            Self::InvalidWriteAckLevel { .. } => self.bad_request(),
+            Self::InvalidChunkSelection { .. } => self.bad_request(),
+            Self::InvalidQueryOutputFormat { .. } => self.bad_request(),
+            Self::InvalidTimeZone { .. } => self.bad_request(),
+            Self::FormattingQueryResults { .. } => self.internal_error(),
             Self::InvalidContentEncoding { .. } => self.bad_request(),
             Self::ReadingHeaderAsUtf8 { .. } => self.bad_request(),
             Self::ReadingBody { .. } => self.bad_request(),
@@ -166,40 +284,162 @@ impl ApplicationError {
             Self::ParsingLineProtocol { .. } => self.bad_request(),
             Self::ReadingBodyAsGzip { .. } => self.bad_request(),
             Self::RouteNotFound { .. } => self.not_found(),
-            Self::DatabaseError { .. } => self.internal_error(),
+            Self::DatabaseError { source, .. } => self.database_error(source.as_ref()),
             Self::JsonGenerationError { .. } => self.internal_error(),
-            Self::ErrorCreatingDatabase { .. } => self.bad_request(),
+            Self::ErrorCreatingDatabase { source } => self.server_error(source),
+            Self::ErrorDeletingDatabase { source } => self.server_error(source),
+            Self::ErrorCreatingNamespace { source } => self.server_error(source),
+            Self::ErrorRestoringDatabase { source } => self.server_error(source),
+            Self::ErrorCopyingPartition { source } => self.server_error(source),
+            Self::ErrorFanningInWals { source } => self.server_error(source),
+            Self::ErrorDeletingSeries { source } => self.server_error(source),
+            Self::ErrorIngestingFiles { source } => match source {
+                server::ingest::Error::InvalidDatabaseName { .. } => self.bad_request(),
+                server::ingest::Error::DatabaseUnavailable { source } => self.server_error(source),
+                server::ingest::Error::ListingDirectory { .. } => self.not_found(),
+                _ => self.internal_error(),
+            },
+            Self::InvalidWriterId { .. } => self.bad_request(),
             Self::DatabaseNameError { .. } => self.bad_request(),
-            Self::DatabaseNotFound { .. } => self.not_found(),
+            Self::DatabaseUnavailable { source } => self.server_error(source),
+            Self::StartingQuery { source } => self.database_error_kind(source.kind()),
+            Self::QueryNotAuthorized { source, .. } => match source {
+                access_policy::Error::MissingToken | access_policy::Error::UnknownToken => {
+                    self.unauthorized()
+                }
+                access_policy::Error::MissingAttribute { .. }
+                | access_policy::Error::RestrictedRows { .. } => self.forbidden(),
+            },
+            Self::RollingPartition { source, .. } => self.database_error_kind(source.kind()),
+            Self::SnapshottingPartition { .. } => self.internal_error(),
+            Self::ListingTagValues { .. } => self.internal_error(),
+            Self::WarmingDatabase { .. } => self.internal_error(),
+            Self::NotAuthorized { .. } => self.forbidden(),
+            Self::DumpingHeapProfile { source } => match source {
+                allocator::Error::NotBuiltWithJemalloc | allocator::Error::ProfilingNotEnabled => {
+                    self.not_implemented()
+                }
+                _ => self.internal_error(),
+            },
+            Self::Auditing { .. } => self.internal_error(),
         })
     }
 
     fn bad_request(&self) -> Response<Body> {
-        Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(self.body())
-            .unwrap()
+        self.error_response(StatusCode::BAD_REQUEST)
+    }
+
+    fn unauthorized(&self) -> Response<Body> {
+        self.error_response(StatusCode::UNAUTHORIZED)
+    }
+
+    fn forbidden(&self) -> Response<Body> {
+        self.error_response(StatusCode::FORBIDDEN)
+    }
+
+    fn not_found(&self) -> Response<Body> {
+        self.error_response(StatusCode::NOT_FOUND)
+    }
+
+    fn payload_too_large(&self) -> Response<Body> {
+        self.error_response(StatusCode::PAYLOAD_TOO_LARGE)
+    }
+
+    fn conflict(&self) -> Response<Body> {
+        self.error_response(StatusCode::CONFLICT)
+    }
+
+    fn too_many_requests(&self) -> Response<Body> {
+        self.error_response(StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    fn not_implemented(&self) -> Response<Body> {
+        self.error_response(StatusCode::NOT_IMPLEMENTED)
+    }
+
+    fn service_unavailable(&self) -> Response<Body> {
+        self.error_response(StatusCode::SERVICE_UNAVAILABLE)
+    }
+
+    fn insufficient_storage(&self) -> Response<Body> {
+        self.error_response(StatusCode::INSUFFICIENT_STORAGE)
     }
 
     fn internal_error(&self) -> Response<Body> {
+        // The details of internal errors are logged by the handlers but
+        // not returned, as they are of no use to clients and may expose
+        // the internals of the server
         Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(self.body())
+            .body(json_error_body(
+                INTERNAL_ERROR_MESSAGE,
+                Some(self.api_error_code()),
+            ))
             .unwrap()
     }
 
-    fn not_found(&self) -> Response<Body> {
+    fn error_response(&self, status: StatusCode) -> Response<Body> {
         Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
+            .status(status)
+            .body(json_error_body(
+                &self.to_string(),
+                Some(self.api_error_code()),
+            ))
             .unwrap()
     }
 
-    fn body(&self) -> Body {
-        let json =
-            serde_json::json!({"error": self.to_string(), "error_code": self.api_error_code()})
-                .to_string();
-        Body::from(json)
+    /// Responds according to the cause of an error from the server
+    fn server_error(&self, source: &server::Error) -> Response<Body> {
+        match source {
+            server::Error::DatabaseNotFound { .. }
+            | server::Error::DeletedDatabaseNotFound { .. } => self.not_found(),
+            server::Error::InvalidDatabaseName { .. }
+            | server::Error::InvalidReplicatedWrite { .. }
+            | server::Error::InvalidTableWriteRules { .. }
+            | server::Error::NonFiniteFloatsRejected { .. }
+            | server::Error::UnknownWalBackend { .. }
+            | server::Error::NoWalBuffer { .. } => self.bad_request(),
+            server::Error::TableWriteRejected { .. } => self.forbidden(),
+            server::Error::WriteQuotaExceeded {
+                source: quota::Error::PartitionKey { .. },
+                ..
+            } => self.bad_request(),
+            server::Error::WriteQuotaExceeded { .. } => self.too_many_requests(),
+            server::Error::WalDiskFull { .. } => self.insufficient_storage(),
+            server::Error::DatabaseNotReady { .. } => self.service_unavailable(),
+            server::Error::DatabaseAlreadyExists { .. } | server::Error::DatabaseDeleted { .. } => {
+                self.conflict()
+            }
+            server::Error::CopyingPartition { source } => match source {
+                copy_partition::Error::PartitionNotFound { .. } => self.not_found(),
+                copy_partition::Error::PartitionExists { .. } => self.conflict(),
+                copy_partition::Error::SameDatabase { .. }
+                | copy_partition::Error::NoObjectStore { .. } => self.bad_request(),
+                _ => self.internal_error(),
+            },
+            server::Error::DeletingSeries {
+                source: delete::Error::NoTagMatchers { .. },
+            } => self.bad_request(),
+            _ => self.database_error(source),
+        }
+    }
+
+    /// Responds according to the kind of the `query::DatabaseError`
+    /// that caused `source`, if any
+    fn database_error(&self, source: &(dyn std::error::Error + 'static)) -> Response<Body> {
+        match query::DatabaseError::find_in(source) {
+            Some(e) => self.database_error_kind(e.kind()),
+            None => self.internal_error(),
+        }
+    }
+
+    fn database_error_kind(&self, kind: DatabaseErrorKind) -> Response<Body> {
+        match kind {
+            DatabaseErrorKind::NotFound => self.not_found(),
+            DatabaseErrorKind::SchemaConflict => self.conflict(),
+            DatabaseErrorKind::ResourceExhausted => self.too_many_requests(),
+            DatabaseErrorKind::Internal => self.internal_error(),
+        }
     }
 
     /// Map the error type into an API error code.
@@ -208,9 +448,17 @@ impl ApplicationError {
 
         match self {
             Self::DatabaseNameError { .. } => ApiErrorCode::DB_INVALID_NAME,
-            Self::DatabaseNotFound { .. } => ApiErrorCode::DB_NOT_FOUND,
 
             // Some errors are wrapped
+            Self::WritingPoints {
+                source: server::Error::DatabaseNotFound { .. },
+                ..
+            } => ApiErrorCode::DB_NOT_FOUND,
+
+            Self::DatabaseUnavailable {
+                source: server::Error::DatabaseNotFound { .. },
+            } => ApiErrorCode::DB_NOT_FOUND,
+
             Self::ErrorCreatingDatabase {
                 source: server::Error::InvalidDatabaseName { .. },
             } => ApiErrorCode::DB_INVALID_NAME,
@@ -223,6 +471,10 @@ impl ApplicationError {
                 source: server::Error::DatabaseAlreadyExists { .. },
             } => ApiErrorCode::DB_ALREADY_EXISTS,
 
+            Self::RequestSizeExceeded { .. }
+            | Self::TooManyLines { .. }
+            | Self::SqlQueryTooLong { .. } => ApiErrorCode::REQUEST_TOO_LARGE,
+
             // A "catch all" error code
             _ => ApiErrorCode::UNKNOWN,
         }
@@ -230,15 +482,55 @@ impl ApplicationError {
     }
 }
 
-const MAX_SIZE: usize = 10_485_760; // max write request size of 10MB
+/// Limits on the size of requests, so that requests too large to be
+/// handled are rejected while they are read rather than once they have
+/// been read into memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestLimits {
+    /// The maximum size in bytes of a request body, both as sent and
+    /// after decompression
+    pub max_body_bytes: usize,
+    /// The maximum number of lines of line protocol in a write
+    pub max_write_lines: usize,
+    /// The maximum length in bytes of a SQL query
+    pub max_sql_bytes: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 10_485_760, // 10MB
+            max_write_lines: 1_000_000,
+            max_sql_bytes: 65_536,
+        }
+    }
+}
+
+/// Returns the limits of the server the request was sent to
+fn request_limits(req: &Request<Body>) -> RequestLimits {
+    *req.data::<RequestLimits>().expect("request limits")
+}
+
+/// The message returned for errors that are not caused by the request
+const INTERNAL_ERROR_MESSAGE: &str = "Internal error";
+
+/// Builds the JSON body of an error response
+fn json_error_body(message: &str, error_code: Option<u32>) -> Body {
+    let json = match error_code {
+        Some(error_code) => serde_json::json!({"error": message, "error_code": error_code}),
+        None => serde_json::json!({ "error": message }),
+    };
+    Body::from(json.to_string())
+}
 
-fn router<M>(server: Arc<AppServer<M>>) -> Router<Body, ApplicationError>
+fn router<M>(server: Arc<AppServer<M>>, limits: RequestLimits) -> Router<Body, ApplicationError>
 where
     M: ConnectionManager + Send + Sync + Debug + 'static,
 {
     // Create a router and specify the the handlers.
     Router::builder()
         .data(server)
+        .data(limits)
         .middleware(Middleware::pre(|req| async move {
             info!(request = ?req, "Processing request");
             Ok(req)
@@ -249,12 +541,53 @@ where
         })) // this endpoint is for API backward compatibility with InfluxDB 2.x
         .post("/api/v2/write", write_handler::<M>)
         .get("/ping", ping)
+        .get("/metrics", metrics_handler::<M>)
+        .get("/debug/pprof/heap", heap_profile_handler::<M>)
         .get("/api/v2/read", read_handler::<M>)
         .put("/iox/api/v1/databases/:name", create_database_handler::<M>)
+        .put(
+            "/iox/api/v1/namespaces/:org/:bucket",
+            create_namespace_handler::<M>,
+        )
+        .get("/iox/api/v1/recovery", get_recovery_handler::<M>)
         .get("/iox/api/v1/databases/:name", get_database_handler::<M>)
+        .get(
+            "/iox/api/v1/databases/:name/schema_changes",
+            get_schema_changes_handler::<M>,
+        )
+        .get(
+            "/iox/api/v1/databases/:name/summary",
+            get_database_summary_handler::<M>,
+        )
+        .get(
+            "/iox/api/v1/databases/:name/tag_values",
+            get_tag_values_handler::<M>,
+        )
+        .delete("/iox/api/v1/databases/:name", delete_database_handler::<M>)
+        .post(
+            "/iox/api/v1/databases/:name/restore",
+            restore_database_handler::<M>,
+        )
+        .post(
+            "/iox/api/v1/databases/:name/copy_partition",
+            copy_partition_handler::<M>,
+        )
+        .post(
+            "/iox/api/v1/databases/:name/fan_in",
+            fan_in_wals_handler::<M>,
+        )
+        .post(
+            "/iox/api/v1/databases/:name/delete_series",
+            delete_series_handler::<M>,
+        )
+        .post(
+            "/iox/api/v1/databases/:name/ingest",
+            ingest_directory_handler::<M>,
+        )
         .put("/iox/api/v1/id", set_writer_handler::<M>)
         .get("/api/v1/partitions", list_partitions_handler::<M>)
         .post("/api/v1/snapshot", snapshot_partition_handler::<M>)
+        .post("/api/v1/warm", warm_database_handler::<M>)
         // Specify the error handler to handle any errors caused by
         // a route or any middleware.
         .err_handler_with_info(error_handler)
@@ -270,10 +603,9 @@ async fn error_handler(err: routerify::Error, req: RequestInfo) -> Response<Body
     let uri = req.uri().clone();
     error!(error = ?err, error_message = ?err.to_string(), method = ?method, uri = ?uri, "Error while handling request");
 
-    let json = serde_json::json!({"error": err.to_string()}).to_string();
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
-        .body(Body::from(json))
+        .body(json_error_body(INTERNAL_ERROR_MESSAGE, None))
         .unwrap()
 }
 
@@ -282,14 +614,28 @@ async fn error_handler(err: routerify::Error, req: RequestInfo) -> Response<Body
 struct WriteInfo {
     org: String,
     bucket: String,
+    /// What the write has to reach before it is acknowledged: `buffered`,
+    /// `wal` or `replicated` (the default)
+    ack: Option<String>,
 }
 
+/// Response header saying whether a write was stored in the mutable buffer
+const WRITE_BUFFERED_HEADER: &str = "X-IOx-Write-Buffered";
+/// Response header saying whether the WAL segment holding a write was persisted
+const WRITE_WAL_HEADER: &str = "X-IOx-Write-WAL";
+/// Response header with the number of host groups a write was replicated to
+const WRITE_REPLICATED_HEADER: &str = "X-IOx-Write-Replicated";
+/// Response header with the number of host groups a write is still being
+/// replicated to
+const WRITE_REPLICATING_HEADER: &str = "X-IOx-Write-Replicating";
+
 /// Parse the request's body into raw bytes, applying size limits and
 /// content encoding as needed.
 async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError> {
     // clippy says the const needs to be assigned to a local variable:
     // error: a `const` item with interior mutability should not be borrowed
     let header_name = CONTENT_ENCODING;
+    let max_size = request_limits(&req).max_body_bytes;
     let ungzip = match req.headers().get(&header_name) {
         None => false,
         Some(content_encoding) => {
@@ -303,17 +649,31 @@ async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError
         }
     };
 
+    // reject bodies declared too large without reading them
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    ensure!(
+        content_length.map_or(true, |length| length <= max_size),
+        RequestSizeExceeded {
+            max_body_size: max_size
+        }
+    );
+
     let mut payload = req.into_body();
 
     let mut body = BytesMut::new();
     while let Some(chunk) = payload.next().await {
-        let chunk = chunk.expect("Should have been able to read the next chunk");
+        let chunk = chunk.context(ReadingBody)?;
         // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > MAX_SIZE {
-            return Err(ApplicationError::RequestSizeExceeded {
-                max_body_size: MAX_SIZE,
-            });
-        }
+        ensure!(
+            body.len() + chunk.len() <= max_size,
+            RequestSizeExceeded {
+                max_body_size: max_size
+            }
+        );
         body.extend_from_slice(&chunk);
     }
     let body = body.freeze();
@@ -323,13 +683,20 @@ async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError
         use std::io::Read;
         let decoder = flate2::read::GzDecoder::new(&body[..]);
 
-        // Read at most MAX_SIZE bytes to prevent a decompression bomb based
-        // DoS.
-        let mut decoder = decoder.take(MAX_SIZE as u64);
+        // Read at most one byte more than the max size to prevent a
+        // decompression bomb based DoS, while still telling a body of the
+        // max size from a larger one.
+        let mut decoder = decoder.take(max_size as u64 + 1);
         let mut decoded_data = Vec::new();
         decoder
             .read_to_end(&mut decoded_data)
             .context(ReadingBodyAsGzip)?;
+        ensure!(
+            decoded_data.len() <= max_size,
+            RequestSizeExceeded {
+                max_body_size: max_size
+            }
+        );
         Ok(decoded_data.into())
     } else {
         Ok(body)
@@ -366,16 +733,38 @@ where
         query_string: String::from(query),
     })?;
 
-    let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket)
+    let ack = match &write_info.ack {
+        Some(ack) => ack.parse().context(InvalidWriteAckLevel)?,
+        None => WriteAckLevel::default(),
+    };
+
+    let db_name = server
+        .namespace_db_name(&write_info.org, &write_info.bucket)
+        .await
         .context(BucketMappingError)?;
+    authorize(&server, &req, Action::Write, Some(db_name.as_str())).await?;
+
+    // only the latency of writes to existing databases is recorded, so
+    // writes to made up buckets don't add metric series
+    server.require_db(&db_name).context(WritingPoints {
+        org: write_info.org.clone(),
+        bucket_name: write_info.bucket.clone(),
+    })?;
+    let _timer = server
+        .latency_metrics()
+        .start(&db_name, OperationKind::Write);
 
+    let max_lines = request_limits(&req).max_write_lines;
     let body = parse_body(req).await?;
 
     let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
 
-    let lines = parse_lines(body)
-        .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
-        .context(ParsingLineProtocol)?;
+    // stop parsing as soon as the write has too many lines
+    let mut lines = Vec::new();
+    for line in parse_lines(body) {
+        ensure!(lines.len() < max_lines, TooManyLines { max_lines });
+        lines.push(line.context(ParsingLineProtocol)?);
+    }
 
     debug!(
         "Inserting {} lines into database {} (org {} bucket {})",
@@ -385,19 +774,28 @@ where
         write_info.bucket
     );
 
-    server
-        .write_lines(&db_name, &lines)
+    let write_ack = server
+        .write_lines_with_ack(&db_name, &lines, ack)
         .await
-        .map_err(|e| Box::new(e) as _)
         .context(WritingPoints {
             org: write_info.org.clone(),
             bucket_name: write_info.bucket.clone(),
         })?;
 
-    Ok(Response::builder()
+    Ok(write_ack_response(write_ack))
+}
+
+/// The response to a write, with headers saying how durable it was when
+/// it was acknowledged
+fn write_ack_response(write_ack: WriteAck) -> Response<Body> {
+    Response::builder()
         .status(StatusCode::NO_CONTENT)
+        .header(WRITE_BUFFERED_HEADER, write_ack.buffered.to_string())
+        .header(WRITE_WAL_HEADER, write_ack.wal.to_string())
+        .header(WRITE_REPLICATED_HEADER, write_ack.replicated.to_string())
+        .header(WRITE_REPLICATING_HEADER, write_ack.replicating.to_string())
         .body(Body::empty())
-        .unwrap())
+        .unwrap()
 }
 
 #[derive(Deserialize, Debug)]
@@ -408,6 +806,19 @@ struct ReadInfo {
     // TODO This is currently a "SQL" request -- should be updated to conform
     // to the V2 API for reading (using timestamps, etc).
     sql_query: String,
+    /// If set, overrides the server's maximum number of rows in each
+    /// record batch produced by the query
+    batch_size: Option<usize>,
+    /// How to format the results: `pretty` (the default), `csv` or `json`
+    /// (JSON lines)
+    format: Option<String>,
+    /// The time zone to show times in, UTC if not set: an offset from
+    /// UTC (e.g. `-08:00`), a zone (e.g. `Europe/Berlin`) or a POSIX TZ
+    /// string
+    time_zone: Option<String>,
+    /// Which chunks the query reads: `all` (the default), `in_memory` or
+    /// `persisted`. A hint in the query takes precedence.
+    chunks: Option<String>,
 }
 
 #[tracing::instrument(level = "debug")]
@@ -441,30 +852,133 @@ async fn read<M: ConnectionManager + Send + Sync + Debug + 'static>(
         query_string: query,
     })?;
 
-    let planner = SQLQueryPlanner::default();
+    let max_sql_bytes = request_limits(&req).max_sql_bytes;
+    ensure!(
+        read_info.sql_query.len() <= max_sql_bytes,
+        SqlQueryTooLong {
+            length: read_info.sql_query.len(),
+            max_sql_bytes
+        }
+    );
+
     let executor = server.executor();
+    let metrics = Arc::new(QueryMetrics::default());
+    let mut planner = SQLQueryPlanner::default().with_metrics(Arc::clone(&metrics));
+    if let Some(batch_size) = read_info.batch_size {
+        let batch_config = BatchSizeConfig {
+            max_rows: batch_size.max(1),
+            ..*executor.batch_config()
+        };
+        planner = planner.with_batch_config(batch_config);
+    }
+    if let Some(chunks) = &read_info.chunks {
+        let chunk_selection: ChunkSelection = chunks.parse().context(InvalidChunkSelection)?;
+        planner = planner.with_chunk_selection(chunk_selection);
+    }
+
+    let format: QueryOutputFormat = match &read_info.format {
+        Some(format) => format.parse().context(InvalidQueryOutputFormat)?,
+        None => QueryOutputFormat::default(),
+    };
+    let time_zone: TimeZone = match &read_info.time_zone {
+        Some(time_zone) => time_zone.parse().context(InvalidTimeZone)?,
+        None => TimeZone::UTC,
+    };
 
-    let db_name = org_and_bucket_to_database(&read_info.org, &read_info.bucket)
+    let db_name = server
+        .namespace_db_name(&read_info.org, &read_info.bucket)
+        .await
         .context(BucketMappingError)?;
+    authorize(&server, &req, Action::Read, Some(db_name.as_str())).await?;
 
-    let db = server.db(&db_name).await.context(BucketNotFound {
-        org: read_info.org.clone(),
-        bucket: read_info.bucket.clone(),
-    })?;
+    let db = server.require_db(&db_name).context(DatabaseUnavailable)?;
+    let _timer = server
+        .latency_metrics()
+        .start(&db_name, OperationKind::of_sql(&read_info.sql_query));
 
-    let physical_plan = planner
-        .query(db.as_ref(), &read_info.sql_query, executor.as_ref())
-        .await
-        .context(PlanningSQLQuery { query })?;
+    let _query = db.start_query().context(StartingQuery)?;
 
-    let batches = collect(physical_plan)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(Query { db_name })?;
+    // the rows of databases with an access policy are filtered according
+    // to the principal whose token the request was sent with
+    let principal = db.authenticate(request_token(&req)?);
+    let principal_name = match &principal {
+        Ok(Some(principal)) => Some(principal.name.clone()),
+        _ => None,
+    };
+    let batches = async {
+        let column_filters = principal
+            .and_then(|principal| db.column_filters(principal))
+            .context(QueryNotAuthorized {
+                db_name: db_name.as_str(),
+            })?;
+
+        let physical_plan = planner
+            .with_column_filters(column_filters)
+            .query(db.as_ref(), &read_info.sql_query, executor.as_ref())
+            .await
+            .context(PlanningSQLQuery { query })?;
 
-    let results = arrow::util::pretty::pretty_format_batches(&batches).unwrap();
+        collect(physical_plan)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(Query {
+                db_name: db_name.as_str(),
+            })
+    }
+    .await;
+
+    let action = AuditAction::new("query")
+        .principal(principal_name.as_deref())
+        .database(&db_name)
+        .statement(&read_info.sql_query);
+    server.audit(action, &batches).await.context(Auditing)?;
+    let batches = batches?;
+    metrics.add_batches(&batches);
+    metrics.emit();
+
+    let results = format
+        .format_in(&batches, &time_zone)
+        .context(FormattingQueryResults)?;
+
+    Ok(Response::builder()
+        .header("Content-Type", format.content_type())
+        .body(Body::from(results.into_bytes()))
+        .expect("builder should be successful"))
+}
+
+/// Returns the token of the `Authorization: Token <token>` header of the
+/// request, if any
+fn request_token(req: &Request<Body>) -> Result<Option<&str>, ApplicationError> {
+    // clippy says the const needs to be assigned to a local variable:
+    // error: a `const` item with interior mutability should not be borrowed
+    let header_name = AUTHORIZATION;
+    match req.headers().get(&header_name) {
+        None => Ok(None),
+        Some(authorization) => {
+            let authorization = authorization.to_str().context(ReadingHeaderAsUtf8 {
+                header_name: header_name.as_str(),
+            })?;
+            Ok(authorization
+                .strip_prefix("Token ")
+                .map(|token| token.trim())
+                .filter(|token| !token.is_empty()))
+        }
+    }
+}
 
-    Ok(Response::new(Body::from(results.into_bytes())))
+/// Checks that the request may perform `action` on the database `db_name`,
+/// or on the server itself if it is `None`
+async fn authorize<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    server: &AppServer<M>,
+    req: &Request<Body>,
+    action: Action,
+    db_name: Option<&str>,
+) -> Result<(), ApplicationError> {
+    let principal = Principal::new(request_token(req)?);
+    server
+        .authorize(principal, action, db_name)
+        .await
+        .context(NotAuthorized)
 }
 
 #[tracing::instrument(level = "debug")]
@@ -496,24 +1010,25 @@ async fn create_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
         .param("name")
         .expect("db name must have been set")
         .clone();
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
     let body = parse_body(req).await?;
 
     let rules: DatabaseRules = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
 
-    server
-        .create_database(db_name, rules)
-        .await
-        .context(ErrorCreatingDatabase)?;
+    let created = server.create_database(db_name.as_str(), rules).await;
+    let action = AuditAction::new("create_database").database(&db_name);
+    server.audit(action, &created).await.context(Auditing)?;
+    created.context(ErrorCreatingDatabase)?;
 
     Ok(Response::new(Body::empty()))
 }
 
 #[tracing::instrument(level = "debug")]
-async fn get_database_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+async fn create_namespace_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
 where
     M: ConnectionManager + Send + Sync + Debug + 'static,
 {
-    match get_database::<M>(req).await {
+    match create_namespace::<M>(req).await {
         Err(e) => {
             error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
 
@@ -523,8 +1038,15 @@ where
     }
 }
 
+/// The response to creating a namespace
+#[derive(Debug, Serialize)]
+struct CreateNamespaceResponse {
+    /// The name of the database storing the data of the namespace
+    database: String,
+}
+
 #[tracing::instrument(level = "debug")]
-async fn get_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
+async fn create_namespace<M: ConnectionManager + Send + Sync + Debug + 'static>(
     req: Request<Body>,
 ) -> Result<Response<Body>, ApplicationError> {
     let server = req
@@ -532,33 +1054,41 @@ async fn get_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
         .expect("server state")
         .clone();
 
-    // with routerify, we shouldn't have gotten here without this being set
-    let db_name_str = req
-        .param("name")
-        .expect("db name must have been set")
+    // with routerify, we shouldn't have gotten here without these being set
+    let org = req.param("org").expect("org must have been set").clone();
+    let bucket = req
+        .param("bucket")
+        .expect("bucket must have been set")
         .clone();
-    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
-    let db = server
-        .db_rules(&db_name)
-        .await
-        .context(DatabaseNotFound { name: &db_name_str })?;
+    authorize(&server, &req, Action::Admin, None).await?;
+    let body = parse_body(req).await?;
 
-    let data = serde_json::to_string(&db).context(JsonGenerationError)?;
-    let response = Response::builder()
-        .header("Content-Type", "application/json")
-        .status(StatusCode::OK)
-        .body(Body::from(data))
-        .expect("builder should be successful");
+    let rules: DatabaseRules = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
 
-    Ok(response)
+    let created = server.create_namespace(&org, &bucket, rules).await;
+    let namespace = format!("{}/{}", org, bucket);
+    let action = AuditAction::new("create_namespace").statement(&namespace);
+    let action = match &created {
+        Ok(db_name) => action.database(db_name),
+        Err(_) => action,
+    };
+    server.audit(action, &created).await.context(Auditing)?;
+    let db_name = created.context(ErrorCreatingNamespace)?;
+
+    let response = CreateNamespaceResponse {
+        database: db_name.to_string(),
+    };
+    let body = serde_json::to_string(&response).context(JsonGenerationError)?;
+
+    Ok(Response::new(Body::from(body)))
 }
 
 #[tracing::instrument(level = "debug")]
-async fn set_writer_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+async fn delete_database_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
 where
     M: ConnectionManager + Send + Sync + Debug + 'static,
 {
-    match set_writer::<M>(req).await {
+    match delete_database::<M>(req).await {
         Err(e) => {
             error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
 
@@ -569,7 +1099,7 @@ where
 }
 
 #[tracing::instrument(level = "debug")]
-async fn set_writer<M: ConnectionManager + Send + Sync + Debug + 'static>(
+async fn delete_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
     req: Request<Body>,
 ) -> Result<Response<Body>, ApplicationError> {
     let server = req
@@ -577,50 +1107,24 @@ async fn set_writer<M: ConnectionManager + Send + Sync + Debug + 'static>(
         .expect("server state")
         .clone();
 
-    // Read the request body
-    let body = parse_body(req).await?;
-
-    // Parse the JSON body into a structure
-    #[derive(Serialize, Deserialize)]
-    struct WriterIdBody {
-        id: u32,
-    };
-    let req: WriterIdBody = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
-
-    // Set the writer ID
-    server.set_id(req.id);
-
-    // Build a HTTP 200 response
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from(
-            serde_json::to_string(&req).expect("json encoding should not fail"),
-        ))
-        .expect("builder should be successful");
-
-    Ok(response)
-}
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name = req.param("name").expect("db name must have been set");
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
 
-// Route to test that the server is alive
-#[tracing::instrument(level = "debug")]
-async fn ping(req: Request<Body>) -> Result<Response<Body>, ApplicationError> {
-    let response_body = "PONG";
-    Ok(Response::new(Body::from(response_body.to_string())))
-}
+    let deleted = server.delete_database(db_name).await;
+    let action = AuditAction::new("delete_database").database(db_name);
+    server.audit(action, &deleted).await.context(Auditing)?;
+    deleted.context(ErrorDeletingDatabase)?;
 
-#[derive(Deserialize, Debug)]
-/// Arguments in the query string of the request to /partitions
-struct DatabaseInfo {
-    org: String,
-    bucket: String,
+    Ok(Response::new(Body::empty()))
 }
 
 #[tracing::instrument(level = "debug")]
-async fn list_partitions_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+async fn restore_database_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
 where
     M: ConnectionManager + Send + Sync + Debug + 'static,
 {
-    match list_partitions::<M>(req).await {
+    match restore_database::<M>(req).await {
         Err(e) => {
             error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
 
@@ -631,57 +1135,44 @@ where
 }
 
 #[tracing::instrument(level = "debug")]
-async fn list_partitions<M: ConnectionManager + Send + Sync + Debug + 'static>(
+async fn restore_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
     req: Request<Body>,
 ) -> Result<Response<Body>, ApplicationError> {
     let server = req
         .data::<Arc<AppServer<M>>>()
         .expect("server state")
         .clone();
-    let query = req.uri().query().context(ExpectedQueryString {})?;
-
-    let info: DatabaseInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
-        query_string: query,
-    })?;
-
-    let db_name =
-        org_and_bucket_to_database(&info.org, &info.bucket).context(BucketMappingError)?;
 
-    let db = server.db(&db_name).await.context(BucketNotFound {
-        org: &info.org,
-        bucket: &info.bucket,
-    })?;
-
-    let partition_keys = db
-        .partition_keys()
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(BucketByName {
-            org: &info.org,
-            bucket_name: &info.bucket,
-        })?;
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name = req.param("name").expect("db name must have been set");
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
 
-    let result = serde_json::to_string(&partition_keys).context(JsonGenerationError)?;
+    let restored = server.restore_database(db_name).await;
+    let action = AuditAction::new("restore_database").database(db_name);
+    server.audit(action, &restored).await.context(Auditing)?;
+    restored.context(ErrorRestoringDatabase)?;
 
-    Ok(Response::new(Body::from(result)))
+    Ok(Response::new(Body::empty()))
 }
 
 #[derive(Deserialize, Debug)]
-/// Arguments in the query string of the request to /snapshot
-struct SnapshotInfo {
-    org: String,
-    bucket: String,
+/// Arguments in the query string of the request to copy_partition
+struct CopyPartitionInfo {
+    /// The key of the partition to copy
     partition: String,
+    /// The database to copy the partition to
+    target: String,
+    /// Whether to copy or move the partition, copy if not set
+    #[serde(default)]
+    mode: CopyMode,
 }
 
 #[tracing::instrument(level = "debug")]
-async fn snapshot_partition_handler<M>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError>
+async fn copy_partition_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
 where
     M: ConnectionManager + Send + Sync + Debug + 'static,
 {
-    match snapshot_partition::<M>(req).await {
+    match copy_partition::<M>(req).await {
         Err(e) => {
             error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
 
@@ -691,263 +1182,2075 @@ where
     }
 }
 
+/// Copies or moves a partition of the database, including its snapshot,
+/// to another database, and responds with what was copied
 #[tracing::instrument(level = "debug")]
-async fn snapshot_partition<M: ConnectionManager + Send + Sync + Debug + 'static>(
+async fn copy_partition<M: ConnectionManager + Send + Sync + Debug + 'static>(
     req: Request<Body>,
 ) -> Result<Response<Body>, ApplicationError> {
     let server = req
         .data::<Arc<AppServer<M>>>()
         .expect("server state")
         .clone();
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name = req.param("name").expect("db name must have been set");
     let query = req.uri().query().context(ExpectedQueryString {})?;
 
-    let snapshot: SnapshotInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
-        query_string: query,
-    })?;
+    let copy: CopyPartitionInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
+    authorize(&server, &req, Action::Admin, Some(copy.target.as_str())).await?;
+
+    let copied = server
+        .copy_partition(db_name, &copy.target, &copy.partition, copy.mode)
+        .await;
+    let statement = format!("partition={} target={}", copy.partition, copy.target);
+    let action = match copy.mode {
+        CopyMode::Copy => AuditAction::new("copy_partition"),
+        CopyMode::Move => AuditAction::new("move_partition"),
+    }
+    .database(db_name)
+    .statement(&statement);
+    server.audit(action, &copied).await.context(Auditing)?;
+    let summary = copied.context(ErrorCopyingPartition)?;
 
-    let db_name =
-        org_and_bucket_to_database(&snapshot.org, &snapshot.bucket).context(BucketMappingError)?;
+    let result = serde_json::to_string(&summary).context(JsonGenerationError)?;
+    Ok(Response::new(Body::from(result)))
+}
 
-    // TODO: refactor the rest of this out of the http route and into the server
-    // crate.
-    let db = server.db(&db_name).await.context(BucketNotFound {
-        org: &snapshot.org,
-        bucket: &snapshot.bucket,
-    })?;
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to fan_in
+struct FanInInfo {
+    /// The ids of the servers whose WALs to replay, separated by commas
+    writers: String,
+}
 
-    let mut metadata_path = ObjectStorePath::default();
-    metadata_path.push_dir(&db_name.to_string());
-    let mut data_path = metadata_path.clone();
-    metadata_path.push_dir("meta");
-    data_path.push_all_dirs(&["data", &snapshot.partition]);
+#[tracing::instrument(level = "debug")]
+async fn fan_in_wals_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match fan_in_wals::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+/// Replays the WALs other servers persisted for the database into it, and
+/// responds with what was replayed
+#[tracing::instrument(level = "debug")]
+async fn fan_in_wals<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name = req.param("name").expect("db name must have been set");
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let fan_in: FanInInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+    let writer_ids = fan_in
+        .writers
+        .split(',')
+        .map(|writer_id| {
+            let writer_id = writer_id.trim();
+            writer_id.parse().context(InvalidWriterId { writer_id })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
+
+    let replayed = server.fan_in_wals(db_name, &writer_ids).await;
+    let statement = format!("writers={}", fan_in.writers);
+    let action = AuditAction::new("fan_in_wals")
+        .database(db_name)
+        .statement(&statement);
+    server.audit(action, &replayed).await.context(Auditing)?;
+    let summary = replayed.context(ErrorFanningInWals)?;
+
+    let result = serde_json::to_string(&summary).context(JsonGenerationError)?;
+    Ok(Response::new(Body::from(result)))
+}
+
+#[derive(Deserialize, Debug)]
+/// Body of the request to delete_series
+struct DeleteSeriesInfo {
+    /// The table to delete points of
+    table: String,
+    /// The tags the points to delete have
+    tag_matchers: Vec<TagMatcher>,
+    /// What the deletion has to reach before it is acknowledged, like for
+    /// writes
+    ack: Option<String>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn delete_series_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match delete_series::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+/// Deletes the points of a table whose tags have the given values, and
+/// responds like a write with how durable the deletion was
+#[tracing::instrument(level = "debug")]
+async fn delete_series<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
+
+    let body = parse_body(req).await?;
+    let delete: DeleteSeriesInfo =
+        serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+    let ack = match &delete.ack {
+        Some(ack) => ack.parse().context(InvalidWriteAckLevel)?,
+        None => WriteAckLevel::default(),
+    };
+
+    let deleted = server
+        .delete_series(&db_name, &delete.table, &delete.tag_matchers, ack)
+        .await;
+    let statement = serde_json::to_string(&delete.tag_matchers).context(JsonGenerationError)?;
+    let statement = format!("table={} tag_matchers={}", delete.table, statement);
+    let action = AuditAction::new("delete_series")
+        .database(&db_name)
+        .statement(&statement);
+    server.audit(action, &deleted).await.context(Auditing)?;
+    let write_ack = deleted.context(ErrorDeletingSeries)?;
+
+    Ok(write_ack_response(write_ack))
+}
+
+#[derive(Deserialize, Debug)]
+/// Body of the request to ingest
+struct IngestInfo {
+    /// The directory of the server holding the line protocol files
+    path: String,
+    /// The pattern of the names of the files to ingest, all of them if not
+    /// set
+    glob: Option<String>,
+    /// How many files to read at a time, one if not set
+    parallelism: Option<usize>,
+    /// What each batch of lines has to reach before the next one is
+    /// written, like for writes
+    ack: Option<String>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn ingest_directory_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match ingest_directory::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+/// Writes the line protocol files of a directory of the server to the
+/// database, and responds with what was ingested from each file
+#[tracing::instrument(level = "debug")]
+async fn ingest_directory<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    // the files are read from the server's file system
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
+
+    let body = parse_body(req).await?;
+    let ingest: IngestInfo = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+    let ack = match &ingest.ack {
+        Some(ack) => ack.parse().context(InvalidWriteAckLevel)?,
+        None => WriteAckLevel::default(),
+    };
+    let glob = ingest.glob.as_deref().unwrap_or("*");
+
+    let ingested = server
+        .ingest_directory(
+            &db_name,
+            &ingest.path,
+            glob,
+            ingest.parallelism.unwrap_or(1),
+            ack,
+        )
+        .await;
+    let statement = format!("path={} glob={}", ingest.path, glob);
+    let action = AuditAction::new("ingest_directory")
+        .database(&db_name)
+        .statement(&statement);
+    server.audit(action, &ingested).await.context(Auditing)?;
+    let summary = ingested.context(ErrorIngestingFiles)?;
+
+    let result = serde_json::to_string(&summary).context(JsonGenerationError)?;
+    Ok(Response::new(Body::from(result)))
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_database_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match get_database::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    authorize(&server, &req, Action::Read, Some(db_name_str.as_str())).await?;
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+    let db = server
+        .require_db(&db_name)
+        .context(DatabaseUnavailable)?
+        .rules
+        .clone();
+
+    let data = serde_json::to_string(&db).context(JsonGenerationError)?;
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(data))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_schema_changes_handler<M>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match get_schema_changes::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to /schema_changes
+struct SchemaChangesInfo {
+    /// Only return the changes made at or after this (RFC 3339) time
+    since: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_schema_changes<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+    let query = req.uri().query().unwrap_or_default();
+    let info: SchemaChangesInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    authorize(&server, &req, Action::Read, Some(db_name_str.as_str())).await?;
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+    let db = server.require_db(&db_name).context(DatabaseUnavailable)?;
+    // the schema reveals the tables and columns of every row, which
+    // principals restricted to some rows by an access policy may not see
+    db.check_unrestricted(request_token(&req)?)
+        .context(QueryNotAuthorized {
+            db_name: db_name.as_str(),
+        })?;
+
+    let data =
+        serde_json::to_string(&db.schema_changes(info.since)).context(JsonGenerationError)?;
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(data))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_database_summary_handler<M>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match get_database_summary::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_database_summary<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    authorize(&server, &req, Action::Read, Some(db_name_str.as_str())).await?;
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+    let db = server.require_db(&db_name).context(DatabaseUnavailable)?;
+    // the summary is gathered from every row, which principals restricted
+    // to some rows by an access policy may not see
+    db.check_unrestricted(request_token(&req)?)
+        .context(QueryNotAuthorized {
+            db_name: db_name.as_str(),
+        })?;
+
+    let data = serde_json::to_string(&db.summary().await).context(JsonGenerationError)?;
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(data))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+/// The number of tag values returned by /tag_values unless a limit is set
+const DEFAULT_TAG_VALUES_LIMIT: usize = 100;
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to /tag_values
+struct TagValuesInfo {
+    /// The tag column whose values are returned
+    column: String,
+    /// Only return the values that start with this prefix
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+    /// Only return the values of rows with a timestamp at or after this
+    start: Option<i64>,
+    /// Only return the values of rows with a timestamp before this
+    end: Option<i64>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_tag_values_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match get_tag_values::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_tag_values<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+    let info: TagValuesInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    authorize(&server, &req, Action::Read, Some(db_name_str.as_str())).await?;
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+    let db = server.require_db(&db_name).context(DatabaseUnavailable)?;
+    // the values are read from every row, which principals restricted to
+    // some rows by an access policy may not see
+    db.check_unrestricted(request_token(&req)?)
+        .context(QueryNotAuthorized {
+            db_name: db_name.as_str(),
+        })?;
+
+    let range = match (info.start, info.end) {
+        (None, None) => None,
+        (start, end) => Some(TimestampRange::new(
+            start.unwrap_or(i64::MIN),
+            end.unwrap_or(i64::MAX),
+        )),
+    };
+    let values = db
+        .column_values_with_prefix(
+            &info.column,
+            &info.prefix,
+            info.limit.unwrap_or(DEFAULT_TAG_VALUES_LIMIT),
+            range,
+        )
+        .await
+        .context(ListingTagValues {
+            db_name: db_name.as_str(),
+        })?;
+
+    let data = serde_json::to_string(&values).context(JsonGenerationError)?;
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(data))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_recovery_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match get_recovery::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+/// The recovery state of a database found in object storage at startup
+#[derive(Debug, Serialize)]
+struct DatabaseRecovery {
+    name: String,
+    #[serde(flatten)]
+    state: RecoveryState,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_recovery<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+    authorize(&server, &req, Action::Read, None).await?;
+
+    let databases: Vec<_> = server
+        .recovery_states()
+        .into_iter()
+        .map(|(name, state)| DatabaseRecovery { name, state })
+        .collect();
+
+    let data = serde_json::to_string(&databases).context(JsonGenerationError)?;
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(data))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn set_writer_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match set_writer::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn set_writer<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    authorize(&server, &req, Action::Admin, None).await?;
+
+    // Read the request body
+    let body = parse_body(req).await?;
+
+    // Parse the JSON body into a structure
+    #[derive(Serialize, Deserialize)]
+    struct WriterIdBody {
+        id: u32,
+    };
+    let req: WriterIdBody = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    // Set the writer ID
+    server.set_id(req.id);
+    let statement = format!("id={}", req.id);
+    let action = AuditAction::new("set_writer_id").statement(&statement);
+    server
+        .audit(action, &Ok::<_, ApplicationError>(()))
+        .await
+        .context(Auditing)?;
+
+    // Build a HTTP 200 response
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(
+            serde_json::to_string(&req).expect("json encoding should not fail"),
+        ))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+// Route to test that the server is alive
+#[tracing::instrument(level = "debug")]
+async fn ping(req: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    let response_body = "PONG";
+    Ok(Response::new(Body::from(response_body.to_string())))
+}
+
+// Route exporting the latency histograms of the requests served and the
+// statistics of the allocator, in the Prometheus text format
+#[tracing::instrument(level = "debug")]
+async fn metrics_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    let mut metrics = server.latency_metrics().render();
+    metrics.push_str(&server.render_quarantine_metrics());
+    metrics.push_str(&server.render_mutable_buffer_metrics().await);
+    metrics.push_str(&server.render_quota_metrics());
+    metrics.push_str(&server.render_wal_metrics());
+    match AllocatorStats::read() {
+        Ok(stats) => metrics.push_str(&stats.render()),
+        Err(allocator::Error::NotBuiltWithJemalloc) => {}
+        Err(e) => warn!(error = ?e, "Error reading allocator statistics"),
+    }
+
+    Ok(Response::builder()
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(Body::from(metrics))
+        .expect("builder should be successful"))
+}
+
+// Route returning a profile of the memory allocated by the server, which
+// can be read with `jeprof`
+#[tracing::instrument(level = "debug")]
+async fn heap_profile_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match heap_profile::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn heap_profile<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+
+    // the profile tells about the data of every database
+    authorize(&server, &req, Action::Admin, None).await?;
+
+    let profile = allocator::dump_heap_profile().context(DumpingHeapProfile)?;
+    Ok(Response::builder()
+        .header("Content-Type", "application/octet-stream")
+        .body(Body::from(profile))
+        .expect("builder should be successful"))
+}
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to /partitions
+struct DatabaseInfo {
+    org: String,
+    bucket: String,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_partitions_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match list_partitions::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_partitions<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let info: DatabaseInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let db_name = server
+        .namespace_db_name(&info.org, &info.bucket)
+        .await
+        .context(BucketMappingError)?;
+    authorize(&server, &req, Action::Read, Some(db_name.as_str())).await?;
+
+    let db = server.require_db(&db_name).context(DatabaseUnavailable)?;
+
+    let partition_keys = db
+        .partition_keys()
+        .await
+        .map_err(|e| Box::new(query::DatabaseError::from(e)) as _)
+        .context(BucketByName {
+            org: &info.org,
+            bucket_name: &info.bucket,
+        })?;
+
+    let result = serde_json::to_string(&partition_keys).context(JsonGenerationError)?;
+
+    Ok(Response::new(Body::from(result)))
+}
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to /snapshot
+struct SnapshotInfo {
+    org: String,
+    bucket: String,
+    partition: String,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn snapshot_partition_handler<M>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match snapshot_partition::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn snapshot_partition<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let snapshot: SnapshotInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let db_name = server
+        .namespace_db_name(&snapshot.org, &snapshot.bucket)
+        .await
+        .context(BucketMappingError)?;
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
+
+    // TODO: refactor the rest of this out of the http route and into the server
+    // crate.
+    let db = server.require_db(&db_name).context(DatabaseUnavailable)?;
+
+    let partition_key = &snapshot.partition;
+    let snapshot = async {
+        let chunk = db
+            .rollover_partition(partition_key)
+            .await
+            .context(RollingPartition { partition_key })?;
+
+        db.snapshot_chunk(server.store.clone(), partition_key, chunk)
+            .context(SnapshottingPartition { partition_key })
+    }
+    .await;
+    let statement = format!("partition={}", partition_key);
+    let action = AuditAction::new("snapshot_partition")
+        .database(&db_name)
+        .statement(&statement);
+    server.audit(action, &snapshot).await.context(Auditing)?;
+    let snapshot = snapshot?;
+
+    let ret = format!("{}", snapshot.id);
+    Ok(Response::new(Body::from(ret)))
+}
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to /warm
+struct WarmInfo {
+    org: String,
+    bucket: String,
+    /// The tables to load, separated by commas. All tables are loaded if
+    /// not set.
+    tables: Option<String>,
+    /// The inclusive start of the time range to load, in nanoseconds
+    /// since the epoch
+    start: Option<i64>,
+    /// The exclusive end of the time range to load, in nanoseconds since
+    /// the epoch
+    end: Option<i64>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn warm_database_handler<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    match warm_database::<M>(req).await {
+        Err(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+
+            e.response()
+        }
+        res => res,
+    }
+}
+
+/// Loads the snapshots written by /snapshot into the read buffer of the
+/// database, and responds with what was loaded
+#[tracing::instrument(level = "debug")]
+async fn warm_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = req
+        .data::<Arc<AppServer<M>>>()
+        .expect("server state")
+        .clone();
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let warm: WarmInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let db_name = server
+        .namespace_db_name(&warm.org, &warm.bucket)
+        .await
+        .context(BucketMappingError)?;
+    authorize(&server, &req, Action::Admin, Some(db_name.as_str())).await?;
+
+    let db = server.require_db(&db_name).context(DatabaseUnavailable)?;
+
+    let (metadata_path, data_path) = server::snapshot::snapshot_paths(db_name.as_str());
+
+    let tables: Vec<String> = warm
+        .tables
+        .as_deref()
+        .map(|tables| tables.split(',').map(|t| t.trim().to_string()).collect())
+        .unwrap_or_default();
+    let range = match (warm.start, warm.end) {
+        (None, None) => None,
+        (start, end) => Some(TimestampRange::new(
+            start.unwrap_or(i64::MIN),
+            end.unwrap_or(i64::MAX),
+        )),
+    };
+
+    let summary = db
+        .warm(&server.store, &metadata_path, &data_path, range, &tables)
+        .await
+        .context(WarmingDatabase {
+            db_name: db_name.as_str(),
+        })?;
+
+    let result = serde_json::to_string(&summary).context(JsonGenerationError)?;
+    Ok(Response::new(Body::from(result)))
+}
+
+pub fn router_service<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    server: Arc<AppServer<M>>,
+    limits: RequestLimits,
+) -> RouterService<Body, ApplicationError> {
+    let router = router(server, limits);
+    RouterService::new(router).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use arrow_deps::{arrow::record_batch::RecordBatch, assert_table_eq};
+    use http::header;
+    use query::exec::Executor;
+    use reqwest::{Client, Response};
+
+    use hyper::Server;
+
+    use data_types::access_policy::{token_sha256, AccessPolicy, Principal, RowFilter};
+    use data_types::database_rules::{
+        DatabaseQuotas, DatabaseRules, MatchTables, PartitionTemplate, TableWriteRules,
+        TemplatePart,
+    };
+    use data_types::DatabaseName;
+    use object_store::{memory::InMemory, ObjectStore};
+    use server::{
+        audit::{AuditEntry, AuditLog, AuditRedaction, AuditStatus, FileAuditSink},
+        authz::{TokenScope, TokenScopes},
+        db::Db,
+        ConnectionManagerImpl,
+    };
+    use test_helpers::assert_contains;
+
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T, E = Error> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn test_ping() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client.get(&format!("{}/ping", server_url)).send().await;
+
+        // Print the response so if the test fails, we have a log of what went wrong
+        check_response("ping", response, StatusCode::OK, "PONG").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_exceeding_quota() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            quotas: DatabaseQuotas {
+                max_lines_per_second: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body("cpu usage=1 10\ncpu usage=2 20")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_contains!(
+            response.text().await.unwrap(),
+            "writing 2 lines exceeds the quota of 1 lines per second"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // Check that the data got into the right bucket
+        let test_db = test_storage
+            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
+            .await
+            .expect("Database exists");
+
+        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
+        let expected = vec![
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| bottom_degrees | location     | state | surface_degrees | time       |",
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
+            "+----------------+--------------+-------+-----------------+------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            future_time_cap: Some(std::time::Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            // the second point is in 2100
+            .body("cpu usage=1 10\ncpu usage=2 4102444800000000000")
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // writes to databases that don't exist aren't recorded
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=NoBucket&org=NoOrg",
+                server_url
+            ))
+            .body("cpu usage=1 10")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // the latency of failed queries is recorded too
+        for query in &["select * from cpu", "SHOW TABLES", "show columns from cpu"] {
+            client
+                .get(&format!("{}/api/v2/read", server_url))
+                .query(&[
+                    ("bucket", "MyBucket"),
+                    ("org", "MyOrg"),
+                    ("sql_query", *query),
+                ])
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let response = client
+            .get(&format!("{}/metrics", server_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.unwrap();
+        let labels =
+            |operation: &str| format!("db_name=\"MyOrg_MyBucket\",operation=\"{}\"", operation);
+        assert_contains!(
+            &body,
+            format!(
+                "iox_request_duration_seconds_count{{{}}} 1\n",
+                labels("write")
+            )
+        );
+        assert_contains!(
+            &body,
+            format!(
+                "iox_request_duration_seconds_count{{{}}} 1\n",
+                labels("sql_select")
+            )
+        );
+        assert_contains!(
+            &body,
+            format!(
+                "iox_request_duration_seconds_count{{{}}} 2\n",
+                labels("sql_show")
+            )
+        );
+        assert_contains!(
+            &body,
+            "iox_quarantined_rows_total{db_name=\"MyOrg_MyBucket\"} 1\n"
+        );
+        assert!(!body.contains("NoOrg_NoBucket"));
+        assert_contains!(
+            &body,
+            "iox_mutable_buffer_chunk_rollovers_total{db_name=\"MyOrg_MyBucket\",reason=\"size\"} 0\n"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "jemalloc"))]
+    #[tokio::test]
+    async fn test_heap_profile_without_jemalloc() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .get(&format!("{}/debug/pprof/heap", server_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+        assert_contains!(
+            response.text().await.unwrap(),
+            "The server was built without jemalloc"
+        );
+
+        // the allocator statistics are left out of the metrics
+        let response = client
+            .get(&format!("{}/metrics", server_url))
+            .send()
+            .await
+            .unwrap();
+        assert!(!response.text().await.unwrap().contains("iox_jemalloc"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_ack() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg&ack=buffered",
+                server_url
+            ))
+            .body("cpu usage=1 10")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(headers["X-IOx-Write-Buffered"], "true");
+        assert_eq!(headers["X-IOx-Write-WAL"], "false");
+        assert_eq!(headers["X-IOx-Write-Replicated"], "0");
+        assert_eq!(headers["X-IOx-Write-Replicating"], "0");
+
+        // the database has no WAL buffer
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg&ack=wal",
+                server_url
+            ))
+            .body("cpu usage=2 20")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg&ack=synced",
+                server_url
+            ))
+            .body("cpu usage=2 20")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_contains!(
+            response.text().await.unwrap(),
+            "Unknown write acknowledgement level 'synced'"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_error_status() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            table_write_rules: TableWriteRules {
+                deny: vec![MatchTables::Table("secrets".to_string())],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let write = |lp_data: &'static str| {
+            client
+                .post(&format!(
+                    "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                    server_url
+                ))
+                .body(lp_data)
+                .send()
+        };
+
+        let response = write("h2o_temperature,state=CA surface_degrees=65.2 1568756160").await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // surface_degrees is a float column, so writing a string to it
+        // conflicts with the schema rather than being an internal error
+        let response = write("h2o_temperature,state=CA surface_degrees=\"warm\" 1568756170")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_contains!(
+            response.text().await.unwrap(),
+            "Unable to insert String type into a column of f64"
+        );
+
+        // writes to tables the database's rules deny are forbidden
+        let response = write("secrets,state=CA code=1 1568756160").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_contains!(
+            response.text().await.unwrap(),
+            "line 1: table secrets matches a deny rule"
+        );
+
+        // writes to unknown databases are rejected as not found
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=NotThere&org=MyOrg",
+                server_url
+            ))
+            .body("h2o_temperature,state=CA surface_degrees=65.2 1568756160")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_contains!(
+            response.text().await.unwrap(),
+            "database not found: MyOrg_NotThere"
+        );
+
+        Ok(())
+    }
+
+    fn gzip_str(s: &str) -> Vec<u8> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        write!(encoder, "{}", s).expect("writing into encoder");
+        encoder.finish().expect("successfully encoding gzip data")
+    }
+
+    #[tokio::test]
+    async fn test_gzip_write() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data encoded with gzip
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(gzip_str(lp_data))
+            .send()
+            .await;
+
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // Check that the data got into the right bucket
+        let test_db = test_storage
+            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
+            .await
+            .expect("Database exists");
+
+        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
+
+        let expected = vec![
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| bottom_degrees | location     | state | surface_degrees | time       |",
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
+            "+----------------+--------------+-------+-----------------+------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_limits() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::default())
+            .await
+            .unwrap();
+        let limits = RequestLimits {
+            max_body_bytes: 64,
+            max_write_lines: 2,
+            max_sql_bytes: 20,
+        };
+        let server_url = test_server_with_limits(test_storage.clone(), limits);
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+
+        let client = Client::new();
+        let response = client
+            .post(&write_url)
+            .body("cpu usage=1 1\ncpu usage=2 2")
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .post(&write_url)
+            .body("cpu usage=1 1\ncpu usage=2 2\ncpu usage=3 3")
+            .send()
+            .await;
+        check_response(
+            "write",
+            response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"error":"Write exceeds limit of 2 lines","error_code":104}"#,
+        )
+        .await;
+
+        let response = client.post(&write_url).body("x".repeat(65)).send().await;
+        check_response(
+            "write",
+            response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"error":"Body exceeds limit of 64 bytes","error_code":104}"#,
+        )
+        .await;
+
+        // the limit applies to the decompressed body
+        let lp_data = format!("cpu usage=1 1 # {}", "x".repeat(100));
+        let response = client
+            .post(&write_url)
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(gzip_str(&lp_data))
+            .send()
+            .await;
+        check_response(
+            "write",
+            response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"error":"Body exceeds limit of 64 bytes","error_code":104}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/api/v2/read", server_url))
+            .query(&[
+                ("org", "MyOrg"),
+                ("bucket", "MyBucket"),
+                ("sql_query", "select usage from cpu order by time"),
+            ])
+            .send()
+            .await;
+        check_response(
+            "read",
+            response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"error":"SQL query of 35 bytes exceeds limit of 20 bytes","error_code":104}"#,
+        )
+        .await;
+
+        Ok(())
+    }
 
-    let partition_key = &snapshot.partition;
-    let chunk = db.rollover_partition(partition_key).await.unwrap();
-    let snapshot = server::snapshot::snapshot_chunk(
-        metadata_path,
-        data_path,
-        server.store.clone(),
-        partition_key,
-        chunk,
-        None,
-    )
-    .unwrap();
+    #[tokio::test]
+    async fn set_writer_id() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let server_url = test_server(server.clone());
 
-    let ret = format!("{}", snapshot.id);
-    Ok(Response::new(Body::from(ret)))
-}
+        let data = r#"{"id":42}"#;
 
-pub fn router_service<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    server: Arc<AppServer<M>>,
-) -> RouterService<Body, ApplicationError> {
-    let router = router(server);
-    RouterService::new(router).unwrap()
-}
+        let client = Client::new();
+        let response = client
+            .put(&format!("{}/iox/api/v1/id", server_url))
+            .body(data)
+            .send()
+            .await;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+        check_response("set_writer_id", response, StatusCode::OK, data).await;
 
-    use arrow_deps::{arrow::record_batch::RecordBatch, assert_table_eq};
-    use http::header;
-    use query::exec::Executor;
-    use reqwest::{Client, Response};
+        assert_eq!(server.require_id().expect("should be set"), 42);
+    }
 
-    use hyper::Server;
+    #[tokio::test]
+    async fn create_database() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let server_url = test_server(server.clone());
 
-    use data_types::database_rules::DatabaseRules;
-    use data_types::DatabaseName;
-    use object_store::{memory::InMemory, ObjectStore};
-    use server::{db::Db, ConnectionManagerImpl};
+        let data = r#"{"store_locally": true}"#;
 
-    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T, E = Error> = std::result::Result<T, E>;
+        let database_name = DatabaseName::new("foo_bar").unwrap();
+
+        let client = Client::new();
+        let response = client
+            .put(&format!(
+                "{}/iox/api/v1/databases/{}",
+                server_url, database_name
+            ))
+            .body(data)
+            .send()
+            .await;
+
+        check_response("create_database", response, StatusCode::OK, "").await;
+
+        server.db(&database_name).await.unwrap();
+        let db_rules = server.db_rules(&database_name).await.unwrap();
+        assert_eq!(db_rules.store_locally, true);
+
+        // creating the database again conflicts with the existing one
+        let response = client
+            .put(&format!(
+                "{}/iox/api/v1/databases/{}",
+                server_url, database_name
+            ))
+            .body(data)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
 
     #[tokio::test]
-    async fn test_ping() -> Result<()> {
-        let test_storage = Arc::new(AppServer::new(
+    async fn delete_and_restore_database() {
+        let server = Arc::new(AppServer::new(
             ConnectionManagerImpl {},
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
-        let server_url = test_server(test_storage.clone());
+        server.set_id(1);
+        server
+            .create_database("foo_bar", DatabaseRules::default())
+            .await
+            .unwrap();
+        let server_url = test_server(server.clone());
+        let database_name = DatabaseName::new("foo_bar").unwrap();
 
         let client = Client::new();
-        let response = client.get(&format!("{}/ping", server_url)).send().await;
+        let database_url = format!("{}/iox/api/v1/databases/{}", server_url, database_name);
+        let restore_url = format!("{}/restore", database_url);
+
+        // restoring a database that isn't deleted is not found
+        let response = client.post(&restore_url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = client.delete(&database_url).send().await;
+        check_response("delete_database", response, StatusCode::OK, "").await;
+        assert!(server.db(&database_name).await.is_none());
+
+        // deleting it again is not found, and it can't be created again
+        // until it is purged
+        let response = client.delete(&database_url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = client.put(&database_url).body("{}").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = client.post(&restore_url).send().await;
+        check_response("restore_database", response, StatusCode::OK, "").await;
+        assert!(server.db(&database_name).await.is_some());
+    }
 
-        // Print the response so if the test fails, we have a log of what went wrong
-        check_response("ping", response, StatusCode::OK, "PONG").await;
-        Ok(())
+    #[tokio::test]
+    async fn namespaces_map_to_their_own_databases() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let server_url = test_server(server.clone());
+        let client = Client::new();
+
+        let response = client
+            .put(&format!(
+                "{}/iox/api/v1/namespaces/MyOrg/MyBucket",
+                server_url
+            ))
+            .body(r#"{"store_locally": true}"#)
+            .send()
+            .await;
+        check_response(
+            "create_namespace",
+            response,
+            StatusCode::OK,
+            r#"{"database":"ns-0000000000000001"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body("cpu,host=a usage=0.5 10")
+            .send()
+            .await;
+        // the write fails unless it is routed to the namespace's database,
+        // as no database has the legacy name of the org and bucket
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let ns_db = server
+            .db(&DatabaseName::new("ns-0000000000000001").unwrap())
+            .await
+            .unwrap();
+        let batches = run_query(ns_db.as_ref(), "select * from cpu").await;
+        let expected = vec![
+            "+------+------+-------+",
+            "| host | time | usage |",
+            "+------+------+-------+",
+            "| a    | 10   | 0.5   |",
+            "+------+------+-------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // registering the namespace again conflicts with its database
+        let response = client
+            .put(&format!(
+                "{}/iox/api/v1/namespaces/MyOrg/MyBucket",
+                server_url
+            ))
+            .body("{}")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn get_recovery() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = AppServer::new(ConnectionManagerImpl {}, store.clone());
+        server.set_id(1);
+        server
+            .create_database("foo_bar", DatabaseRules::default())
+            .await
+            .unwrap();
+
+        let server = Arc::new(AppServer::new(ConnectionManagerImpl {}, store));
+        server.set_id(1);
+        server.load_database_configs().await.unwrap();
+        let server_url = test_server(server);
+
+        let client = Client::new();
+        let response = client
+            .get(&format!("{}/iox/api/v1/recovery", server_url))
+            .send()
+            .await;
+
+        check_response(
+            "get_recovery",
+            response,
+            StatusCode::OK,
+            r#"[{"name":"foo_bar","state":"ready"}]"#,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn get_schema_changes() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        server
+            .create_database("MyOrg_MyBucket", DatabaseRules::default())
+            .await
+            .unwrap();
+        let server_url = test_server(server.clone());
+
+        let lines: Vec<_> = parse_lines("h2o,state=MA temp=50.4 1568756160")
+            .map(|l| l.unwrap())
+            .collect();
+        server.write_lines("MyOrg_MyBucket", &lines).await.unwrap();
+
+        let client = Client::new();
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/schema_changes",
+                server_url
+            ))
+            .send()
+            .await;
+        let body = response.unwrap().text().await.unwrap();
+        let changes: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change["change"].as_str().unwrap(),
+                    change["column_name"].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("table_added", None),
+                ("column_added", Some("state")),
+                ("column_added", Some("temp")),
+            ]
+        );
+
+        // nothing was added after the write
+        let since = Utc::now() + chrono::Duration::seconds(1);
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/schema_changes?since={}",
+                server_url,
+                since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            ))
+            .send()
+            .await;
+        check_response("get_schema_changes", response, StatusCode::OK, "[]").await;
+
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/schema_changes?since=yesterday",
+                server_url
+            ))
+            .send()
+            .await;
+        assert_eq!(response.unwrap().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn get_database_summary() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(server.clone());
+
+        let lines: Vec<_> = parse_lines("h2o,state=MA temp=50.4 1568756160")
+            .map(|l| l.unwrap())
+            .collect();
+        server.write_lines("MyOrg_MyBucket", &lines).await.unwrap();
+
+        let client = Client::new();
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/summary",
+                server_url
+            ))
+            .send()
+            .await;
+        let body = response.unwrap().text().await.unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(summary["partitions"], 1);
+        assert_eq!(summary["tables"]["h2o"]["rows"], 1);
+        assert_eq!(summary["tables"]["h2o"]["min_time"], 1568756160);
+        assert_eq!(
+            summary["tables"]["h2o"]["columns"]["state"],
+            "iox::column_type::tag"
+        );
+        assert!(summary["storage"]["mutable_buffer_bytes"].as_u64().unwrap() > 0);
+
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/NotMyBucket/summary",
+                server_url
+            ))
+            .send()
+            .await;
+        assert_eq!(response.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn get_tag_values() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let server_url = test_server(server.clone());
+
+        let lines: Vec<_> = parse_lines(
+            "h2o,state=MA temp=50.4 100\n\
+             h2o,state=MD temp=51.4 200\n\
+             h2o,state=CA temp=52.4 300",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        server.write_lines("MyOrg_MyBucket", &lines).await.unwrap();
+
+        let client = Client::new();
+        let tag_values = |query: &'static str| {
+            client
+                .get(&format!(
+                    "{}/iox/api/v1/databases/MyOrg_MyBucket/tag_values?{}",
+                    server_url, query
+                ))
+                .send()
+        };
+
+        let body = tag_values("column=state&prefix=M")
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let values: Vec<String> = serde_json::from_str(&body).unwrap();
+        assert_eq!(values, vec!["MA", "MD"]);
+
+        let body = tag_values("column=state&limit=1&start=150")
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let values: Vec<String> = serde_json::from_str(&body).unwrap();
+        assert_eq!(values, vec!["CA"]);
+
+        let response = tag_values("prefix=M").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn warm_database() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        server
+            .create_database("MyOrg_MyBucket", DatabaseRules::default())
+            .await
+            .unwrap();
+        let server_url = test_server(server.clone());
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/api/v1/warm?org=MyOrg&bucket=MyBucket&tables=cpu,mem&start=0&end=100",
+                server_url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).expect("warm summary is JSON");
+        assert_eq!(summary["tables"], 0);
+        assert_eq!(summary["rows"], 0);
+
+        let response = client
+            .post(&format!(
+                "{}/api/v1/warm?org=MyOrg&bucket=NotMyBucket",
+                server_url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_write() -> Result<()> {
-        let test_storage = Arc::new(AppServer::new(
+    async fn fan_in_wals() {
+        let server = Arc::new(AppServer::new(
             ConnectionManagerImpl {},
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
-        test_storage.set_id(1);
+        server.set_id(1);
         let rules = DatabaseRules {
             store_locally: true,
             ..Default::default()
         };
-        test_storage
-            .create_database("MyOrg_MyBucket", rules)
-            .await
-            .unwrap();
-        let server_url = test_server(test_storage.clone());
+        server.create_database("MyDb", rules).await.unwrap();
+        let server_url = test_server(server.clone());
 
         let client = Client::new();
-
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
-
-        // send write data
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
         let response = client
             .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
+                "{}/iox/api/v1/databases/MyDb/fan_in?writers=2,3",
+                server_url
             ))
-            .body(lp_data)
             .send()
-            .await;
-
-        check_response("write", response, StatusCode::NO_CONTENT, "").await;
-
-        // Check that the data got into the right bucket
-        let test_db = test_storage
-            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
             .await
-            .expect("Database exists");
-
-        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
-        let expected = vec![
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| bottom_degrees | location     | state | surface_degrees | time       |",
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
-            "+----------------+--------------+-------+-----------------+------------+",
-        ];
-        assert_table_eq!(expected, &batches);
-
-        Ok(())
-    }
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).expect("summary is JSON");
+        assert_eq!(summary["wals"], 2);
+        assert_eq!(summary["writes"], 0);
 
-    fn gzip_str(s: &str) -> Vec<u8> {
-        use flate2::{write::GzEncoder, Compression};
-        use std::io::Write;
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        write!(encoder, "{}", s).expect("writing into encoder");
-        encoder.finish().expect("successfully encoding gzip data")
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/MyDb/fan_in?writers=two",
+                server_url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn test_gzip_write() -> Result<()> {
-        let test_storage = Arc::new(AppServer::new(
+    async fn delete_series() {
+        let server = Arc::new(AppServer::new(
             ConnectionManagerImpl {},
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
-        test_storage.set_id(1);
+        server.set_id(1);
         let rules = DatabaseRules {
             store_locally: true,
             ..Default::default()
         };
-        test_storage
-            .create_database("MyOrg_MyBucket", rules)
-            .await
-            .unwrap();
-        let server_url = test_server(test_storage.clone());
+        server.create_database("MyDb", rules).await.unwrap();
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10\ncpu,host=b usage=2 20")
+            .map(|l| l.unwrap())
+            .collect();
+        server.write_lines("MyDb", &lines).await.unwrap();
+        let server_url = test_server(server.clone());
 
         let client = Client::new();
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
-
-        // send write data encoded with gzip
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
         let response = client
             .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
+                "{}/iox/api/v1/databases/MyDb/delete_series",
+                server_url
             ))
-            .header(header::CONTENT_ENCODING, "gzip")
-            .body(gzip_str(lp_data))
+            .body(r#"{"table": "cpu", "tag_matchers": [{"tag": "host", "value": "a"}]}"#)
             .send()
-            .await;
-
-        check_response("write", response, StatusCode::NO_CONTENT, "").await;
-
-        // Check that the data got into the right bucket
-        let test_db = test_storage
-            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
             .await
-            .expect("Database exists");
-
-        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[WRITE_BUFFERED_HEADER], "true");
 
-        let expected = vec![
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| bottom_degrees | location     | state | surface_degrees | time       |",
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
-            "+----------------+--------------+-------+-----------------+------------+",
-        ];
-        assert_table_eq!(expected, &batches);
+        let db = server
+            .db(&DatabaseName::new("MyDb").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(db.summary().await.tables["cpu"].rows, 1);
 
-        Ok(())
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/MyDb/delete_series",
+                server_url
+            ))
+            .body(r#"{"table": "cpu", "tag_matchers": []}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn set_writer_id() {
+    async fn ingest_directory() {
         let server = Arc::new(AppServer::new(
             ConnectionManagerImpl {},
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("MyDb", rules).await.unwrap();
         let server_url = test_server(server.clone());
 
-        let data = r#"{"id":42}"#;
+        let dir = test_helpers::tmp_dir().unwrap();
+        std::fs::write(
+            dir.path().join("export.lp"),
+            "cpu,host=a usage=1 10\ncpu,host=b usage=2 20\n",
+        )
+        .unwrap();
 
         let client = Client::new();
+        let body = serde_json::json!({ "path": dir.path(), "glob": "*.lp" });
         let response = client
-            .put(&format!("{}/iox/api/v1/id", server_url))
-            .body(data)
+            .post(&format!("{}/iox/api/v1/databases/MyDb/ingest", server_url))
+            .body(body.to_string())
             .send()
-            .await;
-
-        check_response("set_writer_id", response, StatusCode::OK, data).await;
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).expect("summary is JSON");
+        assert_eq!(summary["lines"], 2);
+        assert_eq!(summary["failed_files"], 0);
 
-        assert_eq!(server.require_id().expect("should be set"), 42);
+        let body = serde_json::json!({ "path": dir.path().join("missing") });
+        let response = client
+            .post(&format!("{}/iox/api/v1/databases/MyDb/ingest", server_url))
+            .body(body.to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn create_database() {
+    async fn copy_partition() {
         let server = Arc::new(AppServer::new(
             ConnectionManagerImpl {},
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("%Y".to_string())],
+                ..Default::default()
+            },
+            store_locally: true,
+            ..Default::default()
+        };
+        for db_name in &["src", "dst"] {
+            server
+                .create_database(*db_name, rules.clone())
+                .await
+                .unwrap();
+        }
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        server.write_lines("src", &lines).await.unwrap();
         let server_url = test_server(server.clone());
 
-        let data = r#"{"store_locally": true}"#;
-
-        let database_name = DatabaseName::new("foo_bar").unwrap();
-
         let client = Client::new();
         let response = client
-            .put(&format!(
-                "{}/iox/api/v1/databases/{}",
-                server_url, database_name
+            .post(&format!(
+                "{}/iox/api/v1/databases/src/copy_partition?partition=1970&target=dst&mode=move",
+                server_url
             ))
-            .body(data)
             .send()
-            .await;
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).expect("copy summary is JSON");
+        assert_eq!(summary["rows"], 1);
 
-        check_response("create_database", response, StatusCode::OK, "").await;
+        let dst = server.db(&DatabaseName::new("dst").unwrap()).await.unwrap();
+        let batches = run_query(dst.as_ref(), "select usage from cpu").await;
+        let expected = vec![
+            "+-------+",
+            "| usage |",
+            "+-------+",
+            "| 1     |",
+            "+-------+",
+        ];
+        assert_table_eq!(expected, &batches);
 
-        server.db(&database_name).await.unwrap();
-        let db_rules = server.db_rules(&database_name).await.unwrap();
-        assert_eq!(db_rules.store_locally, true);
+        // the partition is in dst now
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/src/copy_partition?partition=1970&target=dst",
+                server_url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/src/copy_partition?partition=1970&target=nope",
+                server_url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
@@ -981,6 +3284,342 @@ mod tests {
         check_response("create_database", response, StatusCode::OK, &data).await;
     }
 
+    #[tokio::test]
+    async fn read_with_access_policy() {
+        let app_server = Arc::new(AppServer::new(
+            ConnectionManagerImpl {},
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        app_server.set_id(1);
+        let principal = |tenant: &str| Principal {
+            name: tenant.to_string(),
+            token_sha256: token_sha256(&format!("{}-token", tenant)),
+            attributes: vec![("tenant".to_string(), tenant.to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let rules = DatabaseRules {
+            store_locally: true,
+            access_policy: Some(AccessPolicy {
+                principals: vec![principal("acme"), principal("globex")],
+                row_filters: vec![RowFilter {
+                    column: "tenant_id".to_string(),
+                    attribute: "tenant".to_string(),
+                }],
+            }),
+            ..Default::default()
+        };
+        app_server
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let lines: Vec<_> = parse_lines(
+            "cpu,tenant_id=acme,host=a usage=1 10\n\
+             cpu,tenant_id=globex,host=b usage=2 20\n\
+             mem,host=a free=3i 30",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        app_server
+            .write_lines("MyOrg_MyBucket", &lines)
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&app_server));
+
+        let client = Client::new();
+        let read = |sql: &str, token: Option<&str>| {
+            let mut request = client.get(&format!("{}/api/v2/read", server_url)).query(&[
+                ("org", "MyOrg"),
+                ("bucket", "MyBucket"),
+                ("sql_query", sql),
+            ]);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+            }
+            request.send()
+        };
+
+        let response = read("select host from cpu", Some("acme-token"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.unwrap();
+        assert_contains!(&body, "| a    |");
+        assert!(!body.contains("| b    |"), "{}", body);
+
+        let response = read("select count(*) as n from cpu", Some("globex-token"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_contains!(response.text().await.unwrap(), "| 1 |");
+
+        // tables without the filtered column have no visible rows
+        let response = read("select host from mem", Some("acme-token"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.text().await.unwrap().contains("| a "));
+
+        let response = read("select host from cpu", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_contains!(
+            response.text().await.unwrap(),
+            "A token is required to query this database"
+        );
+
+        let response = read("select host from cpu", Some("other-token"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the summary, schema and tag values describe the rows of every
+        // tenant
+        for endpoint in &["summary", "schema_changes", "tag_values?column=host"] {
+            let url = format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/{}",
+                server_url, endpoint
+            );
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", endpoint);
+
+            let response = client
+                .get(&url)
+                .header(header::AUTHORIZATION, "Token acme-token")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", endpoint);
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_and_admin_actions_are_audited() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        let audit_path = dir.path().join("audit.log");
+        let audit_log = AuditLog::new(
+            Arc::new(FileAuditSink::new(&audit_path)),
+            AuditRedaction::Literals,
+        );
+        let app_server = Arc::new(
+            AppServer::new(
+                ConnectionManagerImpl {},
+                Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+            )
+            .with_audit_log(audit_log),
+        );
+        app_server.set_id(1);
+        let server_url = test_server(Arc::clone(&app_server));
+
+        let client = Client::new();
+        let response = client
+            .put(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket",
+                server_url
+            ))
+            .body(r#"{"store_locally": true}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10")
+            .map(|l| l.unwrap())
+            .collect();
+        app_server
+            .write_lines("MyOrg_MyBucket", &lines)
+            .await
+            .unwrap();
+
+        for sql in &["select * from cpu where host = 'a'", "select * from nope"] {
+            client
+                .get(&format!("{}/api/v2/read", server_url))
+                .query(&[("org", "MyOrg"), ("bucket", "MyBucket"), ("sql_query", sql)])
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&audit_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let actions: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.action.as_str(),
+                    entry.database.as_deref(),
+                    entry.statement.as_deref(),
+                    entry.status,
+                )
+            })
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                (
+                    "create_database",
+                    Some("MyOrg_MyBucket"),
+                    None,
+                    AuditStatus::Succeeded
+                ),
+                (
+                    "query",
+                    Some("MyOrg_MyBucket"),
+                    Some("select * from cpu where host = '?'"),
+                    AuditStatus::Succeeded
+                ),
+                (
+                    "query",
+                    Some("MyOrg_MyBucket"),
+                    Some("select * from nope"),
+                    AuditStatus::Failed
+                ),
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.writer_id == Some(1)));
+    }
+
+    #[tokio::test]
+    async fn queries_fail_if_they_cant_be_audited() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        // a directory can't be appended to
+        let audit_log = AuditLog::new(
+            Arc::new(FileAuditSink::new(dir.path())),
+            AuditRedaction::default(),
+        );
+        let app_server = Arc::new(
+            AppServer::new(
+                ConnectionManagerImpl {},
+                Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+            )
+            .with_audit_log(audit_log),
+        );
+        app_server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        app_server
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10")
+            .map(|l| l.unwrap())
+            .collect();
+        app_server
+            .write_lines("MyOrg_MyBucket", &lines)
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&app_server));
+
+        let response = Client::new()
+            .get(&format!("{}/api/v2/read", server_url))
+            .query(&[
+                ("org", "MyOrg"),
+                ("bucket", "MyBucket"),
+                ("sql_query", "select * from cpu"),
+            ])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.text().await.unwrap();
+        assert!(!body.contains("host"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn requests_are_authorized() {
+        let scope = |token: &str, actions| TokenScope {
+            token_sha256: token_sha256(token),
+            actions,
+            databases: vec!["MyOrg_MyBucket".to_string()],
+        };
+        let token_scopes = TokenScopes {
+            scopes: vec![
+                scope("reader", vec![Action::Read]),
+                scope("owner", vec![Action::Admin]),
+            ],
+        };
+        let app_server = Arc::new(
+            AppServer::new(
+                ConnectionManagerImpl {},
+                Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+            )
+            .with_authorizer(Arc::new(token_scopes)),
+        );
+        app_server.set_id(1);
+        let server_url = test_server(app_server);
+
+        let client = Client::new();
+        let create = |token: &str| {
+            client
+                .put(&format!(
+                    "{}/iox/api/v1/databases/MyOrg_MyBucket",
+                    server_url
+                ))
+                .header(header::AUTHORIZATION, format!("Token {}", token))
+                .body(r#"{"store_locally": true}"#)
+                .send()
+        };
+        check_response(
+            "create",
+            create("reader").await,
+            StatusCode::FORBIDDEN,
+            r#"{"error":"not authorized to admin database MyOrg_MyBucket","error_code":100}"#,
+        )
+        .await;
+        check_response("create", create("owner").await, StatusCode::OK, "").await;
+
+        let write = |token: Option<&str>| {
+            let mut request = client
+                .post(&format!("{}/api/v2/write", server_url))
+                .query(&[("org", "MyOrg"), ("bucket", "MyBucket")])
+                .body("cpu,host=a usage=1 10");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+            }
+            request.send()
+        };
+        let response = write(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = write(Some("reader")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = write(Some("owner")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = client
+            .get(&format!("{}/api/v2/read", server_url))
+            .query(&[
+                ("org", "MyOrg"),
+                ("bucket", "MyBucket"),
+                ("sql_query", "select host from cpu"),
+            ])
+            .header(header::AUTHORIZATION, "Token reader")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the server itself isn't in the scope of either token
+        let response = client
+            .put(&format!("{}/iox/api/v1/id", server_url))
+            .header(header::AUTHORIZATION, "Token owner")
+            .body(r#"{"id": 2}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = client
+            .get(&format!("{}/debug/pprof/heap", server_url))
+            .header(header::AUTHORIZATION, "Token owner")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
     /// checks a http response against expected results
     async fn check_response(
         description: &str,
@@ -1009,7 +3648,16 @@ mod tests {
     /// creates an instance of the http service backed by a in-memory
     /// testable database.  Returns the url of the server
     fn test_server(server: Arc<AppServer<ConnectionManagerImpl>>) -> String {
-        let make_svc = router_service(server);
+        test_server_with_limits(server, RequestLimits::default())
+    }
+
+    /// creates an instance of the http service with the given request
+    /// limits. Returns the url of the server
+    fn test_server_with_limits(
+        server: Arc<AppServer<ConnectionManagerImpl>>,
+        limits: RequestLimits,
+    ) -> String {
+        let make_svc = router_service(server, limits);
 
         // NB: specify port 0 to let the OS pick the port.
         let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
@@ -9,7 +9,10 @@ pub(crate) const TAG_KEY_MEASUREMENT: &[u8] = &[0];
 pub(crate) const TAG_KEY_FIELD: &[u8] = &[255];
 
 pub mod data;
+pub mod encoder;
+pub mod error_details;
 pub mod expr;
+pub mod flight;
 pub mod id;
 pub mod input;
 pub mod service;
@@ -20,7 +20,7 @@ use generated_types::{
         frame::Data, BooleanPointsFrame, DataType, FloatPointsFrame, Frame, GroupFrame,
         IntegerPointsFrame, SeriesFrame, StringPointsFrame,
     },
-    MeasurementFieldsResponse, ReadResponse, Tag,
+    MeasurementFieldsResponse, Tag,
 };
 
 use super::{TAG_KEY_FIELD, TAG_KEY_MEASUREMENT};
@@ -71,7 +71,7 @@ fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
     Ok(frames)
 }
 
-/// Convert `SeriesSetItem` into a form suitable for gRPC transport
+/// Convert `SeriesSetItem` into frames suitable for gRPC transport
 ///
 /// Each `SeriesSetItem` gets converted into this pattern:
 ///
@@ -91,14 +91,15 @@ fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
 /// ```
 ///
 /// The specific type of (*Points) depends on the type of field column.
-pub fn series_set_item_to_read_response(series_set_item: SeriesSetItem) -> Result<ReadResponse> {
-    let frames = match series_set_item {
+/// The frames are not limited in size; see `encoder::FrameEncoder` for
+/// packing them into responses.
+pub fn series_set_item_to_frames(series_set_item: SeriesSetItem) -> Result<Vec<Frame>> {
+    match series_set_item {
         SeriesSetItem::GroupStart(group_description) => {
-            group_description_to_frames(group_description)?
+            group_description_to_frames(group_description)
         }
-        SeriesSetItem::Data(series_set) => series_set_to_frames(series_set)?,
-    };
-    Ok(ReadResponse { frames })
+        SeriesSetItem::Data(series_set) => series_set_to_frames(series_set),
+    }
 }
 
 fn group_description_to_frames(group_description: GroupDescription) -> Result<Vec<Frame>> {
@@ -248,6 +249,11 @@ fn convert_tags(
         Tag { key, value }
     }));
 
+    // The tags of a frame are sorted by key, like the tags of a series key
+    // in InfluxDB. The series sets are grouped by the tag order of the
+    // table, which is not the order the tags of their frames are in.
+    converted_tags.sort_by(|a, b| a.key.cmp(&b.key));
+
     converted_tags
 }
 
@@ -325,6 +331,7 @@ mod tests {
         datatypes::{DataType as ArrowDataType, Field as ArrowField, Schema},
         record_batch::RecordBatch,
     };
+    use generated_types::ReadResponse;
     use query::exec::{field::FieldIndexes, fieldlist::Field};
 
     use super::*;
@@ -356,6 +363,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_convert_tags_sorted_by_key() {
+        // series sets of a table with a tag order putting region first
+        let tags = vec![
+            (Arc::new("region".into()), Arc::new("west".into())),
+            (Arc::new("host".into()), Arc::new("a".into())),
+        ];
+
+        let keys = convert_tags("cpu", "usage", &tags)
+            .into_iter()
+            .map(|tag| String::from_utf8(tag.key).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["_field", "_measurement", "host", "region"]);
+    }
+
     fn series_set_to_read_response(series_set: SeriesSet) -> Result<ReadResponse> {
         let frames = series_set_to_frames(series_set)?;
         Ok(ReadResponse { frames })
@@ -525,14 +547,10 @@ mod tests {
 
         let grouped_series_set_item = SeriesSetItem::GroupStart(group_description);
 
-        let response = series_set_item_to_read_response(grouped_series_set_item)
+        let frames = series_set_item_to_frames(grouped_series_set_item)
             .expect("Correctly converted grouped_series_set_item");
 
-        let dumped_frames = response
-            .frames
-            .iter()
-            .map(|f| dump_frame(f))
-            .collect::<Vec<_>>();
+        let dumped_frames = frames.iter().map(|f| dump_frame(f)).collect::<Vec<_>>();
 
         let expected_frames =
             vec!["GroupFrame, tag_keys: tag1,tag2, partition_key_vals: val1,val2"];
@@ -568,14 +586,10 @@ mod tests {
 
         let series_set_item = SeriesSetItem::Data(series_set);
 
-        let response = series_set_item_to_read_response(series_set_item)
+        let frames = series_set_item_to_frames(series_set_item)
             .expect("Correctly converted series_set_item");
 
-        let dumped_frames = response
-            .frames
-            .iter()
-            .map(|f| dump_frame(f))
-            .collect::<Vec<_>>();
+        let dumped_frames = frames.iter().map(|f| dump_frame(f)).collect::<Vec<_>>();
 
         let expected_frames = vec![
             "SeriesFrame, tags: _field=float_field,_measurement=the_table,tag1=val1, type: 0",
@@ -0,0 +1,423 @@
+//! This module contains the encoder that packs the frames of a
+//! read_filter or read_group result into the sequence of `ReadResponse`
+//! messages sent over the storage gRPC API, limiting the size of each
+//! frame and message the same way InfluxDB does so clients that expect
+//! InfluxDB's limits are not surprised.
+
+use std::mem;
+
+use generated_types::{
+    read_response::{
+        frame::Data, BooleanPointsFrame, FloatPointsFrame, Frame, IntegerPointsFrame,
+        StringPointsFrame, UnsignedPointsFrame,
+    },
+    ReadResponse,
+};
+use prost::Message;
+use query::exec::seriesset::SeriesSetItem;
+
+use super::data::{series_set_item_to_frames, Result};
+
+/// The maximum number of points in a single points frame. Longer series
+/// are sent as several consecutive points frames.
+pub const MAX_POINTS_PER_FRAME: usize = 1000;
+
+/// The (estimated) size in bytes after which a response is sent and
+/// subsequent frames go into the next one
+pub const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Encodes `SeriesSetItem`s into `ReadResponse`s.
+///
+/// The frames of the items are appended to the current response, with
+/// points frames split so none has more than `max_points_per_frame`
+/// points. Once the estimated size of the response reaches
+/// `max_response_size` after a points frame, the response is complete and
+/// a new one is started, so a response never ends with a series frame
+/// whose points are in the next one. Like InfluxDB, the size of points
+/// frames is estimated from the size of their timestamps and values
+/// rather than their encoded size, which is expensive to compute.
+#[derive(Debug)]
+pub struct FrameEncoder {
+    max_points_per_frame: usize,
+    max_response_size: usize,
+    frames: Vec<Frame>,
+    size: usize,
+    /// The number of series frames encoded so far
+    series_count: usize,
+    /// The number of frames encoded so far
+    frame_count: usize,
+}
+
+impl Default for FrameEncoder {
+    fn default() -> Self {
+        Self::new(MAX_POINTS_PER_FRAME, MAX_RESPONSE_SIZE)
+    }
+}
+
+impl FrameEncoder {
+    pub fn new(max_points_per_frame: usize, max_response_size: usize) -> Self {
+        assert!(
+            max_points_per_frame > 0,
+            "frames must hold at least one point"
+        );
+        Self {
+            max_points_per_frame,
+            max_response_size,
+            frames: Vec::new(),
+            size: 0,
+            series_count: 0,
+            frame_count: 0,
+        }
+    }
+
+    /// The number of series encoded so far
+    pub fn series_count(&self) -> usize {
+        self.series_count
+    }
+
+    /// The number of frames encoded so far, after points frames were split
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Encodes `item`, returning the responses it completed (if any)
+    pub fn encode(&mut self, item: SeriesSetItem) -> Result<Vec<ReadResponse>> {
+        let mut responses = Vec::new();
+
+        for frame in series_set_item_to_frames(item)? {
+            match frame.data {
+                Some(data) if points(&data) > self.max_points_per_frame => {
+                    for data in split_points(data, self.max_points_per_frame) {
+                        self.push(data, &mut responses);
+                    }
+                }
+                Some(data) => self.push(data, &mut responses),
+                None => {}
+            }
+        }
+
+        Ok(responses)
+    }
+
+    /// Returns the last, partially filled, response, if there is one
+    pub fn finish(self) -> Option<ReadResponse> {
+        if self.frames.is_empty() {
+            None
+        } else {
+            Some(ReadResponse {
+                frames: self.frames,
+            })
+        }
+    }
+
+    fn push(&mut self, data: Data, responses: &mut Vec<ReadResponse>) {
+        self.size += estimated_size(&data);
+        self.frame_count += 1;
+        if matches!(data, Data::Series(_)) {
+            self.series_count += 1;
+        }
+        let is_points = !matches!(data, Data::Series(_) | Data::Group(_));
+        self.frames.push(Frame { data: Some(data) });
+
+        if is_points && self.size >= self.max_response_size {
+            let frames = mem::take(&mut self.frames);
+            responses.push(ReadResponse { frames });
+            self.size = 0;
+        }
+    }
+}
+
+/// The number of points in a frame (zero for series and group frames)
+fn points(data: &Data) -> usize {
+    match data {
+        Data::FloatPoints(frame) => frame.timestamps.len(),
+        Data::IntegerPoints(frame) => frame.timestamps.len(),
+        Data::UnsignedPoints(frame) => frame.timestamps.len(),
+        Data::BooleanPoints(frame) => frame.timestamps.len(),
+        Data::StringPoints(frame) => frame.timestamps.len(),
+        Data::Series(_) | Data::Group(_) => 0,
+    }
+}
+
+/// The size InfluxDB accounts for a frame: the size of the timestamps and
+/// values of points frames, or the encoded size of other frames
+fn estimated_size(data: &Data) -> usize {
+    const TIMESTAMP_SIZE: usize = mem::size_of::<i64>();
+
+    match data {
+        Data::FloatPoints(frame) => frame.timestamps.len() * (TIMESTAMP_SIZE + 8),
+        Data::IntegerPoints(frame) => frame.timestamps.len() * (TIMESTAMP_SIZE + 8),
+        Data::UnsignedPoints(frame) => frame.timestamps.len() * (TIMESTAMP_SIZE + 8),
+        Data::BooleanPoints(frame) => frame.timestamps.len() * (TIMESTAMP_SIZE + 1),
+        Data::StringPoints(frame) => {
+            frame.timestamps.len() * TIMESTAMP_SIZE
+                + frame.values.iter().map(|v| v.len()).sum::<usize>()
+        }
+        Data::Series(frame) => frame.encoded_len(),
+        Data::Group(frame) => frame.encoded_len(),
+    }
+}
+
+/// Splits a points frame into frames of at most `max_points` points
+fn split_points(data: Data, max_points: usize) -> Vec<Data> {
+    match data {
+        Data::FloatPoints(FloatPointsFrame { timestamps, values }) => {
+            split(timestamps, values, max_points, |timestamps, values| {
+                Data::FloatPoints(FloatPointsFrame { timestamps, values })
+            })
+        }
+        Data::IntegerPoints(IntegerPointsFrame { timestamps, values }) => {
+            split(timestamps, values, max_points, |timestamps, values| {
+                Data::IntegerPoints(IntegerPointsFrame { timestamps, values })
+            })
+        }
+        Data::UnsignedPoints(UnsignedPointsFrame { timestamps, values }) => {
+            split(timestamps, values, max_points, |timestamps, values| {
+                Data::UnsignedPoints(UnsignedPointsFrame { timestamps, values })
+            })
+        }
+        Data::BooleanPoints(BooleanPointsFrame { timestamps, values }) => {
+            split(timestamps, values, max_points, |timestamps, values| {
+                Data::BooleanPoints(BooleanPointsFrame { timestamps, values })
+            })
+        }
+        Data::StringPoints(StringPointsFrame { timestamps, values }) => {
+            split(timestamps, values, max_points, |timestamps, values| {
+                Data::StringPoints(StringPointsFrame { timestamps, values })
+            })
+        }
+        data @ Data::Series(_) | data @ Data::Group(_) => vec![data],
+    }
+}
+
+fn split<T>(
+    timestamps: Vec<i64>,
+    values: Vec<T>,
+    max_points: usize,
+    make_frame: impl Fn(Vec<i64>, Vec<T>) -> Data,
+) -> Vec<Data> {
+    let mut values = values.into_iter();
+    timestamps
+        .chunks(max_points)
+        .map(|timestamps| {
+            let values = values.by_ref().take(timestamps.len()).collect();
+            make_frame(timestamps.to_vec(), values)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::{ArrayRef, Float64Array, Int64Array},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use query::exec::{
+        field::FieldIndexes,
+        seriesset::{GroupDescription, SeriesSet},
+    };
+    use std::sync::Arc;
+
+    /// A series of the `cpu` table with `num_rows` points, for the fields
+    /// at `value_indexes` (1 is the `usage` float field, 0 the `count`
+    /// integer field)
+    fn series(host: &str, num_rows: usize, value_indexes: &[usize]) -> SeriesSetItem {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("count", DataType::Int64, true),
+            Field::new("usage", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+
+        let counts: ArrayRef = Arc::new(Int64Array::from((0..num_rows as i64).collect::<Vec<_>>()));
+        let usages: ArrayRef = Arc::new(Float64Array::from(
+            (0..num_rows).map(|i| i as f64).collect::<Vec<_>>(),
+        ));
+        let times: ArrayRef = Arc::new(Int64Array::from((0..num_rows as i64).collect::<Vec<_>>()));
+        let batch = RecordBatch::try_new(schema, vec![counts, usages, times])
+            .expect("created new record batch");
+
+        SeriesSetItem::Data(SeriesSet {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![(Arc::new("host".into()), Arc::new(host.into()))],
+            field_indexes: FieldIndexes::from_timestamp_and_value_indexes(2, value_indexes),
+            start_row: 0,
+            num_rows,
+            batch,
+        })
+    }
+
+    fn group(host: &str) -> SeriesSetItem {
+        SeriesSetItem::GroupStart(GroupDescription {
+            tags: vec![(Arc::new("host".into()), Arc::new(host.into()))],
+        })
+    }
+
+    fn encode(mut encoder: FrameEncoder, items: Vec<SeriesSetItem>) -> Vec<ReadResponse> {
+        let mut responses = Vec::new();
+        for item in items {
+            responses.extend(encoder.encode(item).expect("encoded item"));
+        }
+        responses.extend(encoder.finish());
+        responses
+    }
+
+    fn join(values: &[Vec<u8>]) -> String {
+        values
+            .iter()
+            .map(|v| String::from_utf8_lossy(v))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn dump_points(name: &str, timestamps: &[i64]) -> String {
+        format!(
+            "{}, points: {}, timestamps: {}..={}",
+            name,
+            timestamps.len(),
+            timestamps[0],
+            timestamps[timestamps.len() - 1]
+        )
+    }
+
+    /// Dumps `responses`, one line per response and frame
+    fn dump(responses: &[ReadResponse]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (i, response) in responses.iter().enumerate() {
+            lines.push(format!("response {}:", i));
+            for data in response.frames.iter().flat_map(|f| f.data.as_ref()) {
+                let line = match data {
+                    Data::Group(frame) => format!(
+                        "GroupFrame, tag_keys: {}, partition_key_vals: {}",
+                        join(&frame.tag_keys),
+                        join(&frame.partition_key_vals)
+                    ),
+                    Data::Series(frame) => {
+                        let tags = frame
+                            .tags
+                            .iter()
+                            .map(|tag| {
+                                format!(
+                                    "{}={}",
+                                    String::from_utf8_lossy(&tag.key),
+                                    String::from_utf8_lossy(&tag.value)
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        format!("SeriesFrame, tags: {}", tags)
+                    }
+                    Data::FloatPoints(frame) => dump_points("FloatPointsFrame", &frame.timestamps),
+                    Data::IntegerPoints(frame) => {
+                        dump_points("IntegerPointsFrame", &frame.timestamps)
+                    }
+                    Data::UnsignedPoints(frame) => {
+                        dump_points("UnsignedPointsFrame", &frame.timestamps)
+                    }
+                    Data::BooleanPoints(frame) => {
+                        dump_points("BooleanPointsFrame", &frame.timestamps)
+                    }
+                    Data::StringPoints(frame) => {
+                        dump_points("StringPointsFrame", &frame.timestamps)
+                    }
+                };
+                lines.push(format!("  {}", line));
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn long_series_are_split_into_frames() {
+        let responses = encode(FrameEncoder::default(), vec![series("a", 2500, &[1])]);
+
+        let expected = vec![
+            "response 0:",
+            "  SeriesFrame, tags: _field=usage,_measurement=cpu,host=a",
+            "  FloatPointsFrame, points: 1000, timestamps: 0..=999",
+            "  FloatPointsFrame, points: 1000, timestamps: 1000..=1999",
+            "  FloatPointsFrame, points: 500, timestamps: 2000..=2499",
+        ];
+        assert_eq!(dump(&responses), expected);
+    }
+
+    #[test]
+    fn large_results_are_split_into_responses() {
+        let responses = encode(
+            FrameEncoder::default(),
+            vec![series("a", 3000, &[1]), series("b", 3000, &[1])],
+        );
+
+        // 16 bytes per float point, so the fifth frame of 1000 points
+        // takes the response past 64KiB
+        let expected = vec![
+            "response 0:",
+            "  SeriesFrame, tags: _field=usage,_measurement=cpu,host=a",
+            "  FloatPointsFrame, points: 1000, timestamps: 0..=999",
+            "  FloatPointsFrame, points: 1000, timestamps: 1000..=1999",
+            "  FloatPointsFrame, points: 1000, timestamps: 2000..=2999",
+            "  SeriesFrame, tags: _field=usage,_measurement=cpu,host=b",
+            "  FloatPointsFrame, points: 1000, timestamps: 0..=999",
+            "  FloatPointsFrame, points: 1000, timestamps: 1000..=1999",
+            "response 1:",
+            "  FloatPointsFrame, points: 1000, timestamps: 2000..=2999",
+        ];
+        assert_eq!(dump(&responses), expected);
+    }
+
+    #[test]
+    fn groups_are_split_like_series() {
+        let responses = encode(
+            FrameEncoder::default(),
+            vec![
+                group("a"),
+                series("a", 10, &[1, 0]),
+                group("b"),
+                series("b", 1500, &[1, 0]),
+            ],
+        );
+
+        let expected = vec![
+            "response 0:",
+            "  GroupFrame, tag_keys: host, partition_key_vals: a",
+            "  SeriesFrame, tags: _field=usage,_measurement=cpu,host=a",
+            "  FloatPointsFrame, points: 10, timestamps: 0..=9",
+            "  SeriesFrame, tags: _field=count,_measurement=cpu,host=a",
+            "  IntegerPointsFrame, points: 10, timestamps: 0..=9",
+            "  GroupFrame, tag_keys: host, partition_key_vals: b",
+            "  SeriesFrame, tags: _field=usage,_measurement=cpu,host=b",
+            "  FloatPointsFrame, points: 1000, timestamps: 0..=999",
+            "  FloatPointsFrame, points: 500, timestamps: 1000..=1499",
+            "  SeriesFrame, tags: _field=count,_measurement=cpu,host=b",
+            "  IntegerPointsFrame, points: 1000, timestamps: 0..=999",
+            "  IntegerPointsFrame, points: 500, timestamps: 1000..=1499",
+        ];
+        assert_eq!(dump(&responses), expected);
+    }
+
+    #[test]
+    fn responses_end_after_points() {
+        // every points frame exceeds the limit, so each completes a response
+        let responses = encode(FrameEncoder::new(2, 1), vec![series("a", 3, &[1, 0])]);
+
+        let frames_per_response: Vec<_> = responses.iter().map(|r| r.frames.len()).collect();
+        assert_eq!(frames_per_response, vec![2, 1, 2, 1]);
+    }
+
+    #[test]
+    fn counts_series_and_frames() {
+        let mut encoder = FrameEncoder::new(2, MAX_RESPONSE_SIZE);
+        encoder.encode(group("a")).unwrap();
+        encoder.encode(series("a", 3, &[1, 0])).unwrap();
+
+        // a group frame, and a series frame and two points frames per field
+        assert_eq!(encoder.series_count(), 2);
+        assert_eq!(encoder.frame_count(), 7);
+    }
+
+    #[test]
+    fn empty_results_have_no_responses() {
+        assert!(FrameEncoder::default().finish().is_none());
+    }
+}
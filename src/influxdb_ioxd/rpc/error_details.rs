@@ -0,0 +1,126 @@
+//! This module contains the creation of gRPC error statuses carrying
+//! structured error details in the `google.rpc` format, which client
+//! tooling can decode from the `grpc-status-details-bin` trailer to tell
+//! users what to change instead of showing them an opaque message
+
+use generated_types::google::rpc::{
+    bad_request::FieldViolation, quota_failure::Violation, BadRequest, QuotaFailure,
+    Status as RpcStatus,
+};
+use prost::Message;
+use tonic::{Code, Status};
+
+/// The message of the statuses of errors that are not caused by the
+/// request
+pub const INTERNAL_ERROR_MESSAGE: &str = "Internal error";
+
+/// Returns an `INTERNAL` status. The details of internal errors are
+/// logged but not returned, as they are of no use to clients and may
+/// expose the internals of the server.
+pub fn internal_error() -> Status {
+    Status::internal(INTERNAL_ERROR_MESSAGE)
+}
+
+/// Returns an `INVALID_ARGUMENT` status whose details describe what is
+/// wrong with the request field `field`
+pub fn field_violation(field: impl Into<String>, description: impl Into<String>) -> Status {
+    let description = description.into();
+    let details = BadRequest {
+        field_violations: vec![FieldViolation {
+            field: field.into(),
+            description: description.clone(),
+        }],
+    };
+    with_details(Code::InvalidArgument, description, "BadRequest", details)
+}
+
+/// Returns a `RESOURCE_EXHAUSTED` status whose details describe the quota
+/// of `subject` (e.g. `database:<name>`) the request exceeded
+pub fn quota_failure(subject: impl Into<String>, description: impl Into<String>) -> Status {
+    let description = description.into();
+    let details = QuotaFailure {
+        violations: vec![Violation {
+            subject: subject.into(),
+            description: description.clone(),
+        }],
+    };
+    with_details(
+        Code::ResourceExhausted,
+        description,
+        "QuotaFailure",
+        details,
+    )
+}
+
+/// Returns a status with `details`, a message of the `google.rpc` package
+/// named `type_name`, encoded as a `google.rpc.Status`
+fn with_details(code: Code, message: String, type_name: &str, details: impl Message) -> Status {
+    let status = RpcStatus {
+        code: code as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: format!("type.googleapis.com/google.rpc.{}", type_name),
+            value: encode(&details),
+        }],
+    };
+
+    Status::with_details(code, message, encode(&status).into())
+}
+
+fn encode(message: &impl Message) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(message.encoded_len());
+    message
+        .encode(&mut buf)
+        .expect("encoding into a Vec can't run out of space");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_violations_are_attached() {
+        let status = field_violation("predicate", "unknown node type");
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "unknown node type");
+
+        let details = RpcStatus::decode(status.details()).unwrap();
+        assert_eq!(details.code, Code::InvalidArgument as i32);
+        assert_eq!(details.details.len(), 1);
+        assert_eq!(
+            details.details[0].type_url,
+            "type.googleapis.com/google.rpc.BadRequest"
+        );
+
+        let bad_request = BadRequest::decode(&details.details[0].value[..]).unwrap();
+        assert_eq!(
+            bad_request.field_violations,
+            vec![FieldViolation {
+                field: "predicate".to_string(),
+                description: "unknown node type".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn quota_failures_are_attached() {
+        let status = quota_failure("database:my_db", "too many queries");
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let details = RpcStatus::decode(status.details()).unwrap();
+        assert_eq!(
+            details.details[0].type_url,
+            "type.googleapis.com/google.rpc.QuotaFailure"
+        );
+
+        let quota_failure = QuotaFailure::decode(&details.details[0].value[..]).unwrap();
+        assert_eq!(
+            quota_failure.violations,
+            vec![Violation {
+                subject: "database:my_db".to_string(),
+                description: "too many queries".to_string(),
+            }]
+        );
+    }
+}
@@ -13,12 +13,15 @@ use arrow_deps::datafusion::{
 };
 use generated_types::{
     aggregate::AggregateType as RPCAggregateType, node::Comparison as RPCComparison,
-    node::Logical as RPCLogical, node::Value as RPCValue, read_group_request::Group as RPCGroup,
-    Aggregate as RPCAggregate, Duration as RPCDuration, Node as RPCNode, Predicate as RPCPredicate,
-    Window as RPCWindow,
+    node::Logical as RPCLogical, node::Type as RPCNodeType, node::Value as RPCValue,
+    read_group_request::Group as RPCGroup, Aggregate as RPCAggregate, Duration as RPCDuration,
+    Node as RPCNode, Predicate as RPCPredicate, Window as RPCWindow,
 };
 
 use super::{TAG_KEY_FIELD, TAG_KEY_MEASUREMENT};
+use query::func::regex_match::{
+    make_regex_match_expr, make_starts_with_expr, regex_matches_empty, Error as RegexMatchError,
+};
 use query::group_by::{Aggregate as QueryAggregate, GroupByAndAggregate, WindowDuration};
 use query::predicate::PredicateBuilder;
 use snafu::{ResultExt, Snafu};
@@ -67,21 +70,23 @@ pub enum Error {
     InternalInvalidFieldReference {},
 
     #[snafu(display(
-        "Error creating predicate: Regular expression predicates are not supported: {}",
+        "Error creating predicate: Regular expression outside of a regex comparison: {}",
         regexp
     ))]
     RegExpLiteralNotSupported { regexp: String },
 
-    #[snafu(display("Error creating predicate: Regular expression predicates are not supported"))]
-    RegExpNotSupported {},
+    #[snafu(display("Error creating predicate: {}", source))]
+    InvalidRegex { source: RegexMatchError },
 
     #[snafu(display(
-        "Error creating predicate: Not Regular expression predicates are not supported"
+        "Error creating predicate: Unsupported operands for comparison {}: {} children (expected an expression and a pattern)",
+        comparison,
+        num_children
     ))]
-    NotRegExpNotSupported {},
-
-    #[snafu(display("Error creating predicate: StartsWith comparisons not supported"))]
-    StartsWithNotSupported {},
+    UnsupportedPatternComparison {
+        comparison: i32,
+        num_children: usize,
+    },
 
     #[snafu(display(
         "Error creating predicate: Unexpected children for predicate: {:?}",
@@ -354,21 +359,44 @@ pub trait SpecialTagKeys {
     fn is_field(&self) -> bool;
 }
 
-impl SpecialTagKeys for Vec<u8> {
+/// The names Flux uses for the measurement and field tag keys in schema
+/// requests, alongside the magic bytes
+const TAG_KEY_MEASUREMENT_NAME: &[u8] = b"_measurement";
+const TAG_KEY_FIELD_NAME: &[u8] = b"_field";
+
+impl SpecialTagKeys for [u8] {
     fn is_measurement(&self) -> bool {
-        self.as_slice() == TAG_KEY_MEASUREMENT
+        self == TAG_KEY_MEASUREMENT || self == TAG_KEY_MEASUREMENT_NAME
     }
 
     /// Return true if this tag key actually refers to a field
     /// name (e.g. _field or _f)
     fn is_field(&self) -> bool {
-        self.as_slice() == TAG_KEY_FIELD
+        self == TAG_KEY_FIELD || self == TAG_KEY_FIELD_NAME
     }
 }
 
-// Note that is_field can *NEVER* return true for a `String` because 0xff
-// is not a valid UTF-8 character, and thus can not be a valid Rust
-// String.
+impl SpecialTagKeys for Vec<u8> {
+    fn is_measurement(&self) -> bool {
+        self.as_slice().is_measurement()
+    }
+
+    fn is_field(&self) -> bool {
+        self.as_slice().is_field()
+    }
+}
+
+impl SpecialTagKeys for String {
+    fn is_measurement(&self) -> bool {
+        self.as_bytes().is_measurement()
+    }
+
+    // Note that this can only be true for `_field`, as 0xff is not a
+    // valid UTF-8 character, and thus can not be in a Rust String.
+    fn is_field(&self) -> bool {
+        self.as_bytes().is_field()
+    }
+}
 
 // converts a Node from the RPC layer into a datafusion logical expr
 fn convert_node_to_expr(node: RPCNode) -> Result<Expr> {
@@ -377,15 +405,126 @@ fn convert_node_to_expr(node: RPCNode) -> Result<Expr> {
         node_type: _,
         value,
     } = node;
+
+    let value = value.expect("Normalization removed all None values");
+    if let RPCValue::Comparison(comparison) = value {
+        return convert_comparison_node(comparison, children);
+    }
+
     let inputs = children
         .into_iter()
         .map(convert_node_to_expr)
         .collect::<Result<Vec<_>>>()?;
 
-    let value = value.expect("Normalization removed all None values");
     build_node(value, inputs)
 }
 
+/// Creates an expr from a "Comparison" Node and its children.
+///
+/// Flux and InfluxDB treat a tag that a series doesn't have as having the
+/// empty string as value, so a comparison of a tag with a string that the
+/// empty string passes (e.g. `tag != "a"` or `tag =~ /a*/`) also matches
+/// rows where the tag is null.
+fn convert_comparison_node(comparison: i32, children: Vec<RPCNode>) -> Result<Expr> {
+    let tag_value = match children.as_slice() {
+        [RPCNode {
+            value: Some(RPCValue::TagRefValue(tag_name)),
+            ..
+        }, RPCNode {
+            value: Some(RPCValue::StringValue(value)),
+            ..
+        }]
+        | [RPCNode {
+            value: Some(RPCValue::TagRefValue(tag_name)),
+            ..
+        }, RPCNode {
+            value: Some(RPCValue::RegexValue(value)),
+            ..
+        }] => Some((tag_name.clone(), value.clone())),
+        _ => None,
+    };
+
+    let expr = if comparison == RPCComparison::Regex as i32 {
+        let (lhs, pattern) = convert_pattern_operands(comparison, children)?;
+        make_regex_match_expr(lhs, &pattern).context(InvalidRegex)?
+    } else if comparison == RPCComparison::NotRegex as i32 {
+        let (lhs, pattern) = convert_pattern_operands(comparison, children)?;
+        let regex_match = make_regex_match_expr(lhs, &pattern).context(InvalidRegex)?;
+        Expr::Not(Box::new(regex_match))
+    } else if comparison == RPCComparison::StartsWith as i32 {
+        let (lhs, prefix) = convert_pattern_operands(comparison, children)?;
+        make_starts_with_expr(lhs, &prefix)
+    } else {
+        let inputs = children
+            .into_iter()
+            .map(convert_node_to_expr)
+            .collect::<Result<Vec<_>>>()?;
+        build_comparison_node(comparison, inputs)?
+    };
+
+    match tag_value {
+        Some((tag_name, value)) if empty_string_passes(comparison, &value)? => {
+            let is_null = Expr::IsNull(Box::new(col(&make_tag_name(tag_name)?)));
+            Ok(binary_expr(is_null, Operator::Or, expr))
+        }
+        _ => Ok(expr),
+    }
+}
+
+/// Converts the children of a regex or prefix comparison into the
+/// compared expression and the pattern (or prefix) it is compared with
+fn convert_pattern_operands(comparison: i32, children: Vec<RPCNode>) -> Result<(Expr, String)> {
+    let num_children = children.len();
+    let mut children = children.into_iter();
+
+    match (children.next(), children.next(), children.next()) {
+        (
+            Some(lhs),
+            Some(RPCNode {
+                value: Some(RPCValue::RegexValue(pattern)),
+                ..
+            }),
+            None,
+        )
+        | (
+            Some(lhs),
+            Some(RPCNode {
+                value: Some(RPCValue::StringValue(pattern)),
+                ..
+            }),
+            None,
+        ) => Ok((convert_node_to_expr(lhs)?, pattern)),
+        _ => UnsupportedPatternComparison {
+            comparison,
+            num_children,
+        }
+        .fail(),
+    }
+}
+
+/// Returns true if the empty string passes `comparison` with `value`
+fn empty_string_passes(comparison: i32, value: &str) -> Result<bool> {
+    let passes = if comparison == RPCComparison::Equal as i32
+        || comparison == RPCComparison::Gte as i32
+        || comparison == RPCComparison::StartsWith as i32
+    {
+        value.is_empty()
+    } else if comparison == RPCComparison::NotEqual as i32 || comparison == RPCComparison::Lt as i32
+    {
+        !value.is_empty()
+    } else if comparison == RPCComparison::Lte as i32 {
+        true
+    } else if comparison == RPCComparison::Regex as i32 {
+        regex_matches_empty(value).context(InvalidRegex)?
+    } else if comparison == RPCComparison::NotRegex as i32 {
+        !regex_matches_empty(value).context(InvalidRegex)?
+    } else {
+        false
+    };
+
+    Ok(passes)
+}
+
 fn make_tag_name(tag_name: Vec<u8>) -> Result<String> {
     // These should have been handled at a higher level -- if we get
     // here it is too late
@@ -401,7 +540,7 @@ fn make_tag_name(tag_name: Vec<u8>) -> Result<String> {
 // Builds an Expr given the Value and the converted children
 fn build_node(value: RPCValue, inputs: Vec<Expr>) -> Result<Expr> {
     // Only logical / comparison ops can have inputs.
-    let can_have_children = matches!(&value,  RPCValue::Logical(_) | RPCValue::Comparison(_));
+    let can_have_children = matches!(&value, RPCValue::Logical(_) | RPCValue::Comparison(_));
 
     if !can_have_children && !inputs.is_empty() {
         return UnexpectedChildren { value }.fail();
@@ -413,6 +552,8 @@ fn build_node(value: RPCValue, inputs: Vec<Expr>) -> Result<Expr> {
         RPCValue::IntValue(v) => Ok(lit(v)),
         RPCValue::UintValue(v) => Ok(lit(v)),
         RPCValue::FloatValue(f) => Ok(lit(f)),
+        // regular expressions are only valid as the pattern of a regex
+        // comparison
         RPCValue::RegexValue(regexp) => RegExpLiteralNotSupported { regexp }.fail(),
         RPCValue::TagRefValue(tag_name) => Ok(col(&make_tag_name(tag_name)?)),
         RPCValue::FieldRefValue(field_name) => Ok(col(&field_name)),
@@ -444,12 +585,6 @@ fn build_comparison_node(comparison: i32, inputs: Vec<Expr>) -> Result<Expr> {
         build_binary_expr(Operator::Eq, inputs)
     } else if comparison == RPCComparison::NotEqual as i32 {
         build_binary_expr(Operator::NotEq, inputs)
-    } else if comparison == RPCComparison::StartsWith as i32 {
-        StartsWithNotSupported {}.fail()
-    } else if comparison == RPCComparison::Regex as i32 {
-        RegExpNotSupported {}.fail()
-    } else if comparison == RPCComparison::NotRegex as i32 {
-        NotRegExpNotSupported {}.fail()
     } else if comparison == RPCComparison::Lt as i32 {
         build_binary_expr(Operator::Lt, inputs)
     } else if comparison == RPCComparison::Lte as i32 {
@@ -613,6 +748,77 @@ fn convert_aggregate(aggregate: Option<RPCAggregate>) -> Result<QueryAggregate>
     }
 }
 
+/// The aggregates of the storage RPC, by the names the `Capabilities` call
+/// reports them with
+const RPC_AGGREGATES: &[(&str, RPCAggregateType)] = &[
+    ("Count", RPCAggregateType::Count),
+    ("Sum", RPCAggregateType::Sum),
+    ("First", RPCAggregateType::First),
+    ("Last", RPCAggregateType::Last),
+    ("Min", RPCAggregateType::Min),
+    ("Max", RPCAggregateType::Max),
+    ("Mean", RPCAggregateType::Mean),
+];
+
+/// The comparisons of the storage RPC predicates, by the names the
+/// `Capabilities` call reports them with
+const RPC_COMPARISONS: &[(&str, RPCComparison)] = &[
+    ("Equal", RPCComparison::Equal),
+    ("NotEqual", RPCComparison::NotEqual),
+    ("StartsWith", RPCComparison::StartsWith),
+    ("Regex", RPCComparison::Regex),
+    ("NotRegex", RPCComparison::NotRegex),
+    ("Lt", RPCComparison::Lt),
+    ("Lte", RPCComparison::Lte),
+    ("Gt", RPCComparison::Gt),
+    ("Gte", RPCComparison::Gte),
+];
+
+/// Returns the names of the RPC aggregates that convert to an aggregate
+/// accepted by `supported`, such as `QueryAggregate::supports_window`
+pub fn supported_aggregates(supported: impl Fn(&QueryAggregate) -> bool) -> Vec<String> {
+    RPC_AGGREGATES
+        .iter()
+        .filter(|(_, aggregate_type)| {
+            let aggregate = RPCAggregate {
+                r#type: *aggregate_type as i32,
+            };
+            convert_aggregate(Some(aggregate)).map_or(false, |agg| supported(&agg))
+        })
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Returns the names of the RPC comparisons of a tag with a value that
+/// convert to an expression accepted by `supported`, such as
+/// `PartitionChunk::supports_expr`
+pub fn supported_comparisons(supported: impl Fn(&Expr) -> bool) -> Vec<String> {
+    RPC_COMPARISONS
+        .iter()
+        .filter(|(_, comparison)| {
+            let value = match comparison {
+                RPCComparison::Regex | RPCComparison::NotRegex => RPCValue::RegexValue("^a".into()),
+                _ => RPCValue::StringValue("a".into()),
+            };
+            let children = vec![
+                RPCNode {
+                    node_type: RPCNodeType::TagRef as i32,
+                    children: vec![],
+                    value: Some(RPCValue::TagRefValue(b"tag".to_vec())),
+                },
+                RPCNode {
+                    node_type: RPCNodeType::Literal as i32,
+                    children: vec![],
+                    value: Some(value),
+                },
+            ];
+            convert_comparison_node(*comparison as i32, children)
+                .map_or(false, |expr| supported(&expr))
+        })
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
 pub fn convert_group_type(group: i32) -> Result<RPCGroup> {
     if group == RPCGroup::None as i32 {
         Ok(RPCGroup::None)
@@ -770,7 +976,6 @@ fn format_comparison(v: i32, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 
 #[cfg(test)]
 mod tests {
-    use generated_types::node::Type as RPCNodeType;
     use std::collections::BTreeSet;
 
     use super::*;
@@ -915,6 +1120,131 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_convert_predicate_comparisons() {
+        let host = || make_node(RPCNodeType::TagRef, RPCValue::TagRefValue(b"host".to_vec()));
+        let usage = || {
+            make_node(
+                RPCNodeType::FieldRef,
+                RPCValue::FieldRefValue("usage".into()),
+            )
+        };
+        let string = |s: &str| make_node(RPCNodeType::Literal, RPCValue::StringValue(s.into()));
+        let regex = |s: &str| make_node(RPCNodeType::Literal, RPCValue::RegexValue(s.into()));
+        let regex_match = |expr: Expr, pattern: &str| make_regex_match_expr(expr, pattern).unwrap();
+        // rows without the host tag pass comparisons the empty string passes
+        let or_null_host =
+            |expr: Expr| binary_expr(Expr::IsNull(Box::new(col("host"))), Operator::Or, expr);
+
+        let cases = vec![
+            (
+                make_comparison_node(host(), RPCComparison::Equal, string("a")),
+                col("host").eq(lit("a")),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::Equal, string("")),
+                or_null_host(col("host").eq(lit(""))),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::NotEqual, string("a")),
+                or_null_host(col("host").not_eq(lit("a"))),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::Lt, string("b")),
+                or_null_host(col("host").lt(lit("b"))),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::Gt, string("b")),
+                col("host").gt(lit("b")),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::Regex, regex("^serv")),
+                regex_match(col("host"), "^serv"),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::Regex, regex("a*")),
+                or_null_host(regex_match(col("host"), "a*")),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::NotRegex, regex("^serv")),
+                or_null_host(Expr::Not(Box::new(regex_match(col("host"), "^serv")))),
+            ),
+            (
+                make_comparison_node(host(), RPCComparison::StartsWith, string("se.")),
+                regex_match(col("host"), "^se\\."),
+            ),
+            (
+                make_comparison_node(usage(), RPCComparison::Regex, regex("^serv")),
+                regex_match(col("usage"), "^serv"),
+            ),
+            (
+                make_comparison_node(usage(), RPCComparison::NotEqual, string("a")),
+                col("usage").not_eq(lit("a")),
+            ),
+        ];
+
+        for (node, expected_expr) in cases {
+            let node_string = format!("{:?}", node);
+            let predicate = PredicateBuilder::default()
+                .rpc_predicate(Some(RPCPredicate { root: Some(node) }))
+                .expect("successfully converting predicate")
+                .build();
+
+            assert_eq!(predicate.exprs.len(), 1);
+            let converted_expr = format!("{:?}", predicate.exprs[0]);
+            let expected_expr = format!("{:?}", expected_expr);
+            assert_eq!(
+                expected_expr, converted_expr,
+                "unexpected conversion of {}",
+                node_string
+            );
+        }
+    }
+
+    #[test]
+    fn test_convert_predicate_bad_regex() {
+        let host = make_node(RPCNodeType::TagRef, RPCValue::TagRefValue(b"host".to_vec()));
+
+        let cases = vec![
+            (
+                make_comparison_node(
+                    host.clone(),
+                    RPCComparison::Regex,
+                    make_node(RPCNodeType::Literal, RPCValue::RegexValue("(".into())),
+                ),
+                "Invalid regular expression '('",
+            ),
+            (
+                make_comparison_node(
+                    host.clone(),
+                    RPCComparison::Equal,
+                    make_node(RPCNodeType::Literal, RPCValue::RegexValue("a".into())),
+                ),
+                "Regular expression outside of a regex comparison: a",
+            ),
+            (
+                make_comparison_node(
+                    host,
+                    RPCComparison::Regex,
+                    make_node(RPCNodeType::Literal, RPCValue::IntValue(1)),
+                ),
+                "Unsupported operands for comparison",
+            ),
+        ];
+
+        for (node, expected_error) in cases {
+            let res =
+                PredicateBuilder::default().rpc_predicate(Some(RPCPredicate { root: Some(node) }));
+            let actual_error = error_result_to_string(res);
+            assert!(
+                actual_error.contains(expected_error),
+                "expected '{}' not found in '{}'",
+                expected_error,
+                actual_error
+            );
+        }
+    }
+
     #[test]
     fn test_convert_predicate_field_selection() {
         let field_selection = make_field_ref_node("field1");
@@ -1119,6 +1449,23 @@ mod tests {
         }
     }
 
+    fn make_node(node_type: RPCNodeType, value: RPCValue) -> RPCNode {
+        RPCNode {
+            node_type: node_type as i32,
+            children: vec![],
+            value: Some(value),
+        }
+    }
+
+    /// make a `lhs <comparison> rhs` node
+    fn make_comparison_node(lhs: RPCNode, comparison: RPCComparison, rhs: RPCNode) -> RPCNode {
+        RPCNode {
+            node_type: RPCNodeType::ComparisonExpression as i32,
+            children: vec![lhs, rhs],
+            value: Some(RPCValue::Comparison(comparison as i32)),
+        }
+    }
+
     /// make n1 OR n2
     fn make_or_node(n1: RPCNode, n2: RPCNode) -> RPCNode {
         RPCNode {
@@ -1471,4 +1818,41 @@ mod tests {
             format!("{}", displayable_predicate(rpc_pred.as_ref()))
         );
     }
+
+    #[test]
+    fn test_supported_comparisons() {
+        assert_eq!(
+            supported_comparisons(|_| true),
+            RPC_COMPARISONS
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect::<Vec<_>>()
+        );
+
+        // comparisons whose expressions the chunks can't be queried with
+        // aren't reported
+        let only_equal = supported_comparisons(|expr| {
+            matches!(
+                expr,
+                Expr::BinaryExpr {
+                    op: Operator::Eq,
+                    ..
+                }
+            )
+        });
+        assert_eq!(only_equal, vec!["Equal".to_string()]);
+    }
+
+    #[test]
+    fn test_special_tag_keys() {
+        assert!(TAG_KEY_MEASUREMENT.to_vec().is_measurement());
+        assert!(b"_measurement".to_vec().is_measurement());
+        assert!(String::from("_measurement").is_measurement());
+        assert!(!b"measurement".to_vec().is_measurement());
+
+        assert!(TAG_KEY_FIELD.to_vec().is_field());
+        assert!(b"_field".to_vec().is_field());
+        assert!(String::from("_field").is_field());
+        assert!(!String::from("_measurement").is_field());
+    }
 }
@@ -0,0 +1,885 @@
+//! This module contains the Arrow Flight service of the server, which
+//! supports `DoExchange`: a client streams record batches to write to the
+//! tables of a database, and SQL queries to run against it, over a single
+//! long-lived stream, and receives an acknowledgement of each write and
+//! the results of each query on the same stream.
+//!
+//! The messages a client sends are interpreted in order:
+//!
+//! * A message with a descriptor of type `PATH` selects the database named
+//!   by the first element of the path for the messages that follow. If the
+//!   path has a second element, it names the table the record batches that
+//!   follow are written to, and the message must carry their schema. The
+//!   `cmd` of the descriptor may then be the acknowledgement level of the
+//!   writes, `buffered`, `wal` or `replicated`, like the `ack` parameter of
+//!   the HTTP write API; it is `replicated` by default.
+//! * A message with a record batch writes its rows to the selected table
+//!   through the write path of the server, and is answered once the write
+//!   has reached the acknowledgement level with a message whose
+//!   `app_metadata` is the JSON of `ExchangeAck::Written`.
+//! * A message with only `app_metadata` runs it as a SQL query against the
+//!   selected database. The schema and the record batches of the results
+//!   are streamed back as they are produced, followed by a message whose
+//!   `app_metadata` is the JSON of `ExchangeAck::Queried`.
+//!
+//! Writes and queries are recorded in the audit log of the server. The
+//! first error ends the exchange with its status.
+
+use std::{convert::TryFrom, fmt::Debug, pin::Pin, sync::Arc};
+
+use arrow_deps::{
+    arrow::{
+        datatypes::{Schema, SchemaRef},
+        error::ArrowError,
+        ipc::writer::IpcWriteOptions,
+    },
+    arrow_flight::{
+        flight_descriptor::DescriptorType,
+        flight_service_server::FlightService,
+        utils::{
+            flight_data_from_arrow_batch, flight_data_from_arrow_schema, flight_data_to_arrow_batch,
+        },
+        Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+        HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+    },
+    datafusion::error::DataFusionError,
+};
+use data_types::{access_policy, DatabaseName, DatabaseNameError};
+use futures::{Stream, StreamExt};
+use query::{frontend::sql::SQLQueryPlanner, Database, DatabaseStore};
+use serde::{Deserialize, Serialize};
+use server::{
+    ack::{WriteAck, WriteAckLevel},
+    audit::AuditAction,
+    authz::{self, Decision, Principal},
+    db::Db,
+    ConnectionManager, Server as AppServer,
+};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{info, warn};
+
+use super::{error_details::internal_error, service::request_token};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Descriptor must be a path of a database and optionally a table: {}",
+        descriptor
+    ))]
+    InvalidDescriptor { descriptor: String },
+
+    #[snafu(display("Invalid database name: {}", source))]
+    InvalidDatabaseName { source: DatabaseNameError },
+
+    #[snafu(display("Database not found: {}", db_name))]
+    DatabaseNotFound { db_name: String },
+
+    #[snafu(display("Database {} is not ready: {}", db_name, state))]
+    DatabaseNotReady {
+        db_name: String,
+        state: server::recovery::RecoveryState,
+    },
+
+    #[snafu(display("Not authorized to {} database {}", action, db_name))]
+    NotAuthorized {
+        action: authz::Action,
+        db_name: String,
+    },
+
+    #[snafu(display("A database must be selected with a descriptor first"))]
+    NoDatabase,
+
+    #[snafu(display("A table must be selected with a descriptor before writing batches"))]
+    NoTable,
+
+    #[snafu(display("Invalid write acknowledgement level: {}", source))]
+    InvalidWriteAckLevel { source: server::ack::Error },
+
+    #[snafu(display("Message is neither a record batch nor a query"))]
+    UnexpectedMessage,
+
+    #[snafu(display("Error decoding schema of table {}: {}", table_name, source))]
+    DecodingSchema {
+        table_name: String,
+        source: ArrowError,
+    },
+
+    #[snafu(display("Error decoding record batch for table {}: {}", table_name, source))]
+    DecodingBatch {
+        table_name: String,
+        source: ArrowError,
+    },
+
+    #[snafu(display("Error writing record batch: {}", source))]
+    WritingBatch { source: server::Error },
+
+    #[snafu(display("Query of database '{}' not authorized: {}", db_name, source))]
+    QueryNotAuthorized {
+        db_name: String,
+        source: access_policy::Error,
+    },
+
+    #[snafu(display("Query is not valid UTF-8: {}", source))]
+    InvalidQuery { source: std::str::Utf8Error },
+
+    #[snafu(display("Error starting query in database '{}': {}", db_name, source))]
+    StartingQuery {
+        db_name: String,
+        source: server::db::Error,
+    },
+
+    #[snafu(display("Error planning query {}: {}", query, source))]
+    PlanningQuery {
+        query: String,
+        source: query::frontend::sql::Error,
+    },
+
+    #[snafu(display("Error running query against database '{}': {}", db_name, source))]
+    RunningQuery {
+        db_name: String,
+        source: DataFusionError,
+    },
+
+    #[snafu(display("The client closed the exchange"))]
+    ClientDisconnected,
+
+    #[snafu(display("{}", source))]
+    Auditing { source: server::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts an error ending an exchange into the status it ends with
+    fn to_status(&self) -> Status {
+        match self {
+            Self::InvalidDescriptor { .. }
+            | Self::InvalidDatabaseName { .. }
+            | Self::NoDatabase
+            | Self::NoTable
+            | Self::InvalidWriteAckLevel { .. }
+            | Self::UnexpectedMessage
+            | Self::DecodingSchema { .. }
+            | Self::DecodingBatch { .. }
+            | Self::InvalidQuery { .. }
+            | Self::PlanningQuery { .. } => Status::invalid_argument(self.to_string()),
+            Self::DatabaseNotFound { .. } => Status::not_found(self.to_string()),
+            Self::DatabaseNotReady { .. } => Status::unavailable(self.to_string()),
+            Self::NotAuthorized { .. } => Status::permission_denied(self.to_string()),
+            Self::QueryNotAuthorized { source, .. } => match source {
+                access_policy::Error::MissingToken | access_policy::Error::UnknownToken => {
+                    Status::unauthenticated(self.to_string())
+                }
+                access_policy::Error::MissingAttribute { .. }
+                | access_policy::Error::RestrictedRows { .. } => {
+                    Status::permission_denied(self.to_string())
+                }
+            },
+            Self::WritingBatch { .. } => Status::failed_precondition(self.to_string()),
+            Self::StartingQuery { .. } => Status::resource_exhausted(self.to_string()),
+            Self::RunningQuery { .. } | Self::ClientDisconnected | Self::Auditing { .. } => {
+                internal_error()
+            }
+        }
+    }
+}
+
+/// The acknowledgements sent in the `app_metadata` of the messages of an
+/// exchange, as JSON
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeAck {
+    /// The rows of a record batch were written to the table, with the
+    /// durability the write had reached when it was acknowledged
+    Written {
+        table: String,
+        rows: usize,
+        /// Whether the write was stored in the mutable buffer
+        buffered: bool,
+        /// Whether the WAL segment holding the write was persisted
+        wal: bool,
+        /// The number of host groups the write was replicated to
+        replicated: usize,
+        /// The number of host groups the write is still being replicated
+        /// to in the background
+        replicating: usize,
+    },
+    /// All the results of a query were sent, with their number of rows
+    Queried { rows: usize },
+}
+
+type TonicStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+/// The Arrow Flight service of the server
+#[derive(Debug)]
+pub struct GrpcService<M: ConnectionManager> {
+    server: Arc<AppServer<M>>,
+}
+
+impl<M> GrpcService<M>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    /// Create a new GrpcService writing to and querying the databases of
+    /// `server`
+    pub fn new(server: Arc<AppServer<M>>) -> Self {
+        Self { server }
+    }
+}
+
+#[tonic::async_trait]
+impl<M> FlightService for GrpcService<M>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    type HandshakeStream = TonicStream<HandshakeResponse>;
+    type ListFlightsStream = TonicStream<FlightInfo>;
+    type DoGetStream = TonicStream<FlightData>;
+    type DoPutStream = TonicStream<PutResult>;
+    type DoActionStream = TonicStream<arrow_deps::arrow_flight::Result>;
+    type ListActionsStream = TonicStream<ActionType>;
+    type DoExchangeStream = mpsc::Receiver<Result<FlightData, Status>>;
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn do_get(
+        &self,
+        _request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn do_exchange(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        let (mut tx, rx) = mpsc::channel(4);
+
+        let mut exchange = Exchange::new(Arc::clone(&self.server), request_token(&request));
+        let mut messages = request.into_inner();
+        tokio::spawn(async move {
+            info!("Flight exchange started");
+            loop {
+                let data = match messages.message().await {
+                    Ok(Some(data)) => data,
+                    Ok(None) => break,
+                    Err(status) => {
+                        warn!("Flight exchange ended by the client: {}", status);
+                        break;
+                    }
+                };
+
+                if let Err(e) = exchange.handle(data, &mut tx).await {
+                    warn!("Flight exchange ended with error: {}", e);
+                    // the client may be gone already
+                    let _ = tx.send(Err(e.to_status())).await;
+                    break;
+                }
+            }
+            info!("Flight exchange ended");
+        });
+
+        Ok(Response::new(rx))
+    }
+}
+
+/// The state of a `DoExchange` stream: the database and table its
+/// messages are for
+#[derive(Debug)]
+struct Exchange<M: ConnectionManager> {
+    server: Arc<AppServer<M>>,
+    /// The token the exchange was started with
+    token: Option<String>,
+    /// The database the last descriptor selected
+    database: Option<DatabaseName<'static>>,
+    /// The table record batches are written to, with their schema
+    table: Option<(String, SchemaRef)>,
+    /// The acknowledgement level of the writes to the table
+    ack: WriteAckLevel,
+}
+
+impl<M> Exchange<M>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    fn new(server: Arc<AppServer<M>>, token: Option<String>) -> Self {
+        Self {
+            server,
+            token,
+            database: None,
+            table: None,
+            ack: WriteAckLevel::default(),
+        }
+    }
+
+    /// Handles a message of the client, sending the answers to `tx`
+    async fn handle(
+        &mut self,
+        data: FlightData,
+        tx: &mut mpsc::Sender<Result<FlightData, Status>>,
+    ) -> Result<()> {
+        if let Some(descriptor) = &data.flight_descriptor {
+            self.table = None;
+            if let Some(table_name) = self.select(descriptor).await? {
+                let schema = Schema::try_from(&data).context(DecodingSchema {
+                    table_name: &table_name,
+                })?;
+                self.ack = if descriptor.cmd.is_empty() {
+                    WriteAckLevel::default()
+                } else {
+                    String::from_utf8_lossy(&descriptor.cmd)
+                        .parse()
+                        .context(InvalidWriteAckLevel)?
+                };
+                self.table = Some((table_name, Arc::new(schema)));
+                return Ok(());
+            }
+            if data.data_header.is_empty() && data.app_metadata.is_empty() {
+                return Ok(());
+            }
+        }
+
+        if !data.data_header.is_empty() {
+            self.write(&data, tx).await
+        } else if !data.app_metadata.is_empty() {
+            self.query(&data.app_metadata, tx).await
+        } else {
+            UnexpectedMessage.fail()
+        }
+    }
+
+    /// Selects the database of `descriptor`, returning the table it names,
+    /// if any
+    async fn select(&mut self, descriptor: &FlightDescriptor) -> Result<Option<String>> {
+        let path = &descriptor.path;
+        ensure!(
+            descriptor.r#type == DescriptorType::Path as i32 && (1..=2).contains(&path.len()),
+            InvalidDescriptor {
+                descriptor: format!("{:?}", descriptor)
+            }
+        );
+
+        let db_name = DatabaseName::new(path[0].clone()).context(InvalidDatabaseName)?;
+        self.database = Some(db_name);
+        Ok(path.get(1).cloned())
+    }
+
+    /// Returns the selected database, if the principal of the exchange may
+    /// perform `action` on it. The principal is authorized before the
+    /// database is looked up, so one that isn't allowed can't tell which
+    /// databases exist.
+    async fn authorized_database(
+        &self,
+        action: authz::Action,
+    ) -> Result<(&DatabaseName<'static>, Arc<Db>)> {
+        let db_name = self.database.as_ref().context(NoDatabase)?;
+        self.authorize(action, db_name).await?;
+
+        match self.server.db(db_name).await {
+            Some(db) => Ok((db_name, db)),
+            None => match self.server.db_not_ready(db_name) {
+                Some(state) => DatabaseNotReady {
+                    db_name: db_name.as_str(),
+                    state,
+                }
+                .fail(),
+                None => DatabaseNotFound {
+                    db_name: db_name.as_str(),
+                }
+                .fail(),
+            },
+        }
+    }
+
+    /// Returns the name of the principal the token of the exchange
+    /// authenticates in `db`, if any, for the audit log
+    fn principal_name(&self, db: &Db) -> Option<String> {
+        match db.authenticate(self.token.as_deref()) {
+            Ok(Some(principal)) => Some(principal.name),
+            _ => None,
+        }
+    }
+
+    async fn authorize(&self, action: authz::Action, db_name: &str) -> Result<()> {
+        let principal = Principal::new(self.token.as_deref());
+        match self
+            .server
+            .authorizer()
+            .check(principal, action, Some(db_name))
+            .await
+        {
+            Decision::Allow => Ok(()),
+            Decision::Deny => NotAuthorized { action, db_name }.fail(),
+        }
+    }
+
+    /// Writes the record batch of `data` to the selected table, sending
+    /// back its acknowledgement once it has reached the acknowledgement
+    /// level of the exchange
+    async fn write(
+        &self,
+        data: &FlightData,
+        tx: &mut mpsc::Sender<Result<FlightData, Status>>,
+    ) -> Result<()> {
+        let (table_name, schema) = self.table.as_ref().context(NoTable)?;
+        let (db_name, db) = self.authorized_database(authz::Action::Write).await?;
+
+        let batch = flight_data_to_arrow_batch(data, Arc::clone(schema), &[])
+            .context(UnexpectedMessage)?
+            .context(DecodingBatch { table_name })?;
+        let written = self
+            .server
+            .write_table_batch(db_name.as_str(), table_name, &batch, self.ack)
+            .await;
+
+        // the write is only acknowledged once it is in the audit log
+        let principal_name = self.principal_name(&db);
+        let statement = format!("table={} rows={}", table_name, batch.num_rows());
+        let action = AuditAction::new("write")
+            .principal(principal_name.as_deref())
+            .database(db_name)
+            .statement(&statement);
+        self.server
+            .audit(action, &written)
+            .await
+            .context(Auditing)?;
+        let WriteAck {
+            buffered,
+            wal,
+            replicated,
+            replicating,
+        } = written.context(WritingBatch)?;
+
+        let ack = ExchangeAck::Written {
+            table: table_name.clone(),
+            rows: batch.num_rows(),
+            buffered,
+            wal,
+            replicated,
+            replicating,
+        };
+        send(tx, ack_data(&ack)).await
+    }
+
+    /// Runs the SQL query `sql` against the selected database, sending
+    /// back its results
+    async fn query(
+        &self,
+        sql: &[u8],
+        tx: &mut mpsc::Sender<Result<FlightData, Status>>,
+    ) -> Result<()> {
+        let (db_name, db) = self.authorized_database(authz::Action::Read).await?;
+        let sql = std::str::from_utf8(sql).context(InvalidQuery)?;
+
+        // the rows of databases with an access policy are filtered as they
+        // are scanned, according to the principal of the exchange's token
+        let principal_name = self.principal_name(&db);
+        let results = async {
+            let column_filters = db
+                .authenticate(self.token.as_deref())
+                .and_then(|principal| db.column_filters(principal))
+                .context(QueryNotAuthorized {
+                    db_name: db_name.as_str(),
+                })?;
+
+            let query = db.start_query().context(StartingQuery {
+                db_name: db_name.as_str(),
+            })?;
+            let executor = self.server.executor();
+            let physical_plan = SQLQueryPlanner::default()
+                .with_column_filters(column_filters)
+                .query(db.as_ref(), sql, executor.as_ref())
+                .await
+                .context(PlanningQuery { query: sql })?;
+            let schema = physical_plan.schema();
+            let stream = executor
+                .new_context()
+                .execute(physical_plan)
+                .await
+                .context(RunningQuery {
+                    db_name: db_name.as_str(),
+                })?;
+            Ok::<_, Error>((query, schema, stream))
+        }
+        .await;
+
+        // the results are only sent once the query is in the audit log
+        let action = AuditAction::new("query")
+            .principal(principal_name.as_deref())
+            .database(db_name)
+            .statement(sql);
+        self.server
+            .audit(action, &results)
+            .await
+            .context(Auditing)?;
+        // the query counts against the quotas of the database until all
+        // its results are sent
+        let (_query, schema, mut stream) = results?;
+
+        let options = IpcWriteOptions::default();
+        send(tx, flight_data_from_arrow_schema(&schema, &options)).await?;
+        let mut rows = 0;
+        while let Some(batch) = stream.next().await {
+            let batch = batch.map_err(DataFusionError::from).context(RunningQuery {
+                db_name: db_name.as_str(),
+            })?;
+            rows += batch.num_rows();
+            let (dictionaries, data) = flight_data_from_arrow_batch(&batch, &options);
+            for data in dictionaries.into_iter().chain(std::iter::once(data)) {
+                send(tx, data).await?;
+            }
+        }
+        send(tx, ack_data(&ExchangeAck::Queried { rows })).await
+    }
+}
+
+/// A message with only `ack` as its `app_metadata`
+fn ack_data(ack: &ExchangeAck) -> FlightData {
+    FlightData {
+        app_metadata: serde_json::to_vec(ack).expect("acknowledgements can be serialized"),
+        ..Default::default()
+    }
+}
+
+async fn send(tx: &mut mpsc::Sender<Result<FlightData, Status>>, data: FlightData) -> Result<()> {
+    tx.send(Ok(data)).await.ok().context(ClientDisconnected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::{ArrayRef, Float64Array, Int64Array, StringArray},
+        datatypes::{DataType, Field},
+        record_batch::RecordBatch,
+    };
+    use data_types::database_rules::{
+        DatabaseRules, WalBufferConfig, WalBufferRollover, WalSegmentStorage,
+    };
+    use object_store::{memory::InMemory, ObjectStore};
+    use server::{
+        audit::{AuditEntry, AuditLog, AuditRedaction, AuditStatus, FileAuditSink},
+        authz::TokenScopes,
+        ConnectionManagerImpl,
+    };
+
+    async fn make_exchange() -> Exchange<ConnectionManagerImpl> {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        make_audited_exchange(AppServer::new(ConnectionManagerImpl {}, store)).await
+    }
+
+    async fn make_audited_exchange(
+        server: AppServer<ConnectionManagerImpl>,
+    ) -> Exchange<ConnectionManagerImpl> {
+        server.set_id(1);
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 500_000,
+                segment_size: 100_000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+                segment_storage: WalSegmentStorage::ObjectStore,
+                skip_invalid_writes: false,
+            }),
+            store_locally: true,
+            ..Default::default()
+        };
+        server.create_database("mydb", rules).await.unwrap();
+        Exchange::new(Arc::new(server), None)
+    }
+
+    fn descriptor(path: &[&str]) -> FlightDescriptor {
+        FlightDescriptor {
+            r#type: DescriptorType::Path as i32,
+            path: path.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Sends `data` to `exchange`, returning its answers
+    async fn exchange_message(
+        exchange: &mut Exchange<ConnectionManagerImpl>,
+        data: FlightData,
+    ) -> Result<Vec<FlightData>> {
+        let (mut tx, mut rx) = mpsc::channel(100);
+        let result = exchange.handle(data, &mut tx).await;
+        drop(tx);
+
+        let mut answers = vec![];
+        while let Some(answer) = rx.recv().await {
+            answers.push(answer.unwrap());
+        }
+        result.map(|_| answers)
+    }
+
+    fn ack(data: &FlightData) -> ExchangeAck {
+        serde_json::from_slice(&data.app_metadata).unwrap()
+    }
+
+    fn cpu_schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, false),
+            Field::new("usage", DataType::Float64, false),
+            Field::new("time", DataType::Int64, false),
+        ]))
+    }
+
+    /// Selects the table "cpu" of "mydb" for writes acknowledged at the
+    /// level `ack`, if any
+    fn select_cpu(ack: Option<&str>) -> FlightData {
+        let mut data = flight_data_from_arrow_schema(&cpu_schema(), &IpcWriteOptions::default());
+        let mut descriptor = descriptor(&["mydb", "cpu"]);
+        descriptor.cmd = ack.map(|ack| ack.as_bytes().to_vec()).unwrap_or_default();
+        data.flight_descriptor = Some(descriptor);
+        data
+    }
+
+    fn cpu_batch() -> FlightData {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["a", "b"])),
+            Arc::new(Float64Array::from(vec![1.5, 2.5])),
+            Arc::new(Int64Array::from(vec![10, 20])),
+        ];
+        let batch = RecordBatch::try_new(cpu_schema(), columns).unwrap();
+        let (_, data) = flight_data_from_arrow_batch(&batch, &IpcWriteOptions::default());
+        data
+    }
+
+    #[tokio::test]
+    async fn writes_and_queries_over_one_exchange() {
+        let mut exchange = make_exchange().await;
+
+        let answers = exchange_message(&mut exchange, select_cpu(None))
+            .await
+            .unwrap();
+        assert!(answers.is_empty());
+        let answers = exchange_message(&mut exchange, cpu_batch()).await.unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(
+            ack(&answers[0]),
+            ExchangeAck::Written {
+                table: "cpu".to_string(),
+                rows: 2,
+                buffered: true,
+                wal: false,
+                replicated: 0,
+                replicating: 0,
+            }
+        );
+
+        // writes acknowledged at the wal level wait for their WAL segment
+        exchange_message(&mut exchange, select_cpu(Some("wal")))
+            .await
+            .unwrap();
+        let answers = exchange_message(&mut exchange, cpu_batch()).await.unwrap();
+        assert!(matches!(
+            ack(&answers[0]),
+            ExchangeAck::Written {
+                buffered: true,
+                wal: true,
+                ..
+            }
+        ));
+
+        let query = FlightData {
+            app_metadata: b"select host, usage from cpu order by time".to_vec(),
+            ..Default::default()
+        };
+        let answers = exchange_message(&mut exchange, query).await.unwrap();
+        assert_eq!(answers.len(), 3);
+        let result_schema = Arc::new(Schema::try_from(&answers[0]).unwrap());
+        let result = flight_data_to_arrow_batch(&answers[1], result_schema, &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.num_columns(), 2);
+        assert_eq!(result.num_rows(), 4);
+        assert_eq!(ack(&answers[2]), ExchangeAck::Queried { rows: 4 });
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_messages() {
+        let mut exchange = make_exchange().await;
+
+        // nothing is selected yet
+        let query = FlightData {
+            app_metadata: b"select 1".to_vec(),
+            ..Default::default()
+        };
+        let err = exchange_message(&mut exchange, query).await.unwrap_err();
+        assert!(matches!(err, Error::NoDatabase));
+
+        // the database is looked up once the message using it is
+        // authorized
+        let data = FlightData {
+            flight_descriptor: Some(descriptor(&["unknown"])),
+            app_metadata: b"select 1".to_vec(),
+            ..Default::default()
+        };
+        let err = exchange_message(&mut exchange, data).await.unwrap_err();
+        assert_eq!(err.to_string(), "Database not found: unknown");
+        assert_eq!(err.to_status().code(), tonic::Code::NotFound);
+
+        let data = FlightData {
+            flight_descriptor: Some(descriptor(&["mydb", "cpu", "extra"])),
+            ..Default::default()
+        };
+        let err = exchange_message(&mut exchange, data).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidDescriptor { .. }));
+
+        // the database is selected, but no table
+        let data = FlightData {
+            flight_descriptor: Some(descriptor(&["mydb"])),
+            ..Default::default()
+        };
+        exchange_message(&mut exchange, data).await.unwrap();
+        let data = FlightData {
+            data_header: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let err = exchange_message(&mut exchange, data).await.unwrap_err();
+        assert!(matches!(err, Error::NoTable));
+        assert_eq!(err.to_status().code(), tonic::Code::InvalidArgument);
+
+        let err = exchange_message(&mut exchange, select_cpu(Some("synced")))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidWriteAckLevel { .. }));
+        assert_eq!(err.to_status().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn unauthorized_principals_cant_tell_databases_apart() {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        // no token is allowed anything
+        let server = AppServer::new(ConnectionManagerImpl {}, store)
+            .with_authorizer(Arc::new(TokenScopes::default()));
+        let mut exchange = make_audited_exchange(server).await;
+
+        for db_name in &["mydb", "unknown"] {
+            let data = FlightData {
+                flight_descriptor: Some(descriptor(&[db_name])),
+                app_metadata: b"select 1".to_vec(),
+                ..Default::default()
+            };
+            let err = exchange_message(&mut exchange, data).await.unwrap_err();
+            assert!(matches!(err, Error::NotAuthorized { .. }), "{}", err);
+            assert_eq!(err.to_status().code(), tonic::Code::PermissionDenied);
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_and_queries_are_audited() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        let audit_path = dir.path().join("audit.log");
+        let audited_server = |path| {
+            let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+            let sink = Arc::new(FileAuditSink::new(path));
+            AppServer::new(ConnectionManagerImpl {}, store)
+                .with_audit_log(AuditLog::new(sink, AuditRedaction::default()))
+        };
+        let select = FlightData {
+            flight_descriptor: Some(descriptor(&["mydb"])),
+            ..Default::default()
+        };
+        let query = FlightData {
+            app_metadata: b"select * from cpu where host = 'a'".to_vec(),
+            ..Default::default()
+        };
+
+        let mut exchange = make_audited_exchange(audited_server(audit_path.clone())).await;
+        exchange_message(&mut exchange, select.clone())
+            .await
+            .unwrap();
+        // there is no table yet
+        exchange_message(&mut exchange, query.clone())
+            .await
+            .unwrap_err();
+        exchange_message(&mut exchange, select_cpu(None))
+            .await
+            .unwrap();
+        exchange_message(&mut exchange, cpu_batch()).await.unwrap();
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&audit_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, "query");
+        assert_eq!(entries[0].database.as_deref(), Some("mydb"));
+        assert_eq!(
+            entries[0].statement.as_deref(),
+            Some("select * from cpu where host = '?'")
+        );
+        assert_eq!(entries[0].status, AuditStatus::Failed);
+        assert_eq!(entries[1].action, "write");
+        assert_eq!(entries[1].database.as_deref(), Some("mydb"));
+        assert_eq!(entries[1].statement.as_deref(), Some("table=cpu rows=?"));
+        assert_eq!(entries[1].status, AuditStatus::Succeeded);
+
+        // a directory can't be appended to, so writes and queries fail
+        let mut exchange = make_audited_exchange(audited_server(dir.path().to_path_buf())).await;
+        exchange_message(&mut exchange, select).await.unwrap();
+        let err = exchange_message(&mut exchange, query).await.unwrap_err();
+        assert!(matches!(err, Error::Auditing { .. }));
+        assert_eq!(err.to_status().code(), tonic::Code::Internal);
+
+        exchange_message(&mut exchange, select_cpu(None))
+            .await
+            .unwrap();
+        let err = exchange_message(&mut exchange, cpu_batch())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Auditing { .. }));
+    }
+}
@@ -2,11 +2,11 @@ use tonic::Status;
 
 use generated_types::{
     MeasurementFieldsRequest, MeasurementNamesRequest, MeasurementTagKeysRequest,
-    MeasurementTagValuesRequest, ReadFilterRequest, ReadGroupRequest, ReadSource,
-    ReadWindowAggregateRequest, TagKeysRequest, TagValuesRequest,
+    MeasurementTagValuesRequest, MeasurementTimeRangeRequest, ReadFilterRequest, ReadGroupRequest,
+    ReadSource, ReadWindowAggregateRequest, TagKeysRequest, TagValuesRequest,
 };
 
-use super::id::ID;
+use super::{error_details::field_violation, id::ID};
 use std::convert::TryInto;
 
 /// This trait implements extraction of information from all storage gRPC
@@ -16,34 +16,46 @@ use std::convert::TryInto;
 pub trait GrpcInputs {
     fn read_source_field(&self) -> Option<&prost_types::Any>;
 
+    /// The name of the field `read_source_field` returns, used to point
+    /// clients at the field when it is invalid
+    fn read_source_field_name(&self) -> &'static str {
+        "read_source"
+    }
+
     fn read_source_raw(&self) -> Result<&prost_types::Any, Status> {
+        let field = self.read_source_field_name();
         Ok(self
             .read_source_field()
-            .ok_or_else(|| Status::invalid_argument("missing read_source"))?)
+            .ok_or_else(|| field_violation(field, format!("missing {}", field)))?)
     }
 
     fn read_source(&self) -> Result<ReadSource, Status> {
         let raw = self.read_source_raw()?;
         let val = &raw.value[..];
         Ok(prost::Message::decode(val).map_err(|_| {
-            Status::invalid_argument("value could not be parsed as a ReadSource message")
+            field_violation(
+                self.read_source_field_name(),
+                "value could not be parsed as a ReadSource message",
+            )
         })?)
     }
 
     fn org_id(&self) -> Result<ID, Status> {
-        Ok(self
-            .read_source()?
-            .org_id
-            .try_into()
-            .map_err(|_| Status::invalid_argument("org_id did not fit in a u64"))?)
+        Ok(self.read_source()?.org_id.try_into().map_err(|_| {
+            field_violation(
+                format!("{}.org_id", self.read_source_field_name()),
+                "org_id did not fit in a u64",
+            )
+        })?)
     }
 
     fn bucket_name(&self) -> Result<String, Status> {
-        let bucket: ID = self
-            .read_source()?
-            .bucket_id
-            .try_into()
-            .map_err(|_| Status::invalid_argument("bucket_id did not fit in a u64"))?;
+        let bucket: ID = self.read_source()?.bucket_id.try_into().map_err(|_| {
+            field_violation(
+                format!("{}.bucket_id", self.read_source_field_name()),
+                "bucket_id did not fit in a u64",
+            )
+        })?;
         Ok(bucket.to_string())
     }
 }
@@ -64,36 +76,70 @@ impl GrpcInputs for TagKeysRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.tags_source.as_ref()
     }
+
+    fn read_source_field_name(&self) -> &'static str {
+        "tags_source"
+    }
 }
 
 impl GrpcInputs for TagValuesRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.tags_source.as_ref()
     }
+
+    fn read_source_field_name(&self) -> &'static str {
+        "tags_source"
+    }
 }
 
 impl GrpcInputs for MeasurementNamesRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.source.as_ref()
     }
+
+    fn read_source_field_name(&self) -> &'static str {
+        "source"
+    }
 }
 
 impl GrpcInputs for MeasurementTagKeysRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.source.as_ref()
     }
+
+    fn read_source_field_name(&self) -> &'static str {
+        "source"
+    }
 }
 
 impl GrpcInputs for MeasurementTagValuesRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.source.as_ref()
     }
+
+    fn read_source_field_name(&self) -> &'static str {
+        "source"
+    }
 }
 
 impl GrpcInputs for MeasurementFieldsRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.source.as_ref()
     }
+
+    fn read_source_field_name(&self) -> &'static str {
+        "source"
+    }
+}
+
+impl GrpcInputs for MeasurementTimeRangeRequest {
+    fn read_source_field(&self) -> Option<&prost_types::Any> {
+        self.source.as_ref()
+    }
+
+    fn read_source_field_name(&self) -> &'static str {
+        "source"
+    }
 }
 
 impl GrpcInputs for ReadWindowAggregateRequest {
@@ -5,41 +5,55 @@
 use std::{collections::HashMap, sync::Arc};
 
 use generated_types::{
+    i_ox_storage_server::{IOxStorage, IOxStorageServer},
     i_ox_testing_server::{IOxTesting, IOxTestingServer},
     storage_server::{Storage, StorageServer},
     CapabilitiesResponse, Capability, Int64ValuesResponse, MeasurementFieldsRequest,
     MeasurementFieldsResponse, MeasurementNamesRequest, MeasurementTagKeysRequest,
-    MeasurementTagValuesRequest, Predicate, ReadFilterRequest, ReadGroupRequest, ReadResponse,
-    ReadSeriesCardinalityRequest, ReadWindowAggregateRequest, StringValuesResponse, TagKeysRequest,
-    TagValuesRequest, TestErrorRequest, TestErrorResponse, TimestampRange,
+    MeasurementTagValuesRequest, MeasurementTimeRangeRequest, MeasurementTimeRangeResponse,
+    Predicate, ReadFilterRequest, ReadGroupRequest, ReadResponse, ReadSeriesCardinalityRequest,
+    ReadWindowAggregateRequest, StringValuesResponse, TagKeysRequest, TagValuesRequest,
+    TestErrorRequest, TestErrorResponse, TimestampRange,
 };
 
 use data_types::error::ErrorLogger;
 
-use query::group_by::GroupByAndAggregate;
-use query::{exec::fieldlist::FieldList, frontend::influxrpc::InfluxRPCPlanner};
+use arrow_deps::arrow_flight::flight_service_server::FlightServiceServer;
+use query::group_by::{Aggregate as QueryAggregate, GroupByAndAggregate};
+use query::{
+    exec::{fieldlist::FieldList, stringset::StringSetRef, QueryMetrics},
+    frontend::influxrpc::InfluxRPCPlanner,
+};
+use server::{
+    audit::{AuditAction, Auditor},
+    authz::{Action, Authorizer, Decision, Principal},
+    latency::{LatencyMetrics, LatencyTimer, OperationKind},
+    ConnectionManager, Server as AppServer,
+};
 
 use super::expr::{self, AddRPCNode, Loggable, SpecialTagKeys};
 use super::input::GrpcInputs;
-use data_types::names::org_and_bucket_to_database;
 
 use data_types::DatabaseName;
 
 use query::{
+    concurrency::QueryGuard,
     exec::seriesset::{Error as SeriesSetError, SeriesSetItem},
     predicate::PredicateBuilder,
-    Database, DatabaseStore,
+    Database, DatabaseError, DatabaseErrorKind, DatabaseStore, PartitionChunk,
 };
 
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, ResultExt, Snafu};
 
 use tokio::{net::TcpListener, sync::mpsc};
 use tonic::Status;
 use tracing::{error, info, warn};
 
-use super::data::{
-    fieldlist_to_measurement_fields_response, series_set_item_to_read_response,
-    tag_keys_to_byte_vecs,
+use super::{
+    data::{fieldlist_to_measurement_fields_response, tag_keys_to_byte_vecs},
+    encoder::FrameEncoder,
+    error_details::{field_violation, internal_error, quota_failure},
+    flight,
 };
 
 #[derive(Debug, Snafu)]
@@ -50,6 +64,21 @@ pub enum Error {
     #[snafu(display("Database not found: {}", db_name))]
     DatabaseNotFound { db_name: String },
 
+    #[snafu(display("Database {} is not ready: {}", db_name, state))]
+    DatabaseNotReady { db_name: String, state: String },
+
+    #[snafu(display("Error starting query in database '{}': {}", db_name, source))]
+    StartingQuery {
+        db_name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display(
+        "Database '{}' has an access policy, which is only supported by SQL queries",
+        db_name
+    ))]
+    AccessPolicyNotSupported { db_name: String },
+
     #[snafu(display("Error listing tables in database '{}': {}", db_name, source))]
     ListingTables {
         db_name: String,
@@ -68,6 +97,18 @@ pub enum Error {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
+    #[snafu(display(
+        "Error reading the time range of '{}' in database '{}': {}",
+        measurement,
+        db_name,
+        source
+    ))]
+    ReadingTimeRange {
+        db_name: String,
+        measurement: String,
+        source: DatabaseError,
+    },
+
     #[snafu(display("Error creating series plans for database '{}': {}", db_name, source))]
     PlanningFilteringSeries {
         db_name: String,
@@ -151,6 +192,20 @@ pub enum Error {
     #[snafu(display("Converting field information series into gRPC response:  {}", source))]
     ConvertingFieldList { source: super::data::Error },
 
+    #[snafu(display(
+        "Query against database '{}' selected more than the limit of {} series",
+        db_name,
+        limit
+    ))]
+    SeriesLimitExceeded { db_name: String, limit: usize },
+
+    #[snafu(display(
+        "Query against database '{}' produced more than the limit of {} frames",
+        db_name,
+        limit
+    ))]
+    FrameLimitExceeded { db_name: String, limit: usize },
+
     #[snafu(display("Error sending results via channel:  {}", source))]
     SendingResults {
         source: Box<dyn std::error::Error + Send + Sync>,
@@ -181,10 +236,27 @@ impl Error {
     /// Converts a result from the business logic into the appropriate tonic
     /// status
     fn to_status(&self) -> tonic::Status {
+        // errors caused by the database are reported according to their
+        // kind, regardless of the operation that failed
+        match DatabaseError::find_in(self).map(|e| e.kind()) {
+            Some(DatabaseErrorKind::NotFound) => return Status::not_found(self.to_string()),
+            Some(DatabaseErrorKind::SchemaConflict) => {
+                return Status::failed_precondition(self.to_string())
+            }
+            Some(DatabaseErrorKind::ResourceExhausted) => {
+                return quota_failure(self.quota_subject(), self.to_string())
+            }
+            Some(DatabaseErrorKind::Internal) => return internal_error(),
+            None => {}
+        }
+
         match &self {
-            Self::ServerError { .. } => Status::internal(self.to_string()),
+            Self::ServerError { .. } => internal_error(),
             Self::DatabaseNotFound { .. } => Status::not_found(self.to_string()),
-            Self::ListingTables { .. } => Status::internal(self.to_string()),
+            Self::DatabaseNotReady { .. } => Status::unavailable(self.to_string()),
+            Self::StartingQuery { .. } => quota_failure(self.quota_subject(), self.to_string()),
+            Self::AccessPolicyNotSupported { .. } => Status::permission_denied(self.to_string()),
+            Self::ListingTables { .. } => internal_error(),
             Self::ListingColumns { .. } => {
                 // TODO: distinguish between input errors and internal errors
                 Status::invalid_argument(self.to_string())
@@ -193,23 +265,79 @@ impl Error {
                 // TODO: distinguish between input errors and internal errors
                 Status::invalid_argument(self.to_string())
             }
+            Self::ReadingTimeRange { .. } => internal_error(),
             Self::PlanningFilteringSeries { .. } => Status::invalid_argument(self.to_string()),
             Self::PlanningGroupSeries { .. } => Status::invalid_argument(self.to_string()),
             Self::FilteringSeries { .. } => Status::invalid_argument(self.to_string()),
             Self::GroupingSeries { .. } => Status::invalid_argument(self.to_string()),
             Self::ListingTagValues { .. } => Status::invalid_argument(self.to_string()),
-            Self::ConvertingPredicate { .. } => Status::invalid_argument(self.to_string()),
-            Self::ConvertingReadGroupAggregate { .. } => Status::invalid_argument(self.to_string()),
-            Self::ConvertingReadGroupType { .. } => Status::invalid_argument(self.to_string()),
-            Self::ConvertingWindowAggregate { .. } => Status::invalid_argument(self.to_string()),
+            Self::ConvertingPredicate { .. } => field_violation("predicate", self.to_string()),
+            Self::ConvertingReadGroupAggregate { .. } => {
+                field_violation("aggregate", self.to_string())
+            }
+            Self::ConvertingReadGroupType { .. } => field_violation("group", self.to_string()),
+            Self::ConvertingWindowAggregate { .. } => {
+                field_violation("aggregate", self.to_string())
+            }
             Self::ComputingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
-            Self::ConvertingTagKeyInTagValues { .. } => Status::invalid_argument(self.to_string()),
+            Self::ConvertingTagKeyInTagValues { .. } => {
+                field_violation("tag_key", self.to_string())
+            }
             Self::ComputingGroupedSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingFieldList { .. } => Status::invalid_argument(self.to_string()),
-            Self::SendingResults { .. } => Status::internal(self.to_string()),
-            Self::InternalHintsFieldNotSupported { .. } => Status::internal(self.to_string()),
-            Self::NotYetImplemented { .. } => Status::internal(self.to_string()),
+            Self::SeriesLimitExceeded { .. } => {
+                quota_failure(self.quota_subject(), self.to_string())
+            }
+            Self::FrameLimitExceeded { .. } => {
+                quota_failure(self.quota_subject(), self.to_string())
+            }
+            Self::SendingResults { .. } => internal_error(),
+            Self::InternalHintsFieldNotSupported { .. } => Status::unimplemented(self.to_string()),
+            Self::NotYetImplemented { .. } => Status::unimplemented(self.to_string()),
+        }
+    }
+
+    /// The subject of the quota a request exceeded: the database it was
+    /// made against, if known
+    fn quota_subject(&self) -> String {
+        match self {
+            Self::DatabaseNotFound { db_name }
+            | Self::DatabaseNotReady { db_name, .. }
+            | Self::StartingQuery { db_name, .. }
+            | Self::ListingTables { db_name, .. }
+            | Self::ListingColumns { db_name, .. }
+            | Self::ListingFields { db_name, .. }
+            | Self::ReadingTimeRange { db_name, .. }
+            | Self::PlanningFilteringSeries { db_name, .. }
+            | Self::PlanningGroupSeries { db_name, .. }
+            | Self::FilteringSeries { db_name, .. }
+            | Self::GroupingSeries { db_name, .. }
+            | Self::ListingTagValues { db_name, .. }
+            | Self::SeriesLimitExceeded { db_name, .. }
+            | Self::FrameLimitExceeded { db_name, .. } => format!("database:{}", db_name),
+            _ => "database".to_string(),
+        }
+    }
+}
+
+/// Limits on the results of a single read_filter or read_group request,
+/// so that a query that accidentally selects millions of series fails
+/// rather than exhausting the memory and bandwidth of the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadLimits {
+    /// The maximum number of series in the results
+    pub max_series: usize,
+    /// The maximum number of frames in the results, counting series, group
+    /// and points frames
+    pub max_frames: usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_series: 1_000_000,
+            max_frames: 10_000_000,
         }
     }
 }
@@ -217,15 +345,91 @@ impl Error {
 #[derive(Debug)]
 pub struct GrpcService<T: DatabaseStore> {
     db_store: Arc<T>,
+    /// Decides which principals may read which databases
+    authorizer: Arc<dyn Authorizer>,
+    /// Records the requests served in the audit log
+    auditor: Arc<dyn Auditor>,
+    /// The latency of the requests served, by database and RPC
+    latency: Arc<LatencyMetrics>,
+    /// The limits on the results of read_filter and read_group requests
+    limits: ReadLimits,
 }
 
 impl<T> GrpcService<T>
 where
     T: DatabaseStore + 'static,
 {
-    /// Create a new GrpcService connected to `db_store`
-    pub fn new(db_store: Arc<T>) -> Self {
-        Self { db_store }
+    /// Create a new GrpcService connected to `db_store`, serving the
+    /// requests `authorizer` allows, recording them with `auditor` and
+    /// their latency in `latency`, and failing reads whose results exceed
+    /// `limits`
+    pub fn new(
+        db_store: Arc<T>,
+        authorizer: Arc<dyn Authorizer>,
+        auditor: Arc<dyn Auditor>,
+        latency: Arc<LatencyMetrics>,
+        limits: ReadLimits,
+    ) -> Self {
+        Self {
+            db_store,
+            authorizer,
+            auditor,
+            latency,
+            limits,
+        }
+    }
+
+    /// Records that the request `action` against `db_name`, described by
+    /// `statement`, ended with `response`. The request fails if it can't
+    /// be recorded, so no results are returned unaudited.
+    async fn audit<R>(
+        &self,
+        action: &str,
+        db_name: &str,
+        statement: &str,
+        response: &Result<R, Status>,
+    ) -> Result<(), Status> {
+        let action = AuditAction::new(action)
+            .database(db_name)
+            .statement(statement);
+        let error = response.as_ref().err().map(|status| status.message());
+        self.auditor
+            .audit_action(action, error)
+            .await
+            .map_err(|_| internal_error())
+    }
+
+    /// Returns the name of the database `input` is for, if the principal
+    /// whose token the request was sent with may read it
+    async fn readable_database_name(
+        &self,
+        token: Option<&str>,
+        input: &impl GrpcInputs,
+    ) -> Result<DatabaseName<'static>, Status> {
+        let db_name = get_database_name(self.db_store.as_ref(), input).await?;
+        match self
+            .authorizer
+            .check(Principal::new(token), Action::Read, Some(db_name.as_str()))
+            .await
+        {
+            Decision::Allow => Ok(db_name),
+            Decision::Deny => Err(Status::permission_denied(format!(
+                "not authorized to read database {}",
+                db_name
+            ))),
+        }
+    }
+
+    /// Starts timing a request of kind `kind` to the database `db_name`.
+    /// Only requests to databases that exist are timed, so clients can't
+    /// add a histogram for every name they send.
+    async fn start_timer(
+        &self,
+        db_name: &DatabaseName<'_>,
+        kind: OperationKind,
+    ) -> Option<LatencyTimer> {
+        self.db_store.db(db_name).await?;
+        Some(self.latency.start(db_name, kind))
     }
 }
 
@@ -244,6 +448,49 @@ where
     }
 }
 
+#[tonic::async_trait]
+/// Implements the protobuf defined IOx storage service for a DatabaseStore
+impl<T> IOxStorage for GrpcService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    async fn measurement_time_range(
+        &self,
+        req: tonic::Request<MeasurementTimeRangeRequest>,
+    ) -> Result<tonic::Response<MeasurementTimeRangeResponse>, Status> {
+        let token = request_token(&req);
+        let measurement_time_range_request = req.into_inner();
+
+        let db_name = self
+            .readable_database_name(token.as_deref(), &measurement_time_range_request)
+            .await?;
+        let _timer = self
+            .start_timer(&db_name, OperationKind::MeasurementTimeRange)
+            .await;
+
+        let MeasurementTimeRangeRequest {
+            source: _source,
+            measurement,
+        } = measurement_time_range_request;
+
+        let statement = format!("measurement: {}", measurement);
+        info!(
+            "measurement_time_range for database {}, {}",
+            db_name, statement
+        );
+
+        let response = time_range_impl(self.db_store.as_ref(), &db_name, &measurement)
+            .await
+            .map_err(|e| e.to_status());
+        self.audit("measurement_time_range", &db_name, &statement, &response)
+            .await?;
+
+        Ok(tonic::Response::new(MeasurementTimeRangeResponse {
+            range: response?,
+        }))
+    }
+}
+
 /// Implementes the protobuf defined Storage service for a DatabaseStore
 #[tonic::async_trait]
 impl<T> Storage for GrpcService<T>
@@ -258,9 +505,13 @@ where
     ) -> Result<tonic::Response<Self::ReadFilterStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let read_filter_request = req.into_inner();
 
-        let db_name = get_database_name(&read_filter_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &read_filter_request)
+            .await?;
+        let _timer = self.start_timer(&db_name, OperationKind::ReadFilter).await;
 
         let ReadFilterRequest {
             read_source: _read_source,
@@ -268,16 +519,22 @@ where
             predicate,
         } = read_filter_request;
 
-        info!(
-            "read_filter for database {}, range: {:?}, predicate: {}",
-            db_name,
-            range,
-            predicate.loggable()
-        );
+        let statement = format!("range: {:?}, predicate: {}", range, predicate.loggable());
+        info!("read_filter for database {}, {}", db_name, statement);
 
-        read_filter_impl(tx.clone(), self.db_store.clone(), db_name, range, predicate)
-            .await
-            .map_err(|e| e.to_status())?;
+        let response = read_filter_impl(
+            tx.clone(),
+            self.db_store.clone(),
+            db_name.clone(),
+            range,
+            predicate,
+            self.limits,
+        )
+        .await
+        .map_err(|e| e.to_status());
+        self.audit("read_filter", &db_name, &statement, &response)
+            .await?;
+        response?;
 
         Ok(tonic::Response::new(rx))
     }
@@ -290,9 +547,13 @@ where
     ) -> Result<tonic::Response<Self::ReadGroupStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let read_group_request = req.into_inner();
 
-        let db_name = get_database_name(&read_group_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &read_group_request)
+            .await?;
+        let _timer = self.start_timer(&db_name, OperationKind::ReadGroup).await;
 
         let ReadGroupRequest {
             read_source: _read_source,
@@ -304,11 +565,15 @@ where
             hints,
         } = read_group_request;
 
-        info!(
-            "read_group for database {}, range: {:?}, group_keys: {:?}, group: {:?}, aggregate: {:?}, predicate: {}",
-            db_name, range, group_keys, group, aggregate,
-              predicate.loggable()
+        let statement = format!(
+            "range: {:?}, group_keys: {:?}, group: {:?}, aggregate: {:?}, predicate: {}",
+            range,
+            group_keys,
+            group,
+            aggregate,
+            predicate.loggable()
         );
+        info!("read_group for database {}, {}", db_name, statement);
 
         if hints != 0 {
             InternalHintsFieldNotSupported { hints }.fail()?
@@ -326,16 +591,20 @@ where
         let gby_agg = expr::make_read_group_aggregate(aggregate, group, group_keys)
             .context(ConvertingReadGroupAggregate { aggregate_string })?;
 
-        query_group_impl(
+        let response = query_group_impl(
             tx.clone(),
             self.db_store.clone(),
-            db_name,
+            db_name.clone(),
             range,
             predicate,
             gby_agg,
+            self.limits,
         )
         .await
-        .map_err(|e| e.to_status())?;
+        .map_err(|e| e.to_status());
+        self.audit("read_group", &db_name, &statement, &response)
+            .await?;
+        response?;
 
         Ok(tonic::Response::new(rx))
     }
@@ -348,9 +617,15 @@ where
     ) -> Result<tonic::Response<Self::ReadGroupStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let read_window_aggregate_request = req.into_inner();
 
-        let db_name = get_database_name(&read_window_aggregate_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &read_window_aggregate_request)
+            .await?;
+        let _timer = self
+            .start_timer(&db_name, OperationKind::ReadWindowAggregate)
+            .await;
 
         let ReadWindowAggregateRequest {
             read_source: _read_source,
@@ -362,11 +637,15 @@ where
             window,
         } = read_window_aggregate_request;
 
-        info!(
-            "read_window_aggregate for database {}, range: {:?}, window_every: {:?}, offset: {:?}, aggregate: {:?}, window: {:?}, predicate: {}",
-            db_name, range, window_every, offset, aggregate, window,
+        let statement = format!(
+            "range: {:?}, window_every: {:?}, offset: {:?}, aggregate: {:?}, window: {:?}, predicate: {}",
+            range, window_every, offset, aggregate, window,
               predicate.loggable()
         );
+        info!(
+            "read_window_aggregate for database {}, {}",
+            db_name, statement
+        );
 
         let aggregate_string = format!(
             "aggregate: {:?}, window_every: {:?}, offset: {:?}, window: {:?}",
@@ -376,16 +655,20 @@ where
         let gby_agg = expr::make_read_window_aggregate(aggregate, window_every, offset, window)
             .context(ConvertingWindowAggregate { aggregate_string })?;
 
-        query_group_impl(
+        let response = query_group_impl(
             tx.clone(),
             self.db_store.clone(),
-            db_name,
+            db_name.clone(),
             range,
             predicate,
             gby_agg,
+            self.limits,
         )
         .await
-        .map_err(|e| e.to_status())?;
+        .map_err(|e| e.to_status());
+        self.audit("read_window_aggregate", &db_name, &statement, &response)
+            .await?;
+        response?;
 
         Ok(tonic::Response::new(rx))
     }
@@ -398,9 +681,13 @@ where
     ) -> Result<tonic::Response<Self::TagKeysStream>, Status> {
         let (mut tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let tag_keys_request = req.into_inner();
 
-        let db_name = get_database_name(&tag_keys_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &tag_keys_request)
+            .await?;
+        let _timer = self.start_timer(&db_name, OperationKind::TagKeys).await;
 
         let TagKeysRequest {
             tags_source: _tag_source,
@@ -408,24 +695,22 @@ where
             predicate,
         } = tag_keys_request;
 
-        info!(
-            "tag_keys for database {}, range: {:?}, predicate: {}",
-            db_name,
-            range,
-            predicate.loggable()
-        );
+        let statement = format!("range: {:?}, predicate: {}", range, predicate.loggable());
+        info!("tag_keys for database {}, {}", db_name, statement);
 
         let measurement = None;
 
         let response = tag_keys_impl(
             self.db_store.clone(),
-            db_name,
+            db_name.clone(),
             measurement,
             range,
             predicate,
         )
         .await
         .map_err(|e| e.to_status());
+        self.audit("tag_keys", &db_name, &statement, &response)
+            .await?;
 
         tx.send(response)
             .await
@@ -442,9 +727,13 @@ where
     ) -> Result<tonic::Response<Self::TagValuesStream>, Status> {
         let (mut tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let tag_values_request = req.into_inner();
 
-        let db_name = get_database_name(&tag_values_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &tag_values_request)
+            .await?;
+        let _timer = self.start_timer(&db_name, OperationKind::TagValues).await;
 
         let TagValuesRequest {
             tags_source: _tag_source,
@@ -453,6 +742,13 @@ where
             tag_key,
         } = tag_values_request;
 
+        let statement = format!(
+            "range: {:?}, tag_key: {}, predicate: {}",
+            range,
+            String::from_utf8_lossy(&tag_key),
+            predicate.loggable()
+        );
+        let audited_db_name = db_name.clone();
         let measurement = None;
 
         // Special case a request for 'tag_key=_measurement" means to list all
@@ -465,10 +761,17 @@ where
             );
 
             if predicate.is_some() {
-                unimplemented!("tag_value for a measurement, with general predicate");
+                measurement_names_matching_impl(
+                    self.db_store.clone(),
+                    db_name,
+                    measurement,
+                    range,
+                    predicate,
+                )
+                .await
+            } else {
+                measurement_name_impl(self.db_store.clone(), db_name, range).await
             }
-
-            measurement_name_impl(self.db_store.clone(), db_name, range).await
         } else if tag_key.is_field() {
             info!(
                 "tag_values with tag_key=[xff] (field name) for database {}, range: {:?}, predicate: {} --> returning fields",
@@ -476,17 +779,15 @@ where
                 predicate.loggable()
             );
 
-            let fieldlist =
-                field_names_impl(self.db_store.clone(), db_name, None, range, predicate).await?;
-
-            // Pick out the field names into a Vec<Vec<u8>>for return
-            let values = fieldlist
-                .fields
-                .into_iter()
-                .map(|f| f.name.bytes().collect())
-                .collect::<Vec<_>>();
-
-            Ok(StringValuesResponse { values })
+            field_names_impl(
+                self.db_store.clone(),
+                db_name,
+                measurement,
+                range,
+                predicate,
+            )
+            .await
+            .map(field_list_to_values)
         } else {
             let tag_key = String::from_utf8(tag_key).context(ConvertingTagKeyInTagValues)?;
 
@@ -510,6 +811,8 @@ where
         };
 
         let response = response.map_err(|e| e.to_status());
+        self.audit("tag_values", &audited_db_name, &statement, &response)
+            .await?;
 
         tx.send(response)
             .await
@@ -535,28 +838,29 @@ where
         // idpe/storage/read/capabilities.go (aka window aggregate /
         // pushdown)
         //
-
-        // For now, hard code our list of support
-        let caps = [
+        // The features are derived from what the planner supports, so
+        // Flux only pushes down what can be calculated here
+        let caps = vec![
             (
                 "WindowAggregate",
-                vec![
-                    "Count", "Sum", // "First"
-                    // "Last",
-                    "Min", "Max", "Mean",
-                    // "Offset"
-                ],
+                expr::supported_aggregates(QueryAggregate::supports_window),
+            ),
+            (
+                "Group",
+                expr::supported_aggregates(QueryAggregate::supports_group),
+            ),
+            (
+                "Predicate",
+                expr::supported_comparisons(
+                    <<T::Database as Database>::Chunk as PartitionChunk>::supports_expr,
+                ),
             ),
-            ("Group", vec!["First", "Last", "Min", "Max"]),
         ];
 
         // Turn it into the HashMap -> Capabiltity
         let caps = caps
-            .iter()
-            .map(|(cap_name, features)| {
-                let features = features.iter().map(|f| f.to_string()).collect::<Vec<_>>();
-                (cap_name.to_string(), Capability { features })
-            })
+            .into_iter()
+            .map(|(cap_name, features)| (cap_name.to_string(), Capability { features }))
             .collect::<HashMap<String, Capability>>();
 
         let caps = CapabilitiesResponse { caps };
@@ -571,9 +875,15 @@ where
     ) -> Result<tonic::Response<Self::MeasurementNamesStream>, Status> {
         let (mut tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let measurement_names_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_names_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &measurement_names_request)
+            .await?;
+        let _timer = self
+            .start_timer(&db_name, OperationKind::MeasurementNames)
+            .await;
 
         let MeasurementNamesRequest {
             source: _source,
@@ -592,16 +902,14 @@ where
             .map_err(|e| e.to_status());
         }
 
-        info!(
-            "measurement_names for database {}, range: {:?}, predicate: {}",
-            db_name,
-            range,
-            predicate.loggable()
-        );
+        let statement = format!("range: {:?}, predicate: {}", range, predicate.loggable());
+        info!("measurement_names for database {}, {}", db_name, statement);
 
-        let response = measurement_name_impl(self.db_store.clone(), db_name, range)
+        let response = measurement_name_impl(self.db_store.clone(), db_name.clone(), range)
             .await
             .map_err(|e| e.to_status());
+        self.audit("measurement_names", &db_name, &statement, &response)
+            .await?;
 
         tx.send(response)
             .await
@@ -618,9 +926,15 @@ where
     ) -> Result<tonic::Response<Self::MeasurementTagKeysStream>, Status> {
         let (mut tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let measurement_tag_keys_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_tag_keys_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &measurement_tag_keys_request)
+            .await?;
+        let _timer = self
+            .start_timer(&db_name, OperationKind::MeasurementTagKeys)
+            .await;
 
         let MeasurementTagKeysRequest {
             source: _source,
@@ -629,25 +943,30 @@ where
             predicate,
         } = measurement_tag_keys_request;
 
-        info!(
-            "measurement_tag_keys for database {}, range: {:?}, measurement: {}, predicate: {}",
-            db_name,
+        let statement = format!(
+            "range: {:?}, measurement: {}, predicate: {}",
             range,
             measurement,
             predicate.loggable()
         );
+        info!(
+            "measurement_tag_keys for database {}, {}",
+            db_name, statement
+        );
 
         let measurement = Some(measurement);
 
         let response = tag_keys_impl(
             self.db_store.clone(),
-            db_name,
+            db_name.clone(),
             measurement,
             range,
             predicate,
         )
         .await
         .map_err(|e| e.to_status());
+        self.audit("measurement_tag_keys", &db_name, &statement, &response)
+            .await?;
 
         tx.send(response)
             .await
@@ -664,9 +983,15 @@ where
     ) -> Result<tonic::Response<Self::MeasurementTagValuesStream>, Status> {
         let (mut tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let measurement_tag_values_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_tag_values_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &measurement_tag_values_request)
+            .await?;
+        let _timer = self
+            .start_timer(&db_name, OperationKind::MeasurementTagValues)
+            .await;
 
         let MeasurementTagValuesRequest {
             source: _source,
@@ -676,24 +1001,61 @@ where
             tag_key,
         } = measurement_tag_values_request;
 
+        let statement = format!(
+            "range: {:?}, measurement: {}, tag_key: {}, predicate: {}",
+            range,
+            measurement,
+            tag_key,
+            predicate.loggable()
+        );
         info!(
-            "measurement_tag_values for database {}, range: {:?}, measurement: {}, tag_key: {}, predicate: {}",
-            db_name, range, measurement, tag_key,
-                    predicate.loggable()
+            "measurement_tag_values for database {}, {}",
+            db_name, statement
         );
 
+        let audited_db_name = db_name.clone();
         let measurement = Some(measurement);
 
-        let response = tag_values_impl(
-            self.db_store.clone(),
-            db_name,
-            tag_key,
-            measurement,
-            range,
-            predicate,
-        )
-        .await
+        // The measurement and field tag keys list the measurement itself
+        // (if it has matching data) and its fields
+        let response = if tag_key.is_measurement() {
+            measurement_names_matching_impl(
+                self.db_store.clone(),
+                db_name,
+                measurement,
+                range,
+                predicate,
+            )
+            .await
+        } else if tag_key.is_field() {
+            field_names_impl(
+                self.db_store.clone(),
+                db_name,
+                measurement,
+                range,
+                predicate,
+            )
+            .await
+            .map(field_list_to_values)
+        } else {
+            tag_values_impl(
+                self.db_store.clone(),
+                db_name,
+                tag_key,
+                measurement,
+                range,
+                predicate,
+            )
+            .await
+        }
         .map_err(|e| e.to_status());
+        self.audit(
+            "measurement_tag_values",
+            &audited_db_name,
+            &statement,
+            &response,
+        )
+        .await?;
 
         tx.send(response)
             .await
@@ -710,9 +1072,15 @@ where
     ) -> Result<tonic::Response<Self::MeasurementFieldsStream>, Status> {
         let (mut tx, rx) = mpsc::channel(4);
 
+        let token = request_token(&req);
         let measurement_fields_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_fields_request)?;
+        let db_name = self
+            .readable_database_name(token.as_deref(), &measurement_fields_request)
+            .await?;
+        let _timer = self
+            .start_timer(&db_name, OperationKind::MeasurementFields)
+            .await;
 
         let MeasurementFieldsRequest {
             source: _source,
@@ -721,29 +1089,31 @@ where
             predicate,
         } = measurement_fields_request;
 
-        info!(
-            "measurement_fields for database {}, range: {:?}, predicate: {}",
-            db_name,
+        let statement = format!(
+            "range: {:?}, measurement: {}, predicate: {}",
             range,
+            measurement,
             predicate.loggable()
         );
+        info!("measurement_fields for database {}, {}", db_name, statement);
 
         let measurement = Some(measurement);
 
-        let response = field_names_impl(
+        let fieldlist = field_names_impl(
             self.db_store.clone(),
-            db_name,
+            db_name.clone(),
             measurement,
             range,
             predicate,
         )
         .await
-        .map(|fieldlist| {
-            fieldlist_to_measurement_fields_response(fieldlist)
-                .context(ConvertingFieldList)
-                .map_err(|e| e.to_status())
-        })
-        .map_err(|e| e.to_status())?;
+        .map_err(|e| e.to_status());
+        self.audit("measurement_fields", &db_name, &statement, &fieldlist)
+            .await?;
+
+        let response = fieldlist_to_measurement_fields_response(fieldlist?)
+            .context(ConvertingFieldList)
+            .map_err(|e| e.to_status());
 
         tx.send(response)
             .await
@@ -767,9 +1137,31 @@ impl SetRange for PredicateBuilder {
     }
 }
 
-fn get_database_name(input: &impl GrpcInputs) -> Result<DatabaseName<'static>, Status> {
-    org_and_bucket_to_database(input.org_id()?.to_string(), &input.bucket_name()?)
-        .map_err(|e| Status::internal(e.to_string()))
+/// Returns the token of the `authorization: Token <token>` metadata of
+/// the request, if any
+pub(crate) fn request_token<R>(req: &tonic::Request<R>) -> Option<String> {
+    req.metadata()
+        .get("authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Token ")
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// Returns the name of the database storing the data of the org and bucket
+/// of the request
+async fn get_database_name<T: DatabaseStore>(
+    db_store: &T,
+    input: &impl GrpcInputs,
+) -> Result<DatabaseName<'static>, Status> {
+    let org = input.org_id()?.to_string();
+    let bucket = input.bucket_name()?;
+
+    db_store
+        .namespace_db_name(&org, &bucket)
+        .await
+        .map_err(|e| Status::invalid_argument(e.to_string()))
 }
 
 // The following code implements the business logic of the requests as
@@ -784,18 +1176,105 @@ async fn measurement_name_impl<T>(
     db_name: DatabaseName<'static>,
     range: Option<TimestampRange>,
 ) -> Result<StringValuesResponse>
+where
+    T: DatabaseStore,
+{
+    let table_names = table_names_impl(db_store, db_name, range).await?;
+
+    // Map the resulting collection of Strings into a Vec<Vec<u8>>for return
+    let values: Vec<Vec<u8>> = table_names
+        .iter()
+        .map(|name| name.bytes().collect())
+        .collect();
+
+    Ok(StringValuesResponse { values })
+}
+
+/// Gathers the names of the measurements (restricted to `measurement`,
+/// if specified) that have at least one field value in the specified
+/// range matching `rpc_predicate`
+async fn measurement_names_matching_impl<T>(
+    db_store: Arc<T>,
+    db_name: DatabaseName<'static>,
+    measurement: Option<String>,
+    range: Option<TimestampRange>,
+    rpc_predicate: Option<Predicate>,
+) -> Result<StringValuesResponse>
+where
+    T: DatabaseStore,
+{
+    let table_names =
+        table_names_impl(Arc::clone(&db_store), db_name.clone(), range.clone()).await?;
+
+    let mut values = Vec::new();
+    for table_name in table_names.iter() {
+        if matches!(&measurement, Some(measurement) if measurement != table_name) {
+            continue;
+        }
+
+        let fieldlist = field_names_impl(
+            Arc::clone(&db_store),
+            db_name.clone(),
+            Some(table_name.clone()),
+            range.clone(),
+            rpc_predicate.clone(),
+        )
+        .await?;
+
+        if !fieldlist.fields.is_empty() {
+            values.push(table_name.bytes().collect());
+        }
+    }
+
+    Ok(StringValuesResponse { values })
+}
+
+/// Registers the start of a query against `db`, which fails if the
+/// database is already running as many queries as it allows. The query
+/// counts as running until the returned guard is dropped.
+///
+/// The storage API has no principals to filter rows for, so databases
+/// whose rows depend on who queries them are refused.
+fn start_query<D: Database>(db: &D, db_name: &str) -> Result<QueryGuard> {
+    ensure!(!db.restricts_rows(), AccessPolicyNotSupported { db_name });
+    db.start_query()
+        .map_err(Into::<DatabaseError>::into)
+        .map_err(|e| Error::StartingQuery {
+            db_name: db_name.to_string(),
+            source: Box::new(e),
+        })
+}
+
+/// Returns the database `db_name`, failing with `DatabaseNotReady` rather
+/// than `DatabaseNotFound` while it is still being loaded
+async fn lookup_db<T: DatabaseStore>(db_store: &T, db_name: &str) -> Result<Arc<T::Database>> {
+    match db_store.db(db_name).await {
+        Some(db) => Ok(db),
+        None => match db_store.db_not_ready(db_name) {
+            Some(state) => DatabaseNotReady { db_name, state }.fail(),
+            None => DatabaseNotFound { db_name }.fail(),
+        },
+    }
+}
+
+/// Lists the names of the tables that have data in the specified
+/// (optional) range
+async fn table_names_impl<T>(
+    db_store: Arc<T>,
+    db_name: DatabaseName<'static>,
+    range: Option<TimestampRange>,
+) -> Result<StringSetRef>
 where
     T: DatabaseStore,
 {
     let predicate = PredicateBuilder::default().set_range(range).build();
     let db_name = db_name.as_ref();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name })?;
+    let db = lookup_db(db_store.as_ref(), &db_name).await?;
+    let _query = start_query(db.as_ref(), db_name)?;
 
-    let planner = InfluxRPCPlanner::new();
+    let metrics = Arc::new(QueryMetrics::default());
+    let planner = InfluxRPCPlanner::new().with_metrics(Arc::clone(&metrics));
 
     let plan = planner
         .table_names(db.as_ref(), predicate)
@@ -809,14 +1288,9 @@ where
         .await
         .map_err(|e| Box::new(e) as _)
         .context(ListingTables { db_name })?;
+    metrics.emit();
 
-    // Map the resulting collection of Strings into a Vec<Vec<u8>>for return
-    let values: Vec<Vec<u8>> = table_names
-        .iter()
-        .map(|name| name.bytes().collect())
-        .collect();
-
-    Ok(StringValuesResponse { values })
+    Ok(table_names)
 }
 
 /// Return tag keys with optional measurement, timestamp and arbitratry
@@ -842,16 +1316,15 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &*db_name })?;
+    let db = lookup_db(db_store.as_ref(), &db_name).await?;
+    let _query = start_query(db.as_ref(), &db_name)?;
 
     let executor = db_store.executor();
 
     let tag_key_plan = db
         .tag_column_names(predicate)
         .await
+        .map_err(Into::<DatabaseError>::into)
         .map_err(|e| Error::ListingColumns {
             db_name: db_name.to_string(),
             source: Box::new(e),
@@ -899,21 +1372,20 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &*db_name })?;
+    let db = lookup_db(db_store.as_ref(), &db_name).await?;
+    let _query = start_query(db.as_ref(), &db_name)?;
 
     let executor = db_store.executor();
 
-    let tag_value_plan =
-        db.column_values(&tag_name, predicate)
-            .await
-            .map_err(|e| Error::ListingTagValues {
-                db_name: db_name.to_string(),
-                tag_name: tag_name.clone(),
-                source: Box::new(e),
-            })?;
+    let tag_value_plan = db
+        .column_values(&tag_name, predicate)
+        .await
+        .map_err(Into::<DatabaseError>::into)
+        .map_err(|e| Error::ListingTagValues {
+            db_name: db_name.to_string(),
+            tag_name: tag_name.clone(),
+            source: Box::new(e),
+        })?;
 
     let tag_values =
         executor
@@ -945,6 +1417,7 @@ async fn read_filter_impl<'a, T>(
     db_name: DatabaseName<'static>,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
+    limits: ReadLimits,
 ) -> Result<()>
 where
     T: DatabaseStore,
@@ -959,33 +1432,34 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &*db_name })?;
+    let db = lookup_db(db_store.as_ref(), &db_name).await?;
+    let query = start_query(db.as_ref(), &db_name)?;
 
     let executor = db_store.executor();
 
-    let series_plan =
-        db.query_series(predicate)
-            .await
-            .map_err(|e| Error::PlanningFilteringSeries {
-                db_name: db_name.to_string(),
-                source: Box::new(e),
-            })?;
+    let series_plan = db
+        .query_series(predicate)
+        .await
+        .map_err(Into::<DatabaseError>::into)
+        .map_err(|e| Error::PlanningFilteringSeries {
+            db_name: db_name.to_string(),
+            source: Box::new(e),
+        })?;
 
     // Spawn task to convert between series sets and the gRPC results
     // and to run the actual plans (so we can return a result to the
     // client before we start sending result)
     let (tx_series, rx_series) = mpsc::channel(4);
+    let converting_db_name = db_name.to_string();
     tokio::spawn(async move {
-        convert_series_set(rx_series, tx)
+        convert_series_set(rx_series, tx, converting_db_name, limits)
             .await
             .log_if_error("Converting series set")
     });
 
     // fire up the plans and start the pipeline flowing
     tokio::spawn(async move {
+        let _query = query;
         executor
             .to_series_set(series_plan, tx_series)
             .await
@@ -999,28 +1473,78 @@ where
     Ok(())
 }
 
-/// Receives SeriesSets from rx, converts them to ReadResponse and
-/// and sends them to tx
+/// Receives SeriesSets from rx, converts them to ReadResponses (limiting
+/// the size of frames and responses) and sends them to tx.
+///
+/// Once the results of the query against `db_name` exceed `limits`, an
+/// error is sent instead of the responses and the conversion stops, which
+/// also stops the plans producing the series sets.
 async fn convert_series_set(
     mut rx: mpsc::Receiver<Result<SeriesSetItem, SeriesSetError>>,
     mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    db_name: String,
+    limits: ReadLimits,
 ) -> Result<()> {
+    let mut encoder = FrameEncoder::default();
+
     while let Some(series_set) = rx.recv().await {
-        let response = series_set
+        let responses = series_set
             .context(ComputingSeriesSet)
-            .and_then(|series_set| {
-                series_set_item_to_read_response(series_set).context(ConvertingSeriesSet)
-            })
-            .map_err(|e| Status::internal(e.to_string()));
+            .and_then(|series_set| encoder.encode(series_set).context(ConvertingSeriesSet));
 
-        tx.send(response)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            .context(SendingResults)?
+        if let Err(e) = check_read_limits(&encoder, &db_name, limits) {
+            warn!("Stopping read: {}", e);
+            return send_response(&mut tx, Err(e.to_status())).await;
+        }
+
+        match responses {
+            Ok(responses) => {
+                for response in responses {
+                    send_response(&mut tx, Ok(response)).await?;
+                }
+            }
+            Err(e) => {
+                error!("Error encoding series: {}", e);
+                send_response(&mut tx, Err(internal_error())).await?
+            }
+        }
     }
+
+    if let Some(response) = encoder.finish() {
+        send_response(&mut tx, Ok(response)).await?;
+    }
+    Ok(())
+}
+
+/// Returns an error if the results encoded by `encoder` exceed `limits`
+fn check_read_limits(encoder: &FrameEncoder, db_name: &str, limits: ReadLimits) -> Result<()> {
+    ensure!(
+        encoder.series_count() <= limits.max_series,
+        SeriesLimitExceeded {
+            db_name,
+            limit: limits.max_series
+        }
+    );
+    ensure!(
+        encoder.frame_count() <= limits.max_frames,
+        FrameLimitExceeded {
+            db_name,
+            limit: limits.max_frames
+        }
+    );
     Ok(())
 }
 
+async fn send_response(
+    tx: &mut mpsc::Sender<Result<ReadResponse, Status>>,
+    response: Result<ReadResponse, Status>,
+) -> Result<()> {
+    tx.send(response)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        .context(SendingResults)
+}
+
 /// Launch async tasks that send the result of executing read_group to `tx`
 async fn query_group_impl<T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
@@ -1029,6 +1553,7 @@ async fn query_group_impl<T>(
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
     gby_agg: GroupByAndAggregate,
+    limits: ReadLimits,
 ) -> Result<()>
 where
     T: DatabaseStore,
@@ -1043,33 +1568,34 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &*db_name })?;
+    let db = lookup_db(db_store.as_ref(), &db_name).await?;
+    let query = start_query(db.as_ref(), &db_name)?;
 
     let executor = db_store.executor();
 
-    let grouped_series_set_plan =
-        db.query_groups(predicate, gby_agg)
-            .await
-            .map_err(|e| Error::PlanningFilteringSeries {
-                db_name: db_name.to_string(),
-                source: Box::new(e),
-            })?;
+    let grouped_series_set_plan = db
+        .query_groups(predicate, gby_agg)
+        .await
+        .map_err(Into::<DatabaseError>::into)
+        .map_err(|e| Error::PlanningFilteringSeries {
+            db_name: db_name.to_string(),
+            source: Box::new(e),
+        })?;
 
     // Spawn task to convert between series sets and the gRPC results
     // and to run the actual plans (so we can return a result to the
     // client before we start sending result)
     let (tx_series, rx_series) = mpsc::channel(4);
+    let converting_db_name = db_name.to_string();
     tokio::spawn(async move {
-        convert_series_set(rx_series, tx)
+        convert_series_set(rx_series, tx, converting_db_name, limits)
             .await
             .log_if_error("Converting grouped series set")
     });
 
     // fire up the plans and start the pipeline flowing
     tokio::spawn(async move {
+        let _query = query;
         executor
             .to_series_set(grouped_series_set_plan, tx_series)
             .await
@@ -1083,6 +1609,17 @@ where
     Ok(())
 }
 
+/// Picks the names out of `fieldlist` for returning as tag values
+fn field_list_to_values(fieldlist: FieldList) -> StringValuesResponse {
+    let values = fieldlist
+        .fields
+        .into_iter()
+        .map(|f| f.name.bytes().collect())
+        .collect();
+
+    StringValuesResponse { values }
+}
+
 /// Return field names, restricted via optional measurement, timestamp and
 /// predicate
 async fn field_names_impl<T>(
@@ -1106,20 +1643,19 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &*db_name })?;
+    let db = lookup_db(db_store.as_ref(), &db_name).await?;
+    let _query = start_query(db.as_ref(), &db_name)?;
 
     let executor = db_store.executor();
 
-    let field_list_plan =
-        db.field_column_names(predicate)
-            .await
-            .map_err(|e| Error::ListingFields {
-                db_name: db_name.to_string(),
-                source: Box::new(e),
-            })?;
+    let field_list_plan = db
+        .field_column_names(predicate)
+        .await
+        .map_err(Into::<DatabaseError>::into)
+        .map_err(|e| Error::ListingFields {
+            db_name: db_name.to_string(),
+            source: Box::new(e),
+        })?;
 
     let field_list =
         executor
@@ -1133,19 +1669,78 @@ where
     Ok(field_list)
 }
 
+/// Returns the range of the timestamps of the rows of `measurement`, as
+/// recorded by the chunks holding them, so no rows are read. The end of
+/// the range is exclusive.
+async fn time_range_impl<T>(
+    db_store: &T,
+    db_name: &str,
+    measurement: &str,
+) -> Result<Option<TimestampRange>>
+where
+    T: DatabaseStore,
+{
+    let db = lookup_db(db_store, db_name).await?;
+    let _query = start_query(db.as_ref(), db_name)?;
+
+    let time_range = db.time_range(measurement).await.context(ReadingTimeRange {
+        db_name,
+        measurement,
+    })?;
+
+    Ok(time_range.map(|(min, max)| TimestampRange {
+        start: min,
+        end: max.saturating_add(1),
+    }))
+}
+
 /// Instantiate a server listening on the specified address
-/// implementing the IOx and Storage gRPC interfaces, the
+/// implementing the IOx and Storage gRPC interfaces, and the Arrow Flight
+/// interface if `flight_server` is set, the
 /// underlying hyper server instance. Resolves when the server has
 /// shutdown.
-pub async fn make_server<T>(socket: TcpListener, storage: Arc<T>) -> Result<()>
+pub async fn make_server<T, M>(
+    socket: TcpListener,
+    storage: Arc<T>,
+    authorizer: Arc<dyn Authorizer>,
+    auditor: Arc<dyn Auditor>,
+    latency: Arc<LatencyMetrics>,
+    limits: ReadLimits,
+    flight_server: Option<Arc<AppServer<M>>>,
+) -> Result<()>
 where
     T: DatabaseStore + 'static,
+    M: ConnectionManager + Send + Sync + std::fmt::Debug + 'static,
 {
-    tonic::transport::Server::builder()
-        .add_service(IOxTestingServer::new(GrpcService::new(storage.clone())))
-        .add_service(StorageServer::new(GrpcService::new(storage.clone())))
-        .serve_with_incoming(socket)
-        .await
+    let router = tonic::transport::Server::builder()
+        .add_service(IOxTestingServer::new(GrpcService::new(
+            storage.clone(),
+            Arc::clone(&authorizer),
+            Arc::clone(&auditor),
+            Arc::clone(&latency),
+            limits,
+        )))
+        .add_service(IOxStorageServer::new(GrpcService::new(
+            storage.clone(),
+            Arc::clone(&authorizer),
+            Arc::clone(&auditor),
+            Arc::clone(&latency),
+            limits,
+        )))
+        .add_service(StorageServer::new(GrpcService::new(
+            storage, authorizer, auditor, latency, limits,
+        )));
+
+    let served = match flight_server {
+        Some(server) => {
+            router
+                .add_service(FlightServiceServer::new(flight::GrpcService::new(server)))
+                .serve_with_incoming(socket)
+                .await
+        }
+        None => router.serve_with_incoming(socket).await,
+    };
+    served
         .context(ServerError {})
         .log_if_error("Running Tonic Server")
 }
@@ -1156,18 +1751,24 @@ mod tests {
 
     use super::*;
     use arrow_deps::arrow::datatypes::DataType;
+    use data_types::names::org_and_bucket_to_database;
     use panic_logging::SendPanicsToTracing;
     use query::{
         exec::fieldlist::{Field, FieldList},
         exec::FieldListPlan,
         exec::SeriesSetPlans,
-        group_by::{Aggregate as QueryAggregate, WindowDuration as QueryWindowDuration},
+        group_by::WindowDuration as QueryWindowDuration,
         test::ColumnNamesRequest,
         test::FieldColumnsRequest,
         test::QueryGroupsRequest,
         test::TestDatabaseStore,
         test::{ColumnValuesRequest, QuerySeriesRequest, TestChunk},
     };
+    use server::{
+        audit::{self, AuditAction},
+        authz::AllowAll,
+        ConnectionManagerImpl,
+    };
     use std::{
         convert::TryFrom,
         net::{IpAddr, Ipv4Addr, SocketAddr},
@@ -1180,19 +1781,162 @@ mod tests {
     use futures::prelude::*;
 
     use generated_types::{
-        aggregate::AggregateType, i_ox_testing_client, node, read_response::frame, storage_client,
-        Aggregate as RPCAggregate, Duration as RPCDuration, Node, ReadSource, Window as RPCWindow,
+        aggregate::AggregateType,
+        google::rpc::{BadRequest, QuotaFailure, Status as RpcStatus},
+        i_ox_storage_client, i_ox_testing_client, node,
+        read_response::frame,
+        storage_client, Aggregate as RPCAggregate, Duration as RPCDuration, Node, ReadSource,
+        Window as RPCWindow,
     };
 
     use prost::Message;
 
     type IOxTestingClient = i_ox_testing_client::IOxTestingClient<tonic::transport::Channel>;
+    type IOxStorageClient = i_ox_storage_client::IOxStorageClient<tonic::transport::Channel>;
     type StorageClient = storage_client::StorageClient<tonic::transport::Channel>;
 
     fn to_str_vec(s: &[&str]) -> Vec<String> {
         s.iter().map(|s| s.to_string()).collect()
     }
 
+    #[test]
+    fn database_error_status() {
+        let status = |kind| {
+            let source = std::io::Error::new(std::io::ErrorKind::Other, "the cause");
+            Error::ListingColumns {
+                db_name: "db".to_string(),
+                source: Box::new(DatabaseError::new(kind, source)),
+            }
+            .to_status()
+        };
+
+        assert_eq!(status(DatabaseErrorKind::NotFound).code(), Code::NotFound);
+        assert_eq!(
+            status(DatabaseErrorKind::SchemaConflict).code(),
+            Code::FailedPrecondition
+        );
+        assert_eq!(
+            status(DatabaseErrorKind::ResourceExhausted).code(),
+            Code::ResourceExhausted
+        );
+        assert_eq!(status(DatabaseErrorKind::Internal).code(), Code::Internal);
+        // the details of internal errors are not returned to clients
+        assert_eq!(
+            status(DatabaseErrorKind::Internal).message(),
+            "Internal error"
+        );
+        assert_eq!(
+            status(DatabaseErrorKind::NotFound).message(),
+            "Error listing columns in database 'db': the cause"
+        );
+
+        // quota failures name the database whose quota was exceeded
+        let details =
+            RpcStatus::decode(status(DatabaseErrorKind::ResourceExhausted).details()).unwrap();
+        let quota_failure = QuotaFailure::decode(&details.details[0].value[..]).unwrap();
+        assert_eq!(quota_failure.violations[0].subject, "database:db");
+    }
+
+    /// A series of the `cpu` table with a single point of one field
+    fn one_point_series(host: &str) -> SeriesSetItem {
+        use arrow_deps::arrow::{
+            array::{ArrayRef, Float64Array, Int64Array},
+            datatypes::{Field as ArrowField, Schema},
+            record_batch::RecordBatch,
+        };
+        use query::exec::{field::FieldIndexes, seriesset::SeriesSet};
+
+        let schema = Arc::new(Schema::new(vec![
+            ArrowField::new("usage", DataType::Float64, true),
+            ArrowField::new("time", DataType::Int64, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Float64Array::from(vec![1.0])),
+            Arc::new(Int64Array::from(vec![100])),
+        ];
+        SeriesSetItem::Data(SeriesSet {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![(Arc::new("host".into()), Arc::new(host.into()))],
+            field_indexes: FieldIndexes::from_timestamp_and_value_indexes(1, &[0]),
+            start_row: 0,
+            num_rows: 1,
+            batch: RecordBatch::try_new(schema, columns).unwrap(),
+        })
+    }
+
+    /// Converts `items` under `limits`, returning what was sent
+    async fn convert_with_limits(
+        items: Vec<SeriesSetItem>,
+        limits: ReadLimits,
+    ) -> Vec<Result<ReadResponse, Status>> {
+        let (mut tx_series, rx_series) = mpsc::channel(items.len() + 1);
+        for item in items {
+            tx_series.send(Ok(item)).await.unwrap();
+        }
+        drop(tx_series);
+
+        let (tx, rx) = mpsc::channel(100);
+        convert_series_set(rx_series, tx, "mydb".to_string(), limits)
+            .await
+            .unwrap();
+        rx.collect().await
+    }
+
+    #[tokio::test]
+    async fn read_limits_stop_conversion() {
+        let items = || vec![one_point_series("a"), one_point_series("b")];
+
+        let sent = convert_with_limits(items(), ReadLimits::default()).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].as_ref().unwrap().frames.len(), 4);
+
+        let limits = ReadLimits {
+            max_series: 1,
+            ..Default::default()
+        };
+        let sent = convert_with_limits(items(), limits).await;
+        assert_eq!(sent.len(), 1);
+        let status = sent[0].as_ref().unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(
+            status.message(),
+            "Query against database 'mydb' selected more than the limit of 1 series"
+        );
+        let details = RpcStatus::decode(status.details()).unwrap();
+        let quota_failure = QuotaFailure::decode(&details.details[0].value[..]).unwrap();
+        assert_eq!(quota_failure.violations[0].subject, "database:mydb");
+
+        // each series is a series frame and a points frame
+        let limits = ReadLimits {
+            max_frames: 3,
+            ..Default::default()
+        };
+        let sent = convert_with_limits(items(), limits).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].as_ref().unwrap_err().message(),
+            "Query against database 'mydb' produced more than the limit of 3 frames"
+        );
+    }
+
+    #[test]
+    fn invalid_request_status() {
+        let status = Error::ConvertingPredicate {
+            rpc_predicate_string: "Predicate { root: None }".to_string(),
+            source: expr::Error::EmptyPredicateNode {},
+        }
+        .to_status();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let details = RpcStatus::decode(status.details()).unwrap();
+        let bad_request = BadRequest::decode(&details.details[0].value[..]).unwrap();
+        assert_eq!(bad_request.field_violations[0].field, "predicate");
+        assert_eq!(
+            bad_request.field_violations[0].description,
+            status.message()
+        );
+    }
+
     #[tokio::test]
     async fn test_storage_rpc_capabilities() -> Result<(), tonic::Status> {
         // Start a test gRPC server on a randomally allocated port
@@ -1205,7 +1949,24 @@ mod tests {
             to_str_vec(&["Count", "Sum", "Min", "Max", "Mean"]),
         );
 
-        expected_capabilities.insert("Group".into(), to_str_vec(&["First", "Last", "Min", "Max"]));
+        expected_capabilities.insert(
+            "Group".into(),
+            to_str_vec(&["Count", "Sum", "First", "Last", "Min", "Max", "Mean"]),
+        );
+        expected_capabilities.insert(
+            "Predicate".into(),
+            to_str_vec(&[
+                "Equal",
+                "NotEqual",
+                "StartsWith",
+                "Regex",
+                "NotRegex",
+                "Lt",
+                "Lte",
+                "Gt",
+                "Gte",
+            ]),
+        );
 
         assert_eq!(
             expected_capabilities,
@@ -1374,6 +2135,38 @@ mod tests {
         });
         assert_eq!(test_db.get_column_names_request().await, expected_request);
 
+        // the latency of both requests was recorded
+        let latency = fixture
+            .latency
+            .histogram(&db_info.db_name, OperationKind::TagKeys);
+        assert_eq!(latency.cumulative_counts().last(), Some(&2));
+
+        // and both were audited, with the predicate they ran
+        let audited = fixture.auditor.actions.lock().unwrap().clone();
+        assert_eq!(audited.len(), 2);
+        assert_eq!(audited[0].0, "tag_keys");
+        assert_eq!(audited[0].1, db_info.db_name.as_str());
+        assert!(audited[0].2.contains("MA"), "{}", audited[0].2);
+        assert!(!audited[0].3);
+        assert!(audited[1].3);
+
+        // requests to databases that don't exist are not timed
+        let missing_db_info = OrgAndBucket::new(123, 789);
+        let request = TagKeysRequest {
+            tags_source: Some(StorageClientWrapper::read_source(
+                missing_db_info.org_id,
+                missing_db_info.bucket_id,
+                partition_id,
+            )),
+            range: None,
+            predicate: None,
+        };
+        assert!(fixture.storage_client.tag_keys(request).await.is_err());
+        assert!(!fixture
+            .latency
+            .render()
+            .contains(missing_db_info.db_name.as_str()));
+
         Ok(())
     }
 
@@ -1576,6 +2369,42 @@ mod tests {
             "unexpected tag values while getting tag values for field names"
         );
 
+        // ---
+        // test tag_key = "_measurement" with a predicate means listing the
+        // measurements with matching fields
+        // ---
+        let request = TagValuesRequest {
+            tags_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            tag_key: "_measurement".into(),
+        };
+
+        let fieldlist = FieldList {
+            fields: vec![Field {
+                name: "Field1".into(),
+                data_type: DataType::Utf8,
+                last_timestamp: 1000,
+            }],
+        };
+        let fieldlist_plan = FieldListPlan::Known(Ok(fieldlist));
+        test_db.set_field_colum_names_values(fieldlist_plan).await;
+
+        let expected_tag_values = vec!["h2o"];
+        let actual_tag_values = fixture.storage_client.tag_values(request).await.unwrap();
+        assert_eq!(
+            actual_tag_values, expected_tag_values,
+            "unexpected tag values while getting tag values for matching measurement names"
+        );
+
+        let expected_request = FieldColumnsRequest {
+            predicate: "Predicate { table_names: h2o exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into()
+        };
+        assert_eq!(
+            test_db.get_field_columns_request().await,
+            Some(expected_request)
+        );
+
         // ---
         // test error
         // ---
@@ -1683,6 +2512,46 @@ mod tests {
             "unexpected request while getting tag values",
         );
 
+        // ---
+        // test tag_key = "_field" means listing the fields of the measurement
+        // ---
+        let request = MeasurementTagValuesRequest {
+            measurement: "m4".into(),
+            source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            tag_key: "_field".into(),
+        };
+
+        let fieldlist = FieldList {
+            fields: vec![Field {
+                name: "Field1".into(),
+                data_type: DataType::Utf8,
+                last_timestamp: 1000,
+            }],
+        };
+        let fieldlist_plan = FieldListPlan::Known(Ok(fieldlist));
+        test_db.set_field_colum_names_values(fieldlist_plan).await;
+
+        let actual_tag_values = fixture
+            .storage_client
+            .measurement_tag_values(request)
+            .await
+            .unwrap();
+        assert_eq!(
+            actual_tag_values,
+            vec!["Field1"],
+            "unexpected tag values while getting tag values for field names",
+        );
+
+        let expected_request = FieldColumnsRequest {
+            predicate: "Predicate { table_names: m4 exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into()
+        };
+        assert_eq!(
+            test_db.get_field_columns_request().await,
+            Some(expected_request)
+        );
+
         // ---
         // test error
         // ---
@@ -2232,6 +3101,64 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_measurement_time_range() -> Result<(), tonic::Status> {
+        let mut fixture = Fixture::new().await.expect("Connecting to test server");
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let test_db = fixture
+            .test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+        test_db
+            .add_chunk(
+                "1970-01-01T00",
+                Arc::new(TestChunk::new(0).with_table_time_range("h2o", 100, 200)),
+            )
+            .await;
+        test_db
+            .add_chunk(
+                "1970-01-01T01",
+                Arc::new(
+                    TestChunk::new(0)
+                        .with_table_time_range("h2o", 50, 150)
+                        .with_table_time_range("o2", 10, 20),
+                ),
+            )
+            .await;
+
+        let source = Some(StorageClientWrapper::read_source(
+            db_info.org_id,
+            db_info.bucket_id,
+            1,
+        ));
+        // the end of the range is exclusive
+        let expected_ranges = vec![
+            ("h2o", make_timestamp_range(50, 201)),
+            ("o2", make_timestamp_range(10, 21)),
+            ("cpu", None),
+        ];
+        for (measurement, expected_range) in expected_ranges {
+            let request = MeasurementTimeRangeRequest {
+                source: source.clone(),
+                measurement: measurement.into(),
+            };
+            let response = fixture
+                .iox_storage_client
+                .measurement_time_range(request)
+                .await?;
+            assert_eq!(
+                response.into_inner().range,
+                expected_range,
+                "unexpected time range of {}",
+                measurement
+            );
+        }
+
+        Ok(())
+    }
+
     fn make_timestamp_range(start: i64, end: i64) -> Option<TimestampRange> {
         Some(TimestampRange { start, end })
     }
@@ -2583,11 +3510,38 @@ mod tests {
         Tonic { source: tonic::transport::Error },
     }
 
+    /// Remembers the action, database, statement and failure of each
+    /// audited request
+    #[derive(Debug, Default)]
+    struct RecordingAuditor {
+        actions: std::sync::Mutex<Vec<(String, String, String, bool)>>,
+    }
+
+    #[tonic::async_trait]
+    impl Auditor for RecordingAuditor {
+        async fn audit_action(
+            &self,
+            action: AuditAction<'_>,
+            error: Option<&str>,
+        ) -> Result<(), audit::Error> {
+            self.actions.lock().unwrap().push((
+                action.action.to_string(),
+                action.database.unwrap_or_default().to_string(),
+                action.statement.unwrap_or_default().to_string(),
+                error.is_some(),
+            ));
+            Ok(())
+        }
+    }
+
     // Wrapper around raw clients and test database
     struct Fixture {
         iox_client: IOxTestingClient,
+        iox_storage_client: IOxStorageClient,
         storage_client: StorageClientWrapper,
         test_storage: Arc<TestDatabaseStore>,
+        auditor: Arc<RecordingAuditor>,
+        latency: Arc<LatencyMetrics>,
     }
 
     impl Fixture {
@@ -2607,12 +3561,25 @@ mod tests {
 
             println!("Starting InfluxDB IOx rpc test server on {:?}", bind_addr);
 
-            let server = make_server(socket, test_storage.clone());
+            let auditor = Arc::new(RecordingAuditor::default());
+            let latency = Arc::new(LatencyMetrics::default());
+            let server = make_server(
+                socket,
+                test_storage.clone(),
+                Arc::new(AllowAll),
+                Arc::clone(&auditor) as _,
+                Arc::clone(&latency),
+                ReadLimits::default(),
+                None::<Arc<AppServer<ConnectionManagerImpl>>>,
+            );
             tokio::task::spawn(server);
 
             let iox_client = connect_to_server::<IOxTestingClient>(bind_addr)
                 .await
                 .context(Tonic)?;
+            let iox_storage_client = connect_to_server::<IOxStorageClient>(bind_addr)
+                .await
+                .context(Tonic)?;
             let storage_client = StorageClientWrapper::new(
                 connect_to_server::<StorageClient>(bind_addr)
                     .await
@@ -2621,8 +3588,11 @@ mod tests {
 
             Ok(Self {
                 iox_client,
+                iox_storage_client,
                 storage_client,
                 test_storage,
+                auditor,
+                latency,
             })
         }
     }
@@ -2640,6 +3610,13 @@ mod tests {
         }
     }
 
+    #[tonic::async_trait]
+    impl NewClient for IOxStorageClient {
+        async fn connect(addr: String) -> Result<Self, tonic::transport::Error> {
+            Self::connect(addr).await
+        }
+    }
+
     #[tonic::async_trait]
     impl NewClient for StorageClient {
         async fn connect(addr: String) -> Result<Self, tonic::transport::Error> {
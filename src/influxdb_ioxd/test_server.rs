@@ -0,0 +1,326 @@
+//! An in-process IOx server for tests.
+//!
+//! [`TestServer`] runs the same HTTP and gRPC services as `main`, backed by
+//! an in-memory object store and bound to kernel-assigned ports on
+//! localhost, so tests can exercise a whole server (writes, queries,
+//! database management) without spawning the binary or fixing ports.
+//! Everything is torn down when the `TestServer` is dropped.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use futures::future::{abortable, AbortHandle};
+use generated_types::storage_client;
+use hyper::Server;
+use object_store::{memory::InMemory, ObjectStore};
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use snafu::{ResultExt, Snafu};
+
+use super::{http_routes, rpc};
+
+pub type StorageClient = storage_client::StorageClient<tonic::transport::Channel>;
+
+/// The writer id assigned to the server unless the test picks another one
+pub const DEFAULT_WRITER_ID: u32 = 1;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error binding test HTTP server: {}", source))]
+    BindHttp { source: hyper::Error },
+
+    #[snafu(display("Error binding test gRPC server: {}", source))]
+    BindGrpc { source: std::io::Error },
+
+    #[snafu(display("Error creating HTTP client: {}", message))]
+    HttpClient { message: String },
+
+    #[snafu(display("Error connecting gRPC client: {}", source))]
+    GrpcClient { source: tonic::transport::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A running in-process IOx server along with clients connected to it
+#[derive(Debug)]
+pub struct TestServer {
+    /// The server instance shared by the HTTP and gRPC services
+    pub app_server: Arc<AppServer<ConnectionManager>>,
+    http_addr: SocketAddr,
+    grpc_addr: SocketAddr,
+    handles: Vec<AbortHandle>,
+}
+
+impl TestServer {
+    /// Start a server with the writer id set to [`DEFAULT_WRITER_ID`]
+    pub async fn new() -> Result<Self> {
+        Self::new_with_writer_id(Some(DEFAULT_WRITER_ID)).await
+    }
+
+    /// Start a server, setting its writer id only if `writer_id` is
+    /// `Some`, so tests can cover the unconfigured state too
+    pub async fn new_with_writer_id(writer_id: Option<u32>) -> Result<Self> {
+        let object_store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let app_server = Arc::new(AppServer::new(ConnectionManager {}, object_store));
+        if let Some(id) = writer_id {
+            app_server.set_id(id);
+        }
+
+        // NB: ask for port 0 so the OS picks free ports and tests can run
+        // concurrently
+        let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+
+        let socket = tokio::net::TcpListener::bind(localhost)
+            .await
+            .context(BindGrpc)?;
+        let grpc_addr = socket.local_addr().context(BindGrpc)?;
+        let (grpc_server, grpc_handle) = abortable(rpc::service::make_server(
+            socket,
+            app_server.clone(),
+            app_server.authorizer(),
+            app_server.clone(),
+            app_server.latency_metrics(),
+            rpc::service::ReadLimits::default(),
+            Some(app_server.clone()),
+        ));
+
+        let http_server =
+            Server::try_bind(&localhost)
+                .context(BindHttp)?
+                .serve(http_routes::router_service(
+                    app_server.clone(),
+                    http_routes::RequestLimits::default(),
+                ));
+        let http_addr = http_server.local_addr();
+        let (http_server, http_handle) = abortable(http_server);
+
+        tokio::task::spawn(grpc_server);
+        tokio::task::spawn(http_server);
+
+        Ok(Self {
+            app_server,
+            http_addr,
+            grpc_addr,
+            handles: vec![grpc_handle, http_handle],
+        })
+    }
+
+    /// The base URL of the HTTP API, e.g. `http://127.0.0.1:12345`
+    pub fn http_base(&self) -> String {
+        format!("http://{}", self.http_addr)
+    }
+
+    /// The URL of the gRPC API, e.g. `http://127.0.0.1:12346`
+    pub fn grpc_base(&self) -> String {
+        format!("http://{}", self.grpc_addr)
+    }
+
+    /// A client for the IOx HTTP management API
+    pub fn iox_client(&self) -> Result<influxdb_iox_client::Client> {
+        influxdb_iox_client::ClientBuilder::default()
+            .build(self.http_base())
+            .map_err(|e| Error::HttpClient {
+                message: e.to_string(),
+            })
+    }
+
+    /// A client for the storage gRPC API
+    pub async fn storage_client(&self) -> Result<StorageClient> {
+        StorageClient::connect(self.grpc_base())
+            .await
+            .context(GrpcClient)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, num::NonZeroU32};
+
+    use data_types::{database_rules::DatabaseRules, names::org_and_bucket_to_database};
+    use futures::prelude::*;
+    use generated_types::{MeasurementNamesRequest, ReadSource};
+    use prost::Message;
+
+    use super::super::rpc::id::ID;
+    use super::*;
+
+    #[tokio::test]
+    async fn write_over_http_and_query_over_grpc() {
+        let server = TestServer::new().await.expect("starting test server");
+
+        let (org_id, bucket_id) = (0x1111_u64, 0x2222_u64);
+        let org = ID::try_from(org_id).unwrap().to_string();
+        let bucket = ID::try_from(bucket_id).unwrap().to_string();
+        let db_name = org_and_bucket_to_database(&org, &bucket).unwrap();
+
+        server
+            .iox_client()
+            .unwrap()
+            .create_database(db_name.as_str(), &DatabaseRules::default())
+            .await
+            .expect("creating database");
+
+        server
+            .iox_client()
+            .unwrap()
+            .write(
+                &org,
+                &bucket,
+                "cpu,host=a usage=0.5 100\nmem,host=a free=10i 100",
+            )
+            .await
+            .expect("writing line protocol");
+
+        let mut source = Vec::new();
+        ReadSource {
+            org_id,
+            bucket_id,
+            partition_id: 0,
+        }
+        .encode(&mut source)
+        .unwrap();
+
+        let request = MeasurementNamesRequest {
+            source: Some(prost_types::Any {
+                type_url: "/TODO".to_string(),
+                value: source,
+            }),
+            range: None,
+            predicate: None,
+        };
+
+        let responses: Vec<_> = server
+            .storage_client()
+            .await
+            .unwrap()
+            .measurement_names(request)
+            .await
+            .expect("measurement names")
+            .into_inner()
+            .try_collect()
+            .await
+            .expect("measurement names stream");
+
+        let mut names: Vec<_> = responses
+            .into_iter()
+            .flat_map(|r| r.values)
+            .map(|v| String::from_utf8(v).unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["cpu", "mem"]);
+    }
+
+    #[tokio::test]
+    async fn read_formats_results() {
+        let server = TestServer::new().await.expect("starting test server");
+
+        let (org, bucket) = ("0000111100001111", "1111000011110000");
+        let db_name = org_and_bucket_to_database(org, bucket).unwrap();
+        server
+            .iox_client()
+            .unwrap()
+            .create_database(db_name.as_str(), &DatabaseRules::default())
+            .await
+            .expect("creating database");
+
+        let iox_client = server.iox_client().unwrap();
+        iox_client
+            .get_database(db_name.as_str())
+            .await
+            .expect("getting database rules");
+        iox_client
+            .write(org, bucket, "cpu,host=a usage=0.5 100")
+            .await
+            .expect("writing line protocol");
+
+        let rows = iox_client
+            .query(org, bucket, "select host, usage from cpu")
+            .await
+            .expect("querying");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["host"], "a");
+        assert_eq!(rows[0]["usage"], 0.5);
+
+        let client = reqwest::Client::new();
+
+        let read = |format: &'static str| {
+            client
+                .get(&format!("{}/api/v2/read", server.http_base()))
+                .query(&[
+                    ("org", org),
+                    ("bucket", bucket),
+                    ("sql_query", "select host, usage from cpu"),
+                    ("format", format),
+                ])
+                .send()
+        };
+
+        let response = read("csv").await.expect("reading csv");
+        assert_eq!(response.headers()["Content-Type"], "text/csv");
+        assert_eq!(
+            response.text().await.unwrap(),
+            "host,usage\r\n\"a\",0.5\r\n"
+        );
+
+        let response = read("json").await.expect("reading json");
+        assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+        assert_eq!(
+            response.text().await.unwrap(),
+            "{\"host\":\"a\",\"usage\":0.5}\n"
+        );
+
+        let response = read("xml").await.expect("reading xml");
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let read_in = |time_zone: &'static str| {
+            client
+                .get(&format!("{}/api/v2/read", server.http_base()))
+                .query(&[
+                    ("org", org),
+                    ("bucket", bucket),
+                    ("sql_query", "select host from cpu"),
+                    ("format", "csv"),
+                    ("time_zone", time_zone),
+                ])
+                .send()
+        };
+
+        let response = read_in("+05:30").await.expect("reading in a time zone");
+        assert_eq!(response.text().await.unwrap(), "host\r\n\"a\"\r\n");
+        let response = read_in("Europe/Berlin")
+            .await
+            .expect("reading in a named time zone");
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let response = read_in("Mars/Olympus_Mons")
+            .await
+            .expect("reading in an unknown time zone");
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn writer_id_can_be_set_over_http() {
+        let server = TestServer::new_with_writer_id(None)
+            .await
+            .expect("starting test server");
+        assert!(server.app_server.require_id().is_err());
+
+        server
+            .iox_client()
+            .unwrap()
+            .set_writer_id(NonZeroU32::new(42).unwrap())
+            .await
+            .expect("setting writer id");
+
+        assert_eq!(server.app_server.require_id().unwrap(), 42);
+    }
+}
@@ -20,16 +20,26 @@ mod commands {
     mod input;
     pub mod logging;
     pub mod stats;
+    pub mod wal;
 }
 pub mod influxdb_ioxd;
 
-use commands::{config::Config, logging::LoggingLevel};
+use commands::{
+    config::{Config, ServerCommand},
+    logging::LoggingLevel,
+};
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 enum ReturnCode {
     ConversionFailed = 1,
     MetadataDumpFailed = 2,
     StatsFailed = 3,
     ServerExitedAbnormally = 4,
+    WalInspectionFailed = 5,
+    ServerCheckFailed = 6,
 }
 
 fn main() -> Result<(), std::io::Error> {
@@ -42,6 +52,9 @@ Examples:
     # Display all server settings
     influxdb_iox server --help
 
+    # Check the server settings work, without running the server
+    influxdb_iox server check
+
     # Run the InfluxDB IOx server with extra verbose logging
     influxdb_iox -v
 
@@ -56,6 +69,10 @@ Examples:
 
     # Dumps storage statistics about out.parquet to stdout
     influxdb_iox stats out.parquet
+
+    # Summarizes the WAL segments persisted under ~/.influxdb_iox and
+    # dumps their writes as line protocol
+    influxdb_iox wal --dump ~/.influxdb_iox
 "#;
     // load all environment variables from .env before doing anything
     load_dotenv();
@@ -120,6 +137,29 @@ Examples:
                         .long("per-file")
                         .help("Include detailed information per file")
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("wal")
+                .about("Print out what the WAL segments persisted in a directory contain, \
+                        to debug recovery problems")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("The directory to search for segments, e.g. the object store \
+                               directory of a server or of one of its databases")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("dump")
+                        .long("dump")
+                        .help("Also print the writes of each segment as line protocol")
+                )
+                .arg(
+                    Arg::with_name("skip-invalid-writes")
+                        .long("skip-invalid-writes")
+                        .help("Skip the writes that fail verification, rather than failing \
+                               to decode their segment")
+                ),
         )
          .subcommand(
             commands::config::Config::clap(),
@@ -190,16 +230,42 @@ async fn dispatch_args(matches: ArgMatches<'_>) {
                 }
             }
         }
+        ("wal", Some(sub_matches)) => {
+            logging_level.setup_basic_logging();
+            let config = commands::wal::WalConfig {
+                input_path: sub_matches.value_of("INPUT").unwrap().into(),
+                dump: sub_matches.is_present("dump"),
+                skip_invalid_writes: sub_matches.is_present("skip-invalid-writes"),
+            };
+
+            match commands::wal::inspect_wal(&config).await {
+                Ok(()) => debug!("WAL inspection completed successfully"),
+                Err(e) => {
+                    eprintln!("WAL inspection failed: {}", e);
+                    std::process::exit(ReturnCode::WalInspectionFailed as _)
+                }
+            }
+        }
         // Handle the case where the user explicitly specified the server command
         ("server", Some(sub_matches)) => {
-            // Note don't set up basic logging here, different logging rules appy in server
-            // mode
-            let res =
-                influxdb_ioxd::main(logging_level, Some(Config::from_clap(sub_matches))).await;
+            let config = Config::from_clap(sub_matches);
+            match config.command {
+                Some(ServerCommand::Check { json }) => {
+                    logging_level.setup_basic_logging();
+                    if !influxdb_ioxd::check::main(&config, json).await {
+                        std::process::exit(ReturnCode::ServerCheckFailed as _);
+                    }
+                }
+                None => {
+                    // Note don't set up basic logging here, different logging rules appy in
+                    // server mode
+                    let res = influxdb_ioxd::main(logging_level, Some(config)).await;
 
-            if let Err(e) = res {
-                error!("Server shutdown with error: {}", e);
-                std::process::exit(ReturnCode::ServerExitedAbnormally as _);
+                    if let Err(e) = res {
+                        error!("Server shutdown with error: {}", e);
+                        std::process::exit(ReturnCode::ServerExitedAbnormally as _);
+                    }
+                }
             }
         }
         // handle the case where the user didn't specify a command
@@ -235,7 +235,7 @@ async fn read_and_write_data() {
     let capabilities_response = capabilities_response.into_inner();
     assert_eq!(
         capabilities_response.caps.len(),
-        2,
+        3,
         "Response: {:?}",
         capabilities_response
     );
@@ -346,6 +346,7 @@ async fn read_and_write_data() {
     load_read_group_data(&client2, org_id_str, bucket_id_str).await;
     test_read_group_none_agg(&mut storage_client, &read_source).await;
     test_read_group_none_agg_with_predicate(&mut storage_client, &read_source).await;
+    test_read_group_none_agg_with_tag_predicates(&mut storage_client, &read_source).await;
     test_read_group_sum_agg(&mut storage_client, &read_source).await;
     test_read_group_last_agg(&mut storage_client, &read_source).await;
 
@@ -697,6 +698,64 @@ async fn test_read_group_none_agg_with_predicate(
     );
 }
 
+/// Test that tag comparisons that are not equalities (`!=`, `=~`, `!~`)
+/// make it through
+async fn test_read_group_none_agg_with_tag_predicates(
+    storage_client: &mut StorageClient<tonic::transport::Channel>,
+    read_source: &std::option::Option<prost_types::Any>,
+) {
+    let expected_group_frames = vec![
+        "GroupFrame, tag_keys: cpu, partition_key_vals: cpu1",
+        "SeriesFrame, tags: _field=usage_system,_measurement=cpu,cpu=cpu1,host=bar, type: 0",
+        "FloatPointsFrame, timestamps: [1000], values: \"20\"",
+        "SeriesFrame, tags: _field=usage_user,_measurement=cpu,cpu=cpu1,host=bar, type: 0",
+        "FloatPointsFrame, timestamps: [1000], values: \"81\"",
+        "GroupFrame, tag_keys: cpu, partition_key_vals: cpu2",
+        "SeriesFrame, tags: _field=usage_system,_measurement=cpu,cpu=cpu2,host=bar, type: 0",
+        "FloatPointsFrame, timestamps: [1000], values: \"40\"",
+        "SeriesFrame, tags: _field=usage_user,_measurement=cpu,cpu=cpu2,host=bar, type: 0",
+        "FloatPointsFrame, timestamps: [1000], values: \"51\"",
+    ];
+
+    let predicates = vec![
+        make_tag_comparison_predicate(
+            "host",
+            Value::StringValue("foo".into()),
+            Comparison::NotEqual,
+        ),
+        make_tag_comparison_predicate("host", Value::RegexValue("^b".into()), Comparison::Regex),
+        make_tag_comparison_predicate("host", Value::RegexValue("^f".into()), Comparison::NotRegex),
+    ];
+
+    for predicate in predicates {
+        let read_group_request = ReadGroupRequest {
+            read_source: read_source.clone(),
+            range: Some(TimestampRange {
+                start: 0,
+                end: 2000, // do not include data at timestamp 2000
+            }),
+            predicate: Some(predicate.clone()),
+            group_keys: vec![String::from("cpu")],
+            group: Group::By as i32,
+            aggregate: Some(Aggregate {
+                r#type: AggregateType::None as i32,
+            }),
+            hints: 0,
+        };
+
+        let actual_group_frames = do_read_group_request(storage_client, read_group_request).await;
+
+        assert_eq!(
+            expected_group_frames,
+            actual_group_frames,
+            "Predicate: {:?}\nExpected:\n{}\nActual:\n{}",
+            predicate,
+            expected_group_frames.join("\n"),
+            actual_group_frames.join("\n")
+        );
+    }
+}
+
 // Standalone test for read_group with group keys and an actual
 // "aggregate" (not a "selector" style).  assumes that
 // load_read_group_data has been previously run
@@ -810,6 +869,20 @@ async fn test_read_group_last_agg(
 /// Create a predicate representing tag_name=tag_value in the horrible gRPC
 /// structs
 fn make_tag_predicate(tag_name: impl Into<String>, tag_value: impl Into<String>) -> Predicate {
+    make_tag_comparison_predicate(
+        tag_name,
+        Value::StringValue(tag_value.into()),
+        Comparison::Equal,
+    )
+}
+
+/// Create a predicate comparing tag_name with the literal `value` in the
+/// horrible gRPC structs
+fn make_tag_comparison_predicate(
+    tag_name: impl Into<String>,
+    value: Value,
+    comparison: Comparison,
+) -> Predicate {
     Predicate {
         root: Some(Node {
             node_type: NodeType::ComparisonExpression as i32,
@@ -822,10 +895,10 @@ fn make_tag_predicate(tag_name: impl Into<String>, tag_value: impl Into<String>)
                 Node {
                     node_type: NodeType::Literal as i32,
                     children: vec![],
-                    value: Some(Value::StringValue(tag_value.into())),
+                    value: Some(value),
                 },
             ],
-            value: Some(Value::Comparison(Comparison::Equal as _)),
+            value: Some(Value::Comparison(comparison as _)),
         }),
     }
 }